@@ -0,0 +1,45 @@
+//! The order in which rules decide where a file goes. `sort` and `test` walk the rules with
+//! this as the engine's evaluations do, so all of them agree on which rules a file reaches.
+
+/// What trying one rule on a file came to.
+pub enum Tried {
+    /// The rule doesn't take the file: its pattern or a condition doesn't match, or its
+    /// command declined.
+    Passed,
+    /// The rule sends the file to `destination`, as a copy with `copy`.
+    Decided { destination: String, copy: bool },
+}
+
+/// A rule that decided for a file, and what it decided.
+pub struct Decision<'a, R> {
+    pub rule: &'a R,
+    pub destination: String,
+    pub copy: bool,
+}
+
+/// The rules that decided for a file, in the order they were tried.
+pub struct Decisions<'a, R> {
+    pub decisions: Vec<Decision<'a, R>>,
+    /// Whether the last rule that decided doesn't `continue`, so no later rule, plugin or
+    /// script is asked.
+    pub settled: bool,
+}
+
+/// Tries `rules` on a file in their order with `try_rule`, until one that decides doesn't
+/// continue with later rules, as `continues` tells.
+pub fn decide<'a, R>(rules: &'a [R], continues: impl Fn(&R) -> bool, mut try_rule: impl FnMut(&R) -> Tried) -> Decisions<'a, R> {
+    let mut decided = Decisions {
+        decisions: Vec::new(),
+        settled: false,
+    };
+    for rule in rules {
+        if let Tried::Decided { destination, copy } = try_rule(rule) {
+            decided.decisions.push(Decision { rule, destination, copy });
+            if !continues(rule) {
+                decided.settled = true;
+                break;
+            }
+        }
+    }
+    decided
+}
@@ -0,0 +1,402 @@
+//! The file system and clock a sort works with, so a sort can run against memory as well as
+//! against the disk.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+/// What a sort does to files and folders.
+pub trait FileSystem {
+    /// The files directly in `folder`, by name; folders in it are left out.
+    fn list(&self, folder: &Path) -> io::Result<Vec<PathBuf>>;
+    /// Whether a file or folder is at `path`.
+    fn exists(&self, path: &Path) -> bool;
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    /// Moves the file at `from` to `to`, replacing a file there as `std::fs::rename` does.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<()>;
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+    /// Gives the file at `original` the further name `link`, which must not be taken.
+    fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()>;
+    /// What is at `path`, without following it when it is a link.
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata>;
+}
+
+/// What [`FileSystem::symlink_metadata`] tells about a path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    pub kind: EntryKind,
+    pub len: u64,
+    /// The same for every name of one file, such as its device and inode; `None` where the
+    /// system doesn't say.
+    pub id: Option<(u64, u64)>,
+}
+
+impl Metadata {
+    /// Whether both are names of one file, rather than two files or ones that can't be told apart.
+    pub fn is_same_file(&self, other: &Metadata) -> bool {
+        self.id.is_some() && self.id == other.id
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Folder,
+    Link,
+}
+
+/// Where a sort takes the time from.
+pub trait Clock {
+    fn now(&self) -> SystemTime;
+}
+
+/// The real file system.
+pub struct Disk;
+
+impl FileSystem for Disk {
+    fn list(&self, folder: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(folder)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                files.push(entry.path());
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        fs::symlink_metadata(path).is_ok()
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::copy(from, to).map(|_| ())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+        fs::hard_link(original, link)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let metadata = fs::symlink_metadata(path)?;
+        let kind = match metadata.file_type() {
+            kind if kind.is_symlink() => EntryKind::Link,
+            kind if kind.is_dir() => EntryKind::Folder,
+            _ => EntryKind::File,
+        };
+        Ok(Metadata {
+            kind,
+            len: metadata.len(),
+            id: file_id(&metadata),
+        })
+    }
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((metadata.dev(), metadata.ino()))
+}
+
+/// Windows only tells the index of a file through an open handle.
+#[cfg(not(unix))]
+fn file_id(_: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// The time of the system.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A file system kept in memory, for simulating sorts and for tests. Paths are taken as they
+/// are, without resolving `..` or links, and fail the way the disk would: a file can't be
+/// placed in a folder that doesn't exist, nor a folder made where a file is.
+#[derive(Default)]
+pub struct MemoryFileSystem {
+    state: Mutex<Memory>,
+}
+
+#[derive(Default)]
+struct Memory {
+    files: BTreeMap<PathBuf, File>,
+    folders: BTreeSet<PathBuf>,
+    /// The id the next file made gets; hard links share the id of their file.
+    next_id: u64,
+}
+
+#[derive(Clone)]
+struct File {
+    id: u64,
+    contents: Vec<u8>,
+}
+
+impl Memory {
+    fn is_folder(&self, path: &Path) -> bool {
+        // The root, or the empty parent of a relative path, always exists.
+        path.parent().is_none() || self.folders.contains(path)
+    }
+
+    fn check_parent(&self, path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) if !self.is_folder(parent) => Err(not_found(parent)),
+            _ if self.folders.contains(path) => Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} is a folder", path.display()))),
+            _ => Ok(()),
+        }
+    }
+
+    fn new_file(&mut self, contents: Vec<u8>) -> File {
+        self.next_id += 1;
+        File { id: self.next_id, contents }
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{} doesn't exist", path.display()))
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, Memory> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Puts a file with `contents` at `path`, making the folders it is in.
+    pub fn add_file(&self, path: impl AsRef<Path>, contents: impl Into<Vec<u8>>) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            self.create_dir_all(parent)?;
+        }
+        let mut state = self.state();
+        state.check_parent(path)?;
+        let file = state.new_file(contents.into());
+        state.files.insert(path.to_path_buf(), file);
+        Ok(())
+    }
+
+    /// What the file at `path` holds, if there is one.
+    pub fn contents(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        self.state().files.get(path.as_ref()).map(|file| file.contents.clone())
+    }
+
+    /// Every file, by path.
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.state().files.keys().cloned().collect()
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn list(&self, folder: &Path) -> io::Result<Vec<PathBuf>> {
+        let state = self.state();
+        if !state.is_folder(folder) {
+            return Err(not_found(folder));
+        }
+        Ok(state.files.keys().filter(|file| file.parent() == Some(folder)).cloned().collect())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        let state = self.state();
+        state.files.contains_key(path) || state.folders.contains(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state();
+        let missing: Vec<&Path> = path.ancestors().take_while(|ancestor| !state.is_folder(ancestor)).collect();
+        if let Some(file) = missing.iter().find(|ancestor| state.files.contains_key(**ancestor)) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} is a file", file.display())));
+        }
+        state.folders.extend(missing.into_iter().map(Path::to_path_buf));
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut state = self.state();
+        state.check_parent(to)?;
+        let contents = state.files.remove(from).ok_or_else(|| not_found(from))?;
+        state.files.insert(to.to_path_buf(), contents);
+        Ok(())
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut state = self.state();
+        state.check_parent(to)?;
+        let contents = state.files.get(from).map(|file| file.contents.clone()).ok_or_else(|| not_found(from))?;
+        let file = state.new_file(contents);
+        state.files.insert(to.to_path_buf(), file);
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.state().files.remove(path).map(|_| ()).ok_or_else(|| not_found(path))
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+        let mut state = self.state();
+        state.check_parent(link)?;
+        if state.files.contains_key(link) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists", link.display())));
+        }
+        let file = state.files.get(original).cloned().ok_or_else(|| not_found(original))?;
+        state.files.insert(link.to_path_buf(), file);
+        Ok(())
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let state = self.state();
+        if let Some(file) = state.files.get(path) {
+            return Ok(Metadata {
+                kind: EntryKind::File,
+                len: file.contents.len() as u64,
+                id: Some((0, file.id)),
+            });
+        }
+        if state.folders.contains(path) {
+            return Ok(Metadata {
+                kind: EntryKind::Folder,
+                len: 0,
+                id: None,
+            });
+        }
+        Err(not_found(path))
+    }
+}
+
+/// A clock that only moves when told to, so sorts give the same result every time.
+pub struct FixedClock {
+    now: Mutex<SystemTime>,
+}
+
+impl FixedClock {
+    pub fn new(now: SystemTime) -> Self {
+        FixedClock { now: Mutex::new(now) }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) += by;
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn memory() -> MemoryFileSystem {
+        let fs = MemoryFileSystem::new();
+        fs.add_file("/inbox/report.pdf", "report").unwrap();
+        fs.add_file("/inbox/Old/notes.txt", "notes").unwrap();
+        fs
+    }
+
+    #[test]
+    fn lists_only_the_files_directly_in_a_folder() {
+        let fs = memory();
+        assert_eq!(fs.list(Path::new("/inbox")).unwrap(), [PathBuf::from("/inbox/report.pdf")]);
+        assert!(fs.exists(Path::new("/inbox/Old")));
+        assert_eq!(fs.list(Path::new("/missing")).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn places_files_only_in_folders_that_exist() {
+        let fs = memory();
+        let target = Path::new("/inbox/Documents/report.pdf");
+        assert_eq!(fs.rename(Path::new("/inbox/report.pdf"), target).unwrap_err().kind(), io::ErrorKind::NotFound);
+        fs.create_dir_all(Path::new("/inbox/Documents")).unwrap();
+        fs.rename(Path::new("/inbox/report.pdf"), target).unwrap();
+        assert_eq!(fs.contents(target), Some(b"report".to_vec()));
+        assert!(!fs.exists(Path::new("/inbox/report.pdf")));
+    }
+
+    #[test]
+    fn makes_no_folders_where_files_are() {
+        let fs = memory();
+        let made = fs.create_dir_all(Path::new("/inbox/report.pdf/2024"));
+        assert_eq!(made.unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert!(!fs.exists(Path::new("/inbox/report.pdf/2024")));
+    }
+
+    #[test]
+    fn replaces_files_when_renaming_onto_them() {
+        let fs = memory();
+        fs.add_file("/inbox/Old/report.pdf", "older").unwrap();
+        fs.rename(Path::new("/inbox/report.pdf"), Path::new("/inbox/Old/report.pdf")).unwrap();
+        assert_eq!(fs.contents("/inbox/Old/report.pdf"), Some(b"report".to_vec()));
+    }
+
+    #[test]
+    fn copies_leave_the_file_in_place() {
+        let fs = memory();
+        fs.copy(Path::new("/inbox/report.pdf"), Path::new("/inbox/Old/report.pdf")).unwrap();
+        assert_eq!(fs.contents("/inbox/report.pdf"), fs.contents("/inbox/Old/report.pdf"));
+        fs.remove_file(Path::new("/inbox/Old/report.pdf")).unwrap();
+        let removed = fs.remove_file(Path::new("/inbox/Old/report.pdf"));
+        assert_eq!(removed.unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn hard_links_are_further_names_of_the_same_file() {
+        let fs = memory();
+        let (original, link) = (Path::new("/inbox/report.pdf"), Path::new("/inbox/Old/report.pdf"));
+        fs.hard_link(original, link).unwrap();
+        assert_eq!(fs.contents(link), Some(b"report".to_vec()));
+        assert!(fs.symlink_metadata(original).unwrap().is_same_file(&fs.symlink_metadata(link).unwrap()));
+        assert_eq!(fs.hard_link(original, link).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        fs.copy(original, Path::new("/inbox/copy.pdf")).unwrap();
+        assert!(!fs.symlink_metadata(original).unwrap().is_same_file(&fs.symlink_metadata(Path::new("/inbox/copy.pdf")).unwrap()));
+        assert_eq!(fs.symlink_metadata(Path::new("/inbox/Old")).unwrap().kind, EntryKind::Folder);
+    }
+
+    #[test]
+    fn fixed_clocks_move_only_when_told() {
+        let clock = FixedClock::new(UNIX_EPOCH);
+        assert_eq!(clock.now(), UNIX_EPOCH);
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(clock.now(), UNIX_EPOCH + Duration::from_secs(90));
+    }
+
+    #[test]
+    fn lists_files_on_disk_by_name() {
+        let folder = std::env::temp_dir().join(format!("organizer-engine-disk-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(folder.join("Old")).unwrap();
+        fs::write(folder.join("b.pdf"), "b").unwrap();
+        fs::write(folder.join("a.pdf"), "a").unwrap();
+        assert_eq!(Disk.list(&folder).unwrap(), [folder.join("a.pdf"), folder.join("b.pdf")]);
+        Disk.hard_link(&folder.join("a.pdf"), &folder.join("Old/a.pdf")).unwrap();
+        let (a, linked) = (Disk.symlink_metadata(&folder.join("a.pdf")).unwrap(), Disk.symlink_metadata(&folder.join("Old/a.pdf")).unwrap());
+        assert_eq!((a.kind, a.len), (EntryKind::File, 1));
+        assert_eq!(a.is_same_file(&linked), cfg!(unix));
+        let _ = fs::remove_dir_all(&folder);
+    }
+}
@@ -0,0 +1,96 @@
+//! The kinds of files Organizer knows by their extension, for `layout` as well as the
+//! playground and the bindings.
+
+/// A kind of file a layout can name, with the extensions that belong to it in groups
+/// that `{category}` expands to.
+pub struct Category {
+    /// Folder names that stand for the category in a layout, compared ignoring case.
+    pub names: &'static [&'static str],
+    pub groups: &'static [(&'static str, &'static [&'static str])],
+    /// What `{year}`, `{month}` and `{day}` mean for these files.
+    pub dates: Dates,
+}
+
+#[derive(Clone, Copy)]
+pub enum Dates {
+    /// When the photo was taken.
+    Exif,
+    /// The year from the music tags, or else when the file was last modified.
+    Tags,
+    Modified,
+}
+
+pub const CATEGORIES: &[Category] = &[
+    Category {
+        names: &["Images", "Photos", "Pictures"],
+        groups: &[
+            ("Photos", &["jpg", "jpeg", "heic", "heif", "cr2", "cr3", "nef", "arw", "dng", "orf", "rw2", "raf"]),
+            ("Graphics", &["png", "gif", "webp", "bmp", "tif", "tiff", "svg"]),
+        ],
+        dates: Dates::Exif,
+    },
+    Category {
+        names: &["Documents", "Docs"],
+        groups: &[
+            ("PDF", &["pdf"]),
+            ("Word", &["doc", "docx", "odt", "rtf", "pages"]),
+            ("Spreadsheets", &["xls", "xlsx", "ods", "csv", "numbers"]),
+            ("Presentations", &["ppt", "pptx", "odp", "key"]),
+            ("Text", &["txt", "md"]),
+            ("Ebooks", &["epub", "mobi", "azw3"]),
+        ],
+        dates: Dates::Modified,
+    },
+    Category {
+        names: &["Music", "Audio"],
+        groups: &[("Music", &["mp3", "flac", "m4a", "ogg", "opus", "wav", "aac"])],
+        dates: Dates::Tags,
+    },
+    Category {
+        names: &["Videos", "Video", "Movies"],
+        groups: &[("Videos", &["mp4", "mkv", "mov", "avi", "webm", "m4v", "wmv"])],
+        dates: Dates::Modified,
+    },
+    Category {
+        names: &["Archives"],
+        groups: &[("Archives", &["zip", "7z", "rar", "tar", "gz", "tgz", "xz", "bz2", "zst"])],
+        dates: Dates::Modified,
+    },
+    Category {
+        names: &["Software", "Programs", "Installers", "Applications"],
+        groups: &[
+            ("Windows", &["exe", "msi", "msix"]),
+            ("macOS", &["dmg", "pkg"]),
+            ("Linux", &["deb", "rpm", "appimage", "flatpak"]),
+            ("Android", &["apk"]),
+        ],
+        dates: Dates::Modified,
+    },
+    Category {
+        names: &["Code", "Source"],
+        groups: &[("Code", &["rs", "py", "js", "ts", "c", "cpp", "h", "java", "go", "rb", "sh"])],
+        dates: Dates::Modified,
+    },
+];
+
+/// The category a layout folder such as `Photos` stands for.
+pub fn named(folder: &str) -> Option<&'static Category> {
+    CATEGORIES.iter().find(|category| category.names.iter().any(|name| name.eq_ignore_ascii_case(folder)))
+}
+
+/// The kind and group of a file name by its extension, e.g. `Documents` and `PDF`.
+pub fn classify(name: &str) -> Option<(&'static str, &'static str)> {
+    let (stem, extension) = name.rsplit_once('.')?;
+    if stem.is_empty() {
+        return None;
+    }
+    CATEGORIES.iter().find_map(|category| {
+        let (group, _) = category.groups.iter().find(|(_, extensions)| extensions.iter().any(|known| known.eq_ignore_ascii_case(extension)))?;
+        Some((category.names[0], *group))
+    })
+}
+
+/// The names kinds are known by in layouts, e.g. for messages.
+pub fn known_names() -> String {
+    CATEGORIES.iter().map(|category| category.names[0]).collect::<Vec<_>>().join(", ")
+}
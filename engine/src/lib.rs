@@ -0,0 +1,283 @@
+//! Organizer's rule engine: which rule decides where a file goes, judged by its name, and
+//! what kind of file it is. The rule playground and the C and Python bindings are built on
+//! this, and `sort` walks its rules with [`decide`]. [`sort::sort`] carries the decisions
+//! out through a [`FileSystem`], the disk or one in memory, to simulate a sort and undo it.
+
+pub mod decide;
+pub mod filesystem;
+pub mod kinds;
+pub mod matching;
+pub mod sort;
+
+pub use filesystem::{Clock, Disk, EntryKind, FileSystem, FixedClock, MemoryFileSystem, Metadata, SystemClock};
+
+use decide::Tried;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The parts of `rules.json` that deciding by name depends on.
+#[derive(Deserialize)]
+struct Config {
+    rules: Option<Rules>,
+    #[serde(default)]
+    plugins: Vec<String>,
+    #[serde(default)]
+    hidden_files: HiddenFiles,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Rules {
+    List(Vec<Rule>),
+    Map(BTreeMap<String, String>),
+}
+
+#[derive(Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum HiddenFiles {
+    #[default]
+    Skip,
+    Sort,
+}
+
+#[derive(Deserialize)]
+struct Rule {
+    name: Option<String>,
+    #[serde(rename = "match")]
+    pattern: String,
+    contains: Option<String>,
+    matches_content: Option<String>,
+    #[serde(default)]
+    destination: String,
+    exec: Option<String>,
+    #[serde(default)]
+    priority: i32,
+    #[serde(default)]
+    copy: bool,
+    #[serde(default, rename = "continue")]
+    continue_matching: bool,
+    #[serde(default)]
+    hidden: bool,
+    expires: Option<String>,
+    ttl: Option<String>,
+}
+
+impl Rule {
+    fn new(pattern: &str, destination: &str) -> Rule {
+        Rule {
+            name: None,
+            pattern: pattern.to_string(),
+            contains: None,
+            matches_content: None,
+            destination: destination.to_string(),
+            exec: None,
+            priority: 0,
+            copy: false,
+            continue_matching: false,
+            hidden: false,
+            expires: None,
+            ttl: None,
+        }
+    }
+
+    fn label(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.pattern.clone())
+    }
+}
+
+/// The rules of a `rules.json`, in the order `sort` tries them, and what else decides.
+struct Loaded {
+    rules: Vec<Rule>,
+    plugins: Vec<String>,
+    sorts_hidden: bool,
+}
+
+/// What the rules do with one file name.
+#[derive(Serialize)]
+pub struct Evaluation {
+    pub file: String,
+    /// The kind of file and its group, e.g. `Documents` and `PDF`, when the extension is known.
+    pub kind: Option<(String, String)>,
+    pub steps: Vec<Step>,
+    /// Where the file goes; empty when no rule decided and it stays where it is.
+    pub destinations: Vec<String>,
+}
+
+/// How one rule was tried. `result` is `match`, `no match`, `skipped`, or `not run` and
+/// `not checked` for what only the file itself or a sort can tell: `exec` commands,
+/// plugins, and the `contains` and `matches_content` conditions.
+#[derive(Serialize)]
+pub struct Step {
+    pub rule: String,
+    pub result: &'static str,
+    pub explanation: String,
+}
+
+/// The rules `sort` uses when there is no `rules.json`.
+fn default_rules() -> Vec<Rule> {
+    vec![
+        Rule::new(".txt", "TextFiles"),
+        Rule::new(".jpg", "Images"),
+        Rule::new(".png", "Images"),
+        Rule::new(".rs", "RustCode"),
+    ]
+}
+
+fn load(rules_json: &str) -> Result<Loaded, String> {
+    let config: Config = serde_json::from_str(rules_json).map_err(|e| format!("Invalid rules.json: {}", e))?;
+    let mut rules = match config.rules {
+        Some(Rules::List(rules)) => rules,
+        Some(Rules::Map(map)) => map.iter().map(|(pattern, destination)| Rule::new(pattern, destination)).collect(),
+        None => default_rules(),
+    };
+    // As `sort` does, refuse rules that would put files nowhere.
+    if let Some(rule) = rules.iter().find(|rule| rule.exec.is_none() && rule.destination.trim().is_empty()) {
+        return Err(format!("Invalid rules.json: rule {} has no destination", rule.pattern));
+    }
+    rules.sort_by_key(|rule| std::cmp::Reverse(rule.priority));
+    Ok(Loaded {
+        rules,
+        plugins: config.plugins,
+        sorts_hidden: config.hidden_files == HiddenFiles::Sort,
+    })
+}
+
+/// Tries the rules of `rules_json` on every file name in `names`, in the order `sort` would.
+pub fn plan<'a>(rules_json: &str, names: impl IntoIterator<Item = &'a str>) -> Result<Vec<Evaluation>, String> {
+    let loaded = load(rules_json)?;
+    Ok(names
+        .into_iter()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| evaluate(&loaded, name))
+        .collect())
+}
+
+fn evaluate(loaded: &Loaded, name: &str) -> Evaluation {
+    let mut steps = Vec::new();
+    let skips_hidden = skips_hidden(loaded, name);
+    let decided = decide::decide(
+        &loaded.rules,
+        |rule| rule.continue_matching,
+        |rule| {
+            let (step, tried) = try_rule(rule, name, skips_hidden);
+            steps.push(step);
+            tried
+        },
+    );
+    for rule in &loaded.rules[steps.len()..] {
+        steps.push(Step {
+            rule: rule.label(),
+            result: "skipped",
+            explanation: "an earlier rule decided".to_string(),
+        });
+    }
+    // Plugins are only asked when no rule decided, and never about hidden files.
+    for plugin in &loaded.plugins {
+        let (result, explanation) = if decided.settled || skips_hidden {
+            ("skipped", "only asked when no rule decides".to_string())
+        } else {
+            ("not run", "plugins are only run by a sort".to_string())
+        };
+        steps.push(Step {
+            rule: plugin.clone(),
+            result,
+            explanation,
+        });
+    }
+    Evaluation {
+        file: name.to_string(),
+        kind: kinds::classify(name).map(|(kind, group)| (kind.to_string(), group.to_string())),
+        steps,
+        destinations: decided
+            .decisions
+            .iter()
+            .map(|decision| format!("{} {}", if decision.copy { "copy to" } else { "move to" }, decision.destination))
+            .collect(),
+    }
+}
+
+/// Whether `name` is a hidden file that only rules with `hidden` sort.
+fn skips_hidden(loaded: &Loaded, name: &str) -> bool {
+    !loaded.sorts_hidden && name.starts_with('.')
+}
+
+/// Tries `rule` on the file called `name` as `sort` would, as far as the name tells.
+/// Conditions on the file's contents can't be checked from the name, so such a rule passes
+/// the file on, as does one whose command would decide.
+fn try_rule(rule: &Rule, name: &str, skips_hidden: bool) -> (Step, Tried) {
+    let step = |result, explanation| Step {
+        rule: rule.label(),
+        result,
+        explanation,
+    };
+    if skips_hidden && !rule.hidden {
+        return (step("no match", "the file is hidden and the rule doesn't set `hidden`".to_string()), Tried::Passed);
+    }
+    let explained = matching::explain(&rule.pattern, name);
+    if !matching::name_matches(&rule.pattern, name) {
+        return (step("no match", explained), Tried::Passed);
+    }
+    if rule.contains.is_some() || rule.matches_content.is_some() {
+        let explanation = format!("{}; `contains` and `matches_content` need the file's contents", explained);
+        return (step("not checked", explanation), Tried::Passed);
+    }
+    if let Some(command) = &rule.exec {
+        return (step("not run", format!("{}; `{}` would decide", explained, command)), Tried::Passed);
+    }
+    let mut notes = vec![explained];
+    if let Some(expires) = &rule.expires {
+        notes.push(format!("applies until {}", expires));
+    }
+    if let Some(ttl) = &rule.ttl {
+        notes.push(format!("applies for {} after a sort first sees it", ttl));
+    }
+    let tried = Tried::Decided {
+        destination: rule.destination.clone(),
+        copy: rule.copy,
+    };
+    (step("match", notes.join(", ")), tried)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steps(evaluation: &Evaluation) -> Vec<(&str, &str)> {
+        evaluation.steps.iter().map(|step| (step.rule.as_str(), step.result)).collect()
+    }
+
+    #[test]
+    fn tries_the_rules_in_the_order_of_a_sort() {
+        let rules = r#"{ "rules": [
+            { "match": ".pdf", "destination": "Documents" },
+            { "match": ".pdf", "exec": "classify", "priority": 5 },
+            { "name": "invoices", "match": "invoice*", "destination": "Invoices", "priority": 1 }
+        ], "plugins": ["sorter.wasm"] }"#;
+        let evaluation = &plan(rules, ["invoice.pdf"]).unwrap()[0];
+        assert_eq!(steps(evaluation), [(".pdf", "not run"), ("invoices", "match"), (".pdf", "skipped"), ("sorter.wasm", "skipped")]);
+        assert_eq!(evaluation.destinations, ["move to Invoices"]);
+    }
+
+    #[test]
+    fn leaves_plugins_to_a_sort_when_no_rule_decides() {
+        let rules = r#"{ "rules": [{ "match": ".pdf", "contains": "ACME", "destination": "Acme" }], "plugins": ["sorter.wasm"] }"#;
+        let evaluation = &plan(rules, ["report.pdf"]).unwrap()[0];
+        assert_eq!(steps(evaluation), [(".pdf", "not checked"), ("sorter.wasm", "not run")]);
+        assert!(evaluation.destinations.is_empty());
+    }
+
+    #[test]
+    fn matches_names_however_their_accents_are_written() {
+        let rules = r#"{ "rules": { "résumé*": "CV" } }"#;
+        let evaluation = &plan(rules, ["re\u{301}sume\u{301}.pdf"]).unwrap()[0];
+        assert_eq!(evaluation.destinations, ["move to CV"]);
+    }
+
+    #[test]
+    fn uses_the_default_rules_without_any() {
+        let evaluation = &plan("{}", ["notes.txt"]).unwrap()[0];
+        assert_eq!(evaluation.destinations, ["move to TextFiles"]);
+        assert_eq!(evaluation.kind, Some(("Documents".to_string(), "Text".to_string())));
+    }
+}
@@ -0,0 +1,54 @@
+//! Matching of rule patterns against file names. Nothing here touches the file system, so
+//! `sort`, the rule playground and the bindings all match with it.
+
+use std::borrow::Cow;
+use unicode_normalization::UnicodeNormalization;
+
+/// Matches an extension such as `.pdf` or a glob such as `invoice*.pdf` against a file name.
+/// The same accented letter can be written composed or decomposed, as macOS does, so both
+/// are compared composed.
+pub fn name_matches(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (&*comparable(pattern), &*comparable(name));
+    if is_extension(pattern) {
+        return extension(name).is_some_and(|extension| pattern[1..] == *extension);
+    }
+    glob::Pattern::new(pattern).is_ok_and(|pattern| pattern.matches(name))
+}
+
+/// Describes why the pattern does or does not match the file name.
+pub fn explain(pattern: &str, name: &str) -> String {
+    let (pattern, name) = (&*comparable(pattern), &*comparable(name));
+    if is_extension(pattern) {
+        return match extension(name) {
+            Some(extension) if pattern[1..] == *extension => format!("extension is .{}", extension),
+            Some(extension) => format!("extension is .{}, not {}", extension, pattern),
+            None => "file has no extension".to_string(),
+        };
+    }
+    match glob::Pattern::new(pattern) {
+        Ok(glob) if glob.matches(name) => format!("name matches glob {}", pattern),
+        Ok(_) => format!("name does not match glob {}", pattern),
+        Err(e) => format!("invalid glob {}: {}", pattern, e),
+    }
+}
+
+/// `text` in the composed form, so names and patterns that only differ in how their
+/// accents are encoded compare equal.
+pub fn comparable(text: &str) -> Cow<'_, str> {
+    if text.is_ascii() {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(text.nfc().collect())
+}
+
+fn is_extension(pattern: &str) -> bool {
+    pattern.starts_with('.') && !pattern.contains(['*', '?', '['])
+}
+
+/// The extension of a file name, as `Path::extension` has it: none for `.bashrc`.
+fn extension(name: &str) -> Option<&str> {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => Some(extension),
+        _ => None,
+    }
+}
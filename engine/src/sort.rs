@@ -0,0 +1,298 @@
+//! Sorting a folder by file names, through a [`FileSystem`] and a [`Clock`], so a sort can be
+//! simulated in memory and undone.
+
+use crate::decide::{self, Decision};
+use crate::filesystem::{Clock, FileSystem};
+use crate::{load, skips_hidden, try_rule, Rule};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// What a sort did with one file; a file copied by several rules has one for each.
+#[derive(Serialize)]
+pub struct Placement {
+    pub file: PathBuf,
+    pub rule: Option<String>,
+    /// `moved`, `copied`, `skipped`, `failed` or `unmatched` as in `sort --output json`, or
+    /// `not run` and `not checked` for the rules only a real sort can try, as in [`crate::Step`].
+    pub result: &'static str,
+    pub destination: Option<PathBuf>,
+    /// Why the file was skipped, or how it failed.
+    pub detail: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Move,
+    Copy,
+}
+
+/// A file the sort placed, as the journal keeps it.
+pub struct Entry {
+    pub time: SystemTime,
+    pub file: PathBuf,
+    pub action: Action,
+    pub destination: PathBuf,
+}
+
+/// The files a sort placed, in order, so they can be put back.
+#[derive(Default)]
+pub struct Journal {
+    pub entries: Vec<Entry>,
+}
+
+/// What [`sort`] did.
+pub struct Sorted {
+    pub placements: Vec<Placement>,
+    pub journal: Journal,
+}
+
+/// Sorts the files directly in `folder` with the rules of `rules_json`, in the order and by
+/// the matching `sort` uses. Destinations are relative to `folder`. A file whose destination
+/// is taken is skipped rather than replaced, and the rules that need more than the name,
+/// such as those with `exec` or `contains`, pass files on as in the rule playground.
+pub fn sort(rules_json: &str, folder: &Path, fs: &impl FileSystem, clock: &impl Clock) -> Result<Sorted, String> {
+    let loaded = load(rules_json)?;
+    let files = fs.list(folder).map_err(|e| format!("Failed to read {}: {}", folder.display(), e))?;
+    let mut sorted = Sorted {
+        placements: Vec::new(),
+        journal: Journal::default(),
+    };
+    for file in files {
+        let Some(name) = file.file_name().map(|name| name.to_string_lossy().into_owned()) else {
+            continue;
+        };
+        let skips_hidden = skips_hidden(&loaded, &name);
+        let decided = decide::decide(
+            &loaded.rules,
+            |rule| rule.continue_matching,
+            |rule| {
+                let (step, tried) = try_rule(rule, &name, skips_hidden);
+                if matches!(step.result, "not run" | "not checked") {
+                    sorted.placements.push(Placement {
+                        file: file.clone(),
+                        rule: Some(step.rule),
+                        result: step.result,
+                        destination: None,
+                        detail: Some(step.explanation),
+                    });
+                }
+                tried
+            },
+        );
+        if decided.decisions.is_empty() {
+            sorted.placements.push(Placement {
+                file,
+                rule: None,
+                result: "unmatched",
+                destination: None,
+                detail: None,
+            });
+            continue;
+        }
+        for decision in decided.decisions {
+            let copy = decision.copy;
+            let placed = place_decision(fs, clock, folder, &file, &name, decision, &mut sorted);
+            // As in a sort, a file is moved by the first rule that moves it, and copies stop
+            // once one fails.
+            if !copy || !placed {
+                break;
+            }
+        }
+    }
+    Ok(sorted)
+}
+
+/// Carries out what `decision` says for `file`, and returns whether it succeeded.
+fn place_decision(
+    fs: &impl FileSystem,
+    clock: &impl Clock,
+    folder: &Path,
+    file: &Path,
+    name: &str,
+    decision: Decision<'_, Rule>,
+    sorted: &mut Sorted,
+) -> bool {
+    let destination = folder.join(&decision.destination).join(name);
+    let action = if decision.copy { Action::Copy } else { Action::Move };
+    let placement = place(fs, file, &destination, action);
+    if placement.is_ok() {
+        sorted.journal.entries.push(Entry {
+            time: clock.now(),
+            file: file.to_path_buf(),
+            action,
+            destination: destination.clone(),
+        });
+    }
+    let placed = !matches!(placement, Err(Placed::Failed(_)));
+    let (result, detail) = match placement {
+        Ok(()) if action == Action::Move => ("moved", None),
+        Ok(()) => ("copied", None),
+        Err(Placed::Taken) => ("skipped", Some("a file with the same name already exists at the destination".to_string())),
+        Err(Placed::Failed(e)) => ("failed", Some(e)),
+    };
+    sorted.placements.push(Placement {
+        file: file.to_path_buf(),
+        rule: Some(decision.rule.label()),
+        result,
+        destination: Some(destination),
+        detail,
+    });
+    placed
+}
+
+enum Placed {
+    /// Something is at the destination already.
+    Taken,
+    Failed(String),
+}
+
+fn place(fs: &impl FileSystem, file: &Path, destination: &Path, action: Action) -> Result<(), Placed> {
+    if fs.exists(destination) {
+        return Err(Placed::Taken);
+    }
+    if let Some(parent) = destination.parent() {
+        fs.create_dir_all(parent).map_err(|e| Placed::Failed(e.to_string()))?;
+    }
+    let placed = match action {
+        Action::Move => fs.rename(file, destination),
+        Action::Copy => fs.copy(file, destination),
+    };
+    placed.map_err(|e| Placed::Failed(e.to_string()))
+}
+
+impl Journal {
+    /// Puts the files back, the last placed first: moved files go back where they were and
+    /// copies are removed. A file whose old place was taken since stays where it is. Returns
+    /// what couldn't be undone.
+    pub fn undo(&self, fs: &impl FileSystem) -> Vec<String> {
+        let mut problems = Vec::new();
+        for entry in self.entries.iter().rev() {
+            let undone = match entry.action {
+                Action::Move if fs.exists(&entry.file) => {
+                    problems.push(format!("{} is taken, so {} stays where it is", entry.file.display(), entry.destination.display()));
+                    continue;
+                }
+                Action::Move => fs.rename(&entry.destination, &entry.file),
+                Action::Copy => fs.remove_file(&entry.destination),
+            };
+            if let Err(e) = undone {
+                problems.push(format!("Failed to put back {}: {}", entry.destination.display(), e));
+            }
+        }
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::{FixedClock, MemoryFileSystem};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    const RULES: &str = r#"{ "rules": [
+        { "match": "invoice*.pdf", "destination": "Invoices", "priority": 10 },
+        { "match": ".pdf", "destination": "Documents" },
+        { "match": ".jpg", "destination": "Backup", "copy": true, "continue": true },
+        { "match": ".jpg", "destination": "Images" }
+    ] }"#;
+
+    fn inbox(files: &[&str]) -> MemoryFileSystem {
+        let fs = MemoryFileSystem::new();
+        for file in files {
+            fs.add_file(Path::new("/inbox").join(file), file.as_bytes()).unwrap();
+        }
+        fs
+    }
+
+    fn results(sorted: &Sorted) -> Vec<&'static str> {
+        sorted.placements.iter().map(|placement| placement.result).collect()
+    }
+
+    #[test]
+    fn places_files_by_the_first_rule_that_decides() {
+        let fs = inbox(&["invoice-1.pdf", "notes.pdf", "notes.txt"]);
+        let sorted = sort(RULES, Path::new("/inbox"), &fs, &FixedClock::new(UNIX_EPOCH)).unwrap();
+        assert_eq!(results(&sorted), ["moved", "moved", "unmatched"]);
+        assert_eq!(
+            fs.files(),
+            [
+                PathBuf::from("/inbox/Documents/notes.pdf"),
+                PathBuf::from("/inbox/Invoices/invoice-1.pdf"),
+                PathBuf::from("/inbox/notes.txt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn copies_and_continues_to_the_rule_that_moves() {
+        let fs = inbox(&["photo.jpg"]);
+        let sorted = sort(RULES, Path::new("/inbox"), &fs, &FixedClock::new(UNIX_EPOCH)).unwrap();
+        assert_eq!(results(&sorted), ["copied", "moved"]);
+        assert_eq!(fs.files(), [PathBuf::from("/inbox/Backup/photo.jpg"), PathBuf::from("/inbox/Images/photo.jpg")]);
+    }
+
+    #[test]
+    fn skips_files_whose_destination_is_taken() {
+        let fs = inbox(&["report.pdf"]);
+        fs.add_file("/inbox/Documents/report.pdf", "older").unwrap();
+        let sorted = sort(RULES, Path::new("/inbox"), &fs, &FixedClock::new(UNIX_EPOCH)).unwrap();
+        assert_eq!(results(&sorted), ["skipped"]);
+        assert_eq!(fs.contents("/inbox/Documents/report.pdf"), Some(b"older".to_vec()));
+        assert!(fs.contents("/inbox/report.pdf").is_some());
+        assert!(sorted.journal.entries.is_empty());
+    }
+
+    #[test]
+    fn passes_files_on_from_rules_the_name_cannot_decide() {
+        let rules = r#"{ "rules": [
+            { "match": ".pdf", "contains": "ACME", "destination": "Acme" },
+            { "match": ".pdf", "exec": "classify" },
+            { "match": ".pdf", "destination": "Documents" }
+        ] }"#;
+        let fs = inbox(&["report.pdf"]);
+        let sorted = sort(rules, Path::new("/inbox"), &fs, &FixedClock::new(UNIX_EPOCH)).unwrap();
+        assert_eq!(results(&sorted), ["not checked", "not run", "moved"]);
+        assert_eq!(fs.files(), [PathBuf::from("/inbox/Documents/report.pdf")]);
+    }
+
+    #[test]
+    fn sorts_hidden_files_only_by_rules_that_ask_for_them() {
+        let rules = r#"{ "rules": [
+            { "match": ".bash*", "destination": "Dotfiles" },
+            { "match": ".note*", "destination": "Notes", "hidden": true }
+        ] }"#;
+        let fs = inbox(&[".bashrc", ".notes"]);
+        let sorted = sort(rules, Path::new("/inbox"), &fs, &FixedClock::new(UNIX_EPOCH)).unwrap();
+        assert_eq!(results(&sorted), ["unmatched", "moved"]);
+        assert_eq!(fs.files(), [PathBuf::from("/inbox/.bashrc"), PathBuf::from("/inbox/Notes/.notes")]);
+    }
+
+    #[test]
+    fn journals_placements_at_the_time_of_the_clock() {
+        let clock = FixedClock::new(UNIX_EPOCH);
+        clock.advance(Duration::from_secs(60));
+        let fs = inbox(&["photo.jpg"]);
+        let sorted = sort(RULES, Path::new("/inbox"), &fs, &clock).unwrap();
+        let times: Vec<SystemTime> = sorted.journal.entries.iter().map(|entry| entry.time).collect();
+        assert_eq!(times, [UNIX_EPOCH + Duration::from_secs(60); 2]);
+    }
+
+    #[test]
+    fn undo_puts_files_back_and_removes_copies() {
+        let fs = inbox(&["notes.pdf", "photo.jpg"]);
+        let sorted = sort(RULES, Path::new("/inbox"), &fs, &FixedClock::new(UNIX_EPOCH)).unwrap();
+        assert!(sorted.journal.undo(&fs).is_empty());
+        assert_eq!(fs.files(), [PathBuf::from("/inbox/notes.pdf"), PathBuf::from("/inbox/photo.jpg")]);
+    }
+
+    #[test]
+    fn undo_leaves_files_whose_old_place_was_taken() {
+        let fs = inbox(&["notes.pdf"]);
+        let sorted = sort(RULES, Path::new("/inbox"), &fs, &FixedClock::new(UNIX_EPOCH)).unwrap();
+        fs.add_file("/inbox/notes.pdf", "newer").unwrap();
+        assert_eq!(sorted.journal.undo(&fs).len(), 1);
+        assert_eq!(fs.contents("/inbox/Documents/notes.pdf"), Some(b"notes.pdf".to_vec()));
+        assert_eq!(fs.contents("/inbox/notes.pdf"), Some(b"newer".to_vec()));
+    }
+}
@@ -0,0 +1,27 @@
+/* Organizer's rule engine. Strings returned here are freed with organizer_free. */
+#ifndef ORGANIZER_H
+#define ORGANIZER_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Tries the rules of rules_json on the file names in names, one per line, and returns the
+ * evaluations as a JSON array. Each step's result is "match", "no match", "skipped", or
+ * "not run" and "not checked" for exec commands, plugins and content conditions, which the
+ * name alone can't decide. On failure returns NULL and, unless error is NULL, sets *error
+ * to the message. */
+char *organizer_plan(const char *rules_json, const char *names, char **error);
+
+/* Returns {"kind":..., "group":...} for a file name by its extension, or NULL when the
+ * extension is not known. */
+char *organizer_classify(const char *name);
+
+/* Frees a string returned by the functions above; NULL is ignored. */
+void organizer_free(char *string);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
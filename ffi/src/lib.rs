@@ -0,0 +1,68 @@
+//! The rule engine behind a C ABI, for programs in other languages. The functions are declared
+//! in `include/organizer.h`; every string they return is freed with `organizer_free`.
+
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+
+/// Tries the rules of `rules_json` on the file names in `names`, one per line, and returns the
+/// evaluations as a JSON array, with the step results of [`organizer_engine::Step`]. On failure returns NULL and, unless `error` is NULL, points it
+/// at the message.
+///
+/// # Safety
+///
+/// `rules_json` and `names` must be NUL-terminated strings, and `error` NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn organizer_plan(rules_json: *const c_char, names: *const c_char, error: *mut *mut c_char) -> *mut c_char {
+    let result = read(rules_json).and_then(|rules_json| {
+        let names = read(names)?;
+        let evaluations = organizer_engine::plan(rules_json, names.lines())?;
+        serde_json::to_string(&evaluations).map_err(|e| e.to_string())
+    });
+    match result {
+        Ok(json) => into_raw(json),
+        Err(message) => {
+            if !error.is_null() {
+                *error = into_raw(message);
+            }
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Returns the kind and group of a file name by its extension as a JSON object such as
+/// `{"kind":"Documents","group":"PDF"}`, or NULL when the extension is not known.
+///
+/// # Safety
+///
+/// `name` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn organizer_classify(name: *const c_char) -> *mut c_char {
+    match read(name).ok().and_then(organizer_engine::kinds::classify) {
+        Some((kind, group)) => into_raw(serde_json::json!({ "kind": kind, "group": group }).to_string()),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Frees a string returned by the functions above; NULL is ignored.
+///
+/// # Safety
+///
+/// `string` must be NULL or returned by this library, and not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn organizer_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+unsafe fn read<'a>(string: *const c_char) -> Result<&'a str, String> {
+    if string.is_null() {
+        return Err("Unexpected NULL string".to_string());
+    }
+    CStr::from_ptr(string).to_str().map_err(|e| e.to_string())
+}
+
+fn into_raw(string: String) -> *mut c_char {
+    // JSON and the messages here have no NUL bytes but those escaped, so this can't fail.
+    CString::new(string).unwrap_or_default().into_raw()
+}
@@ -0,0 +1,13 @@
+//! The rule engine built for the browser: tries the rules of a `rules.json` on typed-in file
+//! names and says which rule decides each. Build with `wasm-pack build --target web` and
+//! open `index.html`.
+
+use wasm_bindgen::prelude::*;
+
+/// Tries the rules of `rules_json` on the file names in `names`, one per line, and returns
+/// the evaluations as JSON.
+#[wasm_bindgen]
+pub fn evaluate_rules(rules_json: &str, names: &str) -> Result<String, JsValue> {
+    let evaluations = organizer_engine::plan(rules_json, names.lines()).map_err(|e| JsValue::from_str(&e))?;
+    serde_json::to_string(&evaluations).map_err(|e| JsValue::from_str(&e.to_string()))
+}
@@ -0,0 +1,49 @@
+//! The rule engine as the Python module `organizer`. Build with `maturin develop` or
+//! `maturin build --release`.
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+/// Tries the rules of `rules_json` on the file names in `names` and returns a dict per name
+/// with its `file`, `kind`, `steps` and `destinations`. A step's `result` is `not run` or
+/// `not checked` for what only a sort can try, such as `exec` commands and `contains`.
+#[pyfunction]
+fn plan<'py>(py: Python<'py>, rules_json: &str, names: Vec<String>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+    let evaluations = organizer_engine::plan(rules_json, names.iter().map(String::as_str)).map_err(PyValueError::new_err)?;
+    evaluations
+        .into_iter()
+        .map(|evaluation| {
+            let steps = evaluation
+                .steps
+                .into_iter()
+                .map(|step| {
+                    let dict = PyDict::new_bound(py);
+                    dict.set_item("rule", step.rule)?;
+                    dict.set_item("result", step.result)?;
+                    dict.set_item("explanation", step.explanation)?;
+                    Ok(dict)
+                })
+                .collect::<PyResult<Vec<_>>>()?;
+            let dict = PyDict::new_bound(py);
+            dict.set_item("file", evaluation.file)?;
+            dict.set_item("kind", evaluation.kind)?;
+            dict.set_item("steps", steps)?;
+            dict.set_item("destinations", evaluation.destinations)?;
+            Ok(dict)
+        })
+        .collect()
+}
+
+/// The kind and group of a file name by its extension, e.g. `("Documents", "PDF")`, or `None`.
+#[pyfunction]
+fn classify(name: &str) -> Option<(&'static str, &'static str)> {
+    organizer_engine::kinds::classify(name)
+}
+
+#[pymodule]
+fn organizer(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(plan, module)?)?;
+    module.add_function(wrap_pyfunction!(classify, module)?)?;
+    Ok(())
+}
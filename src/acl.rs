@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+/// One entry of a destination's `acl` action: who gets, or is denied, which access to the
+/// files moved in. Names are looked up on the machine or its domain when the file is placed.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AclEntry {
+    /// A user or group, e.g. `Family`, `Everyone` or `OFFICE\Scanners`.
+    pub principal: String,
+    pub access: Access,
+    /// Deny the access instead of granting it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deny: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    /// Open and read the file.
+    Read,
+    /// Read, change and delete the file.
+    Modify,
+    /// Everything, including changing its permissions and owner.
+    Full,
+}
+
+/// Adds the entries to the file's access control list. Moved files keep the list they had
+/// in their old folder, so the destination's inherited entries alone are not enough.
+#[cfg(windows)]
+pub fn apply(file_path: &Path, entries: &[AclEntry]) -> io::Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr;
+    use windows_sys::Win32::Foundation::{LocalFree, ERROR_SUCCESS};
+    use windows_sys::Win32::Security::Authorization::{
+        GetNamedSecurityInfoW, SetEntriesInAclW, SetNamedSecurityInfoW, DENY_ACCESS, EXPLICIT_ACCESS_W, GRANT_ACCESS,
+        NO_MULTIPLE_TRUSTEE, SE_FILE_OBJECT, TRUSTEE_IS_SID, TRUSTEE_IS_UNKNOWN, TRUSTEE_W,
+    };
+    use windows_sys::Win32::Security::{ACL, DACL_SECURITY_INFORMATION, NO_INHERITANCE};
+    use windows_sys::Win32::Storage::FileSystem::{
+        DELETE, FILE_ALL_ACCESS, FILE_GENERIC_EXECUTE, FILE_GENERIC_READ, FILE_GENERIC_WRITE,
+    };
+
+    let sids = entries
+        .iter()
+        .map(|entry| lookup_sid(&entry.principal))
+        .collect::<io::Result<Vec<_>>>()?;
+    let explicit: Vec<EXPLICIT_ACCESS_W> = entries
+        .iter()
+        .zip(&sids)
+        .map(|(entry, sid)| EXPLICIT_ACCESS_W {
+            grfAccessPermissions: match entry.access {
+                Access::Read => FILE_GENERIC_READ | FILE_GENERIC_EXECUTE,
+                Access::Modify => FILE_GENERIC_READ | FILE_GENERIC_WRITE | FILE_GENERIC_EXECUTE | DELETE,
+                Access::Full => FILE_ALL_ACCESS,
+            },
+            grfAccessMode: if entry.deny { DENY_ACCESS } else { GRANT_ACCESS },
+            grfInheritance: NO_INHERITANCE,
+            Trustee: TRUSTEE_W {
+                pMultipleTrustee: ptr::null_mut(),
+                MultipleTrusteeOperation: NO_MULTIPLE_TRUSTEE,
+                TrusteeForm: TRUSTEE_IS_SID,
+                TrusteeType: TRUSTEE_IS_UNKNOWN,
+                ptstrName: sid.as_ptr() as *mut u16,
+            },
+        })
+        .collect();
+
+    let path: Vec<u16> = file_path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut old_acl: *mut ACL = ptr::null_mut();
+    let mut descriptor = ptr::null_mut();
+    let status = unsafe {
+        GetNamedSecurityInfoW(
+            path.as_ptr(),
+            SE_FILE_OBJECT,
+            DACL_SECURITY_INFORMATION,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            &mut old_acl,
+            ptr::null_mut(),
+            &mut descriptor,
+        )
+    };
+    if status != ERROR_SUCCESS {
+        return Err(io::Error::from_raw_os_error(status as i32));
+    }
+    let mut new_acl: *mut ACL = ptr::null_mut();
+    let status = unsafe { SetEntriesInAclW(explicit.len() as u32, explicit.as_ptr(), old_acl, &mut new_acl) };
+    unsafe { LocalFree(descriptor as _) };
+    if status != ERROR_SUCCESS {
+        return Err(io::Error::from_raw_os_error(status as i32));
+    }
+    let status = unsafe {
+        SetNamedSecurityInfoW(
+            path.as_ptr(),
+            SE_FILE_OBJECT,
+            DACL_SECURITY_INFORMATION,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            new_acl,
+            ptr::null(),
+        )
+    };
+    unsafe { LocalFree(new_acl as _) };
+    if status != ERROR_SUCCESS {
+        return Err(io::Error::from_raw_os_error(status as i32));
+    }
+    Ok(())
+}
+
+/// The security identifier of a user or group, as bytes.
+#[cfg(windows)]
+fn lookup_sid(principal: &str) -> io::Result<Vec<u8>> {
+    use std::ptr;
+    use windows_sys::Win32::Security::LookupAccountNameW;
+
+    let name: Vec<u16> = principal.encode_utf16().chain(Some(0)).collect();
+    let (mut sid_size, mut domain_size, mut kind) = (0u32, 0u32, 0);
+    // The first call only asks for the buffer sizes.
+    unsafe {
+        LookupAccountNameW(
+            ptr::null(),
+            name.as_ptr(),
+            ptr::null_mut(),
+            &mut sid_size,
+            ptr::null_mut(),
+            &mut domain_size,
+            &mut kind,
+        )
+    };
+    if sid_size == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No user or group named {}", principal),
+        ));
+    }
+    let mut sid = vec![0u8; sid_size as usize];
+    let mut domain = vec![0u16; domain_size as usize];
+    let found = unsafe {
+        LookupAccountNameW(
+            ptr::null(),
+            name.as_ptr(),
+            sid.as_mut_ptr() as _,
+            &mut sid_size,
+            domain.as_mut_ptr(),
+            &mut domain_size,
+            &mut kind,
+        )
+    };
+    if found == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(sid)
+}
+
+/// Access control lists are a Windows feature; elsewhere the entries are ignored.
+#[cfg(not(windows))]
+pub fn apply(_file_path: &Path, _entries: &[AclEntry]) -> io::Result<()> {
+    static WARNED: std::sync::Once = std::sync::Once::new();
+    WARNED.call_once(|| tracing::warn!("`acl` actions only apply on Windows and are ignored here"));
+    Ok(())
+}
@@ -0,0 +1,166 @@
+use crate::acl::{self, AclEntry};
+use crate::archives::{self, CompressAction, ExtractAction};
+use crate::dates;
+use crate::duplicates::DuplicatePolicy;
+use crate::guard::ProtectAction;
+use crate::handoff::HandoffAction;
+use crate::images::{self, OriginalsPolicy};
+use crate::lifecycle::Stage;
+use crate::media;
+use crate::naming::RenameAction;
+use crate::retention::RetentionAction;
+use crate::shortcuts::ShortcutsAction;
+use crate::staging::StageAction;
+use crate::templates;
+use crate::transfer;
+use crate::trash::{self, Trashed};
+use chrono::{Local, NaiveDateTime, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+/// Post-move actions configured for a destination in `rules.json`.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct RuleActions {
+    /// Sources tried in order to find the file's real date: `exif` or `filename`.
+    #[serde(default)]
+    pub set_mtime: Vec<String>,
+    /// Rotate images upright according to their EXIF orientation.
+    #[serde(default)]
+    pub auto_orient: bool,
+    /// Convert images to this format, e.g. `jpg` or `webp`.
+    pub convert_to: Option<String>,
+    #[serde(default)]
+    pub originals: OriginalsPolicy,
+    /// Remux, transcode or normalize media with an external command before it is filed.
+    #[serde(alias = "normalize")]
+    pub transcode: Option<HandoffAction>,
+    /// Overrides the global duplicate policy for this destination.
+    pub duplicates: Option<DuplicatePolicy>,
+    /// Unpack archives after they were filed.
+    pub extract: Option<ExtractAction>,
+    /// Bundle files into dated archives instead of filing them individually.
+    pub compress: Option<CompressAction>,
+    /// Hold files in a staging folder until they are approved for this destination.
+    pub stage: Option<StageAction>,
+    /// Access control entries added to files moved in, on Windows.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub acl: Vec<AclEntry>,
+    /// Record the rule, the original path and these tags on files placed here, as extended
+    /// attributes that `find --tag` looks for.
+    pub tag: Option<Vec<String>>,
+    /// Give files moved here a new name made from a template.
+    pub rename: Option<RenameAction>,
+    /// Watch for files deleted or renamed here by other programs.
+    pub protect: Option<ProtectAction>,
+    /// Link files placed here from other folders, such as `Recent`.
+    pub shortcuts: Option<ShortcutsAction>,
+    /// Replace files moved here with a hard link to an identical file already below the
+    /// destination's top folder, on the same drive.
+    #[serde(default)]
+    pub hardlink_dedupe: bool,
+    /// Clean up files below the destination's top folder once they are too old, or once it
+    /// holds too much.
+    pub retention: Option<RetentionAction>,
+    /// Stages files go through once they are in the destination, such as moving to an archive
+    /// after a month and being compressed after a year.
+    #[serde(default)]
+    pub lifecycle: Vec<Stage>,
+}
+
+impl RuleActions {
+    /// The actions that run on files placed in the destination, by their names in
+    /// `rules.json`. Files that are only copied just get their tags.
+    pub fn names(&self, copy: bool) -> Vec<&'static str> {
+        if copy {
+            return self.tag.iter().map(|_| "tag").collect();
+        }
+        let set = [
+            ("rename", self.rename.is_some()),
+            ("duplicates", self.duplicates.is_some()),
+            ("stage", self.stage.is_some()),
+            ("transcode", self.transcode.is_some()),
+            ("compress", self.compress.is_some()),
+            ("auto_orient", self.auto_orient),
+            ("convert_to", self.convert_to.is_some()),
+            ("set_mtime", !self.set_mtime.is_empty()),
+            ("extract", self.extract.is_some()),
+            ("acl", !self.acl.is_empty()),
+            ("tag", self.tag.is_some()),
+            ("shortcuts", self.shortcuts.is_some()),
+            ("hardlink_dedupe", self.hardlink_dedupe),
+        ];
+        set.into_iter().filter_map(|(name, set)| set.then_some(name)).collect()
+    }
+}
+
+/// What the actions made of a placed file.
+pub struct Applied {
+    /// The resulting file, which differs from the placed one when it was converted.
+    pub file: PathBuf,
+    /// Files put in the trash on the way, such as the original of a converted image.
+    pub trashed: Vec<Trashed>,
+    /// The checksum the file was verified with when it was copied in, while no action
+    /// changed it.
+    pub checksum: Option<String>,
+}
+
+/// Runs the configured actions on `file_path`; originals and archives they replace are deleted
+/// or put in the trash as `transfers` says.
+pub fn apply_actions(file_path: &Path, actions: &RuleActions, templates: &templates::Settings, transfers: &transfer::Settings) -> std::io::Result<Applied> {
+    let mut applied = Applied {
+        file: file_path.to_path_buf(),
+        trashed: Vec::new(),
+        checksum: None,
+    };
+    if actions.auto_orient {
+        images::auto_orient(&applied.file)?;
+    }
+    if let Some(format) = &actions.convert_to {
+        let (converted, trashed) = images::convert(&applied.file, format, &actions.originals, transfers)?;
+        applied.file = converted;
+        applied.trashed.extend(trashed);
+    }
+    if !actions.set_mtime.is_empty() {
+        set_timestamp(&applied.file, &actions.set_mtime)?;
+    }
+    if let Some(extract) = &actions.extract {
+        if let Some(destination) = archives::extract(&applied.file, extract, templates)? {
+            if extract.delete_archive {
+                applied.trashed.extend(trash::remove(&applied.file, transfers)?);
+                applied.file = destination;
+            }
+        }
+    }
+    if !actions.acl.is_empty() && applied.file.is_file() {
+        acl::apply(&applied.file, &actions.acl)?;
+    }
+    Ok(applied)
+}
+
+fn set_timestamp(file_path: &Path, sources: &[String]) -> std::io::Result<()> {
+    let Some(date) = sources.iter().find_map(|source| content_date(file_path, source)) else {
+        return Ok(());
+    };
+    let Some(local) = Local.from_local_datetime(&date).earliest() else {
+        return Ok(());
+    };
+    
+    let modified = SystemTime::from(local);
+    File::options().write(true).open(file_path)?.set_modified(modified)?;
+    info!(file = ?file_path, %date, "Set modification time");
+    Ok(())
+}
+
+fn content_date(file_path: &Path, source: &str) -> Option<NaiveDateTime> {
+    match source {
+        "exif" => media::exif_date(file_path),
+        "filename" => dates::date_from_filename(file_path.file_name()?.to_str()?),
+        _ => {
+            warn!("Unknown timestamp source '{}'", source);
+            None
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+const CLAMD_SOCKET: &str = "/var/run/clamav/clamd.ctl";
+const DEFENDER_COMMAND: &str = r"C:\Program Files\Windows Defender\MpCmdRun.exe";
+const TIMEOUT: Duration = Duration::from_secs(60);
+const CHUNK: usize = 64 * 1024;
+
+/// The virus scanner that rules with `scan` check files with, from `rules.json`.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "scanner", rename_all = "lowercase")]
+pub enum AntivirusSettings {
+    /// The ClamAV daemon.
+    Clamd {
+        /// Path of clamd's local socket, or `host:port` of its TCP socket.
+        socket: Option<String>,
+    },
+    /// Microsoft Defender's command line scanner.
+    Defender { command: Option<PathBuf> },
+}
+
+impl AntivirusSettings {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AntivirusSettings::Clamd { .. } => "clamd",
+            AntivirusSettings::Defender { .. } => "Microsoft Defender",
+        }
+    }
+}
+
+/// Scans the file and returns the name of the threat found in it, if any.
+pub fn scan(settings: &AntivirusSettings, file_path: &Path) -> io::Result<Option<String>> {
+    match settings {
+        AntivirusSettings::Clamd { socket } => clamd(socket.as_deref().unwrap_or(CLAMD_SOCKET), file_path),
+        AntivirusSettings::Defender { command } => {
+            defender(command.as_deref().unwrap_or(Path::new(DEFENDER_COMMAND)), file_path)
+        }
+    }
+}
+
+fn clamd(socket: &str, file_path: &Path) -> io::Result<Option<String>> {
+    #[cfg(unix)]
+    {
+        if !socket.contains(':') {
+            let stream = std::os::unix::net::UnixStream::connect(socket)?;
+            stream.set_read_timeout(Some(TIMEOUT))?;
+            stream.set_write_timeout(Some(TIMEOUT))?;
+            return instream(stream, file_path);
+        }
+    }
+    let stream = TcpStream::connect(socket)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    instream(stream, file_path)
+}
+
+/// Streams the file to clamd, so the daemon doesn't need permission to read it.
+fn instream<S: Read + Write>(mut stream: S, file_path: &Path) -> io::Result<Option<String>> {
+    let mut file = File::open(file_path)?;
+    stream.write_all(b"zINSTREAM\0")?;
+    let mut chunk = vec![0; CHUNK];
+    loop {
+        let read = file.read(&mut chunk)?;
+        stream.write_all(&(read as u32).to_be_bytes())?;
+        if read == 0 {
+            break;
+        }
+        stream.write_all(&chunk[..read])?;
+    }
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let response = response.trim_end_matches(['\0', '\n']).trim_start_matches("stream: ");
+    match response {
+        "OK" => Ok(None),
+        _ => match response.strip_suffix(" FOUND") {
+            Some(threat) => Ok(Some(threat.to_string())),
+            None => Err(io::Error::other(format!("clamd: {}", response))),
+        },
+    }
+}
+
+/// Runs `MpCmdRun -Scan`, which exits with 2 when it found a threat. Remediation is
+/// disabled so that the file is quarantined by the organizer, not deleted by Defender.
+fn defender(command: &Path, file_path: &Path) -> io::Result<Option<String>> {
+    let output = Command::new(command)
+        .args(["-Scan", "-ScanType", "3", "-DisableRemediation", "-File"])
+        .arg(file_path)
+        .output()?;
+    match output.status.code() {
+        Some(0) => Ok(None),
+        Some(2) => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let threat = stdout
+                .lines()
+                .find_map(|line| line.trim().strip_prefix("Threat"))
+                .and_then(|line| line.split_once(':'))
+                .map(|(_, name)| name.trim().to_string());
+            Ok(Some(threat.unwrap_or_else(|| "a threat".to_string())))
+        }
+        _ => Err(io::Error::other(format!(
+            "Microsoft Defender failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))),
+    }
+}
@@ -0,0 +1,244 @@
+use crate::templates;
+use crate::throttle::Throttled;
+use chrono::{DateTime, Local};
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tracing::{debug, info};
+
+/// Serializes writes to compression archives, which several workers may append to at once.
+static ARCHIVE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Unpacks `.zip`, `.tar`, `.tar.gz`/`.tgz` and `.7z` archives after they were filed.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ExtractAction {
+    /// Where to unpack to, relative to the archive's folder. Supports destination placeholders.
+    #[serde(default = "default_destination")]
+    pub destination: String,
+    /// Only extract archives containing at least one entry matching this glob, e.g. `*.pdf`.
+    pub only_if_contains: Option<String>,
+    /// Delete the archive after extracting it instead of keeping it in its destination.
+    #[serde(default)]
+    pub delete_archive: bool,
+}
+
+fn default_destination() -> String {
+    "{stem}".to_string()
+}
+
+/// Bundles files into dated archives in their destination instead of moving them there.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CompressAction {
+    #[serde(default)]
+    pub format: CompressFormat,
+    /// How files are grouped into archives, by their modification time.
+    #[serde(default)]
+    pub group_by: GroupBy,
+    /// Leave files alone until they have not been modified for this many days.
+    pub older_than_days: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+pub enum CompressFormat {
+    #[default]
+    #[serde(rename = "tar.zst")]
+    TarZst,
+    #[serde(rename = "zip")]
+    Zip,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    Day,
+    Week,
+    #[default]
+    Month,
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Zip,
+    Tar,
+    TarGz,
+    SevenZip,
+}
+
+fn format_of(path: &Path) -> Option<Format> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    if name.ends_with(".zip") {
+        Some(Format::Zip)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(Format::TarGz)
+    } else if name.ends_with(".tar") {
+        Some(Format::Tar)
+    } else if name.ends_with(".7z") {
+        Some(Format::SevenZip)
+    } else {
+        None
+    }
+}
+
+/// Extracts `archive` as configured and returns the folder it was unpacked into; deleting the
+/// archive for `delete_archive` is left to the caller.
+pub fn extract(archive: &Path, action: &ExtractAction, templates: &templates::Settings) -> std::io::Result<Option<PathBuf>> {
+    let Some(format) = format_of(archive) else {
+        debug!(file = ?archive, "Not a supported archive, skipping extraction");
+        return Ok(None);
+    };
+    
+    if let Some(pattern) = &action.only_if_contains {
+        let pattern = glob::Pattern::new(pattern).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let entries = list_entries(archive, format)?;
+        let contains_match = entries.iter().any(|entry| {
+            let name = Path::new(entry).file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+            pattern.matches(entry) || pattern.matches(&name)
+        });
+        if !contains_match {
+            debug!(file = ?archive, "Archive has no entries matching {}, skipping extraction", pattern);
+            return Ok(None);
+        }
+    }
+    
+    let parent = archive.parent().unwrap_or(Path::new("."));
+    let destination = parent.join(templates::expand_destination(&action.destination, archive, templates));
+    fs::create_dir_all(&destination)?;
+    match format {
+        Format::Zip => zip::ZipArchive::new(File::open(archive)?)
+            .and_then(|mut zip| zip.extract(&destination))
+            .map_err(std::io::Error::other)?,
+        Format::Tar => tar::Archive::new(File::open(archive)?).unpack(&destination)?,
+        Format::TarGz => tar::Archive::new(GzDecoder::new(File::open(archive)?)).unpack(&destination)?,
+        Format::SevenZip => sevenz_rust::decompress_file(archive, &destination).map_err(std::io::Error::other)?,
+    }
+    info!(file = ?archive, destination = ?destination, "Extracted archive");
+    Ok(Some(destination))
+}
+
+fn list_entries(archive: &Path, format: Format) -> std::io::Result<Vec<String>> {
+    let names = match format {
+        Format::Zip => {
+            let zip = zip::ZipArchive::new(File::open(archive)?).map_err(std::io::Error::other)?;
+            zip.file_names().map(str::to_string).collect()
+        }
+        Format::Tar => tar_entries(tar::Archive::new(File::open(archive)?))?,
+        Format::TarGz => tar_entries(tar::Archive::new(GzDecoder::new(File::open(archive)?)))?,
+        Format::SevenZip => {
+            let reader = sevenz_rust::SevenZReader::open(archive, sevenz_rust::Password::empty())
+                .map_err(std::io::Error::other)?;
+            reader.archive().files.iter().map(|entry| entry.name().to_string()).collect()
+        }
+    };
+    Ok(names)
+}
+
+fn tar_entries<R: std::io::Read>(mut archive: tar::Archive<R>) -> std::io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in archive.entries()? {
+        names.push(entry?.path()?.to_string_lossy().into_owned());
+    }
+    Ok(names)
+}
+
+/// Adds `file_path` to the archive for its group in `destination` and deletes it.
+/// Returns the archive's path, or `None` when the file is not old enough yet.
+pub fn compress(file_path: &Path, destination: &Path, action: &CompressAction) -> std::io::Result<Option<PathBuf>> {
+    let modified = fs::metadata(file_path)?.modified()?;
+    if let Some(days) = action.older_than_days {
+        let age = SystemTime::now().duration_since(modified).unwrap_or_default();
+        if age < Duration::from_secs(days * 24 * 60 * 60) {
+            return Ok(None);
+        }
+    }
+    
+    let date = DateTime::<Local>::from(modified);
+    let group = match action.group_by {
+        GroupBy::Day => date.format("%Y-%m-%d"),
+        GroupBy::Week => date.format("%G-W%V"),
+        GroupBy::Month => date.format("%Y-%m"),
+    };
+    let extension = match action.format {
+        CompressFormat::TarZst => "tar.zst",
+        CompressFormat::Zip => "zip",
+    };
+    let archive = destination.join(format!("{}.{}", group, extension));
+    let name = file_path.file_name().unwrap().to_string_lossy().into_owned();
+    
+    fs::create_dir_all(destination)?;
+    let _guard = ARCHIVE_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    match action.format {
+        CompressFormat::TarZst => append_tar_zst(&archive, file_path, &name)?,
+        CompressFormat::Zip => append_zip(&archive, file_path, &name)?,
+    }
+    fs::remove_file(file_path)?;
+    info!(file = ?file_path, archive = ?archive, "Compressed file");
+    Ok(Some(archive))
+}
+
+/// Zstandard streams cannot be appended to, so the archive is rewritten next to
+/// the old one and swapped in once it is complete.
+fn append_tar_zst(archive: &Path, file_path: &Path, name: &str) -> std::io::Result<()> {
+    let partial = archive.with_file_name(format!(".{}.part", archive.file_name().unwrap().to_string_lossy()));
+    let mut builder = tar::Builder::new(zstd::Encoder::new(File::create(&partial)?, 0)?);
+    let mut names = Vec::new();
+    if archive.exists() {
+        let mut existing = tar::Archive::new(zstd::Decoder::new(File::open(archive)?)?);
+        for entry in existing.entries()? {
+            let mut entry = entry?;
+            let header = entry.header().clone();
+            names.push(entry.path()?.to_string_lossy().into_owned());
+            builder.append(&header, Throttled(&mut entry))?;
+        }
+    }
+    let name = unique_entry_name(name, &names);
+    if file_path.is_dir() {
+        builder.append_path_with_name(file_path, name)?;
+    } else {
+        let file = File::open(file_path)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata(&file.metadata()?);
+        builder.append_data(&mut header, name, Throttled(file))?;
+    }
+    let file = builder.into_inner()?.finish()?;
+    file.sync_all()?;
+    fs::rename(&partial, archive)
+}
+
+fn append_zip(archive: &Path, file_path: &Path, name: &str) -> std::io::Result<()> {
+    let (mut writer, names) = if archive.exists() {
+        let names = zip::ZipArchive::new(File::open(archive)?)
+            .map_err(std::io::Error::other)?
+            .file_names()
+            .map(str::to_string)
+            .collect();
+        let file = File::options().read(true).write(true).open(archive)?;
+        (zip::ZipWriter::new_append(file).map_err(std::io::Error::other)?, names)
+    } else {
+        (zip::ZipWriter::new(File::create(archive)?), Vec::new())
+    };
+    let options = zip::write::FileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .large_file(fs::metadata(file_path)?.len() >= u32::MAX as u64);
+    writer
+        .start_file(unique_entry_name(name, &names), options)
+        .map_err(std::io::Error::other)?;
+    std::io::copy(&mut Throttled(File::open(file_path)?), &mut writer)?;
+    writer.finish().map_err(std::io::Error::other)?.sync_all()
+}
+
+/// Picks an entry name not yet used in the archive, e.g. `app (1).log`.
+fn unique_entry_name(name: &str, existing: &[String]) -> String {
+    if !existing.iter().any(|entry| entry == name) {
+        return name.to_string();
+    }
+    let path = Path::new(name);
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let extension = path.extension().map(|extension| format!(".{}", extension.to_string_lossy()));
+    (1..)
+        .map(|n| format!("{} ({}){}", stem, n, extension.as_deref().unwrap_or("")))
+        .find(|candidate| !existing.iter().any(|entry| entry == candidate))
+        .unwrap()
+}
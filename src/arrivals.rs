@@ -0,0 +1,43 @@
+use crate::paths;
+use crate::statefile;
+use chrono::{DateTime, Local, TimeZone};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const ARRIVALS_FILE: &str = "new_only.json";
+
+/// The moment from which files in `root` count as new: when the daemon first sorted it
+/// with `--new-only`, kept across restarts so files that arrived while it was stopped are
+/// still sorted. `since` replaces the stored moment.
+pub fn high_water_mark(root: &Path, since: Option<DateTime<Local>>) -> io::Result<DateTime<Local>> {
+    let path = paths::data_dir().join(ARRIVALS_FILE);
+    // Daemons of other folders may note theirs meanwhile.
+    let _lock = statefile::lock(&path)?;
+    let mut marks: BTreeMap<PathBuf, i64> = fs::read_to_string(&path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default();
+    let stored = marks.get(root).and_then(|mark| Local.timestamp_opt(*mark, 0).single());
+    if let (Some(stored), None) = (stored, since) {
+        return Ok(stored);
+    }
+    let mark = since.unwrap_or_else(Local::now);
+    marks.insert(root.to_path_buf(), mark.timestamp());
+    statefile::write(&path, serde_json::to_string_pretty(&marks)?.as_bytes())?;
+    Ok(mark)
+}
+
+/// Whether `path` was created at or after `mark`.
+pub fn created_since(path: &Path, mark: SystemTime) -> bool {
+    created(path).is_some_and(|created| created >= mark)
+}
+
+/// When `path` was created. Where the file system doesn't keep creation times, the
+/// modification time stands in for it.
+pub fn created(path: &Path) -> Option<SystemTime> {
+    let metadata = fs::symlink_metadata(path).ok()?;
+    metadata.created().or_else(|_| metadata.modified()).ok()
+}
@@ -0,0 +1,110 @@
+use crate::paths;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+pub const KEY_FILE: &str = "audit.key";
+
+/// Audit trail settings in the user's `config.json`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AuditSettings {
+    /// Sign the move index after this many new entries.
+    #[serde(default = "default_sign_every")]
+    pub sign_every: u64,
+    /// Ed25519 signing key; defaults to `audit.key` in Organizer's config directory.
+    pub key_file: Option<PathBuf>,
+}
+
+impl Default for AuditSettings {
+    fn default() -> Self {
+        AuditSettings {
+            sign_every: default_sign_every(),
+            key_file: None,
+        }
+    }
+}
+
+fn default_sign_every() -> u64 {
+    100
+}
+
+impl AuditSettings {
+    pub fn key_path(&self) -> PathBuf {
+        self.key_file
+            .clone()
+            .unwrap_or_else(|| paths::config_dir().join(KEY_FILE))
+    }
+}
+
+/// Hash linking an index entry to all entries before it.
+pub fn chain_hash(previous: Option<&str>, fields: &[&str]) -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(previous.unwrap_or_default().as_bytes());
+    for field in fields {
+        hasher.update(&[0]);
+        hasher.update(field.as_bytes());
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
+pub fn load_key(settings: &AuditSettings) -> Option<SigningKey> {
+    let contents = fs::read_to_string(settings.key_path()).ok()?;
+    let seed: [u8; 32] = decode_hex(contents.trim())?.try_into().ok()?;
+    Some(SigningKey::from_bytes(&seed))
+}
+
+/// Creates a new signing key, refusing to overwrite an existing one.
+pub fn generate_key(settings: &AuditSettings) -> std::io::Result<VerifyingKey> {
+    let path = settings.key_path();
+    if path.exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{:?} already exists", path),
+        ));
+    }
+    let mut seed = [0u8; 32];
+    getrandom::getrandom(&mut seed).map_err(std::io::Error::other)?;
+    let key = SigningKey::from_bytes(&seed);
+    
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, encode_hex(&seed))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(key.verifying_key())
+}
+
+pub fn parse_public_key(text: &str) -> Option<VerifyingKey> {
+    let bytes: [u8; 32] = decode_hex(text.trim())?.try_into().ok()?;
+    VerifyingKey::from_bytes(&bytes).ok()
+}
+
+pub fn sign(key: &SigningKey, hash: &str) -> String {
+    encode_hex(&key.sign(hash.as_bytes()).to_bytes())
+}
+
+pub fn verify(key: &VerifyingKey, hash: &str, signature: &str) -> bool {
+    let Some(bytes) = decode_hex(signature).and_then(|bytes| <[u8; 64]>::try_from(bytes).ok()) else {
+        return false;
+    };
+    key.verify(hash.as_bytes(), &Signature::from_bytes(&bytes)).is_ok()
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,198 @@
+use crate::paths;
+use mlua::{Lua, LuaOptions, StdLib, Table, Value};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Registry key under which the file being sorted is kept for `read_content`.
+const CURRENT_FILE: &str = "organizer_current_file";
+/// Most bytes `read_content` returns to a Lua script.
+const MAX_CONTENT: usize = 16 * 1024 * 1024;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a Lua script or plugin may do beyond deciding from a file's name and metadata.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum Capability {
+    /// Read the contents of the file being sorted.
+    ReadContent,
+    /// Fetch URLs over HTTP.
+    Network,
+    /// Run programs, and with them read and write any file the user can.
+    RunCommands,
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Capability::ReadContent => "read-content",
+            Capability::Network => "network",
+            Capability::RunCommands => "run-commands",
+        })
+    }
+}
+
+impl FromStr for Capability {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "read-content" => Ok(Capability::ReadContent),
+            "network" => Ok(Capability::Network),
+            "run-commands" => Ok(Capability::RunCommands),
+            _ => Err(format!("unknown capability `{}`", name)),
+        }
+    }
+}
+
+/// The capabilities the user gives one script or plugin, from `grants` in `rules.json`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Grant {
+    /// The script or plugin, relative to the rules folder unless absolute.
+    pub path: String,
+    /// The BLAKE3 hash of the file that was granted, so a file changed since, or another file
+    /// of the same name, gets nothing.
+    pub blake3: String,
+    pub capabilities: BTreeSet<Capability>,
+}
+
+pub type Grants = Vec<Grant>;
+
+/// Reads `grants`, pointing out grants by bare file name, which no longer give anything.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Grants, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Listed {
+        List(Grants),
+        ByName(BTreeMap<String, BTreeSet<Capability>>),
+    }
+    match Listed::deserialize(deserializer)? {
+        Listed::List(grants) => Ok(grants),
+        Listed::ByName(_) => Err(D::Error::custom(
+            "`grants` lists the files it gives capabilities to, as { \"path\": ..., \"blake3\": ..., \"capabilities\": [...] }",
+        )),
+    }
+}
+
+/// What `grants` gives the script or plugin at `file`, read as `contents`: the capabilities of
+/// the grant for its path, as long as `contents` are still those that were granted. The
+/// contents are what runs, so a file swapped after it was read gets nothing it wasn't granted.
+pub fn granted(file: &Path, contents: &[u8], grants: &Grants) -> Result<BTreeSet<Capability>, String> {
+    let Ok(file) = fs::canonicalize(file) else { return Ok(BTreeSet::new()) };
+    let Some(grant) = grants.iter().find(|grant| fs::canonicalize(grant_path(&grant.path)).is_ok_and(|path| path == file)) else {
+        return Ok(BTreeSet::new());
+    };
+    let hash = blake3::hash(contents).to_hex().to_string();
+    if !hash.eq_ignore_ascii_case(&grant.blake3) {
+        return Err(format!(
+            "{} changed since it was granted {}; once you checked it, set its `blake3` in `grants` to {}",
+            file.display(),
+            grant.capabilities.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "),
+            hash
+        ));
+    }
+    Ok(grant.capabilities.clone())
+}
+
+fn grant_path(path: &str) -> PathBuf {
+    let path = paths::resolve_path(path);
+    if path.is_absolute() {
+        path
+    } else {
+        paths::rules_dir().join(path)
+    }
+}
+
+/// The capabilities a Lua script declares in `-- capabilities: read-content, network` lines
+/// among the comments at its top.
+pub fn declared(script: &str) -> Result<BTreeSet<Capability>, String> {
+    let mut declared = BTreeSet::new();
+    let header = script.lines().map(str::trim).take_while(|line| line.is_empty() || line.starts_with("--"));
+    for line in header {
+        let Some(list) = line.trim_start_matches('-').trim().strip_prefix("capabilities:") else { continue };
+        for name in list.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            declared.insert(name.parse()?);
+        }
+    }
+    Ok(declared)
+}
+
+/// Fails when the script at `file`, read as `contents`, declares capabilities that `grants`
+/// doesn't give it.
+pub fn check(file: &Path, contents: &[u8], declared: &BTreeSet<Capability>, grants: &Grants) -> Result<(), String> {
+    if declared.is_empty() {
+        return Ok(());
+    }
+    let granted = granted(file, contents, grants)?;
+    let missing: Vec<String> = declared.difference(&granted).map(ToString::to_string).collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    Err(format!("{} needs {}, which `grants` in rules.json doesn't give it", file.display(), missing.join(", ")))
+}
+
+/// A Lua state with only what `granted` allows: the string, table and math libraries and the
+/// clock of `os` always, `read_content(limit)` with `read-content`, `fetch(url)` with
+/// `network`, and the `io` library and the rest of `os` with `run-commands`.
+pub fn sandbox(granted: &BTreeSet<Capability>) -> mlua::Result<Lua> {
+    let mut libs = StdLib::COROUTINE | StdLib::TABLE | StdLib::STRING | StdLib::UTF8 | StdLib::MATH | StdLib::OS;
+    if granted.contains(&Capability::RunCommands) {
+        libs |= StdLib::IO;
+    }
+    let lua = Lua::new_with(libs, LuaOptions::default())?;
+    {
+        let globals = lua.globals();
+        if !granted.contains(&Capability::RunCommands) {
+            // The base library can load and run other files.
+            for name in ["dofile", "loadfile"] {
+                globals.set(name, Value::Nil)?;
+            }
+            // The rest of `os` runs programs, changes files and reads the environment.
+            let os: Table = globals.get("os")?;
+            let clock = lua.create_table()?;
+            for name in ["clock", "date", "difftime", "time"] {
+                clock.set(name, os.get::<_, Value>(name)?)?;
+            }
+            globals.set("os", clock)?;
+        }
+        if granted.contains(&Capability::ReadContent) {
+            globals.set("read_content", lua.create_function(read_content)?)?;
+        }
+        if granted.contains(&Capability::Network) {
+            globals.set("fetch", lua.create_function(fetch)?)?;
+        }
+    }
+    Ok(lua)
+}
+
+/// Tells `read_content` which file the script is deciding for.
+pub fn set_current_file(lua: &Lua, file: &str) -> mlua::Result<()> {
+    lua.set_named_registry_value(CURRENT_FILE, file)
+}
+
+/// Returns up to `limit` bytes from the start of the file being sorted, by default as many
+/// as `MAX_CONTENT` allows.
+fn read_content<'lua>(lua: &'lua Lua, limit: Option<usize>) -> mlua::Result<mlua::String<'lua>> {
+    let file: String = lua.named_registry_value(CURRENT_FILE)?;
+    let limit = limit.unwrap_or(MAX_CONTENT).min(MAX_CONTENT) as u64;
+    let mut content = Vec::new();
+    File::open(file)
+        .and_then(|file| file.take(limit).read_to_end(&mut content))
+        .map_err(mlua::Error::external)?;
+    lua.create_string(&content)
+}
+
+fn fetch(_: &Lua, url: String) -> mlua::Result<String> {
+    ureq::get(&url)
+        .timeout(FETCH_TIMEOUT)
+        .call()
+        .map_err(mlua::Error::external)?
+        .into_string()
+        .map_err(mlua::Error::external)
+}
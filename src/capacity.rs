@@ -0,0 +1,165 @@
+use crate::diskspace;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long what a destination holds is trusted for, to notice files removed from folders
+/// below it; files removed from the destination itself are noticed right away.
+const REMEASURE_AFTER: Duration = Duration::from_secs(15 * 60);
+
+/// How much a rule's destination may hold, from its `max_dest_size` and `max_files`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Capacity {
+    pub max_bytes: Option<u64>,
+    pub max_files: Option<u64>,
+}
+
+/// A destination reaching its cap or having room again, for alerts.
+pub enum CapacityEvent {
+    Full { folder: PathBuf, files: u64, bytes: u64 },
+    Available { folder: PathBuf, files: u64, bytes: u64 },
+}
+
+#[derive(Clone, Copy)]
+struct Usage {
+    files: u64,
+    bytes: u64,
+    measured: Instant,
+    /// When the destination itself last changed as far as the monitor knows.
+    modified: Option<SystemTime>,
+}
+
+impl Usage {
+    fn is_current(&self, folder: &Path) -> bool {
+        self.measured.elapsed() < REMEASURE_AFTER && self.modified == modified(folder)
+    }
+}
+
+/// A file counted against a destination's cap before it is placed, to give back with
+/// [`CapacityMonitor::release`] when it doesn't get there.
+pub struct Reservation {
+    folder: PathBuf,
+    size: u64,
+}
+
+/// What the capped destinations hold, shared by the workers of a sort. A destination is
+/// measured when first used and counted up as files are placed; the daemon keeps what it
+/// measured between sorts until the destination changes otherwise, and whether it was full,
+/// so it is alerted about once when it fills up.
+#[derive(Default)]
+pub struct CapacityMonitor {
+    usage: Mutex<HashMap<PathBuf, Usage>>,
+    full: Mutex<BTreeMap<PathBuf, bool>>,
+    events: Mutex<Vec<CapacityEvent>>,
+}
+
+impl CapacityMonitor {
+    /// Dry runs count files they don't place, so each one measures the destinations afresh.
+    pub fn start_sort(&self, dry_run: bool) {
+        if dry_run {
+            self.usage().clear();
+        }
+    }
+
+    fn usage(&self) -> MutexGuard<'_, HashMap<PathBuf, Usage>> {
+        self.usage.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Counts `file` against the cap of `folder`, or returns why `folder` can't take it.
+    pub fn reserve(&self, folder: &Path, capacity: &Capacity, file: &Path) -> Result<Reservation, String> {
+        let size = fs::metadata(file).map(|metadata| metadata.len()).unwrap_or_default();
+        // Destinations can be large, so they are walked without holding up other workers.
+        if !self.usage().get(folder).is_some_and(|used| used.is_current(folder)) {
+            let measured = measure(folder);
+            let mut usage = self.usage();
+            // Another worker may have measured it meanwhile and counted files since.
+            if !usage.get(folder).is_some_and(|used| used.is_current(folder)) {
+                usage.insert(folder.to_path_buf(), measured);
+            }
+        }
+        let mut usage = self.usage();
+        let used = usage.entry(folder.to_path_buf()).or_insert_with(|| measure(folder));
+        let over_files = capacity.max_files.filter(|max| used.files + 1 > *max);
+        let over_bytes = capacity.max_bytes.filter(|max| used.bytes + size > *max);
+        let is_full = over_files.is_some() || over_bytes.is_some();
+        let mut full = self.full.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if full.get(folder).copied().unwrap_or(false) != is_full {
+            full.insert(folder.to_path_buf(), is_full);
+            let (folder, files, bytes) = (folder.to_path_buf(), used.files, used.bytes);
+            let event = if is_full {
+                CapacityEvent::Full { folder, files, bytes }
+            } else {
+                CapacityEvent::Available { folder, files, bytes }
+            };
+            self.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(event);
+        }
+        if let Some(max) = over_files {
+            return Err(format!("the destination already holds {} of at most {} files", used.files, max));
+        }
+        if let Some(max) = over_bytes {
+            return Err(format!(
+                "the destination already holds {} of at most {}",
+                diskspace::format_size(used.bytes),
+                diskspace::format_size(max)
+            ));
+        }
+        used.files += 1;
+        used.bytes += size;
+        Ok(Reservation {
+            folder: folder.to_path_buf(),
+            size,
+        })
+    }
+
+    /// Notes that the reserved file is in place, so the change it made to the destination
+    /// isn't taken for someone else's.
+    pub fn placed(&self, reservation: Reservation) {
+        if let Some(used) = self.usage().get_mut(&reservation.folder) {
+            used.modified = modified(&reservation.folder);
+        }
+    }
+
+    /// Gives back what a file that wasn't placed after all took of the cap.
+    pub fn release(&self, reservation: Reservation) {
+        if let Some(used) = self.usage().get_mut(&reservation.folder) {
+            used.files = used.files.saturating_sub(1);
+            used.bytes = used.bytes.saturating_sub(reservation.size);
+        }
+    }
+
+    /// The destinations that filled up or had room again since the last call.
+    pub fn take_events(&self) -> Vec<CapacityEvent> {
+        std::mem::take(&mut *self.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+}
+
+fn modified(folder: &Path) -> Option<SystemTime> {
+    fs::metadata(folder).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// The files below `folder` and their size; nothing when it doesn't exist yet.
+fn measure(folder: &Path) -> Usage {
+    let mut usage = Usage {
+        files: 0,
+        bytes: 0,
+        measured: Instant::now(),
+        modified: modified(folder),
+    };
+    let mut folders = vec![folder.to_path_buf()];
+    while let Some(folder) = folders.pop() {
+        let Ok(entries) = fs::read_dir(&folder) else { continue };
+        for entry in entries.flatten() {
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => folders.push(entry.path()),
+                Ok(file_type) if file_type.is_file() => {
+                    usage.files += 1;
+                    usage.bytes += entry.metadata().map(|metadata| metadata.len()).unwrap_or_default();
+                }
+                _ => {}
+            }
+        }
+    }
+    usage
+}
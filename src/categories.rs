@@ -0,0 +1,59 @@
+use crate::kinds::{self, Dates};
+use crate::rules::Rule;
+use std::collections::HashMap;
+
+/// Renames the top-level folder of rule destinations, e.g. `Images` to `Bilder`, so the
+/// built-in rules and shared rule sets file into folders named in the user's language.
+pub fn localize(rules: &mut [Rule], names: &HashMap<String, String>) {
+    if names.is_empty() {
+        return;
+    }
+    for rule in rules {
+        let (category, rest) = match rule.destination.split_once('/') {
+            Some((category, rest)) => (category, Some(rest)),
+            None => (rule.destination.as_str(), None),
+        };
+        if let Some(name) = names.get(category) {
+            rule.destination = match rest {
+                Some(rest) => format!("{}/{}", name, rest),
+                None => name.clone(),
+            };
+        }
+    }
+}
+
+/// Turns a layout such as `["Photos/{year}/{month}", "Documents/{category}", "Software/"]`
+/// into rules: the first folder of each entry names the kind of files that go there, and
+/// one rule is made for every extension of that kind. Earlier entries win where kinds
+/// overlap.
+pub fn layout_rules(layout: &[String]) -> Result<Vec<Rule>, String> {
+    let mut rules: Vec<Rule> = Vec::new();
+    for entry in layout {
+        let entry = entry.trim_end_matches('/');
+        let folder = entry.split('/').next().unwrap_or_default();
+        let category = kinds::named(folder)
+            .ok_or_else(|| format!("'{}' in the layout is not a known kind of file; use one of {}", folder, kinds::known_names()))?;
+        for (group, extensions) in category.groups {
+            let destination = dated(&entry.replace("{category}", group), category.dates);
+            for extension in *extensions {
+                let pattern = format!(".{}", extension);
+                if !rules.iter().any(|rule| rule.pattern == pattern) {
+                    rules.push(Rule::new(&pattern, &destination));
+                }
+            }
+        }
+    }
+    Ok(rules)
+}
+
+/// Replaces the date placeholders of a layout with the ones for the kind's dates.
+fn dated(destination: &str, dates: Dates) -> String {
+    let source = |part: &str| match (dates, part) {
+        (Dates::Exif, _) => format!("{{exif.{}}}", part),
+        (Dates::Tags, "year") => "{year}".to_string(),
+        _ => format!("{{modified.{}}}", part),
+    };
+    ["year", "month", "day"]
+        .iter()
+        .fold(destination.to_string(), |destination, part| destination.replace(&format!("{{{}}}", part), &source(part)))
+}
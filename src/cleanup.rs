@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// What happens to the folders a recursive sort moved every file out of, from `rules.json`.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct CleanupSettings {
+    /// Remove folders left empty, and their parents once those are empty too; the sorted
+    /// folder itself is always kept.
+    #[serde(default)]
+    pub remove_empty_folders: bool,
+    /// Folders kept even when empty, with the folders inside them, relative to the sorted
+    /// folder unless absolute.
+    #[serde(default)]
+    pub keep: Vec<String>,
+}
+
+/// Removes the folders in `emptied`, the folders files were moved out of, that are empty
+/// now, working up towards `root` without removing it or anything in `keep`.
+pub fn remove_empty(root: &Path, emptied: BTreeSet<PathBuf>, keep: &[PathBuf]) {
+    // Deepest first, so a parent is only looked at after its subfolders.
+    for folder in emptied.into_iter().rev() {
+        let mut folder = folder.as_path();
+        while folder != root && folder.starts_with(root) && !keep.iter().any(|kept| folder.starts_with(kept)) {
+            match remove_if_empty(folder) {
+                Ok(true) => info!(folder = ?folder, "Removed empty folder"),
+                Ok(false) => break,
+                Err(e) => {
+                    warn!("Failed to remove the empty folder {}: {}", folder.display(), e);
+                    break;
+                }
+            }
+            let Some(parent) = folder.parent() else { break };
+            folder = parent;
+        }
+    }
+}
+
+/// Removes `folder` if nothing is in it, not even hidden files.
+fn remove_if_empty(folder: &Path) -> io::Result<bool> {
+    let mut entries = match fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    if entries.next().is_some() {
+        return Ok(false);
+    }
+    // Symlinks to folders are not followed, and never removed.
+    if fs::symlink_metadata(folder)?.file_type().is_symlink() {
+        return Ok(false);
+    }
+    fs::remove_dir(folder)?;
+    Ok(true)
+}
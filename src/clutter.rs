@@ -0,0 +1,140 @@
+use crate::duplicates;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How often the daemon measures the watched folder.
+pub const MEASURE_EVERY: Duration = Duration::from_secs(60 * 60);
+
+/// Days of scores kept in the trend shown by `ctl stats` and the dashboards.
+pub const HISTORY_DAYS: i64 = 30;
+
+/// Files left in a folder that count as half as cluttered as it can get.
+const CROWDED: f64 = 50.0;
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// How untidy a watched folder is, from the files a sort left in it.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Clutter {
+    pub time: DateTime<Local>,
+    pub folder: String,
+    /// Files left in the folder.
+    pub files: usize,
+    /// Of those, files no rule matches.
+    pub unmatched: usize,
+    pub ages: Ages,
+    /// Files with the same contents as another file left in the folder.
+    pub duplicates: usize,
+    /// From 0, tidy, to 100.
+    pub score: u32,
+}
+
+/// The files left in a folder by when they were last modified.
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+pub struct Ages {
+    pub week: usize,
+    pub month: usize,
+    pub year: usize,
+    pub older: usize,
+}
+
+/// The latest measurement of a folder and how it changed.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Trend {
+    #[serde(flatten)]
+    pub current: Clutter,
+    /// Change of the score since a week and a month ago, when it was measured then; below 0 is tidier.
+    pub week: Option<i64>,
+    pub month: Option<i64>,
+    /// One score per day of the last `HISTORY_DAYS`, oldest first.
+    pub history: Vec<DailyScore>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DailyScore {
+    pub day: String,
+    pub score: u32,
+}
+
+/// Measures the clutter of `folder` from the files left in it; `matched` tells whether a rule
+/// matches a file.
+pub fn measure(folder: &Path, files: &[PathBuf], matched: impl Fn(&Path) -> bool) -> Clutter {
+    let now = SystemTime::now();
+    let mut ages = Ages::default();
+    let mut sizes: HashMap<u64, Vec<&Path>> = HashMap::new();
+    for file in files {
+        let Ok(metadata) = fs::metadata(file) else { continue };
+        let age = metadata.modified().ok().and_then(|modified| now.duration_since(modified).ok()).unwrap_or_default();
+        match age {
+            age if age < Duration::from_secs(7 * DAY) => ages.week += 1,
+            age if age < Duration::from_secs(30 * DAY) => ages.month += 1,
+            age if age < Duration::from_secs(365 * DAY) => ages.year += 1,
+            _ => ages.older += 1,
+        }
+        if metadata.is_file() {
+            sizes.entry(metadata.len()).or_default().push(file);
+        }
+    }
+    // Only files of the same size can have the same contents, so only those are read.
+    let mut duplicates = 0;
+    for candidates in sizes.into_values().filter(|candidates| candidates.len() > 1) {
+        let mut hashes = HashMap::new();
+        for file in candidates {
+            if let Ok(hash) = duplicates::hash_file(file) {
+                *hashes.entry(hash).or_insert(0) += 1;
+            }
+        }
+        duplicates += hashes.into_values().map(|count: usize| count - 1).sum::<usize>();
+    }
+    let unmatched = files.iter().filter(|file| !matched(file)).count();
+    let mut clutter = Clutter {
+        time: Local::now(),
+        folder: folder.to_string_lossy().into_owned(),
+        files: files.len(),
+        unmatched,
+        ages,
+        duplicates,
+        score: 0,
+    };
+    clutter.score = score(&clutter);
+    clutter
+}
+
+/// A quarter each for how many files are left, and for the shares of them that no rule
+/// matches, that are older than a month and that are duplicates.
+fn score(clutter: &Clutter) -> u32 {
+    if clutter.files == 0 {
+        return 0;
+    }
+    let files = clutter.files as f64;
+    let crowded = files / (files + CROWDED);
+    let stale = (clutter.ages.year + clutter.ages.older) as f64 / files;
+    let parts = crowded + clutter.unmatched as f64 / files + stale + clutter.duplicates as f64 / files;
+    (parts * 25.0).round() as u32
+}
+
+impl Trend {
+    /// `history` holds the measurements of the folder of the last `HISTORY_DAYS`, oldest
+    /// first, one per day.
+    pub fn new(current: Clutter, history: Vec<Clutter>) -> Trend {
+        let change = |days: i64| {
+            let before = current.time.date_naive() - chrono::Duration::days(days);
+            // The newest measurement from that day or before it.
+            let then = history.iter().rev().find(|clutter| clutter.time.date_naive() <= before)?;
+            Some(current.score as i64 - then.score as i64)
+        };
+        let (week, month) = (change(7), change(30));
+        let history = history
+            .iter()
+            .map(|clutter| DailyScore {
+                day: clutter.time.format("%Y-%m-%d").to_string(),
+                score: clutter.score,
+            })
+            .collect();
+        Trend { current, week, month, history }
+    }
+}
@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Kilobytes read from the start of a file for a rule's content conditions, unless the rule
+/// sets `content_kb`.
+pub const DEFAULT_KB: u64 = 64;
+
+/// The text a rule's `contains` and `matches_content` look at: the first `kb` kilobytes of
+/// the file, or the whole text of PDFs in builds with the `pdf` feature.
+pub fn text(file_path: &Path, kb: u64) -> io::Result<String> {
+    let mut head = Vec::new();
+    File::open(file_path)?.take(kb.saturating_mul(1024)).read_to_end(&mut head)?;
+    if head.starts_with(b"%PDF-") {
+        if let Some(text) = pdf_text(file_path)? {
+            return Ok(text);
+        }
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+/// The text of one file for the content conditions of every rule tried on it, read once for
+/// each `content_kb` the rules ask for rather than once for each rule.
+pub struct Texts<'a> {
+    file_path: &'a Path,
+    read: HashMap<u64, Option<String>>,
+}
+
+impl<'a> Texts<'a> {
+    pub fn new(file_path: &'a Path) -> Self {
+        Texts {
+            file_path,
+            read: HashMap::new(),
+        }
+    }
+
+    pub fn file_path(&self) -> &'a Path {
+        self.file_path
+    }
+
+    /// The text of the first `kb` kilobytes, as [`text`] reads it, or `None` when the file
+    /// can't be read.
+    pub fn get(&mut self, kb: u64) -> Option<&str> {
+        let file_path = self.file_path;
+        self.read.entry(kb).or_insert_with(|| text(file_path, kb).ok()).as_deref()
+    }
+}
+
+#[cfg(feature = "pdf")]
+fn pdf_text(file_path: &Path) -> io::Result<Option<String>> {
+    pdf_extract::extract_text(file_path)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+/// Without the `pdf` feature the raw bytes are searched, which only finds text PDFs don't compress.
+#[cfg(not(feature = "pdf"))]
+fn pdf_text(_file_path: &Path) -> io::Result<Option<String>> {
+    Ok(None)
+}
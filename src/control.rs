@@ -0,0 +1,481 @@
+use crate::clutter::Trend;
+use crate::events::Event;
+use crate::paths;
+use crate::report::{Outcome, Totals};
+use crate::timing::{self, Timings};
+use chrono::{DateTime, Local};
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::thread;
+use tracing::{debug, info, warn};
+
+/// Commands accepted by a running daemon.
+#[derive(Subcommand, Clone, Copy)]
+pub enum ControlCommand {
+    /// Stop sorting until resumed; a sort in progress finishes first
+    Pause,
+    /// Continue sorting after a pause
+    Resume,
+    /// Sort right away instead of waiting for the next interval
+    SortNow,
+    /// Re-read the rules and restart watching with them
+    ReloadRules,
+    /// Show what the daemon has done since it started
+    Stats,
+    /// Show the files waiting in the sort in progress and the latest results, as JSON
+    Activity,
+}
+
+/// How many of the latest results, errors and waiting files `activity` answers with.
+const RECENT: usize = 100;
+
+impl ControlCommand {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ControlCommand::Pause => "pause",
+            ControlCommand::Resume => "resume",
+            ControlCommand::SortNow => "sort-now",
+            ControlCommand::ReloadRules => "reload-rules",
+            ControlCommand::Stats => "stats",
+            ControlCommand::Activity => "activity",
+        }
+    }
+
+    fn parse(command: &str) -> Option<ControlCommand> {
+        [
+            ControlCommand::Pause,
+            ControlCommand::Resume,
+            ControlCommand::SortNow,
+            ControlCommand::ReloadRules,
+            ControlCommand::Stats,
+            ControlCommand::Activity,
+        ]
+        .into_iter()
+        .find(|candidate| candidate.as_str() == command)
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct DaemonStats {
+    pub started: DateTime<Local>,
+    pub paused: bool,
+    /// Whether a sort is in progress, which a pause lets finish.
+    pub sorting: bool,
+    pub runs: u64,
+    pub last_run: Option<DateTime<Local>>,
+    #[serde(flatten)]
+    pub totals: Totals,
+    /// How untidy the watched folder is, and how that changed.
+    pub clutter: Option<Trend>,
+    /// Time spent in each stage of sorting files, by rule.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub timings: Timings,
+}
+
+/// A file the daemon placed, or failed to, as answered to `activity`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ActivityEntry {
+    pub time: DateTime<Local>,
+    pub file: PathBuf,
+    pub rule: Option<String>,
+    /// E.g. `moved`, `copied` or `failed`.
+    pub result: String,
+    pub destination: Option<PathBuf>,
+    pub error: Option<String>,
+}
+
+impl ActivityEntry {
+    /// Describes what happened to a file; `None` for outcomes that leave it in place.
+    fn new(file: &Path, outcome: &Outcome) -> Option<ActivityEntry> {
+        let listed = match outcome {
+            Outcome::Moved { .. } | Outcome::Copied { .. } | Outcome::Staged { .. } | Outcome::Duplicate { .. } | Outcome::Failed { .. } => true,
+            Outcome::Queued { already_queued, .. } => !already_queued,
+            Outcome::Quarantined { planned, .. } | Outcome::Removed { planned, .. } => !planned,
+            _ => false,
+        };
+        if !listed {
+            return None;
+        }
+        let described = outcome.describe();
+        Some(ActivityEntry {
+            time: Local::now(),
+            file: file.to_path_buf(),
+            rule: described.rule.map(str::to_string),
+            result: described.result.to_string(),
+            destination: described.destination.map(Path::to_path_buf),
+            // Why a file was quarantined is listed where errors are.
+            error: described.error.or_else(|| described.detail.map(str::to_string)),
+        })
+    }
+}
+
+/// What a daemon is doing, as answered to `activity`.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Activity {
+    pub paused: bool,
+    /// Files of the sort in progress that are not done yet.
+    pub queued: usize,
+    /// The first of them, in the order they are sorted.
+    pub next: Vec<PathBuf>,
+    /// The latest results, newest first.
+    pub recent: VecDeque<ActivityEntry>,
+    /// The latest failures, newest first.
+    pub errors: VecDeque<ActivityEntry>,
+    /// Files each rule acted on since the daemon started.
+    pub rules: BTreeMap<String, u64>,
+    #[serde(default)]
+    pub clutter: Option<Trend>,
+}
+
+/// The files of the sort in progress, and how many of them are done.
+#[derive(Default)]
+struct Progress {
+    files: Vec<PathBuf>,
+    done: usize,
+}
+
+/// State shared between the daemon loop and the control socket.
+pub struct Control {
+    paused: AtomicBool,
+    sort_now: AtomicBool,
+    reload: AtomicBool,
+    /// Held while a sort runs, so a pause can wait for it to finish.
+    sorting: Mutex<()>,
+    stats: Mutex<DaemonStats>,
+    progress: Mutex<Progress>,
+    activity: Mutex<Activity>,
+    /// Those who follow every event, such as the `--events-file` and the D-Bus service.
+    events: Mutex<Vec<Sender<Event>>>,
+}
+
+/// A sort in progress, until it is dropped. A pause asked for meanwhile takes effect then.
+pub struct Sorting<'a> {
+    control: &'a Control,
+    guard: Option<MutexGuard<'a, ()>>,
+}
+
+impl Drop for Sorting<'_> {
+    fn drop(&mut self) {
+        drop(self.guard.take());
+        if self.control.paused() {
+            self.control.emit(Event::Paused { time: Local::now() });
+        }
+    }
+}
+
+impl Control {
+    pub fn new() -> Arc<Control> {
+        Arc::new(Control {
+            paused: AtomicBool::new(false),
+            sort_now: AtomicBool::new(false),
+            reload: AtomicBool::new(false),
+            sorting: Mutex::new(()),
+            stats: Mutex::new(DaemonStats {
+                started: Local::now(),
+                paused: false,
+                sorting: false,
+                runs: 0,
+                last_run: None,
+                totals: Totals::default(),
+                clutter: None,
+                timings: Timings::new(),
+            }),
+            progress: Mutex::new(Progress::default()),
+            activity: Mutex::new(Activity::default()),
+            events: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Returns whether a sort was requested since the last call.
+    pub fn take_sort_now(&self) -> bool {
+        self.sort_now.swap(false, Ordering::SeqCst)
+    }
+
+    /// Returns whether a rule reload was requested since the last call.
+    pub fn take_reload(&self) -> bool {
+        self.reload.swap(false, Ordering::SeqCst)
+    }
+
+    /// Marks a sort as running until the guard is dropped, unless the daemon is paused. The
+    /// pause is checked under the guard, so no sort starts once `pause` has answered.
+    pub fn start_sorting(&self) -> Option<Sorting<'_>> {
+        let guard = self.sorting.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        (!self.paused()).then_some(Sorting {
+            control: self,
+            guard: Some(guard),
+        })
+    }
+
+    fn is_sorting(&self) -> bool {
+        matches!(self.sorting.try_lock(), Err(TryLockError::WouldBlock))
+    }
+
+    pub fn record_run(&self, totals: &Totals) {
+        let mut stats = self.stats.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        stats.runs += 1;
+        stats.last_run = Some(Local::now());
+        stats.totals.add(totals);
+        *self.progress.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Progress::default();
+        drop(stats);
+        self.emit(Event::ScanFinished {
+            time: Local::now(),
+            processed: totals.processed(),
+            totals: totals.clone(),
+        });
+    }
+
+    /// Notes a sort that stopped short.
+    pub fn record_error(&self, message: String) {
+        self.emit(Event::Error { time: Local::now(), message });
+    }
+
+    /// Notes the latest clutter of the watched folder.
+    pub fn record_clutter(&self, trend: Trend) {
+        self.stats.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clutter = Some(trend.clone());
+        self.activity.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clutter = Some(trend);
+    }
+
+    /// Notes the files a sort is about to go through.
+    pub fn start_sort(&self, files: &[PathBuf]) {
+        *self.progress.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Progress {
+            files: files.to_vec(),
+            done: 0,
+        };
+        self.emit(Event::ScanStarted {
+            time: Local::now(),
+            files: files.len(),
+        });
+    }
+
+    /// Returns every event from now on, in the order they happen, until the receiver is
+    /// dropped.
+    pub fn events(&self) -> Receiver<Event> {
+        let (tx, rx) = mpsc::channel();
+        self.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(tx);
+        rx
+    }
+
+    fn emit(&self, event: Event) {
+        let mut events = self.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        events.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    /// Notes the outcomes of the next file of the sort in progress.
+    pub fn file_done(&self, file: &Path, outcomes: &[Outcome]) {
+        self.progress.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).done += 1;
+        for outcome in outcomes {
+            self.emit(Event::file(file, outcome));
+        }
+        let mut activity = self.activity.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for entry in outcomes.iter().filter_map(|outcome| ActivityEntry::new(file, outcome)) {
+            if entry.result == "failed" {
+                activity.errors.push_front(entry.clone());
+                activity.errors.truncate(RECENT);
+            } else if let Some(rule) = &entry.rule {
+                *activity.rules.entry(rule.clone()).or_default() += 1;
+            }
+            activity.recent.push_front(entry);
+            activity.recent.truncate(RECENT);
+        }
+    }
+
+    pub fn handle(&self, command: ControlCommand) -> String {
+        match command {
+            ControlCommand::Pause => {
+                self.paused.store(true, Ordering::SeqCst);
+                if self.is_sorting() {
+                    "Pausing once the sort in progress has finished".to_string()
+                } else {
+                    self.emit(Event::Paused { time: Local::now() });
+                    "Paused".to_string()
+                }
+            }
+            ControlCommand::Resume => {
+                self.paused.store(false, Ordering::SeqCst);
+                "Resumed".to_string()
+            }
+            ControlCommand::SortNow => {
+                self.sort_now.store(true, Ordering::SeqCst);
+                "Sorting now".to_string()
+            }
+            ControlCommand::ReloadRules => {
+                self.reload.store(true, Ordering::SeqCst);
+                self.sort_now.store(true, Ordering::SeqCst);
+                "Reloading rules".to_string()
+            }
+            ControlCommand::Stats => {
+                let mut stats = self.stats.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+                stats.paused = self.paused();
+                stats.sorting = self.is_sorting();
+                stats.timings = timing::snapshot();
+                serde_json::to_string_pretty(&stats).unwrap_or_else(|e| format!("Failed to serialize stats: {}", e))
+            }
+            ControlCommand::Activity => {
+                let mut activity = self.activity.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+                activity.paused = self.paused();
+                let progress = self.progress.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                let waiting = progress.files.get(progress.done..).unwrap_or_default();
+                activity.queued = waiting.len();
+                activity.next = waiting.iter().take(RECENT).cloned().collect();
+                drop(progress);
+                serde_json::to_string(&activity).unwrap_or_else(|e| format!("Failed to serialize activity: {}", e))
+            }
+        }
+    }
+
+    fn respond<S: Read + Write>(&self, stream: S) -> io::Result<()> {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let response = match ControlCommand::parse(line.trim()) {
+            Some(command) => {
+                // `organizer tui` asks for the activity every second.
+                if matches!(command, ControlCommand::Activity) {
+                    debug!("Control command: {}", command.as_str());
+                } else {
+                    info!("Control command: {}", command.as_str());
+                }
+                self.handle(command)
+            }
+            None => format!("Unknown command '{}'", line.trim()),
+        };
+        let mut stream = reader.into_inner();
+        stream.write_all(response.as_bytes())?;
+        stream.write_all(b"\n")?;
+        stream.flush()
+    }
+}
+
+/// The name of the control socket or pipe of the daemon sorting `root`. It holds the user and
+/// a hash of the folder, so daemons of other users or for other folders don't answer for each
+/// other.
+fn endpoint_name(root: &Path) -> String {
+    let root = std::fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    let folder = blake3::hash(root.to_string_lossy().as_bytes()).to_hex();
+    let user: String = whoami::username()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    format!("organizer-{}-{}", user, &folder[..16])
+}
+
+#[cfg(unix)]
+fn socket_path(root: &Path) -> PathBuf {
+    let directory = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(paths::data_dir);
+    directory.join(format!("{}.sock", endpoint_name(root)))
+}
+
+#[cfg(windows)]
+fn pipe_name(root: &Path) -> String {
+    format!(r"\\.\pipe\{}", endpoint_name(root))
+}
+
+/// Accepts control commands for the daemon sorting `root` on a background thread, for as
+/// long as the daemon runs.
+#[cfg(unix)]
+pub fn listen(control: Arc<Control>, root: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let path = socket_path(root);
+    if UnixStream::connect(&path).is_ok() {
+        warn!("Another daemon is listening on {:?}; control commands go to it", path);
+        return;
+    }
+    // A socket left behind by a daemon that did not shut down cleanly.
+    let _ = std::fs::remove_file(&path);
+    if let Some(parent) = path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn!("Failed to open control socket {:?}: {}", path, e);
+            return;
+        }
+    };
+    let _ = std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600));
+    debug!("Listening for control commands on {:?}", path);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream.and_then(|stream| control.respond(stream)) {
+                Ok(()) => {}
+                Err(e) => debug!("Control connection failed: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(windows)]
+pub fn listen(control: Arc<Control>, root: &Path) {
+    use std::fs::File;
+    use std::os::windows::io::FromRawHandle;
+    use windows_sys::Win32::Foundation::{GetLastError, ERROR_PIPE_CONNECTED, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::Storage::FileSystem::PIPE_ACCESS_DUPLEX;
+    use windows_sys::Win32::System::Pipes::{
+        ConnectNamedPipe, CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+    };
+
+    let name: Vec<u16> = pipe_name(root).encode_utf16().chain(Some(0)).collect();
+    thread::spawn(move || loop {
+        let pipe = unsafe {
+            CreateNamedPipeW(
+                name.as_ptr(),
+                PIPE_ACCESS_DUPLEX,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                PIPE_UNLIMITED_INSTANCES,
+                4096,
+                4096,
+                0,
+                std::ptr::null(),
+            )
+        };
+        if pipe == INVALID_HANDLE_VALUE {
+            warn!("Failed to open control pipe: {}", io::Error::last_os_error());
+            return;
+        }
+        let connected = unsafe { ConnectNamedPipe(pipe, std::ptr::null_mut()) } != 0 || unsafe { GetLastError() } == ERROR_PIPE_CONNECTED;
+        let stream = unsafe { File::from_raw_handle(pipe as _) };
+        if connected {
+            if let Err(e) = control.respond(&stream) {
+                debug!("Control connection failed: {}", e);
+            }
+            // Make sure the client has read the answer before the pipe is closed.
+            let _ = stream.sync_all();
+        }
+    });
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn listen(_control: Arc<Control>, _root: &Path) {}
+
+/// Sends a command to the running daemon sorting `root` and returns its answer.
+pub fn send(root: &Path, command: ControlCommand) -> io::Result<String> {
+    #[cfg(unix)]
+    let mut stream = std::os::unix::net::UnixStream::connect(socket_path(root))?;
+    #[cfg(windows)]
+    let mut stream = std::fs::OpenOptions::new().read(true).write(true).open(pipe_name(root))?;
+    #[cfg(not(any(unix, windows)))]
+    return Err(io::Error::new(io::ErrorKind::Unsupported, "Control commands are not supported on this platform"));
+
+    #[cfg(any(unix, windows))]
+    {
+        writeln!(stream, "{}", command.as_str())?;
+        stream.flush()?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response.trim_end().to_string())
+    }
+}
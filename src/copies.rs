@@ -0,0 +1,195 @@
+use crate::duplicates;
+use crate::index;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Whether a file already at a copy's destination still matches its source.
+pub enum CopyState {
+    UpToDate,
+    /// Copied by an earlier sort, but the source changed since.
+    Stale,
+    /// Some other file is in the way.
+    Foreign,
+}
+
+/// Whether a file in a read-only folder was imported by an earlier sort.
+pub enum Ingest {
+    /// Imported before, from here or from anywhere else, to this destination.
+    Imported(PathBuf),
+    /// Not imported yet; carries the file's hash for recording the import.
+    New(String),
+}
+
+/// Remembers the size, modification time and hash of copied files, so unchanged files are
+/// not copied again and changed ones are. Kept next to the move index; each worker opens
+/// its own connection.
+pub struct CopyLog {
+    connection: Connection,
+}
+
+struct Stamp {
+    size: u64,
+    modified: i64,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> std::io::Result<Stamp> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH).map(|age| age.as_secs() as i64).unwrap_or_default();
+        Ok(Stamp {
+            size: metadata.len(),
+            modified,
+        })
+    }
+}
+
+impl CopyLog {
+    pub fn open() -> rusqlite::Result<CopyLog> {
+        let connection = index::connect()?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS copies (
+                destination TEXT PRIMARY KEY,
+                source TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                modified INTEGER NOT NULL,
+                hash TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS ingests (
+                hash TEXT PRIMARY KEY,
+                source TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                modified INTEGER NOT NULL,
+                destination TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS ingests_source ON ingests (source);",
+        )?;
+        Ok(CopyLog { connection })
+    }
+
+    /// Compares `source` with the file already at `target`, hashing only when the size or
+    /// modification time differ from the last copy.
+    pub fn check(&self, source: &Path, target: &Path) -> std::io::Result<CopyState> {
+        let stamp = Stamp::of(source)?;
+        let previous: Option<(u64, i64, String)> = self
+            .connection
+            .query_row(
+                "SELECT size, modified, hash FROM copies WHERE destination = ?1 AND source = ?2",
+                params![target.to_string_lossy(), source.to_string_lossy()],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()
+            .map_err(to_io)?;
+        let hash = match previous {
+            Some((size, modified, _)) if size == stamp.size && modified == stamp.modified => return Ok(CopyState::UpToDate),
+            Some((_, _, hash)) => {
+                let current = duplicates::hash_file(source)?.to_hex().to_string();
+                if current != hash {
+                    return Ok(CopyState::Stale);
+                }
+                current
+            }
+            // A copy made before the log existed, or by someone else.
+            None => {
+                let current = duplicates::hash_file(source)?;
+                if current != duplicates::hash_file(target)? {
+                    return Ok(CopyState::Foreign);
+                }
+                current.to_hex().to_string()
+            }
+        };
+        self.store(source, target, &stamp, &hash)?;
+        Ok(CopyState::UpToDate)
+    }
+
+    /// Whether `source` changed since it was copied to `target`, for copies that can't be
+    /// read back, such as uploads; `None` when it was never copied there.
+    pub fn changed(&self, source: &Path, target: &Path) -> std::io::Result<Option<bool>> {
+        let stamp = Stamp::of(source)?;
+        let previous: Option<(u64, i64, String)> = self
+            .connection
+            .query_row(
+                "SELECT size, modified, hash FROM copies WHERE destination = ?1 AND source = ?2",
+                params![target.to_string_lossy(), source.to_string_lossy()],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()
+            .map_err(to_io)?;
+        match previous {
+            None => Ok(None),
+            Some((size, modified, _)) if size == stamp.size && modified == stamp.modified => Ok(Some(false)),
+            Some((_, _, hash)) => Ok(Some(duplicates::hash_file(source)?.to_hex().to_string() != hash)),
+        }
+    }
+
+    /// Records that `source` was just copied to `target`.
+    pub fn record(&self, source: &Path, target: &Path) -> std::io::Result<()> {
+        let stamp = Stamp::of(source)?;
+        let hash = duplicates::hash_file(source)?.to_hex().to_string();
+        self.store(source, target, &stamp, &hash)
+    }
+
+    /// Looks up a file from a read-only folder by content. Files seen at the same path with
+    /// the same size and modification time are not hashed again.
+    pub fn ingested(&self, source: &Path) -> std::io::Result<Ingest> {
+        let stamp = Stamp::of(source)?;
+        let seen: Option<String> = self
+            .connection
+            .query_row(
+                "SELECT destination FROM ingests WHERE source = ?1 AND size = ?2 AND modified = ?3",
+                params![source.to_string_lossy(), stamp.size, stamp.modified],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(to_io)?;
+        if let Some(destination) = seen {
+            return Ok(Ingest::Imported(PathBuf::from(destination)));
+        }
+        let hash = duplicates::hash_file(source)?.to_hex().to_string();
+        let imported: Option<String> = self
+            .connection
+            .query_row("SELECT destination FROM ingests WHERE hash = ?1", params![hash], |row| row.get(0))
+            .optional()
+            .map_err(to_io)?;
+        match imported {
+            Some(destination) => {
+                // Seen under another path or on another card; remember where, to skip the hash next time.
+                self.connection
+                    .execute(
+                        "UPDATE ingests SET source = ?1, size = ?2, modified = ?3 WHERE hash = ?4",
+                        params![source.to_string_lossy(), stamp.size, stamp.modified, hash],
+                    )
+                    .map_err(to_io)?;
+                Ok(Ingest::Imported(PathBuf::from(destination)))
+            }
+            None => Ok(Ingest::New(hash)),
+        }
+    }
+
+    /// Records that `source`, with content `hash`, was imported to `destination`.
+    pub fn record_ingest(&self, source: &Path, hash: &str, destination: &Path) -> std::io::Result<()> {
+        let stamp = Stamp::of(source)?;
+        self.connection
+            .execute(
+                "INSERT OR REPLACE INTO ingests (hash, source, size, modified, destination) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![hash, source.to_string_lossy(), stamp.size, stamp.modified, destination.to_string_lossy()],
+            )
+            .map(|_| ())
+            .map_err(to_io)
+    }
+
+    fn store(&self, source: &Path, target: &Path, stamp: &Stamp, hash: &str) -> std::io::Result<()> {
+        self.connection
+            .execute(
+                "INSERT OR REPLACE INTO copies (destination, source, size, modified, hash) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![target.to_string_lossy(), source.to_string_lossy(), stamp.size, stamp.modified, hash],
+            )
+            .map(|_| ())
+            .map_err(to_io)
+    }
+}
+
+fn to_io(error: rusqlite::Error) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, error)
+}
@@ -0,0 +1,104 @@
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+
+/// Parses a point in time given on the command line: an absolute date like
+/// `2024-05-01` or `2024-05-01 13:00`, or an age like `30m`, `12h`, `7d` or `2w`.
+pub fn parse_since(value: &str) -> Result<DateTime<Local>, String> {
+    let value = value.trim();
+    if let Some(age) = parse_age(value) {
+        return Ok(Local::now() - age);
+    }
+    
+    let naive = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S"))
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y-%m-%d").map(|date| date.and_time(NaiveTime::MIN)))
+        .map_err(|_| format!("Invalid time '{}', expected e.g. 2024-05-01, '2024-05-01 13:00' or 7d", value))?;
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .ok_or_else(|| format!("Time '{}' does not exist in the local time zone", value))
+}
+
+/// Parses an age like `30m`, `12h`, `7d` or `2w`.
+pub fn parse_age(value: &str) -> Option<Duration> {
+    let unit = value.chars().last()?;
+    let amount: i64 = value[..value.len() - unit.len_utf8()].parse().ok()?;
+    match unit {
+        'm' => Some(Duration::minutes(amount)),
+        'h' => Some(Duration::hours(amount)),
+        'd' => Some(Duration::days(amount)),
+        'w' => Some(Duration::weeks(amount)),
+        _ => None,
+    }
+}
+
+/// Finds a date such as `2023-04-17`, `20230417` or `IMG_20230417_153012`
+/// in a file name, including the time of day when one follows the date.
+pub fn date_from_filename(name: &str) -> Option<NaiveDateTime> {
+    let bytes = name.as_bytes();
+    for start in 0..bytes.len() {
+        if start > 0 && bytes[start - 1].is_ascii_digit() {
+            continue;
+        }
+        if let Some(date_time) = parse_date_at(bytes, start) {
+            return Some(date_time);
+        }
+    }
+    None
+}
+
+fn parse_date_at(bytes: &[u8], start: usize) -> Option<NaiveDateTime> {
+    let mut pos = start;
+    let year = read_number(bytes, &mut pos, 4)?;
+    if !(1970..=2100).contains(&year) {
+        return None;
+    }
+    let separator = read_separator(bytes, &mut pos, b"-_.");
+    let month = read_number(bytes, &mut pos, 2)?;
+    if read_separator(bytes, &mut pos, b"-_.") != separator {
+        return None;
+    }
+    let day = read_number(bytes, &mut pos, 2)?;
+    let date = NaiveDate::from_ymd_opt(year as i32, month, day)?;
+    
+    match parse_time_at(bytes, pos) {
+        Some(time) => Some(date.and_time(time)),
+        // Digits right after the date mean it was part of a longer number.
+        None if bytes.get(pos).is_some_and(u8::is_ascii_digit) => None,
+        None => Some(date.and_time(NaiveTime::MIN)),
+    }
+}
+
+fn parse_time_at(bytes: &[u8], start: usize) -> Option<NaiveTime> {
+    let mut pos = start;
+    read_separator(bytes, &mut pos, b"-_ T");
+    let hour = read_number(bytes, &mut pos, 2)?;
+    let separator = read_separator(bytes, &mut pos, b"-_.:");
+    let minute = read_number(bytes, &mut pos, 2)?;
+    if read_separator(bytes, &mut pos, b"-_.:") != separator {
+        return None;
+    }
+    let second = read_number(bytes, &mut pos, 2)?;
+    if bytes.get(pos).is_some_and(u8::is_ascii_digit) {
+        return None;
+    }
+    NaiveTime::from_hms_opt(hour, minute, second)
+}
+
+fn read_number(bytes: &[u8], pos: &mut usize, digits: usize) -> Option<u32> {
+    let slice = bytes.get(*pos..*pos + digits)?;
+    if !slice.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    *pos += digits;
+    std::str::from_utf8(slice).ok()?.parse().ok()
+}
+
+fn read_separator(bytes: &[u8], pos: &mut usize, allowed: &[u8]) -> Option<u8> {
+    let byte = *bytes.get(*pos)?;
+    if allowed.contains(&byte) {
+        *pos += 1;
+        Some(byte)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,129 @@
+use crate::control::Control;
+use clap::ValueEnum;
+use std::io;
+use std::sync::Arc;
+
+/// Name the daemon takes on the bus, which is also its interface.
+pub const NAME: &str = "org.mespeet.Organizer1";
+/// Object the interface is served at.
+pub const PATH: &str = "/org/mespeet/Organizer1";
+
+/// Which message bus the daemon is served on. Only the session bus is offered: it is the
+/// user's own, while on the system bus any local user could pause or drive the daemon.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Bus {
+    /// The bus of the desktop session, for a daemon sorting the user's own folders
+    Session,
+}
+
+/// Serves the daemon's controls on `bus` for as long as the process runs, and emits
+/// `FileMoved` for every file it places and `SortingPaused` once a pause takes effect.
+#[cfg(all(feature = "dbus", target_os = "linux"))]
+pub fn serve(control: Arc<Control>, bus: Bus) -> io::Result<()> {
+    service::serve(control, bus)
+}
+
+#[cfg(not(all(feature = "dbus", target_os = "linux")))]
+pub fn serve(_control: Arc<Control>, _bus: Bus) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "D-Bus is not enabled in this build"))
+}
+
+#[cfg(all(feature = "dbus", target_os = "linux"))]
+mod service {
+    use super::{Bus, NAME, PATH};
+    use crate::control::{Control, ControlCommand};
+    use crate::events::Event;
+    use std::io;
+    use std::sync::Arc;
+    use std::thread;
+    use tracing::{debug, warn};
+    use zbus::blocking::connection;
+    use zbus::{interface, SignalContext};
+
+    struct Organizer {
+        control: Arc<Control>,
+    }
+
+    #[interface(name = "org.mespeet.Organizer1")]
+    impl Organizer {
+        /// Sorts right away instead of waiting for the next interval.
+        fn trigger_sort(&self) -> String {
+            self.control.handle(ControlCommand::SortNow)
+        }
+
+        /// Stops sorting until resumed, answering right away; `SortingPaused` follows once a
+        /// sort in progress has finished.
+        fn pause(&self) -> String {
+            self.control.handle(ControlCommand::Pause)
+        }
+
+        fn resume(&self) -> String {
+            self.control.handle(ControlCommand::Resume)
+        }
+
+        /// Re-reads the rules and sorts with them.
+        fn reload(&self) -> String {
+            self.control.handle(ControlCommand::ReloadRules)
+        }
+
+        /// What the daemon has done since it started, as the JSON of `ctl stats`.
+        fn stats(&self) -> String {
+            self.control.handle(ControlCommand::Stats)
+        }
+
+        #[zbus(property)]
+        fn paused(&self) -> bool {
+            self.control.paused()
+        }
+
+        /// A file was moved or copied into `destination` by `rule`.
+        #[zbus(signal)]
+        async fn file_moved(context: &SignalContext<'_>, source: &str, destination: &str, rule: &str) -> zbus::Result<()>;
+
+        /// The daemon was paused and no sort is running any more.
+        #[zbus(signal)]
+        async fn sorting_paused(context: &SignalContext<'_>) -> zbus::Result<()>;
+    }
+
+    fn to_io(error: zbus::Error) -> io::Error {
+        io::Error::other(error.to_string())
+    }
+
+    pub fn serve(control: Arc<Control>, bus: Bus) -> io::Result<()> {
+        let builder = match bus {
+            Bus::Session => connection::Builder::session(),
+        };
+        let organizer = Organizer { control: control.clone() };
+        let connection = builder
+            .and_then(|builder| builder.name(NAME))
+            .and_then(|builder| builder.serve_at(PATH, organizer))
+            .and_then(|builder| builder.build())
+            .map_err(to_io)?;
+        let context = SignalContext::new(connection.inner(), PATH).map_err(to_io)?.into_owned();
+        let events = control.events();
+        debug!("Serving {} on the bus", NAME);
+        // The connection answers calls on its own thread; this one only emits the signals.
+        thread::spawn(move || {
+            let _connection = connection;
+            for event in events {
+                let emitted = match &event {
+                    Event::File {
+                        file,
+                        rule: Some(rule),
+                        result,
+                        destination: Some(destination),
+                        ..
+                    } if result == "moved" || result == "copied" => {
+                        zbus::block_on(Organizer::file_moved(&context, &file.to_string_lossy(), &destination.to_string_lossy(), rule))
+                    }
+                    Event::Paused { .. } => zbus::block_on(Organizer::sorting_paused(&context)),
+                    _ => continue,
+                };
+                if let Err(e) = emitted {
+                    warn!("Failed to emit a signal on the bus: {}", e);
+                }
+            }
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+use crate::duplicates;
+use crate::index;
+use crate::scan::{self, RepositoryPolicy};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::UNIX_EPOCH;
+use tracing::{debug, info};
+
+/// Files below the folders `hardlink_dedupe` destinations link within, by size, with their
+/// hash once one was needed. Kept next to the move index; a folder is read once when it is
+/// first used, and files placed since are added as they arrive. Files are read and hashed
+/// without holding the connection, so workers only wait for each other on the lookups.
+pub struct DedupeIndex {
+    connection: Mutex<Connection>,
+}
+
+impl DedupeIndex {
+    pub fn open() -> rusqlite::Result<DedupeIndex> {
+        let connection = index::connect()?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS dedupe_files (
+                path TEXT PRIMARY KEY,
+                root TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                modified INTEGER NOT NULL,
+                hash TEXT
+            );
+            CREATE INDEX IF NOT EXISTS dedupe_files_size ON dedupe_files (root, size);
+            CREATE TABLE IF NOT EXISTS dedupe_roots (
+                root TEXT PRIMARY KEY
+            );",
+        )?;
+        Ok(DedupeIndex {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Replaces `file` with a hard link to a file of the same contents below `root` on the
+    /// same drive, and returns that file. Without one, `file` is remembered for the next.
+    pub fn link_duplicate(&self, root: &Path, file: &Path) -> io::Result<Option<PathBuf>> {
+        let metadata = fs::symlink_metadata(file)?;
+        if !metadata.is_file() {
+            return Ok(None);
+        }
+        self.read_root(root)?;
+        let root_name = root.to_string_lossy();
+        let candidates: Vec<(String, i64, Option<String>)> = {
+            let connection = self.connection();
+            let mut statement = connection
+                .prepare("SELECT path, modified, hash FROM dedupe_files WHERE root = ?1 AND size = ?2 AND path != ?3")
+                .map_err(io::Error::other)?;
+            let rows = statement
+                .query_map(params![root_name, metadata.len() as i64, file.to_string_lossy()], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })
+                .map_err(io::Error::other)?;
+            rows.collect::<rusqlite::Result<_>>().map_err(io::Error::other)?
+        };
+        let mut hash = None;
+        for (path, modified, known) in candidates {
+            let existing = PathBuf::from(&path);
+            let Ok(existing_metadata) = fs::symlink_metadata(&existing) else {
+                self.forget(&path)?;
+                continue;
+            };
+            if same_file(&metadata, &existing_metadata) {
+                return Ok(None);
+            }
+            if !existing_metadata.is_file() || existing_metadata.len() != metadata.len() || !same_drive(&metadata, &existing_metadata) {
+                continue;
+            }
+            let existing_hash = match known.filter(|_| modified_secs(&existing_metadata) == modified) {
+                Some(known) => known,
+                None => {
+                    let hashed = duplicates::hash_file(&existing)?.to_hex().to_string();
+                    self.remember(root, &existing, &existing_metadata, Some(&hashed))?;
+                    hashed
+                }
+            };
+            if hash.is_none() {
+                hash = Some(duplicates::hash_file(file)?.to_hex().to_string());
+            }
+            let file_hash = hash.as_deref().unwrap_or_default();
+            if existing_hash != file_hash {
+                continue;
+            }
+            match replace_with_link(file, &existing) {
+                Ok(()) => {
+                    info!(file = ?file, existing = ?existing, "Replaced file with a hard link to an identical one");
+                    self.remember(root, file, &fs::symlink_metadata(file)?, Some(file_hash))?;
+                    return Ok(Some(existing));
+                }
+                // Drives that can't hold hard links keep the copy.
+                Err(e) => debug!(file = ?file, "Failed to link to {:?}: {}", existing, e),
+            }
+        }
+        self.remember(root, file, &metadata, hash.as_deref())?;
+        Ok(None)
+    }
+
+    /// Records every file below `root` the first time it is used. Workers that get here at
+    /// the same time may both read the folder; the second finds its files recorded already.
+    fn read_root(&self, root: &Path) -> io::Result<()> {
+        let root_name = root.to_string_lossy();
+        let known = self
+            .connection()
+            .query_row("SELECT 1 FROM dedupe_roots WHERE root = ?1", params![root_name], |_| Ok(()))
+            .optional()
+            .map_err(io::Error::other)?;
+        if known.is_some() || !root.is_dir() {
+            return Ok(());
+        }
+        let files: Vec<(PathBuf, fs::Metadata)> = scan::collect_files(root, true, &[], RepositoryPolicy::Skip, false, None)?
+            .into_iter()
+            .filter_map(|file| fs::metadata(&file).ok().filter(fs::Metadata::is_file).map(|metadata| (file, metadata)))
+            .collect();
+        debug!(root = ?root, "Recording {} files to find duplicates among", files.len());
+        let connection = self.connection();
+        let transaction = connection.unchecked_transaction().map_err(io::Error::other)?;
+        for (file, metadata) in files {
+            transaction
+                .execute(
+                    "INSERT OR IGNORE INTO dedupe_files (path, root, size, modified) VALUES (?1, ?2, ?3, ?4)",
+                    params![file.to_string_lossy(), root_name, metadata.len() as i64, modified_secs(&metadata)],
+                )
+                .map_err(io::Error::other)?;
+        }
+        transaction
+            .execute("INSERT OR IGNORE INTO dedupe_roots (root) VALUES (?1)", params![root_name])
+            .map_err(io::Error::other)?;
+        transaction.commit().map_err(io::Error::other)
+    }
+
+    fn remember(&self, root: &Path, file: &Path, metadata: &fs::Metadata, hash: Option<&str>) -> io::Result<()> {
+        self.connection()
+            .execute(
+                "INSERT OR REPLACE INTO dedupe_files (path, root, size, modified, hash) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    file.to_string_lossy(),
+                    root.to_string_lossy(),
+                    metadata.len() as i64,
+                    modified_secs(metadata),
+                    hash
+                ],
+            )
+            .map(|_| ())
+            .map_err(io::Error::other)
+    }
+
+    fn forget(&self, path: &str) -> io::Result<()> {
+        self.connection()
+            .execute("DELETE FROM dedupe_files WHERE path = ?1", params![path])
+            .map(|_| ())
+            .map_err(io::Error::other)
+    }
+}
+
+/// Links `existing` next to `file` and renames the link over it, so `file` is never missing.
+fn replace_with_link(file: &Path, existing: &Path) -> io::Result<()> {
+    let link = file.with_file_name(format!(".{}.link", file.file_name().unwrap_or_default().to_string_lossy()));
+    fs::hard_link(existing, &link)?;
+    fs::rename(&link, file).map_err(|e| {
+        let _ = fs::remove_file(&link);
+        e
+    })
+}
+
+fn modified_secs(metadata: &fs::Metadata) -> i64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |age| age.as_secs() as i64)
+}
+
+#[cfg(unix)]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(unix)]
+fn same_drive(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev()
+}
+
+/// Elsewhere files already linked are hashed and linked again, and links across drives fail.
+#[cfg(not(unix))]
+fn same_file(_: &fs::Metadata, _: &fs::Metadata) -> bool {
+    false
+}
+
+#[cfg(not(unix))]
+fn same_drive(_: &fs::Metadata, _: &fs::Metadata) -> bool {
+    true
+}
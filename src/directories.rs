@@ -0,0 +1,155 @@
+use crate::duplicates;
+use crate::matching;
+use crate::naming;
+use crate::transfer;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::info;
+
+/// Moves whole folders, such as extracted releases, from the `directories` rules in
+/// `rules.json`. Files inside them are not sorted one by one.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DirectoryRule {
+    /// A glob matched against the folder name, e.g. `*.S[0-9][0-9]E[0-9][0-9]*`.
+    #[serde(rename = "match")]
+    pub pattern: String,
+    /// Regular expression for the folder name; `{1}`, `{2}` and named groups such as
+    /// `{show}` in the destination take what it captured. Folders it doesn't match are left alone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture: Option<String>,
+    /// Folder the matched folder is moved into, keeping its name.
+    pub destination: String,
+    #[serde(default)]
+    pub on_exists: MergeStrategy,
+    /// Seconds nothing in the folder may have changed for before it is moved, so folders
+    /// still being extracted or copied are left for a later sort.
+    #[serde(default = "default_stable_seconds")]
+    pub stable_seconds: u64,
+}
+
+fn default_stable_seconds() -> u64 {
+    60
+}
+
+/// What happens when the destination already has a folder of the same name.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeStrategy {
+    /// Move the contents into the existing folder; files whose name is taken get a number,
+    /// and files that are already there with the same contents are removed.
+    #[default]
+    Merge,
+    /// Move the folder next to the existing one, with a number as in `name (1)`.
+    Number,
+    /// Leave the folder where it is.
+    Skip,
+}
+
+impl DirectoryRule {
+    /// Checks `capture` once when the rules are loaded, rather than for every folder.
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.capture {
+            Some(capture) => Regex::new(capture).map(|_| ()).map_err(|e| format!("invalid directory capture: {}", e)),
+            None => Ok(()),
+        }
+    }
+
+    /// The destination for `folder` with the captured groups filled in, or `None` when the
+    /// rule doesn't match it.
+    pub fn destination_for(&self, folder: &Path) -> Option<String> {
+        let name = folder.file_name()?.to_str()?;
+        if !matching::name_matches(&self.pattern, name) {
+            return None;
+        }
+        match &self.capture {
+            Some(capture) => Some(naming::fill_captures(&self.destination, &Regex::new(capture).ok()?.captures(name)?)),
+            None => Some(self.destination.clone()),
+        }
+    }
+}
+
+/// Whether nothing in `folder`, at any depth, changed in the last `seconds`.
+pub fn settled(folder: &Path, seconds: u64) -> io::Result<bool> {
+    let Some(since) = SystemTime::now().checked_sub(Duration::from_secs(seconds)) else { return Ok(true) };
+    let mut folders = vec![folder.to_path_buf()];
+    while let Some(folder) = folders.pop() {
+        if fs::symlink_metadata(&folder)?.modified()? > since {
+            return Ok(false);
+        }
+        for entry in fs::read_dir(&folder)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                folders.push(entry.path());
+            } else if metadata.modified()? > since {
+                return Ok(false);
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// A move made while placing a folder.
+pub struct Move {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// The whole folder was moved, rather than one of its files.
+    pub folder: bool,
+}
+
+/// Moves `folder` to `target`, or into it as `on_exists` says when it exists. Adds the moves
+/// made to `moves`, one for the whole folder or one for every file merged and none when
+/// skipped, including those made before an error.
+pub fn place(folder: &Path, target: &Path, on_exists: MergeStrategy, settings: &transfer::Settings, moves: &mut Vec<Move>) -> io::Result<()> {
+    settings.protection.check(folder)?;
+    let target = match on_exists {
+        _ if !target.exists() => target.to_path_buf(),
+        MergeStrategy::Skip => return Ok(()),
+        MergeStrategy::Number => duplicates::unique_path(target),
+        MergeStrategy::Merge => return merge(folder, target, settings, moves),
+    };
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(folder, &target).is_ok() {
+        moves.push(Move {
+            from: folder.to_path_buf(),
+            to: target,
+            folder: true,
+        });
+        return Ok(());
+    }
+    // On another filesystem the folder can't be renamed, so its files are moved one by one.
+    merge(folder, &target, settings, moves)
+}
+
+/// Moves everything in `folder` into `target`, and removes `folder` once it is empty.
+fn merge(folder: &Path, target: &Path, settings: &transfer::Settings, moves: &mut Vec<Move>) -> io::Result<()> {
+    fs::create_dir_all(target)?;
+    for entry in fs::read_dir(folder)? {
+        let entry = entry?;
+        let source = entry.path();
+        let destination = target.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            merge(&source, &destination, settings, moves)?;
+            continue;
+        }
+        if destination.is_file() && duplicates::hash_file(&source)? == duplicates::hash_file(&destination)? {
+            fs::remove_file(&source)?;
+            info!(file = ?source, existing = ?destination, "Removed file already in the merged folder");
+            continue;
+        }
+        let destination = duplicates::unique_path(&destination);
+        transfer::move_file(&source, &destination, settings)?;
+        moves.push(Move {
+            from: source,
+            to: destination,
+            folder: false,
+        });
+    }
+    fs::remove_dir(folder)
+}
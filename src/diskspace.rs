@@ -0,0 +1,293 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// When destinations count as full, from `rules.json`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DiskSpaceSettings {
+    /// Free space below which nothing more is placed on a drive, e.g. `5GB` or `10%`.
+    pub min_free: Threshold,
+    /// Free space at which placing resumes; `min_free` when not set. A little more keeps
+    /// rules from switching on and off with every file.
+    pub resume_free: Option<Threshold>,
+    /// Whether the whole sort is checked against `min_free` before anything is placed.
+    #[serde(default)]
+    pub preflight: Preflight,
+}
+
+/// What happens when a sort would leave a destination drive with less than `min_free`.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preflight {
+    /// Only check each file as it is placed, and leave the rest in place once the drive is low.
+    #[default]
+    Off,
+    /// Don't start the sort.
+    Abort,
+    /// Leave the files of the rules that fill the drive in place, and sort the rest.
+    SkipRule,
+}
+
+/// An amount of free space, in bytes or as a share of the drive.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(try_from = "String", into = "String")]
+pub enum Threshold {
+    Bytes(u64),
+    Percent(f64),
+}
+
+impl TryFrom<String> for Threshold {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid amount of space {:?}, expected e.g. 500MB, 5GB or 10%", text);
+        let trimmed = text.trim();
+        if let Some(percent) = trimmed.strip_suffix('%') {
+            let percent: f64 = percent.trim().parse().map_err(|_| invalid())?;
+            return (0.0..=100.0).contains(&percent).then_some(Threshold::Percent(percent)).ok_or_else(invalid);
+        }
+        parse_size(trimmed).map(Threshold::Bytes).ok_or_else(invalid)
+    }
+}
+
+/// Reads an amount of bytes such as `500MB`, `5G` or `1.5TB`.
+pub fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let unit = match unit.trim().to_ascii_uppercase().trim_end_matches('B') {
+        "" => 1u64,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return None,
+    };
+    Some((number * unit as f64) as u64)
+}
+
+impl From<Threshold> for String {
+    fn from(threshold: Threshold) -> String {
+        match threshold {
+            Threshold::Bytes(bytes) => bytes.to_string(),
+            Threshold::Percent(percent) => format!("{}%", percent),
+        }
+    }
+}
+
+impl Threshold {
+    /// The amount in bytes, on a drive of `total` bytes.
+    pub fn bytes(self, total: u64) -> u64 {
+        match self {
+            Threshold::Bytes(bytes) => bytes,
+            Threshold::Percent(percent) => (total as f64 * percent / 100.0) as u64,
+        }
+    }
+}
+
+/// Free and total bytes of the drive `path` is on, for the current user.
+pub fn space(path: &Path) -> io::Result<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let path = std::ffi::CString::new(path.as_os_str().as_bytes()).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let block = widen(stats.f_frsize);
+        Ok((widen(stats.f_bavail) * block, widen(stats.f_blocks) * block))
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::OsStrExt;
+        use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+        let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        let (mut available, mut total) = (0u64, 0u64);
+        if unsafe { GetDiskFreeSpaceExW(wide.as_ptr(), &mut available, &mut total, std::ptr::null_mut()) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((available, total))
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = path;
+        Err(io::Error::new(io::ErrorKind::Unsupported, "free space is not known on this platform"))
+    }
+}
+
+/// The fields of `statvfs` are 32 bits wide on some systems and 64 on others.
+#[cfg(unix)]
+fn widen(value: impl Into<u64>) -> u64 {
+    value.into()
+}
+
+/// The space the placements of a sort need, by destination drive, to check before it starts.
+#[derive(Default)]
+pub struct SpacePlan {
+    drives: BTreeMap<PathBuf, Needed>,
+    /// Drives of the folders seen so far, which take a walk up the tree to find.
+    roots: HashMap<PathBuf, PathBuf>,
+}
+
+#[derive(Default)]
+struct Needed {
+    folder: PathBuf,
+    bytes: u64,
+    rules: BTreeSet<String>,
+}
+
+/// A drive the planned placements would leave with less than `min_free`.
+pub struct Shortfall {
+    pub folder: PathBuf,
+    pub needed: u64,
+    pub free: u64,
+    pub min_free: u64,
+    /// The rules placing files on the drive.
+    pub rules: BTreeSet<String>,
+}
+
+impl fmt::Display for Shortfall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} would take {} but has {} free, of which {} should stay free (rules: {})",
+            self.folder.display(),
+            format_size(self.needed),
+            format_size(self.free),
+            format_size(self.min_free),
+            self.rules.iter().map(String::as_str).collect::<Vec<_>>().join(", ")
+        )
+    }
+}
+
+impl SpacePlan {
+    /// Counts `file` placed in `folder`, which need not exist yet, by `rule`. Moves within a
+    /// drive are renames and take no space.
+    pub fn add(&mut self, rule: &str, file: &Path, folder: &Path, copy: bool) {
+        let Ok(metadata) = fs::metadata(file) else { return };
+        let Some(existing) = folder.ancestors().find(|ancestor| ancestor.exists()) else { return };
+        let drive = self.drive(existing);
+        if !copy && file.parent().is_some_and(|parent| self.drive(parent) == drive) {
+            return;
+        }
+        let needed = self.drives.entry(drive).or_insert_with(|| Needed {
+            folder: existing.to_path_buf(),
+            ..Default::default()
+        });
+        needed.bytes += metadata.len();
+        needed.rules.insert(rule.to_string());
+    }
+
+    fn drive(&mut self, folder: &Path) -> PathBuf {
+        self.roots.entry(folder.to_path_buf()).or_insert_with(|| drive_root(folder)).clone()
+    }
+
+    /// The drives that don't have room for what they are to take with `min_free` to spare.
+    pub fn shortfalls(&self, min_free: Threshold) -> Vec<Shortfall> {
+        self.drives
+            .values()
+            .filter_map(|needed| {
+                let (free, total) = space(&needed.folder).ok()?;
+                let min_free = min_free.bytes(total);
+                (free < needed.bytes.saturating_add(min_free)).then(|| Shortfall {
+                    folder: needed.folder.clone(),
+                    needed: needed.bytes,
+                    free,
+                    min_free,
+                    rules: needed.rules.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// A drive running low or having room again, for alerts.
+pub enum DiskEvent {
+    Low { folder: PathBuf, free: u64 },
+    Recovered { folder: PathBuf, free: u64 },
+}
+
+/// Which drives are too full to place files on, shared by the workers of a sort and kept
+/// between the sorts of a daemon, so each drive is alerted about once when it runs low.
+#[derive(Default)]
+pub struct DiskMonitor {
+    /// Drives by their root, with whether they are too full.
+    low: Mutex<BTreeMap<PathBuf, bool>>,
+    events: Mutex<Vec<DiskEvent>>,
+}
+
+impl DiskMonitor {
+    /// Returns why `file` may not be placed in `folder`, which need not exist yet: the drive
+    /// is too full for anything more, or for `file` with `min_free` to spare. Moves within a
+    /// drive are renames and take no space.
+    pub fn check(&self, file: &Path, folder: &Path, copy: bool, settings: &DiskSpaceSettings) -> Option<String> {
+        let existing = folder.ancestors().find(|ancestor| ancestor.exists())?;
+        let (free, total) = space(existing).ok()?;
+        let drive = drive_root(existing);
+        let mut low = self.low.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let was_low = low.get(&drive).copied().unwrap_or(false);
+        let threshold = if was_low { settings.resume_free.unwrap_or(settings.min_free) } else { settings.min_free };
+        let is_low = free < threshold.bytes(total);
+        if is_low != was_low {
+            low.insert(drive.clone(), is_low);
+            let folder = existing.to_path_buf();
+            let event = if is_low { DiskEvent::Low { folder, free } } else { DiskEvent::Recovered { folder, free } };
+            self.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(event);
+        }
+        if is_low {
+            return Some(format!("only {} free on the destination's drive", format_size(free)));
+        }
+        drop(low);
+        if !copy && file.parent().is_some_and(|parent| drive_root(parent) == drive) {
+            return None;
+        }
+        let size = fs::metadata(file).ok()?.len();
+        (free < settings.min_free.bytes(total).saturating_add(size))
+            .then(|| format!("the file takes {} and the destination's drive has only {} free", format_size(size), format_size(free)))
+    }
+
+    /// The drives that ran low or recovered since the last call.
+    pub fn take_events(&self) -> Vec<DiskEvent> {
+        std::mem::take(&mut *self.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+}
+
+/// The mount point or drive a path is on, to tell drives apart.
+fn drive_root(path: &Path) -> PathBuf {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let device = |path: &Path| std::fs::metadata(path).map(|metadata| metadata.dev()).ok();
+        let own = device(path);
+        let mut root = path;
+        while let Some(parent) = root.parent().filter(|parent| device(parent) == own) {
+            root = parent;
+        }
+        root.to_path_buf()
+    }
+    #[cfg(not(unix))]
+    {
+        path.components().take(2).collect()
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+/// What to do when an identical file already exists in the destination.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicatePolicy {
+    /// Leave the file where it is.
+    Skip,
+    /// Delete the file, keeping only the existing copy.
+    DeleteSource,
+    /// Move the file anyway under a new name.
+    KeepBoth,
+    /// Replace the file with a hard link to the existing copy in the destination.
+    Hardlink,
+}
+
+impl DuplicatePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DuplicatePolicy::Skip => "skip",
+            DuplicatePolicy::DeleteSource => "delete-source",
+            DuplicatePolicy::KeepBoth => "keep-both",
+            DuplicatePolicy::Hardlink => "hardlink",
+        }
+    }
+}
+
+impl fmt::Display for DuplicatePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+pub fn hash_file(path: &Path) -> std::io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+/// Looks for a file in `directory` with the same contents as `file_path`.
+/// Only files of the same size are hashed.
+pub fn find_duplicate(file_path: &Path, directory: &Path) -> std::io::Result<Option<PathBuf>> {
+    if !directory.is_dir() {
+        return Ok(None);
+    }
+    let size = fs::metadata(file_path)?.len();
+    let mut hash = None;
+    
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() || metadata.len() != size {
+            continue;
+        }
+        let source_hash = match hash {
+            Some(hash) => hash,
+            None => *hash.insert(hash_file(file_path)?),
+        };
+        if hash_file(&entry.path())? == source_hash {
+            return Ok(Some(entry.path()));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns `path`, or `name (1).ext`, `name (2).ext`, ... if it is already taken.
+pub fn unique_path(path: &Path) -> PathBuf {
+    if !path.exists() {
+        return path.to_path_buf();
+    }
+    (1..).map(|counter| numbered_path(path, counter)).find(|candidate| !candidate.exists()).unwrap()
+}
+
+/// `path` with a number added to its name, as in `name (1).ext`.
+pub fn numbered_path(path: &Path, counter: u64) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    path.with_file_name(format!("{} ({}){}", stem, counter, extension))
+}
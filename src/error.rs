@@ -0,0 +1,39 @@
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Why a sort stopped before going through the files. Files that fail on their own don't
+/// stop it; they are reported as `Outcome::Failed` and summed up at the end.
+#[derive(Debug, Error)]
+pub enum SortError {
+    /// `rules.json` can't be read or used, or a selected rule is unknown.
+    #[error("{0}")]
+    Config(#[source] io::Error),
+    #[error("{} is not a directory", .0.display())]
+    NotADirectory(PathBuf),
+    #[error("Sort cancelled")]
+    Cancelled,
+    /// A destination drive has no room for the sort, and `preflight` is `abort`.
+    #[error("Not enough space: {0}")]
+    InsufficientSpace(String),
+    /// Listing the folder, or something else every file depends on, failed.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl SortError {
+    pub fn config(message: impl Into<String>) -> SortError {
+        SortError::Config(io::Error::new(io::ErrorKind::InvalidInput, message.into()))
+    }
+}
+
+impl From<SortError> for io::Error {
+    fn from(error: SortError) -> io::Error {
+        match error {
+            SortError::Config(e) | SortError::Io(e) => e,
+            SortError::NotADirectory(_) => io::Error::new(io::ErrorKind::InvalidInput, error.to_string()),
+            SortError::Cancelled => io::Error::new(io::ErrorKind::Interrupted, error.to_string()),
+            SortError::InsufficientSpace(_) => io::Error::other(error.to_string()),
+        }
+    }
+}
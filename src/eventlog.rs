@@ -0,0 +1,109 @@
+use std::io;
+use tracing::Subscriber;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Name the daemon's warnings and errors are written to the Windows Application log under.
+#[cfg(windows)]
+pub const SOURCE: &str = "Organizer";
+
+/// A log layer that writes warnings and errors to the Windows Event Log, where they are
+/// seen when Organizer runs as a service without a console.
+#[cfg(windows)]
+pub fn layer<S>() -> io::Result<Box<dyn Layer<S> + Send + Sync>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    windows::EventLog::open().map(|layer| layer.with_filter(tracing_subscriber::filter::LevelFilter::WARN).boxed())
+}
+
+#[cfg(not(windows))]
+pub fn layer<S>() -> io::Result<Box<dyn Layer<S> + Send + Sync>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    Err(io::Error::new(io::ErrorKind::Unsupported, "The Event Log is only available on Windows"))
+}
+
+/// Registers `SOURCE` with the messages of `EventCreate.exe`, which show each event's text as it is.
+#[cfg(windows)]
+pub fn register() -> io::Result<()> {
+    let key = format!(r"HKLM\SYSTEM\CurrentControlSet\Services\EventLog\Application\{}", SOURCE);
+    let status = std::process::Command::new("reg")
+        .args(["add", &key, "/v", "EventMessageFile", "/t", "REG_EXPAND_SZ", "/d", r"%SystemRoot%\System32\EventCreate.exe", "/f"])
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("Registering the event source failed ({})", status)));
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+pub fn unregister() -> io::Result<()> {
+    let key = format!(r"HKLM\SYSTEM\CurrentControlSet\Services\EventLog\Application\{}", SOURCE);
+    std::process::Command::new("reg").args(["delete", &key, "/f"]).status().map(|_| ())
+}
+
+#[cfg(windows)]
+mod windows {
+    use super::SOURCE;
+    use std::fmt::{Debug, Write};
+    use std::io;
+    use tracing::field::{Field, Visit};
+    use tracing::{Event, Level, Subscriber};
+    use tracing_subscriber::layer::Context;
+    use tracing_subscriber::Layer;
+    use windows_sys::Win32::Foundation::HANDLE;
+    use windows_sys::Win32::System::EventLog::{
+        RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE,
+    };
+
+    /// `EventCreate.exe` shows the first string of events with IDs from 1 to 1000.
+    const EVENT_ID: u32 = 1;
+
+    pub struct EventLog {
+        source: HANDLE,
+    }
+
+    impl EventLog {
+        pub fn open() -> io::Result<EventLog> {
+            let name: Vec<u16> = SOURCE.encode_utf16().chain(Some(0)).collect();
+            let source = unsafe { RegisterEventSourceW(std::ptr::null(), name.as_ptr()) };
+            if source == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(EventLog { source })
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for EventLog {
+        fn on_event(&self, event: &Event<'_>, _context: Context<'_, S>) {
+            let kind = match *event.metadata().level() {
+                Level::ERROR => EVENTLOG_ERROR_TYPE,
+                Level::WARN => EVENTLOG_WARNING_TYPE,
+                _ => EVENTLOG_INFORMATION_TYPE,
+            };
+            let mut message = Message::default();
+            event.record(&mut message);
+            let text: Vec<u16> = message.0.encode_utf16().chain(Some(0)).collect();
+            let strings = [text.as_ptr()];
+            unsafe {
+                ReportEventW(self.source, kind, 0, EVENT_ID, std::ptr::null_mut(), 1, 0, strings.as_ptr(), std::ptr::null());
+            }
+        }
+    }
+
+    /// The message of an event followed by its other fields, as the text log has them.
+    #[derive(Default)]
+    struct Message(String);
+
+    impl Visit for Message {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            if field.name() == "message" {
+                let _ = write!(self.0, "{:?}", value);
+            } else {
+                let _ = write!(self.0, " {}={:?}", field.name(), value);
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use crate::control::Control;
+use crate::report::{Outcome, SkipReason, Totals};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use tracing::{debug, warn};
+
+/// Something the daemon did, written as one line of JSON to the `--events-file`.
+#[derive(Serialize, Clone)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    /// A sort started going through `files`.
+    ScanStarted { time: DateTime<Local>, files: usize },
+    /// What a sort did with a file; a file copied by several rules has an event for each.
+    File {
+        time: DateTime<Local>,
+        file: PathBuf,
+        rule: Option<String>,
+        /// The `result` of `sort --output json`, e.g. `moved`, `skipped` or `failed`.
+        result: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        destination: Option<PathBuf>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<SkipReason>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        detail: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// A sort ended, with what it did.
+    ScanFinished {
+        time: DateTime<Local>,
+        processed: usize,
+        #[serde(flatten)]
+        totals: Totals,
+    },
+    /// A sort stopped short, e.g. because the rules or the folder couldn't be read.
+    Error { time: DateTime<Local>, message: String },
+    /// The daemon was paused and no sort is running any more.
+    Paused { time: DateTime<Local> },
+}
+
+impl Event {
+    pub fn file(file: &Path, outcome: &Outcome) -> Event {
+        let described = outcome.describe();
+        Event::File {
+            time: Local::now(),
+            file: file.to_path_buf(),
+            rule: described.rule.map(str::to_string),
+            result: described.result.to_string(),
+            destination: described.destination.map(Path::to_path_buf),
+            reason: described.reason,
+            detail: described.detail.map(str::to_string),
+            error: described.error,
+        }
+    }
+}
+
+/// Appends every event of the daemon to `path` as JSON Lines, on a background thread, for
+/// as long as the daemon runs. Each line is flushed right away, so `tail -f` and named pipes
+/// see it as it happens.
+pub fn write_to(control: &Control, path: &Path) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let events = control.events();
+    let path = path.to_path_buf();
+    debug!("Writing events to {:?}", path);
+    thread::spawn(move || {
+        for event in events {
+            let written = serde_json::to_string(&event)
+                .map_err(io::Error::other)
+                .and_then(|line| writeln!(file, "{}", line))
+                .and_then(|_| file.flush());
+            if let Err(e) = written {
+                warn!("Failed to write an event to {:?}: {}", path, e);
+            }
+        }
+    });
+    Ok(())
+}
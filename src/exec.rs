@@ -0,0 +1,238 @@
+use crate::paths;
+use crate::plugins;
+use crate::templates;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Limits for the commands of rules with `exec`, from `rules.json`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ExecSettings {
+    /// Seconds a command may take for one file before it is stopped and the rule passes.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    /// How many commands may run at the same time, across all workers.
+    #[serde(default = "default_max_running")]
+    pub max_running: usize,
+}
+
+impl Default for ExecSettings {
+    fn default() -> Self {
+        ExecSettings {
+            timeout: default_timeout(),
+            max_running: default_max_running(),
+        }
+    }
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+fn default_max_running() -> usize {
+    2
+}
+
+/// The environment a rule's external commands run in, from `rules.json`. Under systemd or
+/// launchd the daemon gets few variables and a short PATH, so a command that works from a
+/// shell may not be found or may behave differently.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct CommandEnvironment {
+    /// Variables set for the command, on top of those the daemon has.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub vars: BTreeMap<String, String>,
+    /// Folder the command runs in; the daemon's working directory otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<String>,
+    /// Folders searched for the command and the programs it runs, replacing the PATH.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<Vec<String>>,
+}
+
+impl CommandEnvironment {
+    /// Sets up `command` to run in this environment.
+    pub fn apply(&self, command: &mut Command) -> io::Result<()> {
+        command.envs(&self.vars);
+        if let Some(folder) = &self.working_dir {
+            command.current_dir(paths::resolve_path(folder));
+        }
+        if let Some(path) = &self.path {
+            // The program itself is looked up in the PATH the command is given.
+            let path = env::join_paths(path.iter().map(|folder| paths::resolve_path(folder)))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid command path: {}", e)))?;
+            command.env("PATH", path);
+        }
+        Ok(())
+    }
+}
+
+/// Where a command sent a file.
+#[derive(Deserialize)]
+pub struct Decision {
+    pub destination: String,
+    #[serde(default)]
+    pub copy: bool,
+}
+
+/// Number of commands running right now.
+static RUNNING: Mutex<usize> = Mutex::new(0);
+static SLOT_FREED: Condvar = Condvar::new();
+
+/// A running command's place under `max_running`, given back when dropped.
+struct Slot;
+
+impl Slot {
+    fn acquire(max_running: usize) -> Slot {
+        let mut running = RUNNING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        while *running >= max_running.max(1) {
+            running = SLOT_FREED.wait(running).unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        *running += 1;
+        Slot
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        *RUNNING.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) -= 1;
+        SLOT_FREED.notify_one();
+    }
+}
+
+/// Runs `command` with the file's metadata as JSON on stdin, the same metadata plugins
+/// get. The command prints a destination, or `{"destination": ..., "copy": true}`, or
+/// nothing when the rule should pass.
+pub fn decide(command: &str, environment: Option<&CommandEnvironment>, file_path: &Path, settings: &ExecSettings) -> io::Result<Option<Decision>> {
+    let input = plugins::file_meta(file_path).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a readable file"))?;
+    let words = parse(command).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let (program, args) = words.split_first().expect("parse refuses empty commands");
+
+    let mut command = Command::new(program);
+    command.args(args).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped());
+    if let Some(environment) = environment {
+        environment.apply(&mut command)?;
+    }
+    // In a group of its own, what the command starts can be stopped with it.
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+
+    let _slot = Slot::acquire(settings.max_running);
+    let mut child = command.spawn()?;
+    // Reading and writing on other threads keeps a command that doesn't read all of its
+    // input, or writes a lot, from blocking.
+    let mut stdin = child.stdin.take();
+    let writer = thread::spawn(move || stdin.as_mut().map(|stdin| stdin.write_all(&input)));
+    let mut stdout = child.stdout.take();
+    let reader = thread::spawn(move || read_all(stdout.as_mut()));
+    let mut stderr = child.stderr.take();
+    let errors = thread::spawn(move || read_all(stderr.as_mut()));
+
+    let deadline = Instant::now() + Duration::from_secs(settings.timeout);
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Ok(status),
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+            Ok(None) => {
+                break Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("{} took longer than {}s", program, settings.timeout),
+                ))
+            }
+            Err(e) => break Err(e),
+        }
+    };
+    if status.is_err() {
+        stop(&mut child);
+    }
+    // The threads end once the pipes are closed, and the slot is only given back after them,
+    // so no more commands run at once than `max_running`.
+    let _ = writer.join();
+    let output = reader.join().unwrap_or_default();
+    let errors = errors.join().unwrap_or_default();
+    let status = status?;
+    if !status.success() {
+        return Err(io::Error::other(format!("{} failed ({}): {}", program, status, errors.trim())));
+    }
+
+    let output = output.trim();
+    if output.is_empty() {
+        return Ok(None);
+    }
+    let decision = if output.starts_with('{') {
+        serde_json::from_str(output).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("invalid answer from {}: {}", program, e)))?
+    } else {
+        Decision {
+            destination: output.lines().next().unwrap_or_default().trim().to_string(),
+            copy: false,
+        }
+    };
+    templates::validate_destination(&decision.destination)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("invalid answer from {}: {}", program, e)))?;
+    Ok(Some(decision))
+}
+
+/// Splits an `exec` command into the program and its arguments as a shell would, so
+/// arguments can be quoted, e.g. `python3 "~/My Scripts/classify.py"`.
+pub fn parse(command: &str) -> Result<Vec<String>, String> {
+    let words = shell_words::split(command).map_err(|e| format!("can't split `{}` into arguments: {}", command, e))?;
+    if words.is_empty() {
+        return Err("the command is empty".to_string());
+    }
+    Ok(words)
+}
+
+/// Stops a command, along with what it started that may still hold its pipes open.
+fn stop(child: &mut Child) {
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+fn read_all(stream: Option<&mut impl Read>) -> String {
+    let mut output = String::new();
+    if let Some(stream) = stream {
+        let _ = stream.read_to_string(&mut output);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    #[test]
+    fn splits_commands_as_a_shell_would() {
+        let words = parse(r#"python3 "~/My Scripts/classify.py" --label 'a b' c\ d"#).unwrap();
+        assert_eq!(words, ["python3", "~/My Scripts/classify.py", "--label", "a b", "c d"]);
+        assert!(parse("python3 \"classify.py").is_err());
+        assert!(parse("  ").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stops_commands_that_take_too_long_with_what_they_started() {
+        let folder = TestDir::new("exec", "timeout");
+        let file = folder.join("report.pdf");
+        std::fs::write(&file, "report").unwrap();
+        let settings = ExecSettings { timeout: 1, max_running: 1 };
+        let started = Instant::now();
+        // The background `sleep` keeps the output open after the shell is stopped.
+        let error = decide("sh -c 'sleep 30 & sleep 30'", None, &file, &settings).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(*RUNNING.lock().unwrap(), 0);
+    }
+}
@@ -0,0 +1,130 @@
+use crate::dates;
+use crate::paths;
+use crate::rules::Rule;
+use crate::statefile;
+use chrono::{DateTime, Local, NaiveTime, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const EXPIRY_FILE: &str = "rule_expiry.json";
+
+#[derive(Serialize, Deserialize, Default)]
+struct ExpiryState {
+    /// When a sort first saw each rule with a `ttl`, in seconds since the epoch.
+    #[serde(default)]
+    first_seen: BTreeMap<String, i64>,
+    /// Rules that were reported as expired, so they are reported once.
+    #[serde(default)]
+    reported: BTreeSet<String>,
+}
+
+/// When the temporary rules started and which of them ran out, kept across runs.
+pub struct RuleExpiry {
+    path: PathBuf,
+    state: ExpiryState,
+    /// Whether `state` changed since it was loaded, so it needs to be saved.
+    changed: bool,
+    /// The rules this process decided about, whose `reported` it saves; the others are left
+    /// as they are in the file.
+    decided: BTreeSet<String>,
+}
+
+impl RuleExpiry {
+    pub fn load() -> Self {
+        let path = paths::data_dir().join(EXPIRY_FILE);
+        RuleExpiry {
+            state: read(&path),
+            path,
+            changed: false,
+            decided: BTreeSet::new(),
+        }
+    }
+
+    /// Removes the rules whose `expires` day passed or whose `ttl` ran out, and returns those
+    /// that were not reported before.
+    pub fn remove_expired(&mut self, rules: &mut Vec<Rule>) -> Vec<Rule> {
+        let now = Local::now();
+        let mut expired = Vec::new();
+        rules.retain(|rule| {
+            let key = key(rule);
+            self.decided.insert(key.clone());
+            if rule.ttl.is_some() && !self.state.first_seen.contains_key(&key) {
+                self.state.first_seen.insert(key.clone(), now.timestamp());
+                self.changed = true;
+            }
+            if !self.ends(rule, &key, now).is_some_and(|ends| now >= ends) {
+                // A rule given more time is reported again when it runs out once more.
+                self.changed |= self.state.reported.remove(&key);
+                return true;
+            }
+            if self.state.reported.insert(key) {
+                self.changed = true;
+                expired.push(rule.clone());
+            }
+            false
+        });
+        expired
+    }
+
+    /// Removes the rules that ran out as [`RuleExpiry::remove_expired`] does, but without
+    /// noting anything, for commands that only look at what a sort would do. A `ttl` no sort
+    /// started yet counts from now.
+    pub fn without_expired(&self, rules: &mut Vec<Rule>) {
+        let now = Local::now();
+        rules.retain(|rule| !self.ends(rule, &key(rule), now).is_some_and(|ends| now >= ends));
+    }
+
+    /// When `rule` stops applying, the earlier of its `expires` and its `ttl`.
+    fn ends(&self, rule: &Rule, key: &str, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        let expires = rule
+            .expires
+            .and_then(|day| day.succ_opt())
+            .and_then(|day| Local.from_local_datetime(&day.and_time(NaiveTime::MIN)).earliest());
+        let ttl = rule.ttl.as_deref().and_then(dates::parse_age).and_then(|ttl| {
+            let seen = match self.state.first_seen.get(key) {
+                Some(seen) => Local.timestamp_opt(*seen, 0).single()?,
+                None => now,
+            };
+            Some(seen + ttl)
+        });
+        expires.into_iter().chain(ttl).min()
+    }
+
+    /// Saves the state under the file's lock, unless nothing changed since it was loaded.
+    /// What other processes saved meanwhile is kept: the earliest time a rule was seen wins,
+    /// and only the rules decided about here are reported or not as they are here.
+    pub fn save(&mut self) -> std::io::Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        let _lock = statefile::lock(&self.path)?;
+        let mut saved = read(&self.path);
+        for (key, seen) in std::mem::take(&mut self.state.first_seen) {
+            let first = saved.first_seen.entry(key).or_insert(seen);
+            *first = (*first).min(seen);
+        }
+        saved.reported.retain(|key| !self.decided.contains(key));
+        saved.reported.extend(std::mem::take(&mut self.state.reported).into_iter().filter(|key| self.decided.contains(key)));
+        statefile::write(&self.path, serde_json::to_string_pretty(&saved)?.as_bytes())?;
+        self.state = saved;
+        self.changed = false;
+        Ok(())
+    }
+}
+
+fn read(path: &Path) -> ExpiryState {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Rules are told apart by their name, or by what they match and where they send it.
+fn key(rule: &Rule) -> String {
+    match &rule.name {
+        Some(name) => name.clone(),
+        None => format!("{} -> {}", rule.pattern, rule.destination),
+    }
+}
@@ -0,0 +1,122 @@
+use crate::paths;
+use crate::rules::Rule;
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+use std::path::Path;
+
+/// Rule formats of other organizers that rules can be exported to.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    /// organize (tfeldmann/organize) YAML configs
+    OrganizeTool,
+}
+
+/// Exported rules, and notes on the rules or settings that could not be exported.
+pub struct Export {
+    pub contents: String,
+    pub notes: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct OrganizeConfig {
+    rules: Vec<OrganizeRule>,
+}
+
+#[derive(Serialize)]
+struct OrganizeRule {
+    name: String,
+    locations: Vec<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    subfolders: bool,
+    filters: Vec<Mapping>,
+    actions: Vec<Mapping>,
+}
+
+/// Writes `rules` as an organize config sorting `directory`, in the subset that `import`
+/// reads back: extension and name filters with `move` and `copy` actions.
+pub fn to_organize(rules: &[Rule], directory: &Path, recursive: bool) -> Result<Export, String> {
+    let mut notes = Vec::new();
+    let mut exported = Vec::new();
+    let location = home_relative(directory);
+    for rule in rules {
+        let name = rule.name.clone().unwrap_or_else(|| rule.pattern.clone());
+        let filters = match filters(&rule.pattern) {
+            Some(filters) => filters,
+            None => {
+                notes.push(format!("{}: skipped, `{}` can't be expressed with organize's filters", name, rule.pattern));
+                continue;
+            }
+        };
+        if let Some(command) = &rule.exec {
+            notes.push(format!("{}: skipped, the destination is decided by `{}`", name, command));
+            continue;
+        }
+        if rule.destination.contains('{') {
+            notes.push(format!("{}: skipped, placeholders in `{}` can't be converted", name, rule.destination));
+            continue;
+        }
+        let destination = format!("{}/", home_relative(&directory.join(paths::resolve_path(&rule.destination))));
+        let action = if rule.copy { "copy" } else { "move" };
+        if rule.link_back {
+            notes.push(format!("{}: organize can't leave a symlink behind, link_back was dropped", name));
+        }
+        if rule.rate_limit().is_some() {
+            notes.push(format!("{}: organize has no rate limits, its cooldown and max_per_hour were dropped", name));
+        }
+        if rule.verify_type {
+            notes.push(format!("{}: organize doesn't check file contents against extensions, verify_type was dropped", name));
+        }
+        if rule.scan {
+            notes.push(format!("{}: organize can't run a virus scanner, scan was dropped", name));
+        }
+        if rule.copy && !rule.continue_matching {
+            notes.push(format!("{}: copies without `continue`; organize will also try later rules", name));
+        }
+        exported.push(OrganizeRule {
+            name,
+            locations: vec![location.clone()],
+            subfolders: recursive,
+            filters,
+            actions: vec![entry(action, Value::String(destination))],
+        });
+    }
+    let contents = serde_yaml::to_string(&OrganizeConfig { rules: exported }).map_err(|e| e.to_string())?;
+    Ok(Export { contents, notes })
+}
+
+/// organize matches extensions and the name without extension separately.
+fn filters(pattern: &str) -> Option<Vec<Mapping>> {
+    if pattern.contains(['?', '[', ']', '{']) {
+        return None;
+    }
+    if let Some(extension) = pattern.strip_prefix('.').filter(|extension| !extension.contains('*')) {
+        return Some(vec![entry("extension", Value::String(extension.to_string()))]);
+    }
+    let (stem, extension) = match pattern.rsplit_once('.') {
+        Some((stem, extension)) if !extension.contains('*') => (stem, Some(extension)),
+        _ => (pattern, None),
+    };
+    let mut filters = Vec::new();
+    if stem != "*" {
+        filters.push(entry("name", Value::Mapping(entry("match", Value::String(stem.to_string())))));
+    }
+    if let Some(extension) = extension {
+        filters.push(entry("extension", Value::String(extension.to_string())));
+    }
+    Some(filters)
+}
+
+fn entry(key: &str, value: Value) -> Mapping {
+    let mut mapping = Mapping::new();
+    mapping.insert(Value::String(key.to_string()), value);
+    mapping
+}
+
+/// Paths below the home directory are written as `~/...`, as organize configs usually are.
+fn home_relative(path: &Path) -> String {
+    match paths::home_dir().and_then(|home| path.strip_prefix(home).ok().map(Path::to_path_buf)) {
+        Some(rest) => format!("~/{}", rest.to_string_lossy().replace('\\', "/")),
+        None => path.to_string_lossy().into_owned(),
+    }
+}
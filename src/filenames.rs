@@ -0,0 +1,224 @@
+pub use crate::matching::comparable;
+use crate::templates::{self, ScriptPolicy};
+use serde::{Deserialize, Serialize};
+use std::ffi::{OsStr, OsString};
+use std::path::{Component, Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
+
+/// Characters FAT and exFAT drives, and Windows, don't allow in names.
+const UNSAFE_CHARACTERS: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+
+/// Names Windows and FAT drives reserve for devices, with or without an extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2", "LPT3", "LPT4",
+    "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Longest name FAT and exFAT drives store, in UTF-16 code units.
+const MAX_NAME: usize = 255;
+
+/// How the names of placed files, and of the folders made for them, are written, from
+/// `file_names` in `rules.json`.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct FileNameSettings {
+    #[serde(default)]
+    pub normalization: Normalization,
+    /// Which letters names are written in Latin letters, from `scripts` in `rules.json`, so
+    /// names and placeholder values follow the same setting.
+    #[serde(skip)]
+    pub scripts: ScriptPolicy,
+    #[serde(default)]
+    pub sanitize: Sanitize,
+}
+
+/// The Unicode normalization form names are written in. macOS has long stored names
+/// decomposed, while Windows, Linux and most SMB servers keep them composed, so the same
+/// `é` can be two different names.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Normalization {
+    /// Leave names as they are.
+    #[default]
+    Keep,
+    /// Composed, one code point per accented letter.
+    Nfc,
+    /// Decomposed, a letter followed by its accents.
+    Nfd,
+}
+
+/// When characters that FAT and exFAT drives reject are replaced.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Sanitize {
+    #[default]
+    Off,
+    /// For every destination.
+    Always,
+    /// For destinations on FAT and exFAT drives.
+    Auto,
+}
+
+/// The name a file called `name` gets when placed in `folder`.
+pub fn adapt_name(name: &OsStr, folder: &Path, settings: &FileNameSettings) -> OsString {
+    let sanitize = match settings.sanitize {
+        Sanitize::Off => false,
+        Sanitize::Always => true,
+        Sanitize::Auto => existing_ancestor(folder).is_some_and(is_fat),
+    };
+    adapt(name, settings, sanitize)
+}
+
+/// `folder` with the names of the folders that don't exist yet, and are made for the
+/// files placed in it, written as the settings ask. Existing folders keep their names.
+pub fn adapt_folder(folder: &Path, settings: &FileNameSettings) -> PathBuf {
+    if settings.normalization == Normalization::Keep && settings.scripts == ScriptPolicy::Keep && settings.sanitize == Sanitize::Off {
+        return folder.to_path_buf();
+    }
+    let Some(existing) = existing_ancestor(folder) else {
+        return folder.to_path_buf();
+    };
+    let sanitize = match settings.sanitize {
+        Sanitize::Off => false,
+        Sanitize::Always => true,
+        Sanitize::Auto => is_fat(existing),
+    };
+    let mut adapted = existing.to_path_buf();
+    for component in folder.strip_prefix(existing).unwrap_or(Path::new("")).components() {
+        match component {
+            Component::Normal(name) => adapted.push(adapt(name, settings, sanitize)),
+            other => adapted.push(other),
+        }
+    }
+    adapted
+}
+
+/// `destination` with the folder names written in it, rather than filled in by placeholders,
+/// adapted as the settings ask. Destinations are adapted once when the rules are loaded, so
+/// scans, cleanups and the index know the folders by the names files are placed under; as
+/// the drive isn't known yet, `auto` makes them safe for FAT drives everywhere.
+pub fn adapt_destination(destination: &str, settings: &FileNameSettings) -> String {
+    if settings.normalization == Normalization::Keep && settings.scripts == ScriptPolicy::Keep && settings.sanitize == Sanitize::Off {
+        return destination.to_string();
+    }
+    let sanitize = settings.sanitize != Sanitize::Off;
+    destination
+        .split('/')
+        .enumerate()
+        .map(|(position, name)| {
+            // Placeholders are adapted once expanded, and a drive such as `C:` stays as it is.
+            let drive = position == 0 && name.len() == 2 && name.ends_with(':');
+            if name.contains('{') || matches!(name, "" | "." | ".." | "~") || drive {
+                return name.to_string();
+            }
+            adapt(OsStr::new(name), settings, sanitize).to_string_lossy().into_owned()
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn adapt(name: &OsStr, settings: &FileNameSettings, sanitize: bool) -> OsString {
+    // Names that aren't valid Unicode are kept byte for byte, unless they have to be made safe.
+    let Some(text) = name.to_str() else {
+        return if sanitize { OsString::from(sanitized(&name.to_string_lossy().replace('\u{FFFD}', "_"))) } else { name.to_os_string() };
+    };
+    let mut text = match settings.normalization {
+        Normalization::Keep => text.to_string(),
+        Normalization::Nfc => text.nfc().collect(),
+        Normalization::Nfd => text.nfd().collect(),
+    };
+    if !text.is_ascii() {
+        text = templates::transliterate(&text, settings.scripts);
+    }
+    if sanitize {
+        text = sanitized(&text);
+    }
+    OsString::from(text)
+}
+
+/// `name` without the characters FAT and exFAT reject, the trailing dots and spaces they
+/// drop, or a device name, and short enough for them.
+fn sanitized(name: &str) -> String {
+    let mut name: String = name.chars().map(|c| if c.is_control() || UNSAFE_CHARACTERS.contains(&c) { '_' } else { c }).collect();
+    name.truncate(name.trim_end_matches(['.', ' ']).len());
+    let (stem, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 => name.split_at(dot),
+        _ => (name.as_str(), ""),
+    };
+    let mut stem = stem.to_string();
+    if RESERVED_NAMES.iter().any(|reserved| stem.eq_ignore_ascii_case(reserved)) {
+        stem.push('_');
+    }
+    while !stem.is_empty() && stem.encode_utf16().count() + extension.encode_utf16().count() > MAX_NAME {
+        stem.pop();
+    }
+    if stem.is_empty() {
+        stem.push('_');
+    }
+    format!("{}{}", stem, extension)
+}
+
+fn existing_ancestor(path: &Path) -> Option<&Path> {
+    path.ancestors().find(|ancestor| ancestor.exists())
+}
+
+/// Whether `folder` is on a FAT or exFAT drive.
+#[cfg(target_os = "linux")]
+fn is_fat(folder: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    const MSDOS_SUPER_MAGIC: i64 = 0x4d44;
+    const EXFAT_SUPER_MAGIC: i64 = 0x2011_bab0;
+    let Ok(path) = std::ffi::CString::new(folder.as_os_str().as_bytes()) else {
+        return false;
+    };
+    let mut stats: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stats) } != 0 {
+        return false;
+    }
+    // `f_type` is 32 bits wide on some systems and 64 on others.
+    #[allow(clippy::unnecessary_cast)]
+    let kind = stats.f_type as i64;
+    kind == MSDOS_SUPER_MAGIC || kind == EXFAT_SUPER_MAGIC
+}
+
+#[cfg(target_os = "macos")]
+fn is_fat(folder: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(path) = std::ffi::CString::new(folder.as_os_str().as_bytes()) else {
+        return false;
+    };
+    let mut stats: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stats) } != 0 {
+        return false;
+    }
+    let kind = unsafe { std::ffi::CStr::from_ptr(stats.f_fstypename.as_ptr()) };
+    matches!(kind.to_bytes(), b"msdos" | b"exfat")
+}
+
+#[cfg(windows)]
+fn is_fat(folder: &Path) -> bool {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetVolumeInformationW;
+    // The root of the drive, such as `C:\`.
+    let root: PathBuf = folder.components().take(2).collect();
+    let wide: Vec<u16> = root.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut kind = [0u16; 32];
+    let found = unsafe {
+        GetVolumeInformationW(
+            wide.as_ptr(),
+            std::ptr::null_mut(),
+            0,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            kind.as_mut_ptr(),
+            kind.len() as u32,
+        )
+    };
+    let end = kind.iter().position(|c| *c == 0).unwrap_or(kind.len());
+    found != 0 && matches!(String::from_utf16_lossy(&kind[..end]).as_str(), "FAT" | "FAT32" | "exFAT")
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn is_fat(_folder: &Path) -> bool {
+    false
+}
@@ -0,0 +1,137 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Kinds of content recognized by their leading bytes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Pdf,
+    Zip,
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+    Bmp,
+    Tiff,
+    Gzip,
+    SevenZip,
+    Rar,
+    Mp3,
+    IsoMedia,
+    Wav,
+    Flac,
+    Ogg,
+    /// Compound files: legacy Office documents and Windows installers.
+    Compound,
+    WindowsExecutable,
+    Elf,
+    MachO,
+    Script,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Pdf => "a PDF document",
+            Kind::Zip => "a zip archive",
+            Kind::Png => "a PNG image",
+            Kind::Jpeg => "a JPEG image",
+            Kind::Gif => "a GIF image",
+            Kind::Webp => "a WebP image",
+            Kind::Bmp => "a BMP image",
+            Kind::Tiff => "a TIFF image",
+            Kind::Gzip => "a gzip archive",
+            Kind::SevenZip => "a 7z archive",
+            Kind::Rar => "a RAR archive",
+            Kind::Mp3 => "MP3 audio",
+            Kind::IsoMedia => "an MP4 or QuickTime file",
+            Kind::Wav => "WAV audio",
+            Kind::Flac => "FLAC audio",
+            Kind::Ogg => "Ogg media",
+            Kind::Compound => "an Office document or Windows installer",
+            Kind::WindowsExecutable => "a Windows executable",
+            Kind::Elf => "a Linux executable",
+            Kind::MachO => "a macOS executable",
+            Kind::Script => "a script",
+        }
+    }
+
+    fn is_executable(self) -> bool {
+        matches!(self, Kind::WindowsExecutable | Kind::Elf | Kind::MachO | Kind::Script)
+    }
+}
+
+/// The kinds of content a file with `extension` may have, for the extensions that can be checked.
+fn expected(extension: &str) -> Option<&'static [Kind]> {
+    Some(match extension {
+        "pdf" => &[Kind::Pdf],
+        "zip" | "docx" | "xlsx" | "pptx" | "odt" | "ods" | "odp" | "epub" | "jar" | "apk" => &[Kind::Zip],
+        "png" => &[Kind::Png],
+        "jpg" | "jpeg" => &[Kind::Jpeg],
+        "gif" => &[Kind::Gif],
+        "webp" => &[Kind::Webp],
+        "bmp" => &[Kind::Bmp],
+        "tif" | "tiff" => &[Kind::Tiff],
+        "gz" | "tgz" => &[Kind::Gzip],
+        "7z" => &[Kind::SevenZip],
+        "rar" => &[Kind::Rar],
+        "mp3" => &[Kind::Mp3],
+        "mp4" | "m4a" | "m4v" | "mov" | "heic" | "heif" => &[Kind::IsoMedia],
+        "wav" => &[Kind::Wav],
+        "flac" => &[Kind::Flac],
+        "ogg" | "oga" | "opus" => &[Kind::Ogg],
+        "doc" | "xls" | "ppt" | "msi" => &[Kind::Compound],
+        "exe" | "dll" | "scr" | "sys" => &[Kind::WindowsExecutable],
+        // Scripts are text; a `#!` line is optional.
+        "sh" | "bash" | "zsh" | "ksh" | "command" | "py" | "pl" | "rb" => &[Kind::Script],
+        _ => return None,
+    })
+}
+
+fn detect(header: &[u8]) -> Option<Kind> {
+    let at = |offset: usize, bytes: &[u8]| header.get(offset..offset + bytes.len()) == Some(bytes);
+    Some(match header {
+        _ if at(0, b"%PDF-") => Kind::Pdf,
+        _ if at(0, b"PK\x03\x04") || at(0, b"PK\x05\x06") => Kind::Zip,
+        _ if at(0, b"\x89PNG\r\n\x1a\n") => Kind::Png,
+        _ if at(0, b"\xff\xd8\xff") => Kind::Jpeg,
+        _ if at(0, b"GIF87a") || at(0, b"GIF89a") => Kind::Gif,
+        _ if at(0, b"RIFF") && at(8, b"WEBP") => Kind::Webp,
+        _ if at(0, b"RIFF") && at(8, b"WAVE") => Kind::Wav,
+        _ if at(0, b"BM") => Kind::Bmp,
+        _ if at(0, b"II*\0") || at(0, b"MM\0*") => Kind::Tiff,
+        _ if at(0, b"\x1f\x8b") => Kind::Gzip,
+        _ if at(0, b"7z\xbc\xaf\x27\x1c") => Kind::SevenZip,
+        _ if at(0, b"Rar!\x1a\x07") => Kind::Rar,
+        _ if at(0, b"ID3") => Kind::Mp3,
+        [0xff, second, ..] if second & 0xe0 == 0xe0 => Kind::Mp3,
+        _ if at(4, b"ftyp") => Kind::IsoMedia,
+        _ if at(0, b"fLaC") => Kind::Flac,
+        _ if at(0, b"OggS") => Kind::Ogg,
+        _ if at(0, b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1") => Kind::Compound,
+        _ if at(0, b"MZ") => Kind::WindowsExecutable,
+        _ if at(0, b"\x7fELF") => Kind::Elf,
+        _ if at(0, b"\xcf\xfa\xed\xfe") || at(0, b"\xce\xfa\xed\xfe") || at(0, b"\xca\xfe\xba\xbe") => Kind::MachO,
+        _ if at(0, b"#!") => Kind::Script,
+        _ => return None,
+    })
+}
+
+/// Compares the file's content with its extension and describes the content when they
+/// disagree, e.g. "a Windows executable" for an `.exe` named `invoice.pdf`. Files with
+/// extensions that can't be checked only disagree when they are executables.
+pub fn mismatch(file_path: &Path) -> io::Result<Option<&'static str>> {
+    let mut header = Vec::with_capacity(16);
+    File::open(file_path)?.take(16).read_to_end(&mut header)?;
+    let detected = detect(&header);
+    let extension = file_path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    Ok(match expected(&extension) {
+        Some(kinds) if detected.is_some_and(|kind| kinds.contains(&kind)) => None,
+        Some(kinds) if detected.is_none() && kinds.contains(&Kind::Script) => None,
+        Some(_) => Some(detected.map_or("unrecognized content", Kind::name)),
+        None => detected.filter(|kind| kind.is_executable()).map(Kind::name),
+    })
+}
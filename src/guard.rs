@@ -0,0 +1,221 @@
+use crate::duplicates;
+use crate::index::MoveIndex;
+use crate::paths;
+use crate::scan::{self, RepositoryPolicy};
+use crate::shutdown;
+use crate::watch::{WatchBackend, Watcher};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How long a placed file has to be missing before the guard acts, so files Organizer moves
+/// away itself are in the move index by then.
+const SETTLE: Duration = Duration::from_secs(30);
+
+/// Time between checks when the watchers report no changes, as they can miss some.
+const CHECK_EVERY: Duration = Duration::from_secs(10 * 60);
+
+const POLL: Duration = Duration::from_secs(1);
+
+/// Marks a destination as curated, from its `protect` action in `rules.json`: the daemon
+/// still files into it, and a guard looks out for files deleted or renamed by other programs.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ProtectAction {
+    #[serde(default)]
+    pub on_change: GuardPolicy,
+    /// Folder keeping a copy of every protected file, to restore deleted ones from.
+    pub backup: Option<String>,
+}
+
+/// What the guard does about a file that was deleted or renamed.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GuardPolicy {
+    /// Log it, and follow the file to its new name.
+    #[default]
+    Log,
+    /// Rename the file back, or copy it back from the backup folder.
+    Restore,
+}
+
+/// A protected destination, resolved against the sorted folder.
+#[derive(Clone, PartialEq)]
+pub struct Guarded {
+    pub folder: PathBuf,
+    pub policy: GuardPolicy,
+    pub backup: Option<PathBuf>,
+}
+
+impl Guarded {
+    /// Destinations with placeholders are protected from the folder before the first one;
+    /// `None` when that is the sorted folder itself.
+    pub fn new(root: &Path, destination: &str, protect: &ProtectAction) -> Option<Guarded> {
+        let folder = match destination.split_once('{') {
+            Some((fixed, _)) => fixed.rsplit_once('/').map(|(folder, _)| folder).filter(|folder| !folder.is_empty())?,
+            None => destination,
+        };
+        let resolve = |path: &str| root.join(paths::resolve_path(path));
+        Some(Guarded {
+            folder: resolve(folder),
+            policy: protect.on_change,
+            backup: protect.backup.as_deref().map(resolve),
+        })
+    }
+}
+
+/// Watches the protected destinations from a thread of its own, so tampering is noticed
+/// between sorts as well.
+#[derive(Clone)]
+pub struct Guard {
+    folders: Arc<Mutex<Vec<Guarded>>>,
+}
+
+impl Guard {
+    pub fn start(backend: WatchBackend) -> Guard {
+        let guard = Guard {
+            folders: Arc::new(Mutex::new(Vec::new())),
+        };
+        let folders = guard.folders.clone();
+        thread::spawn(move || run(&folders, backend));
+        guard
+    }
+
+    /// Sets the destinations to guard, after the rules were loaded.
+    pub fn protect(&self, folders: Vec<Guarded>) {
+        *self.folders.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = folders;
+    }
+}
+
+fn run(folders: &Mutex<Vec<Guarded>>, backend: WatchBackend) {
+    let index = match MoveIndex::open() {
+        Ok(index) => index,
+        Err(e) => {
+            warn!("Move index unavailable, protected destinations are not guarded: {}", e);
+            return;
+        }
+    };
+    let mut guarded: Vec<Guarded> = Vec::new();
+    let mut watchers: Vec<Watcher> = Vec::new();
+    let mut missing = HashMap::new();
+    let mut checked: Option<Instant> = None;
+    while !shutdown::requested() {
+        let current = folders.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+        if current != guarded {
+            watchers = current
+                .iter()
+                .filter(|guarded| guarded.folder.is_dir())
+                .map(|guarded| Watcher::new(&guarded.folder, true, Vec::new(), RepositoryPolicy::Skip, backend))
+                .collect();
+            guarded = current;
+            checked = None;
+        }
+        let mut changed = false;
+        if watchers.is_empty() {
+            thread::sleep(POLL);
+        }
+        for watcher in &mut watchers {
+            changed |= watcher.wait(POLL / watchers.len() as u32);
+        }
+        let due = changed || !missing.is_empty() || !checked.is_some_and(|checked| checked.elapsed() < CHECK_EVERY);
+        if guarded.is_empty() || !due {
+            continue;
+        }
+        checked = Some(Instant::now());
+        for guarded in &guarded {
+            if let Err(e) = check(&index, guarded, &mut missing) {
+                warn!("Failed to check the protected folder {}: {}", guarded.folder.display(), e);
+            }
+        }
+    }
+}
+
+/// Looks for files placed in the destination that are gone, and deals with those that have
+/// been gone for long enough; `missing` says since when they are.
+fn check(index: &MoveIndex, guarded: &Guarded, missing: &mut HashMap<PathBuf, Instant>) -> io::Result<()> {
+    let placed: HashSet<PathBuf> = index
+        .placements()
+        .map_err(io::Error::other)?
+        .into_iter()
+        .map(|placement| PathBuf::from(placement.path))
+        .filter(|path| path.starts_with(&guarded.folder))
+        .collect();
+    let known = index.guarded().map_err(io::Error::other)?;
+    // Files Organizer moved away itself are no longer placed here.
+    missing.retain(|path, _| !path.starts_with(&guarded.folder) || placed.contains(path));
+    let mut gone = Vec::new();
+    for path in &placed {
+        if path.exists() {
+            missing.remove(path);
+            if !known.contains_key(&*path.to_string_lossy()) {
+                remember(index, guarded, path)?;
+            }
+        } else if missing.entry(path.clone()).or_insert_with(Instant::now).elapsed() >= SETTLE {
+            gone.push(path);
+        }
+    }
+    if gone.is_empty() {
+        return Ok(());
+    }
+
+    // Renamed files are found among the files here Organizer didn't place, by their contents.
+    let mut strays = HashMap::new();
+    let excluded: Vec<PathBuf> = guarded.backup.iter().cloned().collect();
+    for stray in scan::collect_files(&guarded.folder, true, &excluded, RepositoryPolicy::Skip, false, None)? {
+        if !placed.contains(&stray) && stray.is_file() {
+            if let Ok(hash) = duplicates::hash_file(&stray) {
+                strays.insert(hash.to_hex().to_string(), stray);
+            }
+        }
+    }
+    for path in gone {
+        missing.remove(path);
+        let hash = known.get(&*path.to_string_lossy());
+        let renamed = hash.and_then(|hash| strays.remove(hash));
+        let backup = hash
+            .zip(guarded.backup.as_ref())
+            .map(|(hash, backup)| backup.join(hash))
+            .filter(|backup| backup.is_file());
+        if let (Some(folder), GuardPolicy::Restore) = (path.parent(), guarded.policy) {
+            fs::create_dir_all(folder)?;
+        }
+        match (renamed, backup, guarded.policy) {
+            (Some(renamed), _, GuardPolicy::Restore) => {
+                fs::rename(&renamed, path)?;
+                warn!("{} was renamed to {} by another program, renamed it back", path.display(), renamed.display());
+            }
+            (Some(renamed), _, GuardPolicy::Log) => {
+                warn!("{} was renamed to {} by another program", path.display(), renamed.display());
+                index.record("renamed", None, path, &renamed).map_err(io::Error::other)?;
+            }
+            (None, Some(backup), GuardPolicy::Restore) => {
+                fs::copy(&backup, path)?;
+                warn!("{} was deleted by another program, restored it from {}", path.display(), backup.display());
+            }
+            (None, _, _) => {
+                warn!("{} was deleted by another program", path.display());
+                index.record("deleted", None, path, path).map_err(io::Error::other)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Keeps the hash of a protected file, and a copy of it in the backup folder.
+fn remember(index: &MoveIndex, guarded: &Guarded, path: &Path) -> io::Result<()> {
+    let hash = duplicates::hash_file(path)?.to_hex().to_string();
+    if let Some(backup) = &guarded.backup {
+        let copy = backup.join(&hash);
+        if !copy.exists() {
+            fs::create_dir_all(backup)?;
+            fs::copy(path, &copy)?;
+            info!(file = ?path, backup = ?copy, "Backed up protected file");
+        }
+    }
+    index.record_guarded(&path.to_string_lossy(), &hash).map_err(io::Error::other)
+}
@@ -0,0 +1,404 @@
+use crate::arrivals;
+use crate::exec::CommandEnvironment;
+use crate::images::OriginalsPolicy;
+use crate::paths;
+use crate::statefile;
+use crate::transfer;
+use crate::trash::{self, Trashed};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use tracing::{error, info, warn};
+
+const QUEUE_FILE: &str = "jobs.json";
+
+/// Hands a file to an external command, e.g. `ffmpeg -i {input} -c copy {output}`,
+/// before it is placed in its destination.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HandoffAction {
+    pub command: String,
+    /// Extension of the produced file; defaults to the extension of the input.
+    pub extension: Option<String>,
+    #[serde(default)]
+    pub on_failure: FailurePolicy,
+    /// Variables, working directory and PATH for the command.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<CommandEnvironment>,
+}
+
+/// What happens to a file whose command failed.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailurePolicy {
+    /// Leave the file at its source and keep the failed job in the queue.
+    #[default]
+    Keep,
+    /// Move the untouched original into the destination instead.
+    MoveOriginal,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HandoffSettings {
+    /// How many external commands may run at the same time, across all rules.
+    #[serde(default = "default_max_jobs")]
+    pub max_jobs: usize,
+}
+
+impl Default for HandoffSettings {
+    fn default() -> Self {
+        HandoffSettings {
+            max_jobs: default_max_jobs(),
+        }
+    }
+}
+
+fn default_max_jobs() -> usize {
+    1
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum JobStatus {
+    Pending,
+    Running,
+    Failed,
+    Done,
+}
+
+/// A job that left the queue with its file placed in the destination.
+pub struct CompletedJob {
+    pub rule: String,
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    /// Where the original went when `originals` deleted it into the trash.
+    pub trashed: Option<Trashed>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Job {
+    id: u64,
+    #[serde(default)]
+    rule: String,
+    source: PathBuf,
+    destination: PathBuf,
+    extension: String,
+    command: String,
+    originals: OriginalsPolicy,
+    #[serde(default)]
+    on_failure: FailurePolicy,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    environment: Option<CommandEnvironment>,
+    /// Waits for the other jobs of its rule that arrived before it.
+    #[serde(default)]
+    serial: bool,
+    /// When the source file arrived in the sorted folder.
+    #[serde(default)]
+    arrived: Option<SystemTime>,
+    status: JobStatus,
+}
+
+impl Job {
+    fn final_path(&self) -> PathBuf {
+        let stem = self.source.file_stem().unwrap_or_default().to_string_lossy();
+        self.destination.join(format!("{}.{}", stem, self.extension))
+    }
+    
+    fn partial_path(&self) -> PathBuf {
+        let stem = self.source.file_stem().unwrap_or_default().to_string_lossy();
+        self.destination.join(format!(".{}.partial.{}", stem, self.extension))
+    }
+}
+
+struct QueueState {
+    jobs: Vec<Job>,
+    running: HashMap<u64, Child>,
+    max_jobs: usize,
+    /// How originals are deleted, from the rules the queue was configured with.
+    transfers: transfer::Settings,
+    /// The files of the jobs this queue loaded or queued; the other jobs in the file were
+    /// queued by another process since.
+    owned: HashSet<PathBuf>,
+}
+
+/// Persistent queue of external commands; a file is only moved into its
+/// destination once its command has succeeded.
+pub struct JobQueue {
+    path: PathBuf,
+    state: Mutex<QueueState>,
+}
+
+impl JobQueue {
+    pub fn load() -> Self {
+        let path = paths::data_dir().join(QUEUE_FILE);
+        let mut jobs = read(&path);
+        // Commands that were running when the process stopped are started again.
+        for job in &mut jobs {
+            if job.status == JobStatus::Running {
+                job.status = JobStatus::Pending;
+            }
+        }
+        JobQueue {
+            path,
+            state: Mutex::new(QueueState {
+                owned: jobs.iter().map(|job| job.source.clone()).collect(),
+                jobs,
+                running: HashMap::new(),
+                max_jobs: default_max_jobs(),
+                transfers: transfer::Settings::default(),
+            }),
+        }
+    }
+    
+    pub fn configure(&self, settings: &HandoffSettings, transfers: &transfer::Settings) {
+        let mut state = self.state.lock().unwrap();
+        state.max_jobs = settings.max_jobs.max(1);
+        state.transfers = transfers.clone();
+    }
+    
+    /// Queues `source` for `action`; returns false when the file is already queued. With
+    /// `serial`, the job only starts once the jobs of `rule` for files that arrived earlier
+    /// finished.
+    pub fn enqueue(
+        &self,
+        rule: &str,
+        source: &Path,
+        destination: &Path,
+        action: &HandoffAction,
+        originals: &OriginalsPolicy,
+        serial: bool,
+    ) -> std::io::Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        if state.jobs.iter().any(|job| job.source == source) {
+            return Ok(false);
+        }
+        
+        let extension = action
+            .extension
+            .clone()
+            .or_else(|| source.extension().map(|extension| extension.to_string_lossy().into_owned()))
+            .unwrap_or_default();
+        let id = state.jobs.iter().map(|job| job.id + 1).max().unwrap_or(0);
+        state.owned.insert(source.to_path_buf());
+        state.jobs.push(Job {
+            id,
+            rule: rule.to_string(),
+            source: source.to_path_buf(),
+            destination: destination.to_path_buf(),
+            extension: extension.trim_start_matches('.').to_string(),
+            command: action.command.clone(),
+            originals: originals.clone(),
+            on_failure: action.on_failure,
+            environment: action.environment.clone(),
+            serial,
+            arrived: arrivals::created(source),
+            status: JobStatus::Pending,
+        });
+        self.save(&state)?;
+        Ok(true)
+    }
+    
+    pub fn has_work(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.jobs.iter().any(|job| job.status != JobStatus::Failed)
+    }
+    
+    /// Finalizes finished commands and starts pending ones up to the configured limit.
+    pub fn poll(&self) -> std::io::Result<Vec<CompletedJob>> {
+        let mut state = self.state.lock().unwrap();
+        let QueueState { jobs, running, max_jobs, transfers, .. } = &mut *state;
+        
+        let mut completed = Vec::new();
+        let mut finished = Vec::new();
+        for (id, child) in running.iter_mut() {
+            if let Some(status) = child.try_wait()? {
+                finished.push((*id, status.success()));
+            }
+        }
+        for (id, success) in finished {
+            running.remove(&id);
+            let Some(job) = jobs.iter_mut().find(|job| job.id == id) else { continue };
+            let result = if success {
+                finalize(job, transfers).map_err(|e| error!(file = ?job.source, "Failed to finalize handoff: {}", e))
+            } else {
+                warn!(file = ?job.source, "Handoff command failed");
+                let _ = fs::remove_file(job.partial_path());
+                fail(job).map(|target| (target, None))
+            };
+            complete(job, result, &mut completed);
+        }
+        
+        // Rules with `serial` run one job at a time, that of the earliest file first, however
+        // the workers happened to queue them.
+        let mut busy: HashSet<String> = jobs
+            .iter()
+            .filter(|job| job.serial && job.status == JobStatus::Running)
+            .map(|job| job.rule.clone())
+            .collect();
+        for position in 0..jobs.len() {
+            if running.len() >= *max_jobs {
+                break;
+            }
+            if jobs[position].status != JobStatus::Pending {
+                continue;
+            }
+            let position = if jobs[position].serial {
+                if !busy.insert(jobs[position].rule.clone()) {
+                    continue;
+                }
+                next_serial(jobs, &jobs[position].rule).unwrap_or(position)
+            } else {
+                position
+            };
+            let job = &mut jobs[position];
+            match start(job) {
+                Ok(child) => {
+                    info!(file = ?job.source, "Started handoff command");
+                    job.status = JobStatus::Running;
+                    running.insert(job.id, child);
+                }
+                Err(e) => {
+                    error!(file = ?job.source, "Failed to start handoff command: {}", e);
+                    let result = fail(job).map(|target| (target, None));
+                    complete(job, result, &mut completed);
+                }
+            }
+        }
+        jobs.retain(|job| job.status != JobStatus::Done);
+        
+        self.save(&state)?;
+        Ok(completed)
+    }
+    
+    /// Runs the queue until every job has either finished or failed.
+    pub fn wait(&self) -> std::io::Result<Vec<CompletedJob>> {
+        let mut completed = Vec::new();
+        loop {
+            completed.extend(self.poll()?);
+            if !self.has_work() {
+                return Ok(completed);
+            }
+            thread::sleep(Duration::from_millis(500));
+        }
+    }
+    
+    /// Writes the jobs to the file under its lock, along with those another process queued
+    /// there meanwhile.
+    fn save(&self, state: &QueueState) -> std::io::Result<()> {
+        let _lock = statefile::lock(&self.path)?;
+        let mut jobs: Vec<Job> = read(&self.path).into_iter().filter(|job| !state.owned.contains(&job.source)).collect();
+        jobs.extend(state.jobs.iter().cloned());
+        let contents = serde_json::to_string_pretty(&jobs)?;
+        statefile::write(&self.path, contents.as_bytes())
+    }
+}
+
+fn read(path: &Path) -> Vec<Job> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// The pending serial job of `rule` whose file arrived first.
+fn next_serial(jobs: &[Job], rule: &str) -> Option<usize> {
+    jobs.iter()
+        .enumerate()
+        .filter(|(_, job)| job.serial && job.rule == rule && job.status == JobStatus::Pending)
+        .min_by_key(|(_, job)| (job.arrived, job.id))
+        .map(|(position, _)| position)
+}
+
+fn start(job: &Job) -> std::io::Result<Child> {
+    fs::create_dir_all(&job.destination)?;
+    let input = job.source.to_string_lossy();
+    let output = job.partial_path();
+    let output = output.to_string_lossy();
+    let mut args = job
+        .command
+        .split_whitespace()
+        .map(|arg| arg.replace("{input}", &input).replace("{output}", &output));
+    let program = args
+        .next()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Empty handoff command"))?;
+    let mut command = Command::new(program);
+    command.args(args);
+    if let Some(environment) = &job.environment {
+        environment.apply(&mut command)?;
+    }
+    command.spawn()
+}
+
+fn complete(job: &mut Job, result: Result<(PathBuf, Option<Trashed>), ()>, completed: &mut Vec<CompletedJob>) {
+    match result {
+        Ok((destination, trashed)) => {
+            job.status = JobStatus::Done;
+            completed.push(CompletedJob {
+                rule: job.rule.clone(),
+                source: job.source.clone(),
+                destination,
+                trashed,
+            });
+        }
+        Err(()) => job.status = JobStatus::Failed,
+    }
+}
+
+/// Applies the job's failure policy, returning where the original ended up if it was moved.
+fn fail(job: &Job) -> Result<PathBuf, ()> {
+    if job.on_failure == FailurePolicy::Keep {
+        return Err(());
+    }
+    let target = job.destination.join(job.source.file_name().unwrap());
+    let moved = fs::create_dir_all(&job.destination).and_then(|_| {
+        if target.exists() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{:?} already exists", target),
+            ));
+        }
+        fs::rename(&job.source, &target)
+    });
+    match moved {
+        Ok(()) => {
+            info!(file = ?job.source, destination = ?job.destination, "Moved original after failed handoff");
+            Ok(target)
+        }
+        Err(e) => {
+            error!(file = ?job.source, "Failed to move original after failed handoff: {}", e);
+            Err(())
+        }
+    }
+}
+
+/// Puts the command's output in its place and the original where `originals` says, and
+/// returns where the output went, with the original when it went to the trash.
+fn finalize(job: &Job, transfers: &transfer::Settings) -> std::io::Result<(PathBuf, Option<Trashed>)> {
+    let final_path = job.final_path();
+    let file_name = job.source.file_name().unwrap();
+    let keeps_original_in_place = matches!(job.originals, OriginalsPolicy::Keep);
+    if final_path.exists() || (keeps_original_in_place && job.destination.join(file_name) == final_path) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{:?} already exists", final_path),
+        ));
+    }
+    fs::rename(job.partial_path(), &final_path)?;
+    
+    let mut trashed = None;
+    match &job.originals {
+        OriginalsPolicy::Delete => trashed = trash::remove(&job.source, transfers)?,
+        OriginalsPolicy::Keep => fs::rename(&job.source, job.destination.join(file_name))?,
+        OriginalsPolicy::Folder(folder) => {
+            let folder = job.destination.join(folder);
+            fs::create_dir_all(&folder)?;
+            fs::rename(&job.source, folder.join(file_name))?;
+        }
+    }
+    info!(file = ?job.source, destination = ?final_path, "Finished handoff");
+    Ok((final_path, trashed))
+}
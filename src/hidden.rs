@@ -0,0 +1,122 @@
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+
+/// Files that programs write while they work and remove or rename once done, such as
+/// downloads in progress and the owner files of open Office documents.
+const TEMPORARY: &[&str] = &[
+    "*.part",
+    "*.partial",
+    "*.crdownload",
+    "*.opdownload",
+    "*.tmp",
+    "~$*",
+    ".~lock.*#",
+    ".DS_Store",
+    "._*",
+    "Thumbs.db",
+    "desktop.ini",
+];
+
+/// [`TEMPORARY`], compiled the first time a name is checked.
+static BUILT_IN: OnceLock<GlobSet> = OnceLock::new();
+
+/// Whether hidden files are sorted, from `hidden_files` in `rules.json`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HiddenPolicy {
+    /// Leave hidden files where they are, unless a rule with `hidden` matches them.
+    #[default]
+    Skip,
+    /// Sort hidden files like any other.
+    Sort,
+}
+
+/// What happens to temporary files, from `temporary` in `rules.json`.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct TemporarySettings {
+    #[serde(default)]
+    pub action: TemporaryAction,
+    /// Globs of more file names that count as temporary, on top of the built-in ones.
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// Hours a temporary file has to go unmodified before `cleanup` deletes it, so that
+    /// downloads still in progress are left alone.
+    #[serde(default = "default_cleanup_after_hours")]
+    pub cleanup_after_hours: u64,
+    /// `patterns`, compiled when the rules are loaded.
+    #[serde(skip)]
+    compiled: GlobSet,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TemporaryAction {
+    /// Leave temporary files where they are.
+    #[default]
+    Skip,
+    /// Delete temporary files once they are stale, or put them in the trash when that is on.
+    Cleanup,
+}
+
+fn default_cleanup_after_hours() -> u64 {
+    24
+}
+
+/// One set for all of `patterns`, matching names whatever their case.
+fn glob_set(patterns: &[impl AsRef<str>]) -> Result<GlobSet, String> {
+    let mut set = GlobSetBuilder::new();
+    for pattern in patterns {
+        let pattern = pattern.as_ref();
+        let glob = GlobBuilder::new(pattern).case_insensitive(true).build().map_err(|e| format!("'{}': {}", pattern, e))?;
+        set.add(glob);
+    }
+    set.build().map_err(|e| e.to_string())
+}
+
+impl TemporarySettings {
+    /// Compiles `patterns` once when the rules are loaded, rather than for every file.
+    pub fn compile(&mut self) -> Result<(), String> {
+        self.compiled = glob_set(&self.patterns).map_err(|e| format!("Invalid `temporary` pattern {}", e))?;
+        Ok(())
+    }
+
+    /// Whether the name of `path` is one of a temporary file, ignoring case.
+    pub fn is_temporary(&self, path: &Path) -> bool {
+        let Some(name) = path.file_name() else {
+            return false;
+        };
+        let built_in = BUILT_IN.get_or_init(|| glob_set(TEMPORARY).expect("the built-in temporary patterns are valid"));
+        built_in.is_match(name) || self.compiled.is_match(name)
+    }
+
+    /// Whether a temporary file was left unmodified long enough to be cleaned up.
+    pub fn is_stale(&self, path: &Path) -> bool {
+        let cutoff = SystemTime::now() - Duration::from_secs(self.cleanup_after_hours * 60 * 60);
+        fs::symlink_metadata(path).and_then(|metadata| metadata.modified()).is_ok_and(|modified| modified < cutoff)
+    }
+}
+
+/// Whether `path` is hidden: its name starts with a dot, or on Windows it has the hidden
+/// attribute.
+pub fn is_hidden(path: &Path) -> bool {
+    if path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')) {
+        return true;
+    }
+    has_hidden_attribute(path)
+}
+
+#[cfg(windows)]
+fn has_hidden_attribute(path: &Path) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0)
+}
+
+#[cfg(not(windows))]
+fn has_hidden_attribute(_path: &Path) -> bool {
+    false
+}
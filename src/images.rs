@@ -0,0 +1,79 @@
+use crate::transfer;
+use crate::trash::{self, Trashed};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{info, warn};
+
+const IMAGEMAGICK: &str = "magick";
+
+/// What happens to the original file after an image has been converted.
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum OriginalsPolicy {
+    #[default]
+    Delete,
+    Keep,
+    /// Move originals into this folder, relative to the destination.
+    Folder(String),
+}
+
+pub fn auto_orient(file_path: &Path) -> std::io::Result<()> {
+    let status = Command::new(IMAGEMAGICK)
+        .arg("mogrify")
+        .arg("-auto-orient")
+        .arg(file_path)
+        .status();
+    match status {
+        Ok(status) if status.success() => info!(file = ?file_path, "Applied EXIF orientation"),
+        Ok(status) => warn!(file = ?file_path, %status, "Failed to apply EXIF orientation"),
+        Err(e) => warn!("Could not run {}: {}", IMAGEMAGICK, e),
+    }
+    Ok(())
+}
+
+/// Converts an image to `format`, returning the path of the file that should be used from now on,
+/// and where the original went when it was put in the trash.
+pub fn convert(file_path: &Path, format: &str, originals: &OriginalsPolicy, transfers: &transfer::Settings) -> std::io::Result<(PathBuf, Option<Trashed>)> {
+    let format = format.trim_start_matches('.').to_lowercase();
+    let already_converted = file_path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case(&format));
+    if already_converted {
+        return Ok((file_path.to_path_buf(), None));
+    }
+    
+    let converted = file_path.with_extension(&format);
+    if converted.exists() {
+        warn!(file = ?file_path, "Skipping conversion, {:?} already exists", converted);
+        return Ok((file_path.to_path_buf(), None));
+    }
+    
+    let status = Command::new(IMAGEMAGICK).arg(file_path).arg(&converted).status();
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => {
+            warn!(file = ?file_path, %status, "Failed to convert image to {}", format);
+            return Ok((file_path.to_path_buf(), None));
+        }
+        Err(e) => {
+            warn!("Could not run {}: {}", IMAGEMAGICK, e);
+            return Ok((file_path.to_path_buf(), None));
+        }
+    }
+    
+    let mut trashed = None;
+    match originals {
+        OriginalsPolicy::Delete => trashed = trash::remove(file_path, transfers)?,
+        OriginalsPolicy::Keep => {}
+        OriginalsPolicy::Folder(folder) => {
+            let folder = file_path.parent().unwrap_or(Path::new(".")).join(folder);
+            fs::create_dir_all(&folder)?;
+            fs::rename(file_path, folder.join(file_path.file_name().unwrap()))?;
+        }
+    }
+    info!(file = ?file_path, converted = ?converted, "Converted image");
+    Ok((converted, trashed))
+}
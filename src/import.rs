@@ -0,0 +1,522 @@
+use crate::paths;
+use crate::rules::Rule;
+use clap::ValueEnum;
+use serde_yaml::Value;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::Cursor;
+
+/// How deep references in a Hazel archive are followed.
+const ARCHIVE_DEPTH: usize = 64;
+
+/// Values and bytes of text a Hazel archive may expand to, so objects that refer to the same
+/// objects many times over can't make it grow without bound.
+const ARCHIVE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Organizers whose rule files can be converted.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ImportSource {
+    /// Hazel for macOS, from rules exported to a `.hazelrules` file
+    Hazel,
+    /// organize (tfeldmann/organize) YAML configs
+    OrganizeTool,
+    /// maid Ruby rule files
+    Maid,
+}
+
+/// Rules converted from another organizer, and notes on everything that was not converted.
+#[derive(Default)]
+pub struct Conversion {
+    pub rules: Vec<Rule>,
+    pub notes: Vec<String>,
+    /// Folders the original rules were applied to; rules here apply to the sorted directory.
+    pub locations: BTreeSet<String>,
+}
+
+pub fn convert(source: ImportSource, contents: &[u8]) -> Result<Conversion, String> {
+    let text = || std::str::from_utf8(contents).map_err(|_| "The file is not UTF-8 text".to_string());
+    match source {
+        ImportSource::Hazel => convert_hazel(contents),
+        ImportSource::OrganizeTool => convert_organize(text()?),
+        ImportSource::Maid => Ok(convert_maid(text()?)),
+    }
+}
+
+fn convert_organize(contents: &str) -> Result<Conversion, String> {
+    let document: Value = serde_yaml::from_str(contents).map_err(|e| format!("Invalid YAML: {}", e))?;
+    let rules = document
+        .get("rules")
+        .and_then(Value::as_sequence)
+        .ok_or("No `rules` list found")?;
+    let mut conversion = Conversion::default();
+    for (position, rule) in rules.iter().enumerate() {
+        let name = rule
+            .get("name")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("rule {}", position + 1));
+        if rule.get("enabled").and_then(Value::as_bool) == Some(false) {
+            conversion.notes.push(format!("{}: skipped because it is disabled", name));
+            continue;
+        }
+        if let Err(reason) = convert_organize_rule(rule, &name, &mut conversion) {
+            conversion.notes.push(format!("{}: skipped, {}", name, reason));
+        }
+    }
+    Ok(conversion)
+}
+
+fn convert_organize_rule(rule: &Value, name: &str, conversion: &mut Conversion) -> Result<(), String> {
+    let mode = rule.get("filter_mode").and_then(Value::as_str).unwrap_or("all");
+    if mode != "all" {
+        return Err(format!("filter_mode `{}` has no equivalent", mode));
+    }
+    let mut extensions = Vec::new();
+    let mut stem = None;
+    for filter in rule.get("filters").and_then(Value::as_sequence).into_iter().flatten() {
+        let (kind, argument) = match filter {
+            Value::String(kind) => (kind.as_str(), &Value::Null),
+            Value::Mapping(map) if map.len() == 1 => {
+                let (kind, argument) = map.iter().next().unwrap();
+                (kind.as_str().unwrap_or_default(), argument)
+            }
+            _ => return Err("a filter could not be read".to_string()),
+        };
+        match kind {
+            "extension" => extensions.extend(strings(argument).into_iter().map(|extension| extension.trim_start_matches('.').to_lowercase())),
+            "name" => stem = Some(name_glob(argument).ok_or("the name filter uses options that have no equivalent")?),
+            other if other.starts_with("not ") => return Err(format!("negated filter `{}` has no equivalent", other)),
+            other => return Err(format!("filter `{}` has no equivalent", other)),
+        }
+    }
+    let patterns = match (stem, extensions.is_empty()) {
+        (None, true) => return Err("it has no extension or name filter and would match every file".to_string()),
+        (None, false) => extensions.iter().map(|extension| format!(".{}", extension)).collect(),
+        (Some(stem), false) => extensions.iter().map(|extension| format!("{}.{}", stem, extension)).collect(),
+        (Some(stem), true) if stem.ends_with('*') => vec![stem],
+        (Some(stem), true) => vec![format!("{}.*", stem)],
+    };
+
+    let mut converted = Vec::new();
+    for action in rule.get("actions").and_then(Value::as_sequence).into_iter().flatten() {
+        let (kind, argument) = match action {
+            Value::String(kind) => (kind.as_str(), &Value::Null),
+            Value::Mapping(map) if map.len() == 1 => {
+                let (kind, argument) = map.iter().next().unwrap();
+                (kind.as_str().unwrap_or_default(), argument)
+            }
+            _ => return Err("an action could not be read".to_string()),
+        };
+        let copy = match kind {
+            "move" => false,
+            "copy" => true,
+            "echo" => continue,
+            other => return Err(format!("action `{}` has no equivalent", other)),
+        };
+        let target = argument
+            .as_str()
+            .or_else(|| argument.get("dest").and_then(Value::as_str))
+            .ok_or_else(|| format!("the {} action has no destination", kind))?;
+        if target.contains('{') {
+            return Err(format!("destination `{}` uses placeholders that can't be converted", target));
+        }
+        let folder = match target.strip_suffix(['/', '\\']) {
+            Some(folder) => folder,
+            None => {
+                conversion.notes.push(format!("{}: files are no longer renamed to `{}`, only filed into its folder", name, target));
+                target.rsplit_once(['/', '\\']).map(|(folder, _)| folder).unwrap_or(".")
+            }
+        };
+        converted.push((destination(folder), copy));
+        if !copy {
+            break;
+        }
+    }
+    if converted.is_empty() {
+        return Err("it neither moves nor copies files".to_string());
+    }
+
+    for location in strings(rule.get("locations").unwrap_or(&Value::Null)) {
+        conversion.locations.insert(location);
+    }
+    for location in rule.get("locations").and_then(Value::as_sequence).into_iter().flatten() {
+        if let Some(path) = location.get("path").and_then(Value::as_str) {
+            conversion.locations.insert(path.to_string());
+        }
+    }
+    if rule.get("subfolders").and_then(Value::as_bool) == Some(true) {
+        conversion.notes.push(format!("{}: sorted subfolders; sort with --recursive to do the same", name));
+    }
+    for pattern in &patterns {
+        for (destination, copy) in &converted {
+            conversion.rules.push(imported_rule(name, pattern, destination, *copy));
+        }
+    }
+    Ok(())
+}
+
+/// A string or a list of strings.
+fn strings(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(value) => vec![value.clone()],
+        Value::Sequence(values) => values.iter().filter_map(Value::as_str).map(str::to_string).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Turns organize's `name` filter, which matches the name without extension, into a glob.
+fn name_glob(argument: &Value) -> Option<String> {
+    if let Some(pattern) = argument.as_str() {
+        return Some(pattern.to_string());
+    }
+    let options = argument.as_mapping()?;
+    let mut glob = String::new();
+    for (key, value) in options {
+        let value = value.as_str()?;
+        glob = match key.as_str()? {
+            "match" if glob.is_empty() => value.to_string(),
+            "startswith" if glob.is_empty() => format!("{}*", value),
+            "endswith" if glob.is_empty() => format!("*{}", value),
+            "contains" if glob.is_empty() => format!("*{}*", value),
+            _ => return None,
+        };
+    }
+    (!glob.is_empty()).then_some(glob)
+}
+
+/// Reads maid's Ruby rules line by line, converting `move` and `copy` calls with literal
+/// paths such as `move(dir('~/Downloads/*.pdf'), '~/Documents/')`.
+fn convert_maid(contents: &str) -> Conversion {
+    let mut conversion = Conversion::default();
+    let mut name = String::from("rule");
+    for line in contents.lines().map(str::trim) {
+        if let Some(rest) = line.strip_prefix("rule ") {
+            name = quoted(rest).into_iter().next().unwrap_or_else(|| "rule".to_string());
+            continue;
+        }
+        let verb: String = line.chars().take_while(|c| c.is_ascii_alphanumeric() || *c == '_').collect();
+        let copy = match verb.as_str() {
+            "move" => false,
+            "copy" => true,
+            "trash" | "remove" | "sync" | "rename" | "gzip" | "gunzip" | "unzip" | "mkdir" | "add_tag" | "remove_tag" | "set_tag" => {
+                conversion.notes.push(format!("{}: `{}` has no equivalent and was skipped", name, verb));
+                continue;
+            }
+            _ => continue,
+        };
+        let mut arguments = quoted(&line[verb.len()..]);
+        let Some(target) = arguments.pop().filter(|_| !arguments.is_empty()) else {
+            conversion.notes.push(format!("{}: `{}` without literal paths can't be converted: {}", name, verb, line));
+            continue;
+        };
+        for source in arguments {
+            let (folder, pattern) = source.rsplit_once('/').unwrap_or((".", source.as_str()));
+            if folder.contains("**") {
+                conversion.notes.push(format!("{}: searched subfolders; sort with --recursive to do the same", name));
+            }
+            conversion.locations.insert(folder.trim_end_matches("/**").to_string());
+            for pattern in expand_braces(pattern) {
+                let pattern = match pattern.strip_prefix("*.") {
+                    Some(extension) if !extension.contains(['*', '?', '[']) => format!(".{}", extension),
+                    _ => pattern,
+                };
+                conversion.rules.push(imported_rule(&name, &pattern, &destination(target.trim_end_matches('/')), copy));
+            }
+        }
+    }
+    conversion
+}
+
+/// The contents of the single- or double-quoted strings in a line of Ruby.
+fn quoted(line: &str) -> Vec<String> {
+    let mut strings = Vec::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\'' || c == '"' {
+            strings.push(chars.by_ref().take_while(|next| *next != c).collect());
+        }
+    }
+    strings
+}
+
+/// Expands one `{a,b}` group as used by Ruby's `Dir.glob`, e.g. `*.{jpg,png}`.
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let Some((prefix, rest)) = pattern.split_once('{') else {
+        return vec![pattern.to_string()];
+    };
+    let Some((alternatives, suffix)) = rest.split_once('}') else {
+        return vec![pattern.to_string()];
+    };
+    alternatives
+        .split(',')
+        .map(|alternative| format!("{}{}{}", prefix, alternative, suffix))
+        .collect()
+}
+
+/// An object of a keyed archive, with the references between objects followed.
+enum Archived {
+    Object { class: String, fields: BTreeMap<String, Archived> },
+    List(Vec<Archived>),
+    Text(String),
+    Flag(bool),
+    Other,
+}
+
+impl Archived {
+    fn field(&self, names: &[&str]) -> Option<&Archived> {
+        match self {
+            Archived::Object { fields, .. } => names.iter().find_map(|name| fields.get(*name)),
+            _ => None,
+        }
+    }
+
+    fn text(&self, names: &[&str]) -> Option<&str> {
+        match self.field(names)? {
+            Archived::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// The objects in and below this one whose class name ends with `class`, outermost first.
+    fn find<'a>(&'a self, class: &str, found: &mut Vec<&'a Archived>) {
+        match self {
+            Archived::Object { class: name, .. } if name.ends_with(class) => found.push(self),
+            Archived::Object { fields, .. } => fields.values().for_each(|field| field.find(class, found)),
+            Archived::List(items) => items.iter().for_each(|item| item.find(class, found)),
+            _ => {}
+        }
+    }
+}
+
+/// Reads rules that Hazel exported to a `.hazelrules` file, a keyed archive of its rule objects.
+/// Hazel doesn't document the format, so only rules made of Extension and Name conditions and
+/// Move or Copy actions are converted; anything else is noted instead of guessed at.
+fn convert_hazel(contents: &[u8]) -> Result<Conversion, String> {
+    let archive = plist::Value::from_reader(Cursor::new(contents)).map_err(|e| format!("Not a property list: {}", e))?;
+    let archive = archive.as_dictionary().ok_or("Not a keyed archive")?;
+    let objects = archive
+        .get("$objects")
+        .and_then(plist::Value::as_array)
+        .ok_or("Not a keyed archive; export the rules with Export Rules in Hazel's rule list")?;
+    let top = archive.get("$top").and_then(plist::Value::as_dictionary).ok_or("The archive has no top object")?;
+    let root = top.get("root").or_else(|| top.values().next()).ok_or("The archive is empty")?;
+    let mut rules = Vec::new();
+    let mut unarchiver = Unarchiver {
+        objects,
+        size: 0,
+        following: HashSet::new(),
+    };
+    unarchiver.unarchive(root, 0)?.find("Rule", &mut rules);
+    if rules.is_empty() {
+        return Err("No rules found in the archive".to_string());
+    }
+    let mut conversion = Conversion::default();
+    for (position, rule) in rules.into_iter().enumerate() {
+        let name = rule
+            .text(&["displayName", "name", "ruleName"])
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("rule {}", position + 1));
+        if matches!(rule.field(&["enabled", "isEnabled"]), Some(Archived::Flag(false))) {
+            conversion.notes.push(format!("{}: skipped because it is disabled", name));
+            continue;
+        }
+        if let Err(reason) = convert_hazel_rule(rule, &name, &mut conversion) {
+            conversion.notes.push(format!("{}: skipped, {}", name, reason));
+        }
+    }
+    Ok(conversion)
+}
+
+fn convert_hazel_rule(rule: &Archived, name: &str, conversion: &mut Conversion) -> Result<(), String> {
+    let mut conditions = Vec::new();
+    rule.find("Condition", &mut conditions);
+    let any = rule
+        .text(&["conditionMode", "matchMode", "mode"])
+        .is_some_and(|mode| mode.eq_ignore_ascii_case("any"));
+    let mut extensions = Vec::new();
+    let mut globs = Vec::new();
+    for condition in conditions {
+        let attribute = condition.text(&["attribute", "attributeName", "key"]).unwrap_or_default().to_lowercase();
+        let operator = condition.text(&["operator", "comparison", "operatorName"]).unwrap_or("is").to_lowercase();
+        let value = condition
+            .text(&["value", "string", "text"])
+            .ok_or_else(|| format!("the {} condition has no value that can be read", attribute))?;
+        match (attribute.as_str(), operator.as_str()) {
+            ("extension", "is") => extensions.push(value.trim_start_matches('.').to_lowercase()),
+            ("name" | "full name", operator) => globs.push(match operator {
+                "is" => value.to_string(),
+                "contains" => format!("*{}*", value),
+                "starts with" => format!("{}*", value),
+                "ends with" => format!("*{}", value),
+                other => return Err(format!("name condition `{}` has no equivalent", other)),
+            }),
+            ("", _) => return Err("a condition could not be read".to_string()),
+            (attribute, operator) => return Err(format!("condition `{} {}` has no equivalent", attribute, operator)),
+        }
+    }
+    let extension_patterns = extensions.iter().map(|extension| format!(".{}", extension));
+    let patterns: Vec<String> = match (globs.as_slice(), extensions.is_empty()) {
+        ([], true) => return Err("it has no extension or name condition and would match every file".to_string()),
+        // Rules that match any of their conditions become one rule per condition.
+        _ if any => globs.iter().cloned().chain(extension_patterns).collect(),
+        ([], false) => extension_patterns.collect(),
+        ([glob], false) => extensions.iter().map(|extension| format!("{}.{}", glob, extension)).collect(),
+        ([glob], true) => vec![glob.clone()],
+        _ => return Err("several name conditions that all have to match have no equivalent".to_string()),
+    };
+
+    let mut actions = Vec::new();
+    rule.find("Action", &mut actions);
+    let mut converted = Vec::new();
+    for action in actions {
+        let kind = match action {
+            Archived::Object { class, .. } => action.text(&["actionType", "type", "name"]).unwrap_or(class.as_str()).to_lowercase(),
+            _ => continue,
+        };
+        let copy = if kind.contains("move") {
+            false
+        } else if kind.contains("copy") {
+            true
+        } else {
+            return Err(format!("action `{}` has no equivalent", kind));
+        };
+        let folder = action
+            .text(&["destination", "path", "folder", "targetPath"])
+            .filter(|folder| folder.starts_with(['/', '~']))
+            .ok_or_else(|| format!("the {} action's folder can't be read", if copy { "copy" } else { "move" }))?;
+        let folder = match paths::home_dir().and_then(|home| folder.strip_prefix(&*home.to_string_lossy()).map(|rest| format!("~{}", rest))) {
+            Some(below_home) => below_home,
+            None => folder.to_string(),
+        };
+        converted.push((destination(folder.trim_end_matches('/')), copy));
+        if !copy {
+            break;
+        }
+    }
+    if converted.is_empty() {
+        return Err("it neither moves nor copies files".to_string());
+    }
+    for pattern in &patterns {
+        for (destination, copy) in &converted {
+            conversion.rules.push(imported_rule(name, pattern, destination, *copy));
+        }
+    }
+    Ok(())
+}
+
+/// Follows the references of a keyed archive, within [`ARCHIVE_DEPTH`] and [`ARCHIVE_SIZE`].
+struct Unarchiver<'a> {
+    objects: &'a [plist::Value],
+    /// What the archive expanded to so far, in values and bytes of text.
+    size: usize,
+    /// The objects being followed, so one that refers back to itself is left out rather than
+    /// followed forever.
+    following: HashSet<u64>,
+}
+
+impl Unarchiver<'_> {
+    /// Turns `value` into what it stands for: arrays, dictionaries and strings archived as
+    /// objects become their contents.
+    fn unarchive(&mut self, value: &plist::Value, depth: usize) -> Result<Archived, String> {
+        if depth > ARCHIVE_DEPTH {
+            return Err(format!("The archive nests objects more than {} deep", ARCHIVE_DEPTH));
+        }
+        self.size += 1 + value.as_string().map_or(0, str::len);
+        if self.size > ARCHIVE_SIZE {
+            return Err("The archive refers to its objects too many times over to be read".to_string());
+        }
+        let depth = depth + 1;
+        let archived = match value {
+            plist::Value::Uid(uid) => {
+                let Some(object) = self.objects.get(uid.get() as usize) else {
+                    return Ok(Archived::Other);
+                };
+                if !self.following.insert(uid.get()) {
+                    return Ok(Archived::Other);
+                }
+                let archived = self.unarchive(object, depth);
+                self.following.remove(&uid.get());
+                archived?
+            }
+            plist::Value::String(text) if text == "$null" => Archived::Other,
+            plist::Value::String(text) => Archived::Text(text.clone()),
+            plist::Value::Boolean(flag) => Archived::Flag(*flag),
+            plist::Value::Array(items) => Archived::List(self.unarchive_all(items, depth)?),
+            plist::Value::Dictionary(dictionary) => {
+                let class = dictionary
+                    .get("$class")
+                    .and_then(plist::Value::as_uid)
+                    .and_then(|uid| self.objects.get(uid.get() as usize))
+                    .and_then(plist::Value::as_dictionary)
+                    .and_then(|class| class.get("$classname"))
+                    .and_then(plist::Value::as_string)
+                    .unwrap_or_default()
+                    .to_string();
+                let keys = dictionary.get("NS.keys").and_then(plist::Value::as_array);
+                let values = dictionary.get("NS.objects").and_then(plist::Value::as_array);
+                if let Some(text) = dictionary.get("NS.string") {
+                    return self.unarchive(text, depth);
+                }
+                let mut fields = BTreeMap::new();
+                match (keys, values) {
+                    (Some(keys), Some(values)) => {
+                        for (key, value) in keys.iter().zip(values) {
+                            if let Archived::Text(key) = self.unarchive(key, depth)? {
+                                fields.insert(key, self.unarchive(value, depth)?);
+                            }
+                        }
+                    }
+                    (None, Some(values)) => return Ok(Archived::List(self.unarchive_all(values, depth)?)),
+                    _ => {
+                        for (key, value) in dictionary.iter().filter(|(key, _)| !key.starts_with('$')) {
+                            fields.insert(key.clone(), self.unarchive(value, depth)?);
+                        }
+                    }
+                }
+                Archived::Object { class, fields }
+            }
+            _ => Archived::Other,
+        };
+        Ok(archived)
+    }
+
+    fn unarchive_all(&mut self, values: &[plist::Value], depth: usize) -> Result<Vec<Archived>, String> {
+        values.iter().map(|value| self.unarchive(value, depth)).collect()
+    }
+}
+
+/// Converts a folder below the home directory to an `xdg:` reference where one exists, so
+/// the rule keeps working for other users; other folders become absolute paths.
+fn destination(folder: &str) -> String {
+    let Some(rest) = folder.strip_prefix("~/") else {
+        return folder.to_string();
+    };
+    let (first, below) = match rest.split_once('/') {
+        Some((first, below)) => (first, Some(below)),
+        None => (rest, None),
+    };
+    let xdg = match first.to_lowercase().as_str() {
+        "desktop" => Some("desktop"),
+        "downloads" => Some("download"),
+        "documents" => Some("documents"),
+        "music" => Some("music"),
+        "pictures" => Some("pictures"),
+        "videos" | "movies" => Some("videos"),
+        _ => None,
+    };
+    match (xdg, below) {
+        (Some(xdg), Some(below)) => format!("xdg:{}/{}", xdg, below),
+        (Some(xdg), None) => format!("xdg:{}", xdg),
+        (None, _) => match paths::home_dir() {
+            Some(home) => home.join(rest).to_string_lossy().into_owned(),
+            None => folder.to_string(),
+        },
+    }
+}
+
+fn imported_rule(name: &str, pattern: &str, destination: &str, copy: bool) -> Rule {
+    let mut rule = Rule::new(pattern, destination);
+    rule.name = Some(name.to_string());
+    rule.copy = copy;
+    rule.continue_matching = copy;
+    rule
+}
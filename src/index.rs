@@ -0,0 +1,825 @@
+use crate::audit;
+use crate::clutter::{Ages, Clutter};
+use crate::paths;
+use crate::remote;
+use chrono::{DateTime, Local, TimeZone};
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use rusqlite::{params, Connection, OptionalExtension, ToSql, Transaction, TransactionBehavior};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+pub const INDEX_FILE: &str = "index.sqlite3";
+
+/// How long a connection waits for another process to finish writing before giving up.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Opens the database in the data directory that the index, the copy log and the scan
+/// state share. It is kept in WAL mode, so commands can read it while a daemon sorts,
+/// and writers from several processes wait for each other instead of failing.
+pub fn connect() -> rusqlite::Result<Connection> {
+    let path = paths::data_dir().join(INDEX_FILE);
+    if let Some(parent) = path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    let connection = Connection::open(path)?;
+    connection.busy_timeout(BUSY_TIMEOUT)?;
+    // Setting the journal mode answers with the mode now in use.
+    connection.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+    connection.pragma_update(None, "synchronous", "NORMAL")?;
+    Ok(connection)
+}
+
+/// One performed action as stored in the move index.
+#[derive(Serialize)]
+pub struct IndexEntry {
+    pub time: DateTime<Local>,
+    pub action: String,
+    pub rule: Option<String>,
+    pub source: String,
+    pub destination: String,
+    /// BLAKE3 checksum the copy was verified with, when copies are verified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+}
+
+/// An entry of the move index as it is mirrored: with its chain hash and, when it was
+/// signed, its signature, so the mirror can be checked without the index.
+#[derive(Serialize)]
+pub struct MirroredEntry {
+    pub id: i64,
+    pub time: DateTime<Local>,
+    pub action: String,
+    pub rule: Option<String>,
+    pub source: String,
+    pub destination: String,
+    pub hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// Where a previously moved file ended up, following it across later moves.
+#[derive(Serialize)]
+pub struct Location {
+    pub original: String,
+    pub first_moved: DateTime<Local>,
+    pub last_moved: DateTime<Local>,
+    pub current: String,
+    pub moves: usize,
+    pub exists: bool,
+}
+
+/// What one rule did over a period, from the move index.
+#[derive(Serialize)]
+pub struct RuleSummary {
+    /// `None` for actions no rule performed, such as fetching files from sources.
+    pub rule: Option<String>,
+    pub files: usize,
+    /// Size of the files where they were placed; files recorded before sizes were kept count as 0.
+    pub bytes: u64,
+    pub errors: usize,
+    /// The folders the rule placed most files in.
+    pub top_destinations: Vec<FolderCount>,
+}
+
+#[derive(Serialize)]
+pub struct FolderCount {
+    pub folder: String,
+    pub files: usize,
+}
+
+/// Where a file that was moved into place is now, as far as the index knows.
+pub struct Placement {
+    /// Rule that placed the file the last time it moved.
+    pub rule: Option<String>,
+    pub path: String,
+}
+
+/// What a placed file was like when a scrub last read it.
+#[derive(Clone)]
+pub struct ScrubRecord {
+    pub hash: String,
+    pub size: u64,
+    /// Modification time, in seconds.
+    pub modified: i64,
+    pub checked: i64,
+    /// Why the file couldn't be read at the last check; the rest is from the last check
+    /// that could read it, or empty when none could.
+    pub error: Option<String>,
+}
+
+/// Summaries being built, with the number of files each rule placed in each folder.
+type Summaries = BTreeMap<Option<String>, (RuleSummary, HashMap<String, usize>)>;
+
+/// The numbers `{seq}` in rename templates hands out, one sequence per destination folder.
+/// Workers share one, as the index itself can't be.
+pub struct Sequences {
+    connection: Connection,
+}
+
+impl Sequences {
+    pub fn open() -> rusqlite::Result<Self> {
+        Sequences::with(connect()?)
+    }
+
+    /// Sequences of their own, which start over with every test.
+    #[cfg(test)]
+    pub fn in_memory() -> rusqlite::Result<Self> {
+        Sequences::with(Connection::open_in_memory()?)
+    }
+
+    fn with(connection: Connection) -> rusqlite::Result<Self> {
+        connection.execute_batch("CREATE TABLE IF NOT EXISTS sequences (folder TEXT PRIMARY KEY, next INTEGER NOT NULL)")?;
+        Ok(Sequences { connection })
+    }
+    
+    /// The next number of `folder`, which is used up by it.
+    pub fn next(&self, folder: &str) -> rusqlite::Result<u64> {
+        let number: i64 = self.connection.query_row(
+            "INSERT INTO sequences (folder, next) VALUES (?1, 2)
+            ON CONFLICT (folder) DO UPDATE SET next = next + 1 RETURNING next - 1",
+            [folder],
+            |row| row.get(0),
+        )?;
+        Ok(number as u64)
+    }
+    
+    /// The next number of `folder`, without using it up.
+    pub fn peek(&self, folder: &str) -> rusqlite::Result<u64> {
+        let next: Option<i64> = self
+            .connection
+            .query_row("SELECT next FROM sequences WHERE folder = ?1", [folder], |row| row.get(0))
+            .optional()?;
+        Ok(next.unwrap_or(1) as u64)
+    }
+}
+
+/// Filters for querying the move index; all filters are optional and combined.
+#[derive(Default)]
+pub struct HistoryFilter {
+    pub since: Option<DateTime<Local>>,
+    pub rule: Option<String>,
+    pub destination: Option<String>,
+    pub search: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Result of checking the audit trail of the move index.
+pub struct AuditReport {
+    pub entries: usize,
+    /// Entries recorded before chain hashing was introduced.
+    pub unprotected: usize,
+    pub signatures: usize,
+    pub problems: Vec<String>,
+}
+
+/// Persistent record of every action Organizer performed. Entries are
+/// chain-hashed, and signed periodically when a signing key is configured.
+pub struct MoveIndex {
+    connection: Connection,
+    signer: Option<(SigningKey, u64)>,
+}
+
+impl MoveIndex {
+    pub fn open() -> rusqlite::Result<Self> {
+        let connection = connect()?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS moves (
+                id INTEGER PRIMARY KEY,
+                time INTEGER NOT NULL,
+                action TEXT NOT NULL,
+                rule TEXT,
+                source TEXT NOT NULL,
+                destination TEXT NOT NULL,
+                hash TEXT
+            );
+            CREATE INDEX IF NOT EXISTS moves_time ON moves (time);
+            CREATE TABLE IF NOT EXISTS errors (
+                id INTEGER PRIMARY KEY,
+                time INTEGER NOT NULL,
+                rule TEXT,
+                source TEXT NOT NULL,
+                error TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS signatures (
+                id INTEGER PRIMARY KEY,
+                move_id INTEGER NOT NULL,
+                time INTEGER NOT NULL,
+                signature TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS mirrors (
+                target TEXT PRIMARY KEY,
+                last_id INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS clutter (
+                folder TEXT NOT NULL,
+                day TEXT NOT NULL,
+                time INTEGER NOT NULL,
+                files INTEGER NOT NULL,
+                unmatched INTEGER NOT NULL,
+                week INTEGER NOT NULL,
+                month INTEGER NOT NULL,
+                year INTEGER NOT NULL,
+                older INTEGER NOT NULL,
+                duplicates INTEGER NOT NULL,
+                score INTEGER NOT NULL,
+                PRIMARY KEY (folder, day)
+            );
+            CREATE TABLE IF NOT EXISTS echoes (
+                source TEXT PRIMARY KEY,
+                move_id INTEGER,
+                count INTEGER NOT NULL,
+                time INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS scrub (
+                path TEXT PRIMARY KEY,
+                hash TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                modified INTEGER NOT NULL,
+                checked INTEGER NOT NULL,
+                error TEXT
+            );
+            CREATE TABLE IF NOT EXISTS guarded (
+                path TEXT PRIMARY KEY,
+                hash TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS heartbeats (
+                folder TEXT PRIMARY KEY,
+                time INTEGER NOT NULL
+            );",
+        )?;
+        if connection.prepare("SELECT hash FROM moves LIMIT 0").is_err() {
+            connection.execute_batch("ALTER TABLE moves ADD COLUMN hash TEXT")?;
+        }
+        if connection.prepare("SELECT size FROM moves LIMIT 0").is_err() {
+            connection.execute_batch("ALTER TABLE moves ADD COLUMN size INTEGER")?;
+        }
+        if connection.prepare("SELECT checksum FROM moves LIMIT 0").is_err() {
+            connection.execute_batch("ALTER TABLE moves ADD COLUMN checksum TEXT")?;
+        }
+        if connection.prepare("SELECT error FROM scrub LIMIT 0").is_err() {
+            connection.execute_batch("ALTER TABLE scrub ADD COLUMN error TEXT")?;
+        }
+        Ok(MoveIndex { connection, signer: None })
+    }
+    
+    /// Signs the index with `key` after every `every` new entries.
+    pub fn set_signer(&mut self, key: SigningKey, every: u64) {
+        self.signer = Some((key, every.max(1)));
+    }
+    
+    /// Records an action. The size of the file at `destination` is kept for reports, but is
+    /// not part of the hash chain.
+    pub fn record(&self, action: &str, rule: Option<&str>, source: &Path, destination: &Path) -> rusqlite::Result<()> {
+        self.record_placed(action, rule, source, destination, None)
+    }
+    
+    /// Records an action like [`MoveIndex::record`], with the checksum the file was verified
+    /// with when it was copied to `destination`, which is not part of the hash chain either.
+    pub fn record_placed(&self, action: &str, rule: Option<&str>, source: &Path, destination: &Path, checksum: Option<&str>) -> rusqlite::Result<()> {
+        let time = Local::now().timestamp();
+        let size = fs::metadata(destination).ok().filter(|metadata| metadata.is_file()).map(|metadata| metadata.len() as i64);
+        let (source, destination) = (source.to_string_lossy(), destination.to_string_lossy());
+        // The newest hash is read and extended under a write lock, so entries recorded by
+        // another process at the same time can't fork the chain.
+        let transaction = Transaction::new_unchecked(&self.connection, TransactionBehavior::Immediate)?;
+        let previous: Option<String> = transaction
+            .query_row("SELECT hash FROM moves ORDER BY id DESC LIMIT 1", [], |row| row.get(0))
+            .optional()?
+            .flatten();
+        let hash = audit::chain_hash(
+            previous.as_deref(),
+            &[&time.to_string(), action, rule.unwrap_or_default(), &source, &destination],
+        );
+        transaction.execute(
+            "INSERT INTO moves (time, action, rule, source, destination, hash, size, checksum) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![time, action, rule, source, destination, hash, size, checksum],
+        )?;
+        
+        if let Some((key, every)) = &self.signer {
+            if self.unsigned()? >= *every {
+                self.sign_latest(key)?;
+            }
+        }
+        transaction.commit()
+    }
+    
+    /// Records a file that could not be sorted, for reports; errors are not part of the audit trail.
+    pub fn record_error(&self, rule: Option<&str>, source: &Path, error: &str) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "INSERT INTO errors (time, rule, source, error) VALUES (?1, ?2, ?3, ?4)",
+            params![Local::now().timestamp(), rule, source.to_string_lossy(), error],
+        )?;
+        Ok(())
+    }
+    
+    /// Signs the entries recorded since the last signature, e.g. before the daemon exits.
+    pub fn flush(&self) -> rusqlite::Result<()> {
+        if let Some((key, _)) = &self.signer {
+            if self.unsigned()? > 0 {
+                self.sign_latest(key)?;
+            }
+        }
+        Ok(())
+    }
+    
+    fn unsigned(&self) -> rusqlite::Result<u64> {
+        self.connection.query_row(
+            "SELECT COUNT(*) FROM moves WHERE id > (SELECT COALESCE(MAX(move_id), 0) FROM signatures)",
+            [],
+            |row| row.get(0),
+        )
+    }
+    
+    /// Signs the chain hash of the newest entry, which covers every entry before it.
+    pub fn sign_latest(&self, key: &SigningKey) -> rusqlite::Result<bool> {
+        let latest: Option<(i64, Option<String>)> = self
+            .connection
+            .query_row("SELECT id, hash FROM moves ORDER BY id DESC LIMIT 1", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .optional()?;
+        let Some((id, Some(hash))) = latest else { return Ok(false) };
+        self.connection.execute(
+            "INSERT INTO signatures (move_id, time, signature) VALUES (?1, ?2, ?3)",
+            params![id, Local::now().timestamp(), audit::sign(key, &hash)],
+        )?;
+        Ok(true)
+    }
+    
+    /// Recomputes the hash chain and checks every signature against `key`.
+    pub fn verify(&self, key: Option<&VerifyingKey>) -> rusqlite::Result<AuditReport> {
+        let mut report = AuditReport {
+            entries: 0,
+            unprotected: 0,
+            signatures: 0,
+            problems: Vec::new(),
+        };
+        let mut hashes = HashMap::new();
+        let mut previous: Option<String> = None;
+        
+        let mut statement = self
+            .connection
+            .prepare("SELECT id, time, action, rule, source, destination, hash FROM moves ORDER BY id")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            report.entries += 1;
+            let id: i64 = row.get(0)?;
+            let Some(stored) = row.get::<_, Option<String>>(6)? else {
+                if previous.is_some() {
+                    report.problems.push(format!("Entry {} has no hash", id));
+                } else {
+                    report.unprotected += 1;
+                }
+                continue;
+            };
+            let time: i64 = row.get(1)?;
+            let rule: Option<String> = row.get(3)?;
+            let fields: [String; 4] = [row.get(2)?, rule.unwrap_or_default(), row.get(4)?, row.get(5)?];
+            let expected = audit::chain_hash(
+                previous.as_deref(),
+                &[&time.to_string(), &fields[0], &fields[1], &fields[2], &fields[3]],
+            );
+            if expected != stored {
+                report.problems.push(format!("Entry {} was modified or an entry before it was removed", id));
+            }
+            hashes.insert(id, stored.clone());
+            previous = Some(stored);
+        }
+        
+        let mut statement = self
+            .connection
+            .prepare("SELECT move_id, signature FROM signatures ORDER BY id")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            report.signatures += 1;
+            let (move_id, signature): (i64, String) = (row.get(0)?, row.get(1)?);
+            let Some(key) = key else { continue };
+            match hashes.get(&move_id) {
+                Some(hash) if audit::verify(key, hash, &signature) => {}
+                Some(_) => report.problems.push(format!("Signature for entry {} is invalid", move_id)),
+                None => report.problems.push(format!("Signed entry {} is missing", move_id)),
+            }
+        }
+        Ok(report)
+    }
+    
+    /// Up to `limit` entries recorded after the entry `id`, oldest first.
+    pub fn entries_after(&self, id: i64, limit: usize) -> rusqlite::Result<Vec<MirroredEntry>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, time, action, rule, source, destination, hash,
+                (SELECT signature FROM signatures WHERE move_id = moves.id ORDER BY id DESC LIMIT 1)
+            FROM moves WHERE id > ?1 ORDER BY id LIMIT ?2",
+        )?;
+        let rows = statement.query_map(params![id, limit as i64], |row| {
+            Ok(MirroredEntry {
+                id: row.get(0)?,
+                time: timestamp(row.get(1)?),
+                action: row.get(2)?,
+                rule: row.get(3)?,
+                source: row.get(4)?,
+                destination: row.get(5)?,
+                hash: row.get(6)?,
+                signature: row.get(7)?,
+            })
+        })?;
+        rows.collect()
+    }
+    
+    /// The newest entry that reached the mirror `target`, 0 when none did.
+    pub fn mirrored(&self, target: &str) -> rusqlite::Result<i64> {
+        let last = self
+            .connection
+            .query_row("SELECT last_id FROM mirrors WHERE target = ?1", [target], |row| row.get(0))
+            .optional()?;
+        Ok(last.unwrap_or(0))
+    }
+    
+    pub fn set_mirrored(&self, target: &str, id: i64) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "INSERT INTO mirrors (target, last_id) VALUES (?1, ?2) ON CONFLICT (target) DO UPDATE SET last_id = ?2",
+            params![target, id],
+        )?;
+        Ok(())
+    }
+    
+    /// Keeps the clutter of a folder, replacing an earlier measurement of the same day.
+    pub fn record_clutter(&self, clutter: &Clutter) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO clutter (folder, day, time, files, unmatched, week, month, year, older, duplicates, score)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                clutter.folder,
+                clutter.time.format("%Y-%m-%d").to_string(),
+                clutter.time.timestamp(),
+                clutter.files as i64,
+                clutter.unmatched as i64,
+                clutter.ages.week as i64,
+                clutter.ages.month as i64,
+                clutter.ages.year as i64,
+                clutter.ages.older as i64,
+                clutter.duplicates as i64,
+                clutter.score,
+            ],
+        )?;
+        Ok(())
+    }
+    
+    /// The daily clutter of `folder` since `since`, oldest first.
+    pub fn clutter(&self, folder: &str, since: DateTime<Local>) -> rusqlite::Result<Vec<Clutter>> {
+        let mut statement = self.connection.prepare(
+            "SELECT time, files, unmatched, week, month, year, older, duplicates, score FROM clutter
+            WHERE folder = ?1 AND time >= ?2 ORDER BY time",
+        )?;
+        let rows = statement.query_map(params![folder, since.timestamp()], |row| {
+            Ok(Clutter {
+                time: timestamp(row.get(0)?),
+                folder: folder.to_string(),
+                files: row.get::<_, i64>(1)? as usize,
+                unmatched: row.get::<_, i64>(2)? as usize,
+                ages: Ages {
+                    week: row.get::<_, i64>(3)? as usize,
+                    month: row.get::<_, i64>(4)? as usize,
+                    year: row.get::<_, i64>(5)? as usize,
+                    older: row.get::<_, i64>(6)? as usize,
+                },
+                duplicates: row.get::<_, i64>(7)? as usize,
+                score: row.get(8)?,
+            })
+        })?;
+        rows.collect()
+    }
+    
+    /// Returns matching entries, newest first.
+    pub fn history(&self, filter: &HistoryFilter) -> rusqlite::Result<Vec<IndexEntry>> {
+        let mut conditions = Vec::new();
+        let mut values: Vec<Box<dyn ToSql>> = Vec::new();
+        if let Some(since) = filter.since {
+            conditions.push("time >= ?");
+            values.push(Box::new(since.timestamp()));
+        }
+        if let Some(rule) = &filter.rule {
+            conditions.push("rule = ?");
+            values.push(Box::new(rule.clone()));
+        }
+        if let Some(destination) = &filter.destination {
+            conditions.push("destination LIKE ? ESCAPE '\\'");
+            values.push(Box::new(containing(destination)));
+        }
+        if let Some(search) = &filter.search {
+            conditions.push("(source LIKE ? ESCAPE '\\' OR destination LIKE ? ESCAPE '\\')");
+            values.push(Box::new(containing(search)));
+            values.push(Box::new(containing(search)));
+        }
+        
+        let mut sql = String::from("SELECT time, action, rule, source, destination, checksum FROM moves");
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+        sql.push_str(" ORDER BY time DESC, id DESC");
+        if let Some(limit) = filter.limit {
+            sql.push_str(&format!(" LIMIT {}", limit));
+        }
+        
+        let mut statement = self.connection.prepare(&sql)?;
+        let params: Vec<&dyn ToSql> = values.iter().map(|value| value.as_ref()).collect();
+        let rows = statement.query_map(params.as_slice(), |row| {
+            Ok(IndexEntry {
+                time: timestamp(row.get(0)?),
+                action: row.get(1)?,
+                rule: row.get(2)?,
+                source: row.get(3)?,
+                destination: row.get(4)?,
+                checksum: row.get(5)?,
+            })
+        })?;
+        rows.collect()
+    }
+    
+    /// Totals per rule of the files placed and the errors recorded since `since`, ordered by
+    /// rule. Only moves and copies by a rule count, and not those that were put back since;
+    /// trash, staging and undo entries don't.
+    pub fn summary(&self, since: Option<DateTime<Local>>, top: usize) -> rusqlite::Result<Vec<RuleSummary>> {
+        let since = since.map_or(0, |since| since.timestamp());
+        let mut summaries = Summaries::new();
+        let mut statement = self.connection.prepare(
+            "SELECT rule, destination, size FROM moves AS placed
+             WHERE time >= ?1 AND action IN ('move', 'copy', 'release') AND rule IS NOT NULL
+             AND NOT EXISTS (
+                 SELECT 1 FROM moves AS undone
+                 WHERE undone.id > placed.id AND undone.source = placed.destination AND undone.destination = placed.source
+             )",
+        )?;
+        let mut rows = statement.query([since])?;
+        while let Some(row) = rows.next()? {
+            let destination: String = row.get(1)?;
+            let size: Option<i64> = row.get(2)?;
+            let (summary, folders) = summary_entry(&mut summaries, row.get(0)?);
+            summary.files += 1;
+            summary.bytes += size.unwrap_or_default().max(0) as u64;
+            let folder = Path::new(&destination).parent().map(|folder| folder.to_string_lossy().into_owned());
+            *folders.entry(folder.unwrap_or(destination)).or_default() += 1;
+        }
+        let mut statement = self
+            .connection
+            .prepare("SELECT rule, COUNT(*) FROM errors WHERE time >= ?1 GROUP BY rule")?;
+        let mut rows = statement.query([since])?;
+        while let Some(row) = rows.next()? {
+            let errors: i64 = row.get(1)?;
+            summary_entry(&mut summaries, row.get(0)?).0.errors = errors as usize;
+        }
+        
+        Ok(summaries
+            .into_values()
+            .map(|(mut summary, folders)| {
+                let mut folders: Vec<FolderCount> = folders.into_iter().map(|(folder, files)| FolderCount { folder, files }).collect();
+                folders.sort_by(|a, b| b.files.cmp(&a.files).then_with(|| a.folder.cmp(&b.folder)));
+                folders.truncate(top);
+                summary.top_destinations = folders;
+                summary
+            })
+            .collect())
+    }
+    
+    /// Returns the current location of every file that sorts moved into place, following
+    /// later moves of the same file. Copies are left out, their originals are still sorted.
+    pub fn placements(&self) -> rusqlite::Result<Vec<Placement>> {
+        self.locations("'move', 'release', 'reorganize', 'normalize', 'renamed', 'deleted', 'trash'")
+    }
+    
+    /// Like `placements`, with the copies rules placed as well, for cleanups that only
+    /// touch files sorts put in a destination.
+    pub fn placed_files(&self) -> rusqlite::Result<Vec<Placement>> {
+        self.locations("'move', 'copy', 'release', 'reorganize', 'normalize', 'renamed', 'deleted', 'trash', 'archive', 'lifecycle', 'delete'")
+    }
+    
+    fn locations(&self, actions: &str) -> rusqlite::Result<Vec<Placement>> {
+        let mut statement = self
+            .connection
+            .prepare(&format!("SELECT action, rule, source, destination FROM moves WHERE action IN ({}) ORDER BY id", actions))?;
+        let rows = statement.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?, row.get::<_, String>(2)?, row.get::<_, String>(3)?))
+        })?;
+        let mut current = BTreeMap::new();
+        for row in rows {
+            let (action, rule, source, destination) = row?;
+            // Copies leave their source in place.
+            let placed_by = if action == "copy" { None } else { current.remove(&source) };
+            // Renames keep the rule that placed the file, and only count for placed files.
+            match (placed_by, action.as_str()) {
+                (Some(placed_by), "normalize" | "renamed") => current.insert(destination, placed_by),
+                (_, "normalize" | "renamed" | "deleted" | "trash" | "archive" | "lifecycle" | "delete") => None,
+                _ => current.insert(destination, rule),
+            };
+        }
+        Ok(current.into_iter().map(|(path, rule)| Placement { rule, path }).collect())
+    }
+    
+    /// Where the files moved since `since` went, by the path they were moved from.
+    pub fn recent_moves(&self, since: DateTime<Local>) -> rusqlite::Result<HashMap<String, String>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT source, destination FROM moves WHERE action = 'move' AND time >= ?1 ORDER BY id")?;
+        let rows = statement.query_map([since.timestamp()], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+    
+    /// Whether a sort moved or removed a file from `source`; copies leave it in place.
+    pub fn moved_from(&self, source: &Path) -> rusqlite::Result<bool> {
+        self.connection.query_row(
+            "SELECT EXISTS (SELECT 1 FROM moves WHERE source = ?1 AND action != 'copy')",
+            [source.to_string_lossy()],
+            |row| row.get(0),
+        )
+    }
+    
+    /// Counts a file that reappeared at `source` after it was moved away. Returns how often
+    /// that happened within a day, or `None` when this reappearance was counted before.
+    pub fn record_echo(&self, source: &Path) -> rusqlite::Result<Option<u32>> {
+        let source = source.to_string_lossy();
+        let now = Local::now().timestamp();
+        let moved: Option<i64> = self.connection.query_row(
+            "SELECT MAX(id) FROM moves WHERE action = 'move' AND source = ?1",
+            [&source],
+            |row| row.get(0),
+        )?;
+        let previous: Option<(Option<i64>, u32, i64)> = self
+            .connection
+            .query_row("SELECT move_id, count, time FROM echoes WHERE source = ?1", [&source], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .optional()?;
+        let count = match previous {
+            Some((echoed, _, _)) if echoed == moved => return Ok(None),
+            Some((_, count, time)) if now - time < 24 * 60 * 60 => count + 1,
+            _ => 1,
+        };
+        self.connection.execute(
+            "INSERT OR REPLACE INTO echoes (source, move_id, count, time) VALUES (?1, ?2, ?3, ?4)",
+            params![source, moved, count, now],
+        )?;
+        Ok(Some(count))
+    }
+    
+    /// Where files were copied to; copies stay where they were put.
+    pub fn copied_files(&self) -> rusqlite::Result<Vec<String>> {
+        let mut statement = self.connection.prepare("SELECT DISTINCT destination FROM moves WHERE action = 'copy'")?;
+        let rows = statement.query_map([], |row| row.get(0))?;
+        rows.collect()
+    }
+    
+    /// The checksum a file was verified with when it was last copied to `path`.
+    pub fn checksum(&self, path: &str) -> rusqlite::Result<Option<String>> {
+        self.connection
+            .query_row(
+                "SELECT checksum FROM moves WHERE destination = ?1 AND checksum IS NOT NULL ORDER BY id DESC LIMIT 1",
+                [path],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+    
+    /// What the scrubs found the files they read to be, by path.
+    pub fn scrub_records(&self) -> rusqlite::Result<HashMap<String, ScrubRecord>> {
+        let mut statement = self.connection.prepare("SELECT path, hash, size, modified, checked, error FROM scrub")?;
+        let rows = statement.query_map([], |row| {
+            let record = ScrubRecord {
+                hash: row.get(1)?,
+                size: row.get::<_, i64>(2)? as u64,
+                modified: row.get(3)?,
+                checked: row.get(4)?,
+                error: row.get(5)?,
+            };
+            Ok((row.get(0)?, record))
+        })?;
+        rows.collect()
+    }
+    
+    pub fn record_scrub(&self, path: &str, record: &ScrubRecord) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO scrub (path, hash, size, modified, checked, error) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![path, record.hash, record.size as i64, record.modified, record.checked, record.error],
+        )?;
+        Ok(())
+    }
+    
+    /// The hashes of the files in protected destinations, by path.
+    pub fn guarded(&self) -> rusqlite::Result<HashMap<String, String>> {
+        let mut statement = self.connection.prepare("SELECT path, hash FROM guarded")?;
+        let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+    
+    pub fn record_guarded(&self, path: &str, hash: &str) -> rusqlite::Result<()> {
+        self.connection
+            .execute("INSERT OR REPLACE INTO guarded (path, hash) VALUES (?1, ?2)", params![path, hash])?;
+        Ok(())
+    }
+    
+    /// When a daemon last sorted `folder`, to tell how long it was stopped.
+    pub fn last_seen(&self, folder: &Path) -> rusqlite::Result<Option<DateTime<Local>>> {
+        let time: Option<i64> = self
+            .connection
+            .query_row("SELECT time FROM heartbeats WHERE folder = ?1", [folder.to_string_lossy()], |row| row.get(0))
+            .optional()?;
+        Ok(time.map(timestamp))
+    }
+    
+    pub fn record_heartbeat(&self, folder: &Path) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO heartbeats (folder, time) VALUES (?1, ?2)",
+            params![folder.to_string_lossy(), Local::now().timestamp()],
+        )?;
+        Ok(())
+    }
+    
+    /// When a scrub last read a file.
+    pub fn last_scrub(&self) -> rusqlite::Result<Option<DateTime<Local>>> {
+        let last: Option<i64> = self.connection.query_row("SELECT MAX(checked) FROM scrub", [], |row| row.get(0))?;
+        Ok(last.map(timestamp))
+    }
+    
+    /// Finds files whose original name (or path, when the pattern contains a separator)
+    /// matches `pattern` and returns their latest known location.
+    pub fn locate(&self, pattern: &glob::Pattern) -> rusqlite::Result<Vec<Location>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT time, source, destination FROM moves ORDER BY id")?;
+        let rows: Vec<(i64, String, String)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        
+        let mut by_source: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (position, (_, source, _)) in rows.iter().enumerate() {
+            by_source.entry(source.as_str()).or_default().push(position);
+        }
+        
+        let mut destinations = HashSet::new();
+        let mut locations = Vec::new();
+        for (position, (time, source, destination)) in rows.iter().enumerate() {
+            // Moves of a file that was itself placed by an earlier move continue that file's chain.
+            let continues_chain = destinations.contains(source.as_str());
+            destinations.insert(destination.as_str());
+            if continues_chain || !matches_pattern(pattern, source) {
+                continue;
+            }
+            
+            let (mut current, mut current_position, mut last_time, mut moves) = (destination.as_str(), position, *time, 1);
+            while let Some(&next) = by_source
+                .get(current)
+                .and_then(|positions| positions.iter().find(|&&next| next > current_position))
+            {
+                current = rows[next].2.as_str();
+                current_position = next;
+                last_time = rows[next].0;
+                moves += 1;
+            }
+            
+            locations.push(Location {
+                original: source.clone(),
+                first_moved: timestamp(*time),
+                last_moved: timestamp(last_time),
+                current: current.to_string(),
+                moves,
+                // Files uploaded elsewhere can't be looked for here.
+                exists: remote::is_remote(current) || Path::new(current).exists(),
+            });
+        }
+        Ok(locations)
+    }
+}
+
+/// A `LIKE` pattern for text containing `text`, in which `%` and `_` are taken as they are.
+fn containing(text: &str) -> String {
+    let escaped = text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+fn summary_entry(summaries: &mut Summaries, rule: Option<String>) -> &mut (RuleSummary, HashMap<String, usize>) {
+    summaries.entry(rule.clone()).or_insert_with(|| {
+        let summary = RuleSummary {
+            rule,
+            files: 0,
+            bytes: 0,
+            errors: 0,
+            top_destinations: Vec::new(),
+        };
+        (summary, HashMap::new())
+    })
+}
+
+fn matches_pattern(pattern: &glob::Pattern, source: &str) -> bool {
+    if pattern.as_str().contains(['/', '\\']) {
+        return pattern.matches(source);
+    }
+    Path::new(source)
+        .file_name()
+        .is_some_and(|name| pattern.matches(&name.to_string_lossy()))
+}
+
+fn timestamp(seconds: i64) -> DateTime<Local> {
+    Local.timestamp_opt(seconds, 0).single().unwrap_or_default()
+}
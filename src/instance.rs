@@ -0,0 +1,104 @@
+use crate::paths;
+use crate::statefile;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A PID file per watched directory, locked for as long as a daemon sorts it.
+pub struct InstanceLock {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl InstanceLock {
+    /// Fails with `AlreadyExists` when another daemon is sorting `directory`.
+    pub fn acquire(directory: &Path) -> io::Result<InstanceLock> {
+        let directory = fs::canonicalize(directory).unwrap_or_else(|_| directory.to_path_buf());
+        let path = daemon_file(&directory, "pid");
+        fs::create_dir_all(path.parent().unwrap())?;
+        
+        let mut file = match open_locked(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                let pid = fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|contents| contents.lines().next()?.trim().parse::<u32>().ok());
+                let message = match pid {
+                    Some(pid) => format!("Another daemon (PID {}) is already sorting {:?}", pid, directory),
+                    None => format!("Another daemon is already sorting {:?}", directory),
+                };
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, message));
+            }
+            Err(e) => return Err(e),
+        };
+        file.set_len(0)?;
+        writeln!(file, "{}\n{}", std::process::id(), directory.display())?;
+        Ok(InstanceLock { path, file: Some(file) })
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // Windows can't remove files that are still open.
+        drop(self.file.take());
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The file of the daemon for `directory` in the data directory, named by a hash of it.
+fn daemon_file(directory: &Path, extension: &str) -> PathBuf {
+    let hash = blake3::hash(directory.to_string_lossy().as_bytes()).to_hex();
+    paths::data_dir().join("daemons").join(format!("{}.{}", &hash.as_str()[..16], extension))
+}
+
+/// Waits until nothing else is sorting `directory`, then keeps daemons and `apply` from
+/// sorting it until the lock is dropped.
+pub fn sorting(directory: &Path) -> io::Result<statefile::Lock> {
+    let directory = fs::canonicalize(directory).unwrap_or_else(|_| directory.to_path_buf());
+    statefile::lock(&daemon_file(&directory, "sort"))
+}
+
+/// Whether any daemon is sorting right now, judged by the PID files that are still locked.
+pub fn any_running() -> bool {
+    let Ok(entries) = fs::read_dir(paths::data_dir().join("daemons")) else { return false };
+    entries
+        .flatten()
+        .filter(|entry| entry.path().extension().is_some_and(|extension| extension == "pid"))
+        .any(|entry| open_locked(&entry.path()).is_err_and(|e| e.kind() == io::ErrorKind::WouldBlock))
+}
+
+/// Opens the PID file without truncating it, so a running daemon's PID stays readable.
+#[cfg(unix)]
+fn open_locked(path: &Path) -> io::Result<File> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = OpenOptions::new().read(true).write(true).create(true).open(path)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(file)
+}
+
+#[cfg(windows)]
+fn open_locked(path: &Path) -> io::Result<File> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use windows_sys::Win32::Foundation::ERROR_SHARING_VIOLATION;
+    use windows_sys::Win32::Storage::FileSystem::FILE_SHARE_READ;
+
+    // Others may read the PID, but nobody else can open the file for writing.
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .share_mode(FILE_SHARE_READ)
+        .open(path)
+        .map_err(|e| match e.raw_os_error() {
+            Some(code) if code as u32 == ERROR_SHARING_VIOLATION => io::Error::from(io::ErrorKind::WouldBlock),
+            _ => e,
+        })
+}
+
+#[cfg(not(any(unix, windows)))]
+fn open_locked(path: &Path) -> io::Result<File> {
+    OpenOptions::new().read(true).write(true).create(true).open(path)
+}
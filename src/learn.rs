@@ -0,0 +1,237 @@
+use crate::index;
+use crate::paths;
+use crate::rules::{self, Rule};
+use chrono::{DateTime, Local};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+/// How deep below each folder moved files are looked for.
+const SEARCH_DEPTH: usize = 3;
+
+/// Rules the daemon learns from files moved out of the sorted folder by hand, from `learn`
+/// in `config.json`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LearnSettings {
+    /// Folders where moved files are looked for, besides the sorted folder itself.
+    #[serde(default = "default_folders")]
+    pub folders: Vec<String>,
+    /// Files moved the same way before a rule is proposed.
+    #[serde(default = "default_examples")]
+    pub examples: usize,
+    /// Hours between notifications about proposed rules.
+    #[serde(default = "default_every_hours")]
+    pub every_hours: u64,
+}
+
+fn default_folders() -> Vec<String> {
+    ["xdg:desktop", "xdg:documents", "xdg:music", "xdg:pictures", "xdg:videos"]
+        .map(String::from)
+        .to_vec()
+}
+
+fn default_examples() -> usize {
+    3
+}
+
+fn default_every_hours() -> u64 {
+    24
+}
+
+/// A rule that would have done what the user did by hand.
+pub struct Proposal {
+    pub pattern: String,
+    pub destination: String,
+    /// Files the user moved this way.
+    pub examples: usize,
+}
+
+impl Proposal {
+    pub fn rule(&self) -> Rule {
+        Rule::new(&self.pattern, &self.destination)
+    }
+}
+
+/// Files seen moved by hand, kept next to the move index.
+pub struct Learner {
+    settings: LearnSettings,
+    connection: Connection,
+    proposed: Option<DateTime<Local>>,
+}
+
+impl Learner {
+    pub fn open(settings: LearnSettings) -> rusqlite::Result<Learner> {
+        let connection = index::connect()?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS learned_moves (
+                time INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                extension TEXT NOT NULL,
+                folder TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS learn_dismissed (
+                pattern TEXT NOT NULL,
+                destination TEXT NOT NULL,
+                PRIMARY KEY (pattern, destination)
+            );",
+        )?;
+        Ok(Learner {
+            settings,
+            connection,
+            proposed: None,
+        })
+    }
+
+    /// Looks for files moved out of the sorted folder by hand, and remembers the folder of
+    /// each one found by its name alone. Returns how many.
+    pub fn observe(&self, removed: &[PathBuf], root: &Path) -> rusqlite::Result<usize> {
+        let mut wanted: HashMap<String, usize> = removed
+            .iter()
+            .filter(|file| fs::symlink_metadata(file).is_err())
+            .filter_map(|file| Some((file.file_name()?.to_string_lossy().into_owned(), 0)))
+            .collect();
+        if wanted.is_empty() {
+            return Ok(0);
+        }
+        let mut found: HashMap<String, PathBuf> = HashMap::new();
+        let mut folders: Vec<PathBuf> = self.settings.folders.iter().map(|folder| paths::resolve_path(folder)).collect();
+        folders.push(root.to_path_buf());
+        let mut searched = HashSet::new();
+        for folder in folders {
+            if searched.insert(folder.clone()) {
+                search(&folder, SEARCH_DEPTH, &mut wanted, &mut found);
+            }
+        }
+        let now = Local::now().timestamp();
+        let mut learned = 0;
+        for (name, path) in found {
+            // A name found in several places says nothing about where the file went.
+            let (Some(folder), Some(1)) = (path.parent(), wanted.get(&name)) else {
+                continue;
+            };
+            let Some(extension) = path.extension() else { continue };
+            debug!(file = ?path, "Learned from a file moved by hand");
+            self.connection.execute(
+                "INSERT INTO learned_moves (time, name, extension, folder) VALUES (?1, ?2, ?3, ?4)",
+                params![now, name, extension.to_string_lossy(), folder.to_string_lossy()],
+            )?;
+            learned += 1;
+        }
+        Ok(learned)
+    }
+
+    /// Rules for the extensions mostly moved to one folder, often enough, which no rule in
+    /// `rules` handles yet and which were not dismissed. Those with most examples come first.
+    pub fn proposals(&self, rules: &[Rule]) -> rusqlite::Result<Vec<Proposal>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT extension, folder, COUNT(*), GROUP_CONCAT(name, '/') FROM learned_moves GROUP BY extension, folder")?;
+        let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?;
+        let groups: Vec<(String, String, i64, String)> = rows.collect::<rusqlite::Result<_>>()?;
+        let mut totals: HashMap<&str, i64> = HashMap::new();
+        for (extension, _, count, _) in &groups {
+            *totals.entry(extension).or_default() += count;
+        }
+        let mut proposals = Vec::new();
+        for (extension, folder, count, names) in &groups {
+            // Four in five files of the extension went there.
+            if (*count as usize) < self.settings.examples || count * 5 < totals[extension.as_str()] * 4 {
+                continue;
+            }
+            let names: Vec<&str> = names.split('/').collect();
+            if names
+                .iter()
+                .any(|name| rules.iter().any(|rule| rules::pattern_matches(&rule.pattern, Path::new(name))))
+            {
+                continue;
+            }
+            let pattern = match common_prefix(&names) {
+                Some(prefix) => format!("{}*.{}", prefix, extension),
+                None => format!(".{}", extension),
+            };
+            if !self.dismissed(&pattern, folder)? {
+                proposals.push(Proposal {
+                    pattern,
+                    destination: folder.clone(),
+                    examples: *count as usize,
+                });
+            }
+        }
+        proposals.sort_by(|a, b| b.examples.cmp(&a.examples).then_with(|| a.pattern.cmp(&b.pattern)));
+        Ok(proposals)
+    }
+
+    /// Stops proposing `proposal`.
+    pub fn dismiss(&self, proposal: &Proposal) -> rusqlite::Result<()> {
+        self.connection
+            .execute(
+                "INSERT OR IGNORE INTO learn_dismissed (pattern, destination) VALUES (?1, ?2)",
+                params![proposal.pattern, proposal.destination],
+            )
+            .map(|_| ())
+    }
+
+    fn dismissed(&self, pattern: &str, destination: &str) -> rusqlite::Result<bool> {
+        self.connection.query_row(
+            "SELECT EXISTS (SELECT 1 FROM learn_dismissed WHERE pattern = ?1 AND destination = ?2)",
+            params![pattern, destination],
+            |row| row.get(0),
+        )
+    }
+
+    /// The proposals to show, once every `every_hours`, when there are any.
+    pub fn due_proposals(&mut self, rules: &[Rule]) -> rusqlite::Result<Vec<Proposal>> {
+        let every = chrono::Duration::hours(self.settings.every_hours as i64);
+        if self.proposed.is_some_and(|proposed| Local::now() - proposed < every) {
+            return Ok(Vec::new());
+        }
+        let proposals = self.proposals(rules)?;
+        if !proposals.is_empty() {
+            self.proposed = Some(Local::now());
+            for proposal in &proposals {
+                info!(
+                    "Proposed rule: {} -> {} ({} files moved by hand)",
+                    proposal.pattern, proposal.destination, proposal.examples
+                );
+            }
+        }
+        Ok(proposals)
+    }
+}
+
+/// Records where the files named in `wanted` are below `folder`, counting each name found.
+fn search(folder: &Path, depth: usize, wanted: &mut HashMap<String, usize>, found: &mut HashMap<String, PathBuf>) {
+    let Ok(entries) = fs::read_dir(folder) else { return };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        let Ok(kind) = entry.file_type() else { continue };
+        if kind.is_dir() && depth > 0 {
+            search(&entry.path(), depth - 1, wanted, found);
+        } else if let Some(count) = wanted.get_mut(&name).filter(|_| kind.is_file()) {
+            *count += 1;
+            found.insert(name, entry.path());
+        }
+    }
+}
+
+/// The start the names share up to a separator, when it says more than the extension.
+fn common_prefix(names: &[&str]) -> Option<String> {
+    let (first, rest) = names.split_first()?;
+    let mut shared: &str = first;
+    for name in rest {
+        let end = shared
+            .char_indices()
+            .zip(name.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(shared.len().min(name.len()), |((at, _), _)| at);
+        shared = &shared[..end];
+    }
+    let prefix = &shared[..=shared.rfind(['_', '-', ' '])?];
+    (prefix.chars().filter(|c| c.is_alphanumeric()).count() >= 3).then(|| prefix.to_string())
+}
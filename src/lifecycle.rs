@@ -0,0 +1,269 @@
+use crate::archives::{self, CompressAction, CompressFormat};
+use crate::duplicates;
+use crate::paths;
+use crate::scan::{self, RepositoryPolicy};
+use crate::templates;
+use crate::transfer;
+use crate::trash;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How often the daemon takes files a stage further.
+pub const ADVANCE_EVERY: Duration = Duration::from_secs(60 * 60);
+
+/// One stage of a destination's `lifecycle`: what happens to the files of the stage before
+/// once they were not modified for `after_days`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Stage {
+    pub after_days: u64,
+    /// Folder the files move to, relative to the sorted folder, with placeholders filled in
+    /// from each file, e.g. `Archive/{year}`.
+    pub move_to: Option<String>,
+    /// Add the files to dated archives in the folder they are in.
+    pub compress: Option<CompressAction>,
+    /// Delete the files, or put them in the trash when that is on.
+    #[serde(default)]
+    pub delete: bool,
+}
+
+/// What a stage did with a file.
+pub enum Step {
+    /// Moved to this path, with the checksum it was verified with when it was copied there.
+    Moved(PathBuf, Option<String>),
+    Compressed(PathBuf),
+    Deleted,
+    /// Deleted into the trash, at this path.
+    Trashed(PathBuf),
+}
+
+/// Checks that each stage does one thing, that only the last one ends the lifecycle, and
+/// that the stages follow each other in time.
+pub fn validate(destination: &str, stages: &[Stage]) -> Result<(), String> {
+    for (position, stage) in stages.iter().enumerate() {
+        let steps = [stage.move_to.is_some(), stage.compress.is_some(), stage.delete];
+        if steps.iter().filter(|step| **step).count() != 1 {
+            return Err(format!(
+                "Stage {} of the lifecycle of {} needs one of `move_to`, `compress` and `delete`",
+                position + 1,
+                destination
+            ));
+        }
+        if stage.move_to.is_none() && position + 1 < stages.len() {
+            return Err(format!("Only the last stage of the lifecycle of {} can compress or delete files", destination));
+        }
+        if stage.move_to.as_deref().is_some_and(|move_to| templates::destination_top(move_to).trim_matches(['/', '.']).is_empty()) {
+            return Err(format!(
+                "Stage {} of the lifecycle of {} needs a `move_to` that starts with a folder of its own",
+                position + 1,
+                destination
+            ));
+        }
+        if position > 0 && stage.after_days <= stages[position - 1].after_days {
+            return Err(format!("The stages of the lifecycle of {} need growing `after_days`", destination));
+        }
+    }
+    Ok(())
+}
+
+/// Takes the files of each stage of `destination` below `root` to the next stage once they
+/// are old enough, or with `dry_run` only says which would go. A file moves one stage per
+/// call, the last stages first, so it is never taken through several at once. `others` are
+/// the folders of all destinations, whose files are left alone where they lie below a stage.
+/// Fails when a stage has no folder of its own, as it would take files across the whole
+/// sorted folder.
+pub fn advance(
+    root: &Path,
+    destination: &str,
+    stages: &[Stage],
+    others: &[PathBuf],
+    templates: &templates::Settings,
+    settings: &transfer::Settings,
+    dry_run: bool,
+) -> io::Result<Vec<(PathBuf, io::Result<Step>)>> {
+    // The files of a stage are those below the fixed part of where the stage before put them.
+    let sources = std::iter::once(destination)
+        .chain(stages.iter().filter_map(|stage| stage.move_to.as_deref()))
+        .map(|template| {
+            templates::managed_folder(root, template).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} doesn't start with a folder of its own below {}", template, root.display()),
+                )
+            })
+        })
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+    let now = SystemTime::now();
+    let mut steps = Vec::new();
+    for (position, stage) in stages.iter().enumerate().rev() {
+        let source = &sources[position];
+        // The folders of the other stages and destinations are left to them, even when they
+        // lie below this one.
+        let excluded: Vec<PathBuf> = sources
+            .iter()
+            .chain(others)
+            .filter(|other| *other != source && other.starts_with(source))
+            .cloned()
+            .collect();
+        let Ok(files) = scan::collect_files(source, true, &excluded, RepositoryPolicy::Skip, false, None) else { continue };
+        let cutoff = now - Duration::from_secs(stage.after_days * 24 * 60 * 60);
+        for file in files {
+            let old_enough = fs::metadata(&file).and_then(|metadata| metadata.modified()).is_ok_and(|modified| modified < cutoff);
+            if !old_enough || settings.protection.is_protected(&file) || stage.compress.as_ref().is_some_and(|compress| is_archive(&file, compress)) {
+                continue;
+            }
+            let step = take(root, &file, stage, templates, settings, dry_run);
+            steps.push((file, step));
+        }
+    }
+    Ok(steps)
+}
+
+/// Archives that a compressing stage made itself are not compressed again.
+fn is_archive(file: &Path, compress: &CompressAction) -> bool {
+    let extension = match compress.format {
+        CompressFormat::TarZst => ".tar.zst",
+        CompressFormat::Zip => ".zip",
+    };
+    file.file_name().is_some_and(|name| name.to_string_lossy().to_lowercase().ends_with(extension))
+}
+
+fn take(root: &Path, file: &Path, stage: &Stage, templates: &templates::Settings, settings: &transfer::Settings, dry_run: bool) -> io::Result<Step> {
+    let folder = file.parent().unwrap_or(root);
+    if let Some(template) = &stage.move_to {
+        let target = root.join(paths::resolve_path(&templates::expand_destination(template, file, templates)));
+        let target = duplicates::unique_path(&target.join(file.file_name().unwrap_or_default()));
+        if dry_run {
+            return Ok(Step::Moved(target, None));
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let checksum = transfer::move_file(file, &target, settings)?;
+        return Ok(Step::Moved(target, checksum));
+    }
+    if let Some(compress) = &stage.compress {
+        if dry_run {
+            return Ok(Step::Compressed(folder.to_path_buf()));
+        }
+        let compress = CompressAction {
+            older_than_days: None,
+            ..compress.clone()
+        };
+        let archive = archives::compress(file, folder, &compress)?.unwrap_or_else(|| folder.to_path_buf());
+        return Ok(Step::Compressed(archive));
+    }
+    if dry_run {
+        return Ok(Step::Deleted);
+    }
+    Ok(trash::remove(file, settings)?.map_or(Step::Deleted, |trashed| Step::Trashed(trashed.trashed)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    fn stage(after_days: u64, move_to: Option<&str>, delete: bool) -> Stage {
+        Stage {
+            after_days,
+            move_to: move_to.map(str::to_string),
+            compress: None,
+            delete,
+        }
+    }
+
+    fn stages() -> Vec<Stage> {
+        vec![stage(30, Some("Archive"), false), stage(365, None, true)]
+    }
+
+    fn folder(name: &str) -> TestDir {
+        TestDir::new("lifecycle", name)
+    }
+
+    fn add(root: &Path, name: &str, days_old: u64) -> PathBuf {
+        let file = root.join(name);
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(&file, name).unwrap();
+        let modified = SystemTime::now() - Duration::from_secs(days_old * 24 * 60 * 60);
+        fs::File::options().write(true).open(&file).unwrap().set_modified(modified).unwrap();
+        file
+    }
+
+    fn advance_inbox(root: &Path, others: &[PathBuf], dry_run: bool) -> Vec<PathBuf> {
+        let steps = advance(root, "Inbox", &stages(), others, &templates::Settings::default(), &transfer::Settings::default(), dry_run).unwrap();
+        steps
+            .into_iter()
+            .map(|(_, step)| match step.unwrap() {
+                Step::Moved(target, _) => target,
+                _ => panic!("the file wasn't moved"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn accepts_stages_that_move_and_then_delete() {
+        assert!(validate("Inbox", &stages()).is_ok());
+    }
+
+    #[test]
+    fn refuses_stages_that_do_more_or_less_than_one_thing() {
+        assert!(validate("Inbox", &[stage(30, Some("Archive"), true)]).is_err());
+        assert!(validate("Inbox", &[stage(30, None, false)]).is_err());
+    }
+
+    #[test]
+    fn refuses_stages_after_one_that_ends_the_lifecycle() {
+        assert!(validate("Inbox", &[stage(30, None, true), stage(365, Some("Archive"), false)]).is_err());
+    }
+
+    #[test]
+    fn refuses_stages_that_are_not_later_than_the_one_before() {
+        assert!(validate("Inbox", &[stage(30, Some("Archive"), false), stage(30, None, true)]).is_err());
+    }
+
+    #[test]
+    fn refuses_stages_without_a_folder_of_their_own() {
+        assert!(validate("Inbox", &[stage(30, Some("{year}"), false)]).is_err());
+        let root = folder("unmanaged");
+        let advanced = advance(&root, "{year}", &stages(), &[], &templates::Settings::default(), &transfer::Settings::default(), false);
+        assert_eq!(advanced.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn moves_files_once_they_are_old_enough() {
+        let root = folder("age");
+        add(&root, "Inbox/old.pdf", 40);
+        let new = add(&root, "Inbox/new.pdf", 1);
+        assert_eq!(advance_inbox(&root, &[], false), [root.join("Archive/old.pdf")]);
+        assert!(root.join("Archive/old.pdf").exists());
+        assert!(new.exists());
+    }
+
+    #[test]
+    fn takes_files_one_stage_at_a_time() {
+        let root = folder("stages");
+        add(&root, "Inbox/ancient.pdf", 400);
+        assert_eq!(advance_inbox(&root, &[], false), [root.join("Archive/ancient.pdf")]);
+        assert!(root.join("Archive/ancient.pdf").exists());
+    }
+
+    #[test]
+    fn leaves_the_files_of_other_destinations_alone() {
+        let root = folder("others");
+        let receipt = add(&root, "Inbox/Receipts/receipt.pdf", 40);
+        assert!(advance_inbox(&root, &[root.join("Inbox/Receipts")], false).is_empty());
+        assert!(receipt.exists());
+    }
+
+    #[test]
+    fn only_says_where_files_would_go_in_a_dry_run() {
+        let root = folder("dry-run");
+        let old = add(&root, "Inbox/old.pdf", 40);
+        assert_eq!(advance_inbox(&root, &[], true), [root.join("Archive/old.pdf")]);
+        assert!(old.exists());
+        assert!(!root.join("Archive").exists());
+    }
+}
@@ -0,0 +1,109 @@
+use crate::paths;
+use crate::statefile;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const LIMITS_FILE: &str = "rate_limits.json";
+const HOUR: u64 = 60 * 60;
+
+/// How often a rule may act, from its `cooldown` and `max_per_hour` settings.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Seconds that must pass after the rule handled a file before it handles the next one.
+    pub cooldown: Option<u64>,
+    pub max_per_hour: Option<u32>,
+}
+
+/// When each limited rule last handled files, kept across runs so that scheduled sorts
+/// and daemon restarts share the same budget.
+pub struct RateLimiter {
+    path: PathBuf,
+    fired: Mutex<Fired>,
+}
+
+#[derive(Default)]
+struct Fired {
+    /// When each rule handled files, as last read from the file and counted since.
+    times: BTreeMap<String, Vec<u64>>,
+    /// The times counted since the file was last saved, which saving adds to those other
+    /// processes saved meanwhile.
+    added: BTreeMap<String, Vec<u64>>,
+    /// How long the times of each rule counted here matter, so older ones can be dropped.
+    windows: BTreeMap<String, u64>,
+}
+
+impl RateLimiter {
+    pub fn load() -> Self {
+        let path = paths::data_dir().join(LIMITS_FILE);
+        let times = read(&path);
+        RateLimiter {
+            path,
+            fired: Mutex::new(Fired {
+                times,
+                ..Fired::default()
+            }),
+        }
+    }
+
+    /// Counts a file handled by `rule`, or returns why the rule has to wait.
+    pub fn acquire(&self, rule: &str, limit: &RateLimit) -> Result<(), String> {
+        let now = now();
+        let mut fired = self.fired.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let window = HOUR.max(limit.cooldown.unwrap_or_default());
+        fired.windows.insert(rule.to_string(), window);
+        let times = fired.times.entry(rule.to_string()).or_default();
+        times.retain(|time| now.saturating_sub(*time) < window);
+
+        if let (Some(cooldown), Some(last)) = (limit.cooldown, times.last()) {
+            let elapsed = now.saturating_sub(*last);
+            if elapsed < cooldown {
+                return Err(format!("rule is cooling down for another {}s", cooldown - elapsed));
+            }
+        }
+        if let Some(max) = limit.max_per_hour {
+            if times.iter().filter(|time| now.saturating_sub(**time) < HOUR).count() >= max as usize {
+                return Err(format!("rule already handled {} files in the last hour", max));
+            }
+        }
+        times.push(now);
+        fired.added.entry(rule.to_string()).or_default().push(now);
+        Ok(())
+    }
+
+    /// Adds the times counted since the last save to the file, under its lock, and takes
+    /// over those other processes counted meanwhile.
+    pub fn save(&self) -> std::io::Result<()> {
+        let mut fired = self.fired.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let _lock = statefile::lock(&self.path)?;
+        let mut times = read(&self.path);
+        for (rule, added) in std::mem::take(&mut fired.added) {
+            let rule_times = times.entry(rule).or_default();
+            rule_times.extend(added);
+            rule_times.sort_unstable();
+        }
+        let now = now();
+        for (rule, window) in &fired.windows {
+            if let Some(rule_times) = times.get_mut(rule) {
+                rule_times.retain(|time| now.saturating_sub(*time) < *window);
+            }
+        }
+        times.retain(|_, times| !times.is_empty());
+        statefile::write(&self.path, serde_json::to_string_pretty(&times)?.as_bytes())?;
+        fired.times = times;
+        Ok(())
+    }
+}
+
+fn read(path: &Path) -> BTreeMap<String, Vec<u64>> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or_default()
+}
@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What a sort does with a symlink that points to an existing file or folder.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymlinkPolicy {
+    /// Leave links out of sorts.
+    #[default]
+    Ignore,
+    /// Leave links in place and log a warning about each.
+    Skip,
+    /// Sort the file the link points to and remove the link once the file was moved.
+    Follow,
+    /// Move the link itself; relative links are rewritten to keep pointing at the same file.
+    Move,
+}
+
+/// What a sort does with a symlink whose target no longer exists.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum BrokenSymlinkPolicy {
+    /// Leave broken links out of sorts.
+    #[default]
+    Ignore,
+    /// Leave broken links in place and log a warning about each.
+    Skip,
+    /// Delete broken links, whether or not a rule matches them.
+    Remove,
+}
+
+/// A symlink found by the scanner.
+pub enum Link {
+    /// Points to an existing file or folder, given as its canonical path.
+    Valid(PathBuf),
+    Broken,
+}
+
+/// Returns what `path` links to, or `None` when it is not a symlink.
+pub fn inspect(path: &Path) -> Option<Link> {
+    let metadata = fs::symlink_metadata(path).ok()?;
+    if !metadata.file_type().is_symlink() {
+        return None;
+    }
+    Some(match path.canonicalize() {
+        Ok(target) => Link::Valid(target),
+        Err(_) => Link::Broken,
+    })
+}
@@ -0,0 +1,136 @@
+use crate::eventlog;
+use crate::progress;
+use clap::ValueEnum;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::Subscriber;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+pub struct LogOptions {
+    pub verbose: u8,
+    pub quiet: bool,
+    pub file: Option<PathBuf>,
+    pub format: LogFormat,
+    pub max_size: u64,
+    pub max_files: usize,
+    /// Also write warnings and errors to the Windows Event Log.
+    pub event_log: bool,
+}
+
+/// Sets the log level, e.g. `debug`, unless `-v` or `-q` is given.
+pub const LEVEL_ENV: &str = "ORGANIZER_LOG_LEVEL";
+
+pub fn init(options: &LogOptions) -> std::io::Result<()> {
+    let level = match (options.quiet, options.verbose) {
+        (true, _) => LevelFilter::ERROR,
+        (false, 0) => match std::env::var(LEVEL_ENV) {
+            Ok(level) => level.parse().map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Invalid {} '{}'; use error, warn, info, debug or trace", LEVEL_ENV, level))
+            })?,
+            Err(_) => LevelFilter::INFO,
+        },
+        (false, 1) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    };
+    
+    let mut layers = vec![format_layer(options.format, progress::stderr, true)];
+    if let Some(path) = &options.file {
+        let file = RotatingFile::open(path, options.max_size, options.max_files)?;
+        layers.push(format_layer(options.format, Mutex::new(file), false));
+    }
+    if options.event_log {
+        layers.push(eventlog::layer()?);
+    }
+    
+    tracing_subscriber::registry().with(layers).with(level).init();
+    Ok(())
+}
+
+fn format_layer<S, W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer).with_ansi(ansi);
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
+
+/// A log file that is rotated to `<name>.1`, `<name>.2`, ... once it grows past `max_size` bytes.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_size: u64, max_files: usize) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_size,
+            max_files,
+        })
+    }
+    
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = OsString::from(self.path.as_os_str());
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+    
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        if self.max_files > 0 {
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        self.file = OpenOptions::new().create(true).write(true).truncate(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+    
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -1,114 +1,5136 @@
-use clap::{Parser, Subcommand};
+mod acl;
+mod actions;
+mod antivirus;
+mod archives;
+mod arrivals;
+mod audit;
+mod capabilities;
+mod capacity;
+mod categories;
+mod cleanup;
+mod clutter;
+mod content;
+mod control;
+mod copies;
+mod dates;
+mod dbus;
+mod dedupe;
+mod directories;
+mod diskspace;
+mod exec;
+mod duplicates;
+mod error;
+mod eventlog;
+mod events;
+mod expiry;
+mod export;
+mod filenames;
+mod filetype;
+mod guard;
+mod handoff;
+mod hidden;
+mod images;
+mod import;
+mod index;
+mod instance;
+mod learn;
+mod lifecycle;
+mod limits;
+mod links;
+mod logging;
+mod media;
+mod metrics;
+mod mirror;
+mod naming;
+mod notify;
+mod onboarding;
+mod overlay;
+mod overrides;
+mod paths;
+mod permissions;
+mod pipelines;
+mod plan;
+mod plugins;
+mod policy;
+mod presence;
+mod profiles;
+mod progress;
+mod reconcile;
+mod reload;
+mod remote;
+mod removable;
+mod report;
+mod retention;
+mod retry;
+mod review;
+mod rules;
+mod safemode;
+mod safety;
+mod s3;
+mod scan;
+mod schedule;
+mod scrub;
+mod service;
+mod settings;
+mod shortcuts;
+mod shutdown;
+mod sources;
+mod staging;
+mod state;
+mod statefile;
+mod status;
+mod summary;
+mod tags;
+mod templates;
+#[cfg(test)]
+mod testdir;
+mod throttle;
+mod timing;
+mod transfer;
+mod trash;
+mod tui;
+mod unmatched;
+mod watch;
+mod web;
+mod webhook;
+
+use actions::{Applied, RuleActions};
+use antivirus::AntivirusSettings;
+use capabilities::{Capability, Grants};
+use chrono::{DateTime, Local};
+use clap::{ArgAction, CommandFactory, Parser, Subcommand, ValueEnum};
+use cleanup::CleanupSettings;
+use control::{Control, ControlCommand};
+use copies::{CopyLog, CopyState, Ingest};
+use dbus::Bus;
+use dedupe::DedupeIndex;
+use directories::DirectoryRule;
+use capacity::{CapacityEvent, CapacityMonitor, Reservation};
+use diskspace::{DiskEvent, DiskMonitor, DiskSpaceSettings, Preflight, SpacePlan};
+use duplicates::DuplicatePolicy;
+use error::SortError;
+use exec::ExecSettings;
+use expiry::RuleExpiry;
+use export::ExportFormat;
+use filenames::FileNameSettings;
+use guard::{Guard, Guarded};
+use handoff::{CompletedJob, HandoffSettings, JobQueue};
+use hidden::{HiddenPolicy, TemporaryAction, TemporarySettings};
+use import::ImportSource;
+use index::{HistoryFilter, MoveIndex, Sequences};
+use instance::InstanceLock;
+use learn::Learner;
+use lifecycle::Step;
+use limits::RateLimiter;
+use links::{BrokenSymlinkPolicy, Link, SymlinkPolicy};
+use logging::{LogFormat, LogOptions};
+use metrics::Metrics;
+use mirror::MirrorTarget;
 use mlua::Lua;
+use naming::{Journal, NameConflict, NamingConvention, Rename, RenameCommand};
+use notify::{NotificationSettings, Notifier};
+use organizer_engine::decide::{self, Tried};
+use organizer_engine::{kinds, matching, Clock, SystemClock};
+use overrides::FolderRules;
+use pipelines::{Pipeline, PipelineHealth};
+use plan::{Plan, Replay};
+use plugins::{PluginMatch, Plugins};
+use policy::MachinePolicy;
+use profiles::ProfilesCommand;
+use reconcile::Backlog;
+use reload::RuleFiles;
+use remote::RemoteSettings;
+use report::{Outcome, OutputFormat, Reporter, RuleMatch, SkipReason, Totals};
+use retention::{Cleaned, Limited, Retention};
+use retry::{Retry, RetryQueue};
+use review::{Decision, Planned, Review};
+use safemode::CrashLog;
+use rules::{Mode, Rule};
+use scan::{RepositoryPolicy, ScanState};
+use schedule::{Backoff, QuietHours, Schedule};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use service::ServiceCommand;
+use settings::Profile;
+use sources::SourceConfig;
+use staging::{StageAction, StagedFile, Staging, StagingCommand};
+use state::StateCommand;
+use status::ExitStatus;
+use summary::SummaryFormat;
+use templates::ScriptPolicy;
+use timing::Stage;
+use trash::Trashed;
+use transfer::Verifying;
+use unmatched::{UnmatchedReport, UnmatchedSettings, UNMATCHED_RULE};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
 use std::fs::File;
-use std::io::Read;
+use std::io::{IsTerminal, Read, Write};
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, error, info, warn};
+use watch::{WatchBackend, Watcher};
+use web::Web;
+use webhook::{Webhook, WebhookSettings};
+
+#[derive(Parser)]
+#[command(name = "FileSorter")]
+#[command(about = "A modular file organization tool", long_about = None)]
+struct Cli {
+    /// Without a command, and without rules yet, a guided setup starts
+    #[command(subcommand)]
+    command: Option<Commands>,
+    /// Increase log verbosity (-v for debug, -vv for trace)
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+    /// Only log errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Also write logs to this file
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+    /// Format of log output
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// Rotate the log file once it exceeds this many bytes
+    #[arg(long, global = true, default_value_t = 10 * 1024 * 1024)]
+    log_max_size: u64,
+    /// Number of rotated log files to keep
+    #[arg(long, global = true, default_value_t = 5)]
+    log_max_files: usize,
+    /// Use the folder, rules and daemon settings of this profile from config.json
+    #[arg(long, global = true, env = "ORGANIZER_PROFILE")]
+    profile: Option<String>,
+    /// Set a key of rules.json, or of config.json with `settings.`, e.g. `duplicates=keep-both`; can be repeated
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE")]
+    set: Vec<String>,
+    /// Sort folders Organizer otherwise refuses, such as a home folder or the root of a drive
+    #[arg(long, global = true)]
+    force: bool,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Sort files based on predefined rules
+    Sort {
+        /// The folder to sort; defaults to the `path` of the profile
+        #[arg(short, long)]
+        path: Option<String>,
+        /// Number of worker threads evaluating rules and moving files [default: 1]
+        #[arg(short, long)]
+        jobs: Option<usize>,
+        /// Output format; `json` prints one object per file and a final summary
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+        /// Only show what would be moved
+        #[arg(long, conflicts_with = "apply")]
+        dry_run: bool,
+        /// Move files even when dry runs are the configured default
+        #[arg(long)]
+        apply: bool,
+        /// Don't ask for confirmation when many files would be moved
+        #[arg(short, long)]
+        yes: bool,
+        /// Also sort files in subfolders, except hidden folders and rule destinations
+        #[arg(short, long)]
+        recursive: bool,
+        /// Only apply the rule with this name or `match` pattern; can be repeated
+        #[arg(long = "rule", value_name = "NAME")]
+        rule: Vec<String>,
+        /// Only apply these rules, e.g. `photos,docs`
+        #[arg(long, value_delimiter = ',', value_name = "NAMES")]
+        rules_only: Vec<String>,
+        /// Exit with status 2 when files were skipped, not only when they failed
+        #[arg(long)]
+        strict: bool,
+        /// Move matched files, or copy them and leave them in place
+        #[arg(long, value_enum)]
+        mode: Option<Mode>,
+        /// Never change the sorted folder: copy matched files out and import each file only once
+        #[arg(long, requires = "into", conflicts_with = "mode")]
+        read_only: bool,
+        /// Folder that destinations are relative to in read-only mode
+        #[arg(long, requires = "read_only", value_name = "DIR")]
+        into: Option<String>,
+        /// Ask before placing each file, with the choice to change its destination or to add a rule
+        #[arg(short, long, conflicts_with_all = ["dry_run", "yes"])]
+        interactive: bool,
+        /// Ask once for all files of a rule instead of for every file
+        #[arg(long, requires = "interactive")]
+        by_rule: bool,
+        /// Log a line per file instead of showing a progress bar
+        #[arg(long)]
+        no_progress: bool,
+        /// Sort only these files instead of every file in the folder
+        #[arg(conflicts_with = "files_from")]
+        files: Vec<PathBuf>,
+        /// Sort the files listed in this file, one per line, or those on stdin with `-`
+        #[arg(long, value_name = "FILE")]
+        files_from: Option<PathBuf>,
+        /// Paths in the list are separated by NUL characters, as `find -print0` writes them
+        #[arg(short = '0', long, requires = "files_from")]
+        null: bool,
+    },
+    /// Work out what a sort would do and save it as a plan, to review before `apply`
+    Plan {
+        /// The folder to sort; defaults to the `path` of the profile
+        #[arg(short, long)]
+        path: Option<String>,
+        /// File to write the plan to
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+        /// Also sort files in subfolders, except hidden folders and rule destinations
+        #[arg(short, long)]
+        recursive: bool,
+        /// Only apply the rule with this name or `match` pattern; can be repeated
+        #[arg(long = "rule", value_name = "NAME")]
+        rule: Vec<String>,
+    },
+    /// Move and copy files as a plan says, unless any of them changed since it was made
+    Apply {
+        /// The plan written by `plan`
+        plan: PathBuf,
+        /// Don't ask for confirmation when many files would be moved
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Run the file sorter as a background process
+    Daemon {
+        /// The folder to sort; defaults to the `path` of the profile
+        #[arg(short, long)]
+        path: Option<String>,
+        /// Seconds between sorts; changes noticed by the watcher start a sort earlier [default: 10]
+        #[arg(short, long)]
+        interval: Option<u64>,
+        /// Sort only at the times of this cron expression, e.g. "0 */2 * * *", instead of on changes
+        #[arg(long, value_parser = Schedule::parse, conflicts_with = "interval")]
+        schedule: Option<Schedule>,
+        /// When only polling, wait up to this many seconds between scans of a folder that stays quiet [default: 300]
+        #[arg(long, conflicts_with = "schedule")]
+        max_interval: Option<u64>,
+        /// Number of worker threads evaluating rules and moving files [default: 1]
+        #[arg(short, long)]
+        jobs: Option<usize>,
+        /// Also sort files in subfolders, except hidden folders and rule destinations
+        #[arg(short, long)]
+        recursive: bool,
+        /// How new files are noticed between scans
+        #[arg(long, value_enum, default_value_t = WatchBackend::Auto)]
+        watcher: WatchBackend,
+        /// Serve Prometheus metrics on this address, e.g. `127.0.0.1:9187`
+        #[arg(long)]
+        metrics_addr: Option<SocketAddr>,
+        /// Serve a web page and JSON API to watch and control the daemon on this address, e.g. `127.0.0.1:8686`
+        #[arg(long, value_name = "ADDRESS")]
+        http: Option<SocketAddr>,
+        /// Take the name org.mespeet.Organizer1 on this D-Bus bus, to be controlled from the desktop
+        #[arg(long, value_enum)]
+        dbus: Option<Bus>,
+        /// Append every scan, file result and error to this file as a line of JSON
+        #[arg(long, value_name = "FILE")]
+        events_file: Option<PathBuf>,
+        /// Move matched files, or copy them and leave them in place
+        #[arg(long, value_enum)]
+        mode: Option<Mode>,
+        /// Read at most this many MB per second when copying and compressing files
+        #[arg(long, value_name = "MB/s")]
+        throttle: Option<f64>,
+        /// Run with idle disk priority and the lowest CPU priority
+        #[arg(long)]
+        idle_priority: bool,
+        /// Read every folder and try every file on each sort, not only what changed
+        #[arg(long)]
+        full_scan: bool,
+        /// Only sort files created since the daemon first ran with this, leaving older ones alone
+        #[arg(long)]
+        new_only: bool,
+        /// Only sort files created since a date (2024-05-01, "2024-05-01 13:00") or age (12h, 7d), and remember that for --new-only
+        #[arg(long, value_parser = dates::parse_since, value_name = "WHEN")]
+        new_since: Option<DateTime<Local>>,
+        /// Run as the Windows service set up by `install`, with the rules in this folder
+        #[arg(long, hide = true, value_name = "RULES")]
+        service: Option<PathBuf>,
+    },
+    /// Install the daemon as a system service
+    Install {
+        /// The folder to sort; defaults to the `path` of the profile
+        #[arg(short, long)]
+        path: Option<String>,
+        /// Seconds between sorts [default: 10]
+        #[arg(short, long)]
+        interval: Option<u64>,
+    },
+    /// Start, stop or remove the Windows service set up by `install`
+    Service {
+        #[command(subcommand)]
+        command: ServiceCommand,
+    },
+    /// Read placed files back and report those that are corrupted, changed or missing
+    Scrub {
+        /// Files to read; those checked longest ago come first [default: from config.json, or 200]
+        #[arg(long)]
+        files: Option<usize>,
+    },
+    /// List the files destinations with `retention` clean up next, and exempt files from that
+    Retention {
+        /// The sorted folder destinations are relative to, for `--run`; defaults to the `path` of the profile
+        #[arg(short, long)]
+        path: Option<String>,
+        /// Never clean up these files
+        #[arg(long, value_name = "FILE")]
+        exempt: Vec<PathBuf>,
+        /// Let these exempted files be cleaned up again
+        #[arg(long, value_name = "FILE")]
+        unexempt: Vec<PathBuf>,
+        /// Clean up now rather than at the daemon's next daily cleanup
+        #[arg(long)]
+        run: bool,
+    },
+    /// Take files in destinations with a `lifecycle` to their next stage once they are old enough
+    Lifecycle {
+        /// The sorted folder destinations are relative to; defaults to the `path` of the profile
+        #[arg(short, long)]
+        path: Option<String>,
+        /// Only show which files would move on
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show previously performed actions from the move index
+    History {
+        /// Only show actions since a date (2024-05-01, "2024-05-01 13:00") or age (12h, 7d)
+        #[arg(long, value_parser = dates::parse_since)]
+        since: Option<DateTime<Local>>,
+        /// Only show actions performed by this rule, e.g. `.pdf`
+        #[arg(long)]
+        rule: Option<String>,
+        /// Only show actions whose destination contains this text
+        #[arg(long)]
+        dest: Option<String>,
+        /// Only show actions whose source or destination contains this text
+        #[arg(long)]
+        search: Option<String>,
+        /// Maximum number of entries to show
+        #[arg(short, long, default_value_t = 50)]
+        limit: usize,
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+    /// Summarize what each rule did from the move index, e.g. for a monthly report
+    Report {
+        /// Only count actions since a date (2024-05-01, "2024-05-01 13:00") or age (30d, 2w)
+        #[arg(long, value_parser = dates::parse_since)]
+        since: Option<DateTime<Local>>,
+        #[arg(short, long, value_enum, default_value_t = SummaryFormat::Table)]
+        format: SummaryFormat,
+    },
+    /// Move files placed by earlier sorts to where the current rules would put them
+    Reorganize {
+        #[arg(short, long)]
+        path: String,
+        /// Only show which files would be moved
+        #[arg(long)]
+        dry_run: bool,
+        /// Don't ask for confirmation before moving files
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Rename files to follow the `naming` convention from rules.json
+    Normalize {
+        #[arg(short, long, required_unless_present = "undo")]
+        path: Option<String>,
+        /// Also rename files in subfolders, except hidden folders
+        #[arg(short, long)]
+        recursive: bool,
+        /// Only show which files would be renamed
+        #[arg(long)]
+        dry_run: bool,
+        /// Don't ask for confirmation before renaming files
+        #[arg(short, long)]
+        yes: bool,
+        /// What to do when the new name is already taken
+        #[arg(long, value_enum, default_value_t = NameConflict::Skip)]
+        on_conflict: NameConflict,
+        /// Give the files renamed by the last run their old names back
+        #[arg(long, conflicts_with_all = ["path", "recursive", "dry_run", "on_conflict"])]
+        undo: bool,
+    },
+    /// Preview the names `rename` actions give files
+    Rename {
+        #[command(subcommand)]
+        command: RenameCommand,
+    },
+    /// Give files placed by earlier sorts the permissions their rules ask for
+    Permissions {
+        #[arg(short, long)]
+        path: String,
+        /// Only report which files have other permissions
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Verify or sign the tamper-evident audit trail of the move index
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommand,
+    },
+    /// Explain which rules match a file and where it would go
+    Test {
+        file: PathBuf,
+        /// The watched directory destinations are relative to; defaults to the file's folder
+        #[arg(short, long)]
+        path: Option<String>,
+    },
+    /// Print the rules the `layout` in rules.json generates, to copy into `rules` and adjust
+    Layout,
+    /// Show the configuration Organizer runs with
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Find where previously moved files are now
+    Locate {
+        /// Original file name or glob, e.g. `invoice*.pdf`
+        pattern: String,
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+    /// Find files by the tags the `tag` action wrote on them
+    Find {
+        /// The folder to search, including its subfolders
+        #[arg(short, long)]
+        path: String,
+        /// Only list files with this tag; repeat to require several
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        /// Only list files placed by this rule, e.g. `.pdf`
+        #[arg(long)]
+        rule: Option<String>,
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+    /// Convert the rules of another organizer into a rules file
+    Import {
+        #[arg(long, value_enum)]
+        from: ImportSource,
+        /// The other organizer's rule file
+        config: PathBuf,
+        /// Write the rules to this file instead of printing them
+        #[arg(short, long)]
+        write: Option<PathBuf>,
+    },
+    /// List the rules the daemon learned from files moved by hand, and adopt or dismiss them
+    Learn {
+        /// Add the proposed rules with these numbers to rules.json
+        #[arg(long)]
+        adopt: Vec<usize>,
+        /// Stop proposing the rules with these numbers
+        #[arg(long)]
+        dismiss: Vec<usize>,
+    },
+    /// Write the rules in another organizer's format
+    Export {
+        #[arg(long, value_enum)]
+        to: ExportFormat,
+        /// The directory the rules sort
+        #[arg(short, long)]
+        path: String,
+        /// Sort subfolders as well, like `sort --recursive`
+        #[arg(short, long)]
+        recursive: bool,
+        /// Write the rules to this file instead of printing them
+        #[arg(short, long)]
+        write: Option<PathBuf>,
+    },
+    /// Review files waiting in staging folders
+    Staging {
+        #[command(subcommand)]
+        command: StagingCommand,
+    },
+    /// Export or import the index, journals, queues and rules, e.g. to move to another machine
+    State {
+        #[command(subcommand)]
+        command: StateCommand,
+    },
+    /// Write everything cached for a destination's drive to it, so the drive can be ejected
+    Flush {
+        destination: String,
+        /// Pause a running daemon first, after the sort in progress; resume it with `ctl resume`
+        #[arg(long)]
+        pause: bool,
+        /// The folder the daemon to pause sorts; defaults to the `path` of the profile
+        #[arg(short, long)]
+        path: Option<String>,
+    },
+    /// Control a running daemon
+    Ctl {
+        /// The folder the daemon sorts; defaults to the `path` of the profile
+        #[arg(short, long)]
+        path: Option<String>,
+        #[command(subcommand)]
+        command: ControlCommand,
+    },
+    /// Watch a running daemon's activity live, and pause it or start a sort
+    Tui {
+        /// The folder the daemon sorts; defaults to the `path` of the profile
+        #[arg(short, long)]
+        path: Option<String>,
+    },
+    /// Show whether the daemon is in safe mode after repeated crashes, and leave it
+    Resume {
+        /// The sorted folder; defaults to the `path` of the profile
+        #[arg(short, long)]
+        path: Option<String>,
+        /// Let the daemon place files again instead of only showing what it would do
+        #[arg(long)]
+        enforce: bool,
+    },
+    /// Sort every user's downloads with the profiles an administrator set up
+    Profiles {
+        #[command(subcommand)]
+        command: ProfilesCommand,
+    },
+    /// Print the shell completions, e.g. `Organizer completions bash > /etc/bash_completion.d/Organizer`
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// Print the man page
+    Manpage {
+        /// Write a page for every subcommand to this folder instead
+        #[arg(long)]
+        dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum AuditCommand {
+    /// Create the signing key used for the move index
+    Keygen,
+    /// Sign the newest entries of the move index now
+    Sign,
+    /// Copy the entries the mirror from config.json doesn't have yet to it now
+    Mirror,
+    /// Check the hash chain and signatures of the move index
+    Verify {
+        /// Hex-encoded public key to check signatures with instead of the local signing key
+        #[arg(long)]
+        public_key: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the effective rules and settings, with the environment, `--set` and the machine
+    /// policy applied, and the rules in the order they are tried
+    Dump {
+        #[arg(long, value_enum, default_value_t = DumpFormat::Json)]
+        format: DumpFormat,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DumpFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+const RULES_FILE: &str = "rules.json";
+const LUA_SCRIPT: &str = "sort_rules.lua";
+
+/// The path of `name`, `rules.json` or the Lua script, in the rules folder.
+fn rules_path(name: &str) -> PathBuf {
+    match paths::rules_file() {
+        Some(file) if name == RULES_FILE => file,
+        _ => paths::rules_dir().join(name),
+    }
+}
+
+/// The folder given with `--path`, or else the profile's.
+fn folder_to_sort(path: &Option<String>, profile: &Profile) -> String {
+    match path.as_ref().or(profile.path.as_ref()) {
+        Some(path) => path.clone(),
+        None => {
+            error!("Give the folder to sort with --path, or pick a profile that sets one with --profile");
+            std::process::exit(ExitStatus::ConfigError.code());
+        }
+    }
+}
+
+/// Exits when `folder` is one Organizer only sorts with `--force`, such as a home folder.
+fn refuse_unsafe_folder(folder: &str, force: bool) {
+    if force {
+        return;
+    }
+    let repositories = load_rules().ok().flatten().map(|config| config.repositories).unwrap_or_default();
+    if let Some(reason) = safety::refusal(&paths::resolve_path(folder), repositories) {
+        error!("Refusing to sort {}; pass --force if this is really the folder you mean", reason);
+        std::process::exit(ExitStatus::ConfigError.code());
+    }
+}
+
+/// How often the daemon checks for control commands while waiting.
+const CONTROL_POLL: Duration = Duration::from_secs(1);
+
+/// Seconds after a move in which the same file reappearing at its old path is taken for
+/// an echo of a sync tool, unless `echo_window` says otherwise.
+const ECHO_WINDOW: u64 = 600;
+
+/// Reappearances of one file within a day after which the user is alerted.
+const ECHO_ALERT: u32 = 3;
+
+#[derive(Clone)]
+struct SortOptions {
+    jobs: usize,
+    output: OutputFormat,
+    dry_run: bool,
+    confirm_threshold: Option<usize>,
+    recursive: bool,
+    /// Names of the only rules to apply; empty applies all of them.
+    rules_only: Vec<String>,
+    /// Overrides the `mode` from `rules.json`.
+    mode: Option<Mode>,
+    /// Where destinations go when the sorted folder is read-only. Files are then only
+    /// copied out, and nothing in the sorted folder is changed.
+    into: Option<PathBuf>,
+    /// Only read folders and try files that changed since the last sort.
+    incremental: bool,
+    /// Ask before placing files, once per file or with `review_by_rule` once per rule.
+    interactive: bool,
+    review_by_rule: bool,
+    /// Only try the locked files whose retry is due, without reading the folder.
+    retry_only: bool,
+    /// Show a progress bar and a summary per rule instead of a line per file.
+    progress: bool,
+    /// Files to sort instead of those found in the folder, which destinations are still
+    /// relative to.
+    files: Option<Vec<PathBuf>>,
+    /// Leave files and folders created before this where they are.
+    new_since: Option<SystemTime>,
+}
+
+/// State kept across sort runs of one process.
+struct Session {
+    notifier: Option<Notifier>,
+    jobs: JobQueue,
+    staging: Staging,
+    index: Option<MoveIndex>,
+    /// Whether the user is at an unlocked desktop session right now.
+    user_active: bool,
+    /// Outcomes of the sorts since the totals were last taken.
+    totals: Totals,
+    metrics: Option<Arc<Metrics>>,
+    webhook: Webhook,
+    unmatched: UnmatchedReport,
+    limits: RateLimiter,
+    disk: DiskMonitor,
+    capacity: CapacityMonitor,
+    /// Where new index entries are copied after each sort.
+    mirror: Option<MirrorTarget>,
+    /// The daemon's control state, which keeps the activity shown by `organizer tui`.
+    control: Option<Arc<Control>>,
+    /// Locked files the daemon tries again; a single sort reports them as failed.
+    retries: Option<RetryQueue>,
+    /// Files that appeared while the daemon was stopped, still to be sorted.
+    backlog: Option<Backlog>,
+    pipelines: PipelineHealth,
+    /// Where the daemon learns rules from files moved by hand.
+    learner: Option<Learner>,
+    /// Collects what a dry run would do for `organizer plan`.
+    plan: Option<Plan>,
+    /// The plan `organizer apply` carries out, whose files are placed as it says.
+    replay: Option<Replay>,
+}
+
+/// Everything a worker needs to sort a single file.
+struct SortContext<'a> {
+    /// Folder that destinations are relative to.
+    directory: &'a Path,
+    config: &'a RulesConfig,
+    jobs: &'a JobQueue,
+    staging: &'a Staging,
+    dry_run: bool,
+    /// Paths that are left alone while the user is active.
+    deferred: Vec<PathBuf>,
+    /// Where files moved within the echo window went, by the path they were moved from.
+    echoes: HashMap<PathBuf, PathBuf>,
+    policy: &'a MachinePolicy,
+    /// Rules of folders with `.organizer.toml` files in them or above them.
+    overrides: HashMap<PathBuf, FolderRules>,
+    metrics: Option<&'a Metrics>,
+    limits: &'a RateLimiter,
+    disk: &'a DiskMonitor,
+    capacity: &'a CapacityMonitor,
+    /// Rules whose files stay in place because the preflight found no room for them.
+    short_of_space: HashSet<String>,
+    /// Rules of pipelines that are not running, with why.
+    stopped: HashMap<String, String>,
+    /// The answers of `sort --interactive`.
+    review: Option<&'a Review>,
+    /// Whether the sorted folder must not be changed.
+    read_only: bool,
+    /// The numbers of `{seq}` in rename templates.
+    sequences: Option<Mutex<Sequences>>,
+    /// Where files of this sort are moved to, so no two get the same name.
+    reserved: Mutex<HashSet<PathBuf>>,
+    /// The plan being applied, which decides instead of the rules.
+    replay: Option<&'a Replay>,
+    /// The files `hardlink_dedupe` destinations link to, when any destination has it.
+    dedupe: Option<DedupeIndex>,
+    /// What files are moved and copied through, and where the time they were placed at comes from.
+    fs: &'a (dyn Verifying + Sync),
+    clock: &'a (dyn Clock + Sync),
+    /// Files put in the trash, until the move index records them before the file that
+    /// replaced or removed them.
+    trashed: Mutex<Vec<Trashed>>,
+}
+
+impl SortContext<'_> {
+    fn keep_trashed(&self, trashed: impl IntoIterator<Item = Trashed>) {
+        self.trashed.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).extend(trashed);
+    }
+}
+
+impl Session {
+    fn new(notifier: Option<Notifier>) -> Self {
+        let index = open_index()
+            .map_err(|e| warn!("Move index unavailable, actions will not be recorded: {}", e))
+            .ok();
+        Session {
+            notifier,
+            jobs: JobQueue::load(),
+            staging: Staging::open(),
+            index,
+            user_active: false,
+            totals: Totals::default(),
+            metrics: None,
+            webhook: Webhook::default(),
+            unmatched: UnmatchedReport::default(),
+            limits: RateLimiter::load(),
+            disk: DiskMonitor::default(),
+            capacity: CapacityMonitor::default(),
+            mirror: settings::load_settings().mirror,
+            control: None,
+            retries: None,
+            backlog: None,
+            pipelines: PipelineHealth::default(),
+            learner: None,
+            plan: None,
+            replay: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+struct RulesConfig {
+    /// Tried by priority, first match wins unless a rule sets `continue`.
+    #[serde(default = "define_default_rules", deserialize_with = "rules::deserialize")]
+    rules: Vec<Rule>,
+    /// Folders to file each kind of file into, e.g. `Photos/{year}/{month}`; rules for
+    /// them are generated after the `rules`, which take precedence.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    layout: Vec<String>,
+    /// Folder names used instead of the built-in categories, e.g. `"Images": "Bilder"`.
+    #[serde(default)]
+    category_names: HashMap<String, String>,
+    #[serde(default)]
+    actions: HashMap<String, RuleActions>,
+    #[serde(default)]
+    notifications: NotificationSettings,
+    /// URL that moves and errors are posted to after each sort.
+    webhook: Option<WebhookSettings>,
+    #[serde(default)]
+    handoff: HandoffSettings,
+    /// Policy for files that already exist in their destination with identical contents.
+    duplicates: Option<DuplicatePolicy>,
+    /// Folders the daemon leaves alone while the user's session is active and unlocked.
+    #[serde(default)]
+    defer_while_active: Vec<String>,
+    /// Remote inboxes that are fetched into the watched directory before sorting.
+    #[serde(default)]
+    sources: Vec<SourceConfig>,
+    /// Copy into place and retry stale handles, for destinations on SMB or NFS shares.
+    #[serde(default)]
+    network_share: bool,
+    /// Read copies back and compare their checksum with the original before it is removed.
+    #[serde(default)]
+    verify_copies: bool,
+    /// Put files that are deleted or replaced in the system trash instead.
+    #[serde(default)]
+    trash: bool,
+    /// Globs of files and folders that are never moved or deleted, such as `*.kdbx`.
+    #[serde(default)]
+    protected: Vec<String>,
+    /// How the names of placed files are written: their Unicode form, or safe for FAT drives.
+    /// Which of their letters are written in Latin letters is up to `scripts`.
+    #[serde(default)]
+    file_names: FileNameSettings,
+    /// Whether recursive sorts go into Git working trees below the sorted folder.
+    #[serde(default)]
+    repositories: RepositoryPolicy,
+    /// Whether files whose name starts with a dot are sorted; rules with `hidden` always do.
+    #[serde(default)]
+    hidden_files: HiddenPolicy,
+    /// Whether downloads in progress and other temporary files are left alone or cleaned up.
+    #[serde(default)]
+    temporary: TemporarySettings,
+    /// Free space destinations' drives keep; rules placing files on a fuller drive wait.
+    disk_space: Option<DiskSpaceSettings>,
+    /// Whether rules move or copy files unless they say otherwise; `move` when not set.
+    mode: Option<Mode>,
+    /// Where files no rule claims go instead of staying in place.
+    #[serde(default)]
+    unmatched: UnmatchedSettings,
+    /// What happens to symlinks, unless the rule that matches them says otherwise.
+    #[serde(default)]
+    symlinks: SymlinkPolicy,
+    #[serde(default)]
+    broken_symlinks: BrokenSymlinkPolicy,
+    /// Scanner that rules with `scan` check files with before placing them.
+    antivirus: Option<AntivirusSettings>,
+    /// Where files that fail the checks of `verify_type` or `scan` go; `Quarantine` when not set.
+    quarantine: Option<String>,
+    /// Daily window in which the daemon doesn't sort, e.g. `22:00-07:00`.
+    quiet_hours: Option<QuietHours>,
+    /// How file names should look; applied by `normalize`.
+    #[serde(default)]
+    naming: NamingConvention,
+    /// Timeout and concurrency of the commands of rules with `exec`.
+    #[serde(default)]
+    exec: ExecSettings,
+    /// Regular expressions with `year`, `month` and `day` groups for dates in file names that
+    /// `{name_date}` doesn't recognize, e.g. `(?P<day>\d{2})\.(?P<month>\d{2})\.(?P<year>\d{4})`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    name_dates: Vec<String>,
+    /// Which metadata values in destinations, and letters in the names of placed files, are
+    /// written in Latin letters.
+    #[serde(default)]
+    scripts: ScriptPolicy,
+    /// `name_dates` and `scripts`, compiled when the rules are loaded.
+    #[serde(skip)]
+    templates: templates::Settings,
+    /// `network_share`, `verify_copies`, `trash` and `protected`, compiled when the rules are
+    /// loaded.
+    #[serde(skip)]
+    transfers: transfer::Settings,
+    /// WebAssembly plugins that are asked in order when no rule decided, before the Lua script.
+    #[serde(default)]
+    plugins: Vec<String>,
+    /// Capabilities given to the Lua script and plugins, by path and hash; they are refused
+    /// when they declare one they weren't given.
+    #[serde(default, skip_serializing_if = "Vec::is_empty", deserialize_with = "capabilities::deserialize")]
+    grants: Grants,
+    /// How to log in to `sftp://` and `s3://` destinations, by `sftp://host` or `s3://bucket`.
+    #[serde(default)]
+    remotes: HashMap<String, RemoteSettings>,
+    /// Seconds after a move in which an identical file reappearing at the old path is left
+    /// alone, since sync tools sometimes put moved files back; 0 turns this off.
+    echo_window: Option<u64>,
+    /// Folders that are moved as a whole, before the files in the sorted folder.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    directories: Vec<DirectoryRule>,
+    /// Named groups of rules that start in the order they require each other, and stop
+    /// while a requirement or condition fails.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pipelines: BTreeMap<String, Pipeline>,
+    /// Removing the folders a recursive sort emptied.
+    #[serde(default)]
+    cleanup: CleanupSettings,
+    /// Contents of `sort_rules.lua` when the rules were loaded, so a sort uses one version throughout.
+    #[serde(skip)]
+    lua_script: Option<String>,
+    /// What the Lua script declared it needs, and was granted.
+    #[serde(skip)]
+    lua_capabilities: BTreeSet<Capability>,
+    /// The `plugins`, compiled when the rules were loaded.
+    #[serde(skip)]
+    loaded_plugins: Plugins,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    
+    let log_options = LogOptions {
+        verbose: cli.verbose,
+        quiet: cli.quiet,
+        file: cli.log_file.clone(),
+        format: cli.log_format,
+        max_size: cli.log_max_size,
+        max_files: cli.log_max_files,
+        event_log: matches!(&cli.command, Some(Commands::Daemon { service: Some(_), .. })),
+    };
+    if let Err(e) = logging::init(&log_options) {
+        eprintln!("Error setting up logging: {}", e);
+        std::process::exit(1);
+    }
+    if let Err(e) = overlay::init(&cli.set) {
+        error!("{}", e);
+        std::process::exit(ExitStatus::ConfigError.code());
+    }
+    
+    let profile = match &cli.profile {
+        Some(name) => match settings::load_settings().profiles.remove(name) {
+            Some(profile) => profile,
+            None => {
+                error!("There is no profile named '{}' in {}", name, paths::config_dir().join(settings::SETTINGS_FILE).display());
+                std::process::exit(ExitStatus::ConfigError.code());
+            }
+        },
+        None => Profile::default(),
+    };
+    if let Some(path) = std::env::var_os(overlay::RULES_PATH_ENV).map(PathBuf::from) {
+        // A file, or what is named like one, is read in place of `rules.json`.
+        if path.is_file() || (!path.is_dir() && path.extension().is_some_and(|extension| extension == "json")) {
+            paths::set_rules_file(path);
+        } else {
+            paths::set_rules_dir(path);
+        }
+    } else if let Some(rules) = &profile.rules {
+        paths::set_rules_dir(paths::resolve_path(rules));
+    }
+    let Some(command) = &cli.command else {
+        first_run();
+        return;
+    };
+    match command {
+        Commands::Sort {
+            path,
+            jobs,
+            output,
+            dry_run,
+            apply,
+            yes,
+            recursive,
+            rule,
+            rules_only,
+            strict,
+            mode,
+            read_only: _,
+            into,
+            interactive,
+            by_rule,
+            no_progress,
+            files,
+            files_from,
+            null,
+        } => {
+            let path = &folder_to_sort(path, &profile);
+            refuse_unsafe_folder(path, cli.force);
+            let settings = settings::load_settings();
+            if *interactive && files_from.as_deref() == Some(Path::new("-")) {
+                error!("Answers to --interactive are read from stdin, so the files can't be listed there");
+                std::process::exit(ExitStatus::ConfigError.code());
+            }
+            let listed = match files_from {
+                Some(list) => match read_file_list(list, *null) {
+                    Ok(listed) => Some(listed),
+                    Err(e) => {
+                        error!("Error reading the files to sort from {}: {}", list.display(), e);
+                        std::process::exit(ExitStatus::Failure.code());
+                    }
+                },
+                None if !files.is_empty() => Some(files.clone()),
+                None => None,
+            };
+            let options = SortOptions {
+                jobs: jobs.or(profile.jobs).unwrap_or(1),
+                output: *output,
+                // Answering for every file is confirmation enough.
+                dry_run: !*interactive && (*dry_run || (settings.dry_run_by_default && !*apply)),
+                confirm_threshold: settings.confirm_threshold.filter(|_| !*yes && !*interactive),
+                recursive: *recursive || profile.recursive,
+                rules_only: rule.iter().chain(rules_only).cloned().collect(),
+                mode: mode.or(profile.mode),
+                into: into.as_deref().map(paths::resolve_path),
+                incremental: false,
+                interactive: *interactive,
+                review_by_rule: *by_rule,
+                retry_only: false,
+                // The bar would be drawn over the questions of a review.
+                progress: *output == OutputFormat::Text && !*no_progress && !*interactive && std::io::stderr().is_terminal(),
+                files: listed,
+                new_since: None,
+            };
+            let mut session = Session::new(None);
+            let result = try_load_config()
+                .map_err(SortError::Config)
+                .and_then(|config| sort_files(path, &options, &mut session, config));
+            let mut status = match result {
+                Ok(()) => ExitStatus::from_totals(&session.totals, *strict),
+                Err(e) => {
+                    error!("Error sorting files: {}", e);
+                    ExitStatus::from_error(&e, &session.totals)
+                }
+            };
+            match session.jobs.wait() {
+                Ok(completed) => record_completed(session.index.as_ref(), completed),
+                Err(e) => {
+                    error!("Error running handoff jobs: {}", e);
+                    status = status.with_job_error();
+                }
+            }
+            std::process::exit(status.code());
+        }
+        Commands::Daemon {
+            path,
+            interval,
+            schedule,
+            max_interval,
+            jobs,
+            recursive,
+            watcher,
+            metrics_addr,
+            http,
+            dbus,
+            events_file,
+            mode,
+            throttle,
+            idle_priority,
+            full_scan,
+            new_only,
+            new_since,
+            service,
+        } => {
+            let path = &folder_to_sort(path, &profile);
+            refuse_unsafe_folder(path, cli.force);
+            let new_since = if *new_only || new_since.is_some() || profile.new_only {
+                match arrivals::high_water_mark(&paths::resolve_path(path), *new_since) {
+                    Ok(mark) => {
+                        info!("Only sorting files created since {}", mark.format("%Y-%m-%d %H:%M"));
+                        Some(SystemTime::from(mark))
+                    }
+                    Err(e) => {
+                        error!("Error remembering when new files start: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            } else {
+                None
+            };
+            let options = SortOptions {
+                jobs: jobs.or(profile.jobs).unwrap_or(1),
+                output: OutputFormat::Text,
+                dry_run: false,
+                confirm_threshold: None,
+                recursive: *recursive || profile.recursive,
+                rules_only: Vec::new(),
+                mode: mode.or(profile.mode),
+                into: None,
+                incremental: !*full_scan,
+                interactive: false,
+                review_by_rule: false,
+                retry_only: false,
+                progress: false,
+                files: None,
+                new_since,
+            };
+            if let Some(throttle) = throttle {
+                throttle::set_limit(*throttle);
+            }
+            if *idle_priority {
+                if let Err(e) = throttle::lower_priority() {
+                    warn!("Failed to lower the daemon's priority: {}", e);
+                }
+            }
+            let timing = match schedule {
+                Some(schedule) => Timing::Schedule(schedule.clone()),
+                None => Timing::Interval {
+                    every: Duration::from_secs(interval.or(profile.interval).unwrap_or(10)),
+                    max: Duration::from_secs(max_interval.unwrap_or(300)),
+                },
+            };
+            let outlets = Outlets {
+                metrics_addr: *metrics_addr,
+                http: *http,
+                bus: *dbus,
+                events_file: events_file.clone(),
+            };
+            let watcher = *watcher;
+            let Some(rules) = service else {
+                run_daemon(path, &timing, &options, watcher, &outlets);
+                return;
+            };
+            paths::set_rules_dir(rules.clone());
+            let path = path.clone();
+            if let Err(e) = service::run(move || run_daemon(&path, &timing, &options, watcher, &outlets)) {
+                error!("Error running as a service: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Scrub { files } => {
+            let settings = settings::load_settings().scrub;
+            let files = files.or(settings.map(|settings| settings.files)).unwrap_or(200);
+            let mut session = Session::new(None);
+            session.webhook.configure(load_config().webhook.as_ref());
+            match scrub_destinations(&mut session, files) {
+                Ok(scrub) if scrub.findings.is_empty() => println!("Checked {} files, all as expected", scrub.checked),
+                Ok(scrub) => {
+                    println!("Checked {} files, {} need attention", scrub.checked, scrub.findings.len());
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    error!("Error scrubbing placed files: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Retention { path, exempt, unexempt, run } => {
+            let root = run.then(|| paths::resolve_path(&folder_to_sort(path, &profile)));
+            if let Some(root) = &root {
+                refuse_unsafe_folder(&root.to_string_lossy(), cli.force);
+            }
+            if let Err(e) = manage_retention(root.as_deref(), exempt, unexempt) {
+                error!("Error updating retention: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Lifecycle { path, dry_run } => {
+            let root = paths::resolve_path(&folder_to_sort(path, &profile));
+            refuse_unsafe_folder(&root.to_string_lossy(), cli.force);
+            match try_load_config() {
+                Ok(config) => advance_lifecycles(&config, &root, open_index().ok().as_ref(), *dry_run),
+                Err(e) => {
+                    error!("Error loading rules: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Service { command } => match service::control(*command) {
+            Ok(response) => println!("{}", response),
+            Err(e) => {
+                error!("Error controlling the service: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Commands::Install { path, interval } => {
+            refuse_unsafe_folder(&folder_to_sort(path, &profile), cli.force);
+            install_service(&folder_to_sort(path, &profile), interval.or(profile.interval).unwrap_or(10), cli.profile.as_deref());
+        }
+        Commands::History { since, rule, dest, search, limit, output } => {
+            let filter = HistoryFilter {
+                since: *since,
+                rule: rule.clone(),
+                destination: dest.clone(),
+                search: search.clone(),
+                limit: Some(*limit),
+            };
+            if let Err(e) = show_history(&filter, *output) {
+                error!("Error reading move index: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Report { since, format } => {
+            if let Err(e) = show_report(*since, *format) {
+                error!("Error reading move index: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Plan { path, output, recursive, rule } => {
+            let path = &folder_to_sort(path, &profile);
+            refuse_unsafe_folder(path, cli.force);
+            if let Err(e) = write_plan(path, *recursive || profile.recursive, rule, output) {
+                error!("Error planning the sort: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Apply { plan, yes } => {
+            if let Err(e) = apply_plan(plan, *yes) {
+                error!("Error applying the plan: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Reorganize { path, dry_run, yes } => {
+            refuse_unsafe_folder(path, cli.force);
+            if let Err(e) = reorganize(path, *dry_run, *yes) {
+                error!("Error reorganizing files: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Normalize {
+            path,
+            recursive,
+            dry_run,
+            yes,
+            on_conflict,
+            undo,
+        } => {
+            if let Some(path) = path.as_deref().filter(|_| !*undo) {
+                refuse_unsafe_folder(path, cli.force);
+            }
+            let result = match path {
+                Some(path) if !*undo => normalize(path, *recursive, *dry_run, *yes, *on_conflict),
+                _ => undo_normalize(),
+            };
+            if let Err(e) = result {
+                error!("Error renaming files: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Rename {
+            command: RenameCommand::Preview { path, recursive, csv },
+        } => {
+            if let Err(e) = preview_renames(path, *recursive, csv.as_deref()) {
+                error!("Error previewing renames: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Permissions { path, dry_run } => {
+            if let Err(e) = fix_permissions(path, *dry_run) {
+                error!("Error fixing permissions: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Audit { command } => {
+            if let Err(e) = run_audit(command) {
+                error!("Audit failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Test { file, path } => {
+            if let Err(e) = test_file(file, path.as_deref()) {
+                error!("Error testing {:?}: {}", file, e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Layout => {
+            if let Err(e) = print_layout() {
+                error!("Error reading the layout: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Config {
+            command: ConfigCommand::Dump { format },
+        } => {
+            if let Err(e) = dump_config(*format) {
+                error!("Error reading the configuration: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Locate { pattern, output } => match glob::Pattern::new(pattern) {
+            Ok(pattern) => {
+                if let Err(e) = locate_files(&pattern, *output) {
+                    error!("Error reading move index: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => {
+                error!("Invalid pattern '{}': {}", pattern, e);
+                std::process::exit(1);
+            }
+        },
+        Commands::Find { path, tags, rule, output } => {
+            if let Err(e) = find_tagged(path, tags, rule.as_deref(), *output) {
+                error!("Error searching {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Import { from, config, write } => {
+            if let Err(e) = import_rules(*from, config, write.as_deref()) {
+                error!("Error importing {:?}: {}", config, e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Learn { adopt, dismiss } => {
+            if let Err(e) = learn_rules(adopt, dismiss) {
+                error!("Error updating learned rules: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Export { to, path, recursive, write } => {
+            if let Err(e) = export_rules(*to, path, *recursive, write.as_deref()) {
+                error!("Error exporting rules: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Staging { command } => {
+            if let Err(e) = show_staging(command) {
+                error!("Error updating staged files: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::State { command } => {
+            if let Err(e) = transfer_state(command) {
+                error!("Error moving state: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Flush { destination, pause, path } => {
+            let daemon = pause.then(|| paths::resolve_path(&folder_to_sort(path, &profile)));
+            if let Err(e) = flush_destination(destination, daemon.as_deref()) {
+                error!("Error flushing {}: {}", destination, e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Ctl { path, command } => match control::send(&paths::resolve_path(&folder_to_sort(path, &profile)), *command) {
+            Ok(response) => println!("{}", response),
+            Err(e) => {
+                error!("Daemon not running or not reachable: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Commands::Tui { path } => {
+            if let Err(e) = tui::run(&paths::resolve_path(&folder_to_sort(path, &profile))) {
+                error!("Error showing the daemon's activity: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Resume { path, enforce } => {
+            if let Err(e) = resume(&paths::resolve_path(&folder_to_sort(path, &profile)), *enforce) {
+                error!("Error leaving safe mode: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Profiles { command } => {
+            if let Err(e) = run_profiles(command) {
+                error!("Error running profiles: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Completions { shell } => {
+            let mut command = Cli::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(*shell, &mut command, name, &mut std::io::stdout());
+        }
+        Commands::Manpage { dir } => {
+            let result = match dir {
+                Some(dir) => fs::create_dir_all(dir).and_then(|_| clap_mangen::generate_to(Cli::command(), dir)),
+                None => clap_mangen::Man::new(Cli::command()).render(&mut std::io::stdout()),
+            };
+            if let Err(e) = result {
+                error!("Error writing the man page: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// Sorts the files of `directory`. Files that can't be sorted are reported and left in
+/// place while the others are sorted; only problems that concern every file end the sort.
+fn sort_files(directory: &str, options: &SortOptions, session: &mut Session, mut config: RulesConfig) -> Result<(), SortError> {
+    let directory = paths::resolve_path(directory);
+    let path = directory.as_path();
+    if !path.is_dir() {
+        return Err(SortError::NotADirectory(directory));
+    }
+    
+    let policy = policy::load_policy();
+    policy.enforce(&mut config);
+    let mut expiry = RuleExpiry::load();
+    let mut expired = expiry.remove_expired(&mut config.rules);
+    if config.antivirus.is_none() && config.rules.iter().any(|rule| rule.scan) {
+        return Err(SortError::config("Rules with `scan` need an `antivirus` scanner in rules.json"));
+    }
+    let read_only = options.into.is_some();
+    let root = options.into.as_deref().unwrap_or(path);
+    if read_only {
+        // Links would be followed or moved, both of which change the sorted folder.
+        config.symlinks = SymlinkPolicy::Ignore;
+        config.broken_symlinks = BrokenSymlinkPolicy::Ignore;
+        for rule in &mut config.rules {
+            rule.symlinks = None;
+            rule.broken_symlinks = None;
+        }
+    }
+    if let Some(notifier) = session.notifier.as_mut() {
+        notifier.configure(&config.notifications);
+    }
+    session.jobs.configure(&config.handoff, &config.transfers);
+    session.capacity.start_sort(options.dry_run);
+    session.webhook.configure(config.webhook.as_ref());
+    session.unmatched.configure(&config.unmatched, root);
+    
+    // While the daemon catches up after downtime, each sort takes the next batch of the backlog.
+    let catching_up = !options.retry_only && session.backlog.is_some();
+    let partial = options.retry_only || catching_up || options.files.is_some();
+    if !options.dry_run && !read_only && !partial {
+        for ingested in sources::poll_sources(&config.sources, path) {
+            if let Some(index) = session.index.as_ref() {
+                if let Err(e) = index.record("fetch", None, Path::new(&ingested.remote), &ingested.local) {
+                    warn!("Failed to record fetched file in index: {}", e);
+                }
+            }
+        }
+    }
+    
+    let started = Instant::now();
+    let mut scan_state = if options.incremental {
+        ScanState::open().map_err(|e| warn!("Scan state unavailable, every file is sorted: {}", e)).ok()
+    } else {
+        None
+    };
+    let excluded = excluded_folders(&config, path);
+    // Folder rules only move; a sort that copies leaves folders where they are.
+    if !read_only && !options.retry_only && options.files.is_none() && options.mode.or(config.mode) != Some(Mode::Copy) {
+        sort_directories(&config, path, &excluded, &policy, session.index.as_ref(), options);
+    }
+    let mut files = match (session.retries.as_mut(), session.backlog.as_mut(), &options.files) {
+        (_, _, Some(listed)) => listed_files(listed, path, &excluded),
+        (Some(retries), _, _) if options.retry_only => retries.due(),
+        (_, Some(backlog), _) if catching_up => backlog.next_batch(),
+        _ => scan::collect_files(path, options.recursive, &excluded, config.repositories, scans_links(&config), scan_state.as_mut())?,
+    };
+    match session.backlog.as_ref() {
+        Some(backlog) if backlog.is_empty() => {
+            info!("Caught up on the files that appeared while the daemon was stopped");
+            session.backlog = None;
+        }
+        Some(backlog) if catching_up => debug!("{} files left to catch up on", backlog.len()),
+        _ => {}
+    }
+    files.retain(|file| !session.unmatched.is_report(file));
+    if let Some(mark) = options.new_since {
+        files.retain(|file| arrivals::created_since(file, mark));
+    }
+    // Locked files are left alone until their retry is due, and those given up on until they change.
+    if let (Some(retries), false) = (session.retries.as_mut(), options.retry_only) {
+        files.retain(|file| !retries.waiting(file));
+    }
+    
+    let mut overrides = folder_overrides(&files, path, &config);
+    for local in overrides.values_mut() {
+        expired.extend(expiry.remove_expired(&mut local.rules));
+    }
+    report_expired(&expired, session.notifier.as_mut());
+    if let Err(e) = expiry.save() {
+        warn!("Failed to remember when temporary rules started: {}", e);
+    }
+    restrict_rules(&mut config, &mut overrides, &options.rules_only).map_err(SortError::Config)?;
+    set_mode(&mut config, &mut overrides, options.mode);
+    let scanned = files.len();
+    if let Some(state) = scan_state.as_mut() {
+        let delta = &state.delta;
+        if !delta.is_empty() {
+            debug!(added = delta.added.len(), changed = delta.changed.len(), removed = delta.removed.len(), "Folders changed since the last sort");
+        }
+        if let Some(metrics) = session.metrics.as_deref() {
+            metrics.record_changes(delta);
+        }
+        if let Some(learner) = session.learner.as_ref().filter(|_| !delta.removed.is_empty()) {
+            learn_from(learner, &delta.removed, path, session.index.as_ref());
+        }
+        if let Err(e) = state.use_rules(path, &rules_fingerprint(&config, &overrides)) {
+            warn!("Failed to compare the rules with the last sort: {}", e);
+        }
+        // Files no rule matched last time still count as unmatched, without trying the rules again.
+        let (changed, unchanged): (Vec<_>, Vec<_>) = files.into_iter().partition(|file| state.changed(file));
+        for file in &unchanged {
+            session.unmatched.file_unmatched(file);
+        }
+        files = changed;
+    }
+    
+    if !options.dry_run {
+        if let Some(threshold) = options.confirm_threshold {
+            let planned = count_matches(&files, &config, &overrides)?;
+            if planned > threshold && !confirm(planned, "moved")? {
+                return Err(SortError::Cancelled);
+            }
+        }
+    }
+    let review = if options.interactive {
+        let review = review::run(&plan_review(&files, &config, &overrides)?, &config.templates, options.review_by_rule)?;
+        if !review.rules.is_empty() {
+            if let Err(e) = add_rules(&review.rules) {
+                warn!("Failed to add the new rules to {}: {}", RULES_FILE, e);
+            }
+        }
+        Some(review)
+    } else {
+        None
+    };
+    
+    let short_of_space = if options.dry_run {
+        HashSet::new()
+    } else {
+        check_space(&files, &config, &overrides, review.as_ref(), root, read_only)?
+    };
+    
+    let workers = options.jobs.clamp(1, files.len().max(1));
+    // Workers make their own Lua states; one that can't be made stops the sort before it starts.
+    script_lua(&config).map_err(SortError::Config)?;
+    // They open the copy log for themselves as well. It is tried once here, so a log that
+    // can't be opened is reported once rather than by every worker, and a read-only folder,
+    // whose files would all be imported again without it, isn't sorted.
+    let copy_log = match CopyLog::open() {
+        Ok(_) => true,
+        Err(e) if read_only => {
+            return Err(SortError::Io(std::io::Error::other(format!(
+                "Copy log unavailable, so every file of the folder would be imported again: {}",
+                e
+            ))))
+        }
+        Err(e) => {
+            warn!("Copy log unavailable, existing copies are compared by hash: {}", e);
+            false
+        }
+    };
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    
+    let deferred = if session.user_active {
+        config.defer_while_active.iter().map(|path| paths::resolve_path(path)).collect()
+    } else {
+        Vec::new()
+    };
+    let disk = transfer::Disk {
+        settings: config.transfers.clone(),
+    };
+    let context = SortContext {
+        directory: root,
+        config: &config,
+        jobs: &session.jobs,
+        staging: &session.staging,
+        dry_run: options.dry_run,
+        deferred,
+        echoes: recent_moves(session.index.as_ref(), &config),
+        policy: &policy,
+        overrides,
+        metrics: session.metrics.as_deref(),
+        limits: &session.limits,
+        disk: &session.disk,
+        capacity: &session.capacity,
+        short_of_space,
+        stopped: stopped_rules(&config, &mut session.pipelines),
+        review: review.as_ref(),
+        read_only,
+        sequences: Sequences::open()
+            .map_err(|e| warn!("Sequence numbers unavailable, `{{seq}}` counts like `{{counter}}`: {}", e))
+            .ok()
+            .map(Mutex::new),
+        reserved: Mutex::new(HashSet::new()),
+        replay: session.replay.as_ref(),
+        dedupe: dedupe_index(&config, options.dry_run),
+        fs: &disk,
+        clock: &SystemClock,
+        trashed: Mutex::new(Vec::new()),
+    };
+    let index = session.index.as_ref();
+    let mut written = BTreeSet::new();
+    let recorders = Recorders {
+        notifier: session.notifier.as_mut(),
+        index,
+        totals: &mut session.totals,
+        webhook: &mut session.webhook,
+        unmatched: &mut session.unmatched,
+        written: &mut written,
+        activity: session.control.as_deref(),
+        retries: session.retries.as_mut(),
+        plan: session.plan.as_mut(),
+        config: &config,
+        trashed: &context.trashed,
+    };
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let tx = tx.clone();
+            let (files, context, next) = (&files, &context, &next);
+            let incremental = scan_state.is_some();
+            scope.spawn(move || {
+                // Lua states are not thread safe, so every worker keeps its own.
+                let lua = match script_lua(context.config) {
+                    Ok(lua) => lua,
+                    Err(e) => {
+                        error!("{}", e);
+                        return;
+                    }
+                };
+                let copies = copy_log
+                    .then(|| CopyLog::open().map_err(|e| warn!("Copy log unavailable, existing copies are compared by hash: {}", e)).ok())
+                    .flatten();
+                let state = incremental.then(|| ScanState::open().ok()).flatten();
+                let mut unmatched = Vec::new();
+                // On shutdown, files already being sorted are finished and no new ones started.
+                while !shutdown::requested() {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(file_path) = files.get(index) else { break };
+                    let file_started = Instant::now();
+                    let outcomes = sort_file(context, file_path, &lua, copies.as_ref());
+                    if let (Some(_), [Outcome::Unmatched(SkipReason::NoRuleMatched)]) = (&state, &outcomes[..]) {
+                        unmatched.push(file_path.clone());
+                    }
+                    if let Some(metrics) = context.metrics {
+                        metrics.record_file(&outcomes, file_started.elapsed());
+                    }
+                    if tx.send((index, outcomes)).is_err() {
+                        break;
+                    }
+                }
+                if let Some(Err(e)) = state.map(|state| state.record_unmatched(&unmatched)) {
+                    warn!("Failed to remember unmatched files: {}", e);
+                }
+                timing::flush();
+            });
+        }
+        drop(tx);
+        report_in_order(&files, rx, options, recorders);
+    });
+    // The times of planning the sort, taken on this thread.
+    timing::flush();
+    if let Some(metrics) = context.metrics {
+        metrics.record_scan(scanned, started.elapsed());
+    }
+    if !options.dry_run {
+        if let Err(e) = session.limits.save() {
+            warn!("Failed to save rule rate limits: {}", e);
+        }
+        release_staged(&session.staging, &config, index);
+        prune_shortcuts(&config, root);
+        // Sorts `--into` another folder leave the sorted one as it is.
+        if options.recursive && !read_only && config.cleanup.remove_empty_folders {
+            remove_emptied_folders(&config, path, &files);
+        }
+        // A retry or a batch of the backlog only sees some files, and would leave everything
+        // else out of the report.
+        if !partial {
+            if let Err(e) = session.unmatched.write() {
+                warn!("Failed to write the unmatched files report: {}", e);
+            }
+        }
+        removable::flush_written(&written);
+    }
+    for event in session.disk.take_events() {
+        report_disk_space(event, session.notifier.as_mut(), &mut session.webhook);
+    }
+    for event in session.capacity.take_events() {
+        report_capacity(event, session.notifier.as_mut());
+    }
+    session.webhook.deliver();
+    record_trashed(index, take_trashed(&context.trashed));
+    record_completed(index, context.jobs.poll()?);
+    if let (Some(index), Some(target), false) = (index, &session.mirror, options.dry_run) {
+        mirror_index(index, target);
+    }
+    Ok(())
+}
+
+/// Alerts that a destination holds as much as its rule allows, so files for it stay in
+/// place, or has room again.
+fn report_capacity(event: CapacityEvent, notifier: Option<&mut Notifier>) {
+    let message = match &event {
+        CapacityEvent::Full { folder, files, bytes } => format!(
+            "{} is full with {} files ({}), files for it stay in place",
+            folder.display(),
+            files,
+            diskspace::format_size(*bytes)
+        ),
+        CapacityEvent::Available { folder, files, bytes } => format!(
+            "{} has room again with {} files ({}), placing files there resumes",
+            folder.display(),
+            files,
+            diskspace::format_size(*bytes)
+        ),
+    };
+    match event {
+        CapacityEvent::Full { .. } => warn!("{}", message),
+        CapacityEvent::Available { .. } => info!("{}", message),
+    }
+    if let Some(notifier) = notifier {
+        notifier.alert(message);
+    }
+}
+
+/// Alerts that a destination's drive ran low, suspending the rules that place files on it,
+/// or has room again.
+fn report_disk_space(event: DiskEvent, notifier: Option<&mut Notifier>, webhook: &mut Webhook) {
+    let (folder, free, low) = match event {
+        DiskEvent::Low { folder, free } => (folder, free, true),
+        DiskEvent::Recovered { folder, free } => (folder, free, false),
+    };
+    let message = if low {
+        format!("{} is almost full ({} free), files for it stay in place", folder.display(), diskspace::format_size(free))
+    } else {
+        format!("{} has room again ({} free), placing files there resumes", folder.display(), diskspace::format_size(free))
+    };
+    if low {
+        warn!("{}", message);
+    } else {
+        info!("{}", message);
+    }
+    if let Some(notifier) = notifier {
+        notifier.alert(message);
+    }
+    webhook.disk_space(&folder, free, low);
+}
+
+/// Returns the outcome of every rule applied to the file: copies first, then what
+/// happened to the file itself.
+fn sort_file(context: &SortContext, file_path: &PathBuf, lua: &Lua, copies: Option<&CopyLog>) -> Vec<Outcome> {
+    if context.config.transfers.protection.is_protected(file_path) {
+        return vec![Outcome::Unmatched(SkipReason::Protected)];
+    }
+    if let Some(replay) = context.replay {
+        return replay_file(context, replay, file_path, copies);
+    }
+    if is_echo(context, file_path) {
+        return vec![Outcome::Unmatched(SkipReason::SyncEcho)];
+    }
+    let Some(rules) = effective_rules(&context.overrides, &context.config.rules, file_path) else {
+        return vec![Outcome::Unmatched(SkipReason::Excluded)];
+    };
+    if context.config.temporary.is_temporary(file_path) {
+        return vec![clean_temporary(context, file_path)];
+    }
+    let mut matches = if context.config.hidden_files == HiddenPolicy::Skip && hidden::is_hidden(file_path) {
+        // Hidden files are only sorted by the rules that ask for them, so no other rule,
+        // script or plugin runs on them.
+        let opted_in: Vec<Rule> = rules.iter().filter(|rule| rule.hidden).cloned().collect();
+        let (matches, _) = decide_rules(file_path, &opted_in, context.config);
+        if matches.is_empty() {
+            return vec![Outcome::Unmatched(SkipReason::Hidden)];
+        }
+        matches
+    } else {
+        apply_rules(file_path, rules, lua, context.config)
+    };
+    if let Some(outcome) = apply_review(context, file_path, &mut matches) {
+        return vec![outcome];
+    }
+    // Looking at the file is done once for all its rules, so it is timed for none of them.
+    match timing::time(timing::NO_RULE, Stage::Stat, || links::inspect(file_path)) {
+        Some(link) => sort_link(context, file_path, link, matches, copies),
+        None => apply_matches(context, file_path, matches, copies),
+    }
+}
+
+/// Sorts a file of the plan `apply` carries out by the plan's decisions instead of the rules,
+/// through the same checks and actions as a sort. Once a file wasn't placed as planned, the
+/// files after it are left alone.
+fn replay_file(context: &SortContext, replay: &Replay, file_path: &PathBuf, copies: Option<&CopyLog>) -> Vec<Outcome> {
+    let steps = replay.visit(file_path);
+    let matches = steps.iter().map(|step| planned_match(context, file_path, step));
+    if replay.failed().is_some() {
+        let detail = "left alone, as a file planned before it wasn't placed";
+        return matches
+            .map(|matched| Outcome::Skipped {
+                matched,
+                reason: SkipReason::Conflict,
+                detail: detail.to_string(),
+            })
+            .collect();
+    }
+    let matches: Vec<RuleMatch> = matches.collect();
+    if let Some(position) = steps.iter().zip(&matches).position(|(step, matched)| planned_actions(matched, context.config) != step.actions) {
+        replay.fail(file_path);
+        return vec![Outcome::Skipped {
+            matched: planned_match(context, file_path, &steps[position]),
+            reason: SkipReason::Conflict,
+            detail: "its rule or destination has other actions than when the plan was made".to_string(),
+        }];
+    }
+    let outcomes = apply_matches(context, file_path, matches, copies);
+    for outcome in &outcomes {
+        match outcome {
+            Outcome::Moved { matched, destination, .. } | Outcome::Copied { matched, destination, .. } => replay.placed(file_path, matched.copy, destination),
+            Outcome::Skipped { .. } | Outcome::Failed { .. } => replay.fail(file_path),
+            _ => {}
+        }
+    }
+    outcomes
+}
+
+/// The match a step of a plan was made with, from its rule as it is now.
+fn planned_match(context: &SortContext, file_path: &Path, step: &plan::Step) -> RuleMatch {
+    let copy = step.action == plan::Action::Copy;
+    let rules = effective_rules(&context.overrides, &context.config.rules, file_path).unwrap_or(&context.config.rules);
+    match rules.iter().find(|rule| rule.pattern == step.rule) {
+        Some(rule) => RuleMatch::from_rule(rule, step.template.clone(), copy),
+        None => RuleMatch::decided(step.rule.clone(), step.template.clone(), copy),
+    }
+}
+
+/// What happens to a file `matched` places besides the move or copy, by the names of the
+/// rule options and destination actions that apply, so `apply` can tell when they changed
+/// since the plan was made.
+fn planned_actions(matched: &RuleMatch, config: &RulesConfig) -> Vec<String> {
+    let options = [
+        ("owner", matched.owner.is_some()),
+        ("permissions", matched.permissions.is_some()),
+        ("link_back", matched.link_back && !matched.copy),
+    ];
+    let mut names: Vec<&str> = options.into_iter().filter_map(|(name, set)| set.then_some(name)).collect();
+    let actions = config.actions.get(&matched.destination);
+    names.extend(actions.map(|actions| actions.names(matched.copy)).unwrap_or_default());
+    if config.duplicates.is_some() && !matched.copy && !names.contains(&"duplicates") {
+        names.push("duplicates");
+    }
+    names.into_iter().map(String::from).collect()
+}
+
+/// Files moved within the echo window, by the path they were moved from.
+fn recent_moves(index: Option<&MoveIndex>, config: &RulesConfig) -> HashMap<PathBuf, PathBuf> {
+    let window = config.echo_window.unwrap_or(ECHO_WINDOW);
+    let Some(index) = index.filter(|_| window > 0) else { return HashMap::new() };
+    match index.recent_moves(Local::now() - chrono::Duration::seconds(window as i64)) {
+        Ok(moves) => moves.into_iter().map(|(source, destination)| (PathBuf::from(source), PathBuf::from(destination))).collect(),
+        Err(e) => {
+            warn!("Failed to look up recent moves, sync echoes are sorted again: {}", e);
+            HashMap::new()
+        }
+    }
+}
+
+/// Whether the file was put back, with the same contents, where a file was just moved from.
+fn is_echo(context: &SortContext, file_path: &Path) -> bool {
+    let Some(destination) = context.echoes.get(file_path) else { return false };
+    match (duplicates::hash_file(file_path), duplicates::hash_file(destination)) {
+        (Ok(file), Ok(moved)) => file == moved,
+        _ => false,
+    }
+}
+
+/// Follows the answer given for the file during `sort --interactive`, which is about its
+/// first match. Returns the outcome of a file that was turned down.
+fn apply_review(context: &SortContext, file_path: &Path, matches: &mut Vec<RuleMatch>) -> Option<Outcome> {
+    let decision = context.review?.decision(file_path)?;
+    let first = matches.first_mut()?;
+    match decision {
+        Decision::Skip => Some(Outcome::Skipped {
+            matched: matches.swap_remove(0),
+            reason: SkipReason::Declined,
+            detail: "turned down during review".to_string(),
+        }),
+        Decision::Destination(destination) => {
+            first.destination = destination.clone();
+            None
+        }
+    }
+}
+
+fn apply_matches(context: &SortContext, file_path: &PathBuf, mut matches: Vec<RuleMatch>, copies: Option<&CopyLog>) -> Vec<Outcome> {
+    if let Some(detail) = matches.first().and_then(|matched| context.stopped.get(&matched.rule)) {
+        let detail = detail.clone();
+        return vec![Outcome::Skipped {
+            matched: matches.swap_remove(0),
+            reason: SkipReason::Stopped,
+            detail,
+        }];
+    }
+    if context.read_only {
+        return import_file(context, file_path, matches, copies);
+    }
+    if let Some(outcome) = check_file(context, file_path, &mut matches) {
+        return vec![outcome];
+    }
+    let mut outcomes = Vec::new();
+    for matched in matches {
+        if !matched.copy {
+            outcomes.push(file_with_rule(context, file_path, matched));
+            return outcomes;
+        }
+        let outcome = copy_file(context, file_path, matched, copies);
+        let failed = matches!(outcome, Outcome::Failed { .. });
+        outcomes.push(outcome);
+        if failed {
+            return outcomes;
+        }
+    }
+    if outcomes.is_empty() {
+        outcomes.push(match &context.config.unmatched.folder {
+            Some(folder) => file_with_rule(context, file_path, unmatched_match(context.config, folder)),
+            None => Outcome::Unmatched(SkipReason::NoRuleMatched),
+        });
+    }
+    outcomes
+}
+
+/// Copies a file out of a read-only folder, whatever the rules' own modes, unless a file
+/// with the same content was imported before. All copies of one file make one import.
+fn import_file(context: &SortContext, file_path: &PathBuf, mut matches: Vec<RuleMatch>, copies: Option<&CopyLog>) -> Vec<Outcome> {
+    if let (true, Some(folder)) = (matches.is_empty(), &context.config.unmatched.folder) {
+        matches.push(unmatched_match(context.config, folder));
+    }
+    if matches.is_empty() {
+        return vec![Outcome::Unmatched(SkipReason::NoRuleMatched)];
+    }
+    // Without the log, a file imported before can't be told from a new one.
+    let Some(copies) = copies else {
+        return vec![Outcome::Failed {
+            rule: None,
+            error: std::io::Error::other("the copy log is unavailable, so the file isn't imported again"),
+        }];
+    };
+    let ingest = match copies.ingested(file_path) {
+        Ok(ingest) => ingest,
+        Err(error) => return vec![Outcome::Failed { rule: None, error }],
+    };
+    if let Ingest::Imported(destination) = &ingest {
+        return vec![Outcome::UpToDate {
+            matched: matches.swap_remove(0),
+            destination: destination.clone(),
+        }];
+    }
+    let mut outcomes = Vec::new();
+    if let Some(outcome) = check_file(context, file_path, &mut matches) {
+        outcomes.push(outcome);
+    } else {
+        for mut matched in matches {
+            matched.copy = true;
+            let outcome = copy_file(context, file_path, matched, Some(copies));
+            let failed = matches!(outcome, Outcome::Failed { .. });
+            outcomes.push(outcome);
+            if failed {
+                break;
+            }
+        }
+    }
+    if let (false, Ingest::New(hash)) = (context.dry_run, &ingest) {
+        let imported = outcomes.iter().find_map(|outcome| match outcome {
+            Outcome::Copied { destination, .. } | Outcome::UpToDate { destination, .. } | Outcome::Quarantined { destination, .. } => {
+                Some(destination)
+            }
+            _ => None,
+        });
+        let failed = outcomes.iter().any(|outcome| matches!(outcome, Outcome::Failed { .. }));
+        if let (Some(destination), false) = (imported, failed) {
+            if let Err(e) = copies.record_ingest(file_path, hash, destination) {
+                warn!("Failed to record import of {:?}: {}", file_path, e);
+            }
+        }
+    }
+    outcomes
+}
+
+/// Quarantines the file when a rule that matched it checks files first and the check
+/// fails: its content doesn't fit its extension, or the virus scanner found a threat. No
+/// rule acts on such a file.
+fn check_file(context: &SortContext, file_path: &Path, matches: &mut Vec<RuleMatch>) -> Option<Outcome> {
+    if !file_path.is_file() {
+        return None;
+    }
+    let mut found = None;
+    if let Some(position) = matches.iter().position(|matched| matched.verify_type) {
+        found = match filetype::mismatch(file_path) {
+            Ok(detected) => detected.map(|detected| (position, format!("its content is {}", detected))),
+            Err(error) => return Some(check_failed(matches.swap_remove(position), error)),
+        };
+    }
+    if let (None, Some(position)) = (&found, matches.iter().position(|matched| matched.scan)) {
+        let Some(scanner) = &context.config.antivirus else {
+            let error = std::io::Error::new(std::io::ErrorKind::InvalidInput, "no antivirus scanner is configured");
+            return Some(check_failed(matches.swap_remove(position), error));
+        };
+        found = match antivirus::scan(scanner, file_path) {
+            Ok(threat) => threat.map(|threat| (position, format!("{} found {}", scanner.name(), threat))),
+            Err(error) => return Some(check_failed(matches.swap_remove(position), error)),
+        };
+    }
+    let (position, reason) = found?;
+    Some(quarantine_file(context, file_path, matches.swap_remove(position), reason))
+}
+
+/// A file that could not be checked is left in place rather than trusted.
+fn check_failed(matched: RuleMatch, error: std::io::Error) -> Outcome {
+    Outcome::Failed {
+        rule: Some(matched.rule),
+        error,
+    }
+}
+
+fn quarantine_file(context: &SortContext, file_path: &Path, matched: RuleMatch, reason: String) -> Outcome {
+    let folder = context.directory.join(paths::resolve_path(quarantine_folder(context.config)));
+    let destination = duplicates::unique_path(&folder.join(file_path.file_name().unwrap()));
+    if context.dry_run {
+        return Outcome::Quarantined {
+            matched,
+            destination,
+            reason,
+            planned: true,
+        };
+    }
+    let placed = context.fs.create_dir_all(&folder).and_then(|_| {
+        if context.read_only {
+            context.fs.copy(file_path, &destination)
+        } else {
+            context.fs.rename(file_path, &destination)
+        }
+    });
+    match placed {
+        Ok(()) => Outcome::Quarantined {
+            matched,
+            destination,
+            reason,
+            planned: false,
+        },
+        Err(error) => Outcome::Failed {
+            rule: Some(matched.rule),
+            error,
+        },
+    }
+}
+
+fn quarantine_folder(config: &RulesConfig) -> &str {
+    config.quarantine.as_deref().unwrap_or("Quarantine")
+}
+
+/// Leaves a temporary file where it is, or with `cleanup` deletes it once it is stale.
+fn clean_temporary(context: &SortContext, file_path: &Path) -> Outcome {
+    let temporary = &context.config.temporary;
+    if temporary.action == TemporaryAction::Skip || context.read_only || !temporary.is_stale(file_path) {
+        return Outcome::Unmatched(SkipReason::Temporary);
+    }
+    if context.dry_run {
+        return Outcome::Removed {
+            rule: None,
+            planned: true,
+            temporary: true,
+        };
+    }
+    match remove_source(context, file_path) {
+        Ok(()) => Outcome::Removed {
+            rule: None,
+            planned: false,
+            temporary: true,
+        },
+        Err(error) => Outcome::Failed { rule: None, error },
+    }
+}
+
+/// Removes a file the sort is done with through the sort's file system, or puts it in the
+/// trash when `trash` is set.
+fn remove_source(context: &SortContext, file_path: &Path) -> std::io::Result<()> {
+    let transfers = &context.config.transfers;
+    if transfers.trash {
+        return trash::remove(file_path, transfers).map(|trashed| context.keep_trashed(trashed));
+    }
+    transfers.protection.check(file_path)?;
+    context.fs.remove_file(file_path)
+}
+
+/// Routes a file no rule claimed into the `unmatched` folder like a rule would.
+fn unmatched_match(config: &RulesConfig, folder: &str) -> RuleMatch {
+    RuleMatch::decided(UNMATCHED_RULE.to_string(), folder.to_string(), config.mode == Some(Mode::Copy))
+}
+
+/// Handles a symlink by the policy of the rule that decides it, or the global one.
+fn sort_link(context: &SortContext, link: &PathBuf, target: Link, mut matches: Vec<RuleMatch>, copies: Option<&CopyLog>) -> Vec<Outcome> {
+    let target = match target {
+        Link::Valid(target) => target,
+        Link::Broken => {
+            let rule = matches.last().map(|matched| matched.rule.clone());
+            let policy = matches.last().and_then(|matched| matched.broken_symlinks);
+            return vec![match policy.unwrap_or(context.config.broken_symlinks) {
+                BrokenSymlinkPolicy::Ignore => Outcome::Unmatched(SkipReason::Excluded),
+                BrokenSymlinkPolicy::Skip => {
+                    warn!(file = ?link, "Leaving broken symlink in place");
+                    Outcome::Unmatched(SkipReason::Excluded)
+                }
+                BrokenSymlinkPolicy::Remove if context.dry_run => Outcome::Removed {
+                    rule,
+                    planned: true,
+                    temporary: false,
+                },
+                BrokenSymlinkPolicy::Remove => match transfer::remove_link(link) {
+                    Ok(()) => Outcome::Removed {
+                        rule,
+                        planned: false,
+                        temporary: false,
+                    },
+                    Err(error) => Outcome::Failed { rule, error },
+                },
+            }];
+        }
+    };
+    let policy = matches.last().and_then(|matched| matched.symlinks).unwrap_or(context.config.symlinks);
+    let Some(matched) = matches.pop() else {
+        return vec![Outcome::Unmatched(SkipReason::NoRuleMatched)];
+    };
+    // Links into the sorted folder, such as those left by `link_back`, point to files that are sorted on their own.
+    let internal = context.directory.canonicalize().is_ok_and(|directory| target.starts_with(directory));
+    let detail = match policy {
+        SymlinkPolicy::Ignore => return vec![Outcome::Unmatched(SkipReason::Excluded)],
+        _ if internal => return vec![Outcome::Unmatched(SkipReason::Excluded)],
+        SymlinkPolicy::Skip => {
+            warn!(file = ?link, "Leaving symlink in place");
+            "symlinks are skipped"
+        }
+        SymlinkPolicy::Move if matched.copy => "symlinks are moved, not copied",
+        SymlinkPolicy::Follow if target.is_dir() && !scan::is_bundle(&target) => "the symlink points to a folder",
+        SymlinkPolicy::Follow => {
+            matches.push(matched);
+            let outcomes = apply_matches(context, &target, matches, copies);
+            if !context.dry_run && !target.exists() {
+                if let Err(e) = transfer::remove_link(link) {
+                    warn!(file = ?link, "Failed to remove symlink to the moved file: {}", e);
+                }
+            }
+            return outcomes;
+        }
+        SymlinkPolicy::Move => return vec![move_link(context, link, matched)],
+    };
+    vec![Outcome::Skipped {
+        matched,
+        reason: SkipReason::Excluded,
+        detail: detail.to_string(),
+    }]
+}
+
+fn move_link(context: &SortContext, link: &PathBuf, matched: RuleMatch) -> Outcome {
+    let dest_path = destination_path(context, link, &matched);
+    if let Some((reason, detail)) = placement_blocked(context, link, &dest_path) {
+        return Outcome::Skipped {
+            matched,
+            reason,
+            detail: detail.to_string(),
+        };
+    }
+    let target = dest_path.join(link.file_name().unwrap());
+    // Renaming over an existing file would replace it.
+    if context.fs.symlink_metadata(&target).is_ok() {
+        return Outcome::Skipped {
+            matched,
+            reason: SkipReason::Conflict,
+            detail: "a file with the same name already exists at the destination".to_string(),
+        };
+    }
+    if let Some(detail) = rate_limited(context, &matched) {
+        return Outcome::Skipped {
+            matched,
+            reason: SkipReason::Quota,
+            detail,
+        };
+    }
+    if context.dry_run {
+        return Outcome::Planned {
+            matched,
+            destination: target,
+        };
+    }
+    match context.fs.create_dir_all(&dest_path).and_then(|_| transfer::move_link(link, &target)) {
+        Ok(()) => Outcome::Moved {
+            matched,
+            destination: target,
+            checksum: None,
+        },
+        Err(error) => Outcome::Failed {
+            rule: Some(matched.rule),
+            error,
+        },
+    }
+}
+
+fn destination_path(context: &SortContext, file_path: &Path, matched: &RuleMatch) -> PathBuf {
+    // `.` leaves files in their folder, for destinations that only rename them.
+    if let (".", Some(folder), false) = (matched.destination.as_str(), file_path.parent(), context.read_only) {
+        return folder.to_path_buf();
+    }
+    let destination = templates::expand_destination(&matched.destination, file_path, &context.config.templates);
+    filenames::adapt_folder(&context.directory.join(paths::resolve_path(&destination)), &context.config.file_names)
+}
+
+/// Returns why a file may not be placed in `dest_path` right now, if anything.
+fn placement_blocked(context: &SortContext, file_path: &Path, dest_path: &Path) -> Option<(SkipReason, &'static str)> {
+    if !context.policy.allows_destination(dest_path) {
+        return Some((SkipReason::Excluded, "destination not allowed by machine policy"));
+    }
+    let deferred = context
+        .deferred
+        .iter()
+        .any(|deferred| file_path.starts_with(deferred) || dest_path.starts_with(deferred));
+    deferred.then_some((SkipReason::InUse, "deferred while the user is active"))
+}
+
+/// Checks the pipelines, and returns the rules of those that are not running, with why.
+fn stopped_rules(config: &RulesConfig, health: &mut PipelineHealth) -> HashMap<String, String> {
+    let mut stopped = HashMap::new();
+    if config.pipelines.is_empty() {
+        return stopped;
+    }
+    let unhealthy = health.check(&config.pipelines);
+    health.update(&config.pipelines, &unhealthy);
+    for (name, reason) in &unhealthy {
+        let members = &config.pipelines[name].rules;
+        for rule in config.rules.iter().filter(|rule| members.iter().any(|member| rule.is_named(member))) {
+            stopped.insert(rule.pattern.clone(), format!("pipeline `{}` is not running: {}", name, reason));
+        }
+    }
+    stopped
+}
+
+/// Returns why `file_path` may not be placed in `dest_path` while its drive is this full.
+fn low_on_space(context: &SortContext, file_path: &Path, matched: &RuleMatch, dest_path: &Path) -> Option<String> {
+    if context.short_of_space.contains(&matched.rule) {
+        return Some("the destination's drive has no room for all of this rule's files".to_string());
+    }
+    let settings = context.config.disk_space.as_ref()?;
+    context.disk.check(file_path, dest_path, matched.copy, settings)
+}
+
+/// Plans the sort and checks that every destination drive has room for what it is to take,
+/// with `min_free` to spare. Returns the rules whose files stay in place under `skip-rule`.
+fn check_space(
+    files: &[PathBuf],
+    config: &RulesConfig,
+    overrides: &HashMap<PathBuf, FolderRules>,
+    review: Option<&Review>,
+    root: &Path,
+    read_only: bool,
+) -> Result<HashSet<String>, SortError> {
+    let Some(settings) = config.disk_space.as_ref().filter(|settings| settings.preflight != Preflight::Off) else {
+        return Ok(HashSet::new());
+    };
+    let mut plan = SpacePlan::default();
+    for planned in plan_space(files, config, overrides) {
+        let destination = match review.and_then(|review| review.decision(&planned.file)) {
+            Some(Decision::Skip) => continue,
+            Some(Decision::Destination(destination)) => destination.clone(),
+            None => planned.destination,
+        };
+        let destination = templates::expand_destination(&destination, &planned.file, &config.templates);
+        if remote::is_remote(&destination) {
+            continue;
+        }
+        let folder = match (destination.as_str(), planned.file.parent(), read_only) {
+            (".", Some(folder), false) => folder.to_path_buf(),
+            _ => root.join(paths::resolve_path(&destination)),
+        };
+        // Sorts into another folder leave the sources in place, so they take space like copies.
+        plan.add(&planned.rule, &planned.file, &folder, planned.copy || read_only);
+    }
+    let shortfalls = plan.shortfalls(settings.min_free);
+    for shortfall in &shortfalls {
+        warn!("{}", shortfall);
+    }
+    match settings.preflight {
+        Preflight::Abort if !shortfalls.is_empty() => {
+            Err(SortError::InsufficientSpace(shortfalls.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")))
+        }
+        _ => Ok(shortfalls.into_iter().flat_map(|shortfall| shortfall.rules).collect()),
+    }
+}
+
+/// Returns why the rule may not handle another file yet, counting the file if it may.
+fn rate_limited(context: &SortContext, matched: &RuleMatch) -> Option<String> {
+    let limit = matched.limit.as_ref()?;
+    context.limits.acquire(&matched.rule, limit).err()
+}
+
+/// Counts the file against the cap of the rule's destination, or returns why the
+/// destination is full. A destination with placeholders is capped as a whole, below them.
+fn over_capacity(context: &SortContext, matched: &RuleMatch, file_path: &Path) -> Result<Option<Reservation>, String> {
+    let Some(capacity) = matched.capacity.as_ref() else { return Ok(None) };
+    let folder = context.directory.join(paths::resolve_path(templates::destination_top(&matched.destination)));
+    context.capacity.reserve(&folder, capacity, file_path).map(Some)
+}
+
+/// Gives back what the file took of its destination's cap when it didn't get there.
+fn settle_capacity(context: &SortContext, reservation: Option<Reservation>, outcome: &Outcome) {
+    let Some(reservation) = reservation else { return };
+    match outcome {
+        Outcome::Moved { .. }
+        | Outcome::Copied { .. }
+        | Outcome::Staged { .. }
+        | Outcome::Queued { .. }
+        | Outcome::Duplicate {
+            policy: DuplicatePolicy::Hardlink,
+            ..
+        } => context.capacity.placed(reservation),
+        _ => context.capacity.release(reservation),
+    }
+}
+
+/// Uploads the file to the `sftp://`, `s3://` or `rclone:` folder `destination`. Moving rules delete
+/// the file once it is uploaded; copying rules upload it again when it changed.
+fn upload_file(context: &SortContext, file_path: &Path, matched: RuleMatch, destination: &str, copies: Option<&CopyLog>) -> Outcome {
+    let url = remote::target(destination, &file_path.file_name().unwrap().to_string_lossy());
+    if !context.policy.allows_destination(Path::new(&url)) {
+        return Outcome::Skipped {
+            matched,
+            reason: SkipReason::Excluded,
+            detail: "destination not allowed by machine policy".to_string(),
+        };
+    }
+    // Only the rule decides whether the source stays; the copy log merely remembers uploads.
+    let copying = matched.copy;
+    let mut replace = false;
+    if let Some(copies) = copies {
+        match copies.changed(file_path, Path::new(&url)) {
+            Ok(Some(false)) => {
+                return Outcome::UpToDate {
+                    matched,
+                    destination: PathBuf::from(url),
+                }
+            }
+            Ok(changed) => replace = changed.is_some(),
+            Err(error) => {
+                return Outcome::Failed {
+                    rule: Some(matched.rule),
+                    error,
+                }
+            }
+        }
+    }
+    if let Some(detail) = rate_limited(context, &matched) {
+        return Outcome::Skipped {
+            matched,
+            reason: SkipReason::Quota,
+            detail,
+        };
+    }
+    if context.dry_run {
+        return Outcome::Planned {
+            matched,
+            destination: PathBuf::from(url),
+        };
+    }
+    let uploaded = remote::upload(file_path, destination, &context.config.remotes, replace, matched.bandwidth.as_deref());
+    match uploaded {
+        Ok(url) if copying => {
+            if let Some(Err(e)) = copies.map(|copies| copies.record(file_path, Path::new(&url))) {
+                warn!("Failed to record upload of {:?}: {}", file_path, e);
+            }
+            Outcome::Copied {
+                matched,
+                destination: PathBuf::from(url),
+                checksum: None,
+            }
+        }
+        Ok(url) => match remove_source(context, file_path) {
+            Ok(()) => Outcome::Moved {
+                matched,
+                destination: PathBuf::from(url),
+                checksum: None,
+            },
+            Err(error) => Outcome::Failed {
+                rule: Some(matched.rule),
+                error,
+            },
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Outcome::Skipped {
+            matched,
+            reason: SkipReason::Conflict,
+            detail: "a file with this name already exists at the destination".to_string(),
+        },
+        Err(error) => Outcome::Failed {
+            rule: Some(matched.rule),
+            error,
+        },
+    }
+}
+
+fn copy_file(context: &SortContext, file_path: &PathBuf, matched: RuleMatch, copies: Option<&CopyLog>) -> Outcome {
+    let destination = timing::time(&matched.label, Stage::Metadata, || templates::expand_destination(&matched.destination, file_path, &context.config.templates));
+    if remote::is_remote(&destination) {
+        return upload_file(context, file_path, matched, &destination, copies);
+    }
+    let planned = context.replay.and_then(|replay| replay.target(file_path, &matched.rule, true));
+    let dest_path = match planned {
+        Some(target) => target.parent().unwrap_or(target).to_path_buf(),
+        None => destination_path(context, file_path, &matched),
+    };
+    if let Some((reason, detail)) = placement_blocked(context, file_path, &dest_path) {
+        return Outcome::Skipped {
+            matched,
+            reason,
+            detail: detail.to_string(),
+        };
+    }
+    if let Some(detail) = low_on_space(context, file_path, &matched, &dest_path) {
+        return Outcome::Skipped {
+            matched,
+            reason: SkipReason::LowDiskSpace,
+            detail,
+        };
+    }
+    let mut target = match planned {
+        Some(target) => target.to_path_buf(),
+        None => dest_path.join(filenames::adapt_name(file_path.file_name().unwrap(), &dest_path, &context.config.file_names)),
+    };
+    // A file of the same name placed earlier in this sort is in the way, as it will be on
+    // disk once it is there.
+    let taken = !context.reserved.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(target.clone());
+    if taken && context.read_only {
+        let mut reserved = context.reserved.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        target = unreserved(&target, &reserved);
+        reserved.insert(target.clone());
+    } else if taken {
+        return Outcome::Skipped {
+            matched,
+            reason: SkipReason::Conflict,
+            detail: "another file of this sort has the same name at the destination".to_string(),
+        };
+    }
+    // Files that are only copied stay in place, so later scans see them again.
+    if context.fs.exists(&target) {
+        let state = match copies {
+            Some(copies) => copies.check(file_path, &target),
+            None => duplicates::hash_file(file_path).and_then(|source| {
+                Ok(if source == duplicates::hash_file(&target)? {
+                    CopyState::UpToDate
+                } else {
+                    CopyState::Foreign
+                })
+            }),
+        };
+        match state {
+            Ok(CopyState::UpToDate) => {
+                return Outcome::UpToDate {
+                    matched,
+                    destination: target,
+                }
+            }
+            Ok(CopyState::Stale) => {}
+            // Cameras reuse file names, so imports sit next to files with the same name.
+            Ok(CopyState::Foreign) if context.read_only => target = duplicates::unique_path(&target),
+            Ok(CopyState::Foreign) => {
+                return Outcome::Skipped {
+                    matched,
+                    reason: SkipReason::Conflict,
+                    detail: "a different file already exists at the destination".to_string(),
+                }
+            }
+            Err(error) => {
+                return Outcome::Failed {
+                    rule: Some(matched.rule),
+                    error,
+                }
+            }
+        }
+    }
+    if let Some(detail) = rate_limited(context, &matched) {
+        return Outcome::Skipped {
+            matched,
+            reason: SkipReason::Quota,
+            detail,
+        };
+    }
+    let reservation = match over_capacity(context, &matched, file_path) {
+        Ok(reservation) => reservation,
+        Err(detail) => {
+            return Outcome::Skipped {
+                matched,
+                reason: SkipReason::OverCapacity,
+                detail,
+            }
+        }
+    };
+    if context.dry_run {
+        return Outcome::Planned {
+            matched,
+            destination: target,
+        };
+    }
+    let copied = timing::time(&matched.label, Stage::Move, || {
+        context
+            .fs
+            .create_dir_all(&dest_path)
+            .and_then(|_| if context.fs.exists(&target) { trash::make_room(&target, file_path, &context.config.transfers) } else { Ok(None) })
+            .map(|trashed| context.keep_trashed(trashed))
+            .and_then(|_| context.fs.copy_verified(file_path, &target))
+    });
+    let outcome = match copied {
+        Ok(checksum) => {
+            apply_ownership(&target, &matched);
+            tag_file(file_path, &target, &matched, context.config);
+            if let Some(Err(e)) = copies.map(|copies| copies.record(file_path, &target)) {
+                warn!("Failed to record copy of {:?}: {}", file_path, e);
+            }
+            Outcome::Copied {
+                matched,
+                destination: target,
+                checksum,
+            }
+        }
+        Err(error) => Outcome::Failed {
+            rule: Some(matched.rule),
+            error,
+        },
+    };
+    settle_capacity(context, reservation, &outcome);
+    outcome
+}
+
+fn file_with_rule(context: &SortContext, file_path: &PathBuf, matched: RuleMatch) -> Outcome {
+    let config = context.config;
+    let destination = timing::time(&matched.label, Stage::Metadata, || templates::expand_destination(&matched.destination, file_path, &context.config.templates));
+    if remote::is_remote(&destination) {
+        return upload_file(context, file_path, matched, &destination, None);
+    }
+    let actions = config.actions.get(&matched.destination);
+    let planned = context.replay.and_then(|replay| replay.target(file_path, &matched.rule, false));
+    let (dest_path, target) = match planned {
+        Some(target) => (target.parent().unwrap_or(target).to_path_buf(), target.to_path_buf()),
+        None => {
+            let dest_path = destination_path(context, file_path, &matched);
+            let target = target_path(file_path, &dest_path, actions, &context.config.templates, &context.config.file_names, context.sequences.as_ref(), &context.reserved, context.dry_run);
+            (dest_path, target)
+        }
+    };
+    // Files renamed in place already have their new name on the next sort.
+    if target == *file_path {
+        return Outcome::UpToDate { matched, destination: target };
+    }
+    if let Some((reason, detail)) = placement_blocked(context, file_path, &dest_path) {
+        return Outcome::Skipped {
+            matched,
+            reason,
+            detail: detail.to_string(),
+        };
+    }
+    if let Some(detail) = low_on_space(context, file_path, &matched, &dest_path) {
+        return Outcome::Skipped {
+            matched,
+            reason: SkipReason::LowDiskSpace,
+            detail,
+        };
+    }
+    if let Some(detail) = rate_limited(context, &matched) {
+        return Outcome::Skipped {
+            matched,
+            reason: SkipReason::Quota,
+            detail,
+        };
+    }
+    let reservation = match over_capacity(context, &matched, file_path) {
+        Ok(reservation) => reservation,
+        Err(detail) => {
+            return Outcome::Skipped {
+                matched,
+                reason: SkipReason::OverCapacity,
+                detail,
+            }
+        }
+    };
+    if context.dry_run {
+        return Outcome::Planned { matched, destination: target };
+    }
+    let outcome = place_with_actions(context, file_path, matched, actions, dest_path, &target);
+    settle_capacity(context, reservation, &outcome);
+    outcome
+}
+
+/// Places a file that passed the checks, through its destination's actions.
+fn place_with_actions(
+    context: &SortContext,
+    file_path: &PathBuf,
+    matched: RuleMatch,
+    actions: Option<&RuleActions>,
+    dest_path: PathBuf,
+    target: &Path,
+) -> Outcome {
+    let config = context.config;
+    let duplicates = actions.and_then(|actions| actions.duplicates).or(config.duplicates);
+    if let Some(policy) = duplicates.filter(|_| file_path.is_file()) {
+        match duplicates::find_duplicate(file_path, &dest_path) {
+            Ok(Some(existing)) => return handle_duplicate(context, file_path, matched, existing, target, policy),
+            Ok(None) => {}
+            Err(error) => {
+                return Outcome::Failed {
+                    rule: Some(matched.rule),
+                    error,
+                }
+            }
+        }
+    }
+    
+    if let Some(actions) = actions {
+        if let Some(stage) = &actions.stage {
+            return stage_file(context, file_path, matched, dest_path, stage);
+        }
+        if let Some(handoff) = &actions.transcode {
+            return match context
+                .jobs
+                .enqueue(&matched.rule, file_path, &dest_path, handoff, &actions.originals, matched.serial)
+            {
+                Ok(queued) => Outcome::Queued {
+                    matched,
+                    destination: dest_path,
+                    already_queued: !queued,
+                },
+                Err(error) => Outcome::Failed {
+                    rule: Some(matched.rule),
+                    error,
+                },
+            };
+        }
+        if let Some(compress) = &actions.compress {
+            return match archives::compress(file_path, &dest_path, compress) {
+                Ok(Some(archive)) => Outcome::Moved {
+                    matched,
+                    destination: archive,
+                    checksum: None,
+                },
+                Ok(None) => Outcome::Skipped {
+                    matched,
+                    reason: SkipReason::TooNew,
+                    detail: "not old enough to compress".to_string(),
+                },
+                Err(error) => Outcome::Failed {
+                    rule: Some(matched.rule),
+                    error,
+                },
+            };
+        }
+    }
+    
+    match timing::time(&matched.label, Stage::Move, || move_file(context.fs, target, file_path, &matched.destination, config)) {
+        Ok(Applied { file: destination, trashed, checksum }) => {
+            context.keep_trashed(trashed);
+            apply_ownership(&destination, &matched);
+            tag_file(file_path, &destination, &matched, config);
+            leave_link(file_path, &destination, &matched);
+            link_shortcuts(context, &destination, &matched);
+            if actions.is_some_and(|actions| actions.hardlink_dedupe) {
+                link_duplicate(context, &destination, &matched);
+            }
+            Outcome::Moved { matched, destination, checksum }
+        }
+        Err(error) => Outcome::Failed {
+            rule: Some(matched.rule),
+            error,
+        },
+    }
+}
+
+/// Opens the index of files to link to when a destination has `hardlink_dedupe`.
+fn dedupe_index(config: &RulesConfig, dry_run: bool) -> Option<DedupeIndex> {
+    if dry_run || !config.actions.values().any(|actions| actions.hardlink_dedupe) {
+        return None;
+    }
+    DedupeIndex::open()
+        .map_err(|e| warn!("Duplicate index unavailable, files are not linked to identical ones: {}", e))
+        .ok()
+}
+
+/// Replaces a file just moved in with a hard link to an identical file below the
+/// destination's top folder: the part of the destination before its first placeholder.
+fn link_duplicate(context: &SortContext, destination: &Path, matched: &RuleMatch) {
+    let Some(dedupe) = &context.dedupe else { return };
+    let root = context.directory.join(paths::resolve_path(templates::destination_top(&matched.destination)));
+    if let Err(e) = dedupe.link_duplicate(&root, destination) {
+        warn!(file = ?destination, "Failed to look for an identical file to link to: {}", e);
+    }
+}
+
+/// Moves the file into the staging folder and remembers where it goes once it is approved.
+fn stage_file(context: &SortContext, file_path: &PathBuf, matched: RuleMatch, dest_path: PathBuf, stage: &StageAction) -> Outcome {
+    let folder = context.directory.join(paths::resolve_path(&stage.folder));
+    let target = duplicates::unique_path(&folder.join(file_path.file_name().unwrap()));
+    let staged = StagedFile {
+        rule: matched.rule.clone(),
+        original: file_path.clone(),
+        staged: target.clone(),
+        folder: dest_path,
+        destination: matched.destination.clone(),
+        staged_at: DateTime::from(context.clock.now()),
+        approve_after_hours: stage.approve_after_hours,
+        approved: false,
+    };
+    let result = context
+        .fs
+        .create_dir_all(&folder)
+        .and_then(|_| context.fs.rename(file_path, &target))
+        .and_then(|_| context.staging.add(staged));
+    match result {
+        Ok(()) => Outcome::Staged {
+            matched,
+            destination: target,
+        },
+        Err(error) => Outcome::Failed {
+            rule: Some(matched.rule),
+            error,
+        },
+    }
+}
+
+/// Places the staged files that were approved or waited long enough in their destinations.
+fn release_staged(staging: &Staging, config: &RulesConfig, index: Option<&MoveIndex>) {
+    let due = match staging.take_due() {
+        Ok(due) => due,
+        Err(e) => {
+            warn!("Failed to read staged files: {}", e);
+            return;
+        }
+    };
+    let sequences = Sequences::open().ok().map(Mutex::new);
+    let reserved = Mutex::new(HashSet::new());
+    let disk = transfer::Disk {
+        settings: config.transfers.clone(),
+    };
+    for file in due {
+        let target = target_path(&file.staged, &file.folder, config.actions.get(&file.destination), &config.templates, &config.file_names, sequences.as_ref(), &reserved, false);
+        let placed = fs::create_dir_all(&file.folder).and_then(|_| place_file(&disk, &file.staged, &target, &file.destination, config));
+        match placed {
+            Ok(Applied { file: destination, trashed, checksum }) => {
+                info!(file = ?file.staged, destination = ?destination, "Moved staged file to its destination");
+                record_trashed(index, trashed);
+                if let Some(Err(e)) = index.map(|index| index.record_placed("release", Some(&file.rule), &file.staged, &destination, checksum.as_deref())) {
+                    warn!("Failed to record release in index: {}", e);
+                }
+            }
+            Err(e) => {
+                error!(file = ?file.staged, "Failed to move staged file to its destination: {}", e);
+                // Tried again with the next sort.
+                if let Err(e) = staging.add(file) {
+                    warn!("Failed to keep staged file: {}", e);
+                }
+            }
+        }
+    }
+}
+
+fn show_staging(command: &StagingCommand) -> std::io::Result<()> {
+    let staging = Staging::open();
+    match command {
+        StagingCommand::List => {
+            for file in staging.list() {
+                let status = match file.release_at() {
+                    _ if file.approved => "approved".to_string(),
+                    Some(release) => format!("moves on {}", release.format("%Y-%m-%d %H:%M")),
+                    None => "waiting for approval".to_string(),
+                };
+                println!("{}  -> {}  ({}, {})", file.staged.display(), file.folder.display(), file.rule, status);
+            }
+        }
+        StagingCommand::Approve { files, all } => {
+            let approved = staging.approve(files, *all)?;
+            println!("Approved {} staged files; they move on with the next sort", approved);
+        }
+    }
+    Ok(())
+}
+
+fn transfer_state(command: &StateCommand) -> std::io::Result<()> {
+    match command {
+        StateCommand::Export { archive } => {
+            let rules = rules_path(RULES_FILE);
+            let rules = rules.file_name().map_or_else(|| RULES_FILE.into(), |name| name.to_string_lossy());
+            let count = state::export(archive, &[rules.as_ref(), LUA_SCRIPT])?;
+            println!("Exported {} files to {}", count, archive.display());
+        }
+        StateCommand::Import { archive, force } => {
+            let count = state::import(archive, *force)?;
+            println!("Imported {} files from {}", count, archive.display());
+        }
+    }
+    Ok(())
+}
+
+fn run_profiles(command: &ProfilesCommand) -> std::io::Result<()> {
+    let config = profiles::load()?;
+    match command {
+        ProfilesCommand::List => {
+            let users = config.resolve();
+            if users.is_empty() {
+                println!("No user has a profile");
+            }
+            for user in users {
+                println!("{:<16} {:<12} {}", user.user, user.profile, user.folder);
+            }
+            Ok(())
+        }
+        ProfilesCommand::Run { interval } => {
+            shutdown::install();
+            profiles::run(&config, *interval)
+        }
+    }
+}
+
+/// Deals with a file whose contents `existing` already has, where the sort was to place it at
+/// `target`.
+fn handle_duplicate(context: &SortContext, file_path: &PathBuf, matched: RuleMatch, existing: PathBuf, target: &Path, policy: DuplicatePolicy) -> Outcome {
+    let result = match policy {
+        DuplicatePolicy::Skip => Ok(()),
+        DuplicatePolicy::DeleteSource => remove_source(context, file_path),
+        DuplicatePolicy::KeepBoth => {
+            let target = duplicates::unique_path(&existing.with_file_name(file_path.file_name().unwrap()));
+            return match place_file(context.fs, file_path, &target, &matched.destination, context.config) {
+                Ok(Applied { file: destination, trashed, checksum }) => {
+                    context.keep_trashed(trashed);
+                    leave_link(file_path, &destination, &matched);
+                    Outcome::Moved { matched, destination, checksum }
+                }
+                Err(error) => Outcome::Failed {
+                    rule: Some(matched.rule),
+                    error,
+                },
+            };
+        }
+        DuplicatePolicy::Hardlink => link_duplicate(context.fs, &existing, target, &context.reserved).and_then(|_| remove_source(context, file_path)),
+    };
+    match result {
+        Ok(()) => Outcome::Duplicate {
+            matched,
+            existing,
+            policy,
+        },
+        Err(error) => Outcome::Failed {
+            rule: Some(matched.rule),
+            error,
+        },
+    }
+}
+
+/// Links `existing` at `link`, the name the sort reserved for its duplicate. A name that
+/// already is `existing` is left as it is; when another file has it, the link takes the first
+/// number neither a file nor another file of the sort has.
+fn link_duplicate(fs: &dyn Verifying, existing: &Path, link: &Path, reserved: &Mutex<HashSet<PathBuf>>) -> std::io::Result<()> {
+    let link = match fs.symlink_metadata(link) {
+        Ok(metadata) if metadata.is_same_file(&fs.symlink_metadata(existing)?) => return Ok(()),
+        Ok(_) => {
+            let mut reserved = reserved.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let numbered = (1..)
+                .map(|counter| duplicates::numbered_path(link, counter))
+                .find(|numbered| !reserved.contains(numbered) && !fs.exists(numbered))
+                .unwrap();
+            reserved.insert(numbered.clone());
+            numbered
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => link.to_path_buf(),
+        Err(e) => return Err(e),
+    };
+    fs.hard_link(existing, &link)
+}
+
+/// The files the rules would place and where, to ask about in `sort --interactive`.
+fn plan_review(files: &[PathBuf], config: &RulesConfig, overrides: &HashMap<PathBuf, FolderRules>) -> std::io::Result<Vec<Planned>> {
+    let lua = script_lua(config)?;
+    Ok(files
+        .iter()
+        .filter_map(|file_path| {
+            let rules = effective_rules(overrides, &config.rules, file_path)?;
+            let matched = apply_rules(file_path, rules, &lua, config).into_iter().next()?;
+            Some(Planned {
+                file: file_path.clone(),
+                rule: matched.rule,
+                destination: matched.destination,
+                copy: matched.copy,
+            })
+        })
+        .collect())
+}
+
+/// Where the rules send each file as far as that is known without running anything: the
+/// first rule that matches and doesn't decide with `exec`. Files for commands, plugins and the
+/// Lua script are left to the check of each placement, so nothing runs twice.
+fn plan_space(files: &[PathBuf], config: &RulesConfig, overrides: &HashMap<PathBuf, FolderRules>) -> Vec<Planned> {
+    files
+        .iter()
+        .filter_map(|file_path| {
+            let rules = effective_rules(overrides, &config.rules, file_path)?;
+            let mut texts = content::Texts::new(file_path);
+            let rule = rules.iter().find(|rule| rule.matches_in(&mut texts))?;
+            rule.exec.is_none().then(|| Planned {
+                file: file_path.clone(),
+                rule: rule.pattern.clone(),
+                destination: rule.destination.clone(),
+                copy: rule.copy,
+            })
+        })
+        .collect()
+}
+
+fn count_matches(files: &[PathBuf], config: &RulesConfig, overrides: &HashMap<PathBuf, FolderRules>) -> std::io::Result<usize> {
+    let lua = script_lua(config)?;
+    Ok(files
+        .iter()
+        .filter(|file_path| {
+            effective_rules(overrides, &config.rules, file_path).is_some_and(|rules| !apply_rules(file_path, rules, &lua, config).is_empty())
+        })
+        .count())
+}
+
+/// Loads the `.organizer.toml` overrides for every folder that files were found in.
+fn folder_overrides(files: &[PathBuf], root: &Path, config: &RulesConfig) -> HashMap<PathBuf, FolderRules> {
+    let mut overrides = HashMap::new();
+    let folders: BTreeSet<&Path> = files.iter().filter_map(|file_path| file_path.parent()).collect();
+    for folder in folders {
+        if let Some(rules) = overrides::rules_for(folder, root, &config.rules) {
+            overrides.insert(folder.to_path_buf(), rules);
+        }
+    }
+    overrides
+}
+
+/// The listed files that can be sorted, each once; folders and missing files are left out.
+fn listed_files(listed: &[PathBuf], root: &Path, excluded: &[PathBuf]) -> Vec<PathBuf> {
+    // Listed paths are compared by where they really are, so `..` and links can't reach
+    // past the root or into a destination.
+    let resolve = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let root = resolve(root);
+    let excluded: Vec<PathBuf> = excluded.iter().map(|folder| resolve(folder)).collect();
+    let mut seen = HashSet::new();
+    let mut files = Vec::new();
+    let mut dropped = 0;
+    for file in listed {
+        match fs::symlink_metadata(file) {
+            Ok(metadata) if metadata.is_dir() => warn!(file = ?file, "Not sorting a folder given as a file; sort it with --path"),
+            Ok(_) => {
+                // A link is sorted as itself, so only the folder it is in is resolved.
+                let resolved = match (file.parent().filter(|parent| !parent.as_os_str().is_empty()), file.file_name()) {
+                    (Some(parent), Some(name)) => resolve(parent).join(name),
+                    (None, Some(name)) => resolve(Path::new(".")).join(name),
+                    _ => resolve(file),
+                };
+                if !resolved.starts_with(&root) {
+                    warn!(file = ?file, "Not sorting a listed file outside of {}", root.display());
+                    dropped += 1;
+                } else if let Some(folder) = excluded.iter().find(|folder| resolved.starts_with(folder)) {
+                    warn!(file = ?file, "Not sorting a listed file that is already in {}", folder.display());
+                    dropped += 1;
+                } else if seen.insert(resolved.clone()) {
+                    files.push(resolved);
+                }
+            }
+            Err(e) => warn!(file = ?file, "Not sorting listed file: {}", e),
+        }
+    }
+    if dropped > 0 {
+        info!("Left out {} of the {} listed files", dropped, listed.len());
+    }
+    files
+}
+
+/// Reads the paths of `--files-from`, or of stdin for `-`, leaving out empty lines.
+fn read_file_list(list: &Path, null: bool) -> std::io::Result<Vec<PathBuf>> {
+    let contents = if list == Path::new("-") {
+        let mut contents = Vec::new();
+        std::io::stdin().read_to_end(&mut contents)?;
+        contents
+    } else {
+        fs::read(list)?
+    };
+    let separator = if null { b'\0' } else { b'\n' };
+    let entries = contents.split(|byte| *byte == separator);
+    // Lists written on Windows end their lines with `\r\n`.
+    let entries = entries.map(|entry| if null { entry } else { entry.strip_suffix(b"\r").unwrap_or(entry) });
+    Ok(entries.filter(|entry| !entry.is_empty()).map(path_from_bytes).collect())
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Logs and shows the rules that ran out since the last sort.
+fn report_expired(expired: &[Rule], mut notifier: Option<&mut Notifier>) {
+    for rule in expired {
+        let message = format!(
+            "Rule {} expired and is no longer applied; remove it from {} once it is not needed",
+            rule.name.as_deref().unwrap_or(&rule.pattern),
+            RULES_FILE
+        );
+        warn!("{}", message);
+        if let Some(notifier) = notifier.as_deref_mut() {
+            notifier.alert(message);
+        }
+    }
+}
+
+/// Applies the sort's mode, or the one from `rules.json`, to the global and folder rules.
+fn set_mode(config: &mut RulesConfig, overrides: &mut HashMap<PathBuf, FolderRules>, mode: Option<Mode>) {
+    let mode = mode.or(config.mode).unwrap_or(Mode::Move);
+    config.mode = Some(mode);
+    rules::apply_mode(&mut config.rules, mode);
+    for local in overrides.values_mut() {
+        rules::apply_mode(&mut local.rules, mode);
+    }
+}
+
+/// Identifies the rules a sort applies, including folder rules, the Lua script and plugins,
+/// so that files no rule matched are tried again once any of them change.
+fn rules_fingerprint(config: &RulesConfig, overrides: &HashMap<PathBuf, FolderRules>) -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(serde_json::to_string(config).unwrap_or_default().as_bytes());
+    hasher.update(config.lua_script.as_deref().unwrap_or_default().as_bytes());
+    for plugin in config.loaded_plugins.hashes() {
+        hasher.update(plugin.as_bytes());
+    }
+    for (folder, local) in overrides.iter().collect::<BTreeMap<_, _>>() {
+        hasher.update(folder.to_string_lossy().as_bytes());
+        hasher.update(serde_json::to_string(local).unwrap_or_default().as_bytes());
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
+/// Drops every rule not named in `selection`, including folder rules, plugins and the Lua script.
+/// An empty selection keeps everything.
+fn restrict_rules(config: &mut RulesConfig, overrides: &mut HashMap<PathBuf, FolderRules>, selection: &[String]) -> std::io::Result<()> {
+    if selection.is_empty() {
+        return Ok(());
+    }
+    let known = |name: &String| {
+        name == LUA_SCRIPT
+            || config.loaded_plugins.names().any(|plugin| plugin == name)
+            || config.rules.iter().any(|rule| rule.is_named(name))
+            || overrides.values().any(|local| local.rules.iter().any(|rule| rule.is_named(name)))
+    };
+    if let Some(unknown) = selection.iter().find(|name| !known(name)) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("No rule named '{}'", unknown)));
+    }
+    let selected = |rule: &Rule| selection.iter().any(|name| rule.is_named(name));
+    config.rules.retain(selected);
+    for local in overrides.values_mut() {
+        local.rules.retain(selected);
+    }
+    config.loaded_plugins.retain(|plugin| selection.iter().any(|name| name == plugin));
+    if !selection.iter().any(|name| name == LUA_SCRIPT) {
+        config.lua_script = None;
+    }
+    Ok(())
+}
+
+/// Returns the rules for a file, or `None` if an `.organizer.toml` says to leave it alone.
+fn effective_rules<'a>(overrides: &'a HashMap<PathBuf, FolderRules>, rules: &'a [Rule], file_path: &Path) -> Option<&'a [Rule]> {
+    match file_path.parent().and_then(|folder| overrides.get(folder)) {
+        Some(local) if local.ignores(file_path) => None,
+        Some(local) => Some(&local.rules),
+        None => Some(rules),
+    }
+}
+
+/// Asks whether to go on with `planned` files that would be `action`, e.g. `moved`.
+fn confirm(planned: usize, action: &str) -> std::io::Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Interrupted,
+            format!("{} files would be {}; pass --yes to go ahead without confirmation", planned, action),
+        ));
+    }
+    eprint!("{} files would be {}. Continue? [y/N] ", planned, action);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+fn move_file(fs: &dyn Verifying, target: &Path, file_path: &PathBuf, destination: &str, config: &RulesConfig) -> std::io::Result<Applied> {
+    if let Some(folder) = target.parent() {
+        fs.create_dir_all(folder)?;
+    }
+    place_file(fs, file_path, target, destination, config)
+}
+
+/// Where a file moved into `dest_path` ends up, under the name the destination's `rename`
+/// action gives it. Names given to other files of the same sort are `reserved`: a later file
+/// that would get one gets a number instead, so files sorted together don't replace each
+/// other and a dry run plans the names the sort gives.
+fn target_path(
+    file_path: &Path,
+    dest_path: &Path,
+    actions: Option<&RuleActions>,
+    templates: &templates::Settings,
+    file_names: &FileNameSettings,
+    sequences: Option<&Mutex<Sequences>>,
+    reserved: &Mutex<HashSet<PathBuf>>,
+    dry_run: bool,
+) -> PathBuf {
+    let rename = actions.and_then(|actions| actions.rename.as_ref());
+    let name_for = |reserved: &HashSet<PathBuf>| {
+        let renamed = rename.and_then(|rename| rename.new_name(file_path, dest_path, templates, sequences, reserved, dry_run));
+        let name = match &renamed {
+            Some(renamed) => std::ffi::OsStr::new(&renamed.name),
+            None => file_path.file_name().unwrap(),
+        };
+        dest_path.join(filenames::adapt_name(name, dest_path, file_names))
+    };
+    // Names may need the file's metadata, so they are worked out without holding the lock
+    // and only again under it when another file has the name.
+    let target = name_for(&HashSet::new());
+    let mut reserved = reserved.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut target = if reserved.contains(&target) { name_for(&reserved) } else { target };
+    if target != file_path && reserved.contains(&target) {
+        target = unreserved(&target, &reserved);
+    }
+    reserved.insert(target.clone());
+    target
+}
+
+/// `target` with the first number that neither a file nor another file of the sort has.
+fn unreserved(target: &Path, reserved: &HashSet<PathBuf>) -> PathBuf {
+    (1..)
+        .map(|counter| duplicates::numbered_path(target, counter))
+        .find(|numbered| !reserved.contains(numbered) && !numbered.exists())
+        .unwrap()
+}
+
+/// Gives the placed file the owner and permissions its rule asks for; the file already is
+/// in place, so a failure is only logged.
+fn apply_ownership(destination: &Path, matched: &RuleMatch) {
+    if let Some(owner) = &matched.owner {
+        if let Err(e) = permissions::set_owner(destination, owner) {
+            warn!(file = ?destination, "Failed to give the file to {}: {}", owner, e);
+        }
+    }
+    if let Some(permissions) = matched.permissions.as_ref().filter(|_| destination.is_file()) {
+        let changed = permissions::mode(destination).and_then(|mode| match mode {
+            Some(mode) => permissions::set_mode(destination, permissions.apply(mode)),
+            None => Ok(()),
+        });
+        if let Err(e) = changed {
+            warn!(file = ?destination, "Failed to set permissions {}: {}", permissions, e);
+        }
+    }
+}
+
+/// Writes the tags of the destination's `tag` action on the placed file; the file already
+/// is in place, so a failure is only logged.
+fn tag_file(file_path: &Path, destination: &Path, matched: &RuleMatch, config: &RulesConfig) {
+    let tags = config.actions.get(&matched.destination).and_then(|actions| actions.tag.as_ref());
+    if let Some(tags) = tags.filter(|_| destination.is_file()) {
+        if let Err(e) = tags::write(destination, &matched.rule, file_path, tags) {
+            warn!(file = ?destination, "Failed to tag the file: {}", e);
+        }
+    }
+}
+
+/// Leaves a symlink to the moved file at its old path when the rule asks for one; the
+/// move itself already succeeded, so a failure is only logged.
+fn leave_link(file_path: &Path, destination: &Path, matched: &RuleMatch) {
+    if matched.link_back {
+        if let Err(e) = transfer::link_back(file_path, destination) {
+            warn!(file = ?file_path, "Failed to leave a symlink to {:?}: {}", destination, e);
+        }
+    }
+}
+
+/// Links the placed file from the folders of its destination's `shortcuts` action.
+fn link_shortcuts(context: &SortContext, destination: &Path, matched: &RuleMatch) {
+    let actions = context.config.actions.get(&matched.destination);
+    let Some(shortcuts) = actions.and_then(|actions| actions.shortcuts.as_ref()) else { return };
+    let folders: Vec<PathBuf> = shortcuts.folders.iter().map(|folder| context.directory.join(paths::resolve_path(folder))).collect();
+    if let Err(e) = shortcuts::create(destination, &folders) {
+        warn!(file = ?destination, "Failed to make shortcuts to the file: {}", e);
+    }
+}
+
+/// Removes the shortcuts whose file is gone or that were kept long enough. Destinations
+/// without a folder of their own, such as `{ext}`, have no way to tell their shortcuts from
+/// other links, so theirs are kept.
+fn prune_shortcuts(config: &RulesConfig, root: &Path) {
+    for (destination, actions) in &config.actions {
+        let Some(shortcuts) = actions.shortcuts.as_ref() else { continue };
+        let Some(managed) = templates::managed_folder(root, destination) else {
+            debug!("Not pruning the shortcuts of {}, as it has no folder of its own", destination);
+            continue;
+        };
+        for folder in &shortcuts.folders {
+            let folder = root.join(paths::resolve_path(folder));
+            if let Err(e) = shortcuts::prune(&folder, &managed, shortcuts.keep_days) {
+                warn!("Failed to prune the shortcuts in {}: {}", folder.display(), e);
+            }
+        }
+    }
+}
+
+/// Moves the folders in `root` that a `directories` rule matches, as a whole.
+fn sort_directories(config: &RulesConfig, root: &Path, excluded: &[PathBuf], policy: &MachinePolicy, index: Option<&MoveIndex>, options: &SortOptions) {
+    if config.directories.is_empty() {
+        return;
+    }
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Failed to list the folders in {}: {}", root.display(), e);
+            return;
+        }
+    };
+    let folders = entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+        .map(|entry| entry.path())
+        .filter(|folder| !scan::is_excluded(folder, excluded, config.repositories))
+        .filter(|folder| options.new_since.is_none_or(|mark| arrivals::created_since(folder, mark)));
+    for folder in folders {
+        let matched = config.directories.iter().find_map(|rule| Some((rule, rule.destination_for(&folder)?)));
+        let (Some((rule, destination)), Some(name)) = (matched, folder.file_name()) else { continue };
+        let destination = templates::expand_destination(&destination, &folder, &config.templates);
+        let target = root.join(paths::resolve_path(&destination)).join(name);
+        if target.starts_with(&folder) || !policy.allows_destination(&target) {
+            warn!(folder = ?folder, "Not moving folder into {}, destination not allowed", target.display());
+            continue;
+        }
+        match directories::settled(&folder, rule.stable_seconds) {
+            Ok(true) => {}
+            Ok(false) => {
+                debug!(folder = ?folder, "Leaving the folder for a later sort, it is still changing");
+                continue;
+            }
+            Err(e) => {
+                warn!("Failed to look through the folder {}: {}", folder.display(), e);
+                continue;
+            }
+        }
+        if options.dry_run {
+            info!(folder = ?folder, "Would move folder to {}", target.display());
+            continue;
+        }
+        let mut moves = Vec::new();
+        let placed = directories::place(&folder, &target, rule.on_exists, &config.transfers, &mut moves);
+        // Files merged before an error are where they went, so they are recorded either way.
+        for moved in &moves {
+            let action = if moved.folder { "move_dir" } else { "move" };
+            if let Some(Err(e)) = index.map(|index| index.record(action, Some(&rule.pattern), &moved.from, &moved.to)) {
+                warn!("Failed to record move in index: {}", e);
+            }
+        }
+        match placed {
+            Ok(()) if moves.is_empty() => info!(folder = ?folder, "Left folder in place, {} exists", target.display()),
+            Ok(()) => info!(folder = ?folder, "Moved folder to {}", target.display()),
+            Err(e) => warn!("Failed to move the folder {}: {}", folder.display(), e),
+        }
+    }
+}
+
+/// Removes the folders that files were moved out of once they are empty, keeping the folders
+/// sorts never take files from as well as those in `cleanup.keep`.
+fn remove_emptied_folders(config: &RulesConfig, root: &Path, files: &[PathBuf]) {
+    let emptied = files
+        .iter()
+        .filter(|file| !file.exists())
+        .filter_map(|file| file.parent())
+        .map(Path::to_path_buf)
+        .collect();
+    let mut keep = excluded_folders(config, root);
+    keep.extend(config.cleanup.keep.iter().map(|folder| root.join(paths::resolve_path(folder))));
+    cleanup::remove_empty(root, emptied, &keep);
+}
+
+/// Moves the file to `target` and runs the actions of its destination on it. A file that was
+/// at `target` goes to the trash when that is enabled, and is among the files returned as
+/// trashed.
+fn place_file(fs: &dyn Verifying, file_path: &PathBuf, target: &Path, destination: &str, config: &RulesConfig) -> std::io::Result<Applied> {
+    let replaced = if fs.exists(target) { trash::make_room(target, file_path, &config.transfers)? } else { None };
+    let checksum = fs.rename_verified(file_path, target)?;
+    let mut applied = match config.actions.get(destination) {
+        // Actions may rename or change the file, so the checksum no longer describes it.
+        Some(actions) => actions::apply_actions(target, actions, &config.templates, &config.transfers)?,
+        None => Applied {
+            file: target.to_path_buf(),
+            trashed: Vec::new(),
+            checksum,
+        },
+    };
+    // The replaced file went to the trash first.
+    if let Some(replaced) = replaced {
+        applied.trashed.insert(0, replaced);
+    }
+    Ok(applied)
+}
+
+fn take_trashed(trashed: &Mutex<Vec<Trashed>>) -> Vec<Trashed> {
+    std::mem::take(&mut *trashed.lock().unwrap_or_else(|poisoned| poisoned.into_inner()))
+}
+
+/// Records the files put in the trash in the move index, so they can be found there.
+fn record_trashed(index: Option<&MoveIndex>, trashed: impl IntoIterator<Item = Trashed>) {
+    let Some(index) = index else { return };
+    for trashed in trashed {
+        if let Err(e) = index.record("trash", None, &trashed.original, &trashed.trashed) {
+            warn!("Failed to record {:?} in the trash in index: {}", trashed.original, e);
+        }
+    }
+}
+
+/// Everything besides the output that learns about the outcomes of a sort.
+struct Recorders<'a> {
+    notifier: Option<&'a mut Notifier>,
+    index: Option<&'a MoveIndex>,
+    totals: &'a mut Totals,
+    webhook: &'a mut Webhook,
+    unmatched: &'a mut UnmatchedReport,
+    /// Folders files were placed in, to flush the removable drives among them.
+    written: &'a mut BTreeSet<PathBuf>,
+    activity: Option<&'a Control>,
+    retries: Option<&'a mut RetryQueue>,
+    plan: Option<&'a mut Plan>,
+    /// The rules sorted with, for the actions of planned files.
+    config: &'a RulesConfig,
+    /// The files the sort put in the trash, from [`SortContext`].
+    trashed: &'a Mutex<Vec<Trashed>>,
+}
+
+fn report_in_order(
+    files: &[PathBuf],
+    rx: mpsc::Receiver<(usize, Vec<Outcome>)>,
+    options: &SortOptions,
+    recorders: Recorders,
+) {
+    let Recorders {
+        mut notifier,
+        index,
+        totals,
+        webhook,
+        unmatched,
+        written,
+        activity,
+        mut retries,
+        mut plan,
+        config,
+        trashed,
+    } = recorders;
+    if let Some(activity) = activity {
+        activity.start_sort(files);
+    }
+    // Workers finish out of order; buffer results so output follows directory order.
+    let mut reporter = Reporter::new(options.output);
+    if options.progress {
+        reporter.show_progress(files.len());
+    }
+    let mut pending = BTreeMap::new();
+    let mut next_index = 0;
+    
+    for (position, outcomes) in rx {
+        pending.insert(position, outcomes);
+        while let Some(mut outcomes) = pending.remove(&next_index) {
+            // Files a move replaced went to the trash before it, and are recorded first.
+            record_trashed(index, take_trashed(trashed));
+            if let Some(retries) = retries.as_deref_mut() {
+                retry_locked(&files[next_index], &mut outcomes, retries, notifier.as_deref_mut());
+            }
+            if let Some(activity) = activity {
+                activity.file_done(&files[next_index], &outcomes);
+            }
+            for outcome in outcomes {
+                reporter.record(&files[next_index], &outcome);
+                if let Outcome::Moved { destination, .. } | Outcome::Copied { destination, .. } = &outcome {
+                    written.extend(destination.parent().map(Path::to_path_buf));
+                }
+                if let (Outcome::Planned { matched, destination }, Some(plan)) = (&outcome, plan.as_deref_mut()) {
+                    plan.add(&files[next_index], matched, destination, planned_actions(matched, config));
+                }
+                if let Outcome::Copied { matched, destination, checksum } = &outcome {
+                    if let Some(index) = index {
+                        if let Err(e) = index.record_placed("copy", Some(&matched.rule), &files[next_index], destination, checksum.as_deref()) {
+                            warn!("Failed to record copy in index: {}", e);
+                        }
+                    }
+                }
+                if let Outcome::Staged { matched, destination } = &outcome {
+                    if let Some(Err(e)) = index.map(|index| index.record("stage", Some(&matched.rule), &files[next_index], destination)) {
+                        warn!("Failed to record staging in index: {}", e);
+                    }
+                }
+                if let Outcome::Duplicate { matched, existing, policy } = &outcome {
+                    if let (Some(index), true) = (index, *policy != DuplicatePolicy::Skip) {
+                        if let Err(e) = index.record(policy.as_str(), Some(&matched.rule), &files[next_index], existing) {
+                            warn!("Failed to record duplicate in index: {}", e);
+                        }
+                    }
+                }
+                if let Outcome::Moved { matched, destination, checksum } = &outcome {
+                    if let Some(notifier) = notifier.as_deref_mut() {
+                        notifier.file_moved(&files[next_index], &matched.destination);
+                    }
+                    webhook.file_moved(&files[next_index], destination, &matched.rule);
+                    if let Some(index) = index {
+                        if let Err(e) = index.record_placed("move", Some(&matched.rule), &files[next_index], destination, checksum.as_deref()) {
+                            warn!("Failed to record move in index: {}", e);
+                        }
+                    }
+                }
+                if let Outcome::Quarantined { matched, destination, reason, planned: false } = &outcome {
+                    if let Some(notifier) = notifier.as_deref_mut() {
+                        notifier.file_quarantined(&files[next_index], reason);
+                    }
+                    webhook.file_quarantined(&files[next_index], destination, &matched.rule, reason);
+                    if let Some(Err(e)) = index.map(|index| index.record("quarantine", Some(&matched.rule), &files[next_index], destination)) {
+                        warn!("Failed to record quarantine in index: {}", e);
+                    }
+                }
+                if let (Outcome::Unmatched(SkipReason::SyncEcho), Some(index)) = (&outcome, index) {
+                    match index.record_echo(&files[next_index]) {
+                        Ok(Some(ECHO_ALERT)) => {
+                            let message = format!("{} keeps reappearing after it is moved; a sync tool may be putting it back", files[next_index].display());
+                            warn!("{}", message);
+                            if let Some(notifier) = notifier.as_deref_mut() {
+                                notifier.alert(message);
+                            }
+                        }
+                        Ok(_) => {}
+                        Err(e) => warn!("Failed to record sync echo in index: {}", e),
+                    }
+                }
+                let routed = matches!(&outcome, Outcome::Moved { matched, .. } | Outcome::Copied { matched, .. } if matched.rule == UNMATCHED_RULE);
+                if routed || matches!(outcome, Outcome::Unmatched(SkipReason::NoRuleMatched)) {
+                    unmatched.file_unmatched(&files[next_index]);
+                }
+                if let Outcome::Failed { rule, error } = outcome {
+                    webhook.file_failed(&files[next_index], rule.as_deref(), &error);
+                    if let Some(Err(e)) = index.map(|index| index.record_error(rule.as_deref(), &files[next_index], &error.to_string())) {
+                        warn!("Failed to record error in index: {}", e);
+                    }
+                }
+            }
+            reporter.file_done();
+            next_index += 1;
+        }
+    }
+    reporter.finish();
+    totals.add(reporter.totals());
+}
+
+/// Leaves a file another process holds open in place until its retry, or reports it as
+/// failed once it was locked on every try.
+fn retry_locked(file_path: &Path, outcomes: &mut [Outcome], retries: &mut RetryQueue, notifier: Option<&mut Notifier>) {
+    let locked = outcomes.iter().position(|outcome| matches!(outcome, Outcome::Failed { error, .. } if retry::is_locked(error)));
+    let Some(position) = locked else {
+        retries.done(file_path);
+        return;
+    };
+    match retries.locked(file_path) {
+        Retry::After(delay) => {
+            info!(file = ?file_path, "File is in use by another process, trying again in {}s", delay.as_secs());
+            outcomes[position] = Outcome::Unmatched(SkipReason::Locked);
+        }
+        Retry::GaveUp(attempts) => {
+            let message = format!("{} was still in use by another process after {} tries", file_path.display(), attempts);
+            warn!("{}", message);
+            if let Some(notifier) = notifier {
+                notifier.alert(message);
+            }
+        }
+    }
+}
+
+/// Opens the move index, signing new entries when an audit key exists.
+fn open_index() -> rusqlite::Result<MoveIndex> {
+    let mut index = MoveIndex::open()?;
+    let settings = settings::load_settings();
+    if let Some(key) = audit::load_key(&settings.audit) {
+        index.set_signer(key, settings.audit.sign_every);
+    }
+    Ok(index)
+}
+
+/// Copies new index entries to the mirror; the next sort tries again when that fails.
+fn mirror_index(index: &MoveIndex, target: &MirrorTarget) {
+    match mirror::push(index, target) {
+        Ok(0) => {}
+        Ok(mirrored) => info!("Mirrored {} index entries", mirrored),
+        Err(e) => warn!("Failed to mirror the move index: {}", e),
+    }
+}
+
+fn run_audit(command: &AuditCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let settings = settings::load_settings().audit;
+    match command {
+        AuditCommand::Keygen => {
+            let public_key = audit::generate_key(&settings)?;
+            println!("Created signing key {:?}", settings.key_path());
+            println!("Public key: {}", audit::encode_hex(public_key.as_bytes()));
+        }
+        AuditCommand::Sign => {
+            let key = audit::load_key(&settings).ok_or("No signing key found; run `audit keygen` first")?;
+            if MoveIndex::open()?.sign_latest(&key)? {
+                println!("Signed the move index");
+            } else {
+                println!("Nothing to sign");
+            }
+        }
+        AuditCommand::Mirror => {
+            let target = settings::load_settings().mirror.ok_or("No `mirror` is set in config.json")?;
+            let mirrored = mirror::push(&MoveIndex::open()?, &target)?;
+            println!("Mirrored {} index entries", mirrored);
+        }
+        AuditCommand::Verify { public_key } => {
+            let key = match public_key {
+                Some(public_key) => Some(audit::parse_public_key(public_key).ok_or("Invalid public key")?),
+                None => audit::load_key(&settings).map(|key| key.verifying_key()),
+            };
+            let report = MoveIndex::open()?.verify(key.as_ref())?;
+            println!(
+                "Checked {} entries ({} recorded before hashing) and {} signatures",
+                report.entries, report.unprotected, report.signatures
+            );
+            if key.is_none() && report.signatures > 0 {
+                println!("No signing key found, signatures were not checked");
+            }
+            for problem in &report.problems {
+                println!("PROBLEM: {}", problem);
+            }
+            if !report.problems.is_empty() {
+                return Err(format!("{} problems found", report.problems.len()).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn record_completed(index: Option<&MoveIndex>, completed: Vec<CompletedJob>) {
+    let Some(index) = index else { return };
+    for job in completed {
+        record_trashed(Some(index), job.trashed);
+        if let Err(e) = index.record("handoff", Some(&job.rule), &job.source, &job.destination) {
+            warn!("Failed to record handoff in index: {}", e);
+        }
+    }
+}
+
+/// Lists the rules learned from files moved by hand, or adopts and dismisses those picked
+/// by their number in the list.
+fn learn_rules(adopt: &[usize], dismiss: &[usize]) -> std::io::Result<()> {
+    let settings = settings::load_settings()
+        .learn
+        .ok_or_else(|| std::io::Error::other("learning is off; turn it on with `learn` in config.json"))?;
+    let learner = Learner::open(settings).map_err(std::io::Error::other)?;
+    let proposals = learner.proposals(&try_load_config()?.rules).map_err(std::io::Error::other)?;
+    let picked = |numbers: &[usize]| {
+        numbers
+            .iter()
+            .map(|number| {
+                let proposal = number.checked_sub(1).and_then(|at| proposals.get(at));
+                proposal.ok_or_else(|| std::io::Error::other(format!("there is no proposed rule {}", number)))
+            })
+            .collect::<std::io::Result<Vec<_>>>()
+    };
+    let (adopted, dismissed) = (picked(adopt)?, picked(dismiss)?);
+    if adopted.is_empty() && dismissed.is_empty() {
+        if proposals.is_empty() {
+            println!("No rules learned yet");
+        }
+        for (number, proposal) in proposals.iter().enumerate() {
+            let (pattern, destination) = (&proposal.pattern, &proposal.destination);
+            println!("{:>3}. {} -> {} ({} files moved by hand)", number + 1, pattern, destination, proposal.examples);
+        }
+        return Ok(());
+    }
+    if !adopted.is_empty() {
+        add_rules(&adopted.iter().map(|proposal| proposal.rule()).collect::<Vec<_>>())?;
+        for proposal in adopted {
+            println!("Added rule {} -> {}", proposal.pattern, proposal.destination);
+        }
+    }
+    for proposal in dismissed {
+        learner.dismiss(proposal).map_err(std::io::Error::other)?;
+        println!("Dismissed rule {} -> {}", proposal.pattern, proposal.destination);
+    }
+    Ok(())
+}
+
+/// Converts another organizer's rules and reports what could not be converted on stderr.
+fn import_rules(source: ImportSource, config: &Path, write: Option<&Path>) -> std::io::Result<()> {
+    let contents = fs::read(config)?;
+    let conversion = import::convert(source, &contents).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    let rules = serde_json::to_string_pretty(&serde_json::json!({ "rules": conversion.rules }))?;
+    match write {
+        Some(path) => {
+            // Existing rules are never overwritten; merging is left to the user.
+            fs::OpenOptions::new().write(true).create_new(true).open(path)?.write_all(rules.as_bytes())?;
+        }
+        None => println!("{}", rules),
+    }
+    
+    eprintln!("Converted {} rules.", conversion.rules.len());
+    if !conversion.locations.is_empty() {
+        let locations: Vec<&str> = conversion.locations.iter().map(String::as_str).collect();
+        eprintln!("The original rules sorted {}; run Organizer with --path for each of them.", locations.join(", "));
+    }
+    for note in &conversion.notes {
+        eprintln!("  {}", note);
+    }
+    Ok(())
+}
+
+/// Writes the rules in another format and reports what could not be exported on stderr.
+fn export_rules(format: ExportFormat, directory: &str, recursive: bool, write: Option<&Path>) -> std::io::Result<()> {
+    let config = try_load_config()?;
+    let directory = fs::canonicalize(paths::resolve_path(directory))?;
+    let export = match format {
+        ExportFormat::OrganizeTool => export::to_organize(&config.rules, &directory, recursive),
+    }
+    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    match write {
+        Some(path) => fs::OpenOptions::new().write(true).create_new(true).open(path)?.write_all(export.contents.as_bytes())?,
+        None => print!("{}", export.contents),
+    }
+    
+    let mut notes = export.notes;
+    let unsupported = [
+        (config.lua_script.is_some(), LUA_SCRIPT),
+        (!config.actions.is_empty(), "rule actions"),
+        (config.duplicates.is_some(), "duplicate detection"),
+        (!config.sources.is_empty(), "sources"),
+        (!config.defer_while_active.is_empty(), "defer_while_active"),
+    ];
+    for (_, feature) in unsupported.iter().filter(|(used, _)| *used) {
+        notes.push(format!("{} can't be exported and was left out", feature));
+    }
+    for note in &notes {
+        eprintln!("  {}", note);
+    }
+    Ok(())
+}
+
+/// Runs the guided setup when there are no rules yet and someone is there to answer, and
+/// shows the usage otherwise.
+fn first_run() {
+    if rules_path(RULES_FILE).exists() || !std::io::stdin().is_terminal() {
+        let _ = Cli::command().print_help();
+        std::process::exit(2);
+    }
+    let onboarding = onboarding::Onboarding {
+        rules: rules_path(RULES_FILE),
+        preview: preview_rules,
+        sort: sort_now,
+    };
+    if let Err(e) = onboarding::run(&onboarding) {
+        error!("Setup failed: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Shows what sorting `folder` with the rules in `contents` would do.
+fn preview_rules(folder: &str, contents: &str) -> std::io::Result<()> {
+    let config = parse_rules(contents)?;
+    Ok(sort_files(folder, &onboarding_options(true), &mut Session::new(None), config)?)
+}
+
+fn sort_now(folder: &str) -> std::io::Result<()> {
+    let mut session = Session::new(None);
+    sort_files(folder, &onboarding_options(false), &mut session, try_load_config()?)?;
+    let completed = session.jobs.wait()?;
+    record_completed(session.index.as_ref(), completed);
+    Ok(())
+}
+
+fn onboarding_options(dry_run: bool) -> SortOptions {
+    SortOptions {
+        jobs: 1,
+        output: OutputFormat::Text,
+        dry_run,
+        confirm_threshold: None,
+        recursive: false,
+        rules_only: Vec::new(),
+        mode: None,
+        into: None,
+        incremental: false,
+        interactive: false,
+        review_by_rule: false,
+        retry_only: false,
+        progress: false,
+        files: None,
+        new_since: None,
+    }
+}
+
+fn print_layout() -> std::io::Result<()> {
+    let config = load_rules()?.unwrap_or_default();
+    if config.layout.is_empty() {
+        println!("There is no `layout` in {}.", RULES_FILE);
+        return Ok(());
+    }
+    let generated = categories::layout_rules(&config.layout).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    println!("{}", serde_json::to_string_pretty(&generated)?);
+    Ok(())
+}
+
+/// Prints the rules and settings a sort runs with, and where they came from: `rules.json` or
+/// the built-in rules, the keys set by the environment and `--set`, and the machine policy.
+fn dump_config(format: DumpFormat) -> Result<(), Box<dyn std::error::Error>> {
+    let rules_file = rules_path(RULES_FILE);
+    let mut contents = match fs::read_to_string(&rules_file) {
+        Ok(contents) => serde_json::from_str(&contents)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => serde_json::json!({}),
+        Err(e) => return Err(e.into()),
+    };
+    overlay::apply_rules(&mut contents)?;
+    // Without a `rules` key the built-in rules are used, also next to a layout.
+    let rules_from = match contents.get("rules") {
+        Some(_) => "rules.json",
+        None if rules_file.exists() => "built-in rules, rules.json has no `rules`",
+        None => "built-in rules, there is no rules.json",
+    };
+    let mut config = try_load_config()?;
+    policy::load_policy().enforce(&mut config);
+    set_mode(&mut config, &mut HashMap::new(), None);
+    let order: Vec<String> = config
+        .rules
+        .iter()
+        .enumerate()
+        .map(|(position, rule)| {
+            let target = rule.exec.as_ref().map_or_else(|| rule.destination.clone(), |command| format!("`{}`", command));
+            let mut label = format!("{}. {} -> {} (priority {}", position + 1, rule.pattern, target, rule.priority);
+            for (set, flag) in [(rule.copy, "copy"), (rule.continue_matching, "continue")] {
+                if set {
+                    label.push_str(", ");
+                    label.push_str(flag);
+                }
+            }
+            label + ")"
+        })
+        .collect();
+    let mut dump = serde_json::json!({
+        "files": {
+            "rules": rules_file,
+            "settings": paths::config_dir().join(settings::SETTINGS_FILE),
+            "lua_script": config.lua_script.is_some().then(|| rules_path(LUA_SCRIPT)),
+        },
+        "rules_from": rules_from,
+        "overrides": overlay::describe(),
+        "rule_order": order,
+        "rules": config,
+        "settings": settings::load_settings(),
+    });
+    // TOML has no null, and YAML and JSON read the same without them.
+    strip_nulls(&mut dump);
+    let dumped = match format {
+        DumpFormat::Json => serde_json::to_string_pretty(&dump)?,
+        DumpFormat::Toml => toml::to_string_pretty(&dump)?,
+        DumpFormat::Yaml => serde_yaml::to_string(&dump)?,
+    };
+    println!("{}", dumped.trim_end());
+    Ok(())
+}
+
+fn strip_nulls(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            map.retain(|_, value| !value.is_null());
+            map.values_mut().for_each(strip_nulls);
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(strip_nulls),
+        _ => {}
+    }
+}
+
+fn test_file(file_path: &PathBuf, directory: Option<&str>) -> std::io::Result<()> {
+    if !file_path.is_file() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "Not a file"));
+    }
+    let directory = match directory {
+        Some(directory) => paths::resolve_path(directory),
+        None => file_path.parent().unwrap_or(Path::new(".")).to_path_buf(),
+    };
+    let mut config = load_config();
+    let policy = policy::load_policy();
+    policy.enforce(&mut config);
+    // Rules that ran out are left out as in a sort, without noting them as reported.
+    let expiry = RuleExpiry::load();
+    expiry.without_expired(&mut config.rules);
+    let mut overrides = folder_overrides(std::slice::from_ref(file_path), &directory, &config);
+    for local in overrides.values_mut() {
+        expiry.without_expired(&mut local.rules);
+    }
+    set_mode(&mut config, &mut overrides, None);
+    
+    if let Some((kind, group)) = kinds::classify(&file_path.file_name().unwrap_or_default().to_string_lossy()) {
+        println!("Kind: {} ({}), as a `layout` files it", kind, group);
+    }
+    println!("Rules for {}:", file_path.display());
+    if let Some(Link::Valid(target)) = links::inspect(file_path) {
+        println!("Symlink to {}; the deciding rule's `symlinks` policy applies.", target.display());
+    }
+    let Some(rules) = effective_rules(&overrides, &config.rules, file_path) else {
+        println!("Ignored by {}; the file stays where it is ({}).", overrides::FILE_NAME, SkipReason::Excluded);
+        return Ok(());
+    };
+    if config.temporary.is_temporary(file_path) {
+        let cleanup = match config.temporary.action {
+            TemporaryAction::Cleanup => format!(", until it is unmodified for {} hours and cleaned up", config.temporary.cleanup_after_hours),
+            TemporaryAction::Skip => String::new(),
+        };
+        println!("A temporary file; it stays where it is ({}){}.", SkipReason::Temporary, cleanup);
+        return Ok(());
+    }
+    let skips_hidden = config.hidden_files == HiddenPolicy::Skip && hidden::is_hidden(file_path);
+    // The rules are applied as a sort applies them, so commands and scripts run once, and
+    // what each rule did is told from the matches.
+    let matches = if skips_hidden {
+        // Hidden files are only sorted by the rules that ask for them, not by scripts or plugins.
+        let opted_in: Vec<Rule> = rules.iter().filter(|rule| rule.hidden).cloned().collect();
+        decide_rules(file_path, &opted_in, &config).0
+    } else {
+        apply_rules(file_path, rules, &script_lua(&config)?, &config)
+    };
+    let mut pending = matches.iter().peekable();
+    let mut decided = false;
+    let mut texts = content::Texts::new(file_path);
+    for rule in rules {
+        let target = rule.exec.as_ref().map_or_else(|| rule.destination.clone(), |command| format!("`{}`", command));
+        let label = format!("{} -> {} (priority {})", rule.pattern, target, rule.priority);
+        if decided {
+            println!("  skipped   {}", label);
+            continue;
+        }
+        if skips_hidden && !rule.hidden {
+            println!("  no match  {}: the file is hidden and the rule doesn't set `hidden`", label);
+            continue;
+        }
+        if !rule.matches_in(&mut texts) {
+            println!("  no match  {}: {}", label, rule.explain(&mut texts));
+            continue;
+        }
+        let Some(matched) = pending.next_if(|matched| matched.label == rule.label()) else {
+            println!("  no match  {}: command returned nothing, or failed", label);
+            continue;
+        };
+        let mut notes = vec![rule.explain(&mut texts)];
+        if rule.exec.is_some() {
+            notes.push(format!("command returned \"{}\"", matched.destination));
+        }
+        if matched.copy {
+            notes.push("copies".to_string());
+        }
+        if rule.continue_matching {
+            notes.push("continues with later rules".to_string());
+        }
+        if rule.link_back && !rule.copy {
+            notes.push("leaves a symlink behind".to_string());
+        }
+        if let Some(cooldown) = rule.cooldown {
+            notes.push(format!("waits {}s between files", cooldown));
+        }
+        if let Some(max) = rule.max_per_hour {
+            notes.push(format!("at most {} files per hour", max));
+        }
+        if let Some(max) = &rule.max_dest_size {
+            notes.push(format!("destination holds at most {}", max));
+        }
+        if let Some(max) = rule.max_files {
+            notes.push(format!("destination holds at most {} files", max));
+        }
+        if rule.verify_type {
+            notes.push(match filetype::mismatch(file_path) {
+                Ok(Some(detected)) => format!("content is {}, quarantined in {}", detected, quarantine_folder(&config)),
+                Ok(None) => "content fits the extension".to_string(),
+                Err(e) => format!("content can't be checked: {}", e),
+            });
+        }
+        if rule.scan {
+            notes.push(match &config.antivirus {
+                Some(scanner) => format!("scanned by {} first", scanner.name()),
+                None => "needs an antivirus scanner, none is configured".to_string(),
+            });
+        }
+        println!("  match     {}: {}", label, notes.join(", "));
+        decided = !rule.continue_matching;
+    }
+    // What is left was decided by a plugin or the script, which are only asked when no rule decided.
+    let asked: Vec<&str> = config.loaded_plugins.names().into_iter().chain(config.lua_script.as_ref().map(|_| LUA_SCRIPT)).collect();
+    for name in asked {
+        match pending.next_if(|matched| matched.label == name) {
+            Some(matched) => {
+                println!("  match     {} returned \"{}\"", name, matched.destination);
+                decided = true;
+            }
+            None if decided || skips_hidden => println!("  skipped   {}", name),
+            None => println!("  no match  {} returned nothing, or failed", name),
+        }
+    }
+    
+    if matches.is_empty() && skips_hidden {
+        println!("No rule with `hidden` matches; the file stays where it is ({}).", SkipReason::Hidden);
+    } else if matches.is_empty() {
+        println!("No rule matches; the file stays where it is ({}).", SkipReason::NoRuleMatched);
+    }
+    for matched in &matches {
+        let destination = templates::expand_destination(&matched.destination, file_path, &config.templates);
+        let dest_path = directory.join(paths::resolve_path(&destination));
+        let action = if matched.copy { "Copied" } else { "Moved" };
+        if policy.allows_destination(&dest_path) {
+            println!("{} to {} by {}", action, dest_path.display(), matched.rule);
+        } else {
+            println!(
+                "Not {} to {} by {}: destination not allowed by machine policy ({})",
+                action.to_lowercase(),
+                dest_path.display(),
+                matched.rule,
+                SkipReason::Excluded
+            );
+        }
+    }
+    let timings = timing::snapshot();
+    if !timings.is_empty() {
+        println!("Time spent:");
+        for (rule, stages) in &timings {
+            let stages: Vec<String> = stages.iter().map(|(stage, time)| format!("{} {:.2} ms", stage, time.total_ms)).collect();
+            println!("  {:<24} {}", rule, stages.join(", "));
+        }
+    }
+    Ok(())
+}
+
+fn show_history(filter: &HistoryFilter, output: OutputFormat) -> rusqlite::Result<()> {
+    let index = MoveIndex::open()?;
+    for entry in index.history(filter)? {
+        match output {
+            OutputFormat::Text => println!(
+                "{}  {:<8} {:<10} {} -> {}",
+                entry.time.format("%Y-%m-%d %H:%M"),
+                entry.action,
+                entry.rule.as_deref().unwrap_or("-"),
+                entry.source,
+                entry.destination
+            ),
+            OutputFormat::Json => match serde_json::to_string(&entry) {
+                Ok(line) => println!("{}", line),
+                Err(e) => error!("Failed to serialize history entry: {}", e),
+            },
+        }
+    }
+    Ok(())
+}
+
+fn show_report(since: Option<DateTime<Local>>, format: SummaryFormat) -> Result<(), Box<dyn std::error::Error>> {
+    let summaries = MoveIndex::open()?.summary(since, summary::TOP_DESTINATIONS)?;
+    summary::print(&summaries, format)?;
+    Ok(())
+}
+
+/// Works out what sorting `directory` would do, without changing anything, and writes it to
+/// `output` for `apply_plan`.
+fn write_plan(directory: &str, recursive: bool, rules_only: &[String], output: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let config = try_load_config()?;
+    let options = SortOptions {
+        jobs: 1,
+        output: OutputFormat::Text,
+        dry_run: true,
+        confirm_threshold: None,
+        recursive,
+        rules_only: rules_only.to_vec(),
+        mode: None,
+        into: None,
+        incremental: false,
+        interactive: false,
+        review_by_rule: false,
+        retry_only: false,
+        progress: false,
+        files: None,
+        new_since: None,
+    };
+    let mut session = Session::new(None);
+    session.plan = Some(Plan::new(&paths::resolve_path(directory)));
+    sort_files(directory, &options, &mut session, config)?;
+    let plan = session.plan.take().expect("the plan was set above");
+    plan.save(output)?;
+    println!("Planned {} files in {}", plan.steps.len(), output.display());
+    Ok(())
+}
+
+/// Moves and copies the files of the plan in `file`, all of them or none, with the actions a
+/// sort gives them. Nothing is done when a file changed since the plan was made or something
+/// took its place at the destination, as the plan no longer says what a sort would do then.
+fn apply_plan(file: &Path, yes: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let plan = Plan::load(file)?;
+    let config = try_load_config()?;
+    // A daemon sorting the folder meanwhile could take the files or their destinations.
+    let _sorting = instance::sorting(&plan.folder)?;
+    let conflicts = plan.conflicts();
+    if !conflicts.is_empty() {
+        for (step, why) in &conflicts {
+            println!("{} {}", step.file.display(), why);
+        }
+        let message = format!("{} of {} files can't be applied as planned; make a new plan", conflicts.len(), plan.steps.len());
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, message).into());
+    }
+    if plan.steps.is_empty() {
+        println!("The plan has nothing to do.");
+        return Ok(());
+    }
+    if let Some(threshold) = settings::load_settings().confirm_threshold.filter(|_| !yes) {
+        if plan.steps.len() > threshold && !confirm(plan.steps.len(), "moved")? {
+            return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "Apply cancelled").into());
+        }
+    }
+    let mut files: Vec<PathBuf> = Vec::new();
+    for step in &plan.steps {
+        if !files.contains(&step.file) {
+            files.push(step.file.clone());
+        }
+    }
+    // One worker places the files in the order they were planned.
+    let options = SortOptions {
+        jobs: 1,
+        output: OutputFormat::Text,
+        dry_run: false,
+        confirm_threshold: None,
+        recursive: false,
+        rules_only: Vec::new(),
+        mode: None,
+        into: None,
+        incremental: false,
+        interactive: false,
+        review_by_rule: false,
+        retry_only: false,
+        progress: false,
+        files: Some(files),
+        new_since: None,
+    };
+    let (folder, planned) = (plan.folder.clone(), plan.steps.len());
+    let transfers = config.transfers.clone();
+    let mut session = Session::new(None);
+    session.replay = Some(Replay::new(plan));
+    sort_files(&folder.to_string_lossy(), &options, &mut session, config)?;
+    let replay = session.replay.take().expect("the replay was set above");
+    let Some(failed) = replay.failed().or_else(|| replay.missed()) else {
+        println!("Applied the plan for {}: {} files placed", folder.display(), planned);
+        return Ok(());
+    };
+    let (moved_back, kept) = replay.undo(&transfers);
+    if let Some(index) = session.index.as_ref() {
+        for (from, to) in &moved_back {
+            if let Err(e) = index.record("move", None, from, to) {
+                warn!("Failed to record putting back {:?} in index: {}", to, e);
+            }
+        }
+    }
+    let mut message = format!("{} couldn't be placed as planned; the files placed before it were put back", failed.display());
+    if kept > 0 {
+        message.push_str(&format!(", except {} that were changed or uploaded as they were placed, or whose old place was taken", kept));
+    }
+    Err(std::io::Error::other(message).into())
+}
+
+/// Compares where earlier sorts placed files below `directory` with where the current
+/// rules would put them, and moves the files whose rule now says otherwise. Files no rule
+/// moves any more stay where they are. Folder rules from `.organizer.toml` only apply
+/// where files are sorted from, so the global rules decide here.
+fn reorganize(directory: &str, dry_run: bool, yes: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let root = paths::resolve_path(directory);
+    let mut config = try_load_config()?;
+    // A daemon sorting the folder meanwhile could take the files or their destinations.
+    let _sorting = instance::sorting(&root)?;
+    let policy = policy::load_policy();
+    policy.enforce(&mut config);
+    set_mode(&mut config, &mut HashMap::new(), None);
+    let index = MoveIndex::open()?;
+    let lua = script_lua(&config)?;
+    let mut migrations = Vec::new();
+    for placement in index.placements()? {
+        let file_path = PathBuf::from(&placement.path);
+        if !file_path.starts_with(&root) || !file_path.is_file() {
+            continue;
+        }
+        let matches = apply_rules(&file_path, &config.rules, &lua, &config);
+        let Some(matched) = matches.into_iter().find(|matched| !matched.copy) else { continue };
+        let destination = templates::expand_destination(&matched.destination, &file_path, &config.templates);
+        let folder = root.join(paths::resolve_path(&destination));
+        if file_path.parent() == Some(folder.as_path()) || !policy.allows_destination(&folder) {
+            continue;
+        }
+        let moved_by = placement.rule.as_deref().unwrap_or("-");
+        println!("{} -> {}  ({}, was {})", file_path.display(), folder.display(), matched.rule, moved_by);
+        migrations.push((file_path, folder, matched.rule));
+    }
+    if migrations.is_empty() {
+        println!("All placed files are where the current rules put them.");
+        return Ok(());
+    }
+    if dry_run {
+        return Ok(());
+    }
+    if !yes && !confirm(migrations.len(), "moved")? {
+        return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "Reorganize cancelled").into());
+    }
+    
+    let mut moved = 0;
+    for (file_path, folder, rule) in &migrations {
+        let target = duplicates::unique_path(&folder.join(file_path.file_name().unwrap()));
+        match fs::create_dir_all(folder).and_then(|_| transfer::move_file(file_path, &target, &config.transfers)) {
+            Ok(checksum) => {
+                moved += 1;
+                if let Err(e) = index.record_placed("reorganize", Some(rule), file_path, &target, checksum.as_deref()) {
+                    warn!("Failed to record move of {:?} in index: {}", file_path, e);
+                }
+            }
+            Err(e) => error!(file = ?file_path, "Failed to move to {:?}: {}", folder, e),
+        }
+    }
+    println!("Moved {} of {} files", moved, migrations.len());
+    Ok(())
+}
+
+/// Audits the permissions of the files sorts placed below `directory` against the
+/// `permissions` of the rules that placed them, and fixes them unless `dry_run`.
+fn fix_permissions(directory: &str, dry_run: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let root = paths::resolve_path(directory);
+    let config = try_load_config()?;
+    if config.rules.iter().all(|rule| rule.permissions.is_none()) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "No rule in rules.json sets `permissions`").into());
+    }
+    let index = MoveIndex::open()?;
+
+    let (mut checked, mut changed, mut failed) = (0, 0, 0);
+    for placement in index.placements()? {
+        let file_path = PathBuf::from(&placement.path);
+        if !file_path.starts_with(&root) || !file_path.is_file() {
+            continue;
+        }
+        let Some(placed_by) = placement.rule.as_deref() else { continue };
+        let Some(permissions) = config
+            .rules
+            .iter()
+            .filter(|rule| rule.pattern == placed_by)
+            .find_map(|rule| rule.permissions.as_ref())
+        else {
+            continue;
+        };
+        let Some(mode) = permissions::mode(&file_path)? else {
+            return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "File permissions are only supported on Unix").into());
+        };
+        checked += 1;
+        let wanted = permissions.apply(mode);
+        if wanted == mode {
+            continue;
+        }
+        println!("{}  {:04o} -> {:04o}  ({}: {})", file_path.display(), mode, wanted, placed_by, permissions);
+        if dry_run {
+            changed += 1;
+            continue;
+        }
+        match permissions::set_mode(&file_path, wanted) {
+            Ok(()) => changed += 1,
+            Err(e) => {
+                failed += 1;
+                error!(file = ?file_path, "Failed to change permissions: {}", e);
+            }
+        }
+    }
+    let verb = if dry_run { "Would change" } else { "Changed" };
+    println!("{} permissions of {} of {} checked files", verb, changed, checked);
+    if failed > 0 {
+        return Err(std::io::Error::other(format!("{} files could not be changed", failed)).into());
+    }
+    Ok(())
+}
+
+/// Renames the files in `directory` that don't follow the naming convention, in place.
+fn normalize(directory: &str, recursive: bool, dry_run: bool, yes: bool, on_conflict: NameConflict) -> Result<(), Box<dyn std::error::Error>> {
+    let root = paths::resolve_path(directory);
+    let config = try_load_config()?;
+    if config.naming.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "No `naming` convention in rules.json").into());
+    }
+    let mut renames = Vec::new();
+    for file_path in scan::collect_files(&root, recursive, &[], config.repositories, false, None)? {
+        if !file_path.is_file() {
+            continue;
+        }
+        let Some(name) = config.naming.normalized_name(&file_path) else { continue };
+        let mut to = file_path.with_file_name(&name);
+        let planned = renames.iter().any(|rename: &Rename| rename.to == to);
+        if planned || name_taken(&file_path, &name) {
+            match on_conflict {
+                NameConflict::Skip => {
+                    println!("{}  skipped, {} is taken", file_path.display(), name);
+                    continue;
+                }
+                NameConflict::Number => {
+                    to = (1..)
+                        .map(|counter| file_path.with_file_name(numbered(&name, counter)))
+                        .find(|candidate| !candidate.exists() && !renames.iter().any(|rename| rename.to == *candidate))
+                        .unwrap();
+                }
+            }
+        }
+        println!("{} -> {}", file_path.display(), to.file_name().unwrap_or_default().to_string_lossy());
+        renames.push(Rename { from: file_path, to });
+    }
+    if renames.is_empty() {
+        println!("All file names follow the naming convention.");
+        return Ok(());
+    }
+    if dry_run {
+        return Ok(());
+    }
+    if !yes && !confirm(renames.len(), "renamed")? {
+        return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "Normalize cancelled").into());
+    }
+    
+    let index = open_index().map_err(|e| warn!("Move index unavailable, renames will not be recorded: {}", e)).ok();
+    let renamed = rename_files(&renames, index.as_ref(), &config.transfers);
+    println!("Renamed {} of {} files; `normalize --undo` reverses this", renamed.len(), renames.len());
+    Journal::default().push(renamed)?;
+    Ok(())
+}
+
+/// Whether a different file already has `name`. On case-insensitive file systems the file
+/// itself is found under its new name when only the case changes, which doesn't count.
+fn name_taken(file_path: &Path, name: &str) -> bool {
+    let target = file_path.with_file_name(name);
+    if !target.exists() {
+        return false;
+    }
+    let Some(folder) = file_path.parent() else { return true };
+    fs::read_dir(folder).map_or(true, |entries| entries.flatten().any(|entry| entry.file_name() == name))
+}
+
+/// `name (counter).ext`, like `duplicates::unique_path` names copies.
+fn numbered(name: &str, counter: usize) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => format!("{} ({}).{}", stem, counter, extension),
+        _ => format!("{} ({})", name, counter),
+    }
+}
+
+/// Renames files, recording each in the index, and returns the renames that succeeded.
+fn rename_files(renames: &[Rename], index: Option<&MoveIndex>, transfers: &transfer::Settings) -> Vec<Rename> {
+    let mut renamed = Vec::new();
+    for rename in renames {
+        let case_only = rename.from.to_string_lossy().to_lowercase() == rename.to.to_string_lossy().to_lowercase();
+        // A change of case alone goes through a temporary name, for case-insensitive file systems.
+        let result = if rename.to.exists() && !case_only {
+            Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, "the new name was taken in the meantime"))
+        } else if rename.to.exists() {
+            let temporary = rename.to.with_file_name(format!(".{}.rename", rename.to.file_name().unwrap_or_default().to_string_lossy()));
+            transfer::move_file(&rename.from, &temporary, transfers).and_then(|_| transfer::move_file(&temporary, &rename.to, transfers))
+        } else {
+            transfer::move_file(&rename.from, &rename.to, transfers)
+        };
+        match result {
+            Ok(checksum) => {
+                if let Some(Err(e)) = index.map(|index| index.record_placed("normalize", None, &rename.from, &rename.to, checksum.as_deref())) {
+                    warn!("Failed to record rename of {:?} in index: {}", rename.from, e);
+                }
+                renamed.push(rename.clone());
+            }
+            Err(e) => error!(file = ?rename.from, "Failed to rename to {:?}: {}", rename.to, e),
+        }
+    }
+    renamed
+}
+
+/// Gives the files renamed by the last `normalize` run their old names back, unless the
+/// old name was taken in the meantime or the file is gone.
+fn undo_normalize() -> Result<(), Box<dyn std::error::Error>> {
+    let journal = Journal::default();
+    let Some(run) = journal.last() else {
+        println!("No renames to undo.");
+        return Ok(());
+    };
+    let config = try_load_config()?;
+    let mut reverse = Vec::new();
+    for rename in run.renames.iter().rev() {
+        if !rename.to.exists() {
+            println!("{}  skipped, the file is gone", rename.to.display());
+        } else if name_taken(&rename.to, &rename.from.file_name().unwrap_or_default().to_string_lossy()) {
+            println!("{}  skipped, {} is taken", rename.to.display(), rename.from.display());
+        } else {
+            reverse.push(Rename {
+                from: rename.to.clone(),
+                to: rename.from.clone(),
+            });
+        }
+    }
+    let index = open_index().map_err(|e| warn!("Move index unavailable, renames will not be recorded: {}", e)).ok();
+    let renamed = rename_files(&reverse, index.as_ref(), &config.transfers);
+    println!(
+        "Restored the names of {} of {} files renamed on {}",
+        renamed.len(),
+        run.renames.len(),
+        run.time.format("%Y-%m-%d %H:%M")
+    );
+    // Files whose old name was taken, or that failed to be renamed, are tried again by the
+    // next undo; those that are gone are forgotten.
+    let remaining: Vec<Rename> = run
+        .renames
+        .iter()
+        .filter(|rename| rename.to.exists() && !renamed.iter().any(|restored| restored.to == rename.from))
+        .cloned()
+        .collect();
+    if !remaining.is_empty() {
+        println!("{} files keep their new names until the next --undo", remaining.len());
+    }
+    journal.settle(&run, remaining)?;
+    Ok(())
+}
+
+/// Lists the names `rename` actions would give the files in `directory`, numbered as a sort
+/// would number them, and optionally writes them to a CSV file. Nothing is renamed.
+fn preview_renames(directory: &str, recursive: bool, csv: Option<&Path>) -> Result<(), Box<dyn std::error::Error>> {
+    let root = paths::resolve_path(directory);
+    let mut config = try_load_config()?;
+    let files: Vec<PathBuf> = scan::collect_files(&root, recursive, &[], config.repositories, false, None)?.into_iter().filter(|file| file.is_file()).collect();
+    let expiry = RuleExpiry::load();
+    expiry.without_expired(&mut config.rules);
+    let mut overrides = folder_overrides(&files, &root, &config);
+    for local in overrides.values_mut() {
+        expiry.without_expired(&mut local.rules);
+    }
+    set_mode(&mut config, &mut overrides, None);
+    let sequences = Sequences::open()
+        .map_err(|e| warn!("Sequence numbers unavailable, `{{seq}}` counts like `{{counter}}`: {}", e))
+        .ok()
+        .map(Mutex::new);
+
+    // Names given to earlier files are taken for later ones, as they would be in the sort.
+    let mut reserved = HashSet::new();
+    let mut renames = Vec::new();
+    for planned in plan_review(&files, &config, &overrides)? {
+        let rename = config.actions.get(&planned.destination).and_then(|actions| actions.rename.as_ref());
+        let Some(rename) = rename.filter(|_| !planned.copy) else { continue };
+        let folder = match (planned.destination.as_str(), planned.file.parent()) {
+            (".", Some(folder)) => folder.to_path_buf(),
+            _ => root.join(paths::resolve_path(&templates::expand_destination(&planned.destination, &planned.file, &config.templates))),
+        };
+        let new_name = rename.new_name(&planned.file, &folder, &config.templates, sequences.as_ref(), &reserved, true);
+        let (to, collided) = match new_name {
+            Some(new_name) => (folder.join(new_name.name), new_name.collided),
+            None => (folder.join(planned.file.file_name().unwrap_or_default()), false),
+        };
+        reserved.insert(to.clone());
+        if to == planned.file {
+            continue;
+        }
+        let taken = if collided { "  (name taken, numbered)" } else { "" };
+        println!("{} -> {}{}", planned.file.display(), to.display(), taken);
+        renames.push((planned.file, to, collided));
+    }
+    let collisions = renames.iter().filter(|(_, _, collided)| *collided).count();
+    println!("{} files would be renamed, {} of them numbered because their name is taken", renames.len(), collisions);
+
+    if let Some(csv) = csv {
+        let mut lines = vec!["old,new,collision".to_string()];
+        for (from, to, collided) in &renames {
+            lines.push(format!(
+                "{},{},{}",
+                summary::csv_field(&from.to_string_lossy()),
+                summary::csv_field(&to.to_string_lossy()),
+                collided
+            ));
+        }
+        fs::write(csv, lines.join("\n") + "\n")?;
+        println!("Wrote the new names to {}", csv.display());
+    }
+    Ok(())
+}
+
+fn locate_files(pattern: &glob::Pattern, output: OutputFormat) -> rusqlite::Result<()> {
+    let index = MoveIndex::open()?;
+    let locations = index.locate(pattern)?;
+    if locations.is_empty() && output == OutputFormat::Text {
+        println!("No moved files match '{}'", pattern);
+    }
+    for location in locations {
+        match output {
+            OutputFormat::Text => {
+                let missing = if location.exists { "" } else { " (missing)" };
+                println!("{} -> {}{}", location.original, location.current, missing);
+                println!(
+                    "    moved {} time(s), last on {}",
+                    location.moves,
+                    location.last_moved.format("%Y-%m-%d %H:%M")
+                );
+            }
+            OutputFormat::Json => match serde_json::to_string(&location) {
+                Ok(line) => println!("{}", line),
+                Err(e) => error!("Failed to serialize location: {}", e),
+            },
+        }
+    }
+    Ok(())
+}
 
-#[derive(Parser)]
-#[command(name = "FileSorter")]
-#[command(about = "A modular file organization tool", long_about = None)]
-struct Cli {
-    #[command(subcommand)]
-    command: Commands,
+/// Lists the files below `path` that carry all `tags` and were placed by `rule`.
+fn find_tagged(path: &str, tags: &[String], rule: Option<&str>, output: OutputFormat) -> std::io::Result<()> {
+    let root = paths::resolve_path(path);
+    let mut found = 0;
+    for file_path in scan::collect_files(&root, true, &[], RepositoryPolicy::Skip, false, None)? {
+        let file_tags = match tags::read(&file_path) {
+            Ok(file_tags) => file_tags,
+            Err(e) => {
+                debug!(file = ?file_path, "Failed to read tags: {}", e);
+                continue;
+            }
+        };
+        if file_tags.rule.is_none() && file_tags.tags.is_empty() {
+            continue;
+        }
+        if !file_tags.has_all(tags) || rule.is_some_and(|rule| file_tags.rule.as_deref() != Some(rule)) {
+            continue;
+        }
+        found += 1;
+        match output {
+            OutputFormat::Text => {
+                println!("{}", file_tags.path.display());
+                if !file_tags.tags.is_empty() {
+                    println!("    tags: {}", file_tags.tags.join(", "));
+                }
+                if let (Some(rule), Some(original)) = (&file_tags.rule, &file_tags.original_path) {
+                    println!("    placed by '{}' from {}", rule, original.display());
+                }
+            }
+            OutputFormat::Json => match serde_json::to_string(&file_tags) {
+                Ok(line) => println!("{}", line),
+                Err(e) => error!("Failed to serialize tags: {}", e),
+            },
+        }
+    }
+    if found == 0 && output == OutputFormat::Text {
+        println!("No tagged files found in {}", root.display());
+    }
+    Ok(())
 }
 
-#[derive(Subcommand)]
-enum Commands {
-    /// Sort files based on predefined rules
-    Sort {
-        #[arg(short, long)]
-        path: String,
-    },
-    /// Run the file sorter as a background process
-    Daemon {
-        #[arg(short, long)]
-        path: String,
-        #[arg(short, long, default_value_t = 10)]
-        interval: u64,
-    },
-    /// Install the daemon as a system service
-    Install {
-        #[arg(short, long)]
-        path: String,
-        #[arg(short, long, default_value_t = 10)]
-        interval: u64,
-    },
+/// When the daemon sorts.
+enum Timing {
+    /// After the interval passed, or earlier when the watcher notices a change. Without a
+    /// watcher, the interval grows up to `max` while nothing changes.
+    Interval { every: Duration, max: Duration },
+    /// Only at the times of the schedule.
+    Schedule(Schedule),
 }
 
-#[derive(Serialize, Deserialize)]
-struct RulesConfig {
-    rules: HashMap<String, String>,
+/// Where the daemon tells others what it does, besides its log.
+struct Outlets {
+    metrics_addr: Option<SocketAddr>,
+    http: Option<SocketAddr>,
+    bus: Option<Bus>,
+    events_file: Option<PathBuf>,
 }
 
-fn main() {
-    let cli = Cli::parse();
+/// Keeps `apply` from placing files in `root` while the daemon sorts it, and waits for one
+/// that is. Without the lock the daemon sorts all the same.
+fn sort_lock(root: &Path) -> Option<statefile::Lock> {
+    instance::sorting(root).map_err(|e| warn!("Failed to lock {:?} for sorting: {}", root, e)).ok()
+}
+
+fn run_daemon(directory: &str, timing: &Timing, options: &SortOptions, backend: WatchBackend, outlets: &Outlets) {
+    let root = paths::resolve_path(directory);
+    let _lock = match InstanceLock::acquire(&root) {
+        Ok(lock) => lock,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    shutdown::install();
+    let started = CrashLog::load().start(&root, &settings::load_settings().safe_mode).unwrap_or_else(|e| {
+        warn!("Failed to note that the daemon started: {}", e);
+        safemode::FolderState::default()
+    });
+    // After repeated crashes the rules may be what brings the daemon down, so it only shows
+    // what it would do until `resume --enforce`.
+    let mut safe = started.safe_since().is_some();
+    let mut options = SortOptions { dry_run: safe, ..options.clone() };
+    let mut session = Session::new(Some(Notifier::new()));
+    session.retries = Some(RetryQueue::new(settings::load_settings().retry));
+    if let Some(learn) = settings::load_settings().learn {
+        if !options.incremental {
+            warn!("Rules are only learned from files moved by hand without --full-scan");
+        }
+        session.learner = Learner::open(learn).map_err(|e| warn!("Failed to start learning rules: {}", e)).ok();
+    }
+    if let Some(address) = outlets.metrics_addr {
+        let metrics = Metrics::new();
+        match metrics::serve(metrics.clone(), address) {
+            Ok(()) => session.metrics = Some(metrics),
+            Err(e) => error!("Failed to serve metrics on {}: {}", address, e),
+        }
+    }
+    let mut rule_files = RuleFiles::new(&[rules_path(RULES_FILE), rules_path(LUA_SCRIPT)]);
+    let mut config = load_config();
+    let mut watcher = Watcher::new(&root, options.recursive, excluded_folders(&config, &root), config.repositories, backend);
+    let guard = Guard::start(backend);
+    guard.protect(protected_folders(&config, &root));
+    if safe {
+        let message = format!(
+            "The daemon crashed {} times in a short time and runs in safe mode, only logging what it would do; run `Organizer resume --enforce` once the rules are fixed",
+            started.crashes.len()
+        );
+        warn!("{}", message);
+        if let Some(notifier) = session.notifier.as_mut() {
+            notifier.configure(&config.notifications);
+            notifier.alert(message);
+        }
+    }
+    reconcile_downtime(&mut session, &root, options.recursive, &config);
+    let control = Control::new();
+    control::listen(control.clone(), &root);
+    session.control = Some(control.clone());
+    if let Some(path) = outlets.events_file.as_deref() {
+        if let Err(e) = events::write_to(&control, path) {
+            error!("Failed to open the events file {}: {}", path.display(), e);
+        }
+    }
+    if let Some(address) = outlets.http {
+        let web = Web {
+            control: control.clone(),
+            rules: rules_path(RULES_FILE),
+            check: check_rules,
+        };
+        if let Err(e) = web::serve(web, address) {
+            error!("Failed to serve the web interface on {}: {}", address, e);
+        }
+    }
+    if let Some(bus) = outlets.bus {
+        if let Err(e) = dbus::serve(control.clone(), bus) {
+            error!("Failed to serve {} on the bus: {}", dbus::NAME, e);
+        }
+    }
+    if let Timing::Schedule(schedule) = timing {
+        wait_for_next_run(&mut watcher, &control, schedule.until_next(), false);
+    }
+    let mut backoff = match timing {
+        Timing::Interval { every, max } => Some(Backoff::new(*every, *max)),
+        Timing::Schedule(_) => None,
+    };
+    let mut held_back = false;
+    let mut measured: Option<Instant> = None;
+    let mut measurement: Option<thread::JoinHandle<()>> = None;
+    let mut advanced: Option<Instant> = None;
+    let scrub_settings = settings::load_settings().scrub;
+    let mut scrubbed = session.index.as_ref().and_then(|index| index.last_scrub().ok().flatten());
+    while !shutdown::requested() {
+        if safe && !CrashLog::load().folder(&root).is_some_and(|state| state.safe_since().is_some()) {
+            info!("Left safe mode, placing files again");
+            safe = false;
+            options.dry_run = false;
+        }
+        // Rules are only swapped between sorts, so each sort sees one consistent set.
+        let requested = control.take_reload();
+        if rule_files.changed() || requested {
+            match try_load_config().and_then(|reloaded| check_lua_script(&reloaded).map(|_| reloaded)) {
+                Ok(reloaded) => {
+                    reload::log_diff(&config.rules, &reloaded.rules);
+                    let excluded = excluded_folders(&reloaded, &root);
+                    if excluded != excluded_folders(&config, &root) || reloaded.repositories != config.repositories {
+                        swap_watcher(&mut watcher, Watcher::new(&root, options.recursive, excluded, reloaded.repositories, backend));
+                    }
+                    config = reloaded;
+                    guard.protect(protected_folders(&config, &root));
+                }
+                Err(e) => warn!("Keeping the previous rules: {}", e),
+            }
+        }
+        let quiet = config.quiet_hours.filter(|hours| hours.contains(Local::now().time()));
+        if let Some(hours) = quiet {
+            if !held_back {
+                info!("Quiet hours, not sorting until {}", hours.end().format("%H:%M"));
+            }
+            held_back = true;
+        } else if let Some(sorting) = control.start_sorting() {
+            held_back = false;
+            session.user_active = presence::user_active().unwrap_or(false);
+            let sorting = (sorting, sort_lock(&root));
+            if let Err(e) = sort_files(directory, &options, &mut session, config.clone()) {
+                error!("Daemon error: {}", e);
+                control.record_error(e.to_string());
+            }
+            drop(sorting);
+            if let Some(Err(e)) = session.index.as_ref().map(|index| index.record_heartbeat(&root)) {
+                warn!("Failed to record when the daemon last sorted: {}", e);
+            }
+            if let Some(backoff) = backoff.as_mut() {
+                backoff.sorted(session.totals.handled_any());
+            }
+            control.record_run(&std::mem::take(&mut session.totals));
+            // Hashing the files left in the folder can take a while, so it runs on its own
+            // thread and control requests are answered meanwhile.
+            let measuring = measurement.as_ref().is_some_and(|measurement| !measurement.is_finished());
+            if !measuring && !measured.is_some_and(|measured| measured.elapsed() < clutter::MEASURE_EVERY) {
+                measured = Some(Instant::now());
+                measurement = Some(spawn_clutter_measurement(root.clone(), options.recursive, config.clone(), session.index.is_some(), control.clone()));
+            }
+            if let Some(settings) = scrub_settings.as_ref().filter(|settings| scrub::due(settings, scrubbed)) {
+                scrubbed = Some(Local::now());
+                if let Err(e) = scrub_destinations(&mut session, settings.files) {
+                    warn!("Failed to scrub placed files: {}", e);
+                }
+            }
+            if !safe {
+                clean_destinations(&config, &root, session.index.as_ref(), session.notifier.as_mut(), false);
+            }
+            if !safe && !advanced.is_some_and(|advanced| advanced.elapsed() < lifecycle::ADVANCE_EVERY) {
+                advanced = Some(Instant::now());
+                advance_lifecycles(&config, &root, session.index.as_ref(), false);
+            }
+            if let Some(learner) = session.learner.as_mut() {
+                propose_rules(learner, &config.rules, session.notifier.as_mut());
+            }
+            if let Some(notifier) = session.notifier.as_mut() {
+                notifier.flush_if_due(session.user_active);
+            }
+        }
+        // Sorts held back by quiet hours run as soon as they end.
+        let (wait, on_change) = match (quiet, timing) {
+            // The backlog after downtime is sorted batch after batch, without waiting.
+            (None, _) if session.backlog.is_some() && !control.paused() => (Duration::ZERO, false),
+            (Some(hours), _) => (hours.remaining(Local::now().time()), false),
+            // Without a watcher, quiet folders are scanned less and less often.
+            (None, Timing::Interval { every, .. }) => match backoff.as_ref().filter(|_| !watcher.is_active()) {
+                Some(backoff) => {
+                    debug!("Next scan in {}s", backoff.current().as_secs());
+                    (backoff.current(), true)
+                }
+                None => (*every, true),
+            },
+            (None, Timing::Schedule(schedule)) => (schedule.until_next(), false),
+        };
+        let deadline = Instant::now() + wait;
+        // Locked files are tried again while waiting, each as soon as its delay is up, unless
+        // a pause or quiet hours hold sorting back.
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let held = held_back(&control, &config);
+            let retry = session.retries.as_ref().and_then(RetryQueue::until_next).filter(|until| *until < remaining && !held);
+            let Some(until) = retry else {
+                wait_for_next_run(&mut watcher, &control, remaining, on_change);
+                break;
+            };
+            if !wait_for_next_run(&mut watcher, &control, until, on_change) {
+                break;
+            }
+            // Held back while waiting, the retries wait for the rest of the interval too.
+            if held_back(&control, &config) {
+                continue;
+            }
+            let Some(sorting) = control.start_sorting() else { continue };
+            let sorting = (sorting, sort_lock(&root));
+            let retrying = SortOptions { retry_only: true, ..options.clone() };
+            if let Err(e) = sort_files(directory, &retrying, &mut session, config.clone()) {
+                error!("Daemon error: {}", e);
+                control.record_error(e.to_string());
+            }
+            drop(sorting);
+            control.record_run(&std::mem::take(&mut session.totals));
+        }
+    }
     
-    match &cli.command {
-        Commands::Sort { path } => {
-            if let Err(e) = sort_files(path) {
-                eprintln!("Error sorting files: {}", e);
+    info!("Shutting down");
+    if let Some(index) = session.index.as_ref() {
+        if let Err(e) = index.flush() {
+            warn!("Failed to sign the move index: {}", e);
+        }
+    }
+    if let Some(notifier) = session.notifier.as_mut() {
+        notifier.flush();
+    }
+    if let Err(e) = CrashLog::load().stop(&root) {
+        warn!("Failed to note that the daemon shut down: {}", e);
+    }
+}
+
+/// Whether the daemon is paused or in its quiet hours, so nothing is sorted right now.
+fn held_back(control: &Control, config: &RulesConfig) -> bool {
+    control.paused() || config.quiet_hours.is_some_and(|hours| hours.contains(Local::now().time()))
+}
+
+/// Shows whether the daemon sorting `root` is in safe mode, and with `enforce` takes it out
+/// of it; a running daemon notices before its next sort.
+fn resume(root: &Path, enforce: bool) -> std::io::Result<()> {
+    let mut crashes = CrashLog::load();
+    let Some(since) = crashes.folder(root).and_then(|state| state.safe_since()) else {
+        println!("The daemon for {} is not in safe mode", root.display());
+        return Ok(());
+    };
+    if !enforce {
+        println!(
+            "The daemon for {} is in safe mode since {}, after repeated crashes; run `resume --enforce` to let it place files again",
+            root.display(),
+            since.format("%Y-%m-%d %H:%M")
+        );
+        return Ok(());
+    }
+    crashes.enforce(root)?;
+    println!("The daemon for {} places files again", root.display());
+    Ok(())
+}
+
+/// Compares the folder and the move index with how they were when the daemon last sorted,
+/// records the time it was stopped in the index, and queues the files that appeared meanwhile
+/// for the first sorts.
+fn reconcile_downtime(session: &mut Session, root: &Path, recursive: bool, config: &RulesConfig) {
+    let Some(index) = session.index.as_ref() else { return };
+    let settings = settings::load_settings().reconcile;
+    let since = match reconcile::stopped_since(index, root, settings.min_gap) {
+        Ok(Some(since)) => since,
+        Ok(None) => return,
+        Err(e) => {
+            warn!("Failed to look up when the daemon last sorted {}: {}", root.display(), e);
+            return;
+        }
+    };
+    let protected: Vec<PathBuf> = protected_folders(config, root).into_iter().map(|guarded| guarded.folder).collect();
+    let downtime = scan::collect_files(root, recursive, &excluded_folders(config, root), config.repositories, scans_links(config), None)
+        .map_err(|e| e.to_string())
+        .and_then(|files| reconcile::compare(index, root, since, files, &protected).map_err(|e| e.to_string()));
+    let downtime = match downtime {
+        Ok(downtime) => downtime,
+        Err(e) => {
+            warn!("Failed to compare {} with the move index after downtime: {}", root.display(), e);
+            return;
+        }
+    };
+    for path in &downtime.vanished {
+        warn!(file = ?path, "Placed file vanished while the daemon was stopped");
+        if let Err(e) = index.record("deleted", None, path, path) {
+            warn!("Failed to record the vanished file in the index: {}", e);
+        }
+    }
+    if let Err(e) = index.record("downtime", None, root, root) {
+        warn!("Failed to record the downtime in the index: {}", e);
+    }
+    info!(
+        "Stopped since {}: {} files appeared and {} placed files vanished meanwhile",
+        downtime.since.format("%Y-%m-%d %H:%M"),
+        downtime.appeared.len(),
+        downtime.vanished.len()
+    );
+    if !downtime.appeared.is_empty() {
+        session.backlog = Some(Backlog::new(downtime.appeared, settings.batch));
+    }
+}
+
+/// Measures the clutter of `root` on a thread of its own and hands the trend to `control`.
+/// The thread opens the index itself, as a connection can't be shared with the daemon loop.
+fn spawn_clutter_measurement(root: PathBuf, recursive: bool, config: RulesConfig, indexed: bool, control: Arc<Control>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let index = match indexed.then(MoveIndex::open).transpose() {
+            Ok(index) => index,
+            Err(e) => {
+                warn!("Failed to open the move index to record the clutter of {}: {}", root.display(), e);
+                None
             }
+        };
+        match measure_clutter(&root, recursive, &config, index.as_ref()) {
+            Ok(trend) => control.record_clutter(trend),
+            Err(e) => warn!("Failed to measure the clutter of {}: {}", root.display(), e),
         }
-        Commands::Daemon { path, interval } => {
-            run_daemon(path, *interval);
+    })
+}
+
+/// Measures the files left in the watched folder and keeps the result in the index, for
+/// the trend.
+fn measure_clutter(root: &Path, recursive: bool, config: &RulesConfig, index: Option<&MoveIndex>) -> std::io::Result<clutter::Trend> {
+    let files = scan::collect_files(root, recursive, &excluded_folders(config, root), config.repositories, false, None)?;
+    let current = clutter::measure(root, &files, |file| {
+        let mut texts = content::Texts::new(file);
+        config.rules.iter().any(|rule| rule.matches_in(&mut texts))
+    });
+    let history = match index {
+        Some(index) => {
+            let since = Local::now() - chrono::Duration::days(clutter::HISTORY_DAYS + 1);
+            index
+                .record_clutter(&current)
+                .and_then(|_| index.clutter(&current.folder, since))
+                .map_err(std::io::Error::other)?
         }
-        Commands::Install { path, interval } => {
-            install_service(path, *interval);
+        None => Vec::new(),
+    };
+    Ok(clutter::Trend::new(current, history))
+}
+
+/// Remembers where the files that left `root` since the last sort went, unless a sort
+/// moved them.
+fn learn_from(learner: &Learner, removed: &[PathBuf], root: &Path, index: Option<&MoveIndex>) {
+    // Without the index, files sorted away can't be told from those moved by hand.
+    let Some(index) = index else { return };
+    let by_hand: Vec<PathBuf> = removed.iter().filter(|file| !index.moved_from(file).unwrap_or(true)).cloned().collect();
+    match learner.observe(&by_hand, root) {
+        Ok(0) => {}
+        Ok(learned) => debug!("Learned from {} files moved by hand", learned),
+        Err(e) => warn!("Failed to learn from files moved by hand: {}", e),
+    }
+}
+
+/// Logs the rules learned from files moved by hand and tells the user about them, now and then.
+fn propose_rules(learner: &mut Learner, rules: &[Rule], notifier: Option<&mut Notifier>) {
+    match learner.due_proposals(rules) {
+        Ok(proposals) if proposals.is_empty() => {}
+        Ok(proposals) => {
+            let learned = match proposals.len() {
+                1 => "a rule".to_string(),
+                count => format!("{} rules", count),
+            };
+            if let Some(notifier) = notifier {
+                notifier.alert(format!("Learned {} from files you moved; adopt with `Organizer learn`", learned));
+            }
         }
+        Err(e) => warn!("Failed to propose learned rules: {}", e),
     }
 }
 
-fn sort_files(directory: &str) -> std::io::Result<()> {
-    let path = Path::new(directory);
-    if !path.is_dir() {
-        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Not a directory"));
+/// Cleans up the files of destinations with `retention` that were listed at the last
+/// cleanup, once a day or right away with `now`, and lists those the next cleanup takes.
+fn clean_destinations(config: &RulesConfig, root: &Path, index: Option<&MoveIndex>, mut notifier: Option<&mut Notifier>, now: bool) {
+    let mut retention = Retention::load();
+    let retained = config.actions.iter().filter(|(_, actions)| actions.retention.is_some());
+    if retained.clone().next().is_none() || !(now || retention.due()) {
+        return;
     }
-    
-    let rules = load_rules().unwrap_or_else(|| define_default_rules());
-    let lua = Lua::new();
-    
-    for entry in fs::read_dir(path)? {
-        let entry = entry?;
-        let file_type = entry.file_type()?;
-        if file_type.is_file() {
-            let file_path = entry.path();
-            if let Some(destination) = apply_rules(&file_path, &rules, &lua) {
-                let dest_path = Path::new(directory).join(destination);
-                fs::create_dir_all(&dest_path)?;
-                fs::rename(&file_path, dest_path.join(file_path.file_name().unwrap()))?;
-                println!("Moved {:?} to {:?}", file_path, dest_path);
+    // Only files the index saw a rule place are cleaned up, never others in the folder.
+    let Some(index) = index else {
+        warn!("Destinations are not cleaned up without the move index");
+        return;
+    };
+    let placements = match index.placed_files() {
+        Ok(placements) => placements,
+        Err(e) => {
+            warn!("Failed to read the move index, destinations are not cleaned up: {}", e);
+            return;
+        }
+    };
+    let limited: Vec<Limited> = retained
+        .filter_map(|(destination, actions)| {
+            let action = actions.retention.clone()?;
+            let Some(folder) = templates::managed_folder(root, destination) else {
+                warn!("Not cleaning up {}, as it doesn't start with a folder of its own below {}", destination, root.display());
+                return None;
+            };
+            let rules = placing_rules(config, destination);
+            let placed = placements
+                .iter()
+                .filter(|placement| placement.rule.as_deref().is_some_and(|rule| rules.contains(rule)))
+                .filter_map(|placement| fs::canonicalize(&placement.path).ok())
+                .collect();
+            Some(Limited {
+                destination: destination.clone(),
+                folder,
+                archive: action.archive_to.as_deref().map(|archive| root.join(paths::resolve_path(archive))),
+                action,
+                placed,
+            })
+        })
+        .collect();
+    if limited.is_empty() {
+        return;
+    }
+    let index = Some(index);
+    let cleanup = retention.run(&limited, &config.transfers.protection);
+    for (file, position) in &cleanup.due {
+        match retention::clean(file, &limited[*position], &config.transfers) {
+            Ok(Cleaned::Archived(archived, checksum)) => {
+                info!(file = ?file, archive = ?archived, "Archived file past its destination's retention");
+                if let Some(Err(e)) = index.map(|index| index.record_placed("archive", None, file, &archived, checksum.as_deref())) {
+                    warn!("Failed to record archived file in index: {}", e);
+                }
+            }
+            Ok(Cleaned::Trashed(trashed)) => {
+                info!(file = ?file, "Put file past its destination's retention in the trash");
+                record_trashed(index, Some(trashed));
             }
+            Ok(Cleaned::Removed) => info!(file = ?file, "Removed file past its destination's retention"),
+            Err(e) => warn!(file = ?file, "Failed to clean up file: {}", e),
         }
     }
-    
+    let next = retention.next_run().format("%Y-%m-%d %H:%M");
+    for (position, destination) in limited.iter().enumerate() {
+        let listed: Vec<&PathBuf> = cleanup.listed.iter().filter(|(_, listed)| *listed == position).map(|(file, _)| file).collect();
+        if listed.is_empty() {
+            continue;
+        }
+        for file in &listed {
+            info!(file = ?file, "File is past the retention of {} and goes at the next cleanup, on {}", destination.destination, next);
+        }
+        let message = format!(
+            "{} files in {} will be {} on {}; see `Organizer retention` to keep some",
+            listed.len(),
+            destination.destination,
+            if destination.archive.is_some() { "archived" } else { "deleted" },
+            next
+        );
+        if let Some(notifier) = notifier.as_deref_mut() {
+            notifier.alert(message);
+        }
+    }
+    if let Err(e) = retention.save() {
+        warn!("Failed to remember the files listed for the next cleanup: {}", e);
+    }
+}
+
+/// The rules that place files in `destination`, by the name the index records them with.
+fn placing_rules<'a>(config: &'a RulesConfig, destination: &str) -> HashSet<&'a str> {
+    let mut rules: HashSet<&str> = config
+        .rules
+        .iter()
+        .filter(|rule| rule.destination == destination)
+        .map(|rule| rule.pattern.as_str())
+        .collect();
+    if config.unmatched.folder.as_deref() == Some(destination) {
+        rules.insert(UNMATCHED_RULE);
+    }
+    rules
+}
+
+/// Takes the files of destinations with a `lifecycle` one stage further where they are old
+/// enough, recording where they went in the index.
+fn advance_lifecycles(config: &RulesConfig, root: &Path, index: Option<&MoveIndex>, dry_run: bool) {
+    let others = excluded_folders(config, root);
+    for (destination, actions) in config.actions.iter().filter(|(_, actions)| !actions.lifecycle.is_empty()) {
+        let steps = match lifecycle::advance(root, destination, &actions.lifecycle, &others, &config.templates, &config.transfers, dry_run) {
+            Ok(steps) => steps,
+            Err(e) => {
+                warn!("Not taking the files of {} further: {}", destination, e);
+                continue;
+            }
+        };
+        for (file, step) in steps {
+            let (action, target, checksum) = match step {
+                Ok(Step::Moved(target, checksum)) => ("lifecycle", target, checksum),
+                Ok(Step::Compressed(archive)) => ("compress", archive, None),
+                Ok(Step::Deleted) => ("delete", file.clone(), None),
+                Ok(Step::Trashed(trashed)) => ("trash", trashed, None),
+                Err(e) => {
+                    warn!(file = ?file, "Failed to take file to the next stage of {}: {}", destination, e);
+                    continue;
+                }
+            };
+            if dry_run {
+                match action {
+                    "lifecycle" => println!("Would move {} -> {}", file.display(), target.display()),
+                    "compress" => println!("Would compress {} into {}", file.display(), target.display()),
+                    _ => println!("Would delete {}", file.display()),
+                }
+                continue;
+            }
+            info!(file = ?file, destination = ?target, "Took file to the next stage of {}", destination);
+            if let Some(Err(e)) = index.map(|index| index.record_placed(action, None, &file, &target, checksum.as_deref())) {
+                warn!("Failed to record the file's next stage in index: {}", e);
+            }
+        }
+    }
+}
+
+/// Exempts files from cleanups or lets them be cleaned up again, cleans up right away when
+/// the sorted folder is given, and lists what the next cleanup takes.
+fn manage_retention(root: Option<&Path>, exempt: &[PathBuf], unexempt: &[PathBuf]) -> std::io::Result<()> {
+    let mut retention = Retention::load();
+    for file in exempt {
+        retention.set_exempt(file, true)?;
+        println!("{} is never cleaned up", file.display());
+    }
+    for file in unexempt {
+        retention.set_exempt(file, false)?;
+        println!("{} can be cleaned up again", file.display());
+    }
+    retention.save()?;
+    if let Some(root) = root {
+        let config = try_load_config()?;
+        clean_destinations(&config, root, open_index().ok().as_ref(), None, true);
+        retention = Retention::load();
+    } else if !exempt.is_empty() || !unexempt.is_empty() {
+        return Ok(());
+    }
+    if retention.pending().is_empty() {
+        println!("No files are waiting to be cleaned up");
+        return Ok(());
+    }
+    let next = retention.next_run().format("%Y-%m-%d %H:%M");
+    println!("Cleaned up on {}, unless exempted with --exempt:", next);
+    let mut by_destination: BTreeMap<&str, Vec<&PathBuf>> = BTreeMap::new();
+    for (file, destination) in retention.pending() {
+        by_destination.entry(destination).or_default().push(file);
+    }
+    for (destination, files) in by_destination {
+        println!("{} ({} files)", destination, files.len());
+        for file in files {
+            println!("  {}", file.display());
+        }
+    }
+    Ok(())
+}
+
+/// Reads up to `files` placed files back, logging and alerting about those that are not
+/// as they were.
+fn scrub_destinations(session: &mut Session, files: usize) -> std::io::Result<scrub::Scrub> {
+    let index = session.index.as_ref().ok_or_else(|| std::io::Error::other("The move index is unavailable"))?;
+    let scrub = scrub::run(index, files).map_err(std::io::Error::other)?;
+    for finding in &scrub.findings {
+        warn!("{}", finding);
+        session.webhook.integrity(Path::new(finding.path()), &finding.to_string());
+    }
+    if let (Some(notifier), false) = (session.notifier.as_mut(), scrub.findings.is_empty()) {
+        notifier.alert(format!("{} of {} placed files checked are corrupted, changed or missing", scrub.findings.len(), scrub.checked));
+    }
+    session.webhook.deliver();
+    info!("Scrubbed {} placed files, {} need attention", scrub.checked, scrub.findings.len());
+    Ok(scrub)
+}
+
+/// Flushes the drive `destination` is on, pausing the daemon sorting `pause` first when
+/// given, so that nothing is written to it afterwards.
+fn flush_destination(destination: &str, pause: Option<&Path>) -> std::io::Result<()> {
+    let path = paths::resolve_path(destination);
+    if let Some(root) = pause {
+        match control::send(root, ControlCommand::Pause) {
+            Ok(response) => {
+                println!("{}", response);
+                wait_for_sort(root);
+            }
+            Err(e) => warn!("Daemon not running or not reachable, nothing to pause: {}", e),
+        }
+    }
+    removable::flush(&path)?;
+    if removable::is_removable(&path) {
+        println!("Flushed {}, the drive can be ejected", path.display());
+    } else {
+        println!("Flushed {}", path.display());
+    }
     Ok(())
 }
 
-fn run_daemon(directory: &str, interval: u64) {
+/// Waits until the daemon sorting `root` has finished the sort it was in when it was paused.
+fn wait_for_sort(root: &Path) {
+    loop {
+        let stats = control::send(root, ControlCommand::Stats).ok().and_then(|stats| serde_json::from_str::<serde_json::Value>(&stats).ok());
+        if !stats.is_some_and(|stats| stats["sorting"] == true) {
+            return;
+        }
+        thread::sleep(CONTROL_POLL);
+    }
+}
+
+/// Replaces the running watcher with `standby`, which already watches the folders, so
+/// changes keep being noticed during the switch. Changes the old watcher still held are
+/// found by the full sort that follows every reload. A standby that fell back to polling
+/// does not replace a watcher that works.
+fn swap_watcher(watcher: &mut Watcher, standby: Watcher) {
+    if standby.is_active() || !watcher.is_active() {
+        *watcher = standby;
+    } else {
+        warn!("Keeping the previous watcher, the new one could not be started; new destinations are still watched");
+    }
+}
+
+/// Checks rules sent to the web interface before they replace `rules.json`.
+fn check_rules(contents: &str) -> std::io::Result<()> {
+    parse_rules(contents).map(|_| ())
+}
+
+/// Compiles the Lua script, so that a reload with a broken script keeps the previous rules
+/// instead of leaving every file to the script unmatched.
+fn check_lua_script(config: &RulesConfig) -> std::io::Result<()> {
+    let Some(script) = &config.lua_script else { return Ok(()) };
+    script_lua(config)?
+        .load(script)
+        .into_function()
+        .map(|_| ())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", LUA_SCRIPT, e)))
+}
+
+/// Waits for the interval, or a change when `on_change` is set, checking for control
+/// commands and shutdown every second. While paused, only a control command ends the wait.
+/// Returns whether the whole `interval` passed, rather than a change or a request ending
+/// the wait early.
+fn wait_for_next_run(watcher: &mut Watcher, control: &Control, interval: Duration, on_change: bool) -> bool {
+    let deadline = Instant::now() + interval;
     loop {
-        if let Err(e) = sort_files(directory) {
-            eprintln!("Daemon error: {}", e);
+        if control.take_sort_now() || shutdown::requested() {
+            return false;
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() && !control.paused() {
+            return true;
+        }
+        let slice = if remaining.is_zero() { CONTROL_POLL } else { remaining.min(CONTROL_POLL) };
+        // Changes are still taken from the watcher when they don't end the wait, so they don't pile up.
+        if watcher.wait(slice) && on_change && !control.paused() {
+            return false;
         }
-        thread::sleep(Duration::from_secs(interval));
     }
 }
 
-fn install_service(directory: &str, interval: u64) {
+fn install_service(directory: &str, interval: u64, profile: Option<&str>) {
     #[cfg(target_os = "linux")]
     {
+        let profile = profile.map(|name| format!(" --profile {}", name)).unwrap_or_default();
         let service_content = format!(
-            "[Unit]\nDescription=File Sorter Daemon\nAfter=network.target\n\n[Service]\nExecStart={} daemon --path {} --interval {}\nRestart=always\nUser={}\nWorkingDirectory={}\n\n[Install]\nWantedBy=default.target\n", 
-            std::env::current_exe().unwrap().to_str().unwrap(),
+            "[Unit]\nDescription=File Sorter Daemon\nAfter=network.target\n\n[Service]\nExecStart={} daemon --path {} --interval {}{}\nRestart=always\nUser={}\nWorkingDirectory={}\n\n[Install]\nWantedBy=default.target\n", 
+            std::env::current_exe().unwrap().display(),
             directory,
             interval,
+            profile,
             whoami::username(),
-            std::env::current_dir().unwrap().to_str().unwrap()
+            std::env::current_dir().unwrap().display()
         );
 
         let service_path = "/etc/systemd/system/file_sorter.service";
@@ -131,59 +5153,496 @@ fn install_service(directory: &str, interval: u64) {
 
     #[cfg(target_os = "windows")]
     {
-        Command::new("schtasks")
-            .args(&[
-                "/Create", "/TN", "FileSorterDaemon", "/SC", "ONSTART", "/RL", "HIGHEST", 
-                "/TR", &format!("{} daemon --path {} --interval {}", 
-                    std::env::current_exe().unwrap().to_str().unwrap(), directory, interval)
-            ])
-            .spawn()
-            .expect("Failed to create scheduled task");
+        let rules = paths::rules_dir().canonicalize().unwrap_or_else(|_| paths::rules_dir());
+        match service::install(directory, interval, profile, &rules) {
+            Ok(()) => println!("Installed and started the {} service", service::SERVICE_NAME),
+            Err(e) => {
+                error!("Failed to install the service: {}", e);
+                std::process::exit(1);
+            }
+        }
     }
 }
 
-fn define_default_rules() -> HashMap<String, String> {
-    let mut rules = HashMap::new();
-    rules.insert(".txt".to_string(), "TextFiles".to_string());
-    rules.insert(".jpg".to_string(), "Images".to_string());
-    rules.insert(".png".to_string(), "Images".to_string());
-    rules.insert(".rs".to_string(), "RustCode".to_string());
-    rules
+fn define_default_rules() -> Vec<Rule> {
+    vec![
+        Rule::new(".txt", "TextFiles"),
+        Rule::new(".jpg", "Images"),
+        Rule::new(".png", "Images"),
+        Rule::new(".rs", "RustCode"),
+    ]
+}
+
+fn load_config() -> RulesConfig {
+    try_load_config().unwrap_or_else(|e| {
+        warn!("Using the default rules: {}", e);
+        RulesConfig {
+            lua_script: load_lua_script().ok().flatten(),
+            ..default_config()
+        }
+    })
+}
+
+/// Loads `rules.json`, falling back to the default rules only when there is none.
+fn try_load_config() -> std::io::Result<RulesConfig> {
+    let mut config = load_rules()?.unwrap_or_else(default_config);
+    config.lua_script = load_lua_script()?;
+    if let Some(script) = &config.lua_script {
+        let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::PermissionDenied, message);
+        let declared = capabilities::declared(script).map_err(|e| invalid(format!("{}: {}", LUA_SCRIPT, e)))?;
+        capabilities::check(&rules_path(LUA_SCRIPT), script.as_bytes(), &declared, &config.grants).map_err(invalid)?;
+        config.lua_capabilities = declared;
+    }
+    config.loaded_plugins = Plugins::load(&config.plugins, &config.grants)?;
+    Ok(config)
+}
+
+fn default_config() -> RulesConfig {
+    RulesConfig {
+        rules: define_default_rules(),
+        ..Default::default()
+    }
+}
+
+/// The destinations with a `protect` action, for the guard.
+fn protected_folders(config: &RulesConfig, root: &Path) -> Vec<Guarded> {
+    config
+        .actions
+        .iter()
+        .filter_map(|(destination, actions)| Guarded::new(root, destination, actions.protect.as_ref()?))
+        .collect()
+}
+
+/// Whether symlinks are handed to the workers at all; they are left out of sorts unless
+/// a policy for them is set.
+fn scans_links(config: &RulesConfig) -> bool {
+    config.symlinks != SymlinkPolicy::Ignore
+        || config.broken_symlinks != BrokenSymlinkPolicy::Ignore
+        || config.rules.iter().any(|rule| rule.symlinks.is_some() || rule.broken_symlinks.is_some())
+}
+
+/// Folders that recursive scans skip because files are filed into them.
+fn excluded_folders(config: &RulesConfig, root: &Path) -> Vec<PathBuf> {
+    let staging = config.actions.values().filter_map(|actions| actions.stage.as_ref()).map(|stage| &stage.folder);
+    let shortcuts = config.actions.values().filter_map(|actions| actions.shortcuts.as_ref()).flat_map(|shortcuts| &shortcuts.folders);
+    let stages = config.actions.values().flat_map(|actions| &actions.lifecycle).filter_map(|stage| stage.move_to.as_ref());
+    config
+        .rules
+        .iter()
+        // `.` leaves files where they are rather than filing them in the sorted folder.
+        .filter(|rule| rule.exec.is_none() && rule.destination != ".")
+        .map(|rule| &rule.destination)
+        .chain(config.directories.iter().map(|rule| &rule.destination))
+        .chain(staging)
+        .chain(shortcuts)
+        .chain(stages)
+        .chain(&config.unmatched.folder)
+        .map(String::as_str)
+        .chain(config.rules.iter().any(|rule| rule.verify_type || rule.scan).then(|| quarantine_folder(config)))
+        .filter_map(|destination| {
+            // Only the part before the first placeholder is known in advance.
+            let fixed = match destination.split_once('{') {
+                Some((prefix, _)) => prefix.rsplit_once('/').map(|(folder, _)| folder)?,
+                None => destination,
+            };
+            (!fixed.is_empty()).then(|| root.join(paths::resolve_path(fixed)))
+        })
+        .collect()
 }
 
-fn load_rules() -> Option<HashMap<String, String>> {
-    let config_path = Path::new("rules.json");
+fn load_rules() -> std::io::Result<Option<RulesConfig>> {
+    let config_path = rules_path(RULES_FILE);
     if config_path.exists() {
-        let mut file = File::open(config_path).ok()?;
+        let mut file = File::open(&config_path)?;
         let mut contents = String::new();
-        file.read_to_string(&mut contents).ok()?;
-        let config: RulesConfig = serde_json::from_str(&contents).ok()?;
-        Some(config.rules)
+        file.read_to_string(&mut contents)?;
+        parse_rules(&contents).map(Some)
+    } else if overlay::sets_rules() {
+        parse_rules("{}").map(Some)
     } else {
-        None
+        Ok(None)
     }
 }
 
-fn apply_rules(file_path: &PathBuf, rules: &HashMap<String, String>, lua: &Lua) -> Option<String> {
-    if let Some(extension) = file_path.extension() {
-        if let Some(extension_str) = extension.to_str() {
-            if let Some(dest) = rules.get(&format!(".{}", extension_str)) {
-                return Some(dest.clone());
-            }
+fn parse_rules(contents: &str) -> std::io::Result<RulesConfig> {
+    let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Invalid rules.json: {}", message));
+    let mut contents: serde_json::Value = serde_json::from_str(contents).map_err(|e| invalid(e.to_string()))?;
+    overlay::apply_rules(&mut contents).map_err(invalid)?;
+    let explicit = contents.get("rules").is_some();
+    let mut config: RulesConfig = serde_json::from_value(contents).map_err(|e| invalid(e.to_string()))?;
+    if !config.layout.is_empty() {
+        // A layout replaces the default rules rather than adding to them.
+        if !explicit {
+            config.rules.clear();
         }
+        config.rules.extend(categories::layout_rules(&config.layout).map_err(invalid)?);
     }
-    
-    let lua_script_path = Path::new("sort_rules.lua");
-    if lua_script_path.exists() {
-        let mut file = File::open(lua_script_path).ok()?;
-        let mut script = String::new();
-        file.read_to_string(&mut script).ok()?;
-        
-        if let Ok(lua_func) = lua.load(&script).into_function() {
-            if let Ok(dest) = lua_func.call::<_, Option<String>>(file_path.to_str().unwrap()) {
-                return dest;
+    categories::localize(&mut config.rules, &config.category_names);
+    let imported = pipelines::import(&mut config.pipelines, &paths::rules_dir()).map_err(invalid)?;
+    if !imported.is_empty() {
+        config.rules.extend(imported);
+        // Stable, as when the rules are read, so imported rules come after others of their priority.
+        config.rules.sort_by_key(|rule| std::cmp::Reverse(rule.priority));
+    }
+    config.file_names.scripts = config.scripts;
+    adapt_destinations(&mut config);
+    for rename in config.actions.values_mut().filter_map(|actions| actions.rename.as_mut()) {
+        rename.compile().map_err(invalid)?;
+    }
+    safety::validate(&config.protected).map_err(invalid)?;
+    config.temporary.compile().map_err(invalid)?;
+    for (destination, actions) in &config.actions {
+        lifecycle::validate(destination, &actions.lifecycle).map_err(invalid)?;
+        let cleaned = actions.retention.is_some() || !actions.lifecycle.is_empty();
+        if cleaned && templates::destination_top(destination).trim_matches(['/', '.']).is_empty() {
+            return Err(invalid(format!(
+                "`retention` and `lifecycle` need a destination that starts with a folder of its own, not '{}'",
+                destination
+            )));
+        }
+    }
+    for rule in &mut config.rules {
+        rule.validate().map_err(invalid)?;
+    }
+    for pattern in &config.name_dates {
+        templates::validate_name_date(pattern).map_err(invalid)?;
+    }
+    config.templates = templates::Settings::new(&config.name_dates, config.scripts);
+    config.transfers = transfer::Settings::new(config.network_share, config.verify_copies, config.trash, &config.protected);
+    for rule in &config.directories {
+        rule.validate().map_err(invalid)?;
+    }
+    pipelines::validate(&config.pipelines, &config.rules).map_err(invalid)?;
+    Ok(config)
+}
+
+/// Writes the folder names of every destination as `file_names` asks, so the folders files
+/// are placed in are known by the same names everywhere.
+fn adapt_destinations(config: &mut RulesConfig) {
+    let settings = config.file_names.clone();
+    let adapt = |destination: &mut String| *destination = filenames::adapt_destination(destination, &settings);
+    config.rules.iter_mut().for_each(|rule| adapt(&mut rule.destination));
+    config.directories.iter_mut().for_each(|rule| adapt(&mut rule.destination));
+    config.unmatched.folder.iter_mut().for_each(adapt);
+    config.quarantine.iter_mut().for_each(adapt);
+    config.actions = std::mem::take(&mut config.actions)
+        .into_iter()
+        .map(|(mut destination, mut actions)| {
+            adapt(&mut destination);
+            actions.stage.iter_mut().for_each(|stage| adapt(&mut stage.folder));
+            actions.shortcuts.iter_mut().flat_map(|shortcuts| &mut shortcuts.folders).for_each(adapt);
+            actions.lifecycle.iter_mut().filter_map(|stage| stage.move_to.as_mut()).for_each(adapt);
+            (destination, actions)
+        })
+        .collect();
+}
+
+/// Adds the rules of "always" answers to `rules.json`, ahead of the rules already there.
+fn add_rules(added: &[Rule]) -> std::io::Result<()> {
+    use serde_json::Value;
+    let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+    let config_path = rules_path(RULES_FILE);
+    let mut config: Value = if config_path.exists() {
+        serde_json::from_str(&fs::read_to_string(&config_path)?).map_err(|e| invalid(format!("Invalid rules.json: {}", e)))?
+    } else {
+        serde_json::json!({})
+    };
+    let object = config.as_object_mut().ok_or_else(|| invalid("rules.json must hold an object".to_string()))?;
+    let existing = match object.remove("rules") {
+        Some(rules) => rules,
+        None => serde_json::to_value(define_default_rules())?,
+    };
+    let mut rules = match existing {
+        Value::Array(rules) => rules,
+        // The map form has no order to put the new rules first in, so it becomes a list.
+        Value::Object(map) => map
+            .into_iter()
+            .map(|(pattern, destination)| serde_json::json!({ "match": pattern, "destination": destination }))
+            .collect(),
+        _ => return Err(invalid("`rules` in rules.json must be a list or a map".to_string())),
+    };
+    // Rules with equal priority keep their order, so the new rules win over the old ones.
+    let priority = rules.iter().filter_map(|rule| rule.get("priority")?.as_i64()).max().unwrap_or(0);
+    let added = added
+        .iter()
+        .map(|rule| {
+            let mut rule = rule.clone();
+            rule.priority = priority as i32;
+            serde_json::to_value(rule)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    rules.splice(0..0, added);
+    object.insert("rules".to_string(), Value::Array(rules));
+    fs::write(&config_path, serde_json::to_string_pretty(&config)?)
+}
+
+fn load_lua_script() -> std::io::Result<Option<String>> {
+    match fs::read_to_string(rules_path(LUA_SCRIPT)) {
+        Ok(script) => Ok(Some(script)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns the rules that apply to the file in order, ending with the first one
+/// that does not `continue`. The plugins and then the Lua script are only asked when no
+/// such rule matched.
+fn apply_rules(file_path: &PathBuf, rules: &[Rule], lua: &Lua, config: &RulesConfig) -> Vec<RuleMatch> {
+    let (mut matches, settled) = decide_rules(file_path, rules, config);
+    if settled {
+        return matches;
+    }
+    if let Some((plugin, decided)) = config.loaded_plugins.decide(file_path) {
+        matches.push(plugin_match(plugin, decided));
+        return matches;
+    }
+    if let Some(script) = config.lua_script.as_deref() {
+        matches.extend(timing::time(LUA_SCRIPT, Stage::Lua, || apply_lua_script(file_path, lua, script)));
+    }
+    matches
+}
+
+/// What `rules` alone decide for `file_path`, without plugins or the Lua script, and whether
+/// a rule settled it.
+fn decide_rules(file_path: &PathBuf, rules: &[Rule], config: &RulesConfig) -> (Vec<RuleMatch>, bool) {
+    let mut texts = content::Texts::new(file_path);
+    let decided = decide::decide(
+        rules,
+        |rule| rule.continue_matching,
+        |rule| {
+            if !rule.matches_in(&mut texts) {
+                return Tried::Passed;
             }
+            let Some(command) = &rule.exec else {
+                return Tried::Decided {
+                    destination: rule.destination.clone(),
+                    copy: rule.copy,
+                };
+            };
+            match timing::time(&rule.label(), Stage::Exec, || exec::decide(command, rule.environment.as_ref(), file_path, &config.exec)) {
+                Ok(Some(decision)) => Tried::Decided {
+                    destination: decision.destination,
+                    copy: rule.copy || decision.copy,
+                },
+                Ok(None) => Tried::Passed,
+                Err(e) => {
+                    warn!(file = ?file_path, "Rule {} passed, its command failed: {}", rule.pattern, e);
+                    Tried::Passed
+                }
+            }
+        },
+    );
+    let matches = decided
+        .decisions
+        .into_iter()
+        .map(|decision| RuleMatch::from_rule(decision.rule, decision.destination, decision.copy))
+        .collect();
+    (matches, decided.settled)
+}
+
+fn plugin_match(plugin: String, decided: PluginMatch) -> RuleMatch {
+    RuleMatch::decided(plugin, decided.destination, decided.copy)
+}
+
+fn apply_lua_script(file_path: &PathBuf, lua: &Lua, script: &str) -> Option<RuleMatch> {
+    run_lua_script(file_path, lua, script).ok()?.map(|destination| RuleMatch::decided(LUA_SCRIPT.to_string(), destination, false))
+}
+
+/// A Lua state for the script, with only the libraries and functions its capabilities allow.
+fn script_lua(config: &RulesConfig) -> std::io::Result<Lua> {
+    capabilities::sandbox(&config.lua_capabilities).map_err(|e| std::io::Error::other(format!("Failed to create a Lua state: {}", e)))
+}
+
+/// Returns the destination chosen by the Lua script, or why the script could not be run.
+fn run_lua_script(file_path: &PathBuf, lua: &Lua, script: &str) -> Result<Option<String>, String> {
+    let lua_func = lua.load(script).into_function().map_err(|e| e.to_string())?;
+    capabilities::set_current_file(lua, &file_path.to_string_lossy()).map_err(|e| e.to_string())?;
+    let context = timing::time(LUA_SCRIPT, Stage::Metadata, || lua_context(file_path, lua));
+    lua_func
+        .call::<_, Option<String>>((file_path.to_string_lossy().into_owned(), context))
+        .map_err(|e| e.to_string())
+}
+
+/// Builds the metadata table passed to Lua scripts as their second argument.
+fn lua_context<'lua>(file_path: &Path, lua: &'lua Lua) -> Option<mlua::Table<'lua>> {
+    let context = lua.create_table().ok()?;
+    if let Some(tags) = media::read_audio_tags(file_path) {
+        let audio = lua.create_table().ok()?;
+        audio.set("artist", tags.artist).ok()?;
+        audio.set("album", tags.album).ok()?;
+        audio.set("title", tags.title).ok()?;
+        audio.set("genre", tags.genre).ok()?;
+        audio.set("year", tags.year).ok()?;
+        context.set("audio", audio).ok()?;
+    }
+    Some(context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+    use organizer_engine::{FileSystem, MemoryFileSystem};
+
+    #[test]
+    fn moves_files_through_the_file_system_of_the_sort() {
+        let fs = MemoryFileSystem::new();
+        fs.add_file("/inbox/report.pdf", "report").unwrap();
+        let target = Path::new("/inbox/Documents/2024/report.pdf");
+        let placed = move_file(&fs, target, &PathBuf::from("/inbox/report.pdf"), "Documents/{year}", &RulesConfig::default()).unwrap().file;
+        assert_eq!(placed, target);
+        assert_eq!(fs.files(), [target.to_path_buf()]);
+    }
+
+    fn duplicate(fs: &MemoryFileSystem) -> (&'static Path, &'static Path) {
+        fs.add_file("/inbox/Documents/report.pdf", "report").unwrap();
+        fs.add_file("/inbox/report copy.pdf", "report").unwrap();
+        (Path::new("/inbox/Documents/report.pdf"), Path::new("/inbox/Documents/report copy.pdf"))
+    }
+
+    #[test]
+    fn links_duplicates_under_the_name_reserved_for_them() {
+        let fs = MemoryFileSystem::new();
+        let (existing, link) = duplicate(&fs);
+        link_duplicate(&fs, existing, link, &Mutex::new(HashSet::new())).unwrap();
+        assert!(fs.symlink_metadata(link).unwrap().is_same_file(&fs.symlink_metadata(existing).unwrap()));
+    }
+
+    #[test]
+    fn leaves_duplicates_already_linked_under_their_name() {
+        let fs = MemoryFileSystem::new();
+        let (existing, _) = duplicate(&fs);
+        link_duplicate(&fs, existing, existing, &Mutex::new(HashSet::new())).unwrap();
+        assert_eq!(fs.files().len(), 2);
+    }
+
+    #[test]
+    fn links_duplicates_beside_other_files_with_their_name() {
+        let fs = MemoryFileSystem::new();
+        let (existing, link) = duplicate(&fs);
+        fs.add_file(link, "other").unwrap();
+        fs.add_file("/inbox/Documents/report copy (1).pdf", "other").unwrap();
+        let reserved = Mutex::new(HashSet::from([PathBuf::from("/inbox/Documents/report copy (2).pdf")]));
+        link_duplicate(&fs, existing, link, &reserved).unwrap();
+        let numbered = Path::new("/inbox/Documents/report copy (3).pdf");
+        assert!(fs.symlink_metadata(numbered).unwrap().is_same_file(&fs.symlink_metadata(existing).unwrap()));
+        assert_eq!(fs.contents(link), Some(b"other".to_vec()));
+        assert!(reserved.lock().unwrap().contains(numbered));
+    }
+
+    fn renaming(template: &str, pattern: Option<&str>) -> RuleActions {
+        let mut rename: naming::RenameAction = serde_json::from_value(serde_json::json!({ "template": template, "pattern": pattern })).unwrap();
+        rename.compile().unwrap();
+        RuleActions {
+            rename: Some(rename),
+            ..RuleActions::default()
         }
     }
-    None
-}
\ No newline at end of file
+
+    /// Where `target_path` puts `name` from the inbox of `folder`.
+    fn renamed(folder: &Path, name: &str, actions: &RuleActions, sequences: Option<&Mutex<Sequences>>, reserved: &Mutex<HashSet<PathBuf>>, dry_run: bool) -> PathBuf {
+        let file = folder.join("Inbox").join(name);
+        target_path(&file, folder, Some(actions), &templates::Settings::default(), &FileNameSettings::default(), sequences, reserved, dry_run)
+    }
+
+    #[test]
+    fn pads_counters_past_the_names_taken() {
+        let folder = folder("counter");
+        add(&folder, "scan-0001.pdf");
+        let (actions, reserved) = (renaming("scan-{counter:04}.{ext}", None), Mutex::new(HashSet::new()));
+        assert_eq!(renamed(&folder, "a.pdf", &actions, None, &reserved, false), folder.join("scan-0002.pdf"));
+        assert_eq!(renamed(&folder, "b.pdf", &actions, None, &reserved, false), folder.join("scan-0003.pdf"));
+    }
+
+    #[test]
+    fn fills_in_the_groups_of_the_pattern() {
+        let folder = folder("groups");
+        let actions = renaming("{place}-{1}.{ext}", Some(r"^IMG_(\d+)_(?P<place>\w+)$"));
+        let reserved = Mutex::new(HashSet::new());
+        assert_eq!(renamed(&folder, "IMG_0042_paris.jpg", &actions, None, &reserved, false), folder.join("paris-0042.jpg"));
+        assert_eq!(renamed(&folder, "holiday.jpg", &actions, None, &reserved, false), folder.join("holiday.jpg"));
+    }
+
+    #[test]
+    fn reserves_the_names_of_a_dry_run() {
+        let folder = folder("dry-run");
+        let (actions, reserved) = (renaming("report.{ext}", None), Mutex::new(HashSet::new()));
+        assert_eq!(renamed(&folder, "a.pdf", &actions, None, &reserved, true), folder.join("report.pdf"));
+        assert_eq!(renamed(&folder, "b.pdf", &actions, None, &reserved, true), folder.join("report (1).pdf"));
+        let counted = renaming("{counter}.{ext}", None);
+        assert_eq!(renamed(&folder, "c.pdf", &counted, None, &reserved, true), folder.join("001.pdf"));
+        assert_eq!(renamed(&folder, "d.pdf", &counted, None, &reserved, true), folder.join("002.pdf"));
+    }
+
+    #[test]
+    fn numbers_sequences_across_runs_but_not_dry_ones() {
+        let folder = folder("sequence");
+        let sequences = Some(Mutex::new(Sequences::in_memory().unwrap()));
+        let actions = renaming("{seq:02}.{ext}", None);
+        assert_eq!(renamed(&folder, "a.pdf", &actions, sequences.as_ref(), &Mutex::new(HashSet::new()), false), folder.join("01.pdf"));
+        let planned = Mutex::new(HashSet::new());
+        assert_eq!(renamed(&folder, "b.pdf", &actions, sequences.as_ref(), &planned, true), folder.join("02.pdf"));
+        assert_eq!(renamed(&folder, "c.pdf", &actions, sequences.as_ref(), &planned, true), folder.join("03.pdf"));
+        add(&folder, "02.pdf");
+        assert_eq!(renamed(&folder, "b.pdf", &actions, sequences.as_ref(), &Mutex::new(HashSet::new()), false), folder.join("03.pdf"));
+    }
+
+    #[test]
+    fn keeps_the_sequence_number_of_files_renamed_in_place() {
+        let folder = folder("sequence-kept");
+        let file = add(&folder, "07.pdf");
+        let sequences = Some(Mutex::new(Sequences::in_memory().unwrap()));
+        let actions = renaming("{seq:02}.{ext}", None);
+        let target = target_path(&file, &folder, Some(&actions), &templates::Settings::default(), &FileNameSettings::default(), sequences.as_ref(), &Mutex::new(HashSet::new()), false);
+        assert_eq!(target, file);
+    }
+
+    fn folder(name: &str) -> TestDir {
+        TestDir::new("main", name)
+    }
+
+    fn add(folder: &Path, name: &str) -> PathBuf {
+        let file = folder.join(name);
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(&file, name).unwrap();
+        file
+    }
+
+    #[test]
+    fn reads_file_lists_by_line_or_by_null() {
+        let folder = folder("file-list");
+        let lines = folder.join("lines.txt");
+        fs::write(&lines, "a.pdf\r\n\nb c.pdf\n").unwrap();
+        assert_eq!(read_file_list(&lines, false).unwrap(), [PathBuf::from("a.pdf"), PathBuf::from("b c.pdf")]);
+        let nulls = folder.join("nulls.txt");
+        fs::write(&nulls, "a\nb.pdf\0c.pdf\0").unwrap();
+        assert_eq!(read_file_list(&nulls, true).unwrap(), [PathBuf::from("a\nb.pdf"), PathBuf::from("c.pdf")]);
+    }
+
+    #[test]
+    fn sorts_each_listed_file_once() {
+        let root = folder("listed-once");
+        let file = add(&root, "report.pdf");
+        let listed = [file.clone(), root.join("Documents/../report.pdf"), root.join("missing.pdf")];
+        fs::create_dir_all(root.join("Documents")).unwrap();
+        assert_eq!(listed_files(&listed, &root, &[]), [file]);
+    }
+
+    #[test]
+    fn leaves_out_listed_folders_and_files_outside_the_root() {
+        let root = folder("listed-outside");
+        let inside = add(&root, "Inbox/report.pdf");
+        let outside = add(&root, "elsewhere.pdf");
+        let listed = [root.join("Inbox"), inside.clone(), outside, root.join("Inbox/../elsewhere.pdf")];
+        assert_eq!(listed_files(&listed, &root.join("Inbox"), &[]), [inside]);
+    }
+
+    #[test]
+    fn leaves_out_listed_files_already_in_a_destination() {
+        let root = folder("listed-placed");
+        let placed = add(&root, "Documents/report.pdf");
+        let unsorted = add(&root, "notes.pdf");
+        let listed = [placed, unsorted.clone()];
+        assert_eq!(listed_files(&listed, &root, &[root.join("Documents")]), [unsorted]);
+    }
+}
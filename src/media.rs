@@ -0,0 +1,71 @@
+use chrono::NaiveDateTime;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Metadata read from a photo's EXIF block.
+#[derive(Default)]
+pub struct ExifInfo {
+    pub date: Option<NaiveDateTime>,
+    pub camera: Option<String>,
+}
+
+/// Reads EXIF metadata from JPEG, HEIC and TIFF-based RAW files.
+pub fn read_exif(path: &Path) -> Option<ExifInfo> {
+    let file = File::open(path).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()?;
+    let ascii = |tag| {
+        let field = exif.get_field(tag, exif::In::PRIMARY)?;
+        let exif::Value::Ascii(ref values) = field.value else { return None };
+        let value = String::from_utf8_lossy(values.first()?).trim().to_string();
+        (!value.is_empty()).then_some(value)
+    };
+    
+    let date = [exif::Tag::DateTimeOriginal, exif::Tag::DateTimeDigitized, exif::Tag::DateTime]
+        .into_iter()
+        .find_map(|tag| {
+            let date = exif::DateTime::from_ascii(ascii(tag)?.as_bytes()).ok()?;
+            chrono::NaiveDate::from_ymd_opt(date.year as i32, date.month as u32, date.day as u32)?
+                .and_hms_opt(date.hour as u32, date.minute as u32, date.second as u32)
+        });
+    Some(ExifInfo {
+        date,
+        camera: ascii(exif::Tag::Model),
+    })
+}
+
+/// Reads the capture date of a photo from its EXIF metadata.
+pub fn exif_date(path: &Path) -> Option<NaiveDateTime> {
+    read_exif(path)?.date
+}
+
+/// Music metadata read from ID3, Vorbis comment, FLAC and MP4 tags.
+#[derive(Default)]
+pub struct AudioTags {
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub title: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<u32>,
+}
+
+pub fn read_audio_tags(path: &Path) -> Option<AudioTags> {
+    use lofty::{Accessor, TaggedFileExt};
+    
+    let tagged_file = lofty::read_from_path(path).ok()?;
+    let tag = tagged_file.primary_tag().or_else(|| tagged_file.first_tag())?;
+    let text = |value: Option<std::borrow::Cow<str>>| {
+        value
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    Some(AudioTags {
+        artist: text(tag.artist()),
+        album: text(tag.album()),
+        title: text(tag.title()),
+        genre: text(tag.genre()),
+        year: tag.year(),
+    })
+}
@@ -0,0 +1,228 @@
+use crate::report::{Outcome, SkipReason};
+use crate::scan::ScanDelta;
+use crate::timing;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, info};
+
+const SCAN_BUCKETS: &[f64] = &[0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0];
+const MOVE_BUCKETS: &[f64] = &[0.001, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 30.0];
+
+/// Counters and histograms about the daemon's work, in the Prometheus text format.
+pub struct Metrics {
+    state: Mutex<State>,
+}
+
+struct State {
+    scanned: u64,
+    /// Entries added, changed and removed in folders that changed between incremental scans.
+    changes: [u64; 3],
+    moved: BTreeMap<String, u64>,
+    skipped: BTreeMap<(String, SkipReason), u64>,
+    quarantined: BTreeMap<String, u64>,
+    errors: BTreeMap<String, u64>,
+    scan_duration: Histogram,
+    move_duration: Histogram,
+}
+
+struct Histogram {
+    buckets: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(buckets: &'static [f64]) -> Histogram {
+        Histogram {
+            buckets,
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bound, count) in self.buckets.iter().zip(&mut self.counts) {
+            if seconds <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    fn write(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} histogram", name, help, name);
+        for (bound, count) in self.buckets.iter().zip(&self.counts) {
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count);
+        let _ = writeln!(out, "{}_sum {}\n{}_count {}", name, self.sum, name, self.count);
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Metrics> {
+        Arc::new(Metrics {
+            state: Mutex::new(State {
+                scanned: 0,
+                changes: [0; 3],
+                moved: BTreeMap::new(),
+                skipped: BTreeMap::new(),
+                quarantined: BTreeMap::new(),
+                errors: BTreeMap::new(),
+                scan_duration: Histogram::new(SCAN_BUCKETS),
+                move_duration: Histogram::new(MOVE_BUCKETS),
+            }),
+        })
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Records a finished sort of `files` files.
+    pub fn record_scan(&self, files: usize, duration: Duration) {
+        let mut state = self.state();
+        state.scanned += files as u64;
+        state.scan_duration.observe(duration);
+    }
+
+    /// Records what an incremental scan found changed.
+    pub fn record_changes(&self, delta: &ScanDelta) {
+        let mut state = self.state();
+        state.changes[0] += delta.added.len() as u64;
+        state.changes[1] += delta.changed.len() as u64;
+        state.changes[2] += delta.removed.len() as u64;
+    }
+
+    /// Records the outcomes of one file and how long sorting it took.
+    pub fn record_file(&self, outcomes: &[Outcome], duration: Duration) {
+        let mut state = self.state();
+        for outcome in outcomes {
+            match outcome {
+                Outcome::Moved { matched, .. } => {
+                    *state.moved.entry(matched.rule.clone()).or_default() += 1;
+                    state.move_duration.observe(duration);
+                }
+                Outcome::Skipped { matched, reason, .. } => *state.skipped.entry((matched.rule.clone(), *reason)).or_default() += 1,
+                Outcome::Quarantined { matched, planned: false, .. } => {
+                    *state.quarantined.entry(matched.rule.clone()).or_default() += 1;
+                }
+                Outcome::Unmatched(reason) => *state.skipped.entry((String::new(), *reason)).or_default() += 1,
+                Outcome::Failed { rule, .. } => {
+                    *state.errors.entry(rule.clone().unwrap_or_default()).or_default() += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn render(&self) -> String {
+        let state = self.state();
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP organizer_files_scanned_total Files found in the watched folders.\n\
+             # TYPE organizer_files_scanned_total counter\n\
+             organizer_files_scanned_total {}",
+            state.scanned
+        );
+        let _ = writeln!(
+            out,
+            "# HELP organizer_scan_changes_total Entries found added, changed or removed by incremental scans.\n\
+             # TYPE organizer_scan_changes_total counter"
+        );
+        for (change, count) in ["added", "changed", "removed"].iter().zip(state.changes) {
+            let _ = writeln!(out, "organizer_scan_changes_total{{change=\"{}\"}} {}", change, count);
+        }
+        write_per_rule(&mut out, "organizer_files_moved_total", "Files moved, by rule.", &state.moved);
+        let _ = writeln!(
+            out,
+            "# HELP organizer_files_skipped_total Files left in place, by rule and reason; files no rule applied to have an empty rule.\n\
+             # TYPE organizer_files_skipped_total counter"
+        );
+        for ((rule, reason), count) in &state.skipped {
+            let _ = writeln!(out, "organizer_files_skipped_total{{rule=\"{}\",reason=\"{}\"}} {}", escape(rule), reason, count);
+        }
+        write_per_rule(
+            &mut out,
+            "organizer_files_quarantined_total",
+            "Files whose content did not fit their extension, by rule.",
+            &state.quarantined,
+        );
+        write_per_rule(&mut out, "organizer_errors_total", "Files that could not be sorted, by rule.", &state.errors);
+        state.scan_duration.write(&mut out, "organizer_scan_duration_seconds", "Duration of a complete sort of the watched folders.");
+        state.move_duration.write(&mut out, "organizer_move_duration_seconds", "Time taken to sort a single moved file.");
+        let _ = writeln!(
+            out,
+            "# HELP organizer_rule_stage_seconds Time spent in each stage of sorting files, by rule.\n\
+             # TYPE organizer_rule_stage_seconds summary"
+        );
+        for (rule, stages) in timing::snapshot() {
+            for (stage, time) in stages {
+                let labels = format!("rule=\"{}\",stage=\"{}\"", escape(&rule), stage);
+                let _ = writeln!(out, "organizer_rule_stage_seconds_sum{{{}}} {}", labels, time.total_ms / 1000.0);
+                let _ = writeln!(out, "organizer_rule_stage_seconds_count{{{}}} {}", labels, time.count);
+            }
+        }
+        out
+    }
+
+    fn respond(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut reader = BufReader::new(stream);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // Headers are not needed, but are read so the client sees a clean close.
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+        let mut stream = reader.into_inner();
+        let path = request.split_whitespace().nth(1).unwrap_or_default();
+        if path == "/metrics" {
+            let body = self.render();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        } else {
+            stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+        }
+    }
+}
+
+fn write_per_rule(out: &mut String, name: &str, help: &str, counts: &BTreeMap<String, u64>) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+    for (rule, count) in counts {
+        let _ = writeln!(out, "{}{{rule=\"{}\"}} {}", name, escape(rule), count);
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Serves `/metrics` on `address` from a background thread.
+pub fn serve(metrics: Arc<Metrics>, address: SocketAddr) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    info!("Serving metrics on http://{}/metrics", address);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            if let Err(e) = stream.and_then(|stream| metrics.respond(stream)) {
+                debug!("Metrics request failed: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,165 @@
+use crate::index::MoveIndex;
+use crate::s3::Bucket;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Most entries uploaded as one file.
+const SEGMENT_ENTRIES: usize = 10_000;
+
+/// Where the move index is mirrored to, from the user's `config.json`. Entries are
+/// uploaded in new files that are never changed afterwards, so the target may be
+/// append-only, such as a bucket with object lock.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum MirrorTarget {
+    /// A folder, e.g. on a second disk or a mounted share.
+    Folder { path: PathBuf },
+    /// A WebDAV collection, such as a Nextcloud folder.
+    Webdav {
+        url: String,
+        user: Option<String>,
+        /// Environment variable holding the password.
+        password_env: Option<String>,
+    },
+    /// An S3 bucket, or one of another service with the same API at `endpoint`.
+    S3 {
+        bucket: String,
+        #[serde(default)]
+        prefix: String,
+        region: String,
+        /// E.g. `https://minio.local:9000`; buckets are then addressed by path.
+        endpoint: Option<String>,
+        #[serde(default = "default_access_key_env")]
+        access_key_env: String,
+        #[serde(default = "default_secret_key_env")]
+        secret_key_env: String,
+    },
+}
+
+fn default_access_key_env() -> String {
+    "AWS_ACCESS_KEY_ID".to_string()
+}
+
+fn default_secret_key_env() -> String {
+    "AWS_SECRET_ACCESS_KEY".to_string()
+}
+
+impl MirrorTarget {
+    /// Names the target in the index, which remembers how far each target got.
+    fn key(&self) -> String {
+        match self {
+            MirrorTarget::Folder { path } => format!("folder:{}", path.display()),
+            MirrorTarget::Webdav { url, .. } => format!("webdav:{}", url),
+            MirrorTarget::S3 { bucket, prefix, endpoint, .. } => {
+                format!("s3:{}/{}/{}", endpoint.as_deref().unwrap_or_default(), bucket, prefix)
+            }
+        }
+    }
+}
+
+/// Uploads the index entries the target does not have yet, as JSON lines. Returns how
+/// many entries were mirrored.
+pub fn push(index: &MoveIndex, target: &MirrorTarget) -> io::Result<usize> {
+    let key = target.key();
+    let mut last = index.mirrored(&key).map_err(io::Error::other)?;
+    let mut mirrored = 0;
+    loop {
+        let entries = index.entries_after(last, SEGMENT_ENTRIES).map_err(io::Error::other)?;
+        let (Some(first), Some(newest)) = (entries.first(), entries.last()) else {
+            return Ok(mirrored);
+        };
+        let name = format!("moves-{:012}-{:012}.jsonl", first.id, newest.id);
+        let mut body = Vec::new();
+        for entry in &entries {
+            serde_json::to_writer(&mut body, entry)?;
+            body.push(b'\n');
+        }
+        upload(target, &name, &body)?;
+        last = newest.id;
+        index.set_mirrored(&key, last).map_err(io::Error::other)?;
+        mirrored += entries.len();
+    }
+}
+
+/// Stores one file on the target. A file that already is there was uploaded before the
+/// index could note it, and is left as it is.
+fn upload(target: &MirrorTarget, name: &str, body: &[u8]) -> io::Result<()> {
+    match target {
+        MirrorTarget::Folder { path } => {
+            fs::create_dir_all(path)?;
+            let file_path = path.join(name);
+            if file_path.exists() {
+                return Ok(());
+            }
+            let partial = path.join(format!(".{}.part", name));
+            let mut file = File::create(&partial)?;
+            file.write_all(body)?;
+            file.sync_all()?;
+            fs::rename(&partial, &file_path)
+        }
+        MirrorTarget::Webdav { url, user, password_env } => {
+            let mut request = ureq::put(&format!("{}/{}", url.trim_end_matches('/'), name)).timeout(TIMEOUT);
+            if let Some(user) = user {
+                let password = password_env.as_deref().map(secret).transpose()?.unwrap_or_default();
+                let credentials = encode_base64(format!("{}:{}", user, password).as_bytes());
+                request = request.set("Authorization", &format!("Basic {}", credentials));
+            }
+            send(request, body)
+        }
+        MirrorTarget::S3 {
+            bucket,
+            prefix,
+            region,
+            endpoint,
+            access_key_env,
+            secret_key_env,
+        } => {
+            let object = match prefix.trim_matches('/') {
+                "" => name.to_string(),
+                prefix => format!("{}/{}", prefix, name),
+            };
+            let bucket = Bucket {
+                name: bucket,
+                region,
+                endpoint: endpoint.as_deref(),
+                access_key: secret(access_key_env)?,
+                secret_key: secret(secret_key_env)?,
+            };
+            bucket.put(&object, body, true)
+        }
+    }
+}
+
+/// Sends the file, asking the server not to replace one that already exists.
+fn send(request: ureq::Request, body: &[u8]) -> io::Result<()> {
+    match request.set("If-None-Match", "*").send_bytes(body) {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(412, _)) => Ok(()),
+        Err(e) => Err(io::Error::other(e)),
+    }
+}
+
+fn secret(variable: &str) -> io::Result<String> {
+    std::env::var(variable).map_err(|_| io::Error::new(io::ErrorKind::NotFound, format!("{} is not set", variable)))
+}
+
+fn encode_base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let value = chunk.iter().enumerate().fold(0u32, |value, (position, byte)| value | (u32::from(*byte) << (16 - 8 * position)));
+        for position in 0..4 {
+            encoded.push(if position <= chunk.len() {
+                ALPHABET[((value >> (18 - 6 * position)) & 0x3f) as usize] as char
+            } else {
+                '='
+            });
+        }
+    }
+    encoded
+}
@@ -0,0 +1,432 @@
+use crate::duplicates;
+use crate::index::Sequences;
+use crate::media;
+use crate::paths;
+use crate::statefile;
+use crate::templates;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime};
+use clap::{Subcommand, ValueEnum};
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const JOURNAL_FILE: &str = "normalize.json";
+
+/// Digits `{counter}` and `{seq}` are padded to unless they say otherwise, as `{seq:05}` does.
+const NUMBER_WIDTH: usize = 3;
+
+/// How file names should look, from `rules.json`; `normalize` renames files to match.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct NamingConvention {
+    /// Case of the name without its extension.
+    pub case: Option<NameCase>,
+    /// Put between words in place of spaces, underscores, hyphens and dots, e.g. `-` or `_`.
+    pub separator: Option<String>,
+    /// Format of a date put in front of names, e.g. `%Y-%m-%d`. Photos get the date they
+    /// were taken, other files the date they were last modified.
+    pub date_prefix: Option<String>,
+    /// Writes extensions in lowercase, e.g. `.JPG` as `.jpg`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub lowercase_extensions: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NameCase {
+    Lower,
+    Upper,
+    /// The first letter of every word upper case, the rest lower case.
+    Title,
+}
+
+/// What `normalize` does when the new name is already taken.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum NameConflict {
+    /// Leave the file as it is.
+    Skip,
+    /// Add a number, as in `name (1).ext`.
+    Number,
+}
+
+impl NamingConvention {
+    pub fn is_empty(&self) -> bool {
+        self.case.is_none() && self.separator.is_none() && self.date_prefix.is_none() && !self.lowercase_extensions
+    }
+
+    /// The name the file should have, or `None` when it already follows the convention.
+    /// Hidden files keep their names.
+    pub fn normalized_name(&self, file_path: &Path) -> Option<String> {
+        let name = file_path.file_name()?.to_str()?;
+        if name.starts_with('.') {
+            return None;
+        }
+        let (stem, extension) = split_name(name);
+        let mut stem = match &self.separator {
+            Some(separator) => stem
+                .split([' ', '_', '-', '.'])
+                .filter(|word| !word.is_empty())
+                .collect::<Vec<_>>()
+                .join(separator),
+            None => stem.to_string(),
+        };
+        stem = match self.case {
+            Some(NameCase::Lower) => stem.to_lowercase(),
+            Some(NameCase::Upper) => stem.to_uppercase(),
+            Some(NameCase::Title) => title_case(&stem),
+            None => stem,
+        };
+        if let Some(format) = &self.date_prefix {
+            if !starts_with_date(&stem, format) {
+                let date = format_date(file_date(file_path)?, format)?;
+                let separator = self.separator.as_deref().unwrap_or(" ");
+                stem = format!("{}{}{}", date, separator, stem);
+            }
+        }
+        let extension = if self.lowercase_extensions {
+            extension.to_lowercase()
+        } else {
+            extension.to_string()
+        };
+        let normalized = format!("{}{}", stem, extension);
+        (!stem.is_empty() && normalized != name).then_some(normalized)
+    }
+}
+
+/// Shows what `rename` actions do before a sort applies them.
+#[derive(Subcommand)]
+pub enum RenameCommand {
+    /// List the new names the files in a folder would get, and which of them are taken
+    Preview {
+        #[arg(short, long)]
+        path: String,
+        /// Also preview files in subfolders, except hidden folders
+        #[arg(short, long)]
+        recursive: bool,
+        /// Also write the old and new paths to this CSV file
+        #[arg(long)]
+        csv: Option<PathBuf>,
+    },
+}
+
+/// Renames files as they are moved into a destination, from its `rename` action in
+/// `rules.json`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RenameAction {
+    /// The new name, e.g. `IMG_{exif.date}_{counter}.{ext}`, with the placeholders of
+    /// destinations, `{ext}`, `{counter}` and the groups of `pattern`.
+    pub template: String,
+    /// Regular expression for the name without its extension; `{1}`, `{2}` and named
+    /// groups such as `{id}` take what it captured. Files it doesn't match keep their name.
+    pub pattern: Option<String>,
+    /// Lower case, with anything but letters and digits between words replaced by `-`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub slugify: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub lowercase_extension: bool,
+    /// `pattern`, compiled when the rules are loaded.
+    #[serde(skip)]
+    regex: Option<Regex>,
+}
+
+impl RenameAction {
+    /// Compiles `pattern` once when the rules are loaded, rather than for every file.
+    pub fn compile(&mut self) -> Result<(), String> {
+        self.regex = match &self.pattern {
+            Some(pattern) => Some(Regex::new(pattern).map_err(|e| format!("invalid rename pattern: {}", e))?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// The name `file_path` gets in `folder`, or `None` when it keeps its name. `{counter}`
+    /// counts up from 1 to the first name no other file has, `{seq}` takes the next number
+    /// of the folder from `sequences`, only looking at it in a dry run, and any other name
+    /// that is taken gets a number as in `name (1).ext`, so no file is overwritten. Paths in
+    /// `reserved` count as taken, for previews of many files that are not moved yet.
+    pub fn new_name(
+        &self,
+        file_path: &Path,
+        folder: &Path,
+        templates: &templates::Settings,
+        sequences: Option<&Mutex<Sequences>>,
+        reserved: &HashSet<PathBuf>,
+        dry_run: bool,
+    ) -> Option<NewName> {
+        let name = file_path.file_name()?.to_str()?;
+        let (stem, extension) = split_name(name);
+        let mut template = self.template.replace("{ext}", extension.trim_start_matches('.'));
+        if self.pattern.is_some() {
+            let captures = self.regex.as_ref()?.captures(stem)?;
+            template = fill_captures(&template, &captures);
+        }
+        let expanded = templates::expand_destination(&template, file_path, templates);
+        let on_disk = |name: &str| {
+            let target = folder.join(name);
+            target != file_path && target.exists()
+        };
+        let free = |name: &str| !on_disk(name) && !reserved.contains(&folder.join(name));
+        let Some(number) = Number::find(&expanded) else {
+            let renamed = self.finish(&expanded)?;
+            if free(&renamed) {
+                return Some(NewName::new(renamed, false));
+            }
+            let numbered = (1..)
+                .map(|counter| duplicates::numbered_path(&folder.join(&renamed), counter))
+                .find(|numbered| numbered.file_name().and_then(|name| name.to_str()).is_some_and(free))?;
+            return Some(NewName::new(numbered.file_name()?.to_string_lossy().into_owned(), true));
+        };
+        let numbered = |value: u64| self.finish(&number.fill(&expanded, value));
+        numbered(1)?;
+        let sequences = sequences.filter(|_| number.sequence);
+        let Some(sequences) = sequences else {
+            return (1..)
+                .filter_map(numbered)
+                .find(|renamed| free(renamed))
+                .map(|renamed| NewName::new(renamed, false));
+        };
+        // Files renamed in place before keep the number they were given.
+        let numbers = name.split(|c: char| !c.is_ascii_digit()).filter_map(|digits| digits.parse().ok());
+        if file_path.parent() == Some(folder) && numbers.filter_map(numbered).any(|renamed| renamed == name) {
+            return Some(NewName::new(name.to_string(), false));
+        }
+        let sequences = sequences.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let key = folder.to_string_lossy();
+        if dry_run {
+            // Numbers of files planned before are used up, as they would be by the real run.
+            let mut collided = false;
+            return (sequences.peek(&key).ok()?..).map_while(numbered).find_map(|renamed| {
+                collided |= on_disk(&renamed);
+                free(&renamed).then(|| NewName::new(renamed, collided))
+            });
+        }
+        let mut collided = false;
+        loop {
+            let renamed = numbered(sequences.next(&key).ok()?)?;
+            if free(&renamed) {
+                return Some(NewName::new(renamed, collided));
+            }
+            collided = true;
+        }
+    }
+
+    fn finish(&self, name: &str) -> Option<String> {
+        let name = name.replace(['/', '\\'], "_");
+        let (stem, extension) = split_name(&name);
+        let stem = if self.slugify { slugify(stem) } else { stem.trim().to_string() };
+        let extension = if self.lowercase_extension {
+            extension.to_lowercase()
+        } else {
+            extension.to_string()
+        };
+        (!stem.is_empty()).then(|| format!("{}{}", stem, extension))
+    }
+}
+
+/// A name from a `rename` action.
+pub struct NewName {
+    pub name: String,
+    /// Whether the name the template gave was taken, so the file got another number.
+    pub collided: bool,
+}
+
+impl NewName {
+    fn new(name: String, collided: bool) -> Self {
+        NewName { name, collided }
+    }
+}
+
+/// Where `{counter}` or `{seq}` is in a name, and the digits it is padded to.
+struct Number {
+    start: usize,
+    end: usize,
+    width: usize,
+    /// `{seq}`, numbered across runs, rather than `{counter}`.
+    sequence: bool,
+}
+
+impl Number {
+    fn find(name: &str) -> Option<Number> {
+        let (start, placeholder, sequence) = ["{counter", "{seq"]
+            .into_iter()
+            .find_map(|placeholder| Some((name.find(placeholder)?, placeholder, placeholder == "{seq")))?;
+        let rest = &name[start + placeholder.len()..];
+        let length = rest.find('}')?;
+        let width = match &rest[..length] {
+            "" => NUMBER_WIDTH,
+            format => format.strip_prefix(':')?.parse().ok()?,
+        };
+        Some(Number {
+            start,
+            end: start + placeholder.len() + length + 1,
+            width,
+            sequence,
+        })
+    }
+
+    fn fill(&self, name: &str, value: u64) -> String {
+        format!("{}{:0width$}{}", &name[..self.start], value, &name[self.end..], width = self.width)
+    }
+}
+
+/// Replaces `{1}` and `{name}` with what the groups of a pattern captured; other
+/// placeholders are left for the template.
+pub fn fill_captures(template: &str, captures: &Captures) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(length) = rest[start..].find('}') else { break };
+        let name = &rest[start + 1..start + length];
+        filled.push_str(&rest[..start]);
+        let group = match name.parse::<usize>() {
+            Ok(number) => captures.get(number).map(|group| group.as_str()).or(Some("")),
+            Err(_) => captures.name(name).map(|group| group.as_str()),
+        };
+        match group {
+            Some(value) => filled.push_str(&templates::sanitize(value)),
+            None => filled.push_str(&rest[start..=start + length]),
+        }
+        rest = &rest[start + length + 1..];
+    }
+    filled.push_str(rest);
+    filled
+}
+
+fn slugify(stem: &str) -> String {
+    let mut slug = String::with_capacity(stem.len());
+    for c in stem.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Splits a name into stem and extension, treating `.tar.gz` and similar as one extension.
+fn split_name(name: &str) -> (&str, &str) {
+    let Some(dot) = name.rfind('.').filter(|dot| *dot > 0) else {
+        return (name, "");
+    };
+    let stem = &name[..dot];
+    match stem.len().checked_sub(4) {
+        Some(tar) if tar > 0 && stem.get(tar..).is_some_and(|tar| tar.eq_ignore_ascii_case(".tar")) => (&name[..tar], &name[tar..]),
+        _ => (stem, &name[dot..]),
+    }
+}
+
+fn title_case(stem: &str) -> String {
+    let mut title = String::with_capacity(stem.len());
+    let mut word_start = true;
+    for c in stem.chars() {
+        if word_start {
+            title.extend(c.to_uppercase());
+        } else {
+            title.extend(c.to_lowercase());
+        }
+        word_start = !c.is_alphanumeric();
+    }
+    title
+}
+
+/// Formats a date, or returns `None` for an invalid format instead of panicking.
+fn format_date(date: NaiveDateTime, format: &str) -> Option<String> {
+    let mut formatted = String::new();
+    write!(formatted, "{}", date.format(format)).ok()?;
+    Some(formatted)
+}
+
+/// Whether the name already starts with a date in `format`, so it doesn't get a second one.
+fn starts_with_date(stem: &str, format: &str) -> bool {
+    let Some(sample) = NaiveDate::from_ymd_opt(2000, 1, 1).and_then(|date| date.and_hms_opt(0, 0, 0)) else { return false };
+    let Some(length) = format_date(sample, format).map(|sample| sample.len()) else { return false };
+    let Some(prefix) = stem.get(..length) else { return false };
+    NaiveDate::parse_from_str(prefix, format).is_ok() || NaiveDateTime::parse_from_str(prefix, format).is_ok()
+}
+
+fn file_date(file_path: &Path) -> Option<NaiveDateTime> {
+    if let Some(date) = media::read_exif(file_path).and_then(|exif| exif.date) {
+        return Some(date);
+    }
+    let modified: DateTime<Local> = fs::metadata(file_path).ok()?.modified().ok()?.into();
+    Some(modified.naive_local())
+}
+
+/// One file renamed by `normalize`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Rename {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// The renames of one `normalize` run.
+#[derive(Serialize, Deserialize)]
+pub struct NormalizeRun {
+    pub time: DateTime<Local>,
+    pub renames: Vec<Rename>,
+}
+
+/// The renames of earlier `normalize` runs, newest last, kept in the data directory so
+/// `normalize --undo` can reverse them.
+pub struct Journal {
+    path: PathBuf,
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Journal {
+            path: paths::data_dir().join(JOURNAL_FILE),
+        }
+    }
+}
+
+impl Journal {
+    fn read(&self) -> Vec<NormalizeRun> {
+        fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn write(&self, runs: &[NormalizeRun]) -> io::Result<()> {
+        statefile::write(&self.path, serde_json::to_string_pretty(runs)?.as_bytes())
+    }
+
+    pub fn push(&self, renames: Vec<Rename>) -> io::Result<()> {
+        let _lock = statefile::lock(&self.path)?;
+        let mut runs = self.read();
+        runs.push(NormalizeRun {
+            time: Local::now(),
+            renames,
+        });
+        self.write(&runs)
+    }
+
+    /// The newest run. It stays in the journal until [`Journal::settle`] is told how undoing
+    /// it went, so a failed undo can be tried again.
+    pub fn last(&self) -> Option<NormalizeRun> {
+        self.read().pop()
+    }
+
+    /// Removes `run` once it was undone, or keeps the renames of it that are `remaining`
+    /// for the next undo.
+    pub fn settle(&self, run: &NormalizeRun, remaining: Vec<Rename>) -> io::Result<()> {
+        let _lock = statefile::lock(&self.path)?;
+        let mut runs = self.read();
+        // Another run may have been added since.
+        let Some(position) = runs.iter().rposition(|other| other.time == run.time) else {
+            return Ok(());
+        };
+        if remaining.is_empty() {
+            runs.remove(position);
+        } else {
+            runs[position].renames = remaining;
+        }
+        self.write(&runs)
+    }
+}
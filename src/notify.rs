@@ -0,0 +1,157 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const MAX_SEPARATE_ALERTS: usize = 3;
+
+/// Desktop notification settings from `rules.json`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct NotificationSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Show a "Moved N files from <folder>" summary per batch.
+    #[serde(default = "default_summary")]
+    pub summary: bool,
+    /// Events are collected for this many seconds before notifications are shown.
+    #[serde(default = "default_batch_seconds")]
+    pub batch_seconds: u64,
+    /// Alert messages per destination; `{file}` is replaced with the file name.
+    #[serde(default)]
+    pub alerts: HashMap<String, String>,
+    /// Hold notifications while the user is working and show them once the screen locks.
+    #[serde(default)]
+    pub defer_while_active: bool,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        NotificationSettings {
+            enabled: false,
+            summary: default_summary(),
+            batch_seconds: default_batch_seconds(),
+            alerts: HashMap::new(),
+            defer_while_active: false,
+        }
+    }
+}
+
+fn default_summary() -> bool {
+    true
+}
+
+fn default_batch_seconds() -> u64 {
+    60
+}
+
+/// Collects sort events in the daemon and shows them as batched desktop notifications.
+pub struct Notifier {
+    settings: NotificationSettings,
+    batch_started: Option<Instant>,
+    moved: HashMap<String, usize>,
+    alerts: Vec<String>,
+}
+
+impl Notifier {
+    pub fn new() -> Self {
+        Notifier {
+            settings: NotificationSettings::default(),
+            batch_started: None,
+            moved: HashMap::new(),
+            alerts: Vec::new(),
+        }
+    }
+    
+    pub fn configure(&mut self, settings: &NotificationSettings) {
+        self.settings = settings.clone();
+    }
+    
+    pub fn file_moved(&mut self, source: &Path, destination: &str) {
+        if !self.settings.enabled {
+            return;
+        }
+        self.batch_started.get_or_insert_with(Instant::now);
+        
+        let folder = source
+            .parent()
+            .and_then(|parent| parent.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        *self.moved.entry(folder).or_insert(0) += 1;
+        
+        if let Some(template) = self.settings.alerts.get(destination) {
+            let file_name = source.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+            self.alerts.push(template.replace("{file}", &file_name));
+        }
+    }
+    
+    /// Alerts about a file that was quarantined; `reason` says what was found in it.
+    pub fn file_quarantined(&mut self, source: &Path, reason: &str) {
+        if !self.settings.enabled {
+            return;
+        }
+        self.batch_started.get_or_insert_with(Instant::now);
+        let file_name = source.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        self.alerts.push(format!("Quarantined {}: {}", file_name, reason));
+    }
+    
+    /// Alerts about something that needs attention, such as a full drive.
+    pub fn alert(&mut self, message: String) {
+        if !self.settings.enabled {
+            return;
+        }
+        self.batch_started.get_or_insert_with(Instant::now);
+        self.alerts.push(message);
+    }
+    
+    /// Shows the collected notifications once the batching window has passed.
+    pub fn flush_if_due(&mut self, user_active: bool) {
+        let Some(started) = self.batch_started else { return };
+        if started.elapsed() < Duration::from_secs(self.settings.batch_seconds) {
+            return;
+        }
+        if user_active && self.settings.defer_while_active {
+            return;
+        }
+        self.flush();
+    }
+    
+    /// Shows the collected notifications right away.
+    pub fn flush(&mut self) {
+        self.batch_started = None;
+        
+        if self.settings.summary {
+            for (folder, count) in self.moved.drain() {
+                let noun = if count == 1 { "file" } else { "files" };
+                show("Organizer", &format!("Moved {} {} from {}", count, noun, folder));
+            }
+        }
+        self.moved.clear();
+        
+        if self.alerts.len() > MAX_SEPARATE_ALERTS {
+            show(&format!("{} new alerts", self.alerts.len()), &self.alerts.join("\n"));
+        } else {
+            for alert in &self.alerts {
+                show("Organizer", alert);
+            }
+        }
+        self.alerts.clear();
+    }
+}
+
+#[cfg(feature = "notifications")]
+fn show(summary: &str, body: &str) {
+    let result = notify_rust::Notification::new()
+        .appname("Organizer")
+        .summary(summary)
+        .body(body)
+        .show();
+    if let Err(e) = result {
+        tracing::warn!("Failed to show notification: {}", e);
+    }
+}
+
+#[cfg(not(feature = "notifications"))]
+fn show(summary: &str, body: &str) {
+    tracing::debug!("Notifications are not enabled in this build: {}: {}", summary, body);
+}
@@ -0,0 +1,202 @@
+use crate::paths;
+use serde_json::json;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Seconds between sorts of the daemon set up at the end.
+const INTERVAL: u64 = 10;
+
+/// Folders offered for sorting, when they exist.
+const FOLDERS: &[(&str, &str)] = &[("Downloads", "xdg:download"), ("Desktop", "xdg:desktop")];
+
+/// A starting point for the rules, as a layout of folders.
+struct Preset {
+    name: &'static str,
+    description: &'static str,
+    layout: &'static [&'static str],
+}
+
+const PRESETS: &[Preset] = &[
+    Preset {
+        name: "By kind",
+        description: "one folder each for images, documents, music, videos, archives and installers",
+        layout: &["Images", "Documents/{category}", "Music", "Videos", "Archives", "Software/{category}"],
+    },
+    Preset {
+        name: "By kind and date",
+        description: "the same, with photos by year and month and documents and videos by year",
+        layout: &[
+            "Images/{year}/{month}",
+            "Documents/{category}/{year}",
+            "Music/{artist}",
+            "Videos/{year}",
+            "Archives",
+            "Software/{category}",
+        ],
+    },
+    Preset {
+        name: "Media only",
+        description: "only photos, music and videos; everything else stays where it is",
+        layout: &["Images/{year}", "Music/{artist}/{album}", "Videos"],
+    },
+];
+
+/// What the guided setup needs from the sorter.
+pub struct Onboarding {
+    /// Where the chosen rules are saved.
+    pub rules: PathBuf,
+    /// Shows what sorting the folder with the rules would do, without changing anything.
+    pub preview: fn(&str, &str) -> io::Result<()>,
+    /// Sorts the folder with the saved rules.
+    pub sort: fn(&str) -> io::Result<()>,
+}
+
+/// Walks a new user through picking a folder and rules, shows what would happen, and
+/// offers to sort the folder now and to keep it sorted in the background.
+pub fn run(onboarding: &Onboarding) -> io::Result<()> {
+    println!("Welcome to Organizer. There are no rules here yet, so let's set them up.");
+    println!();
+
+    let folders: Vec<(&str, &str)> = FOLDERS.iter().copied().filter(|(_, folder)| paths::resolve_path(folder).is_dir()).collect();
+    println!("Which folder should be kept tidy?");
+    for (number, (name, folder)) in folders.iter().enumerate() {
+        let path = paths::resolve_path(folder);
+        println!("  {}) {} ({}, {} files)", number + 1, name, path.display(), count_files(&path));
+    }
+    let folder = loop {
+        let Some(answer) = ask("Number, or the path of another folder: ")? else { return Ok(()) };
+        if let Some((_, folder)) = answer.parse::<usize>().ok().and_then(|number| folders.get(number.wrapping_sub(1))) {
+            break folder.to_string();
+        }
+        if !answer.is_empty() && paths::resolve_path(&answer).is_dir() {
+            break answer;
+        }
+        println!("That is not one of the folders above, nor a folder that exists.");
+    };
+
+    println!();
+    println!("How should its files be sorted?");
+    for (number, preset) in PRESETS.iter().enumerate() {
+        println!("  {}) {}: {}", number + 1, preset.name, preset.description);
+        println!("     {}", preset.layout.join(", "));
+    }
+    let preset = loop {
+        let Some(answer) = ask("Number [1]: ")? else { return Ok(()) };
+        let number = if answer.is_empty() { Some(1) } else { answer.parse::<usize>().ok() };
+        if let Some(preset) = number.and_then(|number| PRESETS.get(number.wrapping_sub(1))) {
+            break preset;
+        }
+    };
+    let rules = serde_json::to_string_pretty(&json!({ "layout": preset.layout }))?;
+
+    println!();
+    println!("This is what sorting {} would do; nothing is moved yet:", folder);
+    (onboarding.preview)(&folder, &rules)?;
+    println!();
+    if !yes(&format!("Save these rules to {}?", onboarding.rules.display()))? {
+        println!("Nothing was saved. Run Organizer again to start over.");
+        return Ok(());
+    }
+    fs::write(&onboarding.rules, format!("{}\n", rules))?;
+    println!("Saved. Edit {} to change the rules; `Organizer layout` lists them.", onboarding.rules.display());
+
+    if yes(&format!("Sort {} now?", folder))? {
+        (onboarding.sort)(&folder)?;
+    }
+    if yes("Keep it sorted in the background from now on?")? {
+        let rules = onboarding.rules.canonicalize()?;
+        install(&folder, rules.parent().unwrap_or(Path::new("/")))?;
+        println!("Installed. Organizer now checks {} every {} seconds while you are logged in.", folder, INTERVAL);
+    } else {
+        println!("Run `Organizer daemon --path {}` from this folder to keep it sorted.", folder);
+    }
+    Ok(())
+}
+
+fn count_files(folder: &Path) -> usize {
+    fs::read_dir(folder)
+        .map(|entries| entries.flatten().filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_file())).count())
+        .unwrap_or(0)
+}
+
+/// Reads an answer; `None` at the end of input.
+fn ask(prompt: &str) -> io::Result<Option<String>> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(answer.trim().to_string()))
+}
+
+fn yes(question: &str) -> io::Result<bool> {
+    let answer = ask(&format!("{} [y/N] ", question))?.unwrap_or_default();
+    Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Starts the daemon for `folder` whenever the user logs in, with `directory`, which holds
+/// the rules, as its working directory. Unlike `install`, this needs no administrator.
+fn install(folder: &str, directory: &Path) -> io::Result<()> {
+    let program = std::env::current_exe()?;
+    let interval = INTERVAL.to_string();
+    #[cfg(target_os = "linux")]
+    {
+        let service = format!(
+            "[Unit]\nDescription=Organizer\n\n[Service]\nExecStart=\"{}\" daemon --path \"{}\" --interval {}\nWorkingDirectory={}\nRestart=on-failure\n\n[Install]\nWantedBy=default.target\n",
+            program.display(),
+            folder,
+            interval,
+            directory.display()
+        );
+        let units = paths::config_home().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No home folder"))?.join("systemd").join("user");
+        fs::create_dir_all(&units)?;
+        fs::write(units.join("organizer.service"), service)?;
+        run(Command::new("systemctl").args(["--user", "daemon-reload"]))?;
+        run(Command::new("systemctl").args(["--user", "enable", "--now", "organizer.service"]))
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let agent = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n<plist version=\"1.0\">\n<dict>\n  <key>Label</key><string>org.organizer.daemon</string>\n  <key>ProgramArguments</key>\n  <array><string>{}</string><string>daemon</string><string>--path</string><string>{}</string><string>--interval</string><string>{}</string></array>\n  <key>WorkingDirectory</key><string>{}</string>\n  <key>RunAtLoad</key><true/>\n  <key>KeepAlive</key><true/>\n</dict>\n</plist>\n",
+            program.display(),
+            folder,
+            interval,
+            directory.display()
+        );
+        let agents = paths::home_dir().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No home folder"))?.join("Library/LaunchAgents");
+        fs::create_dir_all(&agents)?;
+        let path = agents.join("org.organizer.daemon.plist");
+        fs::write(&path, agent)?;
+        run(Command::new("launchctl").arg("load").arg("-w").arg(&path))
+    }
+    #[cfg(windows)]
+    {
+        let task = format!(
+            "cmd /c cd /d \"{}\" && \"{}\" daemon --path \"{}\" --interval {}",
+            directory.display(),
+            program.display(),
+            folder,
+            interval
+        );
+        run(Command::new("schtasks").args(["/Create", "/F", "/TN", "Organizer", "/SC", "ONLOGON", "/TR", &task]))?;
+        run(Command::new("schtasks").args(["/Run", "/TN", "Organizer"]))
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    {
+        let _ = (folder, directory, program, interval);
+        Err(io::Error::new(io::ErrorKind::Unsupported, "Starting Organizer at login is not supported on this system"))
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+fn run(command: &mut Command) -> io::Result<()> {
+    let status = command.status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("{:?} failed ({})", command.get_program(), status)))
+    }
+}
@@ -0,0 +1,127 @@
+use serde_json::{Map, Value};
+use std::sync::OnceLock;
+
+/// Environment variables starting with this set a key each, e.g. `ORGANIZER__DUPLICATES=keep-both`
+/// or `ORGANIZER__NOTIFICATIONS__ENABLED=false`; `__` separates the parts of the key.
+const ENV_PREFIX: &str = "ORGANIZER__";
+
+/// Keys with this prefix are set in `config.json` instead of `rules.json`.
+const SETTINGS_PREFIX: &str = "settings.";
+
+/// Replaces the folder `rules.json` is read from, or names the rules file itself.
+pub const RULES_PATH_ENV: &str = "ORGANIZER_RULES_PATH";
+
+static ASSIGNMENTS: OnceLock<Vec<Assignment>> = OnceLock::new();
+
+/// A value given for a configuration key, such as `notifications.enabled=false`.
+struct Assignment {
+    key: Vec<String>,
+    value: Value,
+}
+
+impl Assignment {
+    fn parse(key: &str, value: &str) -> Result<Assignment, String> {
+        let key: Vec<String> = key.split('.').map(str::to_string).collect();
+        if key.iter().any(String::is_empty) {
+            return Err(format!("Invalid configuration key '{}'", key.join(".")));
+        }
+        // Values that aren't JSON, such as `rename`, are strings.
+        let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+        Ok(Assignment { key, value })
+    }
+}
+
+/// Reads the keys set in the environment and with `--set key=value`; those from the command
+/// line win.
+pub fn init(set: &[String]) -> Result<(), String> {
+    let mut assignments = Vec::new();
+    let mut variables: Vec<(String, String)> = std::env::vars().filter(|(name, _)| name.starts_with(ENV_PREFIX)).collect();
+    variables.sort();
+    for (name, value) in variables {
+        let key = name[ENV_PREFIX.len()..].to_lowercase().replace("__", ".");
+        assignments.push(Assignment::parse(&key, &value)?);
+    }
+    for assignment in set {
+        let (key, value) = assignment
+            .split_once('=')
+            .ok_or_else(|| format!("--set needs key=value, not '{}'", assignment))?;
+        assignments.push(Assignment::parse(key.trim(), value)?);
+    }
+    let _ = ASSIGNMENTS.set(assignments);
+    Ok(())
+}
+
+/// Whether any key of `rules.json` is set from outside the file.
+pub fn sets_rules() -> bool {
+    assignments().any(|assignment| !is_settings(assignment))
+}
+
+/// Sets the keys given for `rules.json` in its contents.
+pub fn apply_rules(config: &mut Value) -> Result<(), String> {
+    for assignment in assignments().filter(|assignment| !is_settings(assignment)) {
+        set(config, &assignment.key, assignment.value.clone())?;
+    }
+    Ok(())
+}
+
+/// Sets the keys given with the `settings.` prefix in the contents of `config.json`.
+pub fn apply_settings(settings: &mut Value) -> Result<(), String> {
+    for assignment in assignments().filter(|assignment| is_settings(assignment)) {
+        set(settings, &assignment.key[1..], assignment.value.clone())?;
+    }
+    Ok(())
+}
+
+/// The keys set from outside the files, as `key=value` in the order they are applied.
+pub fn describe() -> Vec<String> {
+    assignments()
+        .map(|assignment| format!("{}={}", assignment.key.join("."), assignment.value))
+        .collect()
+}
+
+fn assignments() -> impl Iterator<Item = &'static Assignment> {
+    ASSIGNMENTS.get().into_iter().flatten()
+}
+
+fn is_settings(assignment: &Assignment) -> bool {
+    assignment.key.len() > 1 && assignment.key[0] == SETTINGS_PREFIX.trim_end_matches('.')
+}
+
+/// Sets `key` in `config`, creating the objects on the way; numbers pick entries of lists,
+/// as in `rules.0.destination`.
+fn set(config: &mut Value, key: &[String], value: Value) -> Result<(), String> {
+    let Some((last, parents)) = key.split_last() else { return Ok(()) };
+    let mut current = config;
+    for part in parents {
+        current = child(current, part, key)?;
+    }
+    if current.is_null() {
+        *current = Value::Object(Map::new());
+    }
+    match current {
+        Value::Array(entries) => match last.parse::<usize>().ok().and_then(|index| entries.get_mut(index)) {
+            Some(entry) => *entry = value,
+            None => return Err(format!("'{}' has no entry {}", key.join("."), last)),
+        },
+        Value::Object(object) => {
+            object.insert(last.clone(), value);
+        }
+        _ => return Err(format!("Can't set '{}': '{}' is not an object", key.join("."), parents.join("."))),
+    }
+    Ok(())
+}
+
+fn child<'a>(value: &'a mut Value, part: &str, key: &[String]) -> Result<&'a mut Value, String> {
+    if value.is_null() {
+        *value = Value::Object(Map::new());
+    }
+    match value {
+        Value::Array(entries) => part
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| entries.get_mut(index))
+            .ok_or_else(|| format!("'{}' has no entry {}", key.join("."), part)),
+        Value::Object(object) => Ok(object.entry(part.to_string()).or_insert(Value::Null)),
+        _ => Err(format!("Can't set '{}': '{}' is neither an object nor a list", key.join("."), part)),
+    }
+}
@@ -0,0 +1,80 @@
+use crate::rules::{self, Rule};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Name of the file that adjusts the rules for the folder it is in and everything below.
+pub const FILE_NAME: &str = ".organizer.toml";
+
+/// Contents of an `.organizer.toml` file.
+#[derive(Deserialize, Default)]
+struct DirectoryOverride {
+    /// Extensions or globs of files that are never moved out of this subtree.
+    #[serde(default)]
+    ignore: Vec<String>,
+    /// Rules tried before the global ones; they replace global rules with the same `match`.
+    #[serde(default, deserialize_with = "rules::deserialize")]
+    rules: Vec<Rule>,
+}
+
+/// The rules in effect for files in one folder.
+#[derive(Serialize)]
+pub struct FolderRules {
+    pub rules: Vec<Rule>,
+    pub ignore: Vec<String>,
+}
+
+impl FolderRules {
+    pub fn ignores(&self, file_path: &Path) -> bool {
+        self.ignore.iter().any(|pattern| rules::pattern_matches(pattern, file_path))
+    }
+}
+
+/// Applies the `.organizer.toml` files from `root` down to `folder` to the global `rules`.
+/// Returns `None` when none of them exist, so the global rules apply unchanged.
+pub fn rules_for(folder: &Path, root: &Path, rules: &[Rule]) -> Option<FolderRules> {
+    let mut folders: Vec<PathBuf> = folder
+        .ancestors()
+        .take_while(|ancestor| ancestor.starts_with(root))
+        .map(Path::to_path_buf)
+        .collect();
+    folders.reverse();
+    
+    let mut effective: Option<FolderRules> = None;
+    for folder in folders {
+        let Some(local) = load(&folder) else { continue };
+        let inherited = effective.take().unwrap_or_else(|| FolderRules {
+            rules: rules.to_vec(),
+            ignore: Vec::new(),
+        });
+        let mut merged = local.rules.clone();
+        merged.extend(
+            inherited
+                .rules
+                .into_iter()
+                .filter(|rule| !local.rules.iter().any(|local| local.pattern == rule.pattern)),
+        );
+        let mut ignore = inherited.ignore;
+        ignore.extend(local.ignore);
+        effective = Some(FolderRules { rules: merged, ignore });
+    }
+    effective
+}
+
+fn load(folder: &Path) -> Option<DirectoryOverride> {
+    let path = folder.join(FILE_NAME);
+    let contents = fs::read_to_string(&path).ok()?;
+    let mut local: DirectoryOverride = match toml::from_str(&contents) {
+        Ok(local) => local,
+        Err(e) => {
+            warn!("Ignoring invalid {:?}: {}", path, e);
+            return None;
+        }
+    };
+    if let Err(e) = local.rules.iter_mut().try_for_each(Rule::validate) {
+        warn!("Ignoring invalid {:?}: {}", path, e);
+        return None;
+    }
+    Some(local)
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+const XDG_PREFIX: &str = "xdg:";
+
+/// Where `rules.json` and the Lua script are read from, when a profile says.
+static RULES_DIR: OnceLock<PathBuf> = OnceLock::new();
+static RULES_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+const XDG_DEFAULTS: &[(&str, &str)] = &[
+    ("DESKTOP", "Desktop"),
+    ("DOWNLOAD", "Downloads"),
+    ("TEMPLATES", "Templates"),
+    ("PUBLICSHARE", "Public"),
+    ("DOCUMENTS", "Documents"),
+    ("MUSIC", "Music"),
+    ("PICTURES", "Pictures"),
+    ("VIDEOS", "Videos"),
+];
+
+/// Resolves a configured path, expanding `xdg:<name>` references such as
+/// `xdg:download` or `xdg:pictures/Screenshots` to the user's localized folders.
+pub fn resolve_path(path: &str) -> PathBuf {
+    let Some(reference) = path.strip_prefix(XDG_PREFIX) else {
+        return PathBuf::from(path);
+    };
+    let (name, rest) = match reference.split_once('/') {
+        Some((name, rest)) => (name, Some(rest)),
+        None => (reference, None),
+    };
+    
+    let Some(mut resolved) = user_dir(name) else {
+        return PathBuf::from(path);
+    };
+    if let Some(rest) = rest {
+        resolved.push(rest);
+    }
+    resolved
+}
+
+fn user_dir(name: &str) -> Option<PathBuf> {
+    let key = name.to_uppercase();
+    if let Some(dir) = read_user_dirs().remove(&key) {
+        return Some(dir);
+    }
+    XDG_DEFAULTS
+        .iter()
+        .find(|(default_key, _)| *default_key == key)
+        .and_then(|(_, folder)| home_dir().map(|home| home.join(folder)))
+}
+
+fn read_user_dirs() -> HashMap<String, PathBuf> {
+    let mut dirs = HashMap::new();
+    let Some(config_home) = config_home() else {
+        return dirs;
+    };
+    let Ok(contents) = fs::read_to_string(config_home.join("user-dirs.dirs")) else {
+        return dirs;
+    };
+    
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else { continue };
+        let Some(name) = key.trim().strip_prefix("XDG_").and_then(|key| key.strip_suffix("_DIR")) else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"');
+        let dir = match value.strip_prefix("$HOME") {
+            Some(rest) => match home_dir() {
+                Some(home) => home.join(rest.trim_start_matches('/')),
+                None => continue,
+            },
+            None => PathBuf::from(value),
+        };
+        dirs.insert(name.to_string(), dir);
+    }
+    dirs
+}
+
+pub fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+/// The user's configuration folder, e.g. `~/.config`.
+pub fn config_home() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home_dir().map(|home| home.join(".config")))
+}
+
+/// The user's data folder, e.g. `~/.local/share`.
+pub fn data_home() -> Option<PathBuf> {
+    env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home_dir().map(|home| home.join(".local").join("share")))
+}
+
+/// Directory with Organizer's per-user configuration.
+pub fn config_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("XDG_CONFIG_HOME") {
+        return PathBuf::from(dir).join("organizer");
+    }
+    if let Some(dir) = env::var_os("APPDATA") {
+        return PathBuf::from(dir).join("Organizer");
+    }
+    match config_home() {
+        Some(config_home) => config_home.join("organizer"),
+        None => PathBuf::from(".organizer"),
+    }
+}
+
+/// Directory where Organizer keeps its persistent state.
+pub fn data_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("XDG_DATA_HOME") {
+        return PathBuf::from(dir).join("organizer");
+    }
+    if let Some(dir) = env::var_os("APPDATA") {
+        return PathBuf::from(dir).join("Organizer");
+    }
+    match home_dir() {
+        Some(home) => home.join(".local").join("share").join("organizer"),
+        None => PathBuf::from(".organizer"),
+    }
+}
+
+/// Reads the rules from `dir` instead of the current directory from now on.
+pub fn set_rules_dir(dir: PathBuf) {
+    let _ = RULES_DIR.set(dir);
+}
+
+/// Reads the rules from the file `path` instead of `rules.json`. The Lua script and the
+/// other files the rules refer to are looked for in the folder it is in.
+pub fn set_rules_file(path: PathBuf) {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).map_or_else(|| PathBuf::from("."), Path::to_path_buf);
+    set_rules_dir(dir);
+    let _ = RULES_FILE.set(path);
+}
+
+/// The file set with [`set_rules_file`], if any.
+pub fn rules_file() -> Option<PathBuf> {
+    RULES_FILE.get().cloned()
+}
+
+/// Directory holding `rules.json` and the Lua script: the current directory, unless a
+/// profile names another.
+pub fn rules_dir() -> PathBuf {
+    RULES_DIR.get().cloned().unwrap_or_else(|| PathBuf::from("."))
+}
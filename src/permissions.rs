@@ -0,0 +1,221 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// The permissions a rule's files should have: an octal mode such as `0640`, or chmod-style
+/// changes such as `a-x` or `g+w,o-rwx` that leave the other bits as they are.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(try_from = "String", into = "String")]
+pub struct Permissions {
+    text: String,
+    change: Change,
+}
+
+#[derive(Clone)]
+enum Change {
+    Mode(u32),
+    Clauses(Vec<Clause>),
+}
+
+#[derive(Clone)]
+struct Clause {
+    /// The user, group and other bits the clause applies to.
+    who: u32,
+    operator: char,
+    /// Read, write and execute bits, repeated for user, group and other.
+    bits: u32,
+}
+
+impl TryFrom<String> for Permissions {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        let change = parse(&text).ok_or_else(|| format!("invalid permissions {:?}, expected e.g. 0640 or a-x", text))?;
+        Ok(Permissions { text, change })
+    }
+}
+
+impl From<Permissions> for String {
+    fn from(permissions: Permissions) -> String {
+        permissions.text
+    }
+}
+
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+fn parse(text: &str) -> Option<Change> {
+    if !text.is_empty() && text.chars().all(|c| c.is_digit(8)) {
+        let mode = u32::from_str_radix(text, 8).ok()?;
+        return (mode <= 0o7777).then_some(Change::Mode(mode));
+    }
+    text.split(',').map(parse_clause).collect::<Option<_>>().map(Change::Clauses)
+}
+
+fn parse_clause(clause: &str) -> Option<Clause> {
+    let split = clause.find(['+', '-', '='])?;
+    let (who, rest) = clause.split_at(split);
+    let mut chars = rest.chars();
+    let operator = chars.next()?;
+    let mut who_bits = 0;
+    for c in who.chars() {
+        who_bits |= match c {
+            'u' => 0o700,
+            'g' => 0o070,
+            'o' => 0o007,
+            'a' => 0o777,
+            _ => return None,
+        };
+    }
+    let mut bits = 0;
+    for c in chars {
+        bits |= match c {
+            'r' => 0o444,
+            'w' => 0o222,
+            'x' => 0o111,
+            _ => return None,
+        };
+    }
+    Some(Clause {
+        who: if who_bits == 0 { 0o777 } else { who_bits },
+        operator,
+        bits,
+    })
+}
+
+impl Permissions {
+    /// The permission bits a file with `mode` should have.
+    pub fn apply(&self, mode: u32) -> u32 {
+        let mode = mode & 0o7777;
+        match &self.change {
+            Change::Mode(wanted) => *wanted,
+            Change::Clauses(clauses) => clauses.iter().fold(mode, |mode, clause| {
+                let bits = clause.bits & clause.who;
+                match clause.operator {
+                    '+' => mode | bits,
+                    '-' => mode & !bits,
+                    _ => (mode & !clause.who) | bits,
+                }
+            }),
+        }
+    }
+}
+
+/// The permission bits of a file; `None` where files have no Unix permissions.
+pub fn mode(file_path: &Path) -> io::Result<Option<u32>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        Ok(Some(std::fs::metadata(file_path)?.permissions().mode() & 0o7777))
+    }
+    #[cfg(not(unix))]
+    {
+        std::fs::metadata(file_path)?;
+        Ok(None)
+    }
+}
+
+pub fn set_mode(file_path: &Path, mode: u32) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(file_path, std::fs::Permissions::from_mode(mode))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (file_path, mode);
+        Err(io::Error::new(io::ErrorKind::Unsupported, "file permissions are only supported on Unix"))
+    }
+}
+
+/// The owner a rule's files are given: `user`, `user:group` or `:group`, by name or number.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(try_from = "String", into = "String")]
+pub struct Owner {
+    text: String,
+    user: Option<String>,
+    group: Option<String>,
+}
+
+impl TryFrom<String> for Owner {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        let (user, group) = text.split_once(':').unwrap_or((&text, ""));
+        let (user, group) = ((!user.is_empty()).then(|| user.to_string()), (!group.is_empty()).then(|| group.to_string()));
+        if user.is_none() && group.is_none() {
+            return Err(format!("invalid owner {:?}, expected user, user:group or :group", text));
+        }
+        Ok(Owner { text, user, group })
+    }
+}
+
+impl From<Owner> for String {
+    fn from(owner: Owner) -> String {
+        owner.text
+    }
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Gives the file to `owner`. Only root can give files to other users; members of a group
+/// can give their own files to it.
+pub fn set_owner(file_path: &Path, owner: &Owner) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let user = owner.user.as_deref().map(lookup_user).transpose()?;
+        let group = owner.group.as_deref().map(lookup_group).transpose()?;
+        std::os::unix::fs::chown(file_path, user, group)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (file_path, &owner.user, &owner.group);
+        Err(io::Error::new(io::ErrorKind::Unsupported, "file owners are only supported on Unix"))
+    }
+}
+
+#[cfg(unix)]
+fn lookup_user(name: &str) -> io::Result<u32> {
+    if let Ok(uid) = name.parse() {
+        return Ok(uid);
+    }
+    let name = std::ffi::CString::new(name).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let status = unsafe { libc::getpwnam_r(name.as_ptr(), &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut found) };
+    if status != 0 {
+        return Err(io::Error::from_raw_os_error(status));
+    }
+    if found.is_null() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("No user named {}", name.to_string_lossy())));
+    }
+    Ok(entry.pw_uid)
+}
+
+#[cfg(unix)]
+fn lookup_group(name: &str) -> io::Result<u32> {
+    if let Ok(gid) = name.parse() {
+        return Ok(gid);
+    }
+    let name = std::ffi::CString::new(name).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let status = unsafe { libc::getgrnam_r(name.as_ptr(), &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut found) };
+    if status != 0 {
+        return Err(io::Error::from_raw_os_error(status));
+    }
+    if found.is_null() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("No group named {}", name.to_string_lossy())));
+    }
+    Ok(entry.gr_gid)
+}
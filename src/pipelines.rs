@@ -0,0 +1,199 @@
+use crate::rules::{self, Rule};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How long `reachable` waits for a service's name to resolve and for it to answer, in all.
+const REACH_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long whether a service answered is taken as still true, so sorts in a row don't each
+/// wait for it.
+const REACH_CACHE: Duration = Duration::from_secs(60);
+
+/// A named group of rules from `pipelines` in `rules.json`, which runs only while the
+/// pipelines it requires and its own conditions are healthy.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Pipeline {
+    /// Names of the rules that belong to the pipeline.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<String>,
+    /// A JSON file with more rules for the pipeline, relative to `rules.json`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub import: Option<String>,
+    /// Pipelines that must be healthy for this one to run.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub requires: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<Condition>,
+}
+
+/// Something outside Organizer a pipeline needs.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    /// A drive or share is mounted at this folder.
+    Mounted(String),
+    /// A service answers at `host:port`.
+    Reachable(String),
+}
+
+impl Condition {
+    fn check(&self, reached: &mut HashMap<String, (Instant, Result<(), String>)>) -> Result<(), String> {
+        match self {
+            Condition::Mounted(folder) if is_mounted(Path::new(folder)) => Ok(()),
+            Condition::Mounted(folder) => Err(format!("nothing is mounted at {}", folder)),
+            Condition::Reachable(address) => match reached.get(address) {
+                Some((checked, result)) if checked.elapsed() < REACH_CACHE => result.clone(),
+                _ => {
+                    let result = reach(address);
+                    reached.insert(address.clone(), (Instant::now(), result.clone()));
+                    result
+                }
+            },
+        }
+    }
+}
+
+/// Whether a service answers at `address` within [`REACH_TIMEOUT`].
+fn reach(address: &str) -> Result<(), String> {
+    let deadline = Instant::now() + REACH_TIMEOUT;
+    // Name lookups can't be given a timeout, so one that takes too long is left to finish
+    // on its own thread.
+    let (tx, rx) = mpsc::channel();
+    let lookup = address.to_string();
+    thread::spawn(move || {
+        let _ = tx.send(lookup.to_socket_addrs().map(Iterator::collect::<Vec<SocketAddr>>));
+    });
+    let addresses = match rx.recv_timeout(REACH_TIMEOUT) {
+        Ok(resolved) => resolved.map_err(|e| format!("can't resolve {}: {}", address, e))?,
+        Err(_) => return Err(format!("{} didn't resolve within {} s", address, REACH_TIMEOUT.as_secs())),
+    };
+    let reached = addresses.iter().any(|socket| {
+        let left = deadline.saturating_duration_since(Instant::now());
+        !left.is_zero() && TcpStream::connect_timeout(socket, left).is_ok()
+    });
+    if reached {
+        Ok(())
+    } else {
+        Err(format!("{} can't be reached", address))
+    }
+}
+
+/// A folder is a mount point when its drive differs from its parent's.
+#[cfg(unix)]
+fn is_mounted(folder: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    let (Ok(metadata), Some(parent)) = (fs::metadata(folder), folder.parent()) else { return false };
+    metadata.is_dir() && fs::metadata(parent).is_ok_and(|parent| parent.dev() != metadata.dev())
+}
+
+/// Drive letters and shares have no parent to compare with, so a folder that exists counts.
+#[cfg(not(unix))]
+fn is_mounted(folder: &Path) -> bool {
+    folder.is_dir()
+}
+
+#[derive(Deserialize)]
+struct Imported {
+    #[serde(deserialize_with = "rules::deserialize")]
+    rules: Vec<Rule>,
+}
+
+/// Reads the rules each pipeline imports from `dir`. Imported rules without a name take the
+/// pipeline's, and belong to it.
+pub fn import(pipelines: &mut BTreeMap<String, Pipeline>, dir: &Path) -> Result<Vec<Rule>, String> {
+    let mut imported = Vec::new();
+    for (name, pipeline) in pipelines.iter_mut() {
+        let Some(file) = &pipeline.import else { continue };
+        let path = dir.join(file);
+        let contents = fs::read_to_string(&path).map_err(|e| format!("pipeline `{}` can't read {}: {}", name, path.display(), e))?;
+        let rules: Imported = serde_json::from_str(&contents).map_err(|e| format!("pipeline `{}` imports an invalid {}: {}", name, path.display(), e))?;
+        for mut rule in rules.rules {
+            let rule_name = rule.name.get_or_insert_with(|| name.clone()).clone();
+            if !pipeline.rules.contains(&rule_name) {
+                pipeline.rules.push(rule_name);
+            }
+            imported.push(rule);
+        }
+    }
+    Ok(imported)
+}
+
+/// Fails for pipelines with unknown rules or requirements, and for pipelines that require
+/// each other.
+pub fn validate(pipelines: &BTreeMap<String, Pipeline>, rules: &[Rule]) -> Result<(), String> {
+    for (name, pipeline) in pipelines {
+        if let Some(missing) = pipeline.requires.iter().find(|required| !pipelines.contains_key(*required)) {
+            return Err(format!("pipeline `{}` requires `{}`, which is not defined", name, missing));
+        }
+        if let Some(missing) = pipeline.rules.iter().find(|rule| !rules.iter().any(|known| known.is_named(rule))) {
+            return Err(format!("pipeline `{}` has no rule named `{}`", name, missing));
+        }
+    }
+    order(pipelines).map(|_| ())
+}
+
+/// The pipelines, each after those it requires.
+fn order(pipelines: &BTreeMap<String, Pipeline>) -> Result<Vec<String>, String> {
+    let mut ordered: Vec<String> = Vec::with_capacity(pipelines.len());
+    while ordered.len() < pipelines.len() {
+        let ready = pipelines
+            .iter()
+            .find(|(name, pipeline)| !ordered.contains(name) && pipeline.requires.iter().all(|required| ordered.contains(required)));
+        match ready {
+            Some((name, _)) => ordered.push(name.clone()),
+            None => {
+                let waiting: Vec<&str> = pipelines.keys().filter(|name| !ordered.contains(name)).map(String::as_str).collect();
+                return Err(format!("pipelines {} require each other", waiting.join(", ")));
+            }
+        }
+    }
+    Ok(ordered)
+}
+
+/// Whether each pipeline ran in the last sort, to log only when that changes, and which
+/// services answered lately.
+#[derive(Default)]
+pub struct PipelineHealth {
+    running: BTreeMap<String, bool>,
+    reached: HashMap<String, (Instant, Result<(), String>)>,
+}
+
+impl PipelineHealth {
+    /// Which pipelines are unhealthy, with why. Pipelines are checked after those they
+    /// require, so a stopped one stops those that require it as well. Requirements that are
+    /// not defined were refused with the rules, and count as healthy.
+    pub fn check(&mut self, pipelines: &BTreeMap<String, Pipeline>) -> BTreeMap<String, String> {
+        let mut unhealthy = BTreeMap::new();
+        for name in order(pipelines).unwrap_or_else(|_| pipelines.keys().cloned().collect()) {
+            let pipeline = &pipelines[&name];
+            let reason = match pipeline.requires.iter().find(|required| unhealthy.contains_key(*required)) {
+                Some(required) => Some(format!("it requires `{}`, which is not running", required)),
+                None => pipeline.conditions.iter().find_map(|condition| condition.check(&mut self.reached).err()),
+            };
+            if let Some(reason) = reason {
+                unhealthy.insert(name, reason);
+            }
+        }
+        unhealthy
+    }
+
+    /// Logs the pipelines that start and stop with `unhealthy`.
+    pub fn update(&mut self, pipelines: &BTreeMap<String, Pipeline>, unhealthy: &BTreeMap<String, String>) {
+        let mut running = BTreeMap::new();
+        for name in order(pipelines).unwrap_or_else(|_| pipelines.keys().cloned().collect()) {
+            match (unhealthy.get(&name), self.running.get(&name)) {
+                (Some(reason), Some(true) | None) => warn!("Pipeline `{}` is not running: {}", name, reason),
+                (None, Some(false) | None) => info!("Starting pipeline `{}`", name),
+                _ => {}
+            }
+            running.insert(name.clone(), !unhealthy.contains_key(&name));
+        }
+        self.running = running;
+    }
+}
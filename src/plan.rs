@@ -0,0 +1,421 @@
+use crate::remote;
+use crate::report::RuleMatch;
+use crate::statefile;
+use crate::transfer;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use tracing::{debug, warn};
+
+/// A sort worked out by `organizer plan` and carried out later by `organizer apply`.
+#[derive(Serialize, Deserialize)]
+pub struct Plan {
+    /// The folder that was sorted.
+    pub folder: PathBuf,
+    /// When the plan was made, in seconds since the epoch.
+    pub created: i64,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Move,
+    Copy,
+}
+
+impl Action {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Move => "move",
+            Action::Copy => "copy",
+        }
+    }
+}
+
+/// One file of the plan, with what it looked like when the plan was made.
+#[derive(Serialize, Deserialize)]
+pub struct Step {
+    pub file: PathBuf,
+    pub rule: String,
+    pub action: Action,
+    /// The destination as the rule gave it, which its actions are configured for.
+    #[serde(default)]
+    pub template: String,
+    /// What else happens to the file as it is placed, by the names of the rule options and
+    /// destination actions, such as `rename` or `tag`.
+    #[serde(default)]
+    pub actions: Vec<String>,
+    pub destination: PathBuf,
+    pub size: u64,
+    /// When the file was last modified, in nanoseconds since the epoch.
+    pub modified: u64,
+}
+
+impl Plan {
+    pub fn new(folder: &Path) -> Self {
+        Plan {
+            folder: folder.to_path_buf(),
+            created: Local::now().timestamp(),
+            steps: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a plan: {}", path.display(), e)))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        statefile::write(path, serde_json::to_string_pretty(self)?.as_bytes())
+    }
+
+    /// Adds a file the sort would place, with the `actions` it would get. Only regular files
+    /// moved or copied into local folders can be applied later; links and uploads are left out.
+    pub fn add(&mut self, file: &Path, matched: &RuleMatch, destination: &Path, actions: Vec<String>) {
+        let metadata = match fs::symlink_metadata(file) {
+            Ok(metadata) if metadata.is_file() && destination.is_absolute() => metadata,
+            _ => {
+                debug!(file = ?file, "Left out of the plan, only files placed in local folders are planned");
+                return;
+            }
+        };
+        self.steps.push(Step {
+            file: file.to_path_buf(),
+            rule: matched.rule.clone(),
+            action: if matched.copy { Action::Copy } else { Action::Move },
+            template: matched.destination.clone(),
+            actions,
+            destination: destination.to_path_buf(),
+            size: metadata.len(),
+            modified: modified(&metadata).unwrap_or_default(),
+        });
+    }
+
+    /// The steps that can't be applied as planned any more, with why: their file changed or
+    /// went, or something else took their destination.
+    pub fn conflicts(&self) -> Vec<(&Step, &'static str)> {
+        let mut destinations = HashSet::new();
+        let mut conflicts = Vec::new();
+        for step in &self.steps {
+            let why = match fs::symlink_metadata(&step.file) {
+                Err(_) => Some("is gone"),
+                Ok(metadata) if metadata.len() != step.size || modified(&metadata) != Some(step.modified) => Some("changed since the plan was made"),
+                Ok(_) if fs::symlink_metadata(&step.destination).is_ok() => Some("has its destination taken by another file"),
+                Ok(_) if !destinations.insert(&step.destination) => Some("has the same destination as another file of the plan"),
+                Ok(_) => None,
+            };
+            conflicts.extend(why.map(|why| (step, why)));
+        }
+        conflicts
+    }
+}
+
+fn modified(metadata: &fs::Metadata) -> Option<u64> {
+    let since_epoch = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    u64::try_from(since_epoch.as_nanos()).ok()
+}
+
+/// A plan being carried out by a sort, which places its files as the plan says instead of as
+/// the rules decide. The files placed are remembered, so they can be put back when a later
+/// one can't be placed as planned.
+pub struct Replay {
+    steps: HashMap<PathBuf, Vec<Step>>,
+    progress: Mutex<Progress>,
+}
+
+#[derive(Default)]
+struct Progress {
+    /// Files placed just where the plan says, as (where they were, action, where they are).
+    placed: Vec<(PathBuf, Action, PathBuf)>,
+    /// Files that ended up elsewhere, e.g. converted or compressed, which can't be put back.
+    kept: usize,
+    /// The first file that wasn't placed as planned.
+    failed: Option<PathBuf>,
+    /// Files the sort came to, placed or not.
+    visited: HashSet<PathBuf>,
+}
+
+impl Replay {
+    pub fn new(plan: Plan) -> Self {
+        let mut steps: HashMap<PathBuf, Vec<Step>> = HashMap::new();
+        for step in plan.steps {
+            // The sort resolves the folders of the files it is given, as they are looked up.
+            let file = match (step.file.parent(), step.file.file_name()) {
+                (Some(parent), Some(name)) => fs::canonicalize(parent).map_or_else(|_| step.file.clone(), |parent| parent.join(name)),
+                _ => step.file.clone(),
+            };
+            steps.entry(file).or_default().push(step);
+        }
+        Replay {
+            steps,
+            progress: Mutex::default(),
+        }
+    }
+
+    fn progress(&self) -> std::sync::MutexGuard<'_, Progress> {
+        self.progress.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// The steps of `file`, in the order they were planned, and remembers the sort came to it.
+    pub fn visit(&self, file: &Path) -> &[Step] {
+        self.progress().visited.insert(file.to_path_buf());
+        self.steps(file)
+    }
+
+    fn steps(&self, file: &Path) -> &[Step] {
+        self.steps.get(file).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Where the plan puts `file` when `rule` moves it, or copies it when `copy` is set.
+    pub fn target(&self, file: &Path, rule: &str, copy: bool) -> Option<&Path> {
+        let action = if copy { Action::Copy } else { Action::Move };
+        let step = self.steps(file).iter().find(|step| step.rule == rule && step.action == action)?;
+        Some(&step.destination)
+    }
+
+    pub fn placed(&self, file: &Path, copy: bool, destination: &Path) {
+        let action = if copy { Action::Copy } else { Action::Move };
+        let planned = self.steps(file).iter().any(|step| step.action == action && step.destination == destination);
+        let mut progress = self.progress();
+        if planned {
+            progress.placed.push((file.to_path_buf(), action, destination.to_path_buf()));
+        } else {
+            progress.kept += 1;
+        }
+    }
+
+    pub fn fail(&self, file: &Path) {
+        self.progress().failed.get_or_insert_with(|| file.to_path_buf());
+    }
+
+    /// The first file that wasn't placed as planned, if any.
+    pub fn failed(&self) -> Option<PathBuf> {
+        self.progress().failed.clone()
+    }
+
+    /// A file of the plan the sort never came to, e.g. because it was stopped or the file is
+    /// in a folder sorts leave alone now.
+    pub fn missed(&self) -> Option<PathBuf> {
+        let progress = self.progress();
+        self.steps.keys().find(|file| !progress.visited.contains(*file)).cloned()
+    }
+
+    /// Puts back the files placed so far, the last first: moved files go back where they
+    /// were and copies are removed. Returns the moves put back, as (from, to), and how many
+    /// files stay where they are.
+    pub fn undo(&self, settings: &transfer::Settings) -> (Vec<(PathBuf, PathBuf)>, usize) {
+        let mut progress = self.progress();
+        let mut moved_back = Vec::new();
+        let mut kept = progress.kept;
+        for (file, action, destination) in progress.placed.drain(..).rev() {
+            // A link the rule left at the old place makes way for the file.
+            if action == Action::Move && fs::read_link(&file).is_ok_and(|link| link == destination) {
+                let _ = fs::remove_file(&file);
+            }
+            let undone = match action {
+                // Nothing here can take a file back from another machine.
+                _ if remote::is_remote(&destination.to_string_lossy()) => Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "it was uploaded to another machine, so it stays there and can't be put back",
+                )),
+                Action::Move if fs::symlink_metadata(&file).is_ok() => Err(io::Error::new(io::ErrorKind::AlreadyExists, "its old place is taken")),
+                Action::Move => transfer::move_file(&destination, &file, settings).map(|_| ()),
+                Action::Copy => fs::remove_file(&destination),
+            };
+            match undone {
+                Ok(()) if action == Action::Move => moved_back.push((destination, file)),
+                Ok(()) => {}
+                Err(e) => {
+                    warn!(file = ?file, "Failed to undo {} to {:?}: {}", action.as_str(), destination, e);
+                    kept += 1;
+                }
+            }
+        }
+        (moved_back, kept)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    fn folder(name: &str) -> TestDir {
+        let folder = TestDir::new("plan", name);
+        fs::create_dir_all(folder.join("Documents")).unwrap();
+        folder
+    }
+
+    /// A step for `name` in `folder`, which is written as the plan is made.
+    fn step(folder: &Path, name: &str, action: Action, destination: &str) -> Step {
+        let file = folder.join(name);
+        fs::write(&file, name).unwrap();
+        let metadata = fs::metadata(&file).unwrap();
+        Step {
+            file,
+            rule: ".pdf".to_string(),
+            action,
+            template: "Documents".to_string(),
+            actions: Vec::new(),
+            destination: folder.join(destination),
+            size: metadata.len(),
+            modified: modified(&metadata).unwrap(),
+        }
+    }
+
+    fn plan(folder: &Path, steps: Vec<Step>) -> Plan {
+        Plan {
+            steps,
+            ..Plan::new(folder)
+        }
+    }
+
+    fn conflicts(plan: &Plan) -> Vec<(PathBuf, &'static str)> {
+        plan.conflicts().into_iter().map(|(step, why)| (step.file.clone(), why)).collect()
+    }
+
+    #[test]
+    fn finds_no_conflicts_when_nothing_changed() {
+        let folder = folder("unchanged");
+        let plan = plan(&folder, vec![step(&folder, "a.pdf", Action::Move, "Documents/a.pdf")]);
+        assert!(plan.conflicts().is_empty());
+    }
+
+    #[test]
+    fn finds_files_that_changed_or_went_since_the_plan() {
+        let folder = folder("changed");
+        let plan = plan(
+            &folder,
+            vec![
+                step(&folder, "changed.pdf", Action::Move, "Documents/changed.pdf"),
+                step(&folder, "gone.pdf", Action::Move, "Documents/gone.pdf"),
+            ],
+        );
+        fs::write(folder.join("changed.pdf"), "changed since").unwrap();
+        fs::remove_file(folder.join("gone.pdf")).unwrap();
+        assert_eq!(
+            conflicts(&plan),
+            [(folder.join("changed.pdf"), "changed since the plan was made"), (folder.join("gone.pdf"), "is gone")]
+        );
+    }
+
+    #[test]
+    fn finds_destinations_that_are_taken() {
+        let folder = folder("taken");
+        let plan = plan(
+            &folder,
+            vec![
+                step(&folder, "a.pdf", Action::Move, "Documents/a.pdf"),
+                step(&folder, "b.pdf", Action::Copy, "Documents/same.pdf"),
+                step(&folder, "c.pdf", Action::Copy, "Documents/same.pdf"),
+            ],
+        );
+        fs::write(folder.join("Documents/a.pdf"), "another").unwrap();
+        assert_eq!(
+            conflicts(&plan),
+            [
+                (folder.join("a.pdf"), "has its destination taken by another file"),
+                (folder.join("c.pdf"), "has the same destination as another file of the plan"),
+            ]
+        );
+    }
+
+    #[test]
+    fn saves_and_loads_plans() {
+        let folder = folder("saved");
+        let path = folder.join("plan.json");
+        plan(&folder, vec![step(&folder, "a.pdf", Action::Copy, "Documents/a.pdf")]).save(&path).unwrap();
+        let loaded = Plan::load(&path).unwrap();
+        assert_eq!(loaded.folder, folder.to_path_buf());
+        assert_eq!(loaded.steps.len(), 1);
+        assert!(loaded.steps[0].action == Action::Copy);
+        assert_eq!(loaded.steps[0].destination, folder.join("Documents/a.pdf"));
+    }
+
+    #[test]
+    fn refuses_files_that_are_not_plans() {
+        let folder = folder("not-a-plan");
+        fs::write(folder.join("rules.json"), r#"{ "rules": [] }"#).unwrap();
+        assert_eq!(Plan::load(&folder.join("rules.json")).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn places_files_where_the_plan_says_for_their_rule_and_action() {
+        let folder = folder("target");
+        let replay = Replay::new(plan(&folder, vec![step(&folder, "a.pdf", Action::Move, "Documents/a.pdf")]));
+        let file = folder.join("a.pdf");
+        assert_eq!(replay.target(&file, ".pdf", false), Some(folder.join("Documents/a.pdf").as_path()));
+        assert_eq!(replay.target(&file, ".pdf", true), None);
+        assert_eq!(replay.target(&file, ".txt", false), None);
+    }
+
+    #[test]
+    fn tells_which_files_the_sort_never_came_to() {
+        let folder = folder("missed");
+        let replay = Replay::new(plan(&folder, vec![step(&folder, "a.pdf", Action::Move, "Documents/a.pdf")]));
+        assert_eq!(replay.missed(), Some(folder.join("a.pdf")));
+        assert_eq!(replay.visit(&folder.join("a.pdf")).len(), 1);
+        assert_eq!(replay.missed(), None);
+    }
+
+    #[test]
+    fn remembers_the_first_file_that_failed() {
+        let folder = folder("failed");
+        let replay = Replay::new(plan(&folder, Vec::new()));
+        replay.fail(&folder.join("a.pdf"));
+        replay.fail(&folder.join("b.pdf"));
+        assert_eq!(replay.failed(), Some(folder.join("a.pdf")));
+    }
+
+    #[test]
+    fn undo_puts_moved_files_back_and_removes_copies() {
+        let folder = folder("undo");
+        let replay = Replay::new(plan(
+            &folder,
+            vec![
+                step(&folder, "moved.pdf", Action::Move, "Documents/moved.pdf"),
+                step(&folder, "copied.pdf", Action::Copy, "Documents/copied.pdf"),
+            ],
+        ));
+        fs::rename(folder.join("moved.pdf"), folder.join("Documents/moved.pdf")).unwrap();
+        replay.placed(&folder.join("moved.pdf"), false, &folder.join("Documents/moved.pdf"));
+        fs::copy(folder.join("copied.pdf"), folder.join("Documents/copied.pdf")).unwrap();
+        replay.placed(&folder.join("copied.pdf"), true, &folder.join("Documents/copied.pdf"));
+
+        let (moved_back, kept) = replay.undo(&transfer::Settings::default());
+        assert_eq!(moved_back, [(folder.join("Documents/moved.pdf"), folder.join("moved.pdf"))]);
+        assert_eq!(kept, 0);
+        assert!(folder.join("moved.pdf").exists());
+        assert!(folder.join("copied.pdf").exists());
+        assert!(!folder.join("Documents/moved.pdf").exists());
+        assert!(!folder.join("Documents/copied.pdf").exists());
+    }
+
+    #[test]
+    fn undo_leaves_files_whose_old_place_was_taken_or_that_went_elsewhere() {
+        let folder = folder("undo-kept");
+        let replay = Replay::new(plan(
+            &folder,
+            vec![
+                step(&folder, "a.pdf", Action::Move, "Documents/a.pdf"),
+                step(&folder, "b.pdf", Action::Move, "Documents/b.pdf"),
+            ],
+        ));
+        fs::rename(folder.join("a.pdf"), folder.join("Documents/a.pdf")).unwrap();
+        replay.placed(&folder.join("a.pdf"), false, &folder.join("Documents/a.pdf"));
+        fs::write(folder.join("a.pdf"), "newer").unwrap();
+        // Converted on the way, so not where the plan put it.
+        replay.placed(&folder.join("b.pdf"), false, &folder.join("Documents/b.txt"));
+
+        let (moved_back, kept) = replay.undo(&transfer::Settings::default());
+        assert!(moved_back.is_empty());
+        assert_eq!(kept, 2);
+        assert!(folder.join("Documents/a.pdf").exists());
+    }
+}
@@ -0,0 +1,315 @@
+use crate::capabilities::{self, Grants};
+use crate::media;
+use crate::paths;
+use crate::timing::{self, Stage};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tracing::warn;
+
+/// Version of the interface between Organizer and plugins. Plugins export
+/// `organizer_plugin_abi() -> i32` returning it, and are refused when it differs.
+pub const ABI_VERSION: i32 = 1;
+
+/// What a plugin decided for a file.
+#[derive(Deserialize)]
+pub struct PluginMatch {
+    pub destination: String,
+    #[serde(default)]
+    pub copy: bool,
+}
+
+/// Everything a plugin learns about a file, passed to `match_file` as JSON.
+#[derive(Serialize)]
+struct FileMeta<'a> {
+    abi: i32,
+    path: &'a str,
+    name: &'a str,
+    extension: Option<&'a str>,
+    size: u64,
+    /// Seconds since the Unix epoch.
+    modified: Option<u64>,
+    audio: Option<AudioMeta>,
+    exif: Option<ExifMeta>,
+}
+
+#[derive(Serialize)]
+struct AudioMeta {
+    artist: Option<String>,
+    album: Option<String>,
+    title: Option<String>,
+    genre: Option<String>,
+    year: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct ExifMeta {
+    date: Option<String>,
+    camera: Option<String>,
+}
+
+/// The WebAssembly plugins from `rules.json`, compiled once and shared by all workers.
+#[derive(Clone, Default)]
+pub struct Plugins {
+    loaded: Arc<Vec<Loaded>>,
+}
+
+#[derive(Clone)]
+struct Loaded {
+    plugin: runtime::Plugin,
+    /// Hash of the plugin's file when it was compiled.
+    hash: blake3::Hash,
+}
+
+impl Plugins {
+    /// Compiles the plugins at `paths`, in the order they are asked, with the capabilities
+    /// `grants` gives them.
+    pub fn load(plugin_paths: &[String], grants: &Grants) -> io::Result<Plugins> {
+        let mut loaded = Vec::new();
+        for path in plugin_paths {
+            let path = paths::resolve_path(path);
+            // The plugin is read once, so what is granted, hashed and compiled is the same.
+            let bytes = fs::read(&path).map_err(|e| io::Error::new(e.kind(), format!("Plugin {}: {}", path.display(), e)))?;
+            let granted = capabilities::granted(&path, &bytes, grants).map_err(|e| io::Error::new(io::ErrorKind::PermissionDenied, e))?;
+            let hash = blake3::hash(&bytes);
+            let plugin = runtime::Plugin::load(&path, &bytes, &granted)
+                .map_err(|e| io::Error::new(e.kind(), format!("Plugin {}: {}", path.display(), e)))?;
+            loaded.push(Loaded { plugin, hash });
+        }
+        Ok(Plugins { loaded: Arc::new(loaded) })
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.loaded.iter().map(|loaded| loaded.plugin.name())
+    }
+
+    /// Hashes of the plugins' files, in the order they are asked, so what they decided can be
+    /// told apart from what changed plugins would.
+    pub fn hashes(&self) -> impl Iterator<Item = &blake3::Hash> {
+        self.loaded.iter().map(|loaded| &loaded.hash)
+    }
+
+    /// Keeps only the plugins whose names `keep` accepts.
+    pub fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        let loaded = self.loaded.iter().filter(|loaded| keep(loaded.plugin.name())).cloned().collect();
+        self.loaded = Arc::new(loaded);
+    }
+
+    /// Asks the plugins in order and returns the first decision with the plugin's name. A
+    /// plugin that fails is logged and passed over.
+    pub fn decide(&self, file_path: &Path) -> Option<(String, PluginMatch)> {
+        if self.loaded.is_empty() {
+            return None;
+        }
+        let input = timing::time("plugins", Stage::Metadata, || file_meta(file_path))?;
+        self.loaded.iter().find_map(|Loaded { plugin, .. }| match timing::time(plugin.name(), Stage::Plugin, || plugin.decide(file_path, &input)) {
+            Ok(decided) => decided.map(|decided| (plugin.name().to_string(), decided)),
+            Err(e) => {
+                warn!(file = ?file_path, "Plugin {} failed: {}", plugin.name(), e);
+                None
+            }
+        })
+    }
+}
+
+/// The file's metadata as JSON, as plugins and `exec` commands get it.
+pub fn file_meta(file_path: &Path) -> Option<Vec<u8>> {
+    let metadata = fs::metadata(file_path).ok()?;
+    let meta = FileMeta {
+        abi: ABI_VERSION,
+        path: file_path.to_str()?,
+        name: file_path.file_name()?.to_str()?,
+        extension: file_path.extension().and_then(|extension| extension.to_str()),
+        size: metadata.len(),
+        modified: metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|age| age.as_secs()),
+        audio: media::read_audio_tags(file_path).map(|tags| AudioMeta {
+            artist: tags.artist,
+            album: tags.album,
+            title: tags.title,
+            genre: tags.genre,
+            year: tags.year,
+        }),
+        exif: media::read_exif(file_path).map(|exif| ExifMeta {
+            date: exif.date.map(|date| date.format("%Y-%m-%dT%H:%M:%S").to_string()),
+            camera: exif.camera,
+        }),
+    };
+    serde_json::to_vec(&meta).ok()
+}
+
+#[cfg(feature = "plugins")]
+mod runtime {
+    use super::{PluginMatch, ABI_VERSION};
+    use crate::capabilities::Capability;
+    use std::collections::BTreeSet;
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::path::{Path, PathBuf};
+    use wasmtime::{Caller, Config, Engine, Extern, InstancePre, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+    /// Instructions a plugin may run per file before it is stopped.
+    const FUEL: u64 = 50_000_000;
+    /// Memory a plugin may grow to.
+    const MEMORY: usize = 64 * 1024 * 1024;
+    /// Longest answer read back from a plugin.
+    const MAX_ANSWER: usize = 64 * 1024;
+    /// Module of the host functions plugins import.
+    const HOST: &str = "organizer";
+
+    #[derive(Clone)]
+    pub struct Plugin {
+        name: String,
+        engine: Engine,
+        instance: InstancePre<Host>,
+    }
+
+    /// What a plugin's instance can reach: its limits and the file it decides for.
+    struct Host {
+        limits: StoreLimits,
+        file: PathBuf,
+    }
+
+    fn to_io(error: wasmtime::Error) -> io::Error {
+        io::Error::other(error.to_string())
+    }
+
+    impl Plugin {
+        pub fn name(&self) -> &str {
+            &self.name
+        }
+
+        /// Compiles the plugin read from `path` as `bytes`.
+        pub fn load(path: &Path, bytes: &[u8], granted: &BTreeSet<Capability>) -> io::Result<Plugin> {
+            let mut config = Config::new();
+            config.consume_fuel(true);
+            let engine = Engine::new(&config).map_err(to_io)?;
+            let module = Module::new(&engine, bytes).map_err(to_io)?;
+            // Plugins get no files, network, clock or environment, only the host functions
+            // their granted capabilities allow; what they import is what they declare.
+            for import in module.imports() {
+                match (import.module(), import.name()) {
+                    (HOST, "read_content") if granted.contains(&Capability::ReadContent) => {}
+                    (HOST, "read_content") => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::PermissionDenied,
+                            format!("needs {}, which `grants` in rules.json doesn't give it", Capability::ReadContent),
+                        ))
+                    }
+                    (module, name) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("imports {}::{}, which plugins can't import", module, name),
+                        ))
+                    }
+                }
+            }
+            let mut linker = Linker::new(&engine);
+            if granted.contains(&Capability::ReadContent) {
+                linker.func_wrap(HOST, "read_content", read_content).map_err(to_io)?;
+            }
+            let plugin = Plugin {
+                name: path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+                instance: linker.instantiate_pre(&module).map_err(to_io)?,
+                engine,
+            };
+            let mut store = plugin.store(PathBuf::new())?;
+            let instance = plugin.instance.instantiate(&mut store).map_err(to_io)?;
+            let abi = instance
+                .get_typed_func::<(), i32>(&mut store, "organizer_plugin_abi")
+                .and_then(|abi| abi.call(&mut store, ()))
+                .map_err(to_io)?;
+            if abi != ABI_VERSION {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("built for plugin ABI {}, but this Organizer speaks {}", abi, ABI_VERSION),
+                ));
+            }
+            Ok(plugin)
+        }
+
+        /// A fresh store for every file, so nothing carries over between files.
+        fn store(&self, file: PathBuf) -> io::Result<Store<Host>> {
+            let limits = StoreLimitsBuilder::new().memory_size(MEMORY).build();
+            let mut store = Store::new(&self.engine, Host { limits, file });
+            store.limiter(|host| &mut host.limits);
+            store.set_fuel(FUEL).map_err(to_io)?;
+            Ok(store)
+        }
+
+        /// Calls `match_file` with the file's metadata. It returns 0 when it has no opinion,
+        /// or the address in the upper and the length in the lower 32 bits of its JSON answer.
+        pub fn decide(&self, file_path: &Path, input: &[u8]) -> io::Result<Option<PluginMatch>> {
+            let mut store = self.store(file_path.to_path_buf())?;
+            let instance = self.instance.instantiate(&mut store).map_err(to_io)?;
+            let memory = instance
+                .get_memory(&mut store, "memory")
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "exports no memory"))?;
+            let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc").map_err(to_io)?;
+            let match_file = instance.get_typed_func::<(i32, i32), i64>(&mut store, "match_file").map_err(to_io)?;
+
+            let length = input.len() as i32;
+            let address = alloc.call(&mut store, length).map_err(to_io)?;
+            memory.write(&mut store, address as u32 as usize, input).map_err(to_io)?;
+            let answer = match_file.call(&mut store, (address, length)).map_err(to_io)?;
+            if answer == 0 {
+                return Ok(None);
+            }
+            let (address, length) = ((answer >> 32) as u32 as usize, (answer & 0xffff_ffff) as usize);
+            if length > MAX_ANSWER {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("answer of {} bytes is too long", length)));
+            }
+            let mut json = vec![0; length];
+            memory.read(&store, address, &mut json).map_err(to_io)?;
+            serde_json::from_slice(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("invalid answer: {}", e)))
+        }
+    }
+
+    /// `organizer::read_content(address, length)`: copies up to `length` bytes from the start
+    /// of the file to `address` and returns how many, or -1 when the file can't be read.
+    fn read_content(mut caller: Caller<'_, Host>, address: i32, length: i32) -> i32 {
+        let Some(Extern::Memory(memory)) = caller.get_export("memory") else { return -1 };
+        let mut content = Vec::new();
+        let read = File::open(&caller.data().file).and_then(|file| file.take(length.max(0) as u64).read_to_end(&mut content));
+        if read.is_err() || memory.write(&mut caller, address as u32 as usize, &content).is_err() {
+            return -1;
+        }
+        content.len() as i32
+    }
+}
+
+#[cfg(not(feature = "plugins"))]
+mod runtime {
+    use super::PluginMatch;
+    use crate::capabilities::Capability;
+    use std::collections::BTreeSet;
+    use std::io;
+    use std::path::Path;
+
+    /// No plugin can be loaded without the `plugins` feature.
+    #[derive(Clone)]
+    pub enum Plugin {}
+
+    impl Plugin {
+        pub fn name(&self) -> &str {
+            match *self {}
+        }
+
+        pub fn load(_path: &Path, _bytes: &[u8], _granted: &BTreeSet<Capability>) -> io::Result<Plugin> {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "WebAssembly plugins are not enabled in this build",
+            ))
+        }
+
+        pub fn decide(&self, _file_path: &Path, _input: &[u8]) -> io::Result<Option<PluginMatch>> {
+            match *self {}
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use crate::paths;
+use crate::RulesConfig;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{error, warn};
+
+/// Machine-wide policy controlled by administrators. It constrains what user
+/// rules may do and cannot be overridden by them.
+#[derive(Deserialize, Default)]
+pub struct MachinePolicy {
+    /// Destinations files may be moved to; globs are allowed. Empty means unrestricted.
+    #[serde(default)]
+    pub allowed_destinations: Vec<String>,
+    /// Actions user rules may not use, e.g. `transcode` or `delete-source`.
+    #[serde(default)]
+    pub banned_actions: Vec<String>,
+}
+
+/// Folder with the machine-wide files administrators control.
+pub fn admin_dir() -> PathBuf {
+    #[cfg(target_os = "windows")]
+    {
+        let program_data = std::env::var_os("ProgramData").unwrap_or_else(|| "C:\\ProgramData".into());
+        PathBuf::from(program_data).join("Organizer")
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        PathBuf::from("/etc/organizer")
+    }
+}
+
+fn policy_path() -> PathBuf {
+    admin_dir().join("policy.json")
+}
+
+/// Loads the machine policy. A policy file that exists but can't be read
+/// allows nothing, so a broken deployment never silently lifts restrictions.
+pub fn load_policy() -> MachinePolicy {
+    let path = policy_path();
+    if !path.exists() {
+        return MachinePolicy::default();
+    }
+    let policy = fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| serde_json::from_str(&contents).map_err(|e| e.to_string()));
+    match policy {
+        Ok(policy) => policy,
+        Err(e) => {
+            error!("Invalid machine policy {:?}, refusing all destinations: {}", path, e);
+            MachinePolicy {
+                allowed_destinations: vec![String::new()],
+                banned_actions: Vec::new(),
+            }
+        }
+    }
+}
+
+impl MachinePolicy {
+    fn is_banned(&self, action: &str) -> bool {
+        self.banned_actions.iter().any(|banned| banned == action)
+    }
+    
+    /// Removes banned actions from a user configuration.
+    pub fn enforce(&self, config: &mut RulesConfig) {
+        if self.banned_actions.is_empty() {
+            return;
+        }
+        if let Some(policy) = config.duplicates {
+            if self.is_banned(policy.as_str()) {
+                warn!("Duplicate policy '{}' is banned by machine policy", policy);
+                config.duplicates = None;
+            }
+        }
+        for (destination, actions) in config.actions.iter_mut() {
+            let ban = |name: &str, enabled: bool| {
+                let banned = enabled && self.is_banned(name);
+                if banned {
+                    warn!("Action '{}' for {} is banned by machine policy", name, destination);
+                }
+                banned
+            };
+            if ban("set_mtime", !actions.set_mtime.is_empty()) {
+                actions.set_mtime.clear();
+            }
+            if ban("auto_orient", actions.auto_orient) {
+                actions.auto_orient = false;
+            }
+            if ban("convert_to", actions.convert_to.is_some()) {
+                actions.convert_to = None;
+            }
+            if ban("transcode", actions.transcode.is_some()) {
+                actions.transcode = None;
+            }
+            if ban("extract", actions.extract.is_some()) {
+                actions.extract = None;
+            }
+            if ban("compress", actions.compress.is_some()) {
+                actions.compress = None;
+            }
+            if ban("hardlink_dedupe", actions.hardlink_dedupe) {
+                actions.hardlink_dedupe = false;
+            }
+            if let Some(policy) = actions.duplicates {
+                if ban(policy.as_str(), true) {
+                    actions.duplicates = None;
+                }
+            }
+        }
+    }
+    
+    pub fn allows_destination(&self, destination: &Path) -> bool {
+        if self.allowed_destinations.is_empty() {
+            return true;
+        }
+        self.allowed_destinations.iter().any(|allowed| {
+            if allowed.is_empty() {
+                return false;
+            }
+            let allowed_path = paths::resolve_path(allowed);
+            if allowed.contains(['*', '?', '[']) {
+                let Ok(pattern) = glob::Pattern::new(&allowed_path.to_string_lossy()) else {
+                    return false;
+                };
+                destination.ancestors().any(|ancestor| pattern.matches_path(ancestor))
+            } else {
+                destination.starts_with(&allowed_path)
+            }
+        })
+    }
+}
@@ -0,0 +1,43 @@
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+use std::process::Command;
+
+/// Whether the user is sitting at an unlocked desktop session, or `None`
+/// when the session state can't be determined on this platform.
+pub fn user_active() -> Option<bool> {
+    screen_locked().map(|locked| !locked)
+}
+
+#[cfg(target_os = "linux")]
+fn screen_locked() -> Option<bool> {
+    // logind tracks the lock state of the user's graphical session.
+    let session = loginctl(&["show-user", &whoami::username(), "--property=Display", "--value"])?;
+    if session.is_empty() {
+        return None;
+    }
+    let locked = loginctl(&["show-session", &session, "--property=LockedHint", "--value"])?;
+    Some(locked == "yes")
+}
+
+#[cfg(target_os = "linux")]
+fn loginctl(args: &[&str]) -> Option<String> {
+    let output = Command::new("loginctl").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(target_os = "windows")]
+fn screen_locked() -> Option<bool> {
+    // The lock screen is drawn by LogonUI, which only runs while the session is locked.
+    let output = Command::new("tasklist")
+        .args(["/FI", "IMAGENAME eq LogonUI.exe", "/NH"])
+        .output()
+        .ok()?;
+    Some(String::from_utf8_lossy(&output.stdout).contains("LogonUI.exe"))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn screen_locked() -> Option<bool> {
+    None
+}
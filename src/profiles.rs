@@ -0,0 +1,254 @@
+use crate::policy;
+use crate::shutdown;
+use clap::Subcommand;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+const PROFILES_FILE: &str = "profiles.json";
+
+/// How often the homes are looked through again for users added since.
+const RESCAN: Duration = Duration::from_secs(60);
+
+/// How long a user's daemon that stopped waits before it is started again.
+const RESTART_DELAY: Duration = Duration::from_secs(30);
+
+/// Sorts the downloads of every user of the machine, each with the rules of their profile.
+#[derive(Subcommand)]
+pub enum ProfilesCommand {
+    /// Show which profile each user gets and which folder is sorted for them
+    List,
+    /// Run a daemon for every user with a profile, restarting those that stop
+    Run {
+        /// Seconds between sorts of each user's folder
+        #[arg(short, long, default_value_t = 10)]
+        interval: u64,
+    },
+}
+
+/// The administrator's `profiles.json`, next to the machine policy.
+#[derive(Deserialize)]
+pub struct ProfilesConfig {
+    /// Globs matching the users' home folders.
+    #[serde(default = "default_homes")]
+    pub homes: Vec<String>,
+    /// The folder sorted for each user, relative to their home, or e.g. `xdg:download`.
+    #[serde(default = "default_folder")]
+    pub folder: String,
+    /// Profiles by name: folders holding the `rules.json`, and optionally the Lua script,
+    /// of the profile.
+    pub profiles: BTreeMap<String, PathBuf>,
+    /// The profile of each user, by the name of their home folder.
+    #[serde(default)]
+    pub users: BTreeMap<String, String>,
+    /// The profile of users not listed; without it they are not sorted.
+    pub default_profile: Option<String>,
+}
+
+fn default_homes() -> Vec<String> {
+    if cfg!(windows) {
+        vec![r"C:\Users\*".to_string()]
+    } else if cfg!(target_os = "macos") {
+        vec!["/Users/*".to_string()]
+    } else {
+        vec!["/home/*".to_string()]
+    }
+}
+
+fn default_folder() -> String {
+    "Downloads".to_string()
+}
+
+/// Home folders that belong to no person.
+const NOT_USERS: &[&str] = &["All Users", "Default", "Default User", "Public", "Shared", "Guest", "lost+found"];
+
+/// A user whose folder is sorted, with the profile they get.
+pub struct UserProfile {
+    pub user: String,
+    pub home: PathBuf,
+    /// The folder sorted, as passed to the user's daemon.
+    pub folder: String,
+    pub profile: String,
+    /// The folder of the profile's rules.
+    pub rules: PathBuf,
+}
+
+/// Loads `profiles.json`; profile folders may be given relative to it.
+pub fn load() -> io::Result<ProfilesConfig> {
+    let path = policy::admin_dir().join(PROFILES_FILE);
+    let contents = fs::read_to_string(&path).map_err(|e| io::Error::new(e.kind(), format!("Failed to read {}: {}", path.display(), e)))?;
+    let mut config: ProfilesConfig =
+        serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid {}: {}", path.display(), e)))?;
+    for rules in config.profiles.values_mut() {
+        if rules.is_relative() {
+            *rules = policy::admin_dir().join(&rules);
+        }
+    }
+    Ok(config)
+}
+
+impl ProfilesConfig {
+    /// The users found in the homes that have a profile.
+    pub fn resolve(&self) -> Vec<UserProfile> {
+        let mut users = Vec::new();
+        for pattern in &self.homes {
+            let homes = match glob::glob(pattern) {
+                Ok(homes) => homes,
+                Err(e) => {
+                    warn!("Invalid home pattern {:?}: {}", pattern, e);
+                    continue;
+                }
+            };
+            for home in homes.flatten().filter(|home| home.is_dir()) {
+                let user = home.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                if user.starts_with('.') || NOT_USERS.contains(&user.as_str()) || users.iter().any(|known: &UserProfile| known.user == user) {
+                    continue;
+                }
+                let Some(profile) = self.users.get(&user).or(self.default_profile.as_ref()) else {
+                    debug!("No profile for {}", user);
+                    continue;
+                };
+                let Some(rules) = self.profiles.get(profile) else {
+                    warn!("Profile '{}' of {} is not defined", profile, user);
+                    continue;
+                };
+                let folder = if self.folder.starts_with("xdg:") {
+                    self.folder.clone()
+                } else {
+                    home.join(&self.folder).to_string_lossy().into_owned()
+                };
+                users.push(UserProfile {
+                    user,
+                    folder,
+                    profile: profile.clone(),
+                    rules: rules.clone(),
+                    home,
+                });
+            }
+        }
+        users
+    }
+}
+
+/// A user's daemon, and when it may be started again after it stopped.
+struct Running {
+    profile: UserProfile,
+    child: Option<Child>,
+    restart_at: Instant,
+}
+
+/// Runs a daemon for every user until asked to stop. Each runs with the user's home, so
+/// its move index, queues and journals are the user's own, and with their account when
+/// this runs as root.
+pub fn run(config: &ProfilesConfig, interval: u64) -> io::Result<()> {
+    let program = std::env::current_exe()?;
+    let mut running: Vec<Running> = Vec::new();
+    let mut next_scan = Instant::now();
+    while !shutdown::requested() {
+        if Instant::now() >= next_scan {
+            next_scan = Instant::now() + RESCAN;
+            let users = config.resolve();
+            for gone in running.iter_mut().filter(|running| !users.iter().any(|user| user.user == running.profile.user)) {
+                info!("{} is gone, stopping their daemon", gone.profile.user);
+                stop(gone.child.take());
+            }
+            running.retain(|running| users.iter().any(|user| user.user == running.profile.user));
+            for user in users {
+                if !running.iter().any(|running| running.profile.user == user.user) {
+                    running.push(Running {
+                        profile: user,
+                        child: None,
+                        restart_at: Instant::now(),
+                    });
+                }
+            }
+        }
+        for user in &mut running {
+            if let Some(child) = user.child.as_mut() {
+                match child.try_wait() {
+                    Ok(None) => continue,
+                    Ok(Some(status)) => warn!("The daemon of {} stopped ({}), restarting it in {}s", user.profile.user, status, RESTART_DELAY.as_secs()),
+                    Err(e) => warn!("Lost track of the daemon of {}: {}", user.profile.user, e),
+                }
+                user.child = None;
+                user.restart_at = Instant::now() + RESTART_DELAY;
+            }
+            if Instant::now() >= user.restart_at {
+                match spawn(&program, &user.profile, interval) {
+                    Ok(child) => {
+                        info!(user = %user.profile.user, profile = %user.profile.profile, folder = %user.profile.folder, "Started daemon");
+                        user.child = Some(child);
+                    }
+                    Err(e) => {
+                        warn!("Failed to start the daemon of {}: {}", user.profile.user, e);
+                        user.restart_at = Instant::now() + RESTART_DELAY;
+                    }
+                }
+            }
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+    info!("Stopping the users' daemons");
+    for user in &mut running {
+        stop(user.child.take());
+    }
+    Ok(())
+}
+
+fn spawn(program: &Path, profile: &UserProfile, interval: u64) -> io::Result<Child> {
+    let mut command = Command::new(program);
+    command
+        .args(["daemon", "--path", &profile.folder, "--interval", &interval.to_string()])
+        // The profile's rules are read from the working directory.
+        .current_dir(&profile.rules)
+        .env_remove("XDG_DATA_HOME")
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("XDG_RUNTIME_DIR")
+        .env_remove("ORGANIZER_PROFILE")
+        .env("USER", &profile.user)
+        .env("LOGNAME", &profile.user);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        use std::os::unix::process::CommandExt;
+
+        command.env("HOME", &profile.home);
+        if unsafe { libc::geteuid() } == 0 {
+            let owner = fs::metadata(&profile.home)?;
+            command.uid(owner.uid()).gid(owner.gid());
+            // Notifications go to the user's desktop session, and the control socket next to it.
+            let runtime = PathBuf::from(format!("/run/user/{}", owner.uid()));
+            if runtime.is_dir() {
+                command
+                    .env("DBUS_SESSION_BUS_ADDRESS", format!("unix:path={}", runtime.join("bus").display()))
+                    .env("XDG_RUNTIME_DIR", runtime);
+            }
+        }
+    }
+    #[cfg(windows)]
+    {
+        command
+            .env_remove("HOME")
+            .env("USERPROFILE", &profile.home)
+            .env("APPDATA", profile.home.join("AppData").join("Roaming"));
+    }
+    command.spawn()
+}
+
+/// Asks a user's daemon to finish the file it is on and stop, then waits for it.
+fn stop(child: Option<Child>) {
+    let Some(mut child) = child else { return };
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+    }
+    #[cfg(not(unix))]
+    let _ = child.kill();
+    let _ = child.wait();
+}
@@ -0,0 +1,162 @@
+use crate::report::Outcome;
+use crate::diskspace;
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+/// The bar of the sort in progress, which log lines are written around.
+static BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// What a sort did with the files of one rule, for the summary at the end.
+#[derive(Default)]
+struct RuleTally {
+    moved: usize,
+    copied: usize,
+    /// Files the rule matched that stayed in place.
+    left: usize,
+    failed: usize,
+    bytes: u64,
+}
+
+/// A progress bar for interactive sorts, with a summary per rule once they finish.
+pub struct Progress {
+    bar: ProgressBar,
+    bytes: u64,
+    rules: BTreeMap<String, RuleTally>,
+}
+
+impl Progress {
+    pub fn start(files: usize) -> Progress {
+        let bar = ProgressBar::new(files as u64);
+        bar.set_style(
+            ProgressStyle::with_template("{spinner} [{elapsed_precise}] {wide_bar} {pos}/{len} files, {msg}, {eta} left")
+                .unwrap_or_else(|_| ProgressStyle::default_bar()),
+        );
+        bar.set_message(format!("{} moved", HumanBytes(0)));
+        *BAR.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(bar.clone());
+        Progress {
+            bar,
+            bytes: 0,
+            rules: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, outcome: &Outcome) {
+        let rule = match outcome {
+            Outcome::Unmatched(_) | Outcome::Removed { .. } => return,
+            Outcome::Failed { rule, .. } => rule.as_deref().unwrap_or("-"),
+            Outcome::Moved { matched, .. }
+            | Outcome::Copied { matched, .. }
+            | Outcome::UpToDate { matched, .. }
+            | Outcome::Planned { matched, .. }
+            | Outcome::Staged { matched, .. }
+            | Outcome::Queued { matched, .. }
+            | Outcome::Duplicate { matched, .. }
+            | Outcome::Quarantined { matched, .. }
+            | Outcome::Skipped { matched, .. } => &matched.rule,
+        };
+        let tally = self.rules.entry(rule.to_string()).or_default();
+        match outcome {
+            Outcome::Moved { destination, .. } | Outcome::Copied { destination, .. } => {
+                let bytes = fs::metadata(destination).map_or(0, |metadata| metadata.len());
+                tally.bytes += bytes;
+                if matches!(outcome, Outcome::Moved { .. }) {
+                    tally.moved += 1;
+                } else {
+                    tally.copied += 1;
+                }
+                self.bytes += bytes;
+                self.bar.set_message(format!("{} moved", HumanBytes(self.bytes)));
+            }
+            // Dry runs count the files that would be moved or copied.
+            Outcome::Planned { matched, .. } if matched.copy => tally.copied += 1,
+            Outcome::Planned { .. } => tally.moved += 1,
+            Outcome::Failed { .. } => tally.failed += 1,
+            _ => tally.left += 1,
+        }
+    }
+
+    pub fn file_done(&self) {
+        self.bar.inc(1);
+    }
+
+    /// Clears the bar and prints what each rule did.
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+        *BAR.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = None;
+        if self.rules.is_empty() {
+            return;
+        }
+        let width = self.rules.keys().map(String::len).max().unwrap_or_default().max(5);
+        println!(
+            "{:<width$}  {:>6}  {:>6}  {:>6}  {:>6}  {:>9}",
+            "RULE", "MOVED", "COPIED", "LEFT", "FAILED", "SIZE"
+        );
+        let mut total = RuleTally::default();
+        for (rule, tally) in &self.rules {
+            print_row(rule, tally, width);
+            total.moved += tally.moved;
+            total.copied += tally.copied;
+            total.left += tally.left;
+            total.failed += tally.failed;
+            total.bytes += tally.bytes;
+        }
+        print_row("TOTAL", &total, width);
+    }
+}
+
+fn print_row(rule: &str, tally: &RuleTally, width: usize) {
+    println!(
+        "{:<width$}  {:>6}  {:>6}  {:>6}  {:>6}  {:>9}",
+        rule,
+        tally.moved,
+        tally.copied,
+        tally.left,
+        tally.failed,
+        diskspace::format_size(tally.bytes)
+    );
+}
+
+/// Writes log lines to stderr, above the progress bar while there is one.
+pub fn stderr() -> LogWriter {
+    LogWriter { line: Vec::new() }
+}
+
+/// Collects what is logged for one event and writes it in one go once the event is done, so
+/// the bar is cleared once for the whole line rather than redrawn in the middle of it.
+pub struct LogWriter {
+    line: Vec<u8>,
+}
+
+impl LogWriter {
+    fn write_line(&mut self) -> io::Result<()> {
+        if self.line.is_empty() {
+            return Ok(());
+        }
+        let line = std::mem::take(&mut self.line);
+        match BAR.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).as_ref() {
+            Some(bar) => bar.suspend(|| io::stderr().write_all(&line)),
+            None => io::stderr().write_all(&line),
+        }
+    }
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.line.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_line()?;
+        io::stderr().flush()
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        let _ = self.write_line();
+    }
+}
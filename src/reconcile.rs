@@ -0,0 +1,114 @@
+use crate::index::MoveIndex;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// How the daemon catches up after it was not running, from `config.json`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReconcileSettings {
+    /// Seconds the daemon has to have been stopped for its start to count as downtime.
+    #[serde(default = "default_min_gap")]
+    pub min_gap: u64,
+    /// Files sorted at a time while catching up, with control commands, quiet hours and
+    /// shutdown checked in between.
+    #[serde(default = "default_batch")]
+    pub batch: usize,
+}
+
+impl Default for ReconcileSettings {
+    fn default() -> Self {
+        ReconcileSettings {
+            min_gap: default_min_gap(),
+            batch: default_batch(),
+        }
+    }
+}
+
+fn default_min_gap() -> u64 {
+    5 * 60
+}
+
+fn default_batch() -> usize {
+    200
+}
+
+/// What changed in the sorted folder while the daemon was not running.
+pub struct Downtime {
+    /// When the daemon last sorted the folder.
+    pub since: DateTime<Local>,
+    /// Files added or changed since, oldest first.
+    pub appeared: Vec<PathBuf>,
+    /// Files sorts placed below the folder that are gone.
+    pub vanished: Vec<PathBuf>,
+}
+
+/// When the daemon last sorted `root`, if it was stopped for at least `min_gap` seconds since;
+/// `None` as well when it never sorted `root` before.
+pub fn stopped_since(index: &MoveIndex, root: &Path, min_gap: u64) -> rusqlite::Result<Option<DateTime<Local>>> {
+    let since = index.last_seen(root)?;
+    Ok(since.filter(|since| (Local::now() - *since).num_seconds() >= min_gap as i64))
+}
+
+/// Compares `files`, the files now in `root`, and the placements in the index with how they
+/// were `since`. Placed files in `protected` folders are left to their guard.
+pub fn compare(index: &MoveIndex, root: &Path, since: DateTime<Local>, files: Vec<PathBuf>, protected: &[PathBuf]) -> rusqlite::Result<Downtime> {
+    let cutoff = SystemTime::from(since);
+    let mut appeared: Vec<(SystemTime, PathBuf)> = files
+        .into_iter()
+        .filter_map(|file| Some((changed(&file)?, file)))
+        .filter(|(changed, _)| *changed >= cutoff)
+        .collect();
+    appeared.sort();
+    let vanished = index
+        .placements()?
+        .into_iter()
+        .map(|placement| PathBuf::from(placement.path))
+        .filter(|path| path.starts_with(root) && !protected.iter().any(|folder| path.starts_with(folder)))
+        .filter(|path| !path.exists())
+        .collect();
+    Ok(Downtime {
+        since,
+        appeared: appeared.into_iter().map(|(_, file)| file).collect(),
+        vanished,
+    })
+}
+
+/// When a file was last written or, where the system keeps it, created; files moved in
+/// keep their modification time but are created anew.
+fn changed(file: &Path) -> Option<SystemTime> {
+    let metadata = fs::symlink_metadata(file).ok()?;
+    let modified = metadata.modified().ok()?;
+    Some(metadata.created().map_or(modified, |created| created.max(modified)))
+}
+
+/// Files that appeared during downtime, handed to the daemon's first sorts a batch at a time.
+pub struct Backlog {
+    files: VecDeque<PathBuf>,
+    batch: usize,
+}
+
+impl Backlog {
+    pub fn new(files: Vec<PathBuf>, batch: usize) -> Self {
+        Backlog {
+            files: files.into(),
+            batch: batch.max(1),
+        }
+    }
+
+    /// Takes the next files to sort; files that are gone meanwhile are dropped.
+    pub fn next_batch(&mut self) -> Vec<PathBuf> {
+        let count = self.batch.min(self.files.len());
+        self.files.drain(..count).filter(|file| fs::symlink_metadata(file).is_ok()).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
@@ -0,0 +1,76 @@
+use crate::rules::Rule;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use tracing::info;
+
+/// The files the daemon's rules are read from, and when they were last changed.
+pub struct RuleFiles {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl RuleFiles {
+    pub fn new(paths: &[PathBuf]) -> RuleFiles {
+        RuleFiles {
+            files: paths.iter().map(|path| (path.clone(), modified(path))).collect(),
+        }
+    }
+
+    /// Whether a file was changed, created or removed since the last call.
+    pub fn changed(&mut self) -> bool {
+        let mut changed = false;
+        for (path, stamp) in &mut self.files {
+            let current = modified(path);
+            if current != *stamp {
+                *stamp = current;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+fn modified(path: impl AsRef<std::path::Path>) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Logs the rules that are new in `new` and the ones that are gone from `old`.
+pub fn log_diff(old: &[Rule], new: &[Rule]) {
+    let old: BTreeSet<String> = old.iter().map(describe).collect();
+    let new: BTreeSet<String> = new.iter().map(describe).collect();
+    let added: Vec<&String> = new.difference(&old).collect();
+    let removed: Vec<&String> = old.difference(&new).collect();
+    if added.is_empty() && removed.is_empty() {
+        info!("Reloaded rules, no rules changed");
+        return;
+    }
+    info!("Reloaded rules, {} added and {} removed", added.len(), removed.len());
+    for rule in added {
+        info!("Added rule {}", rule);
+    }
+    for rule in removed {
+        info!("Removed rule {}", rule);
+    }
+}
+
+fn describe(rule: &Rule) -> String {
+    let mut description = format!("{} -> {} (priority {}", rule.pattern, rule.destination, rule.priority);
+    if rule.copy {
+        description.push_str(", copy");
+    }
+    if rule.continue_matching {
+        description.push_str(", continue");
+    }
+    if rule.link_back {
+        description.push_str(", link back");
+    }
+    if let Some(cooldown) = rule.cooldown {
+        description.push_str(&format!(", cooldown {}s", cooldown));
+    }
+    if let Some(max) = rule.max_per_hour {
+        description.push_str(&format!(", at most {} per hour", max));
+    }
+    description.push(')');
+    description
+}
@@ -0,0 +1,320 @@
+use crate::s3::{self, Bucket};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How often an upload is tried before the file is reported as failed.
+const DEFAULT_ATTEMPTS: u32 = 5;
+
+/// How to log in to a remote destination, from `remotes` in `rules.json`, by `sftp://host`
+/// or `s3://bucket`.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct RemoteSettings {
+    /// SFTP user, when the destination doesn't name one.
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    /// Private key to log in with over SFTP; without it the SSH agent is used.
+    pub key_file: Option<PathBuf>,
+    /// Environment variable holding the SFTP password.
+    pub password_env: Option<String>,
+    pub region: Option<String>,
+    /// E.g. `https://minio.local:9000` for an S3-compatible service.
+    pub endpoint: Option<String>,
+    pub access_key_env: Option<String>,
+    pub secret_key_env: Option<String>,
+    /// How often each upload is tried.
+    pub attempts: Option<u32>,
+}
+
+/// A destination on another machine, such as `sftp://backup-host/incoming/2024` or
+/// `s3://my-bucket/scans/`.
+enum Remote<'a> {
+    Sftp {
+        user: Option<&'a str>,
+        host: &'a str,
+        port: Option<u16>,
+        folder: &'a str,
+    },
+    S3 { bucket: &'a str, prefix: &'a str },
+    /// A remote configured in rclone, as `rclone:<remote>:<path>`.
+    Rclone { remote: &'a str, folder: &'a str },
+}
+
+impl<'a> Remote<'a> {
+    fn parse(destination: &'a str) -> Option<Remote<'a>> {
+        if let Some(rest) = destination.strip_prefix("sftp://") {
+            let (authority, folder) = rest.split_once('/').unwrap_or((rest, ""));
+            let (user, address) = match authority.split_once('@') {
+                Some((user, address)) => (Some(user), address),
+                None => (None, authority),
+            };
+            let (host, port) = match address.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port.parse().ok()?)),
+                None => (address, None),
+            };
+            return Some(Remote::Sftp { user, host, port, folder });
+        }
+        if let Some(rest) = destination.strip_prefix("rclone:") {
+            let (remote, folder) = rest.split_once(':')?;
+            return Some(Remote::Rclone { remote, folder });
+        }
+        let rest = destination.strip_prefix("s3://")?;
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        Some(Remote::S3 { bucket, prefix })
+    }
+
+    /// The key of the destination's settings in `remotes`.
+    fn key(&self) -> String {
+        match self {
+            Remote::Sftp { host, .. } => format!("sftp://{}", host),
+            Remote::S3 { bucket, .. } => format!("s3://{}", bucket),
+            Remote::Rclone { remote, .. } => format!("rclone:{}", remote),
+        }
+    }
+}
+
+/// Whether rules place files with this destination on another machine.
+pub fn is_remote(destination: &str) -> bool {
+    destination.starts_with("sftp://") || destination.starts_with("s3://") || destination.starts_with("rclone:")
+}
+
+/// Where a file named `name` goes in the remote folder `destination`.
+pub fn target(destination: &str, name: &str) -> String {
+    let folder = destination.trim_end_matches('/');
+    if folder.ends_with(':') {
+        // The top of an rclone remote, as in `rclone:photos:`.
+        return format!("{}{}", folder, name);
+    }
+    format!("{}/{}", folder, name)
+}
+
+/// Uploads `file` into the remote folder `destination` and returns where it went. A file
+/// already there is only replaced with `replace`; otherwise the upload fails with
+/// `AlreadyExists`. Interrupted uploads are retried, and continue where they stopped.
+/// `bandwidth` limits uploads through rclone, in its `--bwlimit` notation.
+pub fn upload(
+    file: &Path,
+    destination: &str,
+    remotes: &HashMap<String, RemoteSettings>,
+    replace: bool,
+    bandwidth: Option<&str>,
+) -> io::Result<String> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid remote destination {}", destination));
+    let remote = Remote::parse(destination).ok_or_else(invalid)?;
+    let name = file.file_name().ok_or_else(invalid)?.to_string_lossy().into_owned();
+    let settings = remotes.get(&remote.key()).cloned().unwrap_or_default();
+    let attempts = settings.attempts.unwrap_or(DEFAULT_ATTEMPTS);
+    match remote {
+        Remote::Sftp { user, host, port, folder } => {
+            let user = user.map(str::to_string).or(settings.user.clone()).unwrap_or_else(whoami::username);
+            let folder = format!("/{}", folder.trim_matches('/'));
+            let login = sftp::Login {
+                host,
+                port: port.or(settings.port).unwrap_or(22),
+                user: &user,
+                key_file: settings.key_file.as_deref(),
+                password_env: settings.password_env.as_deref(),
+            };
+            sftp::upload(&login, file, Path::new(&folder), &name, replace, attempts)?;
+        }
+        Remote::S3 { bucket, prefix } => {
+            let variable = |name: Option<&String>, default: &str| {
+                let name = name.map_or(default, String::as_str);
+                std::env::var(name).map_err(|_| io::Error::new(io::ErrorKind::NotFound, format!("{} is not set", name)))
+            };
+            let bucket = Bucket {
+                name: bucket,
+                region: settings.region.as_deref().unwrap_or("us-east-1"),
+                endpoint: settings.endpoint.as_deref(),
+                access_key: variable(settings.access_key_env.as_ref(), "AWS_ACCESS_KEY_ID")?,
+                secret_key: variable(settings.secret_key_env.as_ref(), "AWS_SECRET_ACCESS_KEY")?,
+            };
+            let object = match prefix.trim_matches('/') {
+                "" => name.clone(),
+                prefix => format!("{}/{}", prefix, name),
+            };
+            if !replace && s3::retry(attempts, || bucket.exists(&object))? {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, "a file with this name already exists at the destination"));
+            }
+            bucket.upload(&object, file, attempts)?;
+        }
+        Remote::Rclone { .. } => {
+            let target = target(destination, &name);
+            rclone::upload(file, &target["rclone:".len()..], replace, attempts, bandwidth)?;
+        }
+    }
+    Ok(target(destination, &name))
+}
+
+/// Uploads by running an installed `rclone`, which brings its own retries and resumes.
+mod rclone {
+    use std::io;
+    use std::path::Path;
+    use std::process::{Command, Output};
+
+    /// Copies `file` to `target`, given as `<remote>:<path>`.
+    pub fn upload(file: &Path, target: &str, replace: bool, attempts: u32, bandwidth: Option<&str>) -> io::Result<()> {
+        if !replace {
+            // Lists nothing, or fails, when there is no file at the path.
+            let listed = run(Command::new("rclone").args(["lsf", "--files-only", target]))?;
+            if listed.status.success() && !listed.stdout.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, "a file with this name already exists at the destination"));
+            }
+        }
+        let mut command = Command::new("rclone");
+        command.arg("copyto").arg(file).arg(target).args(["--retries", &attempts.max(1).to_string()]);
+        if let Some(bandwidth) = bandwidth {
+            command.args(["--bwlimit", bandwidth]);
+        }
+        let output = run(&mut command)?;
+        if output.status.success() {
+            return Ok(());
+        }
+        let message = String::from_utf8_lossy(&output.stderr);
+        let message = message.lines().rev().find(|line| !line.trim().is_empty()).unwrap_or("rclone failed").trim();
+        // See "Exit code" in rclone's documentation.
+        let kind = match output.status.code() {
+            Some(1) => io::ErrorKind::InvalidInput,
+            Some(3 | 4) => io::ErrorKind::NotFound,
+            _ => io::ErrorKind::Other,
+        };
+        Err(io::Error::new(kind, format!("rclone: {}", message)))
+    }
+
+    fn run(command: &mut Command) -> io::Result<Output> {
+        command.output().map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => io::Error::new(io::ErrorKind::NotFound, "rclone is not installed or not on the PATH"),
+            _ => e,
+        })
+    }
+}
+
+#[cfg(feature = "sftp")]
+mod sftp {
+    use crate::s3;
+    use crate::sources;
+    use ssh2::{OpenFlags, OpenType, Sftp};
+    use std::fs::File;
+    use std::io::{self, Seek, SeekFrom};
+    use std::path::Path;
+
+    pub struct Login<'a> {
+        pub host: &'a str,
+        pub port: u16,
+        pub user: &'a str,
+        pub key_file: Option<&'a Path>,
+        pub password_env: Option<&'a str>,
+    }
+
+    /// Uploads to a hidden `.part` file first and renames it once complete. A `.part` file
+    /// left by an earlier attempt is continued rather than started over.
+    pub fn upload(login: &Login, file: &Path, folder: &Path, name: &str, replace: bool, attempts: u32) -> io::Result<()> {
+        let size = file.metadata()?.len();
+        s3::retry(attempts, || {
+            let session = sources::connect_sftp(login.host, login.port, login.user, login.key_file, login.password_env)?;
+            let sftp = session.sftp()?;
+            create_dirs(&sftp, folder)?;
+            let (target, partial) = (folder.join(name), folder.join(format!(".{}.part", name)));
+            if !replace && sftp.stat(&target).is_ok() {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, "a file with this name already exists at the destination"));
+            }
+            let offset = sftp.stat(&partial).ok().and_then(|stat| stat.size).filter(|uploaded| *uploaded <= size).unwrap_or(0);
+            let mut flags = OpenFlags::WRITE | OpenFlags::CREATE;
+            if offset == 0 {
+                flags |= OpenFlags::TRUNCATE;
+            }
+            let mut remote = sftp.open_mode(&partial, flags, 0o644, OpenType::File)?;
+            remote.seek(SeekFrom::Start(offset))?;
+            let mut local = File::open(file)?;
+            local.seek(SeekFrom::Start(offset))?;
+            io::copy(&mut local, &mut remote)?;
+            drop(remote);
+            let uploaded = sftp.stat(&partial)?.size.unwrap_or_default();
+            if uploaded != size {
+                return Err(io::Error::other(format!("uploaded {} of {} bytes", uploaded, size)));
+            }
+            if replace && sftp.stat(&target).is_ok() {
+                sftp.unlink(&target)?;
+            }
+            sftp.rename(&partial, &target, None)?;
+            Ok(())
+        })
+    }
+
+    fn create_dirs(sftp: &Sftp, folder: &Path) -> io::Result<()> {
+        let missing: Vec<&Path> = folder.ancestors().take_while(|ancestor| sftp.stat(ancestor).is_err()).collect();
+        for ancestor in missing.into_iter().rev() {
+            sftp.mkdir(ancestor, 0o755)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "sftp"))]
+mod sftp {
+    use std::io;
+    use std::path::Path;
+
+    pub struct Login<'a> {
+        pub host: &'a str,
+        pub port: u16,
+        pub user: &'a str,
+        pub key_file: Option<&'a Path>,
+        pub password_env: Option<&'a str>,
+    }
+
+    pub fn upload(_login: &Login, _file: &Path, _folder: &Path, _name: &str, _replace: bool, _attempts: u32) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "SFTP support is not enabled in this build"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sftp_destinations_with_user_and_port() {
+        let remote = Remote::parse("sftp://scanner@backup-host:2222/incoming/2024");
+        assert!(matches!(
+            remote,
+            Some(Remote::Sftp { user: Some("scanner"), host: "backup-host", port: Some(2222), folder: "incoming/2024" })
+        ));
+        assert_eq!(remote.unwrap().key(), "sftp://backup-host");
+    }
+
+    #[test]
+    fn refuses_sftp_ports_that_are_not_numbers() {
+        assert!(Remote::parse("sftp://backup-host:ssh/incoming").is_none());
+    }
+
+    #[test]
+    fn parses_s3_and_rclone_destinations() {
+        assert!(matches!(Remote::parse("s3://my-bucket/scans/"), Some(Remote::S3 { bucket: "my-bucket", prefix: "scans/" })));
+        assert!(matches!(Remote::parse("s3://my-bucket"), Some(Remote::S3 { bucket: "my-bucket", prefix: "" })));
+        assert!(matches!(Remote::parse("rclone:photos:2024"), Some(Remote::Rclone { remote: "photos", folder: "2024" })));
+        assert!(Remote::parse("rclone:photos").is_none());
+        assert!(Remote::parse("Documents/2024").is_none());
+    }
+
+    #[test]
+    fn tells_remote_destinations_from_folders() {
+        assert!(is_remote("sftp://backup-host/incoming"));
+        assert!(is_remote("s3://my-bucket"));
+        assert!(is_remote("rclone:photos:"));
+        assert!(!is_remote("Documents/sftp"));
+    }
+
+    #[test]
+    fn puts_files_in_the_remote_folder() {
+        assert_eq!(target("s3://my-bucket/scans/", "scan.pdf"), "s3://my-bucket/scans/scan.pdf");
+        assert_eq!(target("sftp://backup-host/incoming", "scan.pdf"), "sftp://backup-host/incoming/scan.pdf");
+        assert_eq!(target("rclone:photos:", "photo.jpg"), "rclone:photos:photo.jpg");
+    }
+
+    #[test]
+    fn refuses_to_upload_to_invalid_destinations() {
+        let uploaded = upload(Path::new("scan.pdf"), "sftp://backup-host:ssh/incoming", &HashMap::new(), false, None);
+        assert_eq!(uploaded.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// Whether `path` is on a drive that can be unplugged, such as a USB stick or an SD card.
+#[cfg(target_os = "linux")]
+pub fn is_removable(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    let Ok(metadata) = fs::metadata(path) else { return false };
+    let device = metadata.dev();
+    let (major, minor) = (libc::major(device), libc::minor(device));
+    let Ok(block) = fs::canonicalize(format!("/sys/dev/block/{}:{}", major, minor)) else { return false };
+    // USB disks often don't call themselves removable, but they are unplugged all the same.
+    if block.components().any(|component| component.as_os_str().to_string_lossy().starts_with("usb")) {
+        return true;
+    }
+    // Partitions have the flag on the disk they belong to.
+    [block.join("removable"), block.join("../removable")]
+        .iter()
+        .any(|flag| fs::read_to_string(flag).is_ok_and(|flag| flag.trim() == "1"))
+}
+
+#[cfg(target_os = "macos")]
+pub fn is_removable(path: &Path) -> bool {
+    // Drives other than the system drive are mounted below /Volumes.
+    fs::canonicalize(path).is_ok_and(|path| path.starts_with("/Volumes"))
+}
+
+#[cfg(windows)]
+pub fn is_removable(path: &Path) -> bool {
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::Component;
+    use windows_sys::Win32::Storage::FileSystem::GetDriveTypeW;
+    const DRIVE_REMOVABLE: u32 = 2;
+
+    let Some(Component::Prefix(prefix)) = path.components().next() else { return false };
+    let root: Vec<u16> = prefix.as_os_str().encode_wide().chain("\\\0".encode_utf16()).collect();
+    unsafe { GetDriveTypeW(root.as_ptr()) == DRIVE_REMOVABLE }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn is_removable(_path: &Path) -> bool {
+    false
+}
+
+/// Writes everything cached for the filesystem `path` is on to the drive, so it can be
+/// unplugged once this returns.
+pub fn flush(path: &Path) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+        let folder = fs::File::open(path)?;
+        if unsafe { libc::syncfs(folder.as_raw_fd()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(all(unix, not(target_os = "linux")))]
+    {
+        fs::metadata(path)?;
+        unsafe { libc::sync() };
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        // Without a call that flushes a whole volume to unprivileged users, every file is flushed.
+        sync_files(path)
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_files(path: &Path) -> io::Result<()> {
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            sync_files(&entry?.path())?;
+        }
+        return Ok(());
+    }
+    fs::File::options().write(true).open(path)?.sync_all()
+}
+
+/// Flushes the removable drives among the folders a sort wrote to. Nothing on them is kept
+/// open between sorts, so they can be ejected once a sort is done.
+pub fn flush_written(folders: &BTreeSet<PathBuf>) {
+    for folder in folders.iter().filter(|folder| is_removable(folder)) {
+        match flush(folder) {
+            Ok(()) => debug!(folder = ?folder, "Flushed removable drive"),
+            Err(e) => warn!(folder = ?folder, "Failed to flush removable drive: {}", e),
+        }
+    }
+}
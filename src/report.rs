@@ -0,0 +1,514 @@
+use crate::capacity::Capacity;
+use crate::duplicates::DuplicatePolicy;
+use crate::limits::RateLimit;
+use crate::links::{BrokenSymlinkPolicy, SymlinkPolicy};
+use crate::permissions::{Owner, Permissions};
+use crate::progress::Progress;
+use crate::rules::Rule;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::{debug, error, info, warn};
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// The rule that selected a destination for a file.
+pub struct RuleMatch {
+    pub rule: String,
+    /// What the rule is called in timings and in `test`, from [`Rule::label`], which tells
+    /// rules with the same pattern apart.
+    pub label: String,
+    pub destination: String,
+    /// The file is copied to the destination and stays where it is.
+    pub copy: bool,
+    /// A symlink to the moved file is left at its old location.
+    pub link_back: bool,
+    pub limit: Option<RateLimit>,
+    pub capacity: Option<Capacity>,
+    pub symlinks: Option<SymlinkPolicy>,
+    pub broken_symlinks: Option<BrokenSymlinkPolicy>,
+    /// The file's content is checked against its extension before it is placed.
+    pub verify_type: bool,
+    /// The file is checked by the virus scanner before it is placed.
+    pub scan: bool,
+    /// Permissions and owner the placed file is given instead of those it had.
+    pub permissions: Option<Permissions>,
+    pub owner: Option<Owner>,
+    /// Limit of uploads to `rclone:` destinations, in rclone's `--bwlimit` notation.
+    pub bandwidth: Option<String>,
+    /// Handoff commands of the rule run one at a time, in the order the files arrived.
+    pub serial: bool,
+}
+
+impl RuleMatch {
+    /// The match of `rule`, placing in `destination`, which its command may have chosen
+    /// instead of the rule's own.
+    pub fn from_rule(rule: &Rule, destination: String, copy: bool) -> RuleMatch {
+        RuleMatch {
+            rule: rule.pattern.clone(),
+            label: rule.label(),
+            destination,
+            copy,
+            link_back: rule.link_back,
+            limit: rule.rate_limit(),
+            capacity: rule.capacity(),
+            symlinks: rule.symlinks,
+            broken_symlinks: rule.broken_symlinks,
+            verify_type: rule.verify_type,
+            scan: rule.scan,
+            permissions: rule.permissions.clone(),
+            owner: rule.owner.clone(),
+            bandwidth: rule.bandwidth.clone(),
+            serial: rule.serial,
+        }
+    }
+
+    /// A match made by something other than a rule, such as a plugin, the Lua script or the
+    /// unmatched folder, without any of the options of rules.
+    pub fn decided(name: String, destination: String, copy: bool) -> RuleMatch {
+        RuleMatch {
+            label: name.clone(),
+            rule: name,
+            destination,
+            copy,
+            link_back: false,
+            limit: None,
+            capacity: None,
+            symlinks: None,
+            broken_symlinks: None,
+            verify_type: false,
+            scan: false,
+            permissions: None,
+            owner: None,
+            bandwidth: None,
+            serial: false,
+        }
+    }
+}
+
+/// Why a file was left where it is.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkipReason {
+    /// No rule applies to the file.
+    NoRuleMatched,
+    /// Ignored by a `.organizer.toml`, or the destination is not allowed by machine policy.
+    Excluded,
+    /// Not old enough for the rule's action yet.
+    TooNew,
+    /// Left alone while the user is working at the desktop.
+    InUse,
+    /// A different file is already in the way at the destination.
+    Conflict,
+    /// The rule reached its cooldown or hourly limit.
+    Quota,
+    /// The destination's drive is running out of space.
+    LowDiskSpace,
+    /// Turned down during `sort --interactive`.
+    Declined,
+    /// Reappeared with the same contents where it was just moved from, as sync tools
+    /// sometimes put files back.
+    SyncEcho,
+    /// Held open or locked by another process; the daemon tries it again later.
+    Locked,
+    /// The rule belongs to a pipeline that is not running.
+    Stopped,
+    /// Matches a `protected` glob, so it is never moved or deleted.
+    Protected,
+    /// A hidden file, which only rules with `hidden` sort.
+    Hidden,
+    /// A temporary file, such as a download in progress.
+    Temporary,
+    /// The destination holds as much as the rule's `max_dest_size` or `max_files` allow.
+    OverCapacity,
+}
+
+impl SkipReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SkipReason::NoRuleMatched => "no-rule-matched",
+            SkipReason::Excluded => "excluded",
+            SkipReason::TooNew => "too-new",
+            SkipReason::InUse => "in-use",
+            SkipReason::Conflict => "conflict",
+            SkipReason::Quota => "quota",
+            SkipReason::LowDiskSpace => "low-disk-space",
+            SkipReason::Declined => "declined",
+            SkipReason::SyncEcho => "sync-echo",
+            SkipReason::Locked => "locked",
+            SkipReason::Stopped => "stopped",
+            SkipReason::Protected => "protected",
+            SkipReason::Hidden => "hidden",
+            SkipReason::Temporary => "temporary",
+            SkipReason::OverCapacity => "over-capacity",
+        }
+    }
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What happened to a single file during a sort.
+pub enum Outcome {
+    /// Moved; `checksum` is what the file was verified with when it was copied to another drive.
+    Moved { matched: RuleMatch, destination: PathBuf, checksum: Option<String> },
+    /// Copied by a rule that leaves the file in place, and verified with `checksum` when
+    /// copies are verified.
+    Copied { matched: RuleMatch, destination: PathBuf, checksum: Option<String> },
+    /// Copied before and unchanged since, or already where and as its rule wants it.
+    UpToDate { matched: RuleMatch, destination: PathBuf },
+    /// Would have been moved, but this is a dry run.
+    Planned { matched: RuleMatch, destination: PathBuf },
+    /// Moved into a staging folder to wait for approval.
+    Staged { matched: RuleMatch, destination: PathBuf },
+    /// Handed to an external command; the file is moved once the command succeeds.
+    Queued { matched: RuleMatch, destination: PathBuf, already_queued: bool },
+    /// An identical file already exists in the destination.
+    Duplicate { matched: RuleMatch, existing: PathBuf, policy: DuplicatePolicy },
+    /// Matched a rule but was intentionally left alone for now; `detail` says what exactly.
+    Skipped { matched: RuleMatch, reason: SkipReason, detail: String },
+    /// Failed the checks of the rule, so the file was moved to the quarantine folder instead of
+    /// its destination, or would have been in a dry run; `reason` says what was found.
+    Quarantined { matched: RuleMatch, destination: PathBuf, reason: String, planned: bool },
+    /// A broken symlink, or with `temporary` a stale temporary file, was deleted, or would
+    /// have been in a dry run.
+    Removed { rule: Option<String>, planned: bool, temporary: bool },
+    /// No rule was applied to the file: `NoRuleMatched`, `Excluded`, `SyncEcho`,
+    /// `Locked`, `Protected`, `Hidden` or `Temporary`.
+    Unmatched(SkipReason),
+    Failed { rule: Option<String>, error: std::io::Error },
+}
+
+/// What an outcome did to its file, in the words `sort --output json`, the `--events-file`
+/// and `activity` share.
+pub struct Described<'a> {
+    pub rule: Option<&'a str>,
+    /// What was to be done to the file, e.g. `move`, `copy` or `none`.
+    pub action: &'static str,
+    /// E.g. `moved`, `skipped` or `failed`.
+    pub result: &'static str,
+    pub destination: Option<&'a Path>,
+    pub reason: Option<SkipReason>,
+    pub detail: Option<&'a str>,
+    pub error: Option<String>,
+}
+
+impl<'a> Described<'a> {
+    fn by_rule(matched: &'a RuleMatch, action: &'static str, result: &'static str, destination: Option<&'a Path>) -> Described<'a> {
+        Described {
+            rule: Some(&matched.rule),
+            action,
+            result,
+            destination,
+            reason: None,
+            detail: None,
+            error: None,
+        }
+    }
+}
+
+impl Outcome {
+    pub fn describe(&self) -> Described<'_> {
+        match self {
+            Outcome::Moved { matched, destination, .. } => Described::by_rule(matched, "move", "moved", Some(destination.as_path())),
+            Outcome::Copied { matched, destination, .. } => Described::by_rule(matched, "copy", "copied", Some(destination.as_path())),
+            Outcome::UpToDate { matched, destination } => Described::by_rule(matched, "copy", "up-to-date", Some(destination.as_path())),
+            Outcome::Planned { matched, destination } => {
+                Described::by_rule(matched, if matched.copy { "copy" } else { "move" }, "planned", Some(destination.as_path()))
+            }
+            Outcome::Staged { matched, destination } => Described::by_rule(matched, "stage", "staged", Some(destination.as_path())),
+            Outcome::Queued { matched, destination, .. } => Described::by_rule(matched, "handoff", "queued", Some(destination.as_path())),
+            Outcome::Duplicate { matched, existing, policy } => Described::by_rule(matched, "dedup", policy.as_str(), Some(existing.as_path())),
+            Outcome::Quarantined { matched, destination, reason, planned } => Described {
+                detail: Some(reason),
+                ..Described::by_rule(matched, "quarantine", if *planned { "planned" } else { "quarantined" }, Some(destination.as_path()))
+            },
+            Outcome::Skipped { matched, reason, detail } => Described {
+                reason: Some(*reason),
+                detail: Some(detail),
+                ..Described::by_rule(matched, "none", "skipped", None)
+            },
+            Outcome::Removed { rule, planned, .. } => Described {
+                rule: rule.as_deref(),
+                action: "remove",
+                result: if *planned { "planned" } else { "removed" },
+                destination: None,
+                reason: None,
+                detail: None,
+                error: None,
+            },
+            Outcome::Unmatched(reason) => Described {
+                rule: None,
+                action: "none",
+                result: "unmatched",
+                destination: None,
+                reason: Some(*reason),
+                detail: None,
+                error: None,
+            },
+            Outcome::Failed { rule, error } => Described {
+                rule: rule.as_deref(),
+                action: "move",
+                result: "failed",
+                destination: None,
+                reason: None,
+                detail: None,
+                error: Some(error.to_string()),
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Record<'a> {
+    File {
+        path: &'a Path,
+        rule: Option<&'a str>,
+        action: &'a str,
+        destination: Option<&'a Path>,
+        result: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<SkipReason>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        detail: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    Summary {
+        processed: usize,
+        #[serde(flatten)]
+        totals: &'a Totals,
+        #[serde(skip_serializing_if = "<[_]>::is_empty")]
+        failures: &'a [Failure],
+    },
+}
+
+/// A file a sort could not place, for the summary at the end.
+#[derive(Serialize)]
+pub struct Failure {
+    pub path: PathBuf,
+    pub rule: Option<String>,
+    pub error: String,
+}
+
+/// Number of outcomes of each kind.
+#[derive(Serialize, Default, Clone)]
+pub struct Totals {
+    /// Files sorted; a file can have several outcomes when rules copy it.
+    #[serde(skip)]
+    pub files: usize,
+    pub moved: usize,
+    pub copied: usize,
+    pub up_to_date: usize,
+    pub planned: usize,
+    pub queued: usize,
+    pub staged: usize,
+    pub duplicates: usize,
+    pub removed: usize,
+    pub quarantined: usize,
+    pub skipped: usize,
+    pub unmatched: usize,
+    pub failed: usize,
+    /// Skipped and unmatched files by why they were left in place.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub skip_reasons: BTreeMap<SkipReason, usize>,
+}
+
+impl Totals {
+    pub fn processed(&self) -> usize {
+        self.files
+    }
+    
+    /// Whether any file was handled, rather than left in place unmatched or skipped.
+    pub fn handled_any(&self) -> bool {
+        self.files > self.unmatched + self.skipped
+    }
+    
+    pub fn add(&mut self, other: &Totals) {
+        self.files += other.files;
+        self.moved += other.moved;
+        self.copied += other.copied;
+        self.up_to_date += other.up_to_date;
+        self.planned += other.planned;
+        self.queued += other.queued;
+        self.staged += other.staged;
+        self.duplicates += other.duplicates;
+        self.removed += other.removed;
+        self.quarantined += other.quarantined;
+        self.skipped += other.skipped;
+        self.unmatched += other.unmatched;
+        self.failed += other.failed;
+        for (reason, count) in &other.skip_reasons {
+            *self.skip_reasons.entry(*reason).or_default() += count;
+        }
+    }
+}
+
+/// Reports sort outcomes either as log lines or as JSON objects on stdout.
+pub struct Reporter {
+    format: OutputFormat,
+    totals: Totals,
+    failures: Vec<Failure>,
+    progress: Option<Progress>,
+}
+
+impl Reporter {
+    pub fn new(format: OutputFormat) -> Self {
+        Reporter {
+            format,
+            totals: Totals::default(),
+            failures: Vec::new(),
+            progress: None,
+        }
+    }
+    
+    /// Shows a progress bar over `files` files instead of a line per file, and a summary
+    /// per rule at the end.
+    pub fn show_progress(&mut self, files: usize) {
+        self.progress = Some(Progress::start(files));
+    }
+    
+    pub fn totals(&self) -> &Totals {
+        &self.totals
+    }
+    
+    /// Counts a file whose outcomes have all been recorded.
+    pub fn file_done(&mut self) {
+        self.totals.files += 1;
+        if let Some(progress) = &self.progress {
+            progress.file_done();
+        }
+    }
+    
+    pub fn record(&mut self, path: &Path, outcome: &Outcome) {
+        let totals = &mut self.totals;
+        match outcome {
+            Outcome::Moved { .. } => totals.moved += 1,
+            Outcome::Copied { .. } => totals.copied += 1,
+            Outcome::UpToDate { .. } => totals.up_to_date += 1,
+            Outcome::Planned { .. } => totals.planned += 1,
+            Outcome::Queued { .. } => totals.queued += 1,
+            Outcome::Staged { .. } => totals.staged += 1,
+            Outcome::Duplicate { .. } => totals.duplicates += 1,
+            Outcome::Quarantined { planned: true, .. } => totals.planned += 1,
+            Outcome::Quarantined { .. } => totals.quarantined += 1,
+            Outcome::Removed { planned: true, .. } => totals.planned += 1,
+            Outcome::Removed { .. } => totals.removed += 1,
+            Outcome::Skipped { .. } => totals.skipped += 1,
+            Outcome::Unmatched(_) => totals.unmatched += 1,
+            Outcome::Failed { rule, error } => {
+                totals.failed += 1;
+                self.failures.push(Failure {
+                    path: path.to_path_buf(),
+                    rule: rule.clone(),
+                    error: error.to_string(),
+                });
+            }
+        }
+        if let Outcome::Skipped { reason, .. } | Outcome::Unmatched(reason) = outcome {
+            *totals.skip_reasons.entry(*reason).or_default() += 1;
+        }
+        
+        if let Some(progress) = self.progress.as_mut() {
+            progress.record(outcome);
+        }
+        let attention = matches!(outcome, Outcome::Quarantined { planned: false, .. } | Outcome::Failed { .. });
+        match self.format {
+            // The bar stands in for the lines of files that went as planned.
+            OutputFormat::Text if self.progress.is_some() && !attention => {}
+            OutputFormat::Text => match outcome {
+                Outcome::Moved { destination, .. } => info!(file = ?path, destination = ?destination, "Moved file"),
+                Outcome::Copied { destination, .. } => info!(file = ?path, destination = ?destination, "Copied file"),
+                Outcome::UpToDate { destination, .. } => debug!(file = ?path, destination = ?destination, "Copy is up to date"),
+                Outcome::Planned { matched, destination } if matched.copy => {
+                    info!(file = ?path, destination = ?destination, "Would copy file")
+                }
+                Outcome::Planned { destination, .. } => info!(file = ?path, destination = ?destination, "Would move file"),
+                Outcome::Staged { destination, .. } => info!(file = ?path, destination = ?destination, "Staged file for review"),
+                Outcome::Queued { already_queued: false, destination, .. } => {
+                    info!(file = ?path, destination = ?destination, "Queued file for handoff")
+                }
+                Outcome::Queued { .. } => debug!(file = ?path, "File is already queued for handoff"),
+                Outcome::Duplicate { existing, policy, .. } => {
+                    info!(file = ?path, existing = ?existing, %policy, "Duplicate of existing file")
+                }
+                Outcome::Quarantined { destination, reason, planned: true, .. } => {
+                    warn!(file = ?path, destination = ?destination, "Would quarantine file, {}", reason)
+                }
+                Outcome::Quarantined { destination, reason, .. } => {
+                    warn!(file = ?path, destination = ?destination, "Quarantined file, {}", reason)
+                }
+                Outcome::Removed { planned: true, temporary: true, .. } => info!(file = ?path, "Would remove stale temporary file"),
+                Outcome::Removed { temporary: true, .. } => info!(file = ?path, "Removed stale temporary file"),
+                Outcome::Removed { planned: true, .. } => info!(file = ?path, "Would remove broken symlink"),
+                Outcome::Removed { .. } => info!(file = ?path, "Removed broken symlink"),
+                Outcome::Skipped { reason, detail, .. } => debug!(file = ?path, %reason, "Skipped file: {}", detail),
+                Outcome::Unmatched(SkipReason::SyncEcho) => {
+                    info!(file = ?path, "Left file in place, it reappeared with the same contents right after it was moved")
+                }
+                Outcome::Unmatched(reason) => debug!(file = ?path, %reason, "Left file in place"),
+                Outcome::Failed { error, .. } => error!(file = ?path, "Failed to sort file: {}", error),
+            },
+            OutputFormat::Json => {
+                let described = outcome.describe();
+                let record = Record::File {
+                    path,
+                    rule: described.rule,
+                    action: described.action,
+                    destination: described.destination,
+                    result: described.result,
+                    reason: described.reason,
+                    detail: described.detail,
+                    error: described.error,
+                };
+                print_json(&record);
+            }
+        }
+    }
+    
+    pub fn finish(&self) {
+        if let Some(progress) = &self.progress {
+            progress.finish();
+        }
+        if let (OutputFormat::Text, false) = (self.format, self.failures.is_empty()) {
+            error!("{} of {} files could not be sorted and were left in place:", self.failures.len(), self.totals.processed());
+            for failure in &self.failures {
+                error!("  {}: {}", failure.path.display(), failure.error);
+            }
+        }
+        match self.format {
+            // Files that match no rule are common; only summarize when a matching file was held back.
+            OutputFormat::Text if self.totals.skipped > 0 => {
+                let reasons: Vec<String> = self
+                    .totals
+                    .skip_reasons
+                    .iter()
+                    .map(|(reason, count)| format!("{} {}", count, reason))
+                    .collect();
+                info!("Left {} files in place: {}", self.totals.skipped + self.totals.unmatched, reasons.join(", "));
+            }
+            OutputFormat::Text => {}
+            OutputFormat::Json => print_json(&Record::Summary {
+                processed: self.totals.processed(),
+                totals: &self.totals,
+                failures: &self.failures,
+            }),
+        }
+    }
+}
+
+fn print_json(record: &Record) {
+    match serde_json::to_string(record) {
+        Ok(line) => println!("{}", line),
+        Err(e) => error!("Failed to serialize output: {}", e),
+    }
+}
@@ -0,0 +1,365 @@
+use crate::diskspace::{self, Threshold};
+use crate::duplicates;
+use crate::paths;
+use crate::safety::Protection;
+use crate::scan::{self, RepositoryPolicy};
+use crate::statefile;
+use crate::transfer;
+use crate::trash::{self, Trashed};
+use chrono::{DateTime, Duration, Local, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const RETENTION_FILE: &str = "retention.json";
+
+/// Limits on what a destination keeps, from the `retention` action in `rules.json`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RetentionAction {
+    /// Files not modified for this many days are cleaned up.
+    pub max_age_days: Option<u64>,
+    /// Most the destination may hold, e.g. `20GB` or `10%` of its drive; the oldest files go
+    /// first.
+    pub max_size: Option<Threshold>,
+    /// Folder cleaned-up files are moved to, relative to the sorted folder; without it they
+    /// are deleted, or put in the trash when that is on.
+    pub archive_to: Option<String>,
+}
+
+/// A destination with a `retention` action, with its folders resolved.
+pub struct Limited {
+    /// The destination as written in `actions`.
+    pub destination: String,
+    pub folder: PathBuf,
+    pub archive: Option<PathBuf>,
+    pub action: RetentionAction,
+    /// The files the move index says the destination's rules placed; nothing else in the
+    /// folder is ever cleaned up.
+    pub placed: HashSet<PathBuf>,
+}
+
+/// What a cleanup found: the files to clean up now, which were listed in the reminder of
+/// the cleanup before, and the files listed now for the next one, with their destination.
+pub struct Cleanup {
+    pub due: Vec<(PathBuf, usize)>,
+    pub listed: Vec<(PathBuf, usize)>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct RetentionState {
+    /// When destinations were last cleaned up, in seconds since the epoch.
+    last_run: Option<i64>,
+    /// Files past their destination's limits at the last cleanup, by their destination;
+    /// they go at the next one.
+    #[serde(default)]
+    pending: BTreeMap<PathBuf, String>,
+    /// Files that are never cleaned up.
+    #[serde(default)]
+    exempt: BTreeSet<PathBuf>,
+}
+
+/// The files waiting for the next cleanup and those exempted from it, kept across runs.
+pub struct Retention {
+    path: PathBuf,
+    state: RetentionState,
+    /// Files exempted, or let be cleaned up again, since the state was loaded.
+    exemptions: BTreeMap<PathBuf, bool>,
+    /// Whether a cleanup ran since the state was loaded, so its list replaces the saved one.
+    ran: bool,
+}
+
+impl Retention {
+    pub fn load() -> Self {
+        Retention::open(paths::data_dir().join(RETENTION_FILE))
+    }
+
+    fn open(path: PathBuf) -> Self {
+        Retention {
+            state: read(&path),
+            path,
+            exemptions: BTreeMap::new(),
+            ran: false,
+        }
+    }
+
+    /// Writes the state to the file under its lock, over what another process saved there
+    /// meanwhile: exemptions made here are added to those, and a cleanup run here replaces
+    /// the list of files for the next one.
+    pub fn save(&mut self) -> io::Result<()> {
+        let _lock = statefile::lock(&self.path)?;
+        let mut state = read(&self.path);
+        if self.ran {
+            state.last_run = self.state.last_run;
+            state.pending = std::mem::take(&mut self.state.pending);
+            state.exempt.retain(|file| file.exists());
+        }
+        for (file, exempt) in std::mem::take(&mut self.exemptions) {
+            if exempt {
+                state.exempt.insert(file);
+            } else {
+                state.exempt.remove(&file);
+            }
+        }
+        state.pending.retain(|file, _| !state.exempt.contains(file));
+        statefile::write(&self.path, serde_json::to_string_pretty(&state)?.as_bytes())?;
+        self.state = state;
+        self.ran = false;
+        Ok(())
+    }
+
+    /// Whether a day passed since the last cleanup.
+    pub fn due(&self) -> bool {
+        self.next_run() <= Local::now()
+    }
+
+    pub fn next_run(&self) -> DateTime<Local> {
+        let last = self.state.last_run.and_then(|last| Local.timestamp_opt(last, 0).single());
+        last.map_or_else(Local::now, |last| last + Duration::days(1))
+    }
+
+    /// The files the next cleanup takes, by their destination.
+    pub fn pending(&self) -> &BTreeMap<PathBuf, String> {
+        &self.state.pending
+    }
+
+    /// Keeps `file` from being cleaned up, or lets it be again.
+    pub fn set_exempt(&mut self, file: &Path, exempt: bool) -> io::Result<()> {
+        let file = fs::canonicalize(file)?;
+        if exempt {
+            self.state.pending.remove(&file);
+            self.state.exempt.insert(file.clone());
+        } else {
+            self.state.exempt.remove(&file);
+        }
+        self.exemptions.insert(file, exempt);
+        Ok(())
+    }
+
+    /// Finds the files past the limits of `limited` that `protection` doesn't keep. Those that
+    /// were already past them at the last cleanup are due now; the others are listed for the
+    /// next one.
+    pub fn run(&mut self, limited: &[Limited], protection: &Protection) -> Cleanup {
+        let now = Local::now();
+        let mut cleanup = Cleanup {
+            due: Vec::new(),
+            listed: Vec::new(),
+        };
+        let mut pending = BTreeMap::new();
+        for (position, destination) in limited.iter().enumerate() {
+            let past = match past_limits(destination, &self.state.exempt, protection, now) {
+                Ok(past) => past,
+                // A destination that can't be read keeps what was listed for it.
+                Err(_) => {
+                    let listed = self.state.pending.iter().filter(|(_, listed)| **listed == destination.destination);
+                    pending.extend(listed.map(|(file, listed)| (file.clone(), listed.clone())));
+                    continue;
+                }
+            };
+            for file in past {
+                if self.state.pending.get(&file) == Some(&destination.destination) {
+                    cleanup.due.push((file, position));
+                } else {
+                    cleanup.listed.push((file.clone(), position));
+                    pending.insert(file, destination.destination.clone());
+                }
+            }
+        }
+        // Files that went, were exempted or are within the limits again are forgotten.
+        self.state.exempt.retain(|file| file.exists());
+        self.state.pending = pending;
+        self.state.last_run = Some(now.timestamp());
+        self.ran = true;
+        cleanup
+    }
+}
+
+fn read(path: &Path) -> RetentionState {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// The files placed below the destination's folder past its age or size, oldest first.
+/// Exempt files count towards the size but are never returned.
+fn past_limits(limited: &Limited, exempt: &BTreeSet<PathBuf>, protection: &Protection, now: DateTime<Local>) -> io::Result<Vec<PathBuf>> {
+    let folder = fs::canonicalize(&limited.folder)?;
+    let archive: Vec<PathBuf> = limited.archive.iter().filter_map(|archive| fs::canonicalize(archive).ok()).collect();
+    let mut files: Vec<(PathBuf, SystemTime, u64)> = scan::collect_files(&folder, true, &archive, RepositoryPolicy::Skip, false, None)?
+        .into_iter()
+        .filter(|file| limited.placed.contains(file))
+        .filter_map(|file| {
+            let metadata = fs::metadata(&file).ok().filter(|metadata| metadata.is_file())?;
+            Some((file, metadata.modified().ok()?, metadata.len()))
+        })
+        .collect();
+    files.sort_by_key(|(_, modified, _)| *modified);
+    let cutoff = limited.action.max_age_days.map(|days| SystemTime::from(now - Duration::days(days as i64)));
+    let mut held: u64 = files.iter().map(|(_, _, size)| size).sum();
+    let limit = match limited.action.max_size {
+        Some(Threshold::Bytes(bytes)) => Some(bytes),
+        Some(threshold) => Some(threshold.bytes(diskspace::space(&folder)?.1)),
+        None => None,
+    };
+    let mut past = Vec::new();
+    for (file, modified, size) in files {
+        if exempt.contains(&file) || protection.is_protected(&file) {
+            continue;
+        }
+        let too_old = cutoff.is_some_and(|cutoff| modified < cutoff);
+        let too_much = limit.is_some_and(|limit| held > limit);
+        if too_old || too_much {
+            held -= size;
+            past.push(file);
+        }
+    }
+    Ok(past)
+}
+
+/// What a cleanup did with a file.
+pub enum Cleaned {
+    /// Moved below the archive folder, to this path, with the checksum it was verified with
+    /// when it was copied there.
+    Archived(PathBuf, Option<String>),
+    Trashed(Trashed),
+    Removed,
+}
+
+/// Moves `file` below the archive folder, where it keeps its place relative to the
+/// destination; without an archive folder it is removed.
+pub fn clean(file: &Path, limited: &Limited, settings: &transfer::Settings) -> io::Result<Cleaned> {
+    let Some(archive) = &limited.archive else {
+        return Ok(trash::remove(file, settings)?.map_or(Cleaned::Removed, Cleaned::Trashed));
+    };
+    let folder = fs::canonicalize(&limited.folder)?;
+    let relative = file.strip_prefix(&folder).unwrap_or(Path::new(file.file_name().unwrap_or_default()));
+    let target = duplicates::unique_path(&archive.join(relative));
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let checksum = transfer::move_file(file, &target, settings)?;
+    Ok(Cleaned::Archived(target, checksum))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    fn folder(name: &str) -> TestDir {
+        TestDir::new("retention", name)
+    }
+
+    fn add(folder: &Path, name: &str, size: usize, days_old: u64) -> PathBuf {
+        let file = folder.join(name);
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(&file, vec![0u8; size]).unwrap();
+        let modified = SystemTime::now() - std::time::Duration::from_secs(days_old * 24 * 60 * 60);
+        fs::File::options().write(true).open(&file).unwrap().set_modified(modified).unwrap();
+        file
+    }
+
+    fn limited(folder: &Path, max_age_days: Option<u64>, max_size: Option<u64>, placed: &[&PathBuf]) -> Limited {
+        Limited {
+            destination: "Documents".to_string(),
+            folder: folder.to_path_buf(),
+            archive: None,
+            action: RetentionAction {
+                max_age_days,
+                max_size: max_size.map(Threshold::Bytes),
+                archive_to: None,
+            },
+            placed: placed.iter().map(|file| file.to_path_buf()).collect(),
+        }
+    }
+
+    fn retention(folder: &Path) -> Retention {
+        Retention::open(folder.join(RETENTION_FILE))
+    }
+
+    #[test]
+    fn lists_old_files_a_cleanup_before_they_are_due() {
+        let folder = folder("age");
+        let old = add(&folder, "old.pdf", 10, 40);
+        let recent = add(&folder, "recent.pdf", 10, 1);
+        let limited = [limited(&folder, Some(30), None, &[&old, &recent])];
+        let mut retention = retention(&folder);
+        let first = retention.run(&limited, &Protection::default());
+        assert!(first.due.is_empty());
+        assert_eq!(first.listed, [(old.clone(), 0)]);
+        let second = retention.run(&limited, &Protection::default());
+        assert_eq!(second.due, [(old, 0)]);
+        assert!(second.listed.is_empty());
+    }
+
+    #[test]
+    fn leaves_files_rules_did_not_place_alone() {
+        let folder = folder("unplaced");
+        let placed = add(&folder, "placed.pdf", 10, 40);
+        add(&folder, "by-hand.pdf", 10, 40);
+        let cleanup = retention(&folder).run(&[limited(&folder, Some(30), None, &[&placed])], &Protection::default());
+        assert_eq!(cleanup.listed, [(placed, 0)]);
+    }
+
+    #[test]
+    fn never_lists_exempt_files() {
+        let folder = folder("exempt");
+        let old = add(&folder, "old.pdf", 10, 40);
+        let mut retention = retention(&folder);
+        retention.set_exempt(&old, true).unwrap();
+        let cleanup = retention.run(&[limited(&folder, Some(30), None, &[&old])], &Protection::default());
+        assert!(cleanup.listed.is_empty());
+        assert!(retention.pending().is_empty());
+    }
+
+    #[test]
+    fn keeps_exemptions_saved_during_a_cleanup() {
+        let folder = folder("exempt-meanwhile");
+        let old = add(&folder, "old.pdf", 10, 40);
+        let mut cleanup = retention(&folder);
+        assert_eq!(cleanup.run(&[limited(&folder, Some(30), None, &[&old])], &Protection::default()).listed, [(old.clone(), 0)]);
+        let mut exempting = retention(&folder);
+        exempting.set_exempt(&old, true).unwrap();
+        exempting.save().unwrap();
+        cleanup.save().unwrap();
+        let saved = retention(&folder);
+        assert!(saved.state.exempt.contains(&old));
+        assert!(saved.pending().is_empty());
+    }
+
+    #[test]
+    fn never_lists_protected_files() {
+        let folder = folder("protected");
+        let wallet = add(&folder, "wallet.kdbx", 10, 40);
+        let protection = Protection::new(&["*.kdbx".to_string()]);
+        let cleanup = retention(&folder).run(&[limited(&folder, Some(30), None, &[&wallet])], &protection);
+        assert!(cleanup.listed.is_empty());
+    }
+
+    #[test]
+    fn lists_the_oldest_files_until_the_destination_fits() {
+        let folder = folder("size");
+        let oldest = add(&folder, "oldest.pdf", 100, 3);
+        let older = add(&folder, "older.pdf", 100, 2);
+        let newest = add(&folder, "newest.pdf", 100, 1);
+        let cleanup = retention(&folder).run(&[limited(&folder, None, Some(150), &[&oldest, &older, &newest])], &Protection::default());
+        assert_eq!(cleanup.listed, [(oldest, 0), (older, 0)]);
+    }
+
+    #[test]
+    fn archives_files_where_they_were_in_the_destination() {
+        let folder = folder("archive");
+        let file = add(&folder.join("Documents"), "2024/report.pdf", 10, 40);
+        let mut limited = limited(&folder.join("Documents"), Some(30), None, &[&file]);
+        limited.archive = Some(folder.join("Archive"));
+        let Cleaned::Archived(archived, _) = clean(&file, &limited, &transfer::Settings::default()).unwrap() else {
+            panic!("the file wasn't archived");
+        };
+        assert_eq!(archived, folder.join("Archive/2024/report.pdf"));
+        assert!(archived.exists());
+        assert!(!file.exists());
+    }
+}
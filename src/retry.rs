@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::fs;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How the daemon retries files other processes hold open, from `config.json`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RetrySettings {
+    /// Tries of a locked file after which it is reported as failed.
+    #[serde(default = "default_attempts")]
+    pub attempts: u32,
+    /// Seconds before the first retry; every further retry waits twice as long.
+    #[serde(default = "default_delay")]
+    pub delay: u64,
+    /// Longest wait between two retries, in seconds.
+    #[serde(default = "default_max_delay")]
+    pub max_delay: u64,
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        RetrySettings {
+            attempts: default_attempts(),
+            delay: default_delay(),
+            max_delay: default_max_delay(),
+        }
+    }
+}
+
+fn default_attempts() -> u32 {
+    8
+}
+
+fn default_delay() -> u64 {
+    5
+}
+
+fn default_max_delay() -> u64 {
+    15 * 60
+}
+
+/// What happens to a file that was locked again.
+pub enum Retry {
+    /// Tried again after the delay.
+    After(Duration),
+    /// Locked on every one of this many tries.
+    GaveUp(u32),
+}
+
+struct Waiting {
+    attempts: u32,
+    next: Instant,
+}
+
+/// Size and modification time of a file, to tell whether it changed.
+type Stamp = (u64, Option<SystemTime>);
+
+/// Files that were locked when the daemon tried to sort them, each tried again on its own
+/// instead of waiting for the next sort of the folder.
+pub struct RetryQueue {
+    settings: RetrySettings,
+    files: HashMap<PathBuf, Waiting>,
+    /// Files locked on every try, as they were then; they are left alone until they change.
+    given_up: HashMap<PathBuf, Stamp>,
+}
+
+impl RetryQueue {
+    pub fn new(settings: RetrySettings) -> Self {
+        RetryQueue {
+            settings,
+            files: HashMap::new(),
+            given_up: HashMap::new(),
+        }
+    }
+    
+    /// Counts a try of `file` that found it locked.
+    pub fn locked(&mut self, file: &Path) -> Retry {
+        let attempts = self.files.get(file).map_or(0, |waiting| waiting.attempts) + 1;
+        if attempts >= self.settings.attempts {
+            self.files.remove(file);
+            if let Some(stamp) = stamp(file) {
+                self.given_up.insert(file.to_path_buf(), stamp);
+            }
+            return Retry::GaveUp(attempts);
+        }
+        let delay = self.settings.delay.saturating_mul(1 << (attempts - 1).min(20)).min(self.settings.max_delay);
+        let delay = Duration::from_secs(delay);
+        self.files.insert(
+            file.to_path_buf(),
+            Waiting {
+                attempts,
+                next: Instant::now() + delay,
+            },
+        );
+        Retry::After(delay)
+    }
+    
+    /// Forgets `file` once it was sorted, or left in place for another reason.
+    pub fn done(&mut self, file: &Path) {
+        self.files.remove(file);
+        self.given_up.remove(file);
+    }
+    
+    /// Whether `file` waits for a retry that is not due yet, or was given up on and hasn't
+    /// changed since; a file given up on that changed is tried again like a new one.
+    pub fn waiting(&mut self, file: &Path) -> bool {
+        if let Some(given_up) = self.given_up.get(file) {
+            if stamp(file).as_ref() == Some(given_up) {
+                return true;
+            }
+            self.given_up.remove(file);
+        }
+        self.files.get(file).is_some_and(|waiting| waiting.next > Instant::now())
+    }
+    
+    /// Files whose retry is due; files that are gone meanwhile are dropped.
+    pub fn due(&mut self) -> Vec<PathBuf> {
+        self.files.retain(|file, _| file.exists());
+        let now = Instant::now();
+        let mut due: Vec<PathBuf> = self.files.iter().filter(|(_, waiting)| waiting.next <= now).map(|(file, _)| file.clone()).collect();
+        due.sort();
+        due
+    }
+    
+    /// Time until the next retry is due, if any file waits for one.
+    pub fn until_next(&self) -> Option<Duration> {
+        let next = self.files.values().map(|waiting| waiting.next).min()?;
+        Some(next.saturating_duration_since(Instant::now()))
+    }
+}
+
+fn stamp(file: &Path) -> Option<Stamp> {
+    let metadata = fs::symlink_metadata(file).ok()?;
+    Some((metadata.len(), metadata.modified().ok()))
+}
+
+/// Whether `error` means another process has the file open or locked.
+pub fn is_locked(error: &io::Error) -> bool {
+    #[cfg(windows)]
+    let locked = [
+        windows_sys::Win32::Foundation::ERROR_SHARING_VIOLATION as i32,
+        windows_sys::Win32::Foundation::ERROR_LOCK_VIOLATION as i32,
+    ];
+    #[cfg(unix)]
+    let locked = [libc::EBUSY, libc::ETXTBSY];
+    #[cfg(not(any(windows, unix)))]
+    let locked: [i32; 0] = [];
+    if error.raw_os_error().is_some_and(|code| locked.contains(&code)) {
+        return true;
+    }
+    // Errors with added context keep the original one inside.
+    error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<io::Error>())
+        .is_some_and(is_locked)
+}
@@ -0,0 +1,173 @@
+use crate::rules::Rule;
+use crate::templates;
+use std::collections::HashMap;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+/// How many file names a batch lists before it only counts the rest.
+const BATCH_PREVIEW: usize = 5;
+
+/// A file a rule would place, as shown for review.
+pub struct Planned {
+    pub file: PathBuf,
+    pub rule: String,
+    /// The rule's destination, placeholders not yet filled in.
+    pub destination: String,
+    pub copy: bool,
+}
+
+pub enum Decision {
+    /// Leave the file where it is.
+    Skip,
+    /// Place the file in another destination than its rule's.
+    Destination(String),
+}
+
+/// The answers given during `sort --interactive`. Files without a decision were accepted.
+#[derive(Default)]
+pub struct Review {
+    decisions: HashMap<PathBuf, Decision>,
+    /// Rules to add to `rules.json` for the answers that should always apply.
+    pub rules: Vec<Rule>,
+}
+
+impl Review {
+    pub fn decision(&self, file_path: &Path) -> Option<&Decision> {
+        self.decisions.get(file_path)
+    }
+
+    fn decide(&mut self, files: &[&Planned], destination: &str) {
+        for planned in files {
+            if planned.destination != destination {
+                self.decisions.insert(planned.file.clone(), Decision::Destination(destination.to_string()));
+            }
+        }
+    }
+
+    fn skip(&mut self, files: &[&Planned]) {
+        for planned in files {
+            self.decisions.insert(planned.file.clone(), Decision::Skip);
+        }
+    }
+
+    /// The destination of an earlier "always" answer that covers the file.
+    fn always(&self, file_path: &Path) -> Option<&str> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(file_path))
+            .map(|rule| rule.destination.as_str())
+    }
+}
+
+/// Asks about every planned file, or with `by_rule` about all files of a rule at once, and
+/// returns the answers. Quitting leaves the files not reviewed yet in place.
+pub fn run(planned: &[Planned], templates: &templates::Settings, by_rule: bool) -> io::Result<Review> {
+    if !io::stdin().is_terminal() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "--interactive needs a terminal to ask in"));
+    }
+    let batches: Vec<Vec<&Planned>> = if by_rule {
+        let mut batches: Vec<(&str, Vec<&Planned>)> = Vec::new();
+        for file in planned {
+            match batches.iter_mut().find(|(rule, _)| *rule == file.rule) {
+                Some((_, files)) => files.push(file),
+                None => batches.push((&file.rule, vec![file])),
+            }
+        }
+        batches.into_iter().map(|(_, files)| files).collect()
+    } else {
+        planned.iter().map(|file| vec![file]).collect()
+    };
+
+    let mut review = Review::default();
+    for (position, batch) in batches.iter().enumerate() {
+        // Files an earlier "always" answer covers are not asked about again.
+        let (covered, batch): (Vec<&Planned>, Vec<&Planned>) = batch.iter().copied().partition(|file| review.always(&file.file).is_some());
+        for file in covered {
+            let destination = review.always(&file.file).unwrap_or_default().to_string();
+            review.decide(&[file], &destination);
+        }
+        if batch.is_empty() {
+            continue;
+        }
+        eprintln!();
+        eprintln!("[{}/{}] rule '{}'", position + 1, batches.len(), batch[0].rule);
+        for file in batch.iter().take(BATCH_PREVIEW) {
+            eprintln!("    {}", file.file.display());
+        }
+        if batch.len() > BATCH_PREVIEW {
+            eprintln!("    ... and {} more", batch.len() - BATCH_PREVIEW);
+        }
+        let mut destination = batch[0].destination.clone();
+        loop {
+            let verb = if batch[0].copy { "Copy" } else { "Move" };
+            eprintln!("  -> {}", preview(&destination, &batch, templates));
+            let Some(answer) = ask(&format!("{} [y]es, [n]o, [e]dit destination, [a]lways, [q]uit? ", verb))? else {
+                review.skip(&batch);
+                review.skip(&batches[position + 1..].concat());
+                return Ok(review);
+            };
+            match answer.to_lowercase().as_str() {
+                "y" | "yes" | "" => review.decide(&batch, &destination),
+                "n" | "no" => review.skip(&batch),
+                "e" | "edit" => {
+                    if let Some(edited) = ask("  Destination: ")?.filter(|edited| !edited.is_empty()) {
+                        destination = edited;
+                    }
+                    continue;
+                }
+                "a" | "always" => {
+                    review.decide(&batch, &destination);
+                    for pattern in patterns(&batch) {
+                        eprintln!("  Adding rule {} -> {}", pattern, destination);
+                        review.rules.push(Rule::new(&pattern, &destination));
+                    }
+                }
+                "q" | "quit" => {
+                    review.skip(&batch);
+                    review.skip(&batches[position + 1..].concat());
+                    return Ok(review);
+                }
+                _ => continue,
+            }
+            break;
+        }
+    }
+    Ok(review)
+}
+
+/// The destination as it will be for the first file, with the template when it differs.
+fn preview(destination: &str, batch: &[&Planned], templates: &templates::Settings) -> String {
+    let expanded = templates::expand_destination(destination, &batch[0].file, templates);
+    if expanded == destination {
+        expanded
+    } else {
+        format!("{} ({})", expanded, destination)
+    }
+}
+
+/// Reads an answer; `None` at the end of input.
+fn ask(prompt: &str) -> io::Result<Option<String>> {
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(answer.trim().to_string()))
+}
+
+/// Patterns that match the files of a batch for an "always" answer: their extensions, or
+/// their names when they have none.
+fn patterns(batch: &[&Planned]) -> Vec<String> {
+    let mut patterns: Vec<String> = Vec::new();
+    for file in batch {
+        let pattern = match file.file.extension() {
+            Some(extension) => format!(".{}", extension.to_string_lossy()),
+            None => file.file.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+        };
+        if !pattern.is_empty() && !patterns.contains(&pattern) {
+            patterns.push(pattern);
+        }
+    }
+    patterns
+}
@@ -0,0 +1,294 @@
+use crate::capacity::Capacity;
+use crate::content;
+use crate::dates;
+use crate::diskspace;
+use crate::exec::{self, CommandEnvironment};
+use crate::limits::RateLimit;
+use crate::links::{BrokenSymlinkPolicy, SymlinkPolicy};
+use crate::matching;
+use crate::permissions::{Owner, Permissions};
+use crate::templates;
+use crate::timing::{self, Stage};
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Whether matched files are moved or copied to their destination.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Move,
+    /// Leave files in place and keep copies of them up to date, like a one-way sync.
+    Copy,
+}
+
+/// One entry of the ordered rule list in `rules.json`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Rule {
+    /// Lets the rule be picked with `sort --rule <name>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// An extension such as `.pdf`, or a glob matched against the file name such as `invoice*.pdf`.
+    #[serde(rename = "match")]
+    pub pattern: String,
+    /// Text the file has to contain as well, e.g. a customer number.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contains: Option<String>,
+    /// Regular expression the file's content has to match as well.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matches_content: Option<String>,
+    /// Kilobytes from the start of the file that `contains` and `matches_content` look at;
+    /// 64 by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_kb: Option<u64>,
+    /// Where matched files go; rules with `exec` leave it to their command instead.
+    #[serde(default)]
+    pub destination: String,
+    /// Command that decides for the files `match` selects: it gets their metadata as JSON
+    /// on stdin and prints a destination, or nothing to pass the file on to later rules.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exec: Option<String>,
+    /// Variables, working directory and PATH for the `exec` command.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<CommandEnvironment>,
+    /// Rules with a higher priority are tried first; rules with equal priority keep their order.
+    #[serde(default)]
+    pub priority: i32,
+    /// Copy the file to the destination instead of moving it.
+    #[serde(default)]
+    pub copy: bool,
+    /// Overrides the mode of the sort for this rule.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<Mode>,
+    /// Keep trying later rules after this one matched, e.g. to copy a file and then move it.
+    #[serde(default, rename = "continue")]
+    pub continue_matching: bool,
+    /// Leave a symlink to the moved file in its place, for programs that still use the old path.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub link_back: bool,
+    /// Overrides the global `symlinks` policy for links this rule matches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symlinks: Option<SymlinkPolicy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub broken_symlinks: Option<BrokenSymlinkPolicy>,
+    /// Seconds to wait after the rule handled a file before it handles the next one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cooldown: Option<u64>,
+    /// Most files the rule handles within an hour; the rest stay in place until a later sort.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_per_hour: Option<u32>,
+    /// Most the destination may hold, e.g. `500G`; once it does, files stay in place.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_dest_size: Option<String>,
+    /// Most files the destination may hold; once it does, files stay in place.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_files: Option<u64>,
+    /// Check that the file's content fits its extension and quarantine it when it doesn't.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub verify_type: bool,
+    /// Have the `antivirus` scanner check the file and quarantine it when a threat is found.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub scan: bool,
+    /// Permissions the rule gives the files it places, e.g. `0640` or `a-x`; the `permissions`
+    /// command restores them later.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<Permissions>,
+    /// Owner the rule gives the files it places, e.g. `media` or `:family`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Owner>,
+    /// Most bytes per second uploads to `rclone:` destinations may use, e.g. `2M`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<String>,
+    /// The last day the rule applies, e.g. `2024-12-31`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<NaiveDate>,
+    /// How long the rule applies after a sort first saw it, e.g. `14d` or `2w`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<String>,
+    /// Run the handoff commands of the files the rule matched one at a time, in the order
+    /// the files arrived, while other commands and moves still run alongside.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub serial: bool,
+    /// Also sort hidden files, which are otherwise left where they are.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hidden: bool,
+    /// `matches_content`, compiled by [`Rule::validate`].
+    #[serde(skip)]
+    content_regex: Option<Regex>,
+}
+
+impl Rule {
+    pub fn new(pattern: &str, destination: &str) -> Rule {
+        Rule {
+            name: None,
+            pattern: pattern.to_string(),
+            contains: None,
+            matches_content: None,
+            content_kb: None,
+            destination: destination.to_string(),
+            exec: None,
+            environment: None,
+            priority: 0,
+            copy: false,
+            mode: None,
+            continue_matching: false,
+            link_back: false,
+            symlinks: None,
+            broken_symlinks: None,
+            cooldown: None,
+            max_per_hour: None,
+            max_dest_size: None,
+            max_files: None,
+            verify_type: false,
+            scan: false,
+            permissions: None,
+            owner: None,
+            bandwidth: None,
+            expires: None,
+            ttl: None,
+            serial: false,
+            hidden: false,
+            content_regex: None,
+        }
+    }
+
+    /// How often the rule may act, if it is limited at all.
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        (self.cooldown.is_some() || self.max_per_hour.is_some()).then_some(RateLimit {
+            cooldown: self.cooldown,
+            max_per_hour: self.max_per_hour,
+        })
+    }
+
+    /// How much the rule's destination may hold, if it is capped at all.
+    pub fn capacity(&self) -> Option<Capacity> {
+        (self.max_dest_size.is_some() || self.max_files.is_some()).then(|| Capacity {
+            max_bytes: self.max_dest_size.as_deref().and_then(diskspace::parse_size),
+            max_files: self.max_files,
+        })
+    }
+
+    /// What the rule is called where rules that share a `match` have to be told apart, as in
+    /// timings: its name, or its `match` and where it sends files.
+    pub fn label(&self) -> String {
+        match (&self.name, &self.exec) {
+            (Some(name), _) => name.clone(),
+            (None, Some(command)) => format!("{} -> `{}`", self.pattern, command),
+            (None, None) => format!("{} -> {}", self.pattern, self.destination),
+        }
+    }
+
+    /// Whether the rule is called `name`, either by its name or by its `match` pattern.
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name.as_deref() == Some(name) || self.pattern == name
+    }
+
+    pub fn matches(&self, file_path: &Path) -> bool {
+        self.matches_in(&mut content::Texts::new(file_path))
+    }
+
+    /// Like [`Rule::matches`], with the content of the file from `texts`, so the rules tried on
+    /// one file read it once.
+    pub fn matches_in(&self, texts: &mut content::Texts) -> bool {
+        let label = self.label();
+        timing::time(&label, Stage::Pattern, || pattern_matches(&self.pattern, texts.file_path()))
+            && (!self.checks_content() || timing::time(&label, Stage::Content, || self.content_matches(texts)))
+    }
+
+    fn checks_content(&self) -> bool {
+        self.contains.is_some() || self.matches_content.is_some()
+    }
+
+    /// Checks the rule when the rules are loaded, and compiles `matches_content` once rather
+    /// than for every file.
+    pub fn validate(&mut self) -> Result<(), String> {
+        match &self.exec {
+            Some(command) => exec::parse(command).map(|_| ()).map_err(|e| format!("invalid exec of rule {}: {}", self.pattern, e))?,
+            None if self.destination.is_empty() => {
+                return Err(format!("rule {} has no destination; give it one, or an `exec` command that decides it", self.pattern));
+            }
+            None => templates::validate_destination(&self.destination).map_err(|e| format!("invalid destination of rule {}: {}", self.pattern, e))?,
+        }
+        if let Some(ttl) = self.ttl.as_deref().filter(|ttl| dates::parse_age(ttl).is_none()) {
+            return Err(format!("invalid ttl of rule {}: '{}', expected e.g. 12h, 14d or 2w", self.pattern, ttl));
+        }
+        if let Some(size) = self.max_dest_size.as_deref().filter(|size| diskspace::parse_size(size).is_none()) {
+            return Err(format!("invalid max_dest_size of rule {}: '{}', expected e.g. 500M or 2T", self.pattern, size));
+        }
+        self.content_regex = match &self.matches_content {
+            Some(pattern) => Some(Regex::new(pattern).map_err(|e| format!("invalid matches_content of rule {}: {}", self.pattern, e))?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Whether the file's content has what `contains` and `matches_content` ask for; files
+    /// that can't be read don't.
+    fn content_matches(&self, texts: &mut content::Texts) -> bool {
+        let Some(text) = texts.get(self.content_kb.unwrap_or(content::DEFAULT_KB)) else {
+            return false;
+        };
+        if self.contains.as_ref().is_some_and(|needle| !text.contains(needle.as_str())) {
+            return false;
+        }
+        match &self.matches_content {
+            // A rule that wasn't validated has no compiled pattern and matches no content.
+            Some(_) => self.content_regex.as_ref().is_some_and(|regex| regex.is_match(text)),
+            None => true,
+        }
+    }
+
+    /// Describes why the rule does or does not match the file.
+    pub fn explain(&self, texts: &mut content::Texts) -> String {
+        let file_path = texts.file_path();
+        let name = file_path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        let explained = matching::explain(&self.pattern, &name);
+        if !self.checks_content() || !pattern_matches(&self.pattern, file_path) {
+            return explained;
+        }
+        if self.content_matches(texts) {
+            format!("{}, content matches", explained)
+        } else {
+            format!("{}, but content does not match", explained)
+        }
+    }
+}
+
+/// Decides for every rule whether it copies: a rule's own `mode` wins, then `copy`, then
+/// the mode of the sort.
+pub fn apply_mode(rules: &mut [Rule], mode: Mode) {
+    for rule in rules {
+        rule.copy = match rule.mode {
+            Some(own) => own == Mode::Copy,
+            None => rule.copy || mode == Mode::Copy,
+        };
+    }
+}
+
+/// Matches an extension such as `.pdf` or a glob such as `invoice*.pdf` against a file name.
+pub fn pattern_matches(pattern: &str, file_path: &Path) -> bool {
+    let Some(name) = file_path.file_name().map(|name| name.to_string_lossy()) else {
+        return false;
+    };
+    matching::name_matches(pattern, &name)
+}
+
+/// Reads either an ordered list of rules or the original `{ ".ext": "Destination" }` map.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Rule>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Rules {
+        List(Vec<Rule>),
+        Map(BTreeMap<String, String>),
+    }
+    let mut rules = match Rules::deserialize(deserializer)? {
+        Rules::List(rules) => rules,
+        Rules::Map(map) => map.iter().map(|(pattern, destination)| Rule::new(pattern, destination)).collect(),
+    };
+    // Stable, so rules with equal priority keep the order they were written in.
+    rules.sort_by_key(|rule| std::cmp::Reverse(rule.priority));
+    Ok(rules)
+}
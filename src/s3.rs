@@ -0,0 +1,250 @@
+use crate::audit;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Files larger than this are uploaded in parts of this size, so a failed part is all
+/// that has to be sent again.
+const PART_SIZE: usize = 16 * 1024 * 1024;
+
+/// A bucket on S3, or on another service with the same API.
+pub struct Bucket<'a> {
+    pub name: &'a str,
+    pub region: &'a str,
+    /// E.g. `https://minio.local:9000`; buckets are then addressed by path.
+    pub endpoint: Option<&'a str>,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+impl Bucket<'_> {
+    /// A request for `object`, signed with Signature Version 4.
+    fn request(&self, method: &str, object: &str, query: &[(&str, &str)], body: &[u8]) -> ureq::Request {
+        let (base, host, path) = match self.endpoint {
+            Some(endpoint) => {
+                let endpoint = endpoint.trim_end_matches('/');
+                let host = endpoint.split_once("://").map_or(endpoint, |(_, host)| host);
+                let path = format!("/{}/{}", self.name, encode(object, true));
+                (endpoint.to_string(), host.to_string(), path)
+            }
+            None => {
+                let host = format!("{}.s3.{}.amazonaws.com", self.name, self.region);
+                (format!("https://{}", host), host, format!("/{}", encode(object, true)))
+            }
+        };
+        let mut query: Vec<String> = query.iter().map(|(key, value)| format!("{}={}", encode(key, false), encode(value, false))).collect();
+        query.sort();
+        let query = query.join("&");
+
+        let now = Utc::now();
+        let (date, time) = (now.format("%Y%m%d").to_string(), now.format("%Y%m%dT%H%M%SZ").to_string());
+        let payload = audit::encode_hex(&Sha256::digest(body));
+        let canonical = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
+            method, path, query, host, payload, time, payload
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", time, scope, audit::encode_hex(&Sha256::digest(canonical.as_bytes())));
+        let key = [self.region, "s3", "aws4_request"]
+            .iter()
+            .fold(hmac(format!("AWS4{}", self.secret_key).as_bytes(), &date), |key, part| hmac(&key, part));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+            self.access_key,
+            scope,
+            audit::encode_hex(&hmac(&key, &to_sign))
+        );
+        let url = if query.is_empty() { format!("{}{}", base, path) } else { format!("{}{}?{}", base, path, query) };
+        ureq::request(method, &url)
+            .timeout(TIMEOUT)
+            .set("x-amz-content-sha256", &payload)
+            .set("x-amz-date", &time)
+            .set("Authorization", &authorization)
+    }
+
+    /// Stores `body` as `object`. With `keep_existing`, an object that is already there is
+    /// left as it is.
+    pub fn put(&self, object: &str, body: &[u8], keep_existing: bool) -> io::Result<()> {
+        let mut request = self.request("PUT", object, &[], body);
+        if keep_existing {
+            request = request.set("If-None-Match", "*");
+        }
+        match request.send_bytes(body) {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(412, _)) if keep_existing => Ok(()),
+            Err(e) => Err(io::Error::other(e)),
+        }
+    }
+
+    /// Whether `object` is in the bucket.
+    pub fn exists(&self, object: &str) -> io::Result<bool> {
+        match self.request("HEAD", object, &[], b"").call() {
+            Ok(_) => Ok(true),
+            Err(ureq::Error::Status(404, _)) => Ok(false),
+            Err(e) => Err(io::Error::other(e)),
+        }
+    }
+
+    /// Uploads the file at `path` as `object`, trying each request up to `attempts` times.
+    /// Large files go up in parts, and a retry continues with the part that failed.
+    pub fn upload(&self, object: &str, path: &Path, attempts: u32) -> io::Result<()> {
+        let mut file = File::open(path)?;
+        let size = file.metadata()?.len();
+        if size <= PART_SIZE as u64 {
+            let mut body = Vec::with_capacity(size as usize);
+            file.read_to_end(&mut body)?;
+            return retry(attempts, || self.put(object, &body, false));
+        }
+
+        let answer = retry(attempts, || call(self.request("POST", object, &[("uploads", "")], b"").send_bytes(b"")))?;
+        let upload_id = element(&answer, "UploadId").ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No UploadId in the answer"))?;
+        let result = self.upload_parts(object, &mut file, &upload_id, attempts);
+        if result.is_err() {
+            // Parts already stored are otherwise kept, and billed, until the bucket expires them.
+            let _ = self.request("DELETE", object, &[("uploadId", &upload_id)], b"").call();
+        }
+        result
+    }
+
+    fn upload_parts(&self, object: &str, file: &mut File, upload_id: &str, attempts: u32) -> io::Result<()> {
+        let mut completion = String::from("<CompleteMultipartUpload>");
+        let mut part = vec![0; PART_SIZE];
+        for number in 1.. {
+            let length = read_full(file, &mut part)?;
+            if length == 0 {
+                break;
+            }
+            let body = &part[..length];
+            let number_text = number.to_string();
+            let query = [("partNumber", number_text.as_str()), ("uploadId", upload_id)];
+            let etag = retry(attempts, || {
+                let response = self.request("PUT", object, &query, body).send_bytes(body).map_err(io::Error::other)?;
+                response
+                    .header("ETag")
+                    .map(str::to_string)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No ETag for an uploaded part"))
+            })?;
+            completion.push_str(&format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", number, etag));
+        }
+        completion.push_str("</CompleteMultipartUpload>");
+        let answer = retry(attempts, || {
+            call(self.request("POST", object, &[("uploadId", upload_id)], completion.as_bytes()).send_bytes(completion.as_bytes()))
+        })?;
+        // The service may still fail the upload after answering 200.
+        match element(&answer, "Code") {
+            Some(code) => Err(io::Error::other(format!("Completing the upload failed: {}", code))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Runs `attempt` until it succeeds or failed `attempts` times, waiting a little longer
+/// after every failure. Errors that another try won't fix are returned right away.
+pub fn retry<T>(attempts: u32, mut attempt: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut failures = 0;
+    loop {
+        match attempt() {
+            Ok(value) => return Ok(value),
+            Err(e) if failures + 1 >= attempts.max(1) || !transient(&e) => return Err(e),
+            Err(e) => {
+                failures += 1;
+                tracing::debug!("Attempt {} failed, retrying: {}", failures, e);
+                std::thread::sleep(Duration::from_secs(2u64.pow(failures.min(6))));
+            }
+        }
+    }
+}
+
+fn transient(error: &io::Error) -> bool {
+    !matches!(
+        error.kind(),
+        io::ErrorKind::AlreadyExists | io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied | io::ErrorKind::InvalidInput | io::ErrorKind::Unsupported
+    )
+}
+
+fn call(result: Result<ureq::Response, ureq::Error>) -> io::Result<String> {
+    result.map_err(io::Error::other)?.into_string()
+}
+
+/// Reads until `buffer` is full or the file ends.
+fn read_full(file: &mut File, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
+
+/// The text of the first `<name>` element of an XML answer.
+fn element(xml: &str, name: &str) -> Option<String> {
+    let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = xml[start..].find(&format!("</{}>", name))? + start;
+    Some(xml[start..end].to_string())
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encodes an object name or query value for a URL; object names keep the
+/// slashes between their parts.
+fn encode(text: &str, keep_slashes: bool) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            b'/' if keep_slashes => "/".to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gives_up_right_away_on_errors_retrying_cannot_fix() {
+        let mut attempts = 0;
+        let result: io::Result<()> = retry(5, || {
+            attempts += 1;
+            Err(io::Error::new(io::ErrorKind::AlreadyExists, "taken"))
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn stops_after_the_last_attempt() {
+        let mut attempts = 0;
+        let result: io::Result<()> = retry(1, || {
+            attempts += 1;
+            Err(io::Error::other("connection reset"))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn encodes_object_names_for_urls() {
+        assert_eq!(encode("scans/2024/my scan+1.pdf", true), "scans/2024/my%20scan%2B1.pdf");
+        assert_eq!(encode("a/b", false), "a%2Fb");
+    }
+
+    #[test]
+    fn reads_elements_of_xml_answers() {
+        let xml = "<InitiateMultipartUploadResult><UploadId>abc123</UploadId></InitiateMultipartUploadResult>";
+        assert_eq!(element(xml, "UploadId").as_deref(), Some("abc123"));
+        assert_eq!(element(xml, "ETag"), None);
+    }
+}
@@ -0,0 +1,123 @@
+use crate::paths;
+use crate::statefile;
+use chrono::{DateTime, Duration, Local, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const CRASHES_FILE: &str = "crashes.json";
+
+/// When the daemon starts in safe mode, from `safe_mode` in `config.json`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SafeModeSettings {
+    /// Crashes within `window` after which the daemon starts in safe mode; 0 never does.
+    #[serde(default = "default_crashes")]
+    pub crashes: usize,
+    /// Seconds the crashes have to fall within.
+    #[serde(default = "default_window")]
+    pub window: u64,
+}
+
+impl Default for SafeModeSettings {
+    fn default() -> Self {
+        SafeModeSettings {
+            crashes: default_crashes(),
+            window: default_window(),
+        }
+    }
+}
+
+fn default_crashes() -> usize {
+    3
+}
+
+fn default_window() -> u64 {
+    10 * 60
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct FolderState {
+    /// A daemon is sorting the folder, or stopped without shutting down.
+    #[serde(default)]
+    running: bool,
+    /// When daemons for the folder crashed, in seconds since the epoch.
+    #[serde(default)]
+    pub crashes: Vec<i64>,
+    /// Since when the daemon only shows what it would do, in seconds since the epoch.
+    safe_since: Option<i64>,
+}
+
+impl FolderState {
+    /// Since when the folder is in safe mode.
+    pub fn safe_since(&self) -> Option<DateTime<Local>> {
+        self.safe_since.and_then(|since| Local.timestamp_opt(since, 0).single())
+    }
+}
+
+/// The daemon starts and crashes of each sorted folder, kept across runs.
+pub struct CrashLog {
+    path: PathBuf,
+    folders: BTreeMap<PathBuf, FolderState>,
+}
+
+impl CrashLog {
+    pub fn load() -> Self {
+        let path = paths::data_dir().join(CRASHES_FILE);
+        CrashLog { folders: read(&path), path }
+    }
+
+    pub fn folder(&self, root: &Path) -> Option<&FolderState> {
+        self.folders.get(root)
+    }
+
+    /// Notes that a daemon starts sorting `root`. A daemon before it that didn't shut down
+    /// counts as a crash, and with enough crashes within the window the folder goes into
+    /// safe mode; returns the state it starts in.
+    pub fn start(&mut self, root: &Path, settings: &SafeModeSettings) -> io::Result<FolderState> {
+        let now = Local::now();
+        self.update(root, |state| {
+            if state.running {
+                state.crashes.push(now.timestamp());
+            }
+            let window_start = (now - Duration::seconds(settings.window as i64)).timestamp();
+            state.crashes.retain(|crashed| *crashed >= window_start);
+            if settings.crashes > 0 && state.crashes.len() >= settings.crashes && state.safe_since.is_none() {
+                state.safe_since = Some(now.timestamp());
+            }
+            state.running = true;
+            state.clone()
+        })
+    }
+
+    /// Notes that the daemon sorting `root` shut down as it should.
+    pub fn stop(&mut self, root: &Path) -> io::Result<()> {
+        self.update(root, |state| state.running = false)
+    }
+
+    /// Takes `root` out of safe mode and forgets its crashes.
+    pub fn enforce(&mut self, root: &Path) -> io::Result<()> {
+        self.update(root, |state| {
+            state.crashes.clear();
+            state.safe_since = None;
+        })
+    }
+
+    /// Changes the state of `root` as it is in the file by now, under the file's lock, and
+    /// saves it, so what daemons of other folders and `resume` saved meanwhile is kept.
+    fn update<T>(&mut self, root: &Path, change: impl FnOnce(&mut FolderState) -> T) -> io::Result<T> {
+        let _lock = statefile::lock(&self.path)?;
+        self.folders = read(&self.path);
+        let changed = change(self.folders.entry(root.to_path_buf()).or_default());
+        statefile::write(&self.path, serde_json::to_string_pretty(&self.folders)?.as_bytes())?;
+        Ok(changed)
+    }
+}
+
+fn read(path: &Path) -> BTreeMap<PathBuf, FolderState> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
@@ -0,0 +1,110 @@
+use crate::paths;
+use crate::scan::RepositoryPolicy;
+use glob::Pattern;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Folders that hold the system rather than anyone's files.
+#[cfg(unix)]
+const SYSTEM_FOLDERS: &[&str] = &[
+    "/bin", "/boot", "/dev", "/etc", "/lib", "/lib64", "/opt", "/proc", "/sbin", "/sys", "/usr", "/var", "/home", "/Users", "/System", "/Library",
+    "/Applications",
+];
+#[cfg(windows)]
+const SYSTEM_FOLDERS: &[&str] = &[r"C:\Windows", r"C:\Program Files", r"C:\Program Files (x86)", r"C:\ProgramData", r"C:\Users"];
+#[cfg(not(any(unix, windows)))]
+const SYSTEM_FOLDERS: &[&str] = &[];
+
+#[derive(Clone)]
+struct Protected {
+    pattern: Pattern,
+    /// Globs without a folder, such as `*.kdbx`, match names rather than whole paths.
+    by_name: bool,
+}
+
+fn parse(glob: &str) -> Result<Protected, String> {
+    let expanded = match glob.strip_prefix("~/") {
+        Some(rest) => paths::home_dir().map_or_else(|| PathBuf::from(glob), |home| home.join(rest)),
+        None => paths::resolve_path(glob),
+    };
+    let expanded = expanded.to_string_lossy();
+    let pattern = Pattern::new(&expanded).map_err(|e| format!("Invalid `protected` glob '{}': {}", glob, e))?;
+    Ok(Protected {
+        pattern,
+        by_name: !expanded.contains(['/', '\\']),
+    })
+}
+
+pub fn validate(globs: &[String]) -> Result<(), String> {
+    globs.iter().try_for_each(|glob| parse(glob).map(|_| ()))
+}
+
+/// The `protected` globs of `rules.json`, compiled; nothing moves or deletes what they match.
+#[derive(Clone, Default)]
+pub struct Protection(Vec<Protected>);
+
+impl Protection {
+    /// Protects what `globs` match; invalid globs were turned down when the rules were read.
+    pub fn new(globs: &[String]) -> Self {
+        Protection(globs.iter().filter_map(|glob| parse(glob).ok()).collect())
+    }
+
+    /// Whether `path`, or a folder it is in, matches a `protected` glob.
+    pub fn is_protected(&self, path: &Path) -> bool {
+        if self.0.is_empty() {
+            return false;
+        }
+        path.ancestors().any(|ancestor| {
+            self.0.iter().any(|protected| match ancestor.file_name() {
+                Some(name) if protected.by_name => protected.pattern.matches(&name.to_string_lossy()),
+                _ => !protected.by_name && protected.pattern.matches_path(ancestor),
+            })
+        })
+    }
+
+    /// Fails for protected paths, before anything moves or deletes them.
+    pub fn check(&self, path: &Path) -> io::Result<()> {
+        if self.is_protected(path) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{} is protected in rules.json", path.display())));
+        }
+        Ok(())
+    }
+}
+
+/// Why sorting `folder` as a whole is refused without `--force`: the root of a drive, a home
+/// folder itself, a system folder, a folder in a `.git` directory, or the top of a working
+/// tree unless `repositories` is `sort`, where one wrong `--path` would scatter files that
+/// can't be put back.
+pub fn refusal(folder: &Path, repositories: RepositoryPolicy) -> Option<String> {
+    let folder = canonical(folder);
+    if folder.parent().is_none() {
+        return Some(format!("{} is the root of a drive", folder.display()));
+    }
+    if paths::home_dir().is_some_and(|home| canonical(&home) == folder) {
+        return Some(format!("{} is your home folder", folder.display()));
+    }
+    if SYSTEM_FOLDERS.iter().any(|system| folder == Path::new(system)) {
+        return Some(format!("{} is a system folder", folder.display()));
+    }
+    // Users' home folders, such as /home/alice or C:\Users\alice.
+    if let Some(parent) = folder.parent().filter(|parent| ["/home", "/Users", r"C:\Users"].iter().any(|homes| *parent == Path::new(homes))) {
+        return Some(format!("{} is the home folder of a user in {}", folder.display(), parent.display()));
+    }
+    if folder.components().any(|component| component == Component::Normal(".git".as_ref())) {
+        return Some(format!("{} is inside a Git repository's .git folder", folder.display()));
+    }
+    if repositories == RepositoryPolicy::Skip && fs::symlink_metadata(folder.join(".git")).is_ok() {
+        return Some(format!("{} is a Git working tree; set `repositories` to `sort` in rules.json to sort it", folder.display()));
+    }
+    None
+}
+
+/// The path with links and `..` resolved, without the `\\?\` Windows puts in front of it.
+fn canonical(path: &Path) -> PathBuf {
+    let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    if let Some(plain) = canonical.to_str().and_then(|canonical| canonical.strip_prefix(r"\\?\")) {
+        return PathBuf::from(plain);
+    }
+    canonical
+}
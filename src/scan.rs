@@ -0,0 +1,558 @@
+use crate::index;
+use crate::overrides;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+/// What recursive sorts and watches do with Git working trees below the sorted folder.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RepositoryPolicy {
+    /// Leave the whole working tree alone, so checkouts keep their files.
+    #[default]
+    Skip,
+    /// Sort files in working trees like in any other folder.
+    Sort,
+}
+
+/// Folders macOS presents as a single item, such as applications and photo libraries.
+#[cfg(target_os = "macos")]
+const BUNDLE_EXTENSIONS: &[&str] = &[
+    "app",
+    "bundle",
+    "framework",
+    "plugin",
+    "kext",
+    "pkg",
+    "photoslibrary",
+    "musiclibrary",
+    "tvlibrary",
+    "rtfd",
+    "pages",
+    "numbers",
+    "key",
+    "logicx",
+    "fcpbundle",
+    "xcodeproj",
+    "xcworkspace",
+    "playground",
+];
+
+/// Lists the files to sort in `root`, descending into subfolders when `recursive` is set.
+/// Hidden folders and `excluded` folders (the rule destinations) are never entered, and
+/// bundles are sorted as a whole like files, and Git working trees are only entered when
+/// `repositories` is `sort`. Symlinks are only listed with `links`, and
+/// special files such as FIFOs and sockets never are. With a `state`, folders that did not
+/// change since the last scan are not read again, and what changed in the others is added
+/// to its `delta`.
+pub fn collect_files(
+    root: &Path,
+    recursive: bool,
+    excluded: &[PathBuf],
+    repositories: RepositoryPolicy,
+    links: bool,
+    mut state: Option<&mut ScanState>,
+) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut folders = Vec::new();
+    for (path, kind) in read_folder(root, state.as_deref_mut())? {
+        match kind {
+            EntryKind::File => {
+                if path.file_name().is_some_and(|name| name != overrides::FILE_NAME) {
+                    files.push(path);
+                }
+            }
+            EntryKind::Folder if is_bundle(&path) => files.push(path),
+            EntryKind::Folder => {
+                if recursive && !is_excluded(&path, excluded, repositories) {
+                    folders.push(path);
+                }
+            }
+            EntryKind::Link => {
+                if links {
+                    files.push(path);
+                }
+            }
+            EntryKind::Special => debug!(file = ?path, "Ignoring special file"),
+        }
+    }
+    for folder in folders {
+        files.extend(collect_files(&folder, recursive, excluded, repositories, links, state.as_deref_mut())?);
+    }
+    Ok(files)
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+enum EntryKind {
+    File,
+    Folder,
+    Link,
+    Special,
+}
+
+/// The size and modification time of an entry, as recorded in the state.
+type Stamp = (i64, i64);
+
+/// What changed in the folders read again since the last scan. Entries in folders that did
+/// not change are in none of the lists.
+#[derive(Default)]
+pub struct ScanDelta {
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub changed: Vec<PathBuf>,
+}
+
+impl ScanDelta {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Compares a folder as it was just read with its listing from the last scan.
+    fn compare(&mut self, previous: &HashMap<PathBuf, (EntryKind, Option<Stamp>)>, entries: &[(PathBuf, EntryKind, Option<Stamp>)]) {
+        for (path, kind, stamp) in entries.iter().filter(|(path, kind, _)| is_sorted(path, *kind)) {
+            match previous.get(path) {
+                None => self.added.push(path.clone()),
+                Some((previous_kind, previous_stamp)) if previous_kind != kind || previous_stamp != stamp => self.changed.push(path.clone()),
+                Some(_) => {}
+            }
+        }
+        let present: HashSet<&PathBuf> = entries.iter().map(|(path, _, _)| path).collect();
+        self.removed.extend(
+            previous
+                .iter()
+                .filter(|(path, (kind, _))| is_sorted(path, *kind) && !present.contains(path))
+                .map(|(path, _)| path.clone()),
+        );
+    }
+}
+
+/// Whether an entry is sorted like a file rather than entered or ignored.
+fn is_sorted(path: &Path, kind: EntryKind) -> bool {
+    match kind {
+        EntryKind::File | EntryKind::Link => true,
+        EntryKind::Folder => is_bundle(path),
+        EntryKind::Special => false,
+    }
+}
+
+/// Lists a folder, from the state when its modification time is the one recorded with it.
+fn read_folder(folder: &Path, state: Option<&mut ScanState>) -> io::Result<Vec<(PathBuf, EntryKind)>> {
+    let Some(state) = state else {
+        return Ok(read_entries(folder)?.into_iter().map(|(path, kind, _)| (path, kind)).collect());
+    };
+    state.load_unmatched(folder);
+    let Some(modified) = fs::metadata(folder).ok().as_ref().and_then(modified_nanos) else {
+        return Ok(read_entries(folder)?.into_iter().map(|(path, kind, _)| (path, kind)).collect());
+    };
+    let previous = state.listing(folder);
+    if let Some((_, entries)) = previous.as_ref().filter(|(recorded, _)| *recorded == modified) {
+        return Ok(state.remember(entries.clone(), false));
+    }
+    let entries = read_entries(folder)?;
+    state.delta.compare(&previous.map(|(_, entries)| entries).unwrap_or_default(), &entries);
+    if let Err(e) = state.store_listing(folder, modified, &entries) {
+        debug!(folder = ?folder, "Failed to remember folder listing: {}", e);
+    }
+    Ok(state.remember(entries.into_iter().map(|(path, kind, stamp)| (path, (kind, stamp))).collect(), true))
+}
+
+/// Reads a folder with the size and modification time of each entry.
+fn read_entries(folder: &Path) -> io::Result<Vec<(PathBuf, EntryKind, Option<Stamp>)>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(folder)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let kind = if file_type.is_file() {
+            EntryKind::File
+        } else if file_type.is_dir() {
+            EntryKind::Folder
+        } else if file_type.is_symlink() {
+            EntryKind::Link
+        } else {
+            EntryKind::Special
+        };
+        entries.push((entry.path(), kind, entry.metadata().ok().as_ref().and_then(metadata_stamp)));
+    }
+    Ok(entries)
+}
+
+/// Lists `root` and, when `recursive` is set, every folder below it that would be scanned.
+pub fn collect_folders(root: &Path, recursive: bool, excluded: &[PathBuf], repositories: RepositoryPolicy) -> Vec<PathBuf> {
+    let mut folders = vec![root.to_path_buf()];
+    let mut next = 0;
+    while recursive && next < folders.len() {
+        if let Ok(entries) = fs::read_dir(&folders[next]) {
+            for entry in entries.flatten() {
+                if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) && !is_excluded(&entry.path(), excluded, repositories) {
+                    folders.push(entry.path());
+                }
+            }
+        }
+        next += 1;
+    }
+    folders
+}
+
+/// Whether a folder is never scanned or watched: hidden folders, bundles, Git working
+/// trees unless `repositories` is `sort`, and `excluded` folders.
+pub fn is_excluded(folder: &Path, excluded: &[PathBuf], repositories: RepositoryPolicy) -> bool {
+    let hidden = folder
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+    hidden
+        || is_bundle(folder)
+        || (repositories == RepositoryPolicy::Skip && is_repository(folder))
+        || excluded.iter().any(|excluded| folder.starts_with(excluded))
+}
+
+/// Whether `folder` is the top of a Git working tree. `.git` is a folder there, or a file
+/// in linked worktrees and submodules.
+fn is_repository(folder: &Path) -> bool {
+    fs::symlink_metadata(folder.join(".git")).is_ok()
+}
+
+/// Whether `folder` is a bundle, which is sorted as a whole like a file. Only macOS has them;
+/// elsewhere a folder named like one is an ordinary folder.
+#[cfg(target_os = "macos")]
+pub fn is_bundle(folder: &Path) -> bool {
+    folder
+        .extension()
+        .is_some_and(|extension| BUNDLE_EXTENSIONS.iter().any(|bundle| extension.eq_ignore_ascii_case(bundle)))
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn is_bundle(_folder: &Path) -> bool {
+    false
+}
+
+/// What earlier scans saw, kept next to the move index: the listing of every folder with
+/// its modification time and the size and modification time of its entries, and the files
+/// no rule matched. Folders are only read again once they change, and files no rule matched
+/// are only sorted again once they or the rules change. Each worker opens its own connection.
+pub struct ScanState {
+    connection: Connection,
+    /// Size and modification time of the entries in the folders read by this scan.
+    stamps: HashMap<PathBuf, Stamp>,
+    /// The files no rule matched in the folders listed by this scan.
+    unmatched: HashMap<PathBuf, Stamp>,
+    pub delta: ScanDelta,
+}
+
+/// How long after a change a folder listing is trusted; a folder changed again within the
+/// same tick of its clock would otherwise look unchanged.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+impl ScanState {
+    pub fn open() -> rusqlite::Result<ScanState> {
+        ScanState::with_connection(index::connect()?)
+    }
+
+    fn with_connection(connection: Connection) -> rusqlite::Result<ScanState> {
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS scanned_folders (
+                path TEXT PRIMARY KEY,
+                modified INTEGER NOT NULL,
+                entries TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS unmatched_files (
+                path TEXT PRIMARY KEY,
+                folder TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                modified INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS unmatched_files_folder ON unmatched_files (folder);
+            CREATE TABLE IF NOT EXISTS scanned_rules (
+                root TEXT PRIMARY KEY,
+                fingerprint TEXT NOT NULL
+            );",
+        )?;
+        Ok(ScanState {
+            connection,
+            stamps: HashMap::new(),
+            unmatched: HashMap::new(),
+            delta: ScanDelta::default(),
+        })
+    }
+
+    /// Forgets the unmatched files below `root` when the rules that apply there changed
+    /// since the last scan, so that they are tried with the new rules.
+    pub fn use_rules(&mut self, root: &Path, fingerprint: &str) -> io::Result<()> {
+        let root = root.to_string_lossy();
+        let previous: Option<String> = self
+            .connection
+            .query_row("SELECT fingerprint FROM scanned_rules WHERE root = ?1", params![root], |row| row.get(0))
+            .optional()
+            .map_err(io::Error::other)?;
+        if previous.as_deref() == Some(fingerprint) {
+            return Ok(());
+        }
+        self.unmatched.retain(|path, _| !path.starts_with(&*root));
+        self.connection
+            .execute("DELETE FROM unmatched_files WHERE substr(path, 1, length(?1)) = ?1", params![root])
+            .map_err(io::Error::other)?;
+        self.connection
+            .execute(
+                "INSERT OR REPLACE INTO scanned_rules (root, fingerprint) VALUES (?1, ?2)",
+                params![root, fingerprint],
+            )
+            .map(|_| ())
+            .map_err(io::Error::other)
+    }
+
+    /// Whether the file is new or changed since no rule matched it. Files in folders read by
+    /// this scan are compared with what it saw, without looking at them again.
+    pub fn changed(&self, file: &Path) -> bool {
+        if let Some(stamp) = self.stamps.get(file) {
+            return self.unmatched.get(file) != Some(stamp);
+        }
+        let Some((size, modified)) = stamp(file) else { return true };
+        let recorded: Option<(i64, i64)> = self
+            .connection
+            .query_row(
+                "SELECT size, modified FROM unmatched_files WHERE path = ?1",
+                params![file.to_string_lossy()],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .ok()
+            .flatten();
+        recorded != Some((size, modified))
+    }
+
+    /// Remembers files no rule matched, so they are left alone until they change.
+    pub fn record_unmatched(&self, files: &[PathBuf]) -> io::Result<()> {
+        let transaction = self.connection.unchecked_transaction().map_err(io::Error::other)?;
+        for file in files {
+            let (Some((size, modified)), Some(folder)) = (stamp(file), file.parent()) else { continue };
+            transaction
+                .execute(
+                    "INSERT OR REPLACE INTO unmatched_files (path, folder, size, modified) VALUES (?1, ?2, ?3, ?4)",
+                    params![file.to_string_lossy(), folder.to_string_lossy(), size, modified],
+                )
+                .map_err(io::Error::other)?;
+        }
+        transaction.commit().map_err(io::Error::other)
+    }
+
+    /// The listing of a folder from the last scan, with the folder's modification time then.
+    fn listing(&self, folder: &Path) -> Option<(i64, HashMap<PathBuf, (EntryKind, Option<Stamp>)>)> {
+        let (modified, entries): (i64, String) = self
+            .connection
+            .query_row(
+                "SELECT modified, entries FROM scanned_folders WHERE path = ?1",
+                params![folder.to_string_lossy()],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .ok()
+            .flatten()?;
+        // Listings from before entries had stamps don't parse, and the folder is read again.
+        let entries: Vec<(String, EntryKind, Option<Stamp>)> = serde_json::from_str(&entries).ok()?;
+        let entries = entries.into_iter().map(|(name, kind, stamp)| (folder.join(name), (kind, stamp))).collect();
+        Some((modified, entries))
+    }
+
+    /// Keeps the stamps of a folder's entries for `changed` when the folder was `read` in this
+    /// scan, and returns the entries. A file written in place leaves its folder's modification
+    /// time alone, so the stamps of a recorded listing may be stale and aren't kept.
+    fn remember(&mut self, entries: HashMap<PathBuf, (EntryKind, Option<Stamp>)>, read: bool) -> Vec<(PathBuf, EntryKind)> {
+        let mut listed = Vec::with_capacity(entries.len());
+        for (path, (kind, stamp)) in entries {
+            if let Some(stamp) = stamp.filter(|_| read) {
+                self.stamps.insert(path.clone(), stamp);
+            }
+            listed.push((path, kind));
+        }
+        listed
+    }
+
+    /// Reads the files no rule matched in `folder`, so `changed` needs no query for each.
+    fn load_unmatched(&mut self, folder: &Path) {
+        let Ok(mut statement) = self.connection.prepare("SELECT path, size, modified FROM unmatched_files WHERE folder = ?1") else { return };
+        let Ok(rows) = statement.query_map(params![folder.to_string_lossy()], |row| {
+            Ok((PathBuf::from(row.get::<_, String>(0)?), (row.get(1)?, row.get(2)?)))
+        }) else {
+            return;
+        };
+        self.unmatched.extend(rows.flatten());
+    }
+
+    /// Remembers the listing of a folder that was just read, and forgets unmatched files
+    /// that are no longer in it.
+    fn store_listing(&self, folder: &Path, modified: i64, entries: &[(PathBuf, EntryKind, Option<Stamp>)]) -> io::Result<()> {
+        let folder_name = folder.to_string_lossy();
+        let present: HashSet<String> = entries.iter().map(|(path, _, _)| path.to_string_lossy().into_owned()).collect();
+        let transaction = self.connection.unchecked_transaction().map_err(io::Error::other)?;
+        let known: Vec<String> = {
+            let mut statement = transaction.prepare("SELECT path FROM unmatched_files WHERE folder = ?1").map_err(io::Error::other)?;
+            let rows = statement.query_map(params![folder_name], |row| row.get(0)).map_err(io::Error::other)?;
+            rows.collect::<rusqlite::Result<_>>().map_err(io::Error::other)?
+        };
+        for gone in known.iter().filter(|path| !present.contains(*path)) {
+            transaction
+                .execute("DELETE FROM unmatched_files WHERE path = ?1", params![gone])
+                .map_err(io::Error::other)?;
+        }
+        // Names that are not valid Unicode can't be stored, and a folder that changed just
+        // now may change again unnoticed; both are read again next time.
+        let names: Option<Vec<(&str, EntryKind, Option<Stamp>)>> =
+            entries.iter().map(|(path, kind, stamp)| Some((path.file_name()?.to_str()?, *kind, *stamp))).collect();
+        let settled = SystemTime::now()
+            .checked_sub(SETTLE_TIME)
+            .and_then(|settled| settled.duration_since(UNIX_EPOCH).ok())
+            .is_some_and(|settled| (settled.as_nanos() as i64) > modified);
+        match names {
+            Some(names) if settled => {
+                let entries = serde_json::to_string(&names).map_err(io::Error::other)?;
+                transaction
+                    .execute(
+                        "INSERT OR REPLACE INTO scanned_folders (path, modified, entries) VALUES (?1, ?2, ?3)",
+                        params![folder_name, modified, entries],
+                    )
+                    .map_err(io::Error::other)?;
+            }
+            _ => {
+                transaction
+                    .execute("DELETE FROM scanned_folders WHERE path = ?1", params![folder_name])
+                    .map_err(io::Error::other)?;
+            }
+        }
+        transaction.commit().map_err(io::Error::other)
+    }
+}
+
+fn modified_nanos(metadata: &fs::Metadata) -> Option<i64> {
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(modified.as_nanos() as i64)
+}
+
+fn stamp(file: &Path) -> Option<Stamp> {
+    metadata_stamp(&fs::symlink_metadata(file).ok()?)
+}
+
+fn metadata_stamp(metadata: &fs::Metadata) -> Option<Stamp> {
+    Some((metadata.len() as i64, modified_nanos(metadata)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    fn folder(name: &str) -> TestDir {
+        let folder = TestDir::new("scan", name);
+        fs::create_dir_all(folder.join("Inbox")).unwrap();
+        folder
+    }
+
+    /// A state kept in `folder`, as each scan opens its own.
+    fn state(folder: &Path) -> ScanState {
+        ScanState::with_connection(Connection::open(folder.join("scans.db")).unwrap()).unwrap()
+    }
+
+    /// Dates the folder back far enough for its listing to be trusted.
+    fn settle(folder: &Path, modified: SystemTime) {
+        fs::File::open(folder).unwrap().set_modified(modified).unwrap();
+    }
+
+    fn scan(folder: &Path, state: &mut ScanState) -> Vec<PathBuf> {
+        let mut files = collect_files(folder, false, &[], RepositoryPolicy::Skip, false, Some(state)).unwrap();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn compares_folder_listings() {
+        let previous = HashMap::from([
+            (PathBuf::from("kept.pdf"), (EntryKind::File, Some((1, 1)))),
+            (PathBuf::from("changed.pdf"), (EntryKind::File, Some((1, 1)))),
+            (PathBuf::from("removed.pdf"), (EntryKind::File, Some((1, 1)))),
+            (PathBuf::from("Folder"), (EntryKind::Folder, None)),
+        ]);
+        let entries = [
+            (PathBuf::from("kept.pdf"), EntryKind::File, Some((1, 1))),
+            (PathBuf::from("changed.pdf"), EntryKind::File, Some((2, 1))),
+            (PathBuf::from("added.pdf"), EntryKind::File, Some((1, 1))),
+        ];
+        let mut delta = ScanDelta::default();
+        delta.compare(&previous, &entries);
+        assert_eq!(delta.added, [PathBuf::from("added.pdf")]);
+        assert_eq!(delta.changed, [PathBuf::from("changed.pdf")]);
+        assert_eq!(delta.removed, [PathBuf::from("removed.pdf")]);
+    }
+
+    #[test]
+    fn reports_only_what_changed_since_the_last_scan() {
+        let root = folder("delta");
+        let inbox = root.join("Inbox");
+        fs::write(inbox.join("a.pdf"), "a").unwrap();
+        settle(&inbox, SystemTime::now() - Duration::from_secs(60 * 60));
+        let mut first = state(&root);
+        assert_eq!(scan(&inbox, &mut first), [inbox.join("a.pdf")]);
+        assert_eq!(first.delta.added, [inbox.join("a.pdf")]);
+
+        let mut second = state(&root);
+        assert_eq!(scan(&inbox, &mut second), [inbox.join("a.pdf")]);
+        assert!(second.delta.is_empty());
+
+        fs::write(inbox.join("b.pdf"), "b").unwrap();
+        let mut third = state(&root);
+        assert_eq!(scan(&inbox, &mut third), [inbox.join("a.pdf"), inbox.join("b.pdf")]);
+        assert_eq!(third.delta.added, [inbox.join("b.pdf")]);
+    }
+
+    #[test]
+    fn notices_unmatched_files_written_in_place() {
+        let root = folder("in-place");
+        let inbox = root.join("Inbox");
+        let file = inbox.join("a.pdf");
+        fs::write(&file, "a").unwrap();
+        let settled = SystemTime::now() - Duration::from_secs(60 * 60);
+        settle(&inbox, settled);
+        let mut first = state(&root);
+        scan(&inbox, &mut first);
+        first.record_unmatched(std::slice::from_ref(&file)).unwrap();
+
+        let mut second = state(&root);
+        scan(&inbox, &mut second);
+        assert!(!second.changed(&file));
+
+        // Writing a file in place leaves its folder's modification time as it was.
+        fs::write(&file, "a, longer").unwrap();
+        settle(&inbox, settled);
+        let mut third = state(&root);
+        scan(&inbox, &mut third);
+        assert!(third.changed(&file));
+    }
+
+    #[test]
+    fn tries_unmatched_files_again_when_the_rules_change() {
+        let root = folder("rules");
+        let file = root.join("Inbox/a.pdf");
+        fs::write(&file, "a").unwrap();
+        let mut first = state(&root);
+        first.use_rules(&root, "one").unwrap();
+        first.record_unmatched(std::slice::from_ref(&file)).unwrap();
+        let mut second = state(&root);
+        second.use_rules(&root, "one").unwrap();
+        assert!(!second.changed(&file));
+        second.use_rules(&root, "two").unwrap();
+        assert!(second.changed(&file));
+    }
+
+    #[test]
+    fn enters_working_trees_only_when_repositories_are_sorted() {
+        let root = folder("repositories");
+        let checkout = root.join("Inbox/checkout");
+        fs::create_dir_all(checkout.join(".git")).unwrap();
+        fs::write(checkout.join("main.rs"), "fn main() {}").unwrap();
+        let inbox = root.join("Inbox");
+        assert!(collect_files(&inbox, true, &[], RepositoryPolicy::Skip, false, None).unwrap().is_empty());
+        assert_eq!(collect_files(&inbox, true, &[], RepositoryPolicy::Sort, false, None).unwrap(), [checkout.join("main.rs")]);
+    }
+}
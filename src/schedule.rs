@@ -0,0 +1,187 @@
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, Local, NaiveDate, NaiveTime, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// A cron expression with the five standard fields: minute, hour, day of month, month
+/// and day of week. Fields take `*`, numbers, ranges (`1-5`), steps (`*/15`, `8-18/2`)
+/// and comma separated lists of those.
+#[derive(Clone)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Cron runs on either day field when both are restricted, and on both otherwise.
+    either_day: bool,
+}
+
+impl Schedule {
+    pub fn parse(expression: &str) -> Result<Schedule, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!("Invalid schedule '{}', expected five fields like '0 */2 * * *'", expression));
+        };
+        let mut weekday_bits = parse_field(weekdays, 0, 7)?;
+        // Both 0 and 7 are Sunday.
+        if weekday_bits & (1 << 7) != 0 {
+            weekday_bits |= 1;
+        }
+        let schedule = Schedule {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekday_bits,
+            either_day: days != "*" && weekdays != "*",
+        };
+        match schedule.next_after(Local::now()) {
+            Some(_) => Ok(schedule),
+            None => Err(format!("Schedule '{}' never runs", expression)),
+        }
+    }
+
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        if self.either_day {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+
+    /// The first time the schedule fires after `after`, looking up to four years ahead
+    /// for schedules that only fire on February 29.
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        let start = after.naive_local().with_second(0)?.with_nanosecond(0)? + ChronoDuration::minutes(1);
+        let limit = start + ChronoDuration::days(4 * 366);
+        let mut time = start;
+        while time < limit {
+            let date = time.date();
+            if self.months & (1 << date.month()) == 0 {
+                let (year, month) = if date.month() == 12 { (date.year() + 1, 1) } else { (date.year(), date.month() + 1) };
+                time = NaiveDate::from_ymd_opt(year, month, 1)?.and_time(NaiveTime::MIN);
+            } else if !self.day_matches(date) {
+                time = date.succ_opt()?.and_time(NaiveTime::MIN);
+            } else if self.hours & (1 << time.hour()) == 0 {
+                time = time.with_minute(0)? + ChronoDuration::hours(1);
+            } else if self.minutes & (1 << time.minute()) == 0 {
+                time += ChronoDuration::minutes(1);
+            } else {
+                // Times skipped by a daylight saving change don't exist and are passed over.
+                if let Some(local) = Local.from_local_datetime(&time).earliest() {
+                    return Some(local);
+                }
+                time += ChronoDuration::minutes(1);
+            }
+        }
+        None
+    }
+
+    /// How long to wait from now until the schedule fires.
+    pub fn until_next(&self) -> Duration {
+        let now = Local::now();
+        self.next_after(now)
+            .and_then(|next| (next - now).to_std().ok())
+            .unwrap_or_default()
+    }
+}
+
+/// Parses one field into a bit set of the values it allows.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let invalid = || format!("Invalid schedule field '{}', expected values from {} to {}", field, min, max);
+    let number = |value: &str| value.parse::<u32>().ok().filter(|value| (min..=max).contains(value)).ok_or_else(invalid);
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<usize>().ok().filter(|step| *step > 0).ok_or_else(invalid)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (number(start)?, number(end)?),
+            // `5/15` means every 15 from 5 on.
+            None if step > 1 => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        if start > end {
+            return Err(invalid());
+        }
+        for value in (start..=end).step_by(step) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+/// The time between scans when changes are only found by scanning: doubled after every
+/// sort that found nothing to do, up to `max`, and back to `min` after one that did.
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Backoff { min, max, current: min }
+    }
+
+    pub fn current(&self) -> Duration {
+        self.current
+    }
+
+    /// Adjusts the interval after a sort, by whether it found files to handle.
+    pub fn sorted(&mut self, active: bool) {
+        self.current = if active { self.min } else { self.current.saturating_mul(2).min(self.max).max(self.min) };
+    }
+}
+
+/// A daily window such as `22:00-07:00` in which the daemon doesn't sort.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct QuietHours {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl QuietHours {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+
+    pub fn end(&self) -> NaiveTime {
+        self.end
+    }
+
+    /// How long from `time` until the window ends.
+    pub fn remaining(&self, time: NaiveTime) -> Duration {
+        let seconds = (self.end - time).num_seconds().rem_euclid(24 * 60 * 60);
+        Duration::from_secs(seconds as u64)
+    }
+}
+
+impl TryFrom<String> for QuietHours {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("Invalid quiet hours '{}', expected e.g. 22:00-07:00", value);
+        let (start, end) = value.split_once('-').ok_or_else(invalid)?;
+        let parse = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| invalid());
+        Ok(QuietHours {
+            start: parse(start)?,
+            end: parse(end)?,
+        })
+    }
+}
+
+impl From<QuietHours> for String {
+    fn from(hours: QuietHours) -> String {
+        format!("{}-{}", hours.start.format("%H:%M"), hours.end.format("%H:%M"))
+    }
+}
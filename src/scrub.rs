@@ -0,0 +1,163 @@
+use crate::duplicates;
+use crate::index::{MoveIndex, ScrubRecord};
+use crate::remote;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::time::UNIX_EPOCH;
+
+/// Background checks of the files Organizer placed, from `config.json`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ScrubSettings {
+    /// Files read on each scrub; the ones checked longest ago come first.
+    #[serde(default = "default_files")]
+    pub files: usize,
+    /// Hours between scrubs of the daemon.
+    #[serde(default = "default_every_hours")]
+    pub every_hours: u64,
+}
+
+fn default_files() -> usize {
+    200
+}
+
+fn default_every_hours() -> u64 {
+    24
+}
+
+/// Something wrong with a placed file.
+pub enum Finding {
+    /// The contents changed while size and modification time stayed the same, as when the
+    /// disk silently corrupts it.
+    Corrupted { path: String },
+    /// The file was changed since it was placed or last checked.
+    Modified { path: String },
+    /// The file is gone, but Organizer did not move it away.
+    Missing { path: String },
+    Unreadable { path: String, error: io::Error },
+}
+
+impl Finding {
+    pub fn path(&self) -> &str {
+        match self {
+            Finding::Corrupted { path } | Finding::Modified { path } | Finding::Missing { path } | Finding::Unreadable { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Finding::Corrupted { path } => write!(f, "{} is corrupted: its contents changed, its size and modification time did not", path),
+            Finding::Modified { path } => write!(f, "{} was modified since it was last checked", path),
+            Finding::Missing { path } => write!(f, "{} is missing", path),
+            Finding::Unreadable { path, error } => write!(f, "{} can't be read: {}", path, error),
+        }
+    }
+}
+
+/// What one scrub checked and found.
+#[derive(Default)]
+pub struct Scrub {
+    pub checked: usize,
+    pub findings: Vec<Finding>,
+}
+
+/// Whether the daemon should scrub again, `last` being when it last did.
+pub fn due(settings: &ScrubSettings, last: Option<DateTime<Local>>) -> bool {
+    match last {
+        Some(last) => Local::now() - last >= chrono::Duration::hours(settings.every_hours as i64),
+        None => true,
+    }
+}
+
+/// Reads up to `count` placed files, those never checked first and then those checked
+/// longest ago, and compares them with the hash they had when last checked or, for the
+/// first check, when they were copied with `verify_copies`.
+pub fn run(index: &MoveIndex, count: usize) -> rusqlite::Result<Scrub> {
+    let mut files: Vec<String> = index.placements()?.into_iter().map(|placement| placement.path).collect();
+    files.extend(index.copied_files()?);
+    files.retain(|path| !remote::is_remote(path));
+    files.sort();
+    files.dedup();
+    let records = index.scrub_records()?;
+    files.sort_by_key(|path| records.get(path).map(|record| record.checked));
+
+    let mut scrub = Scrub::default();
+    for path in files.into_iter().take(count) {
+        scrub.checked += 1;
+        // Files no check could read yet have nothing to compare with.
+        let previous = records.get(&path).filter(|record| !record.hash.is_empty());
+        let now = Local::now().timestamp();
+        let (hash, size, modified) = match read(&path) {
+            Ok(read) => read,
+            Err(error) => {
+                // Checked again once the others have been, so they don't crowd them out.
+                let record = match previous {
+                    Some(previous) => ScrubRecord {
+                        checked: now,
+                        error: Some(error.to_string()),
+                        ..previous.clone()
+                    },
+                    None => ScrubRecord {
+                        hash: String::new(),
+                        size: 0,
+                        modified: 0,
+                        checked: now,
+                        error: Some(error.to_string()),
+                    },
+                };
+                index.record_scrub(&path, &record)?;
+                scrub.findings.push(if error.kind() == io::ErrorKind::NotFound {
+                    Finding::Missing { path: path.clone() }
+                } else {
+                    Finding::Unreadable { path: path.clone(), error }
+                });
+                continue;
+            }
+        };
+        let current = ScrubRecord {
+            hash,
+            size,
+            modified,
+            checked: now,
+            error: None,
+        };
+        match previous {
+            Some(previous) if previous.hash != current.hash => {
+                if previous.size == current.size && previous.modified == current.modified {
+                    scrub.findings.push(Finding::Corrupted { path: path.clone() });
+                    // The hash it should have is kept, so it is reported until restored.
+                    index.record_scrub(
+                        &path,
+                        &ScrubRecord {
+                            checked: now,
+                            error: None,
+                            ..previous.clone()
+                        },
+                    )?;
+                    continue;
+                }
+                scrub.findings.push(Finding::Modified { path: path.clone() });
+            }
+            Some(_) => {}
+            None => {
+                if index.checksum(&path)?.is_some_and(|checksum| checksum != current.hash) {
+                    scrub.findings.push(Finding::Modified { path: path.clone() });
+                }
+            }
+        }
+        index.record_scrub(&path, &current)?;
+    }
+    Ok(scrub)
+}
+
+/// The BLAKE3 hash, size and modification time of a file.
+fn read(path: &str) -> io::Result<(String, u64, i64)> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH).map(|since| since.as_secs() as i64).unwrap_or_default();
+    let hash = duplicates::hash_file(path.as_ref())?;
+    Ok((hash.to_hex().to_string(), metadata.len(), modified))
+}
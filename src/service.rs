@@ -0,0 +1,210 @@
+use clap::Subcommand;
+use std::io;
+
+/// Name of the Windows service `install` sets up.
+#[cfg(windows)]
+pub const SERVICE_NAME: &str = "Organizer";
+
+/// Commands for the Windows service that `install` sets up.
+#[derive(Subcommand, Clone, Copy)]
+pub enum ServiceCommand {
+    /// Start the service
+    Start,
+    /// Stop the service, after the sort in progress
+    Stop,
+    /// Stop and remove the service
+    Uninstall,
+}
+
+/// Registers the daemon for `directory` with the Service Control Manager, starting with
+/// Windows and restarting after a crash, and starts it. `rules` is the folder holding
+/// `rules.json`, since services start in the system folder.
+#[cfg(windows)]
+pub fn install(directory: &str, interval: u64, profile: Option<&str>, rules: &std::path::Path) -> io::Result<()> {
+    windows::install(directory, interval, profile, rules)
+}
+
+#[cfg(windows)]
+pub fn control(command: ServiceCommand) -> io::Result<String> {
+    windows::control(command)
+}
+
+#[cfg(not(windows))]
+pub fn control(_command: ServiceCommand) -> io::Result<String> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Windows services are only available on Windows; use `install` and systemctl or launchctl"))
+}
+
+/// Runs `daemon` as the service, reporting to the Service Control Manager, until it is
+/// stopped. Stopping the service asks the daemon to stop as Ctrl+C does.
+#[cfg(windows)]
+pub fn run(daemon: impl FnOnce() + Send + 'static) -> io::Result<()> {
+    windows::run(Box::new(daemon))
+}
+
+#[cfg(not(windows))]
+pub fn run(_daemon: impl FnOnce() + Send + 'static) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Running as a Windows service is only possible on Windows"))
+}
+
+#[cfg(windows)]
+mod windows {
+    use super::{ServiceCommand, SERVICE_NAME};
+    use crate::eventlog;
+    use crate::shutdown;
+    use std::ffi::OsString;
+    use std::io;
+    use std::path::Path;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tracing::error;
+    use windows_service::service::{
+        ServiceAccess, ServiceAction, ServiceActionType, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+        ServiceFailureActions, ServiceFailureResetPeriod, ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+    };
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle};
+    use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+    use windows_service::{define_windows_service, service_dispatcher};
+
+    /// How long `stop` waits for the sort in progress to finish.
+    const STOP_TIMEOUT: Duration = Duration::from_secs(60);
+
+    /// Restarts after the first three failures in a day, waiting a little longer each time.
+    const RESTART_DELAYS: [u64; 3] = [10, 30, 60];
+
+    static DAEMON: Mutex<Option<Box<dyn FnOnce() + Send>>> = Mutex::new(None);
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    pub fn install(directory: &str, interval: u64, profile: Option<&str>, rules: &Path) -> io::Result<()> {
+        let mut arguments: Vec<OsString> = Vec::new();
+        if let Some(profile) = profile {
+            arguments.extend(["--profile".into(), profile.into()]);
+        }
+        arguments.extend(["daemon".into(), "--path".into(), directory.into(), "--interval".into(), interval.to_string().into()]);
+        arguments.extend(["--service".into(), rules.as_os_str().to_os_string()]);
+        let info = ServiceInfo {
+            name: SERVICE_NAME.into(),
+            display_name: "Organizer".into(),
+            service_type: ServiceType::OWN_PROCESS,
+            start_type: ServiceStartType::AutoStart,
+            error_control: ServiceErrorControl::Normal,
+            executable: std::env::current_exe()?,
+            launch_arguments: arguments,
+            dependencies: Vec::new(),
+            account_name: None,
+            account_password: None,
+        };
+        let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE).map_err(error)?;
+        let access = ServiceAccess::CHANGE_CONFIG | ServiceAccess::START | ServiceAccess::QUERY_STATUS;
+        let service = manager.create_service(&info, access).map_err(error)?;
+        service.set_description(format!("Sorts {} with the rules in {}", directory, rules.display())).map_err(error)?;
+        let actions = RESTART_DELAYS
+            .iter()
+            .map(|&seconds| ServiceAction {
+                action_type: ServiceActionType::Restart,
+                delay: Duration::from_secs(seconds),
+            })
+            .collect();
+        service
+            .update_failure_actions(ServiceFailureActions {
+                reset_period: ServiceFailureResetPeriod::After(Duration::from_secs(24 * 60 * 60)),
+                reboot_msg: None,
+                command: None,
+                actions: Some(actions),
+            })
+            .map_err(error)?;
+        // The daemon exits with an error code when it can't start, which counts as a failure too.
+        service.set_failure_actions_on_non_crash_failures(true).map_err(error)?;
+        eventlog::register()?;
+        service.start::<OsString>(&[]).map_err(error)
+    }
+
+    pub fn control(command: ServiceCommand) -> io::Result<String> {
+        let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT).map_err(error)?;
+        let access = ServiceAccess::START | ServiceAccess::STOP | ServiceAccess::QUERY_STATUS | ServiceAccess::DELETE;
+        let service = manager.open_service(SERVICE_NAME, access).map_err(error)?;
+        match command {
+            ServiceCommand::Start => {
+                service.start::<OsString>(&[]).map_err(error)?;
+                Ok("Started".to_string())
+            }
+            ServiceCommand::Stop => {
+                stop(&service)?;
+                Ok("Stopped".to_string())
+            }
+            ServiceCommand::Uninstall => {
+                stop(&service)?;
+                service.delete().map_err(error)?;
+                eventlog::unregister()?;
+                Ok("Uninstalled".to_string())
+            }
+        }
+    }
+
+    /// Stops the service and waits until it has stopped.
+    fn stop(service: &windows_service::service::Service) -> io::Result<()> {
+        if service.query_status().map_err(error)?.current_state == ServiceState::Stopped {
+            return Ok(());
+        }
+        service.stop().map_err(error)?;
+        let deadline = Instant::now() + STOP_TIMEOUT;
+        while service.query_status().map_err(error)?.current_state != ServiceState::Stopped {
+            if Instant::now() > deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "The service did not stop in time"));
+            }
+            thread::sleep(Duration::from_millis(500));
+        }
+        Ok(())
+    }
+
+    pub fn run(daemon: Box<dyn FnOnce() + Send>) -> io::Result<()> {
+        *DAEMON.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(daemon);
+        service_dispatcher::start(SERVICE_NAME, ffi_service_main).map_err(error)
+    }
+
+    fn service_main(_arguments: Vec<OsString>) {
+        let handler = |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                shutdown::request();
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        };
+        let status = match service_control_handler::register(SERVICE_NAME, handler) {
+            Ok(status) => status,
+            Err(e) => {
+                error!("Failed to register with the Service Control Manager: {}", e);
+                return;
+            }
+        };
+        report(&status, ServiceState::Running, ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN);
+        if let Some(daemon) = DAEMON.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take() {
+            daemon();
+        }
+        report(&status, ServiceState::Stopped, ServiceControlAccept::empty());
+    }
+
+    fn report(status: &ServiceStatusHandle, state: ServiceState, accepted: ServiceControlAccept) {
+        let result = status.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted: accepted,
+            exit_code: ServiceExitCode::Win32(0),
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        });
+        if let Err(e) = result {
+            error!("Failed to report the service's state: {}", e);
+        }
+    }
+
+    fn error(e: windows_service::Error) -> io::Error {
+        match e {
+            windows_service::Error::Winapi(e) => e,
+            e => io::Error::other(e),
+        }
+    }
+}
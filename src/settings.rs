@@ -0,0 +1,81 @@
+use crate::audit::AuditSettings;
+use crate::learn::LearnSettings;
+use crate::mirror::MirrorTarget;
+use crate::overlay;
+use crate::paths;
+use crate::reconcile::ReconcileSettings;
+use crate::retry::RetrySettings;
+use crate::safemode::SafeModeSettings;
+use crate::rules::Mode;
+use crate::scrub::ScrubSettings;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use tracing::warn;
+
+pub const SETTINGS_FILE: &str = "config.json";
+
+/// Per-user settings from `config.json` in Organizer's config directory.
+#[derive(Serialize, Deserialize, Default)]
+pub struct UserSettings {
+    /// Make `sort` a dry run unless `--apply` is given.
+    #[serde(default)]
+    pub dry_run_by_default: bool,
+    /// Ask for confirmation before a sort that would move more than this many files.
+    pub confirm_threshold: Option<usize>,
+    #[serde(default)]
+    pub audit: AuditSettings,
+    /// Copy new entries of the move index here after every sort.
+    pub mirror: Option<MirrorTarget>,
+    /// Named sets of folder, rules and daemon settings, picked with `--profile`.
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Have the daemon read placed files back now and then to find corrupted or changed ones.
+    pub scrub: Option<ScrubSettings>,
+    /// How the daemon tries files again that other processes held open.
+    #[serde(default)]
+    pub retry: RetrySettings,
+    /// How the daemon catches up on what changed while it was stopped.
+    #[serde(default)]
+    pub reconcile: ReconcileSettings,
+    /// Have the daemon propose rules for files moved out of the sorted folder by hand.
+    pub learn: Option<LearnSettings>,
+    /// When the daemon stops placing files after crashing again and again.
+    #[serde(default)]
+    pub safe_mode: SafeModeSettings,
+}
+
+/// What `--profile` sets; options given on the command line still win.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Profile {
+    /// The folder sorted when no `--path` is given.
+    pub path: Option<String>,
+    /// Folder holding the `rules.json`, and the Lua script, used instead of those in the
+    /// current directory.
+    pub rules: Option<String>,
+    /// Seconds between sorts of the daemon.
+    pub interval: Option<u64>,
+    pub jobs: Option<usize>,
+    #[serde(default)]
+    pub recursive: bool,
+    pub mode: Option<Mode>,
+    /// Have the daemon leave files that were there before it first ran alone.
+    #[serde(default)]
+    pub new_only: bool,
+}
+
+/// Loads `config.json`, with the keys set by `--set settings.<key>=<value>` and in the
+/// environment.
+pub fn load_settings() -> UserSettings {
+    let path = paths::config_dir().join(SETTINGS_FILE);
+    let contents = fs::read_to_string(&path).unwrap_or_else(|_| "{}".to_string());
+    let settings = serde_json::from_str(&contents).map_err(|e| e.to_string()).and_then(|mut settings| {
+        overlay::apply_settings(&mut settings)?;
+        serde_json::from_value(settings).map_err(|e| e.to_string())
+    });
+    settings.unwrap_or_else(|e| {
+        warn!("Ignoring invalid settings in {:?}: {}", path, e);
+        UserSettings::default()
+    })
+}
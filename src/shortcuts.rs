@@ -0,0 +1,80 @@
+use crate::duplicates;
+use crate::transfer;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, info};
+
+/// Links to files placed in a destination, from its `shortcuts` action in `rules.json`,
+/// for views such as `Recent` next to the folders files are filed in.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ShortcutsAction {
+    /// Folders the links are made in, relative to the sorted folder unless absolute.
+    pub folders: Vec<String>,
+    /// Days after which a link is removed again; links whose file is gone are always removed.
+    pub keep_days: Option<u64>,
+}
+
+/// Makes a link to `file` in each of `folders`; a link that would take the name of
+/// something else in the folder gets a number.
+pub fn create(file: &Path, folders: &[PathBuf]) -> io::Result<()> {
+    let name = file.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no file name"))?;
+    for folder in folders {
+        fs::create_dir_all(folder)?;
+        let link = duplicates::unique_path(&folder.join(name));
+        transfer::link_back(&link, file)?;
+    }
+    Ok(())
+}
+
+/// Removes the links in `folder` whose file is gone, and with `keep_days` those made longer
+/// ago. Only links into `destination`, the folder the files they were made for are placed
+/// in, are touched; other links, such as those the user made, are left alone like
+/// everything else.
+pub fn prune(folder: &Path, destination: &Path, keep_days: Option<u64>) -> io::Result<()> {
+    let cutoff = keep_days.and_then(|days| SystemTime::now().checked_sub(Duration::from_secs(days.saturating_mul(24 * 60 * 60))));
+    let entries = match fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let link = entry?.path();
+        let metadata = fs::symlink_metadata(&link)?;
+        if !metadata.file_type().is_symlink() {
+            continue;
+        }
+        if !points_into(&link, destination)? {
+            debug!(link = ?link, "Not a shortcut, left alone");
+            continue;
+        }
+        let broken = !link.exists();
+        let expired = cutoff.is_some_and(|cutoff| metadata.modified().is_ok_and(|made| made < cutoff));
+        if broken || expired {
+            transfer::remove_link(&link)?;
+            info!(link = ?link, "Removed shortcut");
+        }
+    }
+    Ok(())
+}
+
+/// Whether the symlink `link` points to something inside `folder`, whether or not it is
+/// still there. `..` in the link is followed by name, since what it leads to may be gone.
+fn points_into(link: &Path, folder: &Path) -> io::Result<bool> {
+    let target = fs::read_link(link)?;
+    let base = link.parent().unwrap_or(Path::new(".")).canonicalize()?;
+    let folder = folder.canonicalize().unwrap_or_else(|_| folder.to_path_buf());
+    let mut resolved = PathBuf::new();
+    for component in base.join(target).components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::CurDir => {}
+            component => resolved.push(component),
+        }
+    }
+    Ok(resolved.starts_with(folder))
+}
@@ -0,0 +1,45 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the process was asked to stop with SIGTERM, SIGINT or Ctrl+C.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Asks the daemon to stop, as Ctrl+C does; for the Windows service being stopped.
+#[cfg(windows)]
+pub fn request() {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Turns the first stop request into a flag the daemon checks between files; a
+/// second one exits right away.
+#[cfg(unix)]
+pub fn install() {
+    extern "C" fn handle(_signal: libc::c_int) {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            unsafe { libc::_exit(130) };
+        }
+    }
+    for signal in [libc::SIGTERM, libc::SIGINT] {
+        unsafe { libc::signal(signal, handle as extern "C" fn(libc::c_int) as libc::sighandler_t) };
+    }
+}
+
+#[cfg(windows)]
+pub fn install() {
+    use windows_sys::Win32::Foundation::BOOL;
+    use windows_sys::Win32::System::Console::SetConsoleCtrlHandler;
+
+    unsafe extern "system" fn handle(_event: u32) -> BOOL {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        1
+    }
+    unsafe { SetConsoleCtrlHandler(Some(handle), 1) };
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn install() {}
@@ -0,0 +1,257 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// A place files are fetched from before the watched directory is sorted.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SourceConfig {
+    Sftp(SftpSource),
+}
+
+/// An SFTP inbox, typically the scan target of a network scanner or MFP.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SftpSource {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    pub user: String,
+    /// Private key used to log in; without it the SSH agent is used.
+    pub key_file: Option<PathBuf>,
+    /// Environment variable holding the password, for servers without key authentication.
+    pub password_env: Option<String>,
+    pub remote_dir: String,
+    /// Destinations per subfolder of `remote_dir`, usually one subfolder per sender.
+    /// Files from other subfolders land in the watched directory and are sorted by the rules.
+    #[serde(default)]
+    pub routes: HashMap<String, String>,
+    /// Files changed more recently than this are assumed to still be uploading.
+    #[serde(default = "default_stable_seconds")]
+    pub stable_seconds: u64,
+    /// Delete files on the server once they have been placed locally.
+    #[serde(default = "default_delete_after")]
+    pub delete_after: bool,
+}
+
+fn default_port() -> u16 {
+    22
+}
+
+fn default_stable_seconds() -> u64 {
+    30
+}
+
+fn default_delete_after() -> bool {
+    true
+}
+
+/// A file fetched from a source.
+pub struct Ingested {
+    pub remote: String,
+    pub local: PathBuf,
+}
+
+/// Fetches new files from every configured source into `directory`.
+pub fn poll_sources(sources: &[SourceConfig], directory: &Path) -> Vec<Ingested> {
+    let mut ingested = Vec::new();
+    for source in sources {
+        match source {
+            SourceConfig::Sftp(sftp) => match sftp::poll(sftp, directory) {
+                Ok(files) => ingested.extend(files),
+                Err(e) => tracing::error!("Failed to poll sftp://{}{}: {}", sftp.host, sftp.remote_dir, e),
+            },
+        }
+    }
+    ingested
+}
+
+#[cfg(feature = "sftp")]
+pub use sftp::connect as connect_sftp;
+
+#[cfg(feature = "sftp")]
+mod sftp {
+    use super::{Ingested, SftpSource};
+    use crate::paths;
+    use crate::statefile;
+    use ssh2::{CheckResult, FileStat, KnownHostFileKind, Session, Sftp};
+    use std::collections::BTreeMap;
+    use std::fs::{self, File};
+    use std::io;
+    use std::net::TcpStream;
+    use std::path::{Path, PathBuf};
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use tracing::{info, warn};
+    
+    /// Where the files fetched from sources that keep them on the server are remembered.
+    const FETCHED_FILE: &str = "sftp_fetched.json";
+    
+    pub fn poll(source: &SftpSource, directory: &Path) -> io::Result<Vec<Ingested>> {
+        let session = connect(&source.host, source.port, &source.user, source.key_file.as_deref(), source.password_env.as_deref())?;
+        let sftp = session.sftp()?;
+        let remote_dir = Path::new(&source.remote_dir);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        
+        let mut candidates = Vec::new();
+        for (path, stat) in sftp.readdir(remote_dir)? {
+            if stat.is_dir() {
+                let sender = path.file_name().map(|name| name.to_string_lossy().into_owned());
+                for (path, stat) in sftp.readdir(&path)? {
+                    if stat.is_file() {
+                        candidates.push((path, stat, sender.clone()));
+                    }
+                }
+            } else if stat.is_file() {
+                candidates.push((path, stat, None));
+            }
+        }
+        
+        // Files left on the server are fetched once for each time they change. Only files
+        // that were fetched are remembered, so those that arrive or change while the others
+        // download, or fail to download, are fetched by a later poll.
+        let inbox = format!("sftp://{}{}", source.host, source.remote_dir);
+        let state = paths::data_dir().join(FETCHED_FILE);
+        let _lock = (!source.delete_after).then(|| statefile::lock(&state)).transpose()?;
+        let mut all_fetched: BTreeMap<String, BTreeMap<String, u64>> = if source.delete_after { BTreeMap::new() } else { read_fetched(&state) };
+        let mut fetched = all_fetched.remove(&inbox).unwrap_or_default();
+        // Files gone from the server are forgotten.
+        fetched.retain(|remote, _| candidates.iter().any(|(path, _, _)| path.to_string_lossy() == remote.as_str()));
+        
+        let mut ingested = Vec::new();
+        for (remote, stat, sender) in candidates {
+            if stat.mtime.is_some_and(|mtime| now.saturating_sub(mtime) < source.stable_seconds) {
+                continue;
+            }
+            let key = remote.to_string_lossy().into_owned();
+            let changed = stat.mtime.unwrap_or_default();
+            if !source.delete_after && fetched.get(&key) == Some(&changed) {
+                continue;
+            }
+            match fetch(&sftp, source, directory, &remote, &stat, sender.as_deref()) {
+                Ok(Some(target)) => {
+                    info!(file = ?remote, destination = ?target, "Fetched file from SFTP inbox");
+                    fetched.insert(key, changed);
+                    ingested.push(Ingested {
+                        remote: format!("sftp://{}{}", source.host, remote.display()),
+                        local: target,
+                    });
+                }
+                Ok(None) => {}
+                // The other files are still fetched, and this one is tried again next time.
+                Err(e) => warn!(file = ?remote, "Failed to fetch file from SFTP inbox: {}", e),
+            }
+        }
+        if !source.delete_after {
+            all_fetched.insert(inbox, fetched);
+            statefile::write(&state, serde_json::to_string_pretty(&all_fetched)?.as_bytes())?;
+        }
+        Ok(ingested)
+    }
+    
+    /// Downloads `remote` into its folder below `directory`, and returns where it went, or
+    /// `None` when it is left for a later poll.
+    fn fetch(sftp: &Sftp, source: &SftpSource, directory: &Path, remote: &Path, stat: &FileStat, sender: Option<&str>) -> io::Result<Option<PathBuf>> {
+        let Some(file_name) = remote.file_name() else { return Ok(None) };
+        let target_dir = match sender.and_then(|sender| source.routes.get(sender)) {
+            Some(destination) => directory.join(paths::resolve_path(destination)),
+            None => directory.to_path_buf(),
+        };
+        fs::create_dir_all(&target_dir)?;
+        let target = target_dir.join(file_name);
+        if target.exists() {
+            warn!(file = ?remote, "Skipping download, {:?} already exists", target);
+            return Ok(None);
+        }
+        
+        // Download next to the target first, so a partial file is never picked up by the sorter.
+        let partial = target_dir.join(format!(".{}.part", file_name.to_string_lossy()));
+        let downloaded = File::create(&partial).and_then(|mut local| -> io::Result<u64> {
+            let copied = io::copy(&mut sftp.open(remote)?, &mut local)?;
+            local.sync_all()?;
+            Ok(copied)
+        });
+        let copied = match downloaded {
+            Ok(copied) => copied,
+            Err(e) => {
+                let _ = fs::remove_file(&partial);
+                return Err(e);
+            }
+        };
+        if stat.size.is_some_and(|size| size != copied) {
+            fs::remove_file(&partial)?;
+            warn!(file = ?remote, "Download incomplete, will retry");
+            return Ok(None);
+        }
+        fs::rename(&partial, &target)?;
+        
+        if let Some(Err(e)) = source.delete_after.then(|| sftp.unlink(remote)) {
+            warn!(file = ?remote, "Fetched file, but failed to delete it on the server: {}", e);
+        }
+        Ok(Some(target))
+    }
+    
+    fn read_fetched(path: &Path) -> BTreeMap<String, BTreeMap<String, u64>> {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+    
+    /// Logs in with the password from `password_env`, the key in `key_file` or else the SSH agent.
+    pub fn connect(host: &str, port: u16, user: &str, key_file: Option<&Path>, password_env: Option<&str>) -> io::Result<Session> {
+        let mut session = Session::new()?;
+        session.set_tcp_stream(TcpStream::connect((host, port))?);
+        session.handshake()?;
+        verify_host_key(&session, host, port)?;
+        
+        if let Some(variable) = password_env {
+            let password = std::env::var(variable)
+                .map_err(|_| io::Error::new(io::ErrorKind::NotFound, format!("{} is not set", variable)))?;
+            session.userauth_password(user, &password)?;
+        } else if let Some(key_file) = key_file {
+            session.userauth_pubkey_file(user, None, key_file, None)?;
+        } else {
+            session.userauth_agent(user)?;
+        }
+        Ok(session)
+    }
+    
+    /// Refuses servers whose host key is not in the user's `known_hosts`.
+    fn verify_host_key(session: &Session, host: &str, port: u16) -> io::Result<()> {
+        let mut known_hosts = session.known_hosts()?;
+        if let Some(home) = paths::home_dir() {
+            let _ = known_hosts.read_file(&home.join(".ssh").join("known_hosts"), KnownHostFileKind::OpenSSH);
+        }
+        let (key, _) = session
+            .host_key()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Server sent no host key"))?;
+        let host = if port == 22 {
+            host.to_string()
+        } else {
+            format!("[{}]:{}", host, port)
+        };
+        match known_hosts.check(&host, key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::Mismatch => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("Host key for {} does not match known_hosts", host),
+            )),
+            _ => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{} is not in known_hosts; connect once with ssh to trust it", host),
+            )),
+        }
+    }
+}
+
+#[cfg(not(feature = "sftp"))]
+mod sftp {
+    use super::{Ingested, SftpSource};
+    use std::path::Path;
+    
+    pub fn poll(_source: &SftpSource, _directory: &Path) -> std::io::Result<Vec<Ingested>> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "SFTP support is not enabled in this build",
+        ))
+    }
+}
@@ -0,0 +1,143 @@
+use crate::paths;
+use crate::statefile;
+use chrono::{DateTime, Duration, Local};
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const STAGING_FILE: &str = "staging.json";
+
+/// Holds files in a staging folder for review before they are placed in the destination.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StageAction {
+    /// Where files wait, relative to the sorted directory like destinations.
+    pub folder: String,
+    /// Hours after which staged files move on without review; when unset they wait for `staging approve`.
+    pub approve_after_hours: Option<u64>,
+}
+
+/// Reviews files waiting in staging folders.
+#[derive(Subcommand)]
+pub enum StagingCommand {
+    /// List staged files and when they move on
+    List,
+    /// Let staged files move on to their destination with the next sort
+    Approve {
+        /// Staged files to approve
+        #[arg(required_unless_present = "all")]
+        files: Vec<PathBuf>,
+        /// Approve every staged file
+        #[arg(long, conflicts_with = "files")]
+        all: bool,
+    },
+}
+
+/// A file waiting in a staging folder.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StagedFile {
+    pub rule: String,
+    pub original: PathBuf,
+    pub staged: PathBuf,
+    /// The folder the file is placed in once it moves on.
+    pub folder: PathBuf,
+    /// The rule's destination, whose other actions run when the file is placed.
+    pub destination: String,
+    pub staged_at: DateTime<Local>,
+    pub approve_after_hours: Option<u64>,
+    #[serde(default)]
+    pub approved: bool,
+}
+
+impl StagedFile {
+    /// When the file moves on without review, if it does.
+    pub fn release_at(&self) -> Option<DateTime<Local>> {
+        self.approve_after_hours.map(|hours| self.staged_at + Duration::hours(hours as i64))
+    }
+
+    fn due(&self, now: DateTime<Local>) -> bool {
+        self.approved || self.release_at().is_some_and(|release| release <= now)
+    }
+}
+
+/// The staged files, kept in the data directory so that `staging approve` and a running
+/// daemon see the same list.
+pub struct Staging {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl Staging {
+    pub fn open() -> Self {
+        Staging {
+            path: paths::data_dir().join(STAGING_FILE),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn read(&self) -> Vec<StagedFile> {
+        fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn write(&self, files: &[StagedFile]) -> io::Result<()> {
+        statefile::write(&self.path, serde_json::to_string_pretty(files)?.as_bytes())
+    }
+
+    pub fn add(&self, file: StagedFile) -> io::Result<()> {
+        let _guard = self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let _lock = statefile::lock(&self.path)?;
+        let mut files = self.read();
+        files.push(file);
+        self.write(&files)
+    }
+
+    /// The files still waiting; files a reviewer moved or deleted are no longer listed.
+    pub fn list(&self) -> Vec<StagedFile> {
+        let _guard = self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        self.read().into_iter().filter(|file| file.staged.exists()).collect()
+    }
+
+    /// Approves the staged files at `paths`, or all of them, and returns how many were approved.
+    pub fn approve(&self, paths: &[PathBuf], all: bool) -> io::Result<usize> {
+        let _guard = self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let _lock = statefile::lock(&self.path)?;
+        let wanted: Vec<PathBuf> = paths.iter().map(|path| canonical(path)).collect();
+        let mut files = self.read();
+        let mut approved = 0;
+        for file in files.iter_mut().filter(|file| !file.approved) {
+            if all || wanted.contains(&canonical(&file.staged)) {
+                file.approved = true;
+                approved += 1;
+            }
+        }
+        self.write(&files)?;
+        Ok(approved)
+    }
+
+    /// Removes and returns the files that were approved or have waited long enough. Files
+    /// that are gone from staging were moved or deleted in review and are forgotten.
+    pub fn take_due(&self) -> io::Result<Vec<StagedFile>> {
+        let _guard = self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let _lock = statefile::lock(&self.path)?;
+        let now = Local::now();
+        let files = self.read();
+        let count = files.len();
+        let (due, waiting): (Vec<StagedFile>, Vec<StagedFile>) = files
+            .into_iter()
+            .filter(|file| file.staged.exists())
+            .partition(|file| file.due(now));
+        if waiting.len() != count {
+            self.write(&waiting)?;
+        }
+        Ok(due)
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
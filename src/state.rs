@@ -0,0 +1,211 @@
+use crate::audit;
+use crate::instance;
+use crate::paths;
+use crate::settings;
+use chrono::{DateTime, Local};
+use clap::Subcommand;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+const MANIFEST: &str = "manifest.json";
+
+/// Moves Organizer's state to another machine or a fresh install.
+#[derive(Subcommand)]
+pub enum StateCommand {
+    /// Bundle the move index, journals, queues, settings and rules into one archive
+    Export {
+        /// The archive to write, e.g. `organizer-state.tar.zst`
+        archive: PathBuf,
+    },
+    /// Restore the state from an archive written by `state export`
+    Import {
+        archive: PathBuf,
+        /// Replace the state this machine already has
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+/// Where a bundled file goes, by the folder it has in the archive.
+#[derive(Clone, Copy)]
+enum Location {
+    /// The data directory: the move index, queues and journals.
+    Data,
+    /// The config directory: settings and the audit signing key.
+    Config,
+    /// The working directory: `rules.json` and the Lua script.
+    Rules,
+}
+
+// On Windows the config and data directories are the same, so the config files are taken first.
+const LOCATIONS: [(Location, &str); 3] = [(Location::Config, "config"), (Location::Data, "data"), (Location::Rules, "rules")];
+
+impl Location {
+    fn folder(self) -> PathBuf {
+        match self {
+            Location::Data => paths::data_dir(),
+            Location::Config => paths::config_dir(),
+            Location::Rules => paths::rules_dir(),
+        }
+    }
+}
+
+/// Describes an archive, so an import can say where it came from.
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    exported_at: DateTime<Local>,
+    host: String,
+    files: Vec<String>,
+}
+
+/// Writes every state file to `archive`. SQLite databases are copied with `VACUUM INTO`,
+/// so a daemon may keep sorting while they are exported.
+pub fn export(archive: &Path, rule_files: &[&str]) -> io::Result<usize> {
+    let mut files = Vec::new();
+    for (location, prefix) in LOCATIONS {
+        let folder = location.folder();
+        let names: Vec<String> = match location {
+            Location::Config => [settings::SETTINGS_FILE, audit::KEY_FILE].map(str::to_string).to_vec(),
+            Location::Rules => rule_files.iter().map(|name| name.to_string()).collect(),
+            Location::Data => match fs::read_dir(&folder) {
+                Ok(entries) => entries
+                    .flatten()
+                    .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_file()))
+                    .filter_map(|entry| entry.file_name().into_string().ok())
+                    .collect(),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => return Err(e),
+            },
+        };
+        for name in names.into_iter().filter(|name| is_state(name)) {
+            let path = folder.join(&name);
+            if path.is_file() && !files.iter().any(|(_, existing)| *existing == path) {
+                files.push((format!("{}/{}", prefix, name), path));
+            }
+        }
+    }
+    if files.iter().any(|(entry, _)| entry.ends_with(".key")) {
+        warn!("The archive holds the audit signing key; keep it as safe as the key itself");
+    }
+
+    let partial = archive.with_file_name(format!(".{}.part", archive.file_name().unwrap_or_default().to_string_lossy()));
+    let mut builder = tar::Builder::new(zstd::Encoder::new(File::create(&partial)?, 0)?);
+    let manifest = Manifest {
+        exported_at: Local::now(),
+        host: whoami::fallible::hostname().unwrap_or_default(),
+        files: files.iter().map(|(entry, _)| entry.clone()).collect(),
+    };
+    let manifest = serde_json::to_vec_pretty(&manifest)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o600);
+    header.set_mtime(Local::now().timestamp().max(0) as u64);
+    builder.append_data(&mut header, MANIFEST, manifest.as_slice())?;
+    for (entry, path) in &files {
+        if path.extension().is_some_and(|extension| extension == "sqlite3") {
+            let snapshot = snapshot(path)?;
+            let result = builder.append_path_with_name(&snapshot, entry);
+            let _ = fs::remove_file(&snapshot);
+            result?;
+        } else {
+            builder.append_path_with_name(path, entry)?;
+        }
+    }
+    builder.into_inner()?.finish()?.sync_all()?;
+    fs::rename(&partial, archive)?;
+    Ok(files.len())
+}
+
+/// Leaves out what only makes sense on this machine while it runs: SQLite's side files,
+/// a daemon's control socket, lock files and half-written files.
+fn is_state(name: &str) -> bool {
+    !name.starts_with('.') && ![".sqlite3-wal", ".sqlite3-shm", ".sqlite3-journal", ".sock", ".part", ".lock"].iter().any(|suffix| name.ends_with(suffix))
+}
+
+/// A consistent copy of the database at `path`, in the temporary folder.
+fn snapshot(path: &Path) -> io::Result<PathBuf> {
+    let target = std::env::temp_dir().join(format!("organizer-{}-{}", std::process::id(), path.file_name().unwrap_or_default().to_string_lossy()));
+    let _ = fs::remove_file(&target);
+    Connection::open(path)
+        .and_then(|connection| connection.execute("VACUUM INTO ?1", [target.to_string_lossy()]))
+        .map_err(io::Error::other)?;
+    Ok(target)
+}
+
+/// Restores the files of `archive`. Refuses to while a daemon runs, and to replace the
+/// state this machine has unless `force` is set.
+pub fn import(archive: &Path, force: bool) -> io::Result<usize> {
+    if instance::any_running() {
+        return Err(io::Error::new(io::ErrorKind::WouldBlock, "Stop the running daemons before importing state"));
+    }
+    let mut restored = Vec::new();
+    if let Err(e) = unpack(archive, force, &mut restored) {
+        for (partial, _) in &restored {
+            let _ = fs::remove_file(partial);
+        }
+        return Err(e);
+    }
+    // Files are only swapped in once all of them were unpacked, so a broken archive changes nothing.
+    let count = restored.len();
+    for (partial, target) in restored {
+        if target.extension().is_some_and(|extension| extension == "sqlite3") {
+            for suffix in ["-wal", "-shm", "-journal"] {
+                let mut side = target.clone().into_os_string();
+                side.push(suffix);
+                let _ = fs::remove_file(side);
+            }
+        }
+        fs::rename(&partial, &target)?;
+        info!(file = ?target, "Restored");
+    }
+    Ok(count)
+}
+
+/// Unpacks the files of `archive` next to where they go, adding each to `restored` with its target.
+fn unpack(archive: &Path, force: bool, restored: &mut Vec<(PathBuf, PathBuf)>) -> io::Result<()> {
+    let mut tar = tar::Archive::new(zstd::Decoder::new(File::open(archive)?)?);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        if name == MANIFEST {
+            let manifest: Manifest = serde_json::from_reader(&mut entry)?;
+            info!(
+                "Importing {} files exported from {} on {}",
+                manifest.files.len(),
+                manifest.host,
+                manifest.exported_at.format("%Y-%m-%d %H:%M")
+            );
+            continue;
+        }
+        let Some((location, file_name)) = destination(&name) else {
+            warn!("Skipping unexpected archive entry {}", name);
+            continue;
+        };
+        let folder = location.folder();
+        let target = folder.join(file_name);
+        if target.exists() && !force {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists; pass --force to replace it", target.display()),
+            ));
+        }
+        fs::create_dir_all(&folder)?;
+        let partial = folder.join(format!(".{}.part", file_name));
+        entry.unpack(&partial)?;
+        restored.push((partial, target));
+    }
+    Ok(())
+}
+
+/// Where an archive entry such as `data/index.sqlite3` goes; entries with any other shape,
+/// such as `data/../x`, are not restored.
+fn destination(name: &str) -> Option<(Location, &str)> {
+    let (prefix, file_name) = name.split_once('/')?;
+    let location = LOCATIONS.iter().find(|(_, folder)| *folder == prefix)?.0;
+    let valid = !file_name.is_empty() && !file_name.contains(['/', '\\']) && is_state(file_name);
+    valid.then_some((location, file_name))
+}
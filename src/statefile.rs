@@ -0,0 +1,82 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// An advisory lock on a state file in the data directory, so that a daemon and the
+/// commands run while it sorts change the file one at a time. It is held on a `.lock` file
+/// next to the state file, and released when dropped.
+pub struct Lock {
+    _file: File,
+}
+
+/// Waits until no other process holds the lock of `path`, then takes it.
+pub fn lock(path: &Path) -> io::Result<Lock> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(lock_path(path))?;
+    lock_file(&file)?;
+    Ok(Lock { _file: file })
+}
+
+fn lock_path(path: &Path) -> PathBuf {
+    let mut lock = path.as_os_str().to_os_string();
+    lock.push(".lock");
+    PathBuf::from(lock)
+}
+
+#[cfg(unix)]
+fn lock_file(file: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+            return Ok(());
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
+
+#[cfg(windows)]
+fn lock_file(file: &File) -> io::Result<()> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Storage::FileSystem::{LockFileEx, LOCKFILE_EXCLUSIVE_LOCK};
+
+    let mut overlapped = unsafe { std::mem::zeroed() };
+    if unsafe { LockFileEx(file.as_raw_handle() as _, LOCKFILE_EXCLUSIVE_LOCK, 0, u32::MAX, u32::MAX, &mut overlapped) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn lock_file(_file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+/// Tells apart the temporary files of writes from the same process.
+static WRITES: AtomicU64 = AtomicU64::new(0);
+
+/// Replaces the contents of `path` in one step, so that a process reading it meanwhile
+/// sees either the old or the new contents, never half of them. The new contents are
+/// written to a temporary file of their own first, so writers at the same time don't
+/// write into each other's.
+pub fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let partial = path.with_file_name(format!(".{}.{}.{}.part", name, process::id(), WRITES.fetch_add(1, Ordering::Relaxed)));
+    let written = File::create(&partial)
+        .and_then(|mut file| file.write_all(contents).and_then(|_| file.sync_all()))
+        .and_then(|_| fs::rename(&partial, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    written
+}
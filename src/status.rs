@@ -0,0 +1,58 @@
+use crate::error::SortError;
+use crate::report::Totals;
+use std::io::ErrorKind;
+
+/// Exit statuses of `sort`, so scripts and schedulers can branch on the outcome.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// Every file that matched a rule was sorted.
+    Success = 0,
+    /// No file matched a rule.
+    NothingToDo = 1,
+    /// Some files could not be sorted, the others were; with `--strict` also when files were skipped or quarantined.
+    PartialFailure = 2,
+    /// `rules.json` or a `--set` could not be read, no directory or one that does not exist was
+    /// given, or a selected rule or profile is unknown.
+    ConfigError = 3,
+    /// The sort was not confirmed.
+    Cancelled = 4,
+    /// The sort could not run at all.
+    Failure = 5,
+}
+
+impl ExitStatus {
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+
+    /// Classifies a sort that ran to the end.
+    pub fn from_totals(totals: &Totals, strict: bool) -> ExitStatus {
+        if totals.failed > 0 || (strict && totals.skipped + totals.quarantined > 0) {
+            return ExitStatus::PartialFailure;
+        }
+        if totals.moved + totals.copied + totals.planned + totals.queued + totals.staged + totals.duplicates + totals.removed + totals.quarantined == 0 {
+            return ExitStatus::NothingToDo;
+        }
+        ExitStatus::Success
+    }
+
+    /// Classifies a sort that stopped with an error.
+    pub fn from_error(error: &SortError, totals: &Totals) -> ExitStatus {
+        match error {
+            SortError::Config(_) | SortError::NotADirectory(_) => ExitStatus::ConfigError,
+            SortError::Cancelled => ExitStatus::Cancelled,
+            // Confirmation can't be asked for without a terminal.
+            SortError::Io(e) if e.kind() == ErrorKind::Interrupted => ExitStatus::Cancelled,
+            SortError::Io(_) if totals.failed > 0 => ExitStatus::PartialFailure,
+            SortError::Io(_) | SortError::InsufficientSpace(_) => ExitStatus::Failure,
+        }
+    }
+
+    /// Handoff jobs that could not be run after the files were sorted make the run a partial failure.
+    pub fn with_job_error(self) -> ExitStatus {
+        match self {
+            ExitStatus::Success | ExitStatus::NothingToDo => ExitStatus::PartialFailure,
+            status => status,
+        }
+    }
+}
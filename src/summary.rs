@@ -0,0 +1,80 @@
+use crate::diskspace::format_size;
+use crate::index::RuleSummary;
+use clap::ValueEnum;
+
+/// Number of destination folders listed per rule.
+pub const TOP_DESTINATIONS: usize = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SummaryFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+/// Prints per-rule summaries for reading, or for spreadsheets and scripts.
+pub fn print(summaries: &[RuleSummary], format: SummaryFormat) -> serde_json::Result<()> {
+    match format {
+        SummaryFormat::Table => print_table(summaries),
+        SummaryFormat::Json => println!("{}", serde_json::to_string_pretty(summaries)?),
+        SummaryFormat::Csv => {
+            println!("rule,files,bytes,errors,top_destinations");
+            for summary in summaries {
+                println!(
+                    "{},{},{},{},{}",
+                    csv_field(rule_name(summary)),
+                    summary.files,
+                    summary.bytes,
+                    summary.errors,
+                    csv_field(&destinations(summary, ";"))
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_table(summaries: &[RuleSummary]) {
+    if summaries.is_empty() {
+        println!("Nothing was recorded in this period.");
+        return;
+    }
+    let width = summaries.iter().map(|summary| rule_name(summary).len()).max().unwrap_or_default().max(4);
+    println!("{:<width$}  {:>6}  {:>9}  {:>6}  TOP DESTINATIONS", "RULE", "FILES", "SIZE", "ERRORS");
+    for summary in summaries {
+        println!(
+            "{:<width$}  {:>6}  {:>9}  {:>6}  {}",
+            rule_name(summary),
+            summary.files,
+            format_size(summary.bytes),
+            summary.errors,
+            destinations(summary, ", ")
+        );
+    }
+    let files: usize = summaries.iter().map(|summary| summary.files).sum();
+    let bytes: u64 = summaries.iter().map(|summary| summary.bytes).sum();
+    let errors: usize = summaries.iter().map(|summary| summary.errors).sum();
+    println!("{:<width$}  {:>6}  {:>9}  {:>6}", "TOTAL", files, format_size(bytes), errors);
+}
+
+fn rule_name(summary: &RuleSummary) -> &str {
+    summary.rule.as_deref().unwrap_or("-")
+}
+
+fn destinations(summary: &RuleSummary, separator: &str) -> String {
+    summary
+        .top_destinations
+        .iter()
+        .map(|destination| format!("{} ({})", destination.folder, destination.files))
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// Quotes a field when it contains characters that are special in CSV.
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,67 @@
+use crate::xattr;
+use serde::Serialize;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Extended attributes written by the `tag` action. On Windows they are alternate data
+/// streams of the same names.
+const RULE: &str = "user.organizer.rule";
+const ORIGINAL_PATH: &str = "user.organizer.original_path";
+const TAGS: &str = "user.organizer.tags";
+
+/// What the `tag` action recorded on a file.
+#[derive(Serialize)]
+pub struct FileTags {
+    pub path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_path: Option<PathBuf>,
+    pub tags: Vec<String>,
+}
+
+impl FileTags {
+    /// Whether the file carries every one of `tags`, ignoring case.
+    pub fn has_all(&self, tags: &[String]) -> bool {
+        tags.iter().all(|wanted| self.tags.iter().any(|tag| tag.eq_ignore_ascii_case(wanted)))
+    }
+}
+
+/// Records the rule that placed the file, where it came from and the rule's tags on it.
+/// Tags the file already has are kept, so a file filed again collects the tags of both rules.
+pub fn write(file_path: &Path, rule: &str, original: &Path, tags: &[String]) -> io::Result<()> {
+    xattr::set(file_path, RULE, rule.as_bytes())?;
+    xattr::set(file_path, ORIGINAL_PATH, original.to_string_lossy().as_bytes())?;
+    let mut all = read_tags(file_path)?;
+    for tag in tags.iter().map(|tag| tag.trim()).filter(|tag| !tag.is_empty()) {
+        if !all.iter().any(|existing| existing.eq_ignore_ascii_case(tag)) {
+            all.push(tag.to_string());
+        }
+    }
+    if !all.is_empty() {
+        xattr::set(file_path, TAGS, all.join(",").as_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn read(file_path: &Path) -> io::Result<FileTags> {
+    let text = |name| -> io::Result<Option<String>> {
+        Ok(xattr::get(file_path, name)?.map(|value| String::from_utf8_lossy(&value).into_owned()))
+    };
+    Ok(FileTags {
+        path: file_path.to_path_buf(),
+        rule: text(RULE)?,
+        original_path: text(ORIGINAL_PATH)?.map(PathBuf::from),
+        tags: read_tags(file_path)?,
+    })
+}
+
+/// Tags are stored as one comma-separated list.
+fn read_tags(file_path: &Path) -> io::Result<Vec<String>> {
+    let Some(value) = xattr::get(file_path, TAGS)? else { return Ok(Vec::new()) };
+    Ok(String::from_utf8_lossy(&value)
+        .split(',')
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect())
+}
@@ -0,0 +1,266 @@
+use crate::dates;
+use crate::media::{self, AudioTags, ExifInfo};
+use crate::paths;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime};
+use deunicode::{deunicode_char, deunicode_with_tofu};
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+const UNKNOWN: &str = "Unknown";
+const NAME_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Which metadata values placeholders write in Latin letters, from `scripts` in `rules.json`.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScriptPolicy {
+    /// Only those of placeholders with `:translit`, e.g. `{artist:translit}`.
+    #[default]
+    Keep,
+    /// Letters of scripts other than Latin, such as Cyrillic, Greek or Chinese; accented
+    /// Latin letters stay.
+    NonLatin,
+    /// Everything outside ASCII, for FAT drives and software that can't handle anything else.
+    Ascii,
+}
+
+/// The `name_dates` patterns and `scripts` policy of `rules.json` placeholders are expanded with.
+#[derive(Clone, Default)]
+pub struct Settings {
+    /// Patterns for dates in file names, tried before the usual forms.
+    name_dates: Vec<Regex>,
+    scripts: ScriptPolicy,
+}
+
+impl Settings {
+    /// Compiles the `name_dates` patterns; those [`validate_name_date`] refuses are left out.
+    pub fn new(name_dates: &[String], scripts: ScriptPolicy) -> Settings {
+        Settings {
+            name_dates: name_dates.iter().filter_map(|pattern| Regex::new(pattern).ok()).collect(),
+            scripts,
+        }
+    }
+}
+
+/// Checks a `name_dates` pattern once when the rules are loaded: it has to be a regular
+/// expression with `year`, `month` and `day` groups.
+pub fn validate_name_date(pattern: &str) -> Result<(), String> {
+    let regex = Regex::new(pattern).map_err(|e| format!("invalid name_dates pattern {}: {}", pattern, e))?;
+    for group in ["year", "month", "day"] {
+        if !regex.capture_names().flatten().any(|name| name == group) {
+            return Err(format!("name_dates pattern {} has no `{}` group", pattern, group));
+        }
+    }
+    Ok(())
+}
+
+/// Checks a destination when the rules are loaded, or when an `exec` command answers with
+/// it: it has to name a folder, and every placeholder in it has to be closed.
+pub fn validate_destination(destination: &str) -> Result<(), String> {
+    if destination.trim().is_empty() {
+        return Err("the destination is empty".to_string());
+    }
+    if destination.chars().any(char::is_control) {
+        return Err(format!("the destination {:?} has control characters", destination));
+    }
+    let mut rest = destination;
+    while let Some(start) = rest.find('{') {
+        let Some(length) = rest[start..].find('}') else {
+            return Err(format!("the destination {} has a placeholder without a closing }}", destination));
+        };
+        rest = &rest[start + length + 1..];
+    }
+    Ok(())
+}
+
+/// Metadata of a file, read on first use.
+struct Metadata<'a> {
+    file_path: &'a Path,
+    exif: Option<Option<ExifInfo>>,
+    audio: Option<Option<AudioTags>>,
+}
+
+impl Metadata<'_> {
+    fn exif(&mut self) -> Option<&ExifInfo> {
+        let file_path = self.file_path;
+        self.exif.get_or_insert_with(|| media::read_exif(file_path)).as_ref()
+    }
+    
+    fn audio(&mut self) -> Option<&AudioTags> {
+        let file_path = self.file_path;
+        self.audio.get_or_insert_with(|| media::read_audio_tags(file_path)).as_ref()
+    }
+}
+
+/// The part of a destination before its first placeholder, which files are placed below.
+pub fn destination_top(template: &str) -> &str {
+    match template.find('{') {
+        Some(placeholder) => template[..placeholder].rfind('/').map_or("", |slash| &template[..slash]),
+        None => template,
+    }
+}
+
+/// The folder below `root` that holds only the files placed by `template`, for cleanups
+/// that move or delete what is in it. `None` when the part before the first placeholder is
+/// empty, or resolves to `root` or a folder around it, as those hold far more than that.
+pub fn managed_folder(root: &Path, template: &str) -> Option<PathBuf> {
+    let top = destination_top(template);
+    let folder = root.join(paths::resolve_path(top));
+    let normalized = |path: &Path| -> PathBuf {
+        fs::canonicalize(path).unwrap_or_else(|_| path.components().filter(|component| *component != Component::CurDir).collect())
+    };
+    let encloses_root = normalized(root).starts_with(normalized(&folder));
+    (!top.trim_matches(['/', '.']).is_empty() && !encloses_root).then_some(folder)
+}
+
+/// Expands placeholders in a destination such as `Photos/{exif.year}/{exif.month}`,
+/// `Music/{artist}/{album}` or `Scans/{name_date:%Y/%m}`. Placeholders whose value is
+/// unknown expand to `Unknown`.
+pub fn expand_destination(template: &str, file_path: &Path, settings: &Settings) -> String {
+    if !template.contains('{') {
+        return template.to_string();
+    }
+    
+    let mut metadata = Metadata {
+        file_path,
+        exif: None,
+        audio: None,
+    };
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(length) = rest[start..].find('}') else { break };
+        let name = &rest[start + 1..start + length];
+        expanded.push_str(&rest[..start]);
+        
+        let value = match name.strip_prefix("name_date") {
+            // The format may lay the date out in folders, e.g. `%Y/%m`.
+            Some(format) => name_date(format, file_path, &settings.name_dates).map(|date| date.split('/').map(sanitize).collect::<Vec<_>>().join("/")),
+            None => match name.strip_suffix(":translit") {
+                Some(name) => placeholder(name, &mut metadata).map(|value| sanitize(&transliterate(&value, ScriptPolicy::Ascii))),
+                None => placeholder(name, &mut metadata).map(|value| sanitize(&transliterate(&value, settings.scripts))),
+            },
+        };
+        match value {
+            Some(value) => expanded.push_str(&value),
+            None => expanded.push_str(&rest[start..=start + length]),
+        }
+        rest = &rest[start + length + 1..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Returns the value for a known placeholder, or `None` to leave it untouched.
+fn placeholder(name: &str, metadata: &mut Metadata) -> Option<String> {
+    let value = match name {
+        "exif.year" => metadata.exif().and_then(|exif| exif.date).map(|date| date.year().to_string()),
+        "exif.month" => metadata.exif().and_then(|exif| exif.date).map(|date| format!("{:02}", date.month())),
+        "exif.day" => metadata.exif().and_then(|exif| exif.date).map(|date| format!("{:02}", date.day())),
+        "exif.date" => metadata.exif().and_then(|exif| exif.date).map(|date| date.format("%Y-%m-%d").to_string()),
+        "camera" | "exif.camera" => metadata.exif().and_then(|exif| exif.camera.clone()),
+        "artist" => metadata.audio().and_then(|audio| audio.artist.clone()),
+        "album" => metadata.audio().and_then(|audio| audio.album.clone()),
+        "title" => metadata.audio().and_then(|audio| audio.title.clone()),
+        "genre" => metadata.audio().and_then(|audio| audio.genre.clone()),
+        "year" => metadata.audio().and_then(|audio| audio.year).map(|year| year.to_string()),
+        "modified.year" => modified(metadata.file_path).map(|date| date.year().to_string()),
+        "modified.month" => modified(metadata.file_path).map(|date| format!("{:02}", date.month())),
+        "modified.day" => modified(metadata.file_path).map(|date| format!("{:02}", date.day())),
+        "modified.date" => modified(metadata.file_path).map(|date| date.format("%Y-%m-%d").to_string()),
+        "stem" => Some(file_stem(metadata.file_path)),
+        _ => return None,
+    };
+    Some(value.unwrap_or_else(|| UNKNOWN.to_string()))
+}
+
+/// Expands `{name_date}` or `{name_date:<format>}`: the date in the file's name, or when it
+/// was last modified when the name has none. `None` for other names and invalid formats.
+fn name_date(placeholder: &str, file_path: &Path, name_dates: &[Regex]) -> Option<String> {
+    let format = match placeholder {
+        "" => NAME_DATE_FORMAT,
+        _ => placeholder.strip_prefix(':')?,
+    };
+    let items: Vec<Item> = StrftimeItems::new(format).collect();
+    if items.iter().any(|item| matches!(item, Item::Error)) {
+        return None;
+    }
+    let Some(date) = date_in_name(file_path, name_dates).or_else(|| modified(file_path).map(|date| date.naive_local())) else {
+        return Some(UNKNOWN.to_string());
+    };
+    let mut formatted = String::new();
+    write!(formatted, "{}", date.format_with_items(items.into_iter())).ok()?;
+    Some(formatted)
+}
+
+/// A date such as `Scan_2023-11-04.pdf` or `IMG_20240102_153012.jpg` in the file's name,
+/// found by the `name_dates` patterns first.
+fn date_in_name(file_path: &Path, name_dates: &[Regex]) -> Option<NaiveDateTime> {
+    let name = file_path.file_name()?.to_str()?;
+    name_dates
+        .iter()
+        .find_map(|pattern| date_from_captures(&pattern.captures(name)?))
+        .or_else(|| dates::date_from_filename(name))
+}
+
+/// The date the `year`, `month` and `day` groups captured, with the time of day from
+/// `hour`, `minute` and `second` when the pattern has them. Two-digit years are after 2000.
+fn date_from_captures(captures: &Captures) -> Option<NaiveDateTime> {
+    let number = |group: &str| captures.name(group).and_then(|matched| matched.as_str().parse::<u32>().ok());
+    let year = number("year")?;
+    let year = if year < 100 { 2000 + year } else { year };
+    let date = NaiveDate::from_ymd_opt(year as i32, number("month")?, number("day")?)?;
+    date.and_hms_opt(number("hour").unwrap_or(0), number("minute").unwrap_or(0), number("second").unwrap_or(0))
+}
+
+/// When the file was last modified, in local time.
+fn modified(file_path: &Path) -> Option<DateTime<Local>> {
+    file_path.metadata().and_then(|metadata| metadata.modified()).ok().map(DateTime::from)
+}
+
+/// The file name without its extension, treating `.tar.gz` and similar as one extension.
+fn file_stem(file_path: &Path) -> String {
+    let stem = file_path.file_stem().unwrap_or_default().to_string_lossy();
+    stem.strip_suffix(".tar").unwrap_or(&stem).to_string()
+}
+
+/// Writes `value` in Latin letters as far as `policy` asks, e.g. `Кино` as `Kino`; letters
+/// without a transliteration become `_`.
+pub fn transliterate(value: &str, policy: ScriptPolicy) -> String {
+    match policy {
+        ScriptPolicy::Keep => value.to_string(),
+        ScriptPolicy::Ascii => deunicode_with_tofu(value, "_"),
+        ScriptPolicy::NonLatin => value
+            .chars()
+            .map(|c| match c {
+                c if !c.is_alphabetic() || is_latin(c) => c.to_string(),
+                c => deunicode_char(c).unwrap_or("_").to_string(),
+            })
+            .collect::<String>()
+            .trim()
+            .to_string(),
+    }
+}
+
+/// Letters of the Latin script: ASCII and the Latin-1 Supplement, Latin Extended and Latin
+/// Extended Additional blocks.
+fn is_latin(c: char) -> bool {
+    c.is_ascii() || matches!(c, '\u{00C0}'..='\u{024F}' | '\u{1E00}'..='\u{1EFF}')
+}
+
+/// Keeps metadata values from introducing path separators or invalid characters.
+pub fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>()
+        .trim_matches(['.', ' '])
+        .to_string()
+}
@@ -0,0 +1,33 @@
+//! Folders on disk for the tests that need real files, removed once a test is done with them.
+
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// An empty folder below the temporary folder, by its canonical path so it compares with the
+/// paths scans and the index return. It goes with everything in it when dropped.
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    /// The folder for the test `name` of `module`; what an earlier run left there is removed.
+    pub fn new(module: &str, name: &str) -> TestDir {
+        let folder = std::env::temp_dir().join(format!("organizer-{}-{}-{}", module, name, std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        TestDir(fs::canonicalize(folder).unwrap())
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,85 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Bytes per second that copies and compression may read in total; 0 when unlimited.
+static LIMIT: AtomicU64 = AtomicU64::new(0);
+/// When the bytes read so far have been paid for; shared by all workers.
+static PAID_UNTIL: Mutex<Option<Instant>> = Mutex::new(None);
+
+pub fn set_limit(megabytes_per_second: f64) {
+    LIMIT.store((megabytes_per_second * 1024.0 * 1024.0) as u64, Ordering::SeqCst);
+}
+
+/// Sleeps long enough that reading `bytes` keeps all workers together under the limit.
+fn pace(bytes: usize) {
+    let limit = LIMIT.load(Ordering::SeqCst);
+    if limit == 0 || bytes == 0 {
+        return;
+    }
+    let cost = Duration::from_secs_f64(bytes as f64 / limit as f64);
+    let wait = {
+        let mut paid_until = PAID_UNTIL.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let now = Instant::now();
+        let start = paid_until.filter(|paid| *paid > now).unwrap_or(now);
+        *paid_until = Some(start + cost);
+        start - now
+    };
+    thread::sleep(wait);
+}
+
+/// A reader that is slowed down to the configured limit.
+pub struct Throttled<R>(pub R);
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.0.read(buf)?;
+        pace(read);
+        Ok(read)
+    }
+}
+
+/// Copies a file like `fs::copy`, at no more than the configured rate.
+pub fn copy(source: &Path, target: &Path) -> io::Result<u64> {
+    if LIMIT.load(Ordering::SeqCst) == 0 {
+        return fs::copy(source, target);
+    }
+    let copied = io::copy(&mut Throttled(File::open(source)?), &mut File::create(target)?)?;
+    fs::set_permissions(target, fs::metadata(source)?.permissions())?;
+    Ok(copied)
+}
+
+/// Gives the process idle disk priority and the lowest CPU priority where the system
+/// supports it, so sorts yield to interactive programs.
+pub fn lower_priority() -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+        const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+        const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+        // Like `ionice -c 3`; threads started afterwards inherit the class.
+        let priority = IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT;
+        if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, priority) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    #[cfg(unix)]
+    {
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 19) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::System::Threading::{GetCurrentProcess, SetPriorityClass, PROCESS_MODE_BACKGROUND_BEGIN};
+        // Background mode lowers the disk and memory priority as well.
+        if unsafe { SetPriorityClass(GetCurrentProcess(), PROCESS_MODE_BACKGROUND_BEGIN) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A step of sorting a file, timed for the rule it was taken for.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    /// Looking at the file itself, e.g. whether it is a symlink.
+    Stat,
+    /// Reading tags or EXIF data, for templates, plugins and the Lua script.
+    Metadata,
+    /// Matching the file name against the rule's `match`.
+    Pattern,
+    /// Reading the file for `contains` and `matches_content`.
+    Content,
+    Exec,
+    Plugin,
+    Lua,
+    /// Moving or copying the file into its destination.
+    Move,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Stage::Stat => "stat",
+            Stage::Metadata => "metadata",
+            Stage::Pattern => "pattern",
+            Stage::Content => "content",
+            Stage::Exec => "exec",
+            Stage::Plugin => "plugin",
+            Stage::Lua => "lua",
+            Stage::Move => "move",
+        })
+    }
+}
+
+/// How often a stage ran for a rule, and how long it took in all and at most.
+#[derive(Serialize, Clone, Copy, Default)]
+pub struct StageTime {
+    pub count: u64,
+    pub total_ms: f64,
+    pub max_ms: f64,
+}
+
+impl StageTime {
+    fn add(&mut self, other: &StageTime) {
+        self.count += other.count;
+        self.total_ms += other.total_ms;
+        self.max_ms = self.max_ms.max(other.max_ms);
+    }
+}
+
+/// Time spent by rule and stage since the process started.
+pub type Timings = BTreeMap<String, BTreeMap<Stage, StageTime>>;
+
+/// What the threads that finished timing steps, or flushed, recorded.
+static TIMINGS: Mutex<Timings> = Mutex::new(BTreeMap::new());
+
+thread_local! {
+    /// What this thread recorded since it last flushed, so sort workers don't contend for a
+    /// lock on every step.
+    static RECORDED: RefCell<Timings> = const { RefCell::new(BTreeMap::new()) };
+}
+
+/// The rule name work on a file is recorded for when it isn't done for any one rule, such as
+/// looking at the file itself.
+pub const NO_RULE: &str = "";
+
+/// Runs `step` and adds the time it took to `rule`'s `stage`.
+pub fn time<T>(rule: &str, stage: Stage, step: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = step();
+    record(rule, stage, started.elapsed());
+    result
+}
+
+/// Adds `elapsed` to `rule`'s `stage` on this thread; [`flush`] makes it part of the
+/// [`snapshot`].
+pub fn record(rule: &str, stage: Stage, elapsed: Duration) {
+    let ms = elapsed.as_secs_f64() * 1000.0;
+    let time = StageTime {
+        count: 1,
+        total_ms: ms,
+        max_ms: ms,
+    };
+    RECORDED.with_borrow_mut(|recorded| recorded.entry(rule.to_string()).or_default().entry(stage).or_default().add(&time));
+}
+
+/// Adds what this thread recorded to the times of the process, as every sort worker does
+/// when it is done.
+pub fn flush() {
+    let recorded = RECORDED.take();
+    if recorded.is_empty() {
+        return;
+    }
+    let mut timings = TIMINGS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    for (rule, stages) in recorded {
+        let merged = timings.entry(rule).or_default();
+        for (stage, time) in stages {
+            merged.entry(stage).or_default().add(&time);
+        }
+    }
+}
+
+/// The times recorded so far, with those of this thread.
+pub fn snapshot() -> Timings {
+    flush();
+    TIMINGS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+}
@@ -0,0 +1,420 @@
+use crate::duplicates;
+use crate::safety::Protection;
+use crate::throttle::{self, Throttled};
+use crate::xattr;
+use organizer_engine::{FileSystem, MemoryFileSystem, Metadata};
+use std::fs::{self, File, FileTimes};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+const ATTEMPTS: u32 = 5;
+
+/// How files are placed and removed, from `network_share`, `verify_copies`, `trash` and
+/// `protected` in `rules.json`.
+#[derive(Clone, Default)]
+pub struct Settings {
+    /// Whether the sorted folder is on a network share.
+    pub network: bool,
+    /// Whether copies are read back and compared with their source by checksum.
+    pub verify: bool,
+    /// Whether files are put in the trash instead of deleted.
+    pub trash: bool,
+    pub protection: Protection,
+}
+
+impl Settings {
+    pub fn new(network: bool, verify: bool, trash: bool, protected: &[String]) -> Self {
+        Settings {
+            network,
+            verify,
+            trash,
+            protection: Protection::new(protected),
+        }
+    }
+}
+
+/// The disk as sorts change it: moves copy across drives and onto network shares as
+/// [`move_file`] does, and copies are throttled and verified as [`copy`] does.
+pub struct Disk {
+    pub settings: Settings,
+}
+
+/// A [`FileSystem`] that tells the checksum a file it placed was verified with, so the move
+/// index can keep it.
+pub trait Verifying: FileSystem {
+    /// Moves like [`FileSystem::rename`], and returns the checksum the file was verified with
+    /// on the way, if it was.
+    fn rename_verified(&self, from: &Path, to: &Path) -> io::Result<Option<String>> {
+        self.rename(from, to).map(|()| None)
+    }
+
+    /// Copies like [`FileSystem::copy`], and returns the checksum the copy was verified with,
+    /// if it was.
+    fn copy_verified(&self, from: &Path, to: &Path) -> io::Result<Option<String>> {
+        self.copy(from, to).map(|()| None)
+    }
+}
+
+impl Verifying for Disk {
+    fn rename_verified(&self, from: &Path, to: &Path) -> io::Result<Option<String>> {
+        move_file(from, to, &self.settings)
+    }
+
+    fn copy_verified(&self, from: &Path, to: &Path) -> io::Result<Option<String>> {
+        copy(from, to, &self.settings).map(|copied| copied.checksum)
+    }
+}
+
+impl Verifying for MemoryFileSystem {}
+
+impl FileSystem for Disk {
+    fn list(&self, folder: &Path) -> io::Result<Vec<PathBuf>> {
+        organizer_engine::Disk.list(folder)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        fs::symlink_metadata(path).is_ok()
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        move_file(from, to, &self.settings).map(|_| ())
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        copy(from, to, &self.settings).map(|_| ())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+        fs::hard_link(original, link)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        organizer_engine::Disk.symlink_metadata(path)
+    }
+}
+
+/// Moves `source` to `target`.
+///
+/// Across filesystems, where a rename is impossible, and on network shares, where
+/// renames across folders are not always atomic and handles go stale when the server
+/// drops a connection, the file is copied into a temporary file inside the destination
+/// folder, checked by size rather than inode, and only then renamed into place before
+/// the source is removed. Returns the checksum the copy was verified with, when copies are
+/// verified and the file was copied.
+pub fn move_file(source: &Path, target: &Path, settings: &Settings) -> io::Result<Option<String>> {
+    settings.protection.check(source)?;
+    // Bundles are folders; they are renamed as a whole.
+    if source.is_dir() {
+        return fs::rename(source, target).map(|()| None);
+    }
+    if !settings.network {
+        match fs::rename(source, target) {
+            Err(e) if crosses_devices(&e) => debug!("{:?} is on another filesystem, copying", target),
+            result => return result.map(|()| None),
+        }
+    }
+    
+    let size = retry(|| fs::metadata(source))?.len();
+    let partial = target.with_file_name(format!(".{}.part", target.file_name().unwrap().to_string_lossy()));
+    let copied = retry(|| {
+        let mut file = File::create(&partial)?;
+        let mut reader = Hashing::new(Throttled(File::open(source)?));
+        let copied = io::copy(&mut reader, &mut file)?;
+        file.sync_all()?;
+        Ok((copied, reader.hasher.finalize()))
+    })
+    .and_then(|(copied, checksum)| {
+        if settings.verify {
+            check_copy(&partial, target, checksum)?;
+        }
+        Ok((copied, checksum))
+    });
+    let checksum = match copied {
+        Ok((copied, checksum)) if copied == size => checksum,
+        Ok((copied, _)) => {
+            let _ = fs::remove_file(&partial);
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Copied {} of {} bytes to {:?}", copied, size, target),
+            ));
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            return Err(e);
+        }
+    };
+    
+    if let Err(e) = preserve_metadata(source, &partial) {
+        warn!("Failed to keep the permissions, times or attributes of {:?}: {}", source, e);
+    }
+    retry(|| fs::rename(&partial, target))?;
+    if retry(|| fs::metadata(target))?.len() != size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{:?} changed size while it was placed", target),
+        ));
+    }
+    retry(|| fs::remove_file(source))?;
+    Ok(settings.verify.then(|| checksum.to_hex().to_string()))
+}
+
+/// What [`copy`] wrote.
+pub struct Copied {
+    pub bytes: u64,
+    /// The checksum the copy was verified with, when copies are verified.
+    pub checksum: Option<String>,
+}
+
+/// Copies `source` to `target` like `throttle::copy`, with its times and attributes, and
+/// reads the copy back to compare it with the source when copies are verified. A copy that
+/// differs is removed.
+pub fn copy(source: &Path, target: &Path, settings: &Settings) -> io::Result<Copied> {
+    let copied = if settings.verify {
+        verified_copy(source, target)?
+    } else {
+        Copied {
+            bytes: throttle::copy(source, target)?,
+            checksum: None,
+        }
+    };
+    if let Err(e) = preserve_metadata(source, target) {
+        warn!("Failed to keep the permissions, times or attributes of {:?}: {}", source, e);
+    }
+    Ok(copied)
+}
+
+fn verified_copy(source: &Path, target: &Path) -> io::Result<Copied> {
+    let mut reader = Hashing::new(Throttled(File::open(source)?));
+    let checked = write_copy(&mut reader, target).and_then(|bytes| {
+        let checksum = reader.hasher.finalize();
+        check_copy(target, target, checksum)?;
+        Ok(Copied {
+            bytes,
+            checksum: Some(checksum.to_hex().to_string()),
+        })
+    });
+    if checked.is_err() {
+        let _ = fs::remove_file(target);
+    }
+    checked
+}
+
+/// Gives a copy the extended attributes, times, permissions and, when running as root, the
+/// owner of its source, as a rename would have kept them.
+fn preserve_metadata(source: &Path, target: &Path) -> io::Result<()> {
+    let metadata = fs::metadata(source)?;
+    #[cfg(unix)]
+    {
+        // Copies of read-only files already are read-only, which keeps attributes from being set.
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(target)?.permissions().mode();
+        if mode & 0o200 == 0 {
+            fs::set_permissions(target, fs::Permissions::from_mode(mode | 0o200))?;
+        }
+    }
+    // Drives such as FAT-formatted sticks have no extended attributes; the file is still placed.
+    if let Err(e) = xattr::copy_all(source, target) {
+        debug!("Extended attributes of {:?} were not copied: {}", source, e);
+    }
+    let mut times = FileTimes::new().set_modified(metadata.modified()?);
+    if let Ok(accessed) = metadata.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    open_attributes(target)?.set_times(times)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if unsafe { libc::geteuid() } == 0 {
+            std::os::unix::fs::chown(target, Some(metadata.uid()), Some(metadata.gid()))?;
+        }
+    }
+    fs::set_permissions(target, metadata.permissions())
+}
+
+/// Opens a file only to change its times, which works for read-only files too.
+fn open_attributes(path: &Path) -> io::Result<File> {
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        use windows_sys::Win32::Storage::FileSystem::FILE_WRITE_ATTRIBUTES;
+        File::options().access_mode(FILE_WRITE_ATTRIBUTES).open(path)
+    }
+    #[cfg(not(windows))]
+    {
+        File::open(path)
+    }
+}
+
+/// Reads the copy at `written` back and compares it with the checksum of its source.
+fn check_copy(written: &Path, target: &Path, checksum: blake3::Hash) -> io::Result<()> {
+    let written = retry(|| duplicates::hash_file(written))?;
+    if written != checksum {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Copy to {:?} doesn't match the original, the original was kept", target),
+        ));
+    }
+    Ok(())
+}
+
+/// A reader that hashes everything read through it.
+struct Hashing<R> {
+    inner: R,
+    hasher: blake3::Hasher,
+}
+
+impl<R> Hashing<R> {
+    fn new(inner: R) -> Self {
+        Hashing {
+            inner,
+            hasher: blake3::Hasher::new(),
+        }
+    }
+}
+
+impl<R: Read> Read for Hashing<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Leaves a symlink at `original` pointing to `moved`, relative so that it survives
+/// the whole tree being moved or mounted elsewhere.
+pub fn link_back(original: &Path, moved: &Path) -> io::Result<()> {
+    let folder = original.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let target = relative_path(&folder.canonicalize()?, &moved.canonicalize()?);
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(target, original)
+    }
+    #[cfg(windows)]
+    {
+        if moved.is_dir() {
+            std::os::windows::fs::symlink_dir(target, original)
+        } else {
+            std::os::windows::fs::symlink_file(target, original)
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = target;
+        Err(io::Error::new(io::ErrorKind::Unsupported, "symlinks are not supported on this platform"))
+    }
+}
+
+/// Moves the symlink `link` to `target`; a relative link is recreated relative to its new
+/// folder, so it keeps pointing at the same file.
+pub fn move_link(link: &Path, target: &Path) -> io::Result<()> {
+    if fs::read_link(link)?.is_absolute() {
+        return fs::rename(link, target);
+    }
+    link_back(target, &link.canonicalize()?)?;
+    remove_link(link)
+}
+
+/// Removes a symlink; on Windows, links to folders are removed like folders.
+pub fn remove_link(link: &Path) -> io::Result<()> {
+    match fs::remove_file(link) {
+        Err(e) if cfg!(windows) && link.is_dir() => fs::remove_dir(link).map_err(|_| e),
+        result => result,
+    }
+}
+
+/// `target` as seen from the folder `base`, e.g. `../Images/photo.jpg`; both must be absolute.
+/// Paths without a common root, such as on different Windows drives, stay absolute.
+fn relative_path(base: &Path, target: &Path) -> PathBuf {
+    let common = base.components().zip(target.components()).take_while(|(a, b)| a == b).count();
+    if common == 0 {
+        return target.to_path_buf();
+    }
+    let mut relative: PathBuf = base.components().skip(common).map(|_| Component::ParentDir).collect();
+    relative.extend(target.components().skip(common));
+    relative
+}
+
+/// Retries `operation` with growing delays while it fails with a stale network handle.
+fn retry<T>(mut operation: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut attempt = 1;
+    loop {
+        match operation() {
+            Err(e) if attempt < ATTEMPTS && is_stale(&e) => {
+                warn!("Network filesystem error, retrying: {}", e);
+                thread::sleep(Duration::from_millis(200 * 2u64.pow(attempt)));
+                attempt += 1;
+            }
+            result => {
+                if attempt > 1 {
+                    debug!("Succeeded after {} attempts", attempt);
+                }
+                return result;
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+const CROSSES_DEVICES: i32 = libc::EXDEV;
+#[cfg(windows)]
+const CROSSES_DEVICES: i32 = windows_sys::Win32::Foundation::ERROR_NOT_SAME_DEVICE as i32;
+#[cfg(not(any(unix, windows)))]
+const CROSSES_DEVICES: i32 = -1;
+
+pub fn crosses_devices(error: &io::Error) -> bool {
+    error.raw_os_error() == Some(CROSSES_DEVICES)
+}
+
+#[cfg(unix)]
+const STALE: &[i32] = &[libc::ESTALE, libc::EIO];
+#[cfg(windows)]
+const STALE: &[i32] = {
+    use windows_sys::Win32::Foundation::{ERROR_NETNAME_DELETED, ERROR_SEM_TIMEOUT, ERROR_UNEXP_NET_ERR};
+    &[ERROR_UNEXP_NET_ERR as i32, ERROR_NETNAME_DELETED as i32, ERROR_SEM_TIMEOUT as i32]
+};
+#[cfg(not(any(unix, windows)))]
+const STALE: &[i32] = &[];
+
+fn is_stale(error: &io::Error) -> bool {
+    error.raw_os_error().is_some_and(|code| STALE.contains(&code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    #[test]
+    fn places_files_in_memory_without_a_checksum() {
+        let fs = MemoryFileSystem::new();
+        fs.add_file("/inbox/report.pdf", "report").unwrap();
+        fs.create_dir_all(Path::new("/inbox/Documents")).unwrap();
+        let fs: &(dyn Verifying + Sync) = &fs;
+        assert_eq!(fs.copy_verified(Path::new("/inbox/report.pdf"), Path::new("/inbox/Documents/copy.pdf")).unwrap(), None);
+        assert_eq!(fs.rename_verified(Path::new("/inbox/report.pdf"), Path::new("/inbox/Documents/report.pdf")).unwrap(), None);
+        assert!(fs.exists(Path::new("/inbox/Documents/copy.pdf")));
+        assert!(!fs.exists(Path::new("/inbox/report.pdf")));
+    }
+
+    #[test]
+    fn moves_files_on_disk_through_the_file_system_of_the_sort() {
+        let folder = TestDir::new("transfer", "disk");
+        let disk = Disk { settings: Settings::default() };
+        disk.create_dir_all(&folder.join("Documents")).unwrap();
+        fs::write(folder.join("report.pdf"), "report").unwrap();
+        disk.rename(&folder.join("report.pdf"), &folder.join("Documents/report.pdf")).unwrap();
+        assert_eq!(disk.list(&folder.join("Documents")).unwrap(), [folder.join("Documents/report.pdf")]);
+        assert!(!disk.exists(&folder.join("report.pdf")));
+    }
+}
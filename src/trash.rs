@@ -0,0 +1,234 @@
+use crate::transfer;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// A file put in the trash, with where it went, for the move index to record.
+pub struct Trashed {
+    pub original: PathBuf,
+    /// Where the file is in the trash; the original path on Windows, whose Recycle Bin doesn't
+    /// say.
+    pub trashed: PathBuf,
+}
+
+/// Deletes the file at `path`, or puts it in the trash when `trash` is set and returns where
+/// it went.
+pub fn remove(path: &Path, settings: &transfer::Settings) -> io::Result<Option<Trashed>> {
+    settings.protection.check(path)?;
+    if !settings.trash {
+        fs::remove_file(path)?;
+        return Ok(None);
+    }
+    let trashed = put_in_trash(path)?;
+    info!(file = ?path, "Put file in the trash");
+    Ok(Some(Trashed {
+        original: path.to_path_buf(),
+        trashed: trashed.unwrap_or_else(|| path.to_path_buf()),
+    }))
+}
+
+/// Puts a different file at `target` in the trash before `source` replaces it, when `trash`
+/// is set; otherwise it is simply replaced.
+pub fn make_room(target: &Path, source: &Path, settings: &transfer::Settings) -> io::Result<Option<Trashed>> {
+    // On case-insensitive drives, renaming `a.JPG` to `a.jpg` finds the file itself there.
+    if !settings.trash || fs::symlink_metadata(target).is_err() || same_file(target, source) {
+        return Ok(None);
+    }
+    remove(target, settings)
+}
+
+/// Whether `a` and `b` are the same file, such as two spellings of its name on a
+/// case-insensitive drive; names that only differ in case are different files elsewhere.
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (fs::symlink_metadata(a), fs::symlink_metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// Whether `a` and `b` are the same file; Windows resolves both to the name the file has on
+/// the drive.
+#[cfg(not(unix))]
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Moves `path` into the trash of the freedesktop.org specification: the home trash, or
+/// for files on other drives the trash at the top of that drive.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn put_in_trash(path: &Path) -> io::Result<Option<PathBuf>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = absolute(path)?;
+    let home_trash = crate::paths::data_home()
+        .ok_or_else(|| io::Error::other("no home folder for the trash"))?
+        .join("Trash");
+    match trash_into(&path, &home_trash, &path) {
+        Err(e) if crate::transfer::crosses_devices(&e) => {}
+        result => return result.map(Some),
+    }
+    let top = top_dir(&path)?;
+    let uid = unsafe { libc::getuid() };
+    // A `.Trash` shared by all users is only used when it is a sticky folder, never a link.
+    let shared = top.join(".Trash");
+    let trash = match fs::symlink_metadata(&shared) {
+        Ok(metadata) if metadata.is_dir() && metadata.permissions().mode() & 0o1000 != 0 => shared.join(uid.to_string()),
+        _ => top.join(format!(".Trash-{}", uid)),
+    };
+    let relative = path.strip_prefix(&top).unwrap_or(&path).to_path_buf();
+    trash_into(&path, &trash, &relative).map(Some)
+}
+
+/// Moves `path` into `trash`, with the `.trashinfo` file that lets file managers restore it
+/// to `original`.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn trash_into(path: &Path, trash: &Path, original: &Path) -> io::Result<PathBuf> {
+    use std::io::Write;
+
+    let (files, info) = (trash.join("files"), trash.join("info"));
+    fs::create_dir_all(&files)?;
+    fs::create_dir_all(&info)?;
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::other("nothing to put in the trash"))?
+        .to_string_lossy();
+    // The info file is created first and exclusively, so two files of the same name can't
+    // take the same place.
+    let mut counter = 1;
+    let (trashed, info_file) = loop {
+        let candidate = match counter {
+            1 => name.to_string(),
+            _ => format!("{}.{}", name, counter),
+        };
+        let info_file = info.join(format!("{}.trashinfo", candidate));
+        match fs::OpenOptions::new().write(true).create_new(true).open(&info_file) {
+            Ok(mut file) => {
+                let deleted = chrono::Local::now().format("%Y-%m-%dT%H:%M:%S");
+                write!(file, "[Trash Info]\nPath={}\nDeletionDate={}\n", percent_encode(original), deleted)?;
+                break (files.join(candidate), info_file);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => counter += 1,
+            Err(e) => return Err(e),
+        }
+    };
+    if let Err(e) = fs::rename(path, &trashed) {
+        let _ = fs::remove_file(info_file);
+        return Err(e);
+    }
+    Ok(trashed)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn percent_encode(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut encoded = String::new();
+    for byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => encoded.push(*byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Moves `path` into the Trash of the user, or of the drive it is on.
+#[cfg(target_os = "macos")]
+fn put_in_trash(path: &Path) -> io::Result<Option<PathBuf>> {
+    let path = absolute(path)?;
+    let home_trash = crate::paths::home_dir()
+        .ok_or_else(|| io::Error::other("no home folder for the trash"))?
+        .join(".Trash");
+    match trash_into(&path, &home_trash) {
+        Err(e) if crate::transfer::crosses_devices(&e) => {}
+        result => return result.map(Some),
+    }
+    let uid = unsafe { libc::getuid() };
+    trash_into(&path, &top_dir(&path)?.join(".Trashes").join(uid.to_string())).map(Some)
+}
+
+/// Moves `path` into `trash`, numbering it like the Finder when the name is taken.
+#[cfg(target_os = "macos")]
+fn trash_into(path: &Path, trash: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(trash)?;
+    let name = path.file_name().ok_or_else(|| io::Error::other("nothing to put in the trash"))?;
+    let mut trashed = trash.join(name);
+    let mut counter = 2;
+    while fs::symlink_metadata(&trashed).is_ok() {
+        let stem = path.file_stem().unwrap_or(name).to_string_lossy();
+        trashed = match path.extension() {
+            Some(extension) => trash.join(format!("{} {}.{}", stem, counter, extension.to_string_lossy())),
+            None => trash.join(format!("{} {}", stem, counter)),
+        };
+        counter += 1;
+    }
+    fs::rename(path, &trashed)?;
+    Ok(trashed)
+}
+
+/// `path` from the root, without following it when it is a link.
+#[cfg(unix)]
+fn absolute(path: &Path) -> io::Result<PathBuf> {
+    let name = path.file_name().ok_or_else(|| io::Error::other("nothing to put in the trash"))?;
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    Ok(fs::canonicalize(parent)?.join(name))
+}
+
+/// The top folder of the drive `path` is on.
+#[cfg(unix)]
+fn top_dir(path: &Path) -> io::Result<PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+
+    let device = fs::symlink_metadata(path)?.dev();
+    let mut top = path.parent().unwrap_or(path);
+    while let Some(parent) = top.parent() {
+        if fs::metadata(parent)?.dev() != device {
+            break;
+        }
+        top = parent;
+    }
+    Ok(top.to_path_buf())
+}
+
+/// Sends `path` to the Recycle Bin, which doesn't say where it put it.
+#[cfg(windows)]
+fn put_in_trash(path: &Path) -> io::Result<Option<PathBuf>> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::UI::Shell::{SHFileOperationW, FOF_ALLOWUNDO, FOF_NOCONFIRMATION, FOF_NOERRORUI, FOF_SILENT, FO_DELETE, SHFILEOPSTRUCTW};
+
+    // The shell doesn't take `\\?\` paths as `canonicalize` returns them, and the list of
+    // files ends with an empty name.
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+    let from: Vec<u16> = path.as_os_str().encode_wide().chain([0, 0]).collect();
+    let mut operation = SHFILEOPSTRUCTW {
+        hwnd: 0,
+        wFunc: FO_DELETE,
+        pFrom: from.as_ptr(),
+        pTo: std::ptr::null(),
+        fFlags: (FOF_ALLOWUNDO | FOF_NOCONFIRMATION | FOF_NOERRORUI | FOF_SILENT) as u16,
+        fAnyOperationsAborted: 0,
+        hNameMappings: std::ptr::null_mut(),
+        lpszProgressTitle: std::ptr::null(),
+    };
+    match unsafe { SHFileOperationW(&mut operation) } {
+        0 if operation.fAnyOperationsAborted == 0 => Ok(None),
+        0 => Err(io::Error::other("putting the file in the Recycle Bin was cancelled")),
+        code => Err(io::Error::other(format!("failed to put the file in the Recycle Bin (error {})", code))),
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn put_in_trash(_: &Path) -> io::Result<Option<PathBuf>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "there is no trash on this system"))
+}
@@ -0,0 +1,139 @@
+use std::io;
+use std::path::Path;
+
+/// Shows what the running daemon sorting `root` is doing until `q` is pressed, with keys to
+/// pause it, resume it and start a sort. Everything goes through the daemon's control socket.
+#[cfg(feature = "tui")]
+pub fn run(root: &Path) -> io::Result<()> {
+    screen::run(root)
+}
+
+#[cfg(not(feature = "tui"))]
+pub fn run(_root: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "The dashboard is not enabled in this build"))
+}
+
+#[cfg(feature = "tui")]
+mod screen {
+    use crate::control::{self, Activity, ActivityEntry, ControlCommand};
+    use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+    use ratatui::layout::{Constraint, Layout};
+    use ratatui::style::{Color, Style};
+    use ratatui::text::Line;
+    use ratatui::widgets::{Block, List, ListItem, Paragraph};
+    use ratatui::{DefaultTerminal, Frame};
+    use std::io;
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+
+    /// How often the activity is asked for.
+    const REFRESH: Duration = Duration::from_secs(1);
+
+    pub fn run(root: &Path) -> io::Result<()> {
+        // Fails before the terminal is taken over when no daemon answers.
+        let activity = fetch(root)?;
+        let mut terminal = ratatui::init();
+        let result = show(&mut terminal, root, activity);
+        ratatui::restore();
+        result
+    }
+
+    fn fetch(root: &Path) -> io::Result<Activity> {
+        let response = control::send(root, ControlCommand::Activity)?;
+        serde_json::from_str(&response).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Unexpected answer from the daemon: {}", e)))
+    }
+
+    fn show(terminal: &mut DefaultTerminal, root: &Path, mut activity: Activity) -> io::Result<()> {
+        // The answer to the last key, or why the daemon could not be reached.
+        let mut status = String::new();
+        let mut fetched = Instant::now();
+        loop {
+            terminal.draw(|frame| draw(frame, &activity, &status))?;
+            if event::poll(REFRESH.saturating_sub(fetched.elapsed()))? {
+                let Event::Key(key) = event::read()? else { continue };
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                let command = match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    KeyCode::Char('p') => ControlCommand::Pause,
+                    KeyCode::Char('r') => ControlCommand::Resume,
+                    KeyCode::Char('s') => ControlCommand::SortNow,
+                    _ => continue,
+                };
+                if matches!(command, ControlCommand::Pause) {
+                    // The daemon answers once the sort in progress has finished.
+                    status = "Pausing after the sort in progress...".to_string();
+                    terminal.draw(|frame| draw(frame, &activity, &status))?;
+                }
+                status = control::send(root, command).unwrap_or_else(|e| format!("Daemon not reachable: {}", e));
+            }
+            fetched = Instant::now();
+            match fetch(root) {
+                Ok(latest) => activity = latest,
+                Err(e) => status = format!("Daemon not reachable: {}", e),
+            }
+        }
+    }
+
+    fn draw(frame: &mut Frame, activity: &Activity, status: &str) {
+        let [header, body, footer] = Layout::vertical([Constraint::Length(1), Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [left, right] = Layout::horizontal([Constraint::Percentage(35), Constraint::Percentage(65)]).areas(body);
+        let [waiting, rules] = Layout::vertical([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(left);
+        let [recent, errors] = Layout::vertical([Constraint::Percentage(65), Constraint::Percentage(35)]).areas(right);
+
+        let state = match (activity.paused, activity.queued) {
+            (true, _) => "Paused".to_string(),
+            (false, 0) => "Waiting for changes".to_string(),
+            (false, queued) => format!("Sorting, {} files to go", queued),
+        };
+        let clutter = match &activity.clutter {
+            Some(trend) => format!("  ·  clutter {}/100{}", trend.current.score, change(trend.week, "this week")),
+            None => String::new(),
+        };
+        frame.render_widget(Paragraph::new(format!("Organizer daemon: {}{}", state, clutter)), header);
+
+        let names = activity.next.iter().map(|file| ListItem::new(file.display().to_string()));
+        frame.render_widget(list(format!("Waiting ({})", activity.queued), names), waiting);
+
+        let mut counts: Vec<(&String, &u64)> = activity.rules.iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(a.1));
+        let counts = counts.into_iter().map(|(rule, count)| ListItem::new(format!("{:>6}  {}", count, rule)));
+        frame.render_widget(list("Files by rule".to_string(), counts), rules);
+
+        let results = activity.recent.iter().map(|entry| {
+            let line = match &entry.destination {
+                Some(destination) => format!("{} -> {}", entry.file.display(), destination.display()),
+                None => entry.file.display().to_string(),
+            };
+            ListItem::new(format!("{} {:<11} {}", entry.time.format("%H:%M:%S"), entry.result, line)).style(style(entry))
+        });
+        frame.render_widget(list("Recent".to_string(), results), recent);
+
+        let failures = activity.errors.iter().map(|entry| {
+            let error = entry.error.as_deref().unwrap_or_default();
+            ListItem::new(format!("{} {}: {}", entry.time.format("%H:%M:%S"), entry.file.display(), error)).style(style(entry))
+        });
+        frame.render_widget(list(format!("Errors ({})", activity.errors.len()), failures), errors);
+
+        let keys = Line::from(format!("[p] pause  [r] resume  [s] sort now  [q] quit  {}", status));
+        frame.render_widget(Paragraph::new(keys), footer);
+    }
+
+    /// E.g. `, -4 this week`; nothing without an earlier score to compare with.
+    fn change(change: Option<i64>, since: &str) -> String {
+        change.map(|change| format!(", {:+} {}", change, since)).unwrap_or_default()
+    }
+
+    fn list<'a>(title: String, items: impl Iterator<Item = ListItem<'a>>) -> List<'a> {
+        List::new(items).block(Block::bordered().title(title))
+    }
+
+    fn style(entry: &ActivityEntry) -> Style {
+        match entry.result.as_str() {
+            "failed" => Style::default().fg(Color::Red),
+            "quarantined" => Style::default().fg(Color::Yellow),
+            _ => Style::default(),
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use crate::paths;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Name that files routed to the `unmatched` folder are reported and recorded under.
+pub const UNMATCHED_RULE: &str = "unmatched";
+
+/// What happens to files that no rule claims, from `rules.json`.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct UnmatchedSettings {
+    /// Folder that unmatched files are moved into, e.g. `Unsorted`, relative to the sorted directory.
+    pub folder: Option<String>,
+    /// File that lists the files left unmatched by the last sort, relative to the sorted directory.
+    pub report: Option<String>,
+}
+
+/// Collects the files a sort left unmatched and writes them to the configured report.
+#[derive(Default)]
+pub struct UnmatchedReport {
+    path: Option<PathBuf>,
+    files: Vec<PathBuf>,
+}
+
+impl UnmatchedReport {
+    pub fn configure(&mut self, settings: &UnmatchedSettings, directory: &Path) {
+        self.path = settings.report.as_deref().map(|report| directory.join(paths::resolve_path(report)));
+        self.files.clear();
+    }
+
+    /// Whether `file` is the report itself, which is never sorted.
+    pub fn is_report(&self, file: &Path) -> bool {
+        self.path.as_deref() == Some(file)
+    }
+
+    pub fn file_unmatched(&mut self, file: &Path) {
+        if self.path.is_some() {
+            self.files.push(file.to_path_buf());
+        }
+    }
+
+    /// Replaces the report with the files of this sort, one path per line.
+    pub fn write(&mut self) -> io::Result<()> {
+        let Some(path) = &self.path else { return Ok(()) };
+        let mut report = fs::File::create(path)?;
+        for file in self.files.drain(..) {
+            writeln!(report, "{}", file.display())?;
+        }
+        Ok(())
+    }
+}
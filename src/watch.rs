@@ -0,0 +1,995 @@
+use crate::scan::RepositoryPolicy;
+use clap::ValueEnum;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+use std::time::Instant;
+
+#[cfg(target_os = "linux")]
+use self::linux as platform;
+#[cfg(target_os = "macos")]
+use self::macos as platform;
+#[cfg(windows)]
+use self::windows as platform;
+
+/// Events arrive in bursts while files are copied; wait for them to settle before sorting.
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+const SETTLE: Duration = Duration::from_secs(1);
+
+/// How the daemon notices new files between its regular scans.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum WatchBackend {
+    /// fanotify as root and inotify otherwise on Linux, FSEvents on macOS, the USN journal or ReadDirectoryChangesW on Windows
+    Auto,
+    Inotify,
+    /// Watches whole filesystems without per-folder limits; needs root
+    Fanotify,
+    /// Reads the NTFS change journal of the volume; needs administrator rights
+    Usn,
+    ReadDirectoryChanges,
+    Fsevents,
+    /// Only scan every `--interval` seconds
+    Poll,
+}
+
+/// Wakes the daemon early when files appear in the watched folders.
+pub struct Watcher {
+    #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+    inner: Option<platform::Backend>,
+}
+
+impl Watcher {
+    pub fn new(root: &Path, recursive: bool, excluded: Vec<PathBuf>, repositories: RepositoryPolicy, backend: WatchBackend) -> Watcher {
+        let tree = Tree {
+            root: root.to_path_buf(),
+            recursive,
+            excluded,
+            repositories,
+        };
+        #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+        {
+            Watcher {
+                inner: platform::Backend::new(tree, backend),
+            }
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+        {
+            let _ = (tree, backend);
+            Watcher {}
+        }
+    }
+
+    /// Whether changes are reported as they happen, rather than only found by the next scan.
+    pub fn is_active(&self) -> bool {
+        #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+        {
+            self.inner.is_some()
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+        {
+            false
+        }
+    }
+
+    /// Blocks until something changed in the watched folders or `timeout` elapsed, and
+    /// returns whether something changed.
+    pub fn wait(&mut self, timeout: Duration) -> bool {
+        #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+        if let Some(inner) = self.inner.as_mut() {
+            let deadline = Instant::now() + timeout;
+            loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return false;
+                }
+                if inner.wait(remaining) {
+                    std::thread::sleep(SETTLE);
+                    inner.drain();
+                    return true;
+                }
+            }
+        }
+        std::thread::sleep(timeout);
+        false
+    }
+}
+
+/// The folders a watcher covers.
+#[derive(Clone)]
+#[cfg_attr(not(any(target_os = "linux", target_os = "macos", windows)), allow(dead_code))]
+struct Tree {
+    root: PathBuf,
+    recursive: bool,
+    excluded: Vec<PathBuf>,
+    repositories: RepositoryPolicy,
+}
+
+#[cfg_attr(not(any(target_os = "linux", windows)), allow(dead_code))]
+impl Tree {
+    fn folders_below(&self, folder: &Path) -> Vec<PathBuf> {
+        crate::scan::collect_folders(folder, self.recursive, &self.excluded, self.repositories)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{Tree, WatchBackend};
+    use crate::scan;
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::{HashMap, HashSet};
+    use std::ffi::CString;
+    use std::fs;
+    use std::hash::{Hash, Hasher};
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::MetadataExt;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+    use tracing::{debug, error, info, warn};
+
+    /// How often folders that could not be watched are checked for changes.
+    const POLL_STEP: Duration = Duration::from_secs(5);
+
+    // Not all of these are exported by every libc release.
+    const FAN_CLOEXEC: libc::c_uint = 0x1;
+    const FAN_NONBLOCK: libc::c_uint = 0x2;
+    const FAN_REPORT_DIR_FID: libc::c_uint = 0x400;
+    const FAN_MARK_ADD: libc::c_uint = 0x1;
+    const FAN_MARK_FILESYSTEM: libc::c_uint = 0x100;
+    const FAN_CLOSE_WRITE: u64 = 0x8;
+    const FAN_MOVED_TO: u64 = 0x80;
+    const FAN_CREATE: u64 = 0x100;
+    const FAN_Q_OVERFLOW: u64 = 0x4000;
+    const FAN_ONDIR: u64 = 0x4000_0000;
+    const FAN_EVENT_METADATA_LEN: usize = 24;
+
+    pub enum Backend {
+        Inotify(Inotify),
+        Fanotify(Fanotify),
+    }
+
+    impl Backend {
+        pub fn new(tree: Tree, backend: WatchBackend) -> Option<Backend> {
+            let use_fanotify = match backend {
+                WatchBackend::Poll => return None,
+                WatchBackend::Fanotify => true,
+                // Marking a whole filesystem needs CAP_SYS_ADMIN.
+                WatchBackend::Auto => tree.recursive && unsafe { libc::geteuid() } == 0,
+                _ => false,
+            };
+            if use_fanotify {
+                match Fanotify::new(tree.clone()) {
+                    Ok(fanotify) => return Some(Backend::Fanotify(fanotify)),
+                    Err(e) => warn!("fanotify is unavailable, falling back to inotify: {}", e),
+                }
+            }
+            let root = tree.root.clone();
+            match Inotify::new(tree) {
+                Ok(inotify) => Some(Backend::Inotify(inotify)),
+                Err(e) => {
+                    error!("Failed to watch {:?}, only scanning periodically: {}", root, e);
+                    None
+                }
+            }
+        }
+
+        /// Waits up to `timeout` for events and returns whether any of them was relevant.
+        pub fn wait(&mut self, timeout: Duration) -> bool {
+            match self {
+                Backend::Inotify(inotify) => inotify.wait(timeout),
+                Backend::Fanotify(fanotify) => fanotify.wait(timeout),
+            }
+        }
+
+        pub fn drain(&mut self) {
+            match self {
+                Backend::Inotify(inotify) => inotify.read_events(),
+                Backend::Fanotify(fanotify) => fanotify.read_events(),
+            };
+        }
+    }
+
+    /// A subtree that could not be watched and is compared against its last state instead.
+    struct PolledTree {
+        root: PathBuf,
+        fingerprint: u64,
+    }
+
+    impl PolledTree {
+        /// Folder modification times change whenever entries are added, removed or renamed.
+        fn fingerprint(folders: &[PathBuf]) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            for folder in folders {
+                folder.hash(&mut hasher);
+                if let Ok(modified) = fs::metadata(folder).and_then(|metadata| metadata.modified()) {
+                    modified.hash(&mut hasher);
+                }
+            }
+            hasher.finish()
+        }
+    }
+
+    pub struct Inotify {
+        fd: OwnedFd,
+        tree: Tree,
+        watches: HashMap<i32, PathBuf>,
+        polled: Vec<PolledTree>,
+        limit_reported: bool,
+    }
+
+    impl Inotify {
+        fn new(tree: Tree) -> io::Result<Inotify> {
+            let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut inotify = Inotify {
+                fd: unsafe { OwnedFd::from_raw_fd(fd) },
+                tree,
+                watches: HashMap::new(),
+                polled: Vec::new(),
+                limit_reported: false,
+            };
+            let root = inotify.tree.root.clone();
+            inotify.watch_tree(&root);
+            debug!("Watching {} folders with inotify", inotify.watches.len());
+            Ok(inotify)
+        }
+
+        fn watch_tree(&mut self, folder: &Path) {
+            let folders = self.tree.folders_below(folder);
+            for (position, folder) in folders.iter().enumerate() {
+                let Ok(path) = CString::new(folder.as_os_str().as_bytes()) else { continue };
+                let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_CREATE | libc::IN_ONLYDIR;
+                let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), mask) };
+                if wd >= 0 {
+                    self.watches.insert(wd, folder.clone());
+                    continue;
+                }
+                let error = io::Error::last_os_error();
+                if error.raw_os_error() != Some(libc::ENOSPC) {
+                    debug!("Failed to watch {:?}: {}", folder, error);
+                    continue;
+                }
+
+                // Out of watches: poll the folders that are left instead of missing their events.
+                self.report_limit();
+                for remaining in &folders[position..] {
+                    if !self.polled.iter().any(|polled| remaining.starts_with(&polled.root)) {
+                        let folders = self.tree.folders_below(remaining);
+                        self.polled.push(PolledTree {
+                            root: remaining.clone(),
+                            fingerprint: PolledTree::fingerprint(&folders),
+                        });
+                    }
+                }
+                return;
+            }
+        }
+
+        fn report_limit(&mut self) {
+            if std::mem::replace(&mut self.limit_reported, true) {
+                return;
+            }
+            let limit = fs::read_to_string("/proc/sys/fs/inotify/max_user_watches")
+                .map(|limit| limit.trim().to_string())
+                .unwrap_or_else(|_| "unknown".to_string());
+            error!(
+                "Reached the inotify limit of {} watches (fs.inotify.max_user_watches) after {} folders; \
+                 the remaining folders are polled every {} seconds. Raise the limit with \
+                 `sysctl fs.inotify.max_user_watches=524288` or run as root with `--watcher fanotify`",
+                limit,
+                self.watches.len(),
+                POLL_STEP.as_secs()
+            );
+        }
+
+        fn wait(&mut self, timeout: Duration) -> bool {
+            let step = if self.polled.is_empty() { timeout } else { timeout.min(POLL_STEP) };
+            if poll_readable(&self.fd, step) && self.read_events() {
+                return true;
+            }
+            let mut changed = false;
+            for polled in self.polled.iter_mut() {
+                let fingerprint = PolledTree::fingerprint(&self.tree.folders_below(&polled.root));
+                changed |= std::mem::replace(&mut polled.fingerprint, fingerprint) != fingerprint;
+            }
+            changed
+        }
+
+        /// Drains pending events and returns whether any of them was a new file.
+        fn read_events(&mut self) -> bool {
+            let mut changed = false;
+            let mut buffer = [0u8; 4096];
+            loop {
+                let read = unsafe { libc::read(self.fd.as_raw_fd(), buffer.as_mut_ptr().cast(), buffer.len()) };
+                if read <= 0 {
+                    return changed;
+                }
+                let mut offset = 0;
+                while offset < read as usize {
+                    let event = unsafe { std::ptr::read_unaligned(buffer[offset..].as_ptr() as *const libc::inotify_event) };
+                    let name_start = offset + std::mem::size_of::<libc::inotify_event>();
+                    let name = &buffer[name_start..name_start + event.len as usize];
+                    let name = name.split(|byte| *byte == 0).next().unwrap_or_default();
+                    offset = name_start + event.len as usize;
+
+                    if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                        warn!("inotify event queue overflowed, rescanning");
+                        changed = true;
+                    } else if event.mask & libc::IN_IGNORED != 0 {
+                        self.watches.remove(&event.wd);
+                    } else if event.mask & libc::IN_ISDIR != 0 {
+                        let folder = self.watches.get(&event.wd).map(|parent| parent.join(std::ffi::OsStr::from_bytes(name)));
+                        if let Some(folder) = folder.filter(|folder| self.tree.recursive && !scan::is_excluded(folder, &self.tree.excluded, self.tree.repositories)) {
+                            self.watch_tree(&folder);
+                            changed = true;
+                        }
+                    } else if event.mask & (libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO) != 0 {
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Watches whole filesystems and filters events to the folders of the tree by
+    /// their file handles, so large trees need no per-folder watches.
+    pub struct Fanotify {
+        fd: OwnedFd,
+        tree: Tree,
+        handles: HashSet<Vec<u8>>,
+        devices: HashSet<u64>,
+    }
+
+    impl Fanotify {
+        fn new(tree: Tree) -> io::Result<Fanotify> {
+            let fd = unsafe {
+                libc::fanotify_init(
+                    FAN_CLOEXEC | FAN_NONBLOCK | FAN_REPORT_DIR_FID,
+                    (libc::O_RDONLY | libc::O_CLOEXEC) as libc::c_uint,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut fanotify = Fanotify {
+                fd: unsafe { OwnedFd::from_raw_fd(fd) },
+                tree,
+                handles: HashSet::new(),
+                devices: HashSet::new(),
+            };
+            fanotify.refresh()?;
+            info!("Watching {} folders with fanotify", fanotify.handles.len());
+            Ok(fanotify)
+        }
+
+        /// Re-reads the folders of the tree and marks any filesystem not marked yet.
+        fn refresh(&mut self) -> io::Result<()> {
+            self.handles.clear();
+            for folder in self.tree.folders_below(&self.tree.root.clone()) {
+                let Ok(path) = CString::new(folder.as_os_str().as_bytes()) else { continue };
+                let Ok(metadata) = fs::metadata(&folder) else { continue };
+                if self.devices.insert(metadata.dev()) {
+                    let mask = FAN_CLOSE_WRITE | FAN_MOVED_TO | FAN_CREATE | FAN_ONDIR;
+                    let result = unsafe {
+                        libc::fanotify_mark(self.fd.as_raw_fd(), FAN_MARK_ADD | FAN_MARK_FILESYSTEM, mask, libc::AT_FDCWD, path.as_ptr())
+                    };
+                    if result < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                if let Some(handle) = file_handle(&path) {
+                    self.handles.insert(handle);
+                }
+            }
+            Ok(())
+        }
+
+        fn wait(&mut self, timeout: Duration) -> bool {
+            poll_readable(&self.fd, timeout) && self.read_events()
+        }
+
+        fn read_events(&mut self) -> bool {
+            let mut changed = false;
+            let mut new_folder = false;
+            let mut buffer = [0u8; 8192];
+            loop {
+                let read = unsafe { libc::read(self.fd.as_raw_fd(), buffer.as_mut_ptr().cast(), buffer.len()) };
+                if read <= 0 {
+                    break;
+                }
+                let mut offset = 0;
+                while offset + FAN_EVENT_METADATA_LEN <= read as usize {
+                    let event = unsafe { std::ptr::read_unaligned(buffer[offset..].as_ptr() as *const libc::fanotify_event_metadata) };
+                    let record = &buffer[offset + event.metadata_len as usize..offset + event.event_len as usize];
+                    offset += event.event_len as usize;
+
+                    if event.mask & FAN_Q_OVERFLOW != 0 {
+                        warn!("fanotify event queue overflowed, rescanning");
+                        changed = true;
+                        continue;
+                    }
+                    if !record_handle(record).is_some_and(|handle| self.handles.contains(handle)) {
+                        continue;
+                    }
+                    if event.mask & FAN_ONDIR != 0 {
+                        new_folder |= self.tree.recursive && event.mask & (FAN_CREATE | FAN_MOVED_TO) != 0;
+                    } else if event.mask & (FAN_CLOSE_WRITE | FAN_MOVED_TO) != 0 {
+                        changed = true;
+                    }
+                }
+            }
+            if new_folder {
+                if let Err(e) = self.refresh() {
+                    warn!("Failed to watch new folders: {}", e);
+                }
+            }
+            changed || new_folder
+        }
+    }
+
+    /// Returns the handle type and bytes identifying the folder at `path`.
+    fn file_handle(path: &CString) -> Option<Vec<u8>> {
+        #[repr(C)]
+        struct FileHandle {
+            handle_bytes: u32,
+            handle_type: i32,
+            f_handle: [u8; 128],
+        }
+        let mut handle = FileHandle {
+            handle_bytes: 128,
+            handle_type: 0,
+            f_handle: [0; 128],
+        };
+        let mut mount_id: libc::c_int = 0;
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_name_to_handle_at,
+                libc::AT_FDCWD,
+                path.as_ptr(),
+                &mut handle as *mut FileHandle,
+                &mut mount_id as *mut libc::c_int,
+                0,
+            )
+        };
+        if result < 0 {
+            return None;
+        }
+        let mut key = handle.handle_type.to_ne_bytes().to_vec();
+        key.extend_from_slice(&handle.f_handle[..handle.handle_bytes as usize]);
+        Some(key)
+    }
+
+    /// Extracts the same key as `file_handle` from an event's directory information record.
+    fn record_handle(record: &[u8]) -> Option<&[u8]> {
+        // Header (type, padding, length), then the filesystem id, then `struct file_handle`.
+        let handle = record.get(4 + 8..)?;
+        let length = u32::from_ne_bytes(handle.get(..4)?.try_into().ok()?) as usize;
+        handle.get(4..8 + length)
+    }
+
+    fn poll_readable(fd: &OwnedFd, timeout: Duration) -> bool {
+        let mut pollfd = libc::pollfd {
+            fd: fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.as_millis().min(i32::MAX as u128) as i32;
+        unsafe { libc::poll(&mut pollfd, 1, timeout) > 0 }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use super::{Tree, WatchBackend};
+    use crate::scan;
+    use std::collections::HashSet;
+    use std::io;
+    use std::mem;
+    use std::os::windows::ffi::{OsStrExt, OsStringExt};
+    use std::path::Path;
+    use std::ptr;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tracing::{debug, info, warn};
+    use windows_sys::Win32::Foundation::{CloseHandle, ERROR_JOURNAL_ENTRY_DELETED, GENERIC_READ, HANDLE, INVALID_HANDLE_VALUE, WAIT_OBJECT_0};
+    use windows_sys::Win32::Storage::FileSystem::{
+        CreateFileW, GetFileInformationByHandle, GetVolumePathNameW, ReadDirectoryChangesW, BY_HANDLE_FILE_INFORMATION,
+        FILE_ACTION_ADDED, FILE_ACTION_MODIFIED, FILE_ACTION_RENAMED_NEW_NAME, FILE_ATTRIBUTE_DIRECTORY, FILE_FLAG_BACKUP_SEMANTICS,
+        FILE_FLAG_OVERLAPPED, FILE_LIST_DIRECTORY, FILE_NOTIFY_CHANGE_DIR_NAME, FILE_NOTIFY_CHANGE_FILE_NAME,
+        FILE_NOTIFY_CHANGE_LAST_WRITE, FILE_READ_ATTRIBUTES, FILE_SHARE_DELETE, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
+    };
+    use windows_sys::Win32::System::Ioctl::{
+        FSCTL_QUERY_USN_JOURNAL, FSCTL_READ_USN_JOURNAL, READ_USN_JOURNAL_DATA_V0, USN_JOURNAL_DATA_V0, USN_REASON_CLOSE,
+        USN_REASON_DATA_EXTEND, USN_REASON_DATA_OVERWRITE, USN_REASON_FILE_CREATE, USN_REASON_RENAME_NEW_NAME,
+    };
+    use windows_sys::Win32::System::Threading::{CreateEventW, WaitForSingleObject};
+    use windows_sys::Win32::System::IO::{CancelIo, DeviceIoControl, GetOverlappedResult, OVERLAPPED};
+
+    /// How often the change journal is read; reading it is cheap, unlike scanning the tree.
+    const JOURNAL_STEP: Duration = Duration::from_secs(1);
+
+    pub enum Backend {
+        Journal(Journal),
+        DirectoryChanges(DirectoryChanges),
+    }
+
+    impl Backend {
+        pub fn new(tree: Tree, backend: WatchBackend) -> Option<Backend> {
+            match backend {
+                WatchBackend::Poll => return None,
+                WatchBackend::Auto | WatchBackend::Usn => match Journal::new(tree.clone()) {
+                    Ok(journal) => return Some(Backend::Journal(journal)),
+                    Err(e) => info!("USN journal unavailable, falling back to ReadDirectoryChangesW: {}", e),
+                },
+                _ => {}
+            }
+            match DirectoryChanges::new(tree) {
+                Ok(changes) => Some(Backend::DirectoryChanges(changes)),
+                Err(e) => {
+                    warn!("Failed to watch for changes, only scanning periodically: {}", e);
+                    None
+                }
+            }
+        }
+
+        /// Waits up to `timeout` for changes and returns whether any of them was relevant.
+        pub fn wait(&mut self, timeout: Duration) -> bool {
+            match self {
+                Backend::Journal(journal) => journal.wait(timeout),
+                Backend::DirectoryChanges(changes) => changes.wait(timeout),
+            }
+        }
+
+        pub fn drain(&mut self) {
+            match self {
+                Backend::Journal(journal) => journal.read(),
+                Backend::DirectoryChanges(changes) => changes.wait(Duration::ZERO),
+            };
+        }
+    }
+
+    struct Handle(HANDLE);
+
+    impl Drop for Handle {
+        fn drop(&mut self) {
+            unsafe { CloseHandle(self.0) };
+        }
+    }
+
+    fn wide(path: &Path) -> Vec<u16> {
+        path.as_os_str().encode_wide().chain(Some(0)).collect()
+    }
+
+    fn open(path: &Path, access: u32, flags: u32) -> io::Result<Handle> {
+        let path = wide(path);
+        let handle = unsafe {
+            CreateFileW(
+                path.as_ptr(),
+                access,
+                FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+                ptr::null(),
+                OPEN_EXISTING,
+                flags,
+                0,
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(Handle(handle))
+        }
+    }
+
+    /// Reads the NTFS change journal of the volume and keeps records whose parent
+    /// is one of the watched folders, so a large tree costs one handle instead of many.
+    pub struct Journal {
+        volume: Handle,
+        journal_id: u64,
+        next_usn: i64,
+        tree: Tree,
+        folders: HashSet<u64>,
+    }
+
+    impl Journal {
+        fn new(tree: Tree) -> io::Result<Journal> {
+            let mut volume_path = [0u16; 261];
+            let root = wide(&tree.root);
+            if unsafe { GetVolumePathNameW(root.as_ptr(), volume_path.as_mut_ptr(), volume_path.len() as u32) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // `C:\` becomes `\\.\C:`, the device the journal is read from.
+            let length = volume_path.iter().position(|c| *c == 0).unwrap_or(volume_path.len());
+            let volume_path = std::ffi::OsString::from_wide(&volume_path[..length]);
+            let volume_path = volume_path.to_string_lossy();
+            let device = format!(r"\\.\{}", volume_path.trim_end_matches('\\'));
+            let volume = open(Path::new(&device), GENERIC_READ, 0)?;
+            let data = query_journal(&volume)?;
+
+            let mut journal = Journal {
+                volume,
+                journal_id: data.UsnJournalID,
+                next_usn: data.NextUsn,
+                tree,
+                folders: HashSet::new(),
+            };
+            journal.refresh();
+            info!("Watching {} folders through the USN journal of {}", journal.folders.len(), device);
+            Ok(journal)
+        }
+
+        fn refresh(&mut self) {
+            self.folders = self
+                .tree
+                .folders_below(&self.tree.root)
+                .iter()
+                .filter_map(|folder| file_id(folder).ok())
+                .collect();
+        }
+
+        fn wait(&mut self, timeout: Duration) -> bool {
+            let deadline = Instant::now() + timeout;
+            loop {
+                if self.read() {
+                    return true;
+                }
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return false;
+                }
+                thread::sleep(remaining.min(JOURNAL_STEP));
+            }
+        }
+
+        /// Reads the records written since the last call and returns whether any was relevant.
+        fn read(&mut self) -> bool {
+            let mut changed = false;
+            let mut new_folder = false;
+            let mut buffer = vec![0u64; 8192];
+            loop {
+                let request = READ_USN_JOURNAL_DATA_V0 {
+                    StartUsn: self.next_usn,
+                    ReasonMask: USN_REASON_FILE_CREATE
+                        | USN_REASON_RENAME_NEW_NAME
+                        | USN_REASON_CLOSE
+                        | USN_REASON_DATA_EXTEND
+                        | USN_REASON_DATA_OVERWRITE,
+                    ReturnOnlyOnClose: 0,
+                    Timeout: 0,
+                    BytesToWaitFor: 0,
+                    UsnJournalID: self.journal_id,
+                };
+                let mut returned = 0u32;
+                let read = unsafe {
+                    DeviceIoControl(
+                        self.volume.0,
+                        FSCTL_READ_USN_JOURNAL,
+                        &request as *const _ as *const _,
+                        mem::size_of::<READ_USN_JOURNAL_DATA_V0>() as u32,
+                        buffer.as_mut_ptr().cast(),
+                        (buffer.len() * 8) as u32,
+                        &mut returned,
+                        ptr::null_mut(),
+                    )
+                };
+                if read == 0 {
+                    let error = io::Error::last_os_error();
+                    if error.raw_os_error() == Some(ERROR_JOURNAL_ENTRY_DELETED as i32) {
+                        // The journal wrapped around while we were not looking; rescan everything.
+                        warn!("USN journal records were overwritten, rescanning");
+                        if let Ok(data) = query_journal(&self.volume) {
+                            self.journal_id = data.UsnJournalID;
+                            self.next_usn = data.NextUsn;
+                        }
+                        return true;
+                    }
+                    debug!("Failed to read the USN journal: {}", error);
+                    break;
+                }
+                let bytes = unsafe { std::slice::from_raw_parts(buffer.as_ptr().cast::<u8>(), returned as usize) };
+                if bytes.len() <= 8 {
+                    break;
+                }
+                self.next_usn = i64::from_ne_bytes(bytes[..8].try_into().unwrap());
+
+                // USN_RECORD_V2 fields are read by offset; V3 records use 128-bit ids and are skipped.
+                let mut offset = 8;
+                while offset + 60 <= bytes.len() {
+                    let record = &bytes[offset..];
+                    let length = u32::from_ne_bytes(record[0..4].try_into().unwrap()) as usize;
+                    if length == 0 {
+                        break;
+                    }
+                    offset += length;
+                    if u16::from_ne_bytes(record[4..6].try_into().unwrap()) != 2 {
+                        continue;
+                    }
+                    let parent = u64::from_ne_bytes(record[16..24].try_into().unwrap());
+                    if !self.folders.contains(&parent) {
+                        continue;
+                    }
+                    let reason = u32::from_ne_bytes(record[40..44].try_into().unwrap());
+                    let attributes = u32::from_ne_bytes(record[52..56].try_into().unwrap());
+                    if attributes & FILE_ATTRIBUTE_DIRECTORY != 0 {
+                        new_folder |= self.tree.recursive && reason & (USN_REASON_FILE_CREATE | USN_REASON_RENAME_NEW_NAME) != 0;
+                    } else if reason & USN_REASON_RENAME_NEW_NAME != 0
+                        || reason & USN_REASON_CLOSE != 0
+                            && reason & (USN_REASON_FILE_CREATE | USN_REASON_DATA_EXTEND | USN_REASON_DATA_OVERWRITE) != 0
+                    {
+                        changed = true;
+                    }
+                }
+            }
+            if new_folder {
+                self.refresh();
+            }
+            changed || new_folder
+        }
+    }
+
+    fn query_journal(volume: &Handle) -> io::Result<USN_JOURNAL_DATA_V0> {
+        let mut data: USN_JOURNAL_DATA_V0 = unsafe { mem::zeroed() };
+        let mut returned = 0;
+        let queried = unsafe {
+            DeviceIoControl(
+                volume.0,
+                FSCTL_QUERY_USN_JOURNAL,
+                ptr::null(),
+                0,
+                &mut data as *mut _ as *mut _,
+                mem::size_of::<USN_JOURNAL_DATA_V0>() as u32,
+                &mut returned,
+                ptr::null_mut(),
+            )
+        };
+        if queried == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(data)
+    }
+
+    /// The NTFS file reference number, as used for parents in journal records.
+    fn file_id(folder: &Path) -> io::Result<u64> {
+        let handle = open(folder, FILE_READ_ATTRIBUTES, FILE_FLAG_BACKUP_SEMANTICS)?;
+        let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { mem::zeroed() };
+        if unsafe { GetFileInformationByHandle(handle.0, &mut info) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((info.nFileIndexHigh as u64) << 32 | info.nFileIndexLow as u64)
+    }
+
+    /// Watches the tree with an overlapped ReadDirectoryChangesW request, which works
+    /// without administrator rights and on any filesystem, including network shares.
+    pub struct DirectoryChanges {
+        directory: Handle,
+        event: Handle,
+        overlapped: Box<OVERLAPPED>,
+        buffer: Box<[u32; 16384]>,
+        tree: Tree,
+    }
+
+    impl DirectoryChanges {
+        fn new(tree: Tree) -> io::Result<DirectoryChanges> {
+            let directory = open(&tree.root, FILE_LIST_DIRECTORY, FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OVERLAPPED)?;
+            let event = unsafe { CreateEventW(ptr::null(), 1, 0, ptr::null()) };
+            if event == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut changes = DirectoryChanges {
+                directory,
+                event: Handle(event),
+                overlapped: Box::new(unsafe { mem::zeroed() }),
+                buffer: Box::new([0; 16384]),
+                tree,
+            };
+            changes.request()?;
+            info!("Watching {:?} with ReadDirectoryChangesW", changes.tree.root);
+            Ok(changes)
+        }
+
+        fn request(&mut self) -> io::Result<()> {
+            *self.overlapped = unsafe { mem::zeroed() };
+            self.overlapped.hEvent = self.event.0;
+            let requested = unsafe {
+                ReadDirectoryChangesW(
+                    self.directory.0,
+                    self.buffer.as_mut_ptr().cast(),
+                    (self.buffer.len() * 4) as u32,
+                    self.tree.recursive as i32,
+                    FILE_NOTIFY_CHANGE_FILE_NAME | FILE_NOTIFY_CHANGE_DIR_NAME | FILE_NOTIFY_CHANGE_LAST_WRITE,
+                    ptr::null_mut(),
+                    &mut *self.overlapped,
+                    None,
+                )
+            };
+            if requested == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        fn wait(&mut self, timeout: Duration) -> bool {
+            let milliseconds = timeout.as_millis().min(u32::MAX as u128 - 1) as u32;
+            if unsafe { WaitForSingleObject(self.event.0, milliseconds) } != WAIT_OBJECT_0 {
+                return false;
+            }
+            let mut returned = 0u32;
+            let completed = unsafe { GetOverlappedResult(self.directory.0, &*self.overlapped, &mut returned, 0) } != 0;
+            // An empty result means the buffer overflowed and changes were lost.
+            let changed = !completed || returned == 0 || self.relevant(returned as usize);
+            if let Err(e) = self.request() {
+                warn!("Failed to keep watching {:?}: {}", self.tree.root, e);
+            }
+            changed
+        }
+
+        /// Walks the FILE_NOTIFY_INFORMATION entries and ignores changes in excluded folders.
+        fn relevant(&self, length: usize) -> bool {
+            let bytes = unsafe { std::slice::from_raw_parts(self.buffer.as_ptr().cast::<u8>(), length) };
+            let mut offset = 0;
+            loop {
+                let entry = &bytes[offset..];
+                let next = u32::from_ne_bytes(entry[0..4].try_into().unwrap()) as usize;
+                let action = u32::from_ne_bytes(entry[4..8].try_into().unwrap());
+                let name_length = u32::from_ne_bytes(entry[8..12].try_into().unwrap()) as usize;
+                let name: Vec<u16> = entry[12..12 + name_length]
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_ne_bytes([pair[0], pair[1]]))
+                    .collect();
+                let path = self.tree.root.join(std::ffi::OsString::from_wide(&name));
+                let excluded = path
+                    .ancestors()
+                    .skip(1)
+                    .take_while(|folder| *folder != self.tree.root)
+                    .any(|folder| scan::is_excluded(folder, &self.tree.excluded, self.tree.repositories));
+                if !excluded && matches!(action, FILE_ACTION_ADDED | FILE_ACTION_MODIFIED | FILE_ACTION_RENAMED_NEW_NAME) {
+                    return true;
+                }
+                if next == 0 {
+                    return false;
+                }
+                offset += next;
+            }
+        }
+    }
+
+    impl Drop for DirectoryChanges {
+        fn drop(&mut self) {
+            // The pending request writes into `buffer`; make sure it is finished before freeing it.
+            let mut returned = 0u32;
+            unsafe {
+                CancelIo(self.directory.0);
+                GetOverlappedResult(self.directory.0, &*self.overlapped, &mut returned, 1);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod macos {
+    use super::{Tree, WatchBackend};
+    use crate::scan;
+    use fsevent_sys as fse;
+    use fsevent_sys::core_foundation as cf;
+    use std::ffi::{c_char, c_void, CStr};
+    use std::path::{Path, PathBuf};
+    use std::ptr;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::thread;
+    use std::time::Duration;
+    use tracing::{info, warn};
+
+    /// How long FSEvents coalesces events before delivering them, in seconds.
+    const LATENCY: f64 = 0.5;
+
+    /// Watches the tree recursively with a single FSEvents stream, which runs on its own thread.
+    pub struct Backend {
+        events: Receiver<(PathBuf, u32)>,
+        tree: Tree,
+    }
+
+    impl Backend {
+        pub fn new(tree: Tree, backend: WatchBackend) -> Option<Backend> {
+            if backend == WatchBackend::Poll {
+                return None;
+            }
+            let (sender, events) = mpsc::channel();
+            let root = tree.root.to_string_lossy().into_owned();
+            thread::spawn(move || unsafe { run_stream(&root, sender) });
+            info!("Watching {:?} with FSEvents", tree.root);
+            Some(Backend { events, tree })
+        }
+
+        /// Waits up to `timeout` for events and returns whether any of them was relevant.
+        pub fn wait(&mut self, timeout: Duration) -> bool {
+            match self.events.recv_timeout(timeout) {
+                Ok((path, flags)) => self.relevant(&path, flags),
+                Err(mpsc::RecvTimeoutError::Timeout) => false,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    warn!("FSEvents stream stopped, only scanning periodically");
+                    thread::sleep(timeout);
+                    false
+                }
+            }
+        }
+
+        pub fn drain(&mut self) {
+            while self.events.try_recv().is_ok() {}
+        }
+
+        /// FSEvents reports everything below the root, including the insides of bundles
+        /// and destination folders; only new items in sorted folders count.
+        fn relevant(&self, path: &Path, flags: u32) -> bool {
+            if flags & (fse::kFSEventStreamEventFlagMustScanSubDirs | fse::kFSEventStreamEventFlagUserDropped | fse::kFSEventStreamEventFlagKernelDropped) != 0 {
+                return true;
+            }
+            if flags & (fse::kFSEventStreamEventFlagItemCreated | fse::kFSEventStreamEventFlagItemRenamed | fse::kFSEventStreamEventFlagItemModified) == 0 {
+                return false;
+            }
+            let Some(parent) = path.parent() else { return false };
+            if !parent.starts_with(&self.tree.root) || (!self.tree.recursive && parent != self.tree.root) {
+                return false;
+            }
+            let hidden_or_excluded = parent
+                .ancestors()
+                .take_while(|folder| *folder != self.tree.root)
+                .any(|folder| scan::is_excluded(folder, &self.tree.excluded, self.tree.repositories));
+            if hidden_or_excluded {
+                return false;
+            }
+            // New folders only matter if they are bundles, which are sorted as one item.
+            flags & fse::kFSEventStreamEventFlagItemIsDir == 0 || scan::is_bundle(path)
+        }
+    }
+
+    unsafe fn run_stream(root: &str, sender: Sender<(PathBuf, u32)>) {
+        let paths = cf::CFArrayCreateMutable(cf::kCFAllocatorDefault, 0, &cf::kCFTypeArrayCallBacks);
+        let mut error = ptr::null_mut();
+        let path = cf::str_path_to_cfstring_ref(root, &mut error);
+        if path.is_null() {
+            warn!("Failed to watch {:?} with FSEvents", root);
+            return;
+        }
+        cf::CFArrayAppendValue(paths, path);
+        cf::CFRelease(path);
+
+        // The sender lives as long as the stream, which runs until the process exits.
+        let context = fse::FSEventStreamContext {
+            version: 0,
+            info: Box::into_raw(Box::new(sender)) as *mut c_void,
+            retain: None,
+            release: None,
+            copy_description: None,
+        };
+        let stream = fse::FSEventStreamCreate(
+            cf::kCFAllocatorDefault,
+            callback,
+            &context,
+            paths,
+            fse::kFSEventStreamEventIdSinceNow,
+            LATENCY,
+            fse::kFSEventStreamCreateFlagFileEvents | fse::kFSEventStreamCreateFlagNoDefer,
+        );
+        fse::FSEventStreamScheduleWithRunLoop(stream, cf::CFRunLoopGetCurrent(), cf::kCFRunLoopDefaultMode);
+        fse::FSEventStreamStart(stream);
+        cf::CFRunLoopRun();
+    }
+
+    extern "C" fn callback(
+        _stream: fse::FSEventStreamRef,
+        info: *mut c_void,
+        count: usize,
+        paths: *mut c_void,
+        flags: *const fse::FSEventStreamEventFlags,
+        _ids: *const fse::FSEventStreamEventId,
+    ) {
+        let sender = unsafe { &*(info as *const Sender<(PathBuf, u32)>) };
+        let paths = paths as *const *const c_char;
+        for index in 0..count {
+            let (path, flags) = unsafe { (CStr::from_ptr(*paths.add(index)), *flags.add(index)) };
+            let _ = sender.send((PathBuf::from(path.to_string_lossy().into_owned()), flags));
+        }
+    }
+}
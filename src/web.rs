@@ -0,0 +1,403 @@
+use crate::control::{Control, ControlCommand};
+use crate::index::{HistoryFilter, MoveIndex};
+use crate::statefile;
+use serde_json::json;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, info};
+
+/// The page served at `/`, which shows the daemon through the API below.
+const PAGE: &str = include_str!("web.html");
+
+/// Environment variable holding the token requests must carry.
+pub const TOKEN_ENV: &str = "ORGANIZER_HTTP_TOKEN";
+
+/// Largest request body accepted, which is plenty for `rules.json`.
+const MAX_BODY: usize = 1024 * 1024;
+
+/// Entries `/api/history` answers with when the request doesn't say.
+const DEFAULT_HISTORY: usize = 50;
+
+/// What the HTTP server needs from the daemon.
+pub struct Web {
+    pub control: Arc<Control>,
+    /// The `rules.json` the daemon sorts with.
+    pub rules: PathBuf,
+    /// Checks edited rules before they replace the file.
+    pub check: fn(&str) -> io::Result<()>,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+    token: Option<String>,
+    /// The `Host` header, which names the server as the browser sees it.
+    host: Option<String>,
+    /// The `Origin` header browsers send with requests that change something.
+    origin: Option<String>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn json(status: &'static str, body: String) -> Response {
+        Response {
+            status,
+            content_type: "application/json",
+            body: body.into_bytes(),
+        }
+    }
+
+    fn message(status: &'static str, message: impl std::fmt::Display) -> Response {
+        Response::json(status, json!({ "message": message.to_string() }).to_string())
+    }
+}
+
+impl Web {
+    fn respond(&self, stream: TcpStream, token: Option<&str>) -> io::Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut reader = BufReader::new(stream);
+        let response = match read_request(&mut reader) {
+            Ok(request) => self.route(&request, token),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => Response::message("400 Bad Request", e),
+            Err(e) => return Err(e),
+        };
+        let mut stream = reader.into_inner();
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+            response.status,
+            response.content_type,
+            response.body.len()
+        )?;
+        stream.write_all(&response.body)?;
+        stream.flush()
+    }
+
+    fn route(&self, request: &Request, token: Option<&str>) -> Response {
+        if request.method == "GET" && request.path == "/" {
+            return Response {
+                status: "200 OK",
+                content_type: "text/html; charset=utf-8",
+                body: PAGE.as_bytes().to_vec(),
+            };
+        }
+        let authorized = match (token, request.token.as_deref()) {
+            (Some(expected), Some(given)) => same(expected, given),
+            (Some(_), None) => false,
+            // Without a token the server only listens on loopback, where any page open in a
+            // browser could reach it: requests must name it by a loopback address, against
+            // DNS rebinding, and changes must come from its own page.
+            (None, _) => request.host.as_deref().is_some_and(is_loopback_host) && (request.method == "GET" || same_origin(request)),
+        };
+        if !authorized {
+            return Response::message("401 Unauthorized", "A valid token is needed");
+        }
+        debug!("HTTP {} {}", request.method, request.path);
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/api/status") => Response::json("200 OK", self.control.handle(ControlCommand::Stats)),
+            ("GET", "/api/activity") => Response::json("200 OK", self.control.handle(ControlCommand::Activity)),
+            ("GET", "/api/history") => self.history(&request.query),
+            ("POST", "/api/sort") => Response::message("200 OK", self.control.handle(ControlCommand::SortNow)),
+            ("POST", "/api/pause") => Response::message("200 OK", self.control.handle(ControlCommand::Pause)),
+            ("POST", "/api/resume") => Response::message("200 OK", self.control.handle(ControlCommand::Resume)),
+            ("GET", "/api/rules") => match std::fs::read(&self.rules) {
+                Ok(contents) => Response {
+                    status: "200 OK",
+                    content_type: "application/json",
+                    body: contents,
+                },
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Response::message("404 Not Found", "There is no rules.json; the default rules are used"),
+                Err(e) => Response::message("500 Internal Server Error", e),
+            },
+            ("PUT", "/api/rules") if token.is_none() => {
+                Response::message("403 Forbidden", format!("Set {} for the daemon to edit rules over HTTP", TOKEN_ENV))
+            }
+            ("PUT", "/api/rules") => self.save_rules(&request.body),
+            (_, "/api/status" | "/api/activity" | "/api/history" | "/api/sort" | "/api/pause" | "/api/resume" | "/api/rules") => {
+                Response::message("405 Method Not Allowed", "Method not allowed")
+            }
+            _ => Response::message("404 Not Found", "Not found"),
+        }
+    }
+
+    fn history(&self, query: &HashMap<String, String>) -> Response {
+        let filter = HistoryFilter {
+            rule: query.get("rule").filter(|rule| !rule.is_empty()).cloned(),
+            search: query.get("search").filter(|search| !search.is_empty()).cloned(),
+            limit: Some(query.get("limit").and_then(|limit| limit.parse().ok()).unwrap_or(DEFAULT_HISTORY)),
+            ..HistoryFilter::default()
+        };
+        match MoveIndex::open().and_then(|index| index.history(&filter)) {
+            Ok(entries) => Response::json("200 OK", serde_json::to_string(&entries).unwrap_or_default()),
+            Err(e) => Response::message("500 Internal Server Error", e),
+        }
+    }
+
+    /// Replaces `rules.json` with valid rules and has the daemon load them before its next sort.
+    fn save_rules(&self, body: &[u8]) -> Response {
+        let Ok(contents) = std::str::from_utf8(body) else {
+            return Response::message("400 Bad Request", "The rules are not UTF-8");
+        };
+        if let Err(e) = (self.check)(contents) {
+            return Response::message("422 Unprocessable Entity", e);
+        }
+        if let Err(e) = statefile::write(&self.rules, body) {
+            return Response::message("500 Internal Server Error", e);
+        }
+        info!("Rules replaced over HTTP");
+        self.control.handle(ControlCommand::ReloadRules);
+        Response::message("200 OK", "Saved; the daemon sorts with the new rules from now on")
+    }
+}
+
+fn read_request(reader: &mut BufReader<TcpStream>) -> io::Result<Request> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(invalid("Malformed request line"));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query: HashMap<String, String> = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (decode(key), decode(value)))
+        .collect();
+
+    let mut length = 0;
+    let mut token = query.get("token").cloned();
+    let (mut host, mut origin) = (None, None);
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? <= 2 {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else { continue };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            length = value.parse().map_err(|_| invalid("Invalid Content-Length"))?;
+        } else if name.eq_ignore_ascii_case("authorization") {
+            token = value.strip_prefix("Bearer ").map(str::to_string).or(token);
+        } else if name.eq_ignore_ascii_case("host") {
+            host = Some(value.to_string());
+        } else if name.eq_ignore_ascii_case("origin") {
+            origin = Some(value.to_string());
+        }
+    }
+    if length > MAX_BODY {
+        return Err(invalid("Request body is too large"));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query,
+        token,
+        host,
+        origin,
+        body,
+    })
+}
+
+/// Whether a `Host` header names this machine by a loopback name or address, with or
+/// without a port.
+fn is_loopback_host(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(bracketed) => bracketed.split(']').next().unwrap_or_default(),
+        None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+    };
+    name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok_and(|address| address.is_loopback())
+}
+
+/// Whether a request comes from the server's own page. Browsers always send `Origin` with
+/// requests from other pages that change something; tools like `curl` send none.
+fn same_origin(request: &Request) -> bool {
+    match (&request.origin, &request.host) {
+        (None, _) => true,
+        (Some(origin), Some(host)) => origin.strip_prefix("http://") == Some(host.as_str()),
+        (Some(_), None) => false,
+    }
+}
+
+/// Decodes a query string value, e.g. `a%20b+c` to `a b c`.
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut position = 0;
+    while position < bytes.len() {
+        let hex = bytes.get(position + 1..position + 3).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (bytes[position], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                position += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        position += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Compares tokens in a time that doesn't depend on where they differ.
+fn same(expected: &str, given: &str) -> bool {
+    expected.len() == given.len() && expected.bytes().zip(given.bytes()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+/// Serves the API and the page on `address` from a background thread. Requests must
+/// carry the token from `ORGANIZER_HTTP_TOKEN` when it is set; without it, only loopback
+/// addresses are served.
+pub fn serve(web: Web, address: SocketAddr) -> io::Result<()> {
+    let token: Option<Arc<str>> = std::env::var(TOKEN_ENV).ok().filter(|token| !token.is_empty()).map(Arc::from);
+    if token.is_none() && !address.ip().is_loopback() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("Set {} to serve on {}, which other machines can reach", TOKEN_ENV, address.ip()),
+        ));
+    }
+    let listener = TcpListener::bind(address)?;
+    let web = Arc::new(web);
+    info!("Serving the web interface on http://{}/", address);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // Each request gets a thread, since pausing waits for the sort in progress.
+            let (web, token) = (web.clone(), token.clone());
+            thread::spawn(move || {
+                if let Err(e) = web.respond(stream, token.as_deref()) {
+                    debug!("HTTP request failed: {}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    const TOKEN: &str = "secret";
+
+    // The folder goes with the test, so it is handed out to be kept as long as the `Web`.
+    fn web(name: &str) -> (Web, TestDir) {
+        let folder = TestDir::new("web", name);
+        let web = Web {
+            control: Control::new(),
+            rules: folder.join("rules.json"),
+            check: |rules| serde_json::from_str::<serde_json::Value>(rules).map(|_| ()).map_err(io::Error::other),
+        };
+        (web, folder)
+    }
+
+    fn request(method: &str, path: &str, host: &str, origin: Option<&str>, token: Option<&str>) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: HashMap::new(),
+            token: token.map(str::to_string),
+            host: Some(host.to_string()),
+            origin: origin.map(str::to_string),
+            body: Vec::new(),
+        }
+    }
+
+    // Requests that get through are answered `405` here, as the API doesn't take them
+    // with that method; requests that don't are answered `401`.
+    fn status(web: &Web, request: &Request, token: Option<&str>) -> &'static str {
+        web.route(request, token).status
+    }
+
+    #[test]
+    fn serves_the_page_to_anyone() {
+        let (web, _folder) = web("page");
+        assert_eq!(status(&web, &request("GET", "/", "evil.example", None, None), Some(TOKEN)), "200 OK");
+    }
+
+    #[test]
+    fn needs_the_token_when_one_is_set() {
+        let (web, _folder) = web("token");
+        let missing = request("DELETE", "/api/status", "nas.local:8686", None, None);
+        let wrong = request("DELETE", "/api/status", "nas.local:8686", None, Some("guess"));
+        let right = request("DELETE", "/api/status", "nas.local:8686", None, Some(TOKEN));
+        assert_eq!(status(&web, &missing, Some(TOKEN)), "401 Unauthorized");
+        assert_eq!(status(&web, &wrong, Some(TOKEN)), "401 Unauthorized");
+        assert_eq!(status(&web, &right, Some(TOKEN)), "405 Method Not Allowed");
+    }
+
+    #[test]
+    fn answers_tokenless_requests_only_by_a_loopback_name() {
+        let (web, _folder) = web("rebinding");
+        let loopback = request("DELETE", "/api/status", "127.0.0.1:8686", None, None);
+        let rebound = request("DELETE", "/api/status", "evil.example:8686", None, None);
+        assert_eq!(status(&web, &loopback, None), "405 Method Not Allowed");
+        assert_eq!(status(&web, &rebound, None), "401 Unauthorized");
+    }
+
+    #[test]
+    fn takes_tokenless_changes_only_from_its_own_page() {
+        let (web, _folder) = web("origin");
+        let own = request("DELETE", "/api/sort", "localhost:8686", Some("http://localhost:8686"), None);
+        let other = request("DELETE", "/api/sort", "localhost:8686", Some("http://evil.example"), None);
+        assert_eq!(status(&web, &own, None), "405 Method Not Allowed");
+        assert_eq!(status(&web, &other, None), "401 Unauthorized");
+    }
+
+    #[test]
+    fn edits_rules_only_with_a_token() {
+        let (web, _folder) = web("rules");
+        let mut edit = request("PUT", "/api/rules", "localhost:8686", None, None);
+        edit.body = br#"{ "rules": [] }"#.to_vec();
+        assert_eq!(status(&web, &edit, None), "403 Forbidden");
+        assert!(!web.rules.exists());
+        edit.token = Some(TOKEN.to_string());
+        assert_eq!(status(&web, &edit, Some(TOKEN)), "200 OK");
+        assert_eq!(std::fs::read(&web.rules).unwrap(), edit.body);
+    }
+
+    #[test]
+    fn keeps_the_rules_when_the_edit_is_invalid() {
+        let (web, _folder) = web("invalid");
+        let mut edit = request("PUT", "/api/rules", "localhost:8686", None, Some(TOKEN));
+        edit.body = b"{ not json".to_vec();
+        assert_eq!(status(&web, &edit, Some(TOKEN)), "422 Unprocessable Entity");
+        assert!(!web.rules.exists());
+    }
+
+    #[test]
+    fn tells_loopback_hosts_from_others() {
+        assert!(is_loopback_host("localhost"));
+        assert!(is_loopback_host("127.0.0.1:8686"));
+        assert!(is_loopback_host("[::1]:8686"));
+        assert!(!is_loopback_host("192.168.1.20:8686"));
+        assert!(!is_loopback_host("localhost.evil.example"));
+    }
+
+    #[test]
+    fn decodes_query_values() {
+        assert_eq!(decode("a%20b+c"), "a b c");
+        assert_eq!(decode("100%"), "100%");
+    }
+
+    #[test]
+    fn compares_tokens_by_every_byte() {
+        assert!(same(TOKEN, TOKEN));
+        assert!(!same(TOKEN, "secreT"));
+        assert!(!same(TOKEN, "secret1"));
+    }
+}
@@ -0,0 +1,195 @@
+use crate::audit;
+use chrono::{DateTime, Local};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where to POST sort events, from `rules.json`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WebhookSettings {
+    pub url: String,
+    /// Environment variable holding the secret the body is signed with, e.g. `ORGANIZER_WEBHOOK_SECRET`.
+    pub secret_env: Option<String>,
+    /// Further attempts after a failed delivery, waiting twice as long before each one.
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+enum Event {
+    Moved {
+        time: DateTime<Local>,
+        file: PathBuf,
+        destination: PathBuf,
+        rule: String,
+    },
+    Quarantined {
+        time: DateTime<Local>,
+        file: PathBuf,
+        destination: PathBuf,
+        rule: String,
+        reason: String,
+    },
+    Failed {
+        time: DateTime<Local>,
+        file: PathBuf,
+        rule: Option<String>,
+        error: String,
+    },
+    /// A destination's drive fell below `min_free`; rules placing files on it are suspended.
+    #[serde(rename = "disk_low")]
+    DiskLow {
+        time: DateTime<Local>,
+        folder: PathBuf,
+        free_bytes: u64,
+    },
+    #[serde(rename = "disk_recovered")]
+    DiskRecovered {
+        time: DateTime<Local>,
+        folder: PathBuf,
+        free_bytes: u64,
+    },
+    /// A scrub found a placed file corrupted, changed or missing.
+    Integrity {
+        time: DateTime<Local>,
+        file: PathBuf,
+        problem: String,
+    },
+}
+
+/// Collects the moves, quarantined files and errors of a sort and posts them as one JSON payload.
+#[derive(Default)]
+pub struct Webhook {
+    settings: Option<WebhookSettings>,
+    events: Vec<Event>,
+}
+
+impl Webhook {
+    pub fn configure(&mut self, settings: Option<&WebhookSettings>) {
+        self.settings = settings.cloned();
+    }
+
+    pub fn file_moved(&mut self, file: &Path, destination: &Path, rule: &str) {
+        if self.settings.is_some() {
+            self.events.push(Event::Moved {
+                time: Local::now(),
+                file: file.to_path_buf(),
+                destination: destination.to_path_buf(),
+                rule: rule.to_string(),
+            });
+        }
+    }
+
+    pub fn file_quarantined(&mut self, file: &Path, destination: &Path, rule: &str, reason: &str) {
+        if self.settings.is_some() {
+            self.events.push(Event::Quarantined {
+                time: Local::now(),
+                file: file.to_path_buf(),
+                destination: destination.to_path_buf(),
+                rule: rule.to_string(),
+                reason: reason.to_string(),
+            });
+        }
+    }
+
+    pub fn file_failed(&mut self, file: &Path, rule: Option<&str>, error: &std::io::Error) {
+        if self.settings.is_some() {
+            self.events.push(Event::Failed {
+                time: Local::now(),
+                file: file.to_path_buf(),
+                rule: rule.map(str::to_string),
+                error: error.to_string(),
+            });
+        }
+    }
+
+    pub fn disk_space(&mut self, folder: &Path, free_bytes: u64, low: bool) {
+        if self.settings.is_some() {
+            let (time, folder) = (Local::now(), folder.to_path_buf());
+            self.events.push(if low {
+                Event::DiskLow { time, folder, free_bytes }
+            } else {
+                Event::DiskRecovered { time, folder, free_bytes }
+            });
+        }
+    }
+
+    pub fn integrity(&mut self, file: &Path, problem: &str) {
+        if self.settings.is_some() {
+            self.events.push(Event::Integrity {
+                time: Local::now(),
+                file: file.to_path_buf(),
+                problem: problem.to_string(),
+            });
+        }
+    }
+
+    /// Posts the collected events, retrying with backoff; they are dropped once all attempts failed.
+    pub fn deliver(&mut self) {
+        let Some(settings) = &self.settings else { return };
+        if self.events.is_empty() {
+            return;
+        }
+        let body = match serde_json::to_string(&serde_json::json!({ "events": self.events })) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Failed to serialize webhook payload: {}", e);
+                return;
+            }
+        };
+        self.events.clear();
+        let signature = match &settings.secret_env {
+            Some(variable) => match std::env::var(variable) {
+                Ok(secret) => Some(sign(&secret, &body)),
+                Err(_) => {
+                    warn!("Not sending webhook, {} is not set", variable);
+                    return;
+                }
+            },
+            None => None,
+        };
+
+        let mut delay = Duration::from_secs(1);
+        for attempt in 0..=settings.retries {
+            let mut request = ureq::post(&settings.url).timeout(TIMEOUT).set("Content-Type", "application/json");
+            if let Some(signature) = &signature {
+                request = request.set("X-Organizer-Signature", &format!("sha256={}", signature));
+            }
+            match request.send_string(&body) {
+                Ok(_) => {
+                    debug!("Delivered webhook to {}", settings.url);
+                    return;
+                }
+                // The receiver rejected the payload; sending it again won't help.
+                Err(ureq::Error::Status(status, _)) if status < 500 && status != 429 => {
+                    warn!("Webhook {} rejected the payload with status {}", settings.url, status);
+                    return;
+                }
+                Err(e) if attempt < settings.retries => {
+                    debug!("Webhook delivery failed, retrying in {:?}: {}", delay, e);
+                    thread::sleep(delay);
+                    delay *= 2;
+                }
+                Err(e) => warn!("Failed to deliver webhook to {}: {}", settings.url, e),
+            }
+        }
+    }
+}
+
+/// HMAC-SHA256 of the body, hex encoded.
+fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body.as_bytes());
+    audit::encode_hex(&mac.finalize().into_bytes())
+}
@@ -0,0 +1,205 @@
+use std::io;
+use std::path::Path;
+
+/// The value of the attribute `name`, or `None` when the file doesn't have it.
+pub fn get(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+    sys::get(path, name)
+}
+
+pub fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+    sys::set(path, name, value)
+}
+
+/// Copies the extended attributes of `source` to `target`; on Windows, its alternate data
+/// streams, such as the zone a download came from.
+pub fn copy_all(source: &Path, target: &Path) -> io::Result<()> {
+    sys::copy_all(source, target)
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod sys {
+    use std::ffi::{CString, OsString};
+    use std::io;
+    use std::os::raw::{c_char, c_void};
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+    use std::path::Path;
+    use std::ptr;
+
+    #[cfg(target_os = "linux")]
+    const NO_ATTRIBUTE: i32 = libc::ENODATA;
+    #[cfg(target_os = "macos")]
+    const NO_ATTRIBUTE: i32 = libc::ENOATTR;
+
+    fn c_string(bytes: &[u8]) -> io::Result<CString> {
+        CString::new(bytes).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "name contains a NUL byte"))
+    }
+
+    // Symlinks are never followed, so links get their own attributes.
+    #[cfg(target_os = "linux")]
+    unsafe fn list_raw(path: *const c_char, names: *mut c_char, size: usize) -> isize {
+        libc::llistxattr(path, names, size)
+    }
+    #[cfg(target_os = "macos")]
+    unsafe fn list_raw(path: *const c_char, names: *mut c_char, size: usize) -> isize {
+        libc::listxattr(path, names, size, libc::XATTR_NOFOLLOW)
+    }
+    #[cfg(target_os = "linux")]
+    unsafe fn get_raw(path: *const c_char, name: *const c_char, value: *mut c_void, size: usize) -> isize {
+        libc::lgetxattr(path, name, value, size)
+    }
+    #[cfg(target_os = "macos")]
+    unsafe fn get_raw(path: *const c_char, name: *const c_char, value: *mut c_void, size: usize) -> isize {
+        libc::getxattr(path, name, value, size, 0, libc::XATTR_NOFOLLOW)
+    }
+    #[cfg(target_os = "linux")]
+    unsafe fn set_raw(path: *const c_char, name: *const c_char, value: *const c_void, size: usize) -> i32 {
+        libc::lsetxattr(path, name, value, size, 0)
+    }
+    #[cfg(target_os = "macos")]
+    unsafe fn set_raw(path: *const c_char, name: *const c_char, value: *const c_void, size: usize) -> i32 {
+        libc::setxattr(path, name, value, size, 0, libc::XATTR_NOFOLLOW)
+    }
+
+    /// Calls `read` with a buffer as large as it asks for, again when the value grew in between.
+    fn read_grown(mut read: impl FnMut(*mut c_void, usize) -> isize) -> io::Result<Vec<u8>> {
+        loop {
+            let size = read(ptr::null_mut(), 0);
+            if size < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut buffer = vec![0u8; size as usize];
+            let read = read(buffer.as_mut_ptr() as *mut c_void, buffer.len());
+            if read >= 0 {
+                buffer.truncate(read as usize);
+                return Ok(buffer);
+            }
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() != Some(libc::ERANGE) {
+                return Err(error);
+            }
+        }
+    }
+
+    pub fn list(path: &Path) -> io::Result<Vec<OsString>> {
+        let path = c_string(path.as_os_str().as_bytes())?;
+        let names = read_grown(|names, size| unsafe { list_raw(path.as_ptr(), names as *mut c_char, size) })?;
+        Ok(names
+            .split(|byte| *byte == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| OsString::from_vec(name.to_vec()))
+            .collect())
+    }
+
+    pub fn get(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let (path, name) = (c_string(path.as_os_str().as_bytes())?, c_string(name.as_bytes())?);
+        match read_grown(|value, size| unsafe { get_raw(path.as_ptr(), name.as_ptr(), value, size) }) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.raw_os_error() == Some(NO_ATTRIBUTE) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        let (path, name) = (c_string(path.as_os_str().as_bytes())?, c_string(name.as_bytes())?);
+        if unsafe { set_raw(path.as_ptr(), name.as_ptr(), value.as_ptr() as *const c_void, value.len()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn copy_all(source: &Path, target: &Path) -> io::Result<()> {
+        for name in list(source)? {
+            let Some(name) = name.to_str() else { continue };
+            if let Some(value) = get(source, name)? {
+                set(target, name, &value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::ffi::{c_void, OsString};
+    use std::fs::File;
+    use std::io;
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::Path;
+
+    /// Alternate data streams stand in for extended attributes, as `file:name`.
+    fn stream(path: &Path, name: &str) -> OsString {
+        let mut stream = path.as_os_str().to_owned();
+        stream.push(":");
+        stream.push(name);
+        stream
+    }
+
+    pub fn list(path: &Path) -> io::Result<Vec<OsString>> {
+        use windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE;
+        use windows_sys::Win32::Storage::FileSystem::{
+            FindClose, FindFirstStreamW, FindNextStreamW, FindStreamInfoStandard, WIN32_FIND_STREAM_DATA,
+        };
+
+        let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        let mut data: WIN32_FIND_STREAM_DATA = unsafe { std::mem::zeroed() };
+        let handle = unsafe { FindFirstStreamW(wide.as_ptr(), FindStreamInfoStandard, &mut data as *mut _ as *mut c_void, 0) };
+        if handle == INVALID_HANDLE_VALUE {
+            // Files with only their main stream, and drives without streams, end up here.
+            return Ok(Vec::new());
+        }
+        let mut names = Vec::new();
+        loop {
+            // Names look like `:Zone.Identifier:$DATA`; the main stream is `::$DATA`.
+            let length = data.cStreamName.iter().position(|c| *c == 0).unwrap_or(data.cStreamName.len());
+            let name = String::from_utf16_lossy(&data.cStreamName[..length]);
+            if let Some(name) = name.strip_prefix(':').and_then(|name| name.strip_suffix(":$DATA")) {
+                if !name.is_empty() {
+                    names.push(OsString::from(name));
+                }
+            }
+            if unsafe { FindNextStreamW(handle, &mut data as *mut _ as *mut c_void) } == 0 {
+                break;
+            }
+        }
+        unsafe { FindClose(handle) };
+        Ok(names)
+    }
+
+    pub fn get(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+        match std::fs::read(stream(path, name)) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        std::fs::write(stream(path, name), value)
+    }
+
+    pub fn copy_all(source: &Path, target: &Path) -> io::Result<()> {
+        for name in list(source)? {
+            let Some(name) = name.to_str() else { continue };
+            io::copy(&mut File::open(stream(source, name))?, &mut File::create(stream(target, name))?)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod sys {
+    use std::io;
+    use std::path::Path;
+
+    pub fn get(_path: &Path, _name: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    pub fn set(_path: &Path, _name: &str, _value: &[u8]) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "extended attributes are not supported on this platform"))
+    }
+
+    pub fn copy_all(_source: &Path, _target: &Path) -> io::Result<()> {
+        Ok(())
+    }
+}